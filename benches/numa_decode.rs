@@ -0,0 +1,70 @@
+/*!
+ * Decoding of consecutive BED variants with and without the NUMA mode (see
+ * `config::set_numa`).
+ *
+ *     cargo bench --features numa --bench numa_decode
+ *
+ * The BED is random and kept in memory, so that only the decoding (and the
+ * allocation of the genotypes) is timed. The gain is only expected on
+ * servers with multiple NUMA nodes.
+ */
+
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use rsgeneparselib::config::{numa_nodes, set_numa, threads};
+use rsgeneparselib::plink::BedReader;
+
+
+const N_SAMPLES: u32 = 20_000;
+const N_VARIANTS: u32 = 4_000;
+const N_RUNS: usize = 10;
+
+
+// Minimum and mean times to read all the variants.
+fn bench(reader: &mut BedReader<Cursor<Vec<u8>>>, numa: bool)
+    -> (Duration, Duration)
+{
+    set_numa(numa);
+
+    let times: Vec<Duration> = (0..N_RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let genotypes = reader.read_variants(0, N_VARIANTS);
+            let elapsed = start.elapsed();
+            assert_eq!(genotypes.len(), N_VARIANTS as usize);
+            elapsed
+        })
+        .collect();
+
+    set_numa(false);
+
+    let total: Duration = times.iter().sum();
+    (*times.iter().min().unwrap(), total / N_RUNS as u32)
+}
+
+
+fn main() {
+    let chunk_size = (N_SAMPLES as usize).div_ceil(4);
+    let mut rng = ChaCha8Rng::seed_from_u64(1);
+    let mut bed = vec![0x6c, 0x1b, 0x01];
+    bed.extend((0..chunk_size * N_VARIANTS as usize).map(|_| rng.gen::<u8>()));
+
+    let mut reader = BedReader::new_from_reader(Cursor::new(bed), N_SAMPLES,
+                                                N_VARIANTS)
+        .expect("Could not read the BED.");
+
+    println!("{} samples, {} variants, {} threads, {} NUMA node(s)",
+             N_SAMPLES, N_VARIANTS, threads(), numa_nodes().len());
+
+    // Start the thread pools before timing.
+    reader.read_variants(0, N_VARIANTS);
+
+    for &numa in [false, true].iter() {
+        let (min, mean) = bench(&mut reader, numa);
+        println!("numa={:<5}  min {:>8.2?}  mean {:>8.2?}", numa, min, mean);
+    }
+}
@@ -26,6 +26,7 @@ ffi.cdef("""
     plink_reader plink_reader_new(char *prefix);
     void* plink_reader_free(plink_reader);
     genotypes plink_reader_next(plink_reader);
+    void* plink_reader_attach_samples(plink_reader, bool);
 
     // Genotypes
     void* genotypes_print(genotypes);
@@ -35,6 +36,10 @@ ffi.cdef("""
     double genotypes_maf(genotypes);
     void* genotypes_free(genotypes);
     void* genotypes_data_free(genotypes);
+    char* genotypes_get_sample_iid(genotypes, size_t);
+
+    void* string_free(char*);
+    char* genepa_last_error();
 
 """)
 
@@ -115,6 +120,21 @@ class Genotypes(object):
             dtype=np.float32
         )
 
+    @property
+    def samples(self):
+        """Sample IIDs attached by the reader (None if not attached)."""
+        samples = []
+        for i in range(len(self)):
+            ptr = C.genotypes_get_sample_iid(self._obj, i)
+
+            if ptr == ffi.NULL:
+                return None
+
+            samples.append(ffi.string(ptr).decode("utf-8"))
+            C.string_free(ptr)
+
+        return samples
+
     def print(self):
         C.genotypes_print(self._obj)
 
@@ -129,11 +149,30 @@ def print_genotypes(obj):
     C.genotypes_print(obj)
 
 
+def last_error():
+    """Message of the error of the last failed call (None if it succeeded)."""
+    ptr = C.genepa_last_error()
+    if ptr == ffi.NULL:
+        return None
+
+    message = ffi.string(ptr).decode("utf-8")
+    C.string_free(ptr)
+    return message
+
+
 class PlinkReader(object):
     __slots__ = ["_obj"]
 
-    def __init__(self, prefix):
+    def __init__(self, prefix, attach_samples=False):
         self._obj = C.plink_reader_new(prefix.encode("ascii"))
+        if self._obj == ffi.NULL:
+            raise IOError(
+                "Could not open the plink fileset '{}': {}".format(
+                    prefix, last_error()
+                )
+            )
+
+        C.plink_reader_attach_samples(self._obj, attach_samples)
 
     def __del__(self):
         C.plink_reader_free(self._obj)
@@ -145,6 +184,10 @@ class PlinkReader(object):
         ptr = C.plink_reader_next(self._obj)
 
         if ptr == ffi.NULL:
+            error = last_error()
+            if error is not None:
+                raise IOError(error)
+
             raise StopIteration()
 
         return Genotypes.new_from_pointer(ptr)
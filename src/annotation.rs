@@ -0,0 +1,250 @@
+/*!
+ * Annotation of variants from a VCF (e.g. dbSNP or gnomAD).
+ *
+ * The annotation VCF must be bgzipped and indexed with tabix; it is queried
+ * at the position of every variant and the records with the same alleles
+ * (in any order or on the other strand, see `Variant::eq`) are used.
+ * Multiallelic records are split by ALT allele. The annotation has the ID
+ * of the record and the frequency of the ALT allele from an INFO field (`AF`
+ * by default).
+ *
+ * This is mostly useful to name the variants of BIM files without names
+ * (`.`) using `AnnotationVcf::rename`.
+ */
+
+use std::path::Path;
+use std::process::Command;
+
+use crate::core::{Chromosome, Variant, complement, normalize_chromosome};
+use crate::units::Frequency;
+
+
+pub const DEFAULT_FREQUENCY_KEY: &str = "AF";
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Annotation {
+    // None if the ID of the record is missing.
+    pub id: Option<String>,
+    pub reference: String,
+    pub alt: String,
+    pub alt_frequency: Option<Frequency>
+}
+
+
+impl Annotation {
+    // Frequency of one of the alleles of the variant (None if it isn't an
+    // allele of the record or if the frequency is unknown).
+    pub fn frequency_of(&self, allele: &str) -> Option<Frequency> {
+        let allele = allele.to_uppercase();
+        let alt_frequency = self.alt_frequency?;
+
+        let is = |other: &str| {
+            allele == other || complement(&allele) == other
+        };
+
+        if is(&self.alt) {
+            Some(alt_frequency)
+        } else if is(&self.reference) {
+            Some(alt_frequency.complement())
+        } else {
+            None
+        }
+    }
+}
+
+
+// Whether a variant name is missing (e.g. `.` in BIM files).
+pub fn is_missing_name(name: &str) -> bool {
+    name.is_empty() || name == "."
+}
+
+
+// Annotations of the records matching the variant among VCF lines.
+fn find_annotations<'a, I>(lines: I, v: &Variant, frequency_key: &str)
+    -> Vec<Annotation>
+    where I: IntoIterator<Item = &'a str>
+{
+    let mut annotations = Vec::new();
+
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 8 {
+            panic!("Expected at least 8 fields in the annotation VCF, found \
+                    {}.", fields.len());
+        }
+
+        let chrom = match normalize_chromosome(fields[0]) {
+            Ok(chrom) => chrom,
+            Err(_) => continue
+        };
+        let position = match fields[1].parse() {
+            Ok(position) => position,
+            Err(_) => panic!("Invalid position `{}` in the annotation VCF.",
+                             fields[1])
+        };
+
+        let frequencies: Vec<&str> = fields[7].split(';')
+            .find_map(|entry| {
+                entry.strip_prefix(frequency_key)?.strip_prefix('=')
+            })
+            .map_or_else(Vec::new, |values| values.split(',').collect());
+
+        for (i, alt) in fields[4].split(',').enumerate() {
+            let record = Variant::new(fields[2].to_string(), chrom.clone(),
+                                      position, (fields[3].to_string(),
+                                                 alt.to_string()));
+            if record != *v {
+                continue;
+            }
+
+            annotations.push(Annotation {
+                id: Some(fields[2].to_string())
+                    .filter(|id| !is_missing_name(id)),
+                reference: fields[3].to_uppercase(),
+                alt: alt.to_uppercase(),
+                alt_frequency: frequencies.get(i)
+                    .and_then(|p| p.parse().ok())
+                    .and_then(|p| Frequency::new(p).ok())
+            });
+        }
+    }
+
+    annotations
+}
+
+
+pub struct AnnotationVcf {
+    filename: String,
+    frequency_key: String,
+    // Contigs of the VCF (listed on the first query).
+    contigs: Option<Vec<String>>
+}
+
+
+impl AnnotationVcf {
+    pub fn new(filename: &str) -> AnnotationVcf {
+        let has_index = Path::new(&format!("{}.tbi", filename)).is_file() ||
+                        Path::new(&format!("{}.csi", filename)).is_file();
+        if !has_index {
+            panic!("The annotation VCF `{}` must be indexed with tabix.",
+                   filename);
+        }
+
+        AnnotationVcf {
+            filename: filename.to_string(),
+            frequency_key: DEFAULT_FREQUENCY_KEY.to_string(),
+            contigs: None
+        }
+    }
+
+    // INFO field of the ALT allele frequencies (e.g. `AF_nfe` in gnomAD).
+    pub fn with_frequency_key(mut self, key: &str) -> AnnotationVcf {
+        self.frequency_key = key.to_string();
+        self
+    }
+
+    fn _tabix(&self, args: &[&str]) -> String {
+        let tabix = Command::new("tabix")
+            .arg(&self.filename)
+            .args(args)
+            .output()
+            .expect("Couldn't spawn tabix for the annotation VCF.");
+
+        if !tabix.status.success() {
+            panic!("Error searching the annotation VCF using tabix.");
+        }
+
+        String::from_utf8(tabix.stdout).unwrap()
+    }
+
+    // Name of the chromosome as written in the VCF (None if it isn't in the
+    // VCF).
+    fn contig_name(&mut self, chrom: &Chromosome) -> Option<String> {
+        if self.contigs.is_none() {
+            let contigs = self._tabix(&["-l"]).lines()
+                .map(|name| name.to_string())
+                .collect();
+            self.contigs = Some(contigs);
+        }
+
+        self.contigs.as_ref().unwrap()
+            .iter()
+            .find(|name| Chromosome::new(name) == *chrom)
+            .cloned()
+    }
+
+    // Annotations of the records matching the variant.
+    pub fn lookup(&mut self, v: &Variant) -> Vec<Annotation> {
+        // The contig is found by its normalized name (e.g. `chr23` for `X`).
+        let contig = match self.contig_name(&v.chrom) {
+            Some(contig) => contig,
+            None => return Vec::new()
+        };
+
+        let region = format!("{}:{}-{}", contig, v.position, v.position);
+        let output = self._tabix(&[&region]);
+        find_annotations(output.lines(), v, &self.frequency_key)
+    }
+
+    // Name the variant with the first ID of the matching records if its
+    // name is missing. Returns true if the variant was renamed.
+    pub fn rename(&mut self, v: &mut Variant) -> bool {
+        if !is_missing_name(&v.name) {
+            return false;
+        }
+
+        match self.lookup(v).into_iter().find_map(|a| a.id) {
+            Some(id) => {
+                v.name = id;
+                true
+            },
+            None => false
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_annotations() {
+        let lines = [
+            "chr1\t100\trs1\tA\tG\t.\tPASS\tAC=3;AF=0.1",
+            "chr1\t100\trs2;rs3\tA\tC,T\t.\tPASS\tAF=0.2,0.3",
+            "chr1\t100\t.\tA\tAT\t.\tPASS\t.",
+            "chrUn_gl000220\t100\trs4\tA\tT\t.\tPASS\tAF=0.4"
+        ];
+        let variant = |a1: &str, a2: &str| {
+            Variant::new(".".to_string(), "1".to_string(), 100,
+                         (a1.to_string(), a2.to_string()))
+        };
+
+        // Swapped alleles.
+        let found = find_annotations(lines.iter().copied(), &variant("G", "A"),
+                                     "AF");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id.as_deref(), Some("rs1"));
+        assert_eq!(found[0].frequency_of("G"), Frequency::new(0.1).ok());
+        assert_eq!(found[0].frequency_of("a"), Frequency::new(0.9).ok());
+        assert_eq!(found[0].frequency_of("C"), Frequency::new(0.1).ok());
+
+        // Second ALT allele of a multiallelic record.
+        let found = find_annotations(lines.iter().copied(), &variant("A", "T"),
+                                     "AF");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id.as_deref(), Some("rs2;rs3"));
+        assert_eq!(found[0].alt_frequency, Frequency::new(0.3).ok());
+
+        // Missing ID and frequency.
+        let found = find_annotations(lines.iter().copied(),
+                                     &variant("A", "AT"), "AF");
+        assert_eq!(found[0].id, None);
+        assert_eq!(found[0].frequency_of("AT"), None);
+
+        assert!(find_annotations(lines.iter().copied(), &variant("A", "G"),
+                                 "AF_nfe")[0].alt_frequency.is_none());
+    }
+}
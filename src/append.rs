@@ -0,0 +1,355 @@
+/*!
+ * Incremental growth of plink filesets.
+ *
+ * New variants (genotyped on the same samples) are appended to the BIM and
+ * BED in place, without rewriting the fileset. New samples (genotyped on
+ * the same variants) are appended to the FAM and their calls are
+ * interleaved with the calls of every variant of the BED, which is
+ * rewritten; the BIM is left unchanged.
+ *
+ * Both filesets are validated before anything is modified: the BEDs must be
+ * variant major and match their BIM and FAM, the shared samples or variants
+ * must be identical (in the same order) and the appended variants (by name)
+ * or samples must not already be in the fileset. As the BIM changes when
+ * variants are appended, the indices of the fileset are deleted (they can
+ * be rebuilt using `build_index`). Checksum manifests must be rewritten.
+ */
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+use crate::core::{Sample, Variant};
+use crate::index::{delete_index, IndexFormat};
+use crate::plink::{bed_mode, parse_bim_line, read_fam_from_reader, BedMode,
+                   BedReader};
+
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AppendReport {
+    // Size of the fileset after the append.
+    pub n_samples: usize,
+    pub n_variants: usize
+}
+
+
+// A fileset whose BED was checked against its BIM and FAM.
+struct Fileset {
+    // Variants and coded alleles.
+    variants: Vec<(Variant, String)>,
+    samples: Vec<Sample>
+}
+
+impl Fileset {
+    fn open(prefix: &str) -> io::Result<Fileset> {
+        let bim = fs::read_to_string(format!("{}.bim", prefix))?;
+        let variants = bim.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(parse_bim_line)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(invalid_input)?;
+
+        let fam = fs::read(format!("{}.fam", prefix))?;
+        let samples = read_fam_from_reader(&fam[..]);
+
+        let bed_filename = format!("{}.bed", prefix);
+        let mut magic = [0; 3];
+        File::open(&bed_filename)?.read_exact(&mut magic)?;
+        if bed_mode(&magic) != Some(BedMode::VariantMajor) {
+            return Err(invalid_input(format!(
+                "`{}` is not a variant major BED.", bed_filename
+            )));
+        }
+
+        let n_bytes = fs::metadata(&bed_filename)?.len();
+        let n_bed = BedReader::count_variants(n_bytes, samples.len() as u32);
+        if n_bed != Some(variants.len() as u32) {
+            return Err(invalid_input(format!(
+                "The size of the BED `{}` doesn't match {} variants and {} \
+                 samples.", bed_filename, variants.len(), samples.len()
+            )));
+        }
+
+        Ok(Fileset { variants, samples })
+    }
+
+    fn chunk_size(&self) -> usize {
+        BedReader::get_chunk_size(self.samples.len() as u32)
+    }
+}
+
+
+// Append the content of `src` (after the first `skip` bytes) to `dst`. A
+// newline is added first to text files that don't end with one.
+fn append_file(src: &str, dst: &str, skip: u64, text: bool)
+    -> io::Result<()>
+{
+    let mut input = BufReader::new(File::open(src)?);
+    input.seek(SeekFrom::Start(skip))?;
+
+    if text {
+        let content = fs::read(dst)?;
+        if content.last().is_some_and(|&c| c != b'\n') {
+            OpenOptions::new().append(true).open(dst)?.write_all(b"\n")?;
+        }
+    }
+
+    let mut output = BufWriter::new(OpenOptions::new().append(true)
+                                                     .open(dst)?);
+    io::copy(&mut input, &mut output)?;
+    output.flush()
+}
+
+
+// Append the variants of `new_prefix` (genotyped on the same samples) to
+// the fileset `prefix`.
+pub fn append_variants(prefix: &str, new_prefix: &str)
+    -> io::Result<AppendReport>
+{
+    let fileset = Fileset::open(prefix)?;
+    let new = Fileset::open(new_prefix)?;
+
+    let same_samples = fileset.samples.len() == new.samples.len() &&
+        fileset.samples.iter()
+            .zip(new.samples.iter())
+            .all(|(a, b)| a.fid == b.fid && a.iid == b.iid);
+
+    if !same_samples {
+        return Err(invalid_input(format!(
+            "The samples of `{}` don't match the samples of `{}`.",
+            new_prefix, prefix
+        )));
+    }
+
+    let mut names: HashSet<&str> = fileset.variants.iter()
+        .map(|(v, _)| v.name.as_str())
+        .collect();
+
+    for (v, _) in new.variants.iter() {
+        if !names.insert(&v.name) {
+            return Err(invalid_input(format!(
+                "The variant `{}` of `{}` is already in `{}`.", v.name,
+                new_prefix, prefix
+            )));
+        }
+    }
+
+    append_file(&format!("{}.bed", new_prefix), &format!("{}.bed", prefix),
+                3, false)?;
+    append_file(&format!("{}.bim", new_prefix), &format!("{}.bim", prefix),
+                0, true)?;
+
+    for format in [IndexFormat::V1, IndexFormat::V2].iter() {
+        delete_index(prefix, *format)?;
+    }
+
+    Ok(AppendReport {
+        n_samples: fileset.samples.len(),
+        n_variants: fileset.variants.len() + new.variants.len()
+    })
+}
+
+
+// Calls of the `n_a` samples of a BED chunk followed by the calls of the
+// `n_b` samples of another chunk.
+fn concat_chunks(a: &[u8], n_a: usize, b: &[u8], n_b: usize,
+                 out: &mut Vec<u8>)
+{
+    out.clear();
+    out.extend_from_slice(a);
+
+    if n_a.is_multiple_of(4) {
+        out.extend_from_slice(b);
+        return;
+    }
+
+    // Clear the padding of the last byte.
+    let last = out.len() - 1;
+    out[last] &= (1 << (2 * (n_a % 4))) - 1;
+
+    for i in 0..n_b {
+        let code = (b[i / 4] >> (2 * (i % 4))) & 0b11;
+        let j = n_a + i;
+        if j / 4 == out.len() {
+            out.push(0);
+        }
+        out[j / 4] |= code << (2 * (j % 4));
+    }
+}
+
+
+// Append the samples of `new_prefix` (genotyped on the same variants, in
+// the same order) to the fileset `prefix`.
+pub fn append_samples(prefix: &str, new_prefix: &str)
+    -> io::Result<AppendReport>
+{
+    let fileset = Fileset::open(prefix)?;
+    let new = Fileset::open(new_prefix)?;
+
+    if fileset.variants.len() != new.variants.len() {
+        return Err(invalid_input(format!(
+            "`{}` has {} variants but `{}` has {}.", new_prefix,
+            new.variants.len(), prefix, fileset.variants.len()
+        )));
+    }
+
+    for ((v, a1), (new_v, new_a1)) in fileset.variants.iter()
+        .zip(new.variants.iter())
+    {
+        let same = v.name == new_v.name && v.chrom == new_v.chrom &&
+                   v.position == new_v.position &&
+                   v.alleles == new_v.alleles && a1 == new_a1;
+
+        if !same {
+            return Err(invalid_input(format!(
+                "The variant `{}` of `{}` doesn't match the variant `{}` of \
+                 `{}`.", new_v.name, new_prefix, v.name, prefix
+            )));
+        }
+    }
+
+    let mut ids: HashSet<(&str, &str)> = fileset.samples.iter()
+        .map(|s| (s.fid.as_str(), s.iid.as_str()))
+        .collect();
+
+    for s in new.samples.iter() {
+        if !ids.insert((&s.fid, &s.iid)) {
+            return Err(invalid_input(format!(
+                "The sample `{} {}` of `{}` is already in `{}`.", s.fid,
+                s.iid, new_prefix, prefix
+            )));
+        }
+    }
+
+    // Interleave the calls into a new BED that replaces the current one.
+    let bed_filename = format!("{}.bed", prefix);
+    let tmp_filename = format!("{}.tmp", bed_filename);
+    {
+        let mut bed = BufReader::new(File::open(&bed_filename)?);
+        let mut new_bed = BufReader::new(File::open(format!("{}.bed",
+                                                            new_prefix))?);
+        bed.seek(SeekFrom::Start(3))?;
+        new_bed.seek(SeekFrom::Start(3))?;
+
+        let mut out = BufWriter::new(File::create(&tmp_filename)?);
+        out.write_all(&[0x6c, 0x1b, 0x01])?;
+
+        let mut chunk = vec![0; fileset.chunk_size()];
+        let mut new_chunk = vec![0; new.chunk_size()];
+        let mut merged = Vec::new();
+
+        for _ in 0..fileset.variants.len() {
+            bed.read_exact(&mut chunk)?;
+            new_bed.read_exact(&mut new_chunk)?;
+
+            concat_chunks(&chunk, fileset.samples.len(), &new_chunk,
+                          new.samples.len(), &mut merged);
+            out.write_all(&merged)?;
+        }
+
+        out.flush()?;
+    }
+    fs::rename(&tmp_filename, &bed_filename)?;
+
+    append_file(&format!("{}.fam", new_prefix), &format!("{}.fam", prefix),
+                0, true)?;
+
+    Ok(AppendReport {
+        n_samples: fileset.samples.len() + new.samples.len(),
+        n_variants: fileset.variants.len()
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Genotypes, Sex};
+    use crate::plink::{PlinkReader, PlinkWriter};
+
+    fn samples(ids: &[&str]) -> Vec<Sample> {
+        ids.iter()
+            .map(|iid| Sample::new(iid.to_string(), iid.to_string(),
+                                   Sex::Unknown))
+            .collect()
+    }
+
+    fn write(prefix: &str, samples: &[Sample], variants: &[(&str, u32)],
+             calls: &[Vec<Option<u8>>])
+    {
+        let mut writer = PlinkWriter::new(prefix, samples).unwrap();
+        for ((name, pos), calls) in variants.iter().zip(calls.iter()) {
+            let v = Variant::new(name.to_string(), "1".to_string(), *pos,
+                                 ("A".to_string(), "G".to_string()));
+            writer.write(&Genotypes::new(v, calls.clone(), "G")).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn read(prefix: &str) -> (Vec<Sample>, Vec<Vec<Option<u8>>>) {
+        let open = |ext: &str| {
+            File::open(format!("{}.{}", prefix, ext)).unwrap()
+        };
+        let reader = PlinkReader::from_readers(open("bed"), open("bim"),
+                                               open("fam")).unwrap();
+        (reader.samples().to_vec(), reader.map(|g| g.genotypes).collect())
+    }
+
+    #[test]
+    fn test_concat_chunks() {
+        let mut out = Vec::new();
+        // 3 samples (0b10_00_11) then 2 samples (0b01_00).
+        concat_chunks(&[0b11_10_00_11], 3, &[0b0000_0100], 2, &mut out);
+        assert_eq!(out, vec![0b00_10_00_11, 0b01]);
+    }
+
+    #[test]
+    fn test_append() {
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_append_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let base = samples(&["s1", "s2", "s3"]);
+        write(&prefix("a"), &base, &[("rs1", 100), ("rs2", 200)],
+              &[vec![Some(0), Some(1), Some(2)],
+                vec![None, Some(2), Some(1)]]);
+        write(&prefix("variants"), &base, &[("rs3", 300)],
+              &[vec![Some(2), Some(2), None]]);
+
+        let report = append_variants(&prefix("a"), &prefix("variants"))
+            .unwrap();
+        assert_eq!(report, AppendReport { n_samples: 3, n_variants: 3 });
+
+        // The variants are already in the fileset.
+        assert!(append_variants(&prefix("a"), &prefix("variants")).is_err());
+
+        write(&prefix("samples"), &samples(&["s4", "s5"]),
+              &[("rs1", 100), ("rs2", 200), ("rs3", 300)],
+              &[vec![Some(1), None], vec![Some(0), Some(0)],
+                vec![Some(2), Some(1)]]);
+
+        let report = append_samples(&prefix("a"), &prefix("samples"))
+            .unwrap();
+        assert_eq!(report, AppendReport { n_samples: 5, n_variants: 3 });
+
+        let (samples, calls) = read(&prefix("a"));
+        assert_eq!(samples[3].iid, "s4");
+        assert_eq!(calls, vec![
+            vec![Some(0), Some(1), Some(2), Some(1), None],
+            vec![None, Some(2), Some(1), Some(0), Some(0)],
+            vec![Some(2), Some(2), None, Some(2), Some(1)]
+        ]);
+
+        // The samples are already in the fileset and the variants differ.
+        assert!(append_samples(&prefix("a"), &prefix("samples")).is_err());
+        assert!(append_samples(&prefix("a"), &prefix("variants")).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
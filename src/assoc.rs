@@ -0,0 +1,258 @@
+/*!
+ * Single variant association tests.
+ *
+ * The phenotype is regressed on the additive coding of the genotypes (number
+ * of copies of the coded allele) and on the covariates, using the samples
+ * without missing values. Quantitative phenotypes use a linear model (t test
+ * of the genotype coefficient) and binary phenotypes a logistic model fitted
+ * by iteratively reweighted least squares (Wald test).
+ *
+ * The genotypes are used as given: on the X chromosome, the callers code the
+ * males 0 or 1 first (see `sexchrom::hemizygous_coding`).
+ */
+
+use std::str::FromStr;
+
+use ndarray::{Array1, Array2};
+
+use crate::core::{Genotypes, MissingGenotypesError, MissingPolicy};
+use crate::linalg::{invert, solve};
+use crate::stats::{normal_sf, student_t_two_sided};
+
+
+const MAX_LOGISTIC_ITERATIONS: usize = 25;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    Linear,
+    Logistic
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Model, String> {
+        match s {
+            "linear" => Ok(Model::Linear),
+            "logistic" => Ok(Model::Logistic),
+            _ => Err(format!("Unknown model `{}` (expected linear or \
+                              logistic).", s))
+        }
+    }
+}
+
+
+// Estimate of a coefficient with its standard error, test statistic (t or
+// z) and p-value.
+#[derive(Clone, Copy, Debug)]
+pub struct Estimate {
+    pub beta: f64,
+    pub se: f64,
+    pub stat: f64,
+    pub p: f64
+}
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct AssocResult {
+    pub n_obs: usize,
+    // None if the model could not be fitted (e.g. monomorphic variant or
+    // perfect separation).
+    pub estimate: Option<Estimate>
+}
+
+
+// Linear regression of y on X. Returns the coefficients and their standard
+// errors.
+pub fn linear_regression(x: &Array2<f64>, y: &Array1<f64>)
+    -> Option<(Array1<f64>, Array1<f64>)>
+{
+    let (n, p) = (x.rows(), x.cols());
+    if n <= p {
+        return None;
+    }
+
+    let xtx_inv = invert(&x.t().dot(x))?;
+    let beta = xtx_inv.dot(&x.t().dot(y));
+
+    let residuals = y - &x.dot(&beta);
+    let sigma2 = residuals.dot(&residuals) / (n - p) as f64;
+
+    let se = xtx_inv.diag().mapv(|v| (v * sigma2).sqrt());
+    Some((beta, se))
+}
+
+
+// Logistic regression of y (0 or 1) on X using Newton's method. Returns the
+// coefficients and their standard errors, or None if the fit doesn't
+// converge.
+pub fn logistic_regression(x: &Array2<f64>, y: &Array1<f64>)
+    -> Option<(Array1<f64>, Array1<f64>)>
+{
+    let (n, p) = (x.rows(), x.cols());
+    let mut beta = Array1::zeros(p);
+
+    for _ in 0..MAX_LOGISTIC_ITERATIONS {
+        let mu = x.dot(&beta).mapv(|eta: f64| 1.0 / (1.0 + (-eta).exp()));
+
+        // Information matrix X'WX.
+        let mut xw = x.to_owned();
+        for i in 0..n {
+            let w = mu[i] * (1.0 - mu[i]);
+            xw.row_mut(i).mapv_inplace(|v| v * w);
+        }
+        let information = x.t().dot(&xw);
+
+        let gradient = x.t().dot(&(y - &mu));
+        let step = solve(&information, &gradient)?;
+        beta += &step;
+
+        if step.iter().all(|s| s.abs() < 1e-8) {
+            let se = invert(&information)?.diag().mapv(f64::sqrt);
+            return Some((beta, se));
+        }
+    }
+
+    None
+}
+
+
+// Test the association between the genotypes and the phenotype given the
+// covariates (one vector per covariate, in the order of the samples). The
+// samples with missing genotypes are dropped.
+pub fn test_association(g: &Genotypes, phenotype: &[Option<f64>],
+                        covariates: &[Vec<Option<f64>>], model: Model)
+    -> AssocResult
+{
+    test_association_with(g, phenotype, covariates, model,
+                          MissingPolicy::Drop)
+        .expect("Dropping the missing genotypes can't fail.")
+}
+
+
+// See `test_association`, with the missing genotypes handled according to
+// the policy.
+pub fn test_association_with(g: &Genotypes, phenotype: &[Option<f64>],
+                             covariates: &[Vec<Option<f64>>], model: Model,
+                             missing: MissingPolicy)
+    -> Result<AssocResult, MissingGenotypesError>
+{
+    let mut rows: Vec<f64> = Vec::new();
+    let mut y: Vec<f64> = Vec::new();
+
+    for (i, (geno, pheno)) in g.values(missing)?
+        .into_iter()
+        .zip(phenotype.iter())
+        .enumerate()
+    {
+        let covars: Option<Vec<f64>> = covariates.iter()
+            .map(|c| c[i])
+            .collect();
+
+        if let (Some(geno), Some(pheno), Some(covars)) = (geno, pheno,
+                                                          covars)
+        {
+            rows.push(1.0);
+            rows.push(geno);
+            rows.extend(covars);
+            y.push(*pheno);
+        }
+    }
+
+    let n_obs = y.len();
+    let x = Array2::from_shape_vec((n_obs, 2 + covariates.len()), rows)
+        .unwrap();
+    let y = Array1::from_vec(y);
+
+    let fit = match model {
+        Model::Linear => linear_regression(&x, &y),
+        Model::Logistic => logistic_regression(&x, &y)
+    };
+
+    // The genotype is the second column of the design matrix.
+    let estimate = fit.and_then(|(beta, se)| {
+        let (beta, se) = (beta[1], se[1]);
+        if !se.is_finite() || se <= 0.0 {
+            return None;
+        }
+
+        let stat = beta / se;
+        let p = match model {
+            Model::Linear => {
+                let df = (n_obs - x.cols()) as f64;
+                student_t_two_sided(stat, df)
+            },
+            Model::Logistic => 2.0 * normal_sf(stat.abs())
+        };
+
+        Some(Estimate { beta, se, stat, p })
+    });
+
+    Ok(AssocResult { n_obs, estimate })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Variant;
+
+    fn genotypes(calls: Vec<Option<u8>>) -> Genotypes {
+        let v = Variant::new("rs1".to_string(), "1".to_string(), 1,
+                             ("A".to_string(), "G".to_string()));
+        Genotypes::new(v, calls, "G")
+    }
+
+    #[test]
+    fn test_linear() {
+        let g = genotypes(vec![Some(0), Some(1), Some(2), Some(0), Some(1),
+                               Some(2), None]);
+        let y = vec![Some(1.0), Some(2.5), Some(3.0), Some(0.5), Some(2.0),
+                     Some(3.5), Some(10.0)];
+        let covar = vec![vec![Some(1.0), Some(0.0), Some(1.0), Some(0.0),
+                              Some(1.0), Some(0.0), Some(1.0)]];
+
+        let result = test_association(&g, &y, &covar, Model::Linear);
+        assert_eq!(result.n_obs, 6);
+
+        let imputed = test_association_with(&g, &y, &covar, Model::Linear,
+                                            MissingPolicy::MeanImpute)
+            .unwrap();
+        assert_eq!(imputed.n_obs, 7);
+        assert!(test_association_with(&g, &y, &covar, Model::Linear,
+                                      MissingPolicy::Fail).is_err());
+
+        let e = result.estimate.unwrap();
+        assert!((e.beta - 1.25).abs() < 1e-10);
+        assert!((e.se - 0.186_339).abs() < 1e-6);
+        assert!((e.stat - 6.708_204).abs() < 1e-6);
+        assert!((e.p - 0.006_760_1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_logistic() {
+        let g = genotypes(vec![Some(0), Some(0), Some(1), Some(1), Some(2),
+                               Some(2), Some(0), Some(1)]);
+        let y: Vec<Option<f64>> = [0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 1.0, 0.0]
+            .iter()
+            .map(|&v| Some(v))
+            .collect();
+
+        let e = test_association(&g, &y, &[], Model::Logistic)
+            .estimate
+            .unwrap();
+
+        assert!((e.beta - 1.438_121).abs() < 1e-5);
+        assert!((e.se - 1.151_295).abs() < 1e-5);
+
+        // Perfect separation doesn't converge.
+        let y: Vec<Option<f64>> = [0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 0.0, 1.0]
+            .iter()
+            .map(|&v| Some(v))
+            .collect();
+        assert!(test_association(&g, &y, &[], Model::Logistic)
+                .estimate
+                .is_none());
+    }
+}
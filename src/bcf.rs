@@ -0,0 +1,591 @@
+/*!
+ * Reader for BCF (binary VCF, version 2.2) files.
+ *
+ * Records are decoded natively and converted to `Genotypes` like the
+ * `VcfReader` does (see the `vcf` module). Compressed BCFs are decompressed
+ * using bgzip. Region queries scan the file because CSI indexes are not
+ * supported.
+ */
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+
+use crate::core::{Chromosome, Genotypes, Sample, Sex, Variant};
+use crate::source::RegionPage;
+use crate::vcf;
+
+
+// Typed value types from the BCF specification.
+const BCF_MISSING: u8 = 0;
+const BCF_INT8: u8 = 1;
+const BCF_INT16: u8 = 2;
+const BCF_INT32: u8 = 3;
+const BCF_FLOAT: u8 = 5;
+const BCF_CHAR: u8 = 7;
+
+
+fn type_size(ty: u8) -> usize {
+    match ty {
+        BCF_MISSING => 0,
+        BCF_INT8 | BCF_CHAR => 1,
+        BCF_INT16 => 2,
+        BCF_INT32 | BCF_FLOAT => 4,
+        _ => panic!("Unknown BCF type: {}", ty)
+    }
+}
+
+
+// Cursor over the bytes of a record.
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize
+}
+
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, n: usize) -> &'a [u8] {
+        if self.pos + n > self.buf.len() {
+            panic!("Truncated BCF record.");
+        }
+
+        let bytes = &self.buf[self.pos..(self.pos + n)];
+        self.pos += n;
+        bytes
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.bytes(1)[0]
+    }
+
+    fn i32(&mut self) -> i32 {
+        let b = self.bytes(4);
+        i32::from_le_bytes([b[0], b[1], b[2], b[3]])
+    }
+
+    // Integer of the given type, sign extended.
+    fn int(&mut self, ty: u8) -> i32 {
+        let b = self.bytes(type_size(ty));
+        match ty {
+            BCF_INT8 => i32::from(b[0] as i8),
+            BCF_INT16 => i32::from(i16::from_le_bytes([b[0], b[1]])),
+            BCF_INT32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            _ => panic!("Expected an integer type in BCF but got {}.", ty)
+        }
+    }
+
+    // Type and number of values of a typed value.
+    fn descriptor(&mut self) -> (u8, usize) {
+        let byte = self.u8();
+        let ty = byte & 0x0f;
+        let mut n = usize::from(byte >> 4);
+
+        // The count overflows to a typed integer.
+        if n == 15 {
+            let (count_ty, _) = self.descriptor();
+            n = self.int(count_ty) as usize;
+        }
+
+        (ty, n)
+    }
+
+    fn typed_int(&mut self) -> i32 {
+        let (ty, _) = self.descriptor();
+        self.int(ty)
+    }
+
+    fn typed_string(&mut self) -> String {
+        let (ty, n) = self.descriptor();
+        let bytes = self.bytes(n * type_size(ty));
+
+        String::from_utf8_lossy(bytes)
+            .trim_end_matches('\0')
+            .to_string()
+    }
+}
+
+
+// Sentinel marking the end of a vector shorter than the declared length
+// (e.g. haploid calls in a diploid GT vector).
+fn is_vector_end(ty: u8, value: i32) -> bool {
+    match ty {
+        BCF_INT8 => value == -127,
+        BCF_INT16 => value == -32_767,
+        _ => value == -2_147_483_647
+    }
+}
+
+
+// Parse the GT values of a sample into the number of ALT alleles (None if
+// missing) and the ploidy. Alleles are coded as (allele + 1) << 1 | phased
+// with 0 for a missing allele.
+fn decode_gt(cursor: &mut Cursor, ty: u8, n: usize) -> (Option<u8>, u8) {
+    let mut n_alt = 0;
+    let mut ploidy = 0;
+    let mut missing = false;
+
+    for _ in 0..n {
+        let value = cursor.int(ty);
+        if is_vector_end(ty, value) {
+            continue;
+        }
+
+        ploidy += 1;
+        match (value >> 1) - 1 {
+            0 => {},
+            1 => n_alt += 1,
+            _ => missing = true
+        }
+    }
+
+    (if missing { None } else { Some(n_alt) }, ploidy)
+}
+
+
+// Dictionaries from the header (IDX attributes are respected).
+struct BcfHeader {
+    contigs: Vec<String>,
+    strings: HashMap<usize, String>,
+    samples: Vec<Sample>
+}
+
+
+fn header_id_and_idx(line: &str) -> Option<(String, Option<usize>)> {
+    let start = line.find('<')? + 1;
+    let end = line.rfind('>')?;
+
+    let mut id = None;
+    let mut idx = None;
+    for field in line[start..end].split(',') {
+        if let Some(value) = field.strip_prefix("ID=") {
+            id = Some(value.to_string());
+        } else if let Some(value) = field.strip_prefix("IDX=") {
+            idx = value.parse().ok();
+        }
+    }
+
+    Some((id?, idx))
+}
+
+
+fn parse_header(text: &str) -> BcfHeader {
+    let mut contigs = Vec::new();
+
+    // PASS is always the first string.
+    let mut strings = HashMap::new();
+    strings.insert(0, "PASS".to_string());
+    let mut next_string = 1;
+
+    let mut samples = Vec::new();
+
+    for line in text.lines() {
+        if line.starts_with("##contig=") {
+            let (id, idx) = header_id_and_idx(line)
+                .expect("Invalid contig line in BCF header.");
+            let idx = idx.unwrap_or(contigs.len());
+
+            if contigs.len() <= idx {
+                contigs.resize(idx + 1, String::new());
+            }
+            contigs[idx] = id;
+        }
+
+        else if ["##FILTER=", "##INFO=", "##FORMAT="].iter()
+                .any(|prefix| line.starts_with(prefix))
+        {
+            let (id, idx) = header_id_and_idx(line)
+                .expect("Invalid line in BCF header.");
+
+            if strings.values().any(|s| *s == id) {
+                continue;
+            }
+
+            let idx = idx.unwrap_or(next_string);
+            next_string = next_string.max(idx + 1);
+            strings.insert(idx, id);
+        }
+
+        else if line.starts_with("#CHROM") {
+            samples = line.split('\t')
+                .skip(9)
+                .map(|id| Sample::new(id.to_string(), id.to_string(),
+                                      Sex::Unknown))
+                .collect();
+        }
+    }
+
+    BcfHeader { contigs, strings, samples }
+}
+
+
+pub struct BcfReader<R: Read> {
+    reader: R,
+    filename: Option<String>,
+    // Decompression process for compressed BCFs.
+    bgzip: Option<Child>,
+    header: BcfHeader,
+    samples: Arc<Vec<Sample>>,
+    attach_samples: bool,
+    n_read: u64,
+    n_skipped: u64
+}
+
+
+impl BcfReader<Box<dyn Read>> {
+    // Open a BCF. Compressed BCFs (the default for bcftools) are detected
+    // from the gzip magic number.
+    pub fn new(filename: &str) -> BcfReader<Box<dyn Read>> {
+        let mut f = File::open(filename)
+            .unwrap_or_else(|_| panic!("Could not open BCF: `{}`", filename));
+
+        let mut magic = [0; 2];
+        let compressed = f.read_exact(&mut magic).is_ok() &&
+                         magic == [0x1f, 0x8b];
+
+        let mut reader = if compressed {
+            let mut bgzip = Command::new("bgzip")
+                .args(["-dc", filename])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("Couldn't spawn bgzip to read the BCF.");
+
+            let stdout = bgzip.stdout.take()
+                .expect("Could not get bgzip stdout.");
+
+            let mut reader = BcfReader::from_reader(
+                Box::new(BufReader::new(stdout)) as Box<dyn Read>
+            );
+            reader.bgzip = Some(bgzip);
+            reader
+        } else {
+            let f = File::open(filename).unwrap();
+            BcfReader::from_reader(Box::new(BufReader::new(f)) as Box<dyn Read>)
+        };
+
+        reader.filename = Some(filename.to_string());
+        reader
+    }
+}
+
+
+impl<R: Read> BcfReader<R> {
+    // Create a reader from uncompressed BCF data.
+    pub fn from_reader(mut reader: R) -> BcfReader<R> {
+        let mut magic = [0; 5];
+        reader.read_exact(&mut magic).expect("Could not read BCF.");
+        if &magic[..3] != b"BCF" || magic[3] != 2 {
+            panic!("The provided file is not in the BCF v2 format (according \
+                    to the magic number)");
+        }
+
+        let mut l_text = [0; 4];
+        reader.read_exact(&mut l_text).expect("Could not read BCF header.");
+
+        let mut text = vec![0; u32::from_le_bytes(l_text) as usize];
+        reader.read_exact(&mut text).expect("Could not read BCF header.");
+
+        let header = parse_header(&String::from_utf8_lossy(&text));
+        let samples = Arc::new(header.samples.clone());
+
+        BcfReader {
+            reader,
+            filename: None,
+            bgzip: None,
+            header,
+            samples,
+            attach_samples: false,
+            n_read: 0,
+            n_skipped: 0
+        }
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn attach_samples(&mut self, attach: bool) {
+        self.attach_samples = attach;
+    }
+
+    // Number of records that were skipped because they are not biallelic.
+    pub fn n_skipped(&self) -> u64 {
+        self.n_skipped
+    }
+
+    // Read the shared and individual parts of the next record.
+    fn read_record(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let mut lengths = [0; 8];
+        match self.reader.read_exact(&mut lengths) {
+            Ok(()) => {},
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => {
+                return None;
+            },
+            Err(e) => panic!("Could not read from BCF: {}", e)
+        }
+
+        let l_shared = u32::from_le_bytes(
+            [lengths[0], lengths[1], lengths[2], lengths[3]]
+        );
+        let l_indiv = u32::from_le_bytes(
+            [lengths[4], lengths[5], lengths[6], lengths[7]]
+        );
+
+        let mut shared = vec![0; l_shared as usize];
+        let mut indiv = vec![0; l_indiv as usize];
+        self.reader.read_exact(&mut shared)
+            .and_then(|_| self.reader.read_exact(&mut indiv))
+            .expect("Could not read bytes (the BCF may be truncated).");
+
+        Some((shared, indiv))
+    }
+
+    fn parse_record(&self, shared: &[u8], indiv: &[u8]) -> Option<Genotypes> {
+        let mut cursor = Cursor { buf: shared, pos: 0 };
+
+        let chrom_idx = cursor.i32() as usize;
+        let pos = cursor.i32() + 1;
+        cursor.bytes(8); // rlen and QUAL
+        let n_allele = cursor.i32() as u32 >> 16;
+        let n_fmt_sample = cursor.i32() as u32;
+        let name = cursor.typed_string();
+
+        if n_allele != 2 {
+            return None;
+        }
+
+        let reference = cursor.typed_string();
+        let alt = cursor.typed_string();
+
+        let chrom = self.header.contigs.get(chrom_idx)
+            .unwrap_or_else(|| panic!("Unknown contig index {} in BCF.",
+                                      chrom_idx));
+
+        let name = if name == "." || name.is_empty() {
+            format!("{}:{}", chrom, pos)
+        } else {
+            name
+        };
+
+        let location = format!("record {} of the BCF", self.n_read);
+        let variant = vcf::build_variant(&name, chrom, &pos.to_string(),
+                                         &reference, &alt, &location)?;
+
+        let n_fmt = n_fmt_sample >> 24;
+        let n_samples = (n_fmt_sample & 0x00ff_ffff) as usize;
+        if n_samples != self.samples.len() {
+            panic!("Expected {} samples but found {} in {}.",
+                   self.samples.len(), n_samples, location);
+        }
+
+        let mut cursor = Cursor { buf: indiv, pos: 0 };
+        for _ in 0..n_fmt {
+            let key = cursor.typed_int() as usize;
+            let (ty, n) = cursor.descriptor();
+
+            if self.header.strings.get(&key).map(|s| s.as_str()) != Some("GT") {
+                cursor.bytes(n * type_size(ty) * n_samples);
+                continue;
+            }
+
+            let calls = (0..n_samples)
+                .map(|_| decode_gt(&mut cursor, ty, n))
+                .collect();
+
+            let g = vcf::make_genotypes(variant, &alt, calls);
+            return if self.attach_samples {
+                Some(g.with_samples(Arc::clone(&self.samples)))
+            } else {
+                Some(g)
+            };
+        }
+
+        panic!("No GT field in {}.", location);
+    }
+
+    // Make sure that the decompression completed successfully.
+    fn _check_termination(&mut self) {
+        if let Some(mut bgzip) = self.bgzip.take() {
+            let status = bgzip.wait().expect("Error waiting for bgzip.");
+            if !status.success() {
+                panic!("Error decompressing the BCF using bgzip.");
+            }
+        }
+    }
+
+    // Variants in the region, found by scanning the whole file.
+    pub fn get_variants_in_region(&self, chrom: &Chromosome, start: u32,
+                                  end: u32) -> Vec<Genotypes>
+    {
+        let filename = self.filename.as_ref()
+            .expect("Region queries require a BCF opened from a file.");
+
+        let mut reader = BcfReader::new(filename);
+        reader.attach_samples(self.attach_samples);
+
+        reader
+            .filter(|g| {
+                g.variant.chrom == *chrom &&
+                g.variant.position >= start && g.variant.position <= end
+            })
+            .collect()
+    }
+
+    pub fn get_variants_in_region_page(&self, chrom: &Chromosome, start: u32,
+                                       end: u32, offset: usize, limit: usize)
+        -> RegionPage
+    {
+        let filename = self.filename.as_ref()
+            .expect("Region queries require a BCF opened from a file.");
+
+        let mut reader = BcfReader::new(filename);
+        reader.attach_samples(self.attach_samples);
+
+        let genotypes = reader
+            .filter(|g| {
+                g.variant.chrom == *chrom &&
+                g.variant.position >= start && g.variant.position <= end
+            })
+            .skip(offset);
+
+        RegionPage::collect(genotypes, offset, limit)
+    }
+
+    pub fn get_variant_genotypes(&self, v: &Variant) -> Option<Genotypes> {
+        let mut matches = self.get_variants_in_region(&v.chrom, v.position,
+                                                      v.position);
+        matches.retain(|g| g.variant == *v);
+
+        match matches.len() {
+            0 => None,
+            1 => matches.pop(),
+            _ => panic!("There are duplicate variants in the BCF.")
+        }
+    }
+}
+
+
+impl<R: Read> Iterator for BcfReader<R> {
+    type Item = Genotypes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (shared, indiv) = match self.read_record() {
+                Some(record) => record,
+                None => {
+                    self._check_termination();
+                    return None;
+                }
+            };
+
+            self.n_read += 1;
+
+            match self.parse_record(&shared, &indiv) {
+                Some(g) => return Some(g),
+                None => self.n_skipped += 1
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "\
+##fileformat=VCFv4.2
+##FILTER=<ID=PASS,Description=\"All filters passed\">
+##contig=<ID=1>
+##contig=<ID=MT>
+##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">
+##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ts1\ts2\ts3
+";
+
+    fn typed_string(s: &str) -> Vec<u8> {
+        let mut bytes = vec![((s.len() as u8) << 4) | BCF_CHAR];
+        bytes.extend(s.as_bytes());
+        bytes
+    }
+
+    // Encode a record with a DP and a GT (int8) field.
+    fn record(chrom: i32, pos: i32, id: &str, alleles: &[&str],
+              gt: &[[i8; 2]]) -> Vec<u8>
+    {
+        let mut shared = Vec::new();
+        shared.extend(&chrom.to_le_bytes());
+        shared.extend(&(pos - 1).to_le_bytes());
+        shared.extend(&1_i32.to_le_bytes());
+        shared.extend(&0_f32.to_le_bytes());
+        shared.extend(&((alleles.len() as u32) << 16).to_le_bytes());
+        shared.extend(&((2_u32 << 24) | gt.len() as u32).to_le_bytes());
+        shared.extend(typed_string(id));
+        for allele in alleles {
+            shared.extend(typed_string(allele));
+        }
+
+        // DP is string 1 and GT is string 2.
+        let mut indiv = vec![0x11, 1, 0x11];
+        indiv.extend(gt.iter().map(|_| 10));
+        indiv.extend(&[0x11, 2, 0x21]);
+        for call in gt {
+            indiv.extend(call.iter().map(|&x| x as u8));
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend(&(shared.len() as u32).to_le_bytes());
+        bytes.extend(&(indiv.len() as u32).to_le_bytes());
+        bytes.extend(shared);
+        bytes.extend(indiv);
+        bytes
+    }
+
+    fn get_bcf() -> Vec<u8> {
+        let mut text = HEADER.as_bytes().to_vec();
+        text.push(0);
+
+        let mut bcf = b"BCF\x02\x02".to_vec();
+        bcf.extend(&(text.len() as u32).to_le_bytes());
+        bcf.extend(text);
+
+        // 0/0, 0|1, 1/1
+        bcf.extend(record(0, 100, "rs1", &["A", "G"],
+                          &[[2, 2], [2, 5], [4, 4]]));
+        // Multiallelic, skipped.
+        bcf.extend(record(0, 200, ".", &["A", "G", "T"],
+                          &[[2, 2], [2, 2], [2, 2]]));
+        // Haploid: 1, 0, missing.
+        bcf.extend(record(1, 300, ".", &["C", "T"],
+                          &[[4, -127], [2, -127], [0, -127]]));
+
+        bcf
+    }
+
+    #[test]
+    fn test_read() {
+        let bcf = get_bcf();
+        let mut reader = BcfReader::from_reader(&bcf[..]);
+
+        let iids: Vec<&str> = reader.samples().iter()
+            .map(|s| s.iid.as_str())
+            .collect();
+        assert_eq!(iids, vec!["s1", "s2", "s3"]);
+
+        let genotypes: Vec<Genotypes> = reader.by_ref().collect();
+        assert_eq!(genotypes.len(), 2);
+        assert_eq!(reader.n_skipped(), 1);
+
+        let g = &genotypes[0];
+        assert_eq!(g.variant.name, "rs1");
+        assert_eq!(g.variant.position, 100);
+        assert_eq!(g.coded_allele(), "G");
+        assert_eq!(g.genotypes, vec![Some(0), Some(1), Some(2)]);
+
+        let g = &genotypes[1];
+        assert_eq!(g.variant.name, "MT:300");
+        assert!(g.is_haploid());
+        assert_eq!(g.genotypes, vec![Some(1), Some(0), None]);
+    }
+}
@@ -0,0 +1,969 @@
+/*!
+ * Reader for BGEN files (versions 1.2 and 1.3, i.e. layout 2).
+ *
+ * The genotype probabilities are converted to the expected number of copies
+ * of the second allele. Only biallelic variants are supported: the other
+ * variants are skipped unless they are split into biallelic variants (see
+ * `split_multiallelic`). The phased calls can also be read as haplotypes
+ * (see `haplotypes`). Zstandard compressed files (BGEN 1.3) require the
+ * `zstd` feature.
+ *
+ * The writer produces zlib compressed BGEN 1.2 files with sample
+ * identifiers and 16 bits per probability. The coded allele of the dosages
+ * is written as the second allele, so reading the file back gives the same
+ * dosages.
+ */
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::iter;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use crate::core::{Dosages, Haplotypes, Sample, Sex};
+#[cfg(feature = "http")]
+use crate::http::HttpReader;
+use crate::vcf;
+
+
+fn read_u16<R: Read>(reader: &mut R) -> u16 {
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf).expect("Could not read from BGEN.");
+    u16::from_le_bytes(buf)
+}
+
+
+fn read_u32<R: Read>(reader: &mut R) -> u32 {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf).expect("Could not read from BGEN.");
+    u32::from_le_bytes(buf)
+}
+
+
+fn read_bytes<R: Read>(reader: &mut R, n: usize) -> Vec<u8> {
+    let mut buf = vec![0; n];
+    reader.read_exact(&mut buf).expect("Could not read from BGEN.");
+    buf
+}
+
+
+fn read_string<R: Read>(reader: &mut R, len: usize) -> String {
+    String::from_utf8_lossy(&read_bytes(reader, len)).to_string()
+}
+
+
+// Read `n_bits` (at most 32) starting at the given bit offset. Values are
+// packed little endian.
+fn read_bits(data: &[u8], bit_offset: usize, n_bits: u8) -> u64 {
+    let start = bit_offset / 8;
+
+    let mut value: u64 = 0;
+    for (i, byte) in data[start..].iter().take(5).enumerate() {
+        value |= u64::from(*byte) << (8 * i);
+    }
+
+    (value >> (bit_offset % 8)) & ((1 << n_bits) - 1)
+}
+
+
+// Copies of every allele in the unphased genotypes with the given ploidy, in
+// the order of their probabilities (colex order of the sorted alleles, e.g.
+// AA, AB, BB, AC, BC, CC).
+fn unphased_genotypes(n_alleles: usize, ploidy: usize) -> Vec<Vec<u8>> {
+    let mut genotypes: Vec<Vec<usize>> = vec![Vec::new()];
+    for _ in 0..ploidy {
+        genotypes = genotypes.iter()
+            .flat_map(|g| {
+                let first = g.last().copied().unwrap_or(0);
+                (first..n_alleles).map(move |allele| {
+                    let mut g = g.clone();
+                    g.push(allele);
+                    g
+                })
+            })
+            .collect();
+    }
+
+    genotypes.sort_by(|a, b| a.iter().rev().cmp(b.iter().rev()));
+
+    genotypes.iter()
+        .map(|g| {
+            (0..n_alleles)
+                .map(|allele| g.iter().filter(|&&a| a == allele).count() as u8)
+                .collect()
+        })
+        .collect()
+}
+
+
+// Probability data of a variant.
+struct Probabilities<'a> {
+    n_alleles: usize,
+    // Ploidy of every sample, with the missing flag (0x80).
+    ploidy: Vec<u8>,
+    phased: bool,
+    n_bits: u8,
+    // Packed values.
+    values: &'a [u8]
+}
+
+fn read_probabilities(data: &[u8], n_samples: usize) -> Probabilities<'_> {
+    let mut reader = data;
+    let n = read_u32(&mut reader) as usize;
+    let n_alleles = usize::from(read_u16(&mut reader));
+    let _ploidy_range = read_bytes(&mut reader, 2);
+
+    if n != n_samples || n_alleles < 2 {
+        panic!("Unexpected probability data in BGEN (n={}, k={}).",
+               n, n_alleles);
+    }
+
+    let ploidy = read_bytes(&mut reader, n);
+    let phased = read_bytes(&mut reader, 1)[0] == 1;
+    let n_bits = read_bytes(&mut reader, 1)[0];
+
+    Probabilities { n_alleles, ploidy, phased, n_bits, values: reader }
+}
+
+
+// Decode the probability data into dosages of every allele but the first,
+// i.e. the expected number of copies of the second allele for biallelic
+// variants.
+fn decode_dosages(data: &[u8], n_samples: usize) -> Vec<Vec<Option<f64>>> {
+    let Probabilities {
+        n_alleles, ploidy, phased, n_bits, values: reader
+    } = read_probabilities(data, n_samples);
+    let n = ploidy.len();
+    let max_value = ((1_u64 << n_bits) - 1) as f64;
+
+    // For unphased data, the stored values are the probabilities of the
+    // genotypes (the last one is implied). For phased data, they are the
+    // probabilities of the alleles on every haplotype (the last allele is
+    // implied).
+    let max_ploidy = ploidy.iter().map(|byte| byte & 0x3f).max().unwrap_or(0);
+    let genotypes: Vec<Vec<Vec<u8>>> = if phased {
+        Vec::new()
+    } else {
+        (0..=usize::from(max_ploidy))
+            .map(|z| unphased_genotypes(n_alleles, z))
+            .collect()
+    };
+
+    let mut dosages = vec![Vec::with_capacity(n); n_alleles - 1];
+    let mut bit_offset = 0;
+    for byte in ploidy.iter() {
+        let z = usize::from(byte & 0x3f);
+        let n_values = if phased {
+            z * (n_alleles - 1)
+        } else {
+            genotypes[z].len() - 1
+        };
+
+        let values: Vec<f64> = (0..n_values)
+            .map(|i| {
+                let v = read_bits(reader, bit_offset + i * n_bits as usize,
+                                  n_bits);
+                v as f64 / max_value
+            })
+            .collect();
+
+        bit_offset += n_values * n_bits as usize;
+
+        if byte & 0x80 != 0 {
+            for d in dosages.iter_mut() {
+                d.push(None);
+            }
+            continue;
+        }
+
+        // Expected number of copies of every allele.
+        let mut expected = vec![0.0; n_alleles];
+        if phased {
+            for haplotype in values.chunks(n_alleles - 1) {
+                let p_last = 1.0 - haplotype.iter().sum::<f64>();
+                for (e, p) in expected.iter_mut()
+                    .zip(haplotype.iter().chain(iter::once(&p_last)))
+                {
+                    *e += p;
+                }
+            }
+        } else {
+            let p_last = 1.0 - values.iter().sum::<f64>();
+            for (copies, p) in genotypes[z].iter()
+                .zip(values.iter().chain(iter::once(&p_last)))
+            {
+                for (e, &c) in expected.iter_mut().zip(copies.iter()) {
+                    *e += f64::from(c) * p;
+                }
+            }
+        }
+
+        for (d, e) in dosages.iter_mut().zip(expected[1..].iter()) {
+            d.push(Some(*e));
+        }
+    }
+
+    dosages
+}
+
+
+// Decode the probability data of a biallelic variant into phased calls
+// (copies of the second allele on the two haplotypes). The alleles with a
+// probability below `min_prob` are not called, and the calls of the samples
+// that aren't diploid with both alleles called are None (as are all the
+// calls of unphased variants).
+fn decode_haplotypes(data: &[u8], n_samples: usize, min_prob: f64)
+    -> Vec<Option<(u8, u8)>>
+{
+    let probabilities = read_probabilities(data, n_samples);
+    if probabilities.n_alleles != 2 {
+        panic!("Expected a biallelic variant to decode haplotypes.");
+    }
+
+    if !probabilities.phased {
+        return vec![None; n_samples];
+    }
+
+    let n_bits = usize::from(probabilities.n_bits);
+    let max_value = ((1_u64 << n_bits) - 1) as f64;
+
+    // Copies of the second allele given the probability of the first.
+    let call = |p: f64| {
+        if p >= min_prob {
+            Some(0)
+        } else if 1.0 - p >= min_prob {
+            Some(1)
+        } else {
+            None
+        }
+    };
+
+    let mut bit_offset = 0;
+    probabilities.ploidy.iter()
+        .map(|byte| {
+            let z = usize::from(byte & 0x3f);
+            let values: Vec<f64> = (0..z)
+                .map(|i| {
+                    let v = read_bits(probabilities.values,
+                                      bit_offset + i * n_bits,
+                                      probabilities.n_bits);
+                    v as f64 / max_value
+                })
+                .collect();
+
+            bit_offset += z * n_bits;
+
+            if byte & 0x80 != 0 || z != 2 {
+                return None;
+            }
+
+            Some((call(values[0])?, call(values[1])?))
+        })
+        .collect()
+}
+
+
+// Variant of the BGEN before the decompression of its probability data.
+struct Record {
+    name: String,
+    chrom: String,
+    position: u32,
+    alleles: Vec<String>,
+    block: Vec<u8>
+}
+
+
+pub struct BgenReader<R: Read> {
+    reader: R,
+    n_variants: u32,
+    compression: u32,
+    samples: Arc<Vec<Sample>>,
+    attach_samples: bool,
+    split_multiallelic: bool,
+    // Biallelic variants split from the last multiallelic variant.
+    pending: VecDeque<Dosages>,
+    n_read: u32,
+    n_skipped: u32
+}
+
+
+impl BgenReader<BufReader<File>> {
+    pub fn new(filename: &str) -> BgenReader<BufReader<File>> {
+        let f = File::open(filename)
+            .unwrap_or_else(|_| panic!("Could not open BGEN: `{}`", filename));
+
+        BgenReader::from_reader(BufReader::new(f))
+    }
+}
+
+
+// The file is streamed using range requests, so it can be read from a
+// `https://` (or, with the `object-store` feature, `s3://` or `gs://`) URL
+// without being downloaded first.
+#[cfg(feature = "http")]
+impl BgenReader<BufReader<HttpReader>> {
+    pub fn new_remote(url: &str) -> BgenReader<BufReader<HttpReader>> {
+        let reader = HttpReader::open(url)
+            .unwrap_or_else(|e| panic!("Could not open BGEN: {}", e));
+
+        BgenReader::from_reader(BufReader::new(reader))
+    }
+}
+
+
+impl<R: Read> BgenReader<R> {
+    pub fn from_reader(mut reader: R) -> BgenReader<R> {
+        let offset = read_u32(&mut reader) as usize;
+
+        let header_length = read_u32(&mut reader) as usize;
+        let n_variants = read_u32(&mut reader);
+        let n_samples = read_u32(&mut reader) as usize;
+
+        let magic = read_bytes(&mut reader, 4);
+        if magic != b"bgen" && magic != [0; 4] {
+            panic!("The provided file is not in the BGEN format (according \
+                    to the magic number)");
+        }
+
+        read_bytes(&mut reader, header_length - 20);
+        let flags = read_u32(&mut reader);
+
+        let compression = flags & 0b11;
+        let layout = (flags >> 2) & 0b1111;
+        if layout != 2 {
+            panic!("Only BGEN layout 2 (v1.2 and v1.3) is supported, got \
+                    layout {}.", layout);
+        }
+
+        let mut consumed = 4 + header_length;
+
+        // Sample identifiers are optional. Without them, samples are named
+        // after their index.
+        let samples = if flags >> 31 == 1 {
+            let block_length = read_u32(&mut reader) as usize;
+            read_u32(&mut reader);
+            consumed += block_length;
+
+            (0..n_samples)
+                .map(|_| {
+                    let len = read_u16(&mut reader) as usize;
+                    let id = read_string(&mut reader, len);
+                    Sample::new(id.clone(), id, Sex::Unknown)
+                })
+                .collect()
+        } else {
+            (0..n_samples)
+                .map(|i| Sample::new(format!("sample_{}", i),
+                                     format!("sample_{}", i),
+                                     Sex::Unknown))
+                .collect()
+        };
+
+        // Skip to the first variant.
+        read_bytes(&mut reader, (offset + 4).saturating_sub(consumed));
+
+        BgenReader {
+            reader,
+            n_variants,
+            compression,
+            samples: Arc::new(samples),
+            attach_samples: false,
+            split_multiallelic: false,
+            pending: VecDeque::new(),
+            n_read: 0,
+            n_skipped: 0
+        }
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn n_variants(&self) -> u32 {
+        self.n_variants
+    }
+
+    // If set, every Dosages produced by the reader will hold a reference to
+    // the samples (the other dosage readers have the same option).
+    pub fn attach_samples(&mut self, attach: bool) {
+        self.attach_samples = attach;
+    }
+
+    // If set, the multiallelic variants are split into biallelic variants,
+    // one per allele after the first (coded), instead of being skipped.
+    pub fn split_multiallelic(&mut self, split: bool) {
+        self.split_multiallelic = split;
+    }
+
+    // Number of variants that were skipped because they are not biallelic
+    // (unless split) or only have symbolic alleles.
+    pub fn n_skipped(&self) -> u32 {
+        self.n_skipped
+    }
+
+    fn decompress(&self, data: Vec<u8>) -> Vec<u8> {
+        if self.compression == 0 {
+            return data;
+        }
+
+        let mut reader = &data[..];
+        let length = read_u32(&mut reader) as usize;
+        let mut out = Vec::with_capacity(length);
+
+        match self.compression {
+            1 => {
+                ZlibDecoder::new(reader).read_to_end(&mut out)
+                    .expect("Could not decompress BGEN genotype block.");
+            },
+            #[cfg(feature = "zstd")]
+            2 => {
+                out = zstd::stream::decode_all(reader)
+                    .expect("Could not decompress BGEN genotype block.");
+            },
+            #[cfg(not(feature = "zstd"))]
+            2 => panic!("Reading zstd compressed BGEN files requires the \
+                         `zstd` feature."),
+            c => panic!("Unknown BGEN compression: {}", c)
+        }
+
+        if out.len() != length {
+            panic!("Expected {} bytes of genotype data but got {}.", length,
+                   out.len());
+        }
+
+        out
+    }
+
+    // Dosages of a variant: none if the variant is skipped, and one per
+    // allele after the first for the multiallelic variants that are split.
+    fn read_record(&mut self) -> Record {
+        let id_length = read_u16(&mut self.reader) as usize;
+        let id = read_string(&mut self.reader, id_length);
+        let rsid_length = read_u16(&mut self.reader) as usize;
+        let rsid = read_string(&mut self.reader, rsid_length);
+        let chrom_length = read_u16(&mut self.reader) as usize;
+        let chrom = read_string(&mut self.reader, chrom_length);
+        let position = read_u32(&mut self.reader);
+
+        let n_alleles = read_u16(&mut self.reader);
+        let alleles: Vec<String> = (0..n_alleles)
+            .map(|_| {
+                let len = read_u32(&mut self.reader) as usize;
+                read_string(&mut self.reader, len)
+            })
+            .collect();
+
+        let block_length = read_u32(&mut self.reader) as usize;
+        let block = read_bytes(&mut self.reader, block_length);
+
+        let name = if !rsid.is_empty() && rsid != "." {
+            rsid
+        } else if !id.is_empty() && id != "." {
+            id
+        } else {
+            format!("{}:{}", chrom, position)
+        };
+
+        Record { name, chrom, position, alleles, block }
+    }
+
+    fn read_variant(&mut self) -> Vec<Dosages> {
+        let Record { name, chrom, position, alleles, block } =
+            self.read_record();
+
+        let n_alleles = alleles.len();
+        if n_alleles < 2 || (n_alleles > 2 && !self.split_multiallelic) {
+            return Vec::new();
+        }
+
+        // The variants with symbolic alleles are None.
+        let location = format!("variant {} of the BGEN", self.n_read);
+        let variants: Vec<Option<_>> = alleles[1..].iter()
+            .map(|allele| {
+                vcf::build_variant(&name, &chrom, &position.to_string(),
+                                   &alleles[0], allele, &location)
+            })
+            .collect();
+
+        if variants.iter().all(|v| v.is_none()) {
+            return Vec::new();
+        }
+
+        let data = self.decompress(block);
+        let dosages = decode_dosages(&data, self.samples.len());
+
+        variants.into_iter()
+            .zip(dosages)
+            .zip(alleles[1..].iter())
+            .filter_map(|((variant, dosages), allele)| {
+                let d = Dosages::new(variant?, dosages, allele);
+                if self.attach_samples {
+                    Some(d.with_samples(Arc::clone(&self.samples)))
+                } else {
+                    Some(d)
+                }
+            })
+            .collect()
+    }
+
+    fn read_haplotypes(&mut self, min_prob: f64) -> Option<Haplotypes> {
+        let record = self.read_record();
+        if record.alleles.len() != 2 {
+            return None;
+        }
+
+        let location = format!("variant {} of the BGEN", self.n_read);
+        let variant = vcf::build_variant(&record.name, &record.chrom,
+                                         &record.position.to_string(),
+                                         &record.alleles[0],
+                                         &record.alleles[1], &location)?;
+
+        let data = self.decompress(record.block);
+        let haplotypes = decode_haplotypes(&data, self.samples.len(),
+                                           min_prob);
+
+        let h = Haplotypes::new(variant, haplotypes, &record.alleles[1]);
+        if self.attach_samples {
+            Some(h.with_samples(Arc::clone(&self.samples)))
+        } else {
+            Some(h)
+        }
+    }
+
+    // Iterate over the phased calls instead of the dosages (see
+    // `decode_haplotypes`, e.g. 0.9 for the most likely allele). Only the
+    // biallelic variants are read.
+    pub fn haplotypes(self, min_prob: f64) -> BgenHaplotypes<R> {
+        BgenHaplotypes { reader: self, min_prob }
+    }
+}
+
+
+pub struct BgenHaplotypes<R: Read> {
+    reader: BgenReader<R>,
+    min_prob: f64
+}
+
+impl<R: Read> BgenHaplotypes<R> {
+    pub fn n_skipped(&self) -> u32 {
+        self.reader.n_skipped
+    }
+}
+
+impl<R: Read> Iterator for BgenHaplotypes<R> {
+    type Item = Haplotypes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.reader.n_read < self.reader.n_variants {
+            let haplotypes = self.reader.read_haplotypes(self.min_prob);
+            self.reader.n_read += 1;
+
+            match haplotypes {
+                Some(h) => return Some(h),
+                None => self.reader.n_skipped += 1
+            }
+        }
+
+        None
+    }
+}
+
+
+impl<R: Read> Iterator for BgenReader<R> {
+    type Item = Dosages;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(d) = self.pending.pop_front() {
+                return Some(d);
+            }
+
+            if self.n_read == self.n_variants {
+                return None;
+            }
+
+            let dosages = self.read_variant();
+            self.n_read += 1;
+
+            if dosages.is_empty() {
+                self.n_skipped += 1;
+            }
+
+            self.pending.extend(dosages);
+        }
+    }
+}
+
+
+const WRITER_BITS: u8 = 16;
+
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+
+fn write_string_u16<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    if s.len() > usize::from(u16::MAX) {
+        return Err(invalid_input(format!(
+            "`{}` is too long to be written in a BGEN.", s
+        )));
+    }
+
+    out.write_all(&(s.len() as u16).to_le_bytes())?;
+    out.write_all(s.as_bytes())
+}
+
+
+// Encode dosages of the second allele as unphased diploid probability data.
+// The probabilities are the ones with the smallest variance given the
+// dosage, i.e. only two adjacent genotypes have a non-zero probability.
+fn encode_dosages(dosages: &[Option<f64>]) -> Vec<u8> {
+    let max_value = f64::from(u16::MAX);
+
+    let mut data = Vec::with_capacity(10 + dosages.len() * 5);
+    data.extend(&(dosages.len() as u32).to_le_bytes());
+    data.extend(&2_u16.to_le_bytes());
+    data.extend(&[2, 2]);
+    data.extend(dosages.iter().map(|d| if d.is_some() { 2 } else { 0x82 }));
+    data.extend(&[0, WRITER_BITS]);
+
+    for d in dosages {
+        // Probabilities of 0 and 1 copies (the last one is implied).
+        let (p0, p1) = match d {
+            Some(d) if *d <= 1.0 => (1.0 - d, *d),
+            Some(d) => (0.0, 2.0 - d),
+            None => (0.0, 0.0)
+        };
+
+        let p0 = (p0 * max_value).round() as u16;
+        let p1 = ((p1 * max_value).round() as u16).min(u16::MAX - p0);
+        data.extend(&p0.to_le_bytes());
+        data.extend(&p1.to_le_bytes());
+    }
+
+    data
+}
+
+
+// Writer for BGEN 1.2 files. As the number of variants is in the header, it
+// is updated when the writer is finished.
+pub struct BgenWriter<W: Write + Seek> {
+    out: W,
+    n_samples: usize,
+    n_variants: u32
+}
+
+impl BgenWriter<BufWriter<File>> {
+    pub fn create(filename: &str, samples: &[Sample])
+        -> io::Result<BgenWriter<BufWriter<File>>>
+    {
+        BgenWriter::new(BufWriter::new(File::create(filename)?), samples)
+    }
+}
+
+impl<W: Write + Seek> BgenWriter<W> {
+    // Write the header and the sample identifiers (the IIDs).
+    pub fn new(mut out: W, samples: &[Sample]) -> io::Result<BgenWriter<W>> {
+        let mut sample_block = Vec::new();
+        for s in samples {
+            write_string_u16(&mut sample_block, &s.iid)?;
+        }
+
+        let header_length: u32 = 20;
+        let sample_block_length = sample_block.len() as u32 + 8;
+        let flags: u32 = (1 << 31) | (2 << 2) | 1;
+
+        out.write_all(&(header_length + sample_block_length).to_le_bytes())?;
+        out.write_all(&header_length.to_le_bytes())?;
+        out.write_all(&0_u32.to_le_bytes())?;
+        out.write_all(&(samples.len() as u32).to_le_bytes())?;
+        out.write_all(b"bgen")?;
+        out.write_all(&flags.to_le_bytes())?;
+
+        out.write_all(&sample_block_length.to_le_bytes())?;
+        out.write_all(&(samples.len() as u32).to_le_bytes())?;
+        out.write_all(&sample_block)?;
+
+        Ok(BgenWriter { out, n_samples: samples.len(), n_variants: 0 })
+    }
+
+    // Write the dosages of a variant. Dosages must be between 0 and 2.
+    pub fn write(&mut self, d: &Dosages) -> io::Result<()> {
+        let v = &d.variant;
+
+        if d.dosages.len() != self.n_samples {
+            return Err(invalid_input(format!(
+                "Got {} dosages for {} samples.", d.dosages.len(),
+                self.n_samples
+            )));
+        }
+
+        if d.dosages.iter().flatten().any(|x| !(0.0..=2.0).contains(x)) {
+            return Err(invalid_input(format!(
+                "The dosages of `{}` are not between 0 and 2.", v.name
+            )));
+        }
+
+        write_string_u16(&mut self.out, &v.name)?;
+        write_string_u16(&mut self.out, &v.name)?;
+        write_string_u16(&mut self.out, &v.chrom.to_string())?;
+        self.out.write_all(&v.position.to_le_bytes())?;
+
+        self.out.write_all(&2_u16.to_le_bytes())?;
+        for allele in &[d.other_allele(), d.coded_allele()] {
+            self.out.write_all(&(allele.len() as u32).to_le_bytes())?;
+            self.out.write_all(allele.as_bytes())?;
+        }
+
+        let data = encode_dosages(&d.dosages);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data)?;
+        let compressed = encoder.finish()?;
+
+        self.out.write_all(&(compressed.len() as u32 + 4).to_le_bytes())?;
+        self.out.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.out.write_all(&compressed)?;
+
+        self.n_variants += 1;
+        Ok(())
+    }
+
+    pub fn n_variants(&self) -> u32 {
+        self.n_variants
+    }
+
+    // Update the number of variants in the header, flush and return the
+    // underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.seek(SeekFrom::Start(8))?;
+        self.out.write_all(&self.n_variants.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::core::{Chromosome, Variant};
+
+    fn string_u16(s: &str) -> Vec<u8> {
+        let mut bytes = (s.len() as u16).to_le_bytes().to_vec();
+        bytes.extend(s.as_bytes());
+        bytes
+    }
+
+    // Probability data for 3 diploid samples with 8 bits per value.
+    fn probabilities(phased: bool, n_alleles: u16, values: &[u8],
+                     missing: u8) -> Vec<u8>
+    {
+        let mut data = Vec::new();
+        data.extend(&3_u32.to_le_bytes());
+        data.extend(&n_alleles.to_le_bytes());
+        data.extend(&[2, 2]);
+        data.extend(&[2, 2, 2 | missing]);
+        data.extend(&[phased as u8, 8]);
+        data.extend(values);
+        data
+    }
+
+    fn variant(rsid: &str, alleles: &[&str], data: Vec<u8>, compress: bool)
+        -> Vec<u8>
+    {
+        let mut bytes = string_u16("");
+        bytes.extend(string_u16(rsid));
+        bytes.extend(string_u16("01"));
+        bytes.extend(&1000_u32.to_le_bytes());
+        bytes.extend(&(alleles.len() as u16).to_le_bytes());
+        for allele in alleles {
+            bytes.extend(&(allele.len() as u32).to_le_bytes());
+            bytes.extend(allele.as_bytes());
+        }
+
+        let block = if compress {
+            let mut encoder = ZlibEncoder::new(Vec::new(),
+                                               Compression::default());
+            encoder.write_all(&data).unwrap();
+
+            let mut block = (data.len() as u32).to_le_bytes().to_vec();
+            block.extend(encoder.finish().unwrap());
+            block
+        } else {
+            data
+        };
+
+        bytes.extend(&(block.len() as u32).to_le_bytes());
+        bytes.extend(block);
+        bytes
+    }
+
+    fn get_bgen(compress: bool) -> Vec<u8> {
+        let mut samples = Vec::new();
+        for id in &["s1", "s2", "s3"] {
+            samples.extend(string_u16(id));
+        }
+
+        let mut header: Vec<u8> = Vec::new();
+        header.extend(&20_u32.to_le_bytes());
+        header.extend(&3_u32.to_le_bytes());
+        header.extend(&3_u32.to_le_bytes());
+        header.extend(b"bgen");
+        let flags: u32 = (1 << 31) | (2 << 2) | compress as u32;
+        header.extend(&flags.to_le_bytes());
+
+        let mut sample_block = ((samples.len() + 8) as u32).to_le_bytes()
+            .to_vec();
+        sample_block.extend(&3_u32.to_le_bytes());
+        sample_block.extend(samples);
+
+        let offset = (header.len() + sample_block.len()) as u32;
+        let mut bgen = offset.to_le_bytes().to_vec();
+        bgen.extend(header);
+        bgen.extend(sample_block);
+
+        // Unphased: P(AA)=1 / P(AG)=1 / missing.
+        bgen.extend(variant("rs1", &["A", "G"], probabilities(
+            false, 2, &[255, 0, 0, 255, 0, 0], 0x80
+        ), compress));
+
+        // Multiallelic (unless split): P(AG)=1 / P(GT)=1 / P(TT)=1.
+        bgen.extend(variant("rs2", &["A", "G", "T"], probabilities(
+            false, 3, &[0, 255, 0, 0, 0, 0, 0, 0, 0, 255, 0, 0, 0, 0, 0], 0
+        ), compress));
+
+        // Phased: G|G / A|G / G|A
+        bgen.extend(variant("rs3", &["A", "G"], probabilities(
+            true, 2, &[0, 0, 255, 0, 0, 255], 0
+        ), compress));
+
+        bgen
+    }
+
+    #[test]
+    fn test_unphased_genotypes() {
+        assert_eq!(unphased_genotypes(2, 2),
+                   vec![vec![2, 0], vec![1, 1], vec![0, 2]]);
+        assert_eq!(unphased_genotypes(3, 2),
+                   vec![vec![2, 0, 0], vec![1, 1, 0], vec![0, 2, 0],
+                        vec![1, 0, 1], vec![0, 1, 1], vec![0, 0, 2]]);
+        assert_eq!(unphased_genotypes(3, 1),
+                   vec![vec![1, 0, 0], vec![0, 1, 0], vec![0, 0, 1]]);
+    }
+
+    #[test]
+    fn test_read_bits() {
+        let data = [0b1010_1100, 0b0000_0011];
+        assert_eq!(read_bits(&data, 2, 3), 0b011);
+        assert_eq!(read_bits(&data, 6, 4), 0b1110);
+    }
+
+    fn check(bgen: &[u8]) {
+        let mut reader = BgenReader::from_reader(bgen);
+
+        let iids: Vec<&str> = reader.samples().iter()
+            .map(|s| s.iid.as_str())
+            .collect();
+        assert_eq!(iids, vec!["s1", "s2", "s3"]);
+
+        let dosages: Vec<Dosages> = reader.by_ref().collect();
+        assert_eq!(dosages.len(), 2);
+        assert_eq!(reader.n_skipped(), 1);
+
+        assert_eq!(dosages[0].variant.name, "rs1");
+        assert_eq!(dosages[0].variant.chrom, Chromosome::Autosome(1));
+        assert_eq!(dosages[0].coded_allele(), "G");
+        assert_eq!(dosages[0].dosages, vec![Some(0.0), Some(1.0), None]);
+
+        assert_eq!(dosages[1].dosages, vec![Some(2.0), Some(1.0), Some(1.0)]);
+
+        let mut reader = BgenReader::from_reader(bgen);
+        reader.split_multiallelic(true);
+        let dosages: Vec<Dosages> = reader.by_ref().collect();
+        assert_eq!(dosages.len(), 4);
+        assert_eq!(reader.n_skipped(), 0);
+
+        assert_eq!(dosages[1].coded_allele(), "G");
+        assert_eq!(dosages[1].other_allele(), "A");
+        assert_eq!(dosages[1].dosages, vec![Some(1.0), Some(1.0), Some(0.0)]);
+        assert_eq!(dosages[2].coded_allele(), "T");
+        assert_eq!(dosages[2].dosages, vec![Some(0.0), Some(1.0), Some(2.0)]);
+    }
+
+    #[test]
+    fn test_read() {
+        check(&get_bgen(false));
+        check(&get_bgen(true));
+    }
+
+    #[test]
+    fn test_haplotypes() {
+        let bgen = get_bgen(true);
+        let mut reader = BgenReader::from_reader(&bgen[..]).haplotypes(0.9);
+        let haplotypes: Vec<Haplotypes> = reader.by_ref().collect();
+        assert_eq!(haplotypes.len(), 2);
+        assert_eq!(reader.n_skipped(), 1);
+
+        // The calls of the unphased variant are None.
+        assert_eq!(haplotypes[0].n_phased(), 0);
+        assert_eq!(haplotypes[1].coded_allele(), "G");
+        assert_eq!(haplotypes[1].haplotypes,
+                   vec![Some((1, 1)), Some((0, 1)), Some((1, 0))]);
+    }
+
+    #[test]
+    fn test_writer_roundtrip() {
+        let samples: Vec<Sample> = ["s1", "s2", "s3"].iter()
+            .map(|id| Sample::new("0".to_string(), id.to_string(),
+                                  Sex::Unknown))
+            .collect();
+
+        let v = Variant::new("rs1".to_string(), "1".to_string(), 1000,
+                             ("A".to_string(), "G".to_string()));
+        let values = vec![Some(0.0), Some(0.25), Some(1.5), None];
+
+        let mut writer = BgenWriter::new(Cursor::new(Vec::new()), &samples)
+            .unwrap();
+
+        // Wrong number of samples and invalid dosages.
+        let d = Dosages::new(v.clone(), values.clone(), "A");
+        assert!(writer.write(&d).is_err());
+        let d = Dosages::new(v.clone(), vec![Some(0.0), Some(2.5), None],
+                             "A");
+        assert!(writer.write(&d).is_err());
+
+        writer.write(&Dosages::new(v.clone(), values[1..].to_vec(), "A"))
+            .unwrap();
+        writer.write(&Dosages::new(v, values[..3].to_vec(), "G")).unwrap();
+        assert_eq!(writer.n_variants(), 2);
+
+        let bgen = writer.finish().unwrap().into_inner();
+        let mut reader = BgenReader::from_reader(&bgen[..]);
+        assert_eq!(reader.n_variants(), 2);
+        assert_eq!(reader.samples()[2].iid, "s3");
+
+        let dosages: Vec<Dosages> = reader.by_ref().collect();
+        assert_eq!(dosages.len(), 2);
+        assert_eq!(dosages[0].variant.name, "rs1");
+        assert_eq!(dosages[0].coded_allele(), "A");
+        assert_eq!(dosages[1].coded_allele(), "G");
+
+        for (d, expected) in dosages.iter().zip(&[&values[1..], &values[..3]])
+        {
+            for (x, y) in d.dosages.iter().zip(expected.iter()) {
+                match (x, y) {
+                    (Some(x), Some(y)) => assert!((x - y).abs() < 1e-4),
+                    (x, y) => assert_eq!(x, y)
+                }
+            }
+        }
+    }
+}
@@ -1,11 +1,29 @@
+// The C API necessarily takes raw pointers from the caller.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
 use std::f32::NAN;
+use std::cell::RefCell;
 use std::os::raw::{c_char, c_uint, c_float};
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 
 use crate::core::{Variant, Genotypes};
+use crate::error::GenepaError;
 use crate::plink::PlinkReader;
 
 
+thread_local! {
+    // Error of the last call that failed on this thread (see
+    // `genepa_last_error`).
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: Option<&GenepaError>) {
+    LAST_ERROR.with(|last| {
+        *last.borrow_mut() = error.map(|e| e.to_string());
+    });
+}
+
+
 fn c_string_to_string(ptr: *const c_char) -> String {
     unsafe {
         CStr::from_ptr(ptr).to_string_lossy().into_owned()
@@ -13,6 +31,21 @@ fn c_string_to_string(ptr: *const c_char) -> String {
 }
 
 
+/// Returns the message of the error of the last call on this thread that
+/// returned a null pointer because of an error (`plink_reader_new` or
+/// `plink_reader_next`), or a null pointer if that call succeeded. Free using
+/// `string_free`.
+#[no_mangle]
+pub extern "C" fn genepa_last_error() -> *mut c_char {
+    LAST_ERROR.with(|last| match last.borrow().as_ref() {
+        Some(message) => CString::new(message.replace('\0', ""))
+            .unwrap()
+            .into_raw(),
+        None => std::ptr::null_mut()
+    })
+}
+
+
 #[no_mangle]
 pub extern "C" fn variant_new(
         name: *const c_char,
@@ -115,6 +148,27 @@ pub extern "C" fn genotypes_get_genotypes(ptr: *const Genotypes)
 
 }
 
+/// Returns the IID of the sample at the given index, or a null pointer if no
+/// samples are attached to the genotypes. Free using `string_free`.
+///
+/// # Safety
+///
+/// `ptr` must be a valid pointer to Genotypes returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn genotypes_get_sample_iid(ptr: *const Genotypes,
+                                                  idx: usize)
+    -> *mut c_char {
+
+    let geno = ptr.as_ref().unwrap();
+
+    match geno.samples.as_ref().and_then(|samples| samples.get(idx)) {
+        Some(sample) => CString::new(sample.iid.as_str())
+            .expect("Sample IID contains a null byte.")
+            .into_raw(),
+        None => std::ptr::null_mut()
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn genotypes_free(ptr: *mut Genotypes) {
     if ptr.is_null() { return; }
@@ -148,6 +202,20 @@ pub extern "C" fn genotypes_maf(ptr: *mut Genotypes) -> f64 {
 }
 
 
+/// Free a string returned by this library.
+///
+/// # Safety
+///
+/// `ptr` must be null or a string returned by this library that wasn't
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn string_free(ptr: *mut c_char) {
+    if ptr.is_null() { return; }
+
+    drop(CString::from_raw(ptr));
+}
+
+
 /**
  * Plink reader bindings.
  **/
@@ -163,9 +231,18 @@ pub extern "C" fn plink_reader_new(
         )
     };
 
-    let reader = Box::new(PlinkReader::new(prefix_str));
-
-    Box::into_raw(reader)
+    // A null pointer is returned on errors, so that the caller can handle
+    // missing or invalid filesets (see `genepa_last_error`).
+    match PlinkReader::new(prefix_str) {
+        Ok(reader) => {
+            set_last_error(None);
+            Box::into_raw(Box::new(reader))
+        }
+        Err(e) => {
+            set_last_error(Some(&e));
+            std::ptr::null_mut()
+        }
+    }
 
 }
 
@@ -180,6 +257,19 @@ pub extern "C" fn plink_reader_free(ptr: *mut PlinkReader) {
 }
 
 
+/// Attach the samples to the genotypes produced by the reader.
+///
+/// # Safety
+///
+/// `ptr` must be a valid pointer to a reader returned by `plink_reader_new`.
+#[no_mangle]
+pub unsafe extern "C" fn plink_reader_attach_samples(ptr: *mut PlinkReader,
+                                                     attach: bool) {
+    let reader = ptr.as_mut().unwrap();
+    reader.attach_samples(attach);
+}
+
+
 #[no_mangle]
 pub extern "C" fn plink_reader_next(ptr: *mut PlinkReader) -> *mut Genotypes {
     if ptr.is_null() {
@@ -191,9 +281,21 @@ pub extern "C" fn plink_reader_next(ptr: *mut PlinkReader) -> *mut Genotypes {
         ptr.as_mut()
     }.unwrap();
 
-    match reader.next() {
-        Some(g) => Box::into_raw(Box::new(g)),
-        None => std::ptr::null_mut()
+    // A null pointer is returned both at the end of the fileset and on
+    // errors, which are told apart using `genepa_last_error`.
+    match reader.try_next() {
+        Some(Ok(g)) => {
+            set_last_error(None);
+            Box::into_raw(Box::new(g))
+        }
+        Some(Err(e)) => {
+            set_last_error(Some(&e));
+            std::ptr::null_mut()
+        }
+        None => {
+            set_last_error(None);
+            std::ptr::null_mut()
+        }
     }
 
 }
@@ -0,0 +1,553 @@
+/*!
+ * Checksum manifests to detect silent corruption of datasets.
+ *
+ * The manifest of a fileset `prefix` is `{prefix}.sha256`, in the format of
+ * `sha256sum` (so it can also be checked using `sha256sum -c`). The BED can
+ * additionally have per-block digests in `{prefix}.bed.sha256blocks`: a
+ * `block_size` line followed by the digest of every block. These are used to
+ * verify the BED lazily, as the blocks are read.
+ *
+ * Genotype manifests instead list a digest of the genotypes of every variant
+ * (see `Genotypes::content_hash`), so that two sites can check that they hold
+ * the same genotypes for their shared variants without exchanging them.
+ */
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom,
+              Write};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::core::{Genotypes, Sample};
+
+
+pub const DEFAULT_BLOCK_SIZE: u64 = 1 << 20;
+
+
+// When to verify the checksums of the data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verification {
+    // Hash every file when it is opened.
+    Eager,
+    // Hash the blocks of large files (e.g. the BED) as they are read.
+    Lazy
+}
+
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+
+pub fn sha256_hex<R: Read>(mut reader: R) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(to_hex(&hasher.finalize()))
+}
+
+
+fn file_name(path: &str) -> &str {
+    Path::new(path).file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+}
+
+
+// Write the BED block digests and the manifest of a plink fileset. The
+// block digests are also listed in the manifest.
+pub fn write_plink_manifest(prefix: &str, block_size: u64) -> io::Result<()> {
+    let bed_filename = format!("{}.bed", prefix);
+    let mut bed = BufReader::new(File::open(&bed_filename)?);
+    let mut blocks = BufWriter::new(
+        File::create(format!("{}.sha256blocks", bed_filename))?
+    );
+
+    writeln!(blocks, "block_size\t{}", block_size)?;
+
+    let mut buf = vec![0; block_size as usize];
+    loop {
+        let n = read_block(&mut bed, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        writeln!(blocks, "{}", to_hex(&Sha256::digest(&buf[..n])))?;
+    }
+    blocks.flush()?;
+
+    let mut manifest = BufWriter::new(
+        File::create(format!("{}.sha256", prefix))?
+    );
+
+    for ext in &["bed", "bed.sha256blocks", "bim", "fam"] {
+        let filename = format!("{}.{}", prefix, ext);
+        let digest = sha256_hex(BufReader::new(File::open(&filename)?))?;
+        writeln!(manifest, "{}  {}", digest, file_name(&filename))?;
+    }
+
+    manifest.flush()
+}
+
+
+// Fill the buffer unless the end of the reader is reached.
+fn read_block<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..])? {
+            0 => break,
+            k => n += k
+        }
+    }
+
+    Ok(n)
+}
+
+
+// The digests of a `sha256sum` manifest by file name.
+pub struct Manifest {
+    filename: String,
+    digests: HashMap<String, String>
+}
+
+impl Manifest {
+    pub fn read(filename: &str) -> io::Result<Manifest> {
+        let mut digests = HashMap::new();
+
+        for line in BufReader::new(File::open(filename)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // The file name is preceded by ' ' (text) or '*' (binary).
+            let (digest, name) = line.split_once(' ')
+                .ok_or_else(|| invalid_data(format!(
+                    "Invalid line in checksum manifest `{}`: {}",
+                    filename, line
+                )))?;
+
+            let name = name.trim_start_matches([' ', '*']);
+            digests.insert(file_name(name).to_string(),
+                           digest.to_lowercase());
+        }
+
+        Ok(Manifest { filename: filename.to_string(), digests })
+    }
+
+    fn expected(&self, path: &str) -> io::Result<&str> {
+        self.digests.get(file_name(path))
+            .map(|digest| digest.as_str())
+            .ok_or_else(|| invalid_data(format!(
+                "`{}` is not in the checksum manifest `{}`.",
+                path, self.filename
+            )))
+    }
+
+    // Hash a file and compare it to the manifest.
+    pub fn verify_file(&self, path: &str) -> io::Result<()> {
+        let expected = self.expected(path)?;
+        let observed = sha256_hex(BufReader::new(File::open(path)?))?;
+
+        if observed != expected {
+            return Err(invalid_data(format!(
+                "Checksum mismatch for `{}` (expected {}, got {}).",
+                path, expected, observed
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+
+// A reader that verifies every block against its digest the first time it
+// is read.
+pub struct VerifyingReader<R: Read + Seek> {
+    inner: R,
+    name: String,
+    block_size: u64,
+    digests: Vec<String>,
+    verified: Vec<bool>,
+    len: u64,
+    pos: u64,
+    block: Option<(u64, Vec<u8>)>
+}
+
+impl VerifyingReader<File> {
+    // Open a file with the digests from `{filename}.sha256blocks`.
+    pub fn open(filename: &str) -> io::Result<VerifyingReader<File>> {
+        let blocks = format!("{}.sha256blocks", filename);
+        let mut lines = BufReader::new(File::open(&blocks)?).lines();
+
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let block_size = header.strip_prefix("block_size\t")
+            .and_then(|size| size.parse().ok())
+            .ok_or_else(|| invalid_data(format!(
+                "Missing block size in `{}`.", blocks
+            )))?;
+
+        let digests = lines.collect::<io::Result<Vec<String>>>()?;
+
+        VerifyingReader::new(File::open(filename)?, filename, block_size,
+                             digests)
+    }
+}
+
+impl<R: Read + Seek> VerifyingReader<R> {
+    pub fn new(mut inner: R, name: &str, block_size: u64,
+               digests: Vec<String>) -> io::Result<VerifyingReader<R>>
+    {
+        let len = inner.seek(SeekFrom::End(0))?;
+
+        if block_size == 0 || len.div_ceil(block_size) != digests.len() as u64 {
+            return Err(invalid_data(format!(
+                "The size of `{}` is not consistent with {} blocks of {} \
+                 bytes.", name, digests.len(), block_size
+            )));
+        }
+
+        Ok(VerifyingReader {
+            inner,
+            name: name.to_string(),
+            block_size,
+            verified: vec![false; digests.len()],
+            digests: digests.into_iter().map(|d| d.to_lowercase()).collect(),
+            len,
+            pos: 0,
+            block: None
+        })
+    }
+
+    fn _load_block(&mut self, idx: u64) -> io::Result<()> {
+        if let Some((loaded, _)) = self.block {
+            if loaded == idx {
+                return Ok(());
+            }
+        }
+
+        let start = idx * self.block_size;
+        let n = self.block_size.min(self.len - start) as usize;
+
+        self.inner.seek(SeekFrom::Start(start))?;
+        let mut buf = vec![0; n];
+        self.inner.read_exact(&mut buf)?;
+
+        let i = idx as usize;
+        if !self.verified[i] {
+            let observed = to_hex(&Sha256::digest(&buf));
+            if observed != self.digests[i] {
+                return Err(invalid_data(format!(
+                    "Checksum mismatch in block {} of `{}`.", idx, self.name
+                )));
+            }
+            self.verified[i] = true;
+        }
+
+        self.block = Some((idx, buf));
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for VerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let idx = self.pos / self.block_size;
+        self._load_block(idx)?;
+
+        let block = &self.block.as_ref().unwrap().1;
+        let start = (self.pos - idx * self.block_size) as usize;
+        let n = buf.len().min(block.len() - start);
+
+        buf[..n].copy_from_slice(&block[start..start + n]);
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for VerifyingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n)
+        };
+
+        match new_pos {
+            Some(n) => {
+                self.pos = n;
+                Ok(n)
+            },
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                       "Invalid seek to a negative or \
+                                        overflowing position."))
+        }
+    }
+}
+
+
+// Digest of the sample identifiers, as the genotype digests depend on the
+// order of the samples.
+fn samples_hash(samples: &[Sample]) -> String {
+    let mut hasher = Sha256::new();
+    for s in samples {
+        hasher.update(format!("{}\t{}\n", s.fid, s.iid));
+    }
+
+    to_hex(&hasher.finalize())
+}
+
+
+// Chromosome, position and alleles (in the order of Variant).
+type VariantKey = (String, u32, String, String);
+
+
+// Write a genotype manifest: a `#samples` line with the digest of the
+// samples, then a `chrom pos allele1 allele2 digest` line per variant.
+// Returns the number of variants.
+pub fn write_genotype_manifest<W, I>(out: &mut W, samples: &[Sample],
+                                     genotypes: I) -> io::Result<u64>
+    where W: Write,
+          I: IntoIterator<Item = Genotypes>
+{
+    writeln!(out, "#samples\t{}", samples_hash(samples))?;
+
+    let mut n = 0;
+    for g in genotypes {
+        let v = &g.variant;
+        writeln!(out, "{}\t{}\t{}\t{}\t{}", v.chrom, v.position,
+                 v.alleles.0, v.alleles.1, g.content_hash())?;
+        n += 1;
+    }
+
+    Ok(n)
+}
+
+
+pub struct GenotypeManifest {
+    samples: String,
+    digests: HashMap<VariantKey, String>
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestComparison {
+    // If both datasets have the same samples in the same order. Otherwise,
+    // the digests can't be compared.
+    pub same_samples: bool,
+    pub n_shared: usize,
+    // Shared variants (`chrom:pos:allele1:allele2`) with different
+    // genotypes.
+    pub mismatched: Vec<String>
+}
+
+
+impl GenotypeManifest {
+    pub fn read<R: BufRead>(reader: R) -> io::Result<GenotypeManifest> {
+        let mut lines = reader.lines();
+
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let samples = header.strip_prefix("#samples\t")
+            .ok_or_else(|| invalid_data(
+                "The genotype manifest has no `#samples` line.".to_string()
+            ))?
+            .to_string();
+
+        let mut digests = HashMap::new();
+        for line in lines {
+            let line = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+
+            let position = fields.get(1).and_then(|pos| pos.parse().ok());
+            match (fields.len(), position) {
+                (5, Some(position)) => {
+                    digests.insert((fields[0].to_string(), position,
+                                    fields[2].to_string(),
+                                    fields[3].to_string()),
+                                   fields[4].to_string());
+                },
+                _ if line.trim().is_empty() => {},
+                _ => return Err(invalid_data(format!(
+                    "Invalid line in genotype manifest: {}", line
+                )))
+            }
+        }
+
+        Ok(GenotypeManifest { samples, digests })
+    }
+
+    pub fn n_variants(&self) -> usize {
+        self.digests.len()
+    }
+
+    // Compare the digests of the variants in both manifests.
+    pub fn compare(&self, other: &GenotypeManifest) -> ManifestComparison {
+        let mut n_shared = 0;
+        let mut mismatched = Vec::new();
+
+        for (key, digest) in self.digests.iter() {
+            if let Some(other_digest) = other.digests.get(key) {
+                n_shared += 1;
+                if digest != other_digest {
+                    mismatched.push(format!("{}:{}:{}:{}", key.0, key.1,
+                                            key.2, key.3));
+                }
+            }
+        }
+        mismatched.sort();
+
+        ManifestComparison {
+            same_samples: self.samples == other.samples,
+            n_shared,
+            mismatched
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            sha256_hex(&b"abc"[..]).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_plink_manifest() {
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_checksum_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("test").to_str().unwrap().to_string();
+
+        std::fs::write(format!("{}.bed", prefix), vec![1; 10]).unwrap();
+        std::fs::write(format!("{}.bim", prefix), "bim").unwrap();
+        std::fs::write(format!("{}.fam", prefix), "fam").unwrap();
+
+        write_plink_manifest(&prefix, 4).unwrap();
+
+        let manifest = Manifest::read(&format!("{}.sha256", prefix)).unwrap();
+        for ext in &["bed", "bed.sha256blocks", "bim", "fam"] {
+            manifest.verify_file(&format!("{}.{}", prefix, ext)).unwrap();
+        }
+
+        let bed = format!("{}.bed", prefix);
+        let mut data = Vec::new();
+        VerifyingReader::open(&bed).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, vec![1; 10]);
+
+        std::fs::write(format!("{}.fam", prefix), "fan").unwrap();
+        assert!(manifest.verify_file(&format!("{}.fam", prefix)).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_genotype_manifest() {
+        use crate::core::{Sex, Variant};
+
+        let samples = vec![
+            Sample::new("f1".to_string(), "s1".to_string(), Sex::Unknown),
+            Sample::new("f2".to_string(), "s2".to_string(), Sex::Unknown)
+        ];
+
+        let genotypes = |pos: u32, calls: Vec<Option<u8>>, coded: &str| {
+            let v = Variant::new(format!("rs{}", pos), "1".to_string(), pos,
+                                 ("A".to_string(), "G".to_string()));
+            Genotypes::new(v, calls, coded)
+        };
+
+        let mut a = Vec::new();
+        write_genotype_manifest(&mut a, &samples, vec![
+            genotypes(100, vec![Some(0), Some(1)], "G"),
+            genotypes(200, vec![Some(2), None], "G"),
+            genotypes(300, vec![Some(0), Some(0)], "G")
+        ]).unwrap();
+
+        // The same genotypes counting the other allele, a variant with a
+        // different call and one that isn't in the first dataset.
+        let mut b = Vec::new();
+        write_genotype_manifest(&mut b, &samples, vec![
+            genotypes(100, vec![Some(2), Some(1)], "A"),
+            genotypes(200, vec![Some(2), Some(0)], "G"),
+            genotypes(400, vec![Some(0), Some(0)], "G")
+        ]).unwrap();
+
+        let a = GenotypeManifest::read(&a[..]).unwrap();
+        let b = GenotypeManifest::read(&b[..]).unwrap();
+        assert_eq!(a.n_variants(), 3);
+        assert_eq!(a.compare(&b), ManifestComparison {
+            same_samples: true,
+            n_shared: 2,
+            mismatched: vec!["1:200:A:G".to_string()]
+        });
+
+        let mut c = Vec::new();
+        write_genotype_manifest(&mut c, &samples[..1], Vec::new()).unwrap();
+        let c = GenotypeManifest::read(&c[..]).unwrap();
+        assert!(!a.compare(&c).same_samples);
+
+        assert!(GenotypeManifest::read(&b"1\t100\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_verifying_reader() {
+        let data: Vec<u8> = (0..100).collect();
+        let digests: Vec<String> = data.chunks(32)
+            .map(|block| to_hex(&Sha256::digest(block)))
+            .collect();
+
+        let mut reader = VerifyingReader::new(
+            Cursor::new(data.clone()), "data", 32, digests.clone()
+        ).unwrap();
+
+        let mut buf = [0; 10];
+        reader.seek(SeekFrom::Start(60)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[60..70]);
+
+        let mut all = Vec::new();
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data);
+
+        // Only the corrupted block fails.
+        let mut corrupted = data.clone();
+        corrupted[70] ^= 1;
+        let mut reader = VerifyingReader::new(
+            Cursor::new(corrupted), "data", 32, digests.clone()
+        ).unwrap();
+
+        assert!(reader.read_exact(&mut buf).is_ok());
+        reader.seek(SeekFrom::Start(64)).unwrap();
+        assert!(reader.read_exact(&mut buf).is_err());
+
+        // The number of blocks must match the size.
+        assert!(VerifyingReader::new(
+            Cursor::new(data), "data", 64, digests
+        ).is_err());
+    }
+}
@@ -0,0 +1,2086 @@
+/*!
+ * Command line interface.
+ *
+ * Options are given as `--name value` pairs after the command name. Some
+ * options take multiple values (e.g. `--bfiles a b c`).
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::str::FromStr;
+
+use rsgeneparselib::{Chromosome, Genotypes, Sample, Sex, Variant};
+use rsgeneparselib::annotation::{is_missing_name, AnnotationVcf};
+use rsgeneparselib::append::{append_samples, append_variants};
+use rsgeneparselib::assoc::{test_association, AssocResult, Model};
+use rsgeneparselib::bgen::BgenWriter;
+use rsgeneparselib::cluster::{hierarchical, Linkage};
+use rsgeneparselib::convert::{convert_filtered, Filters};
+use rsgeneparselib::export::write_long_format;
+use rsgeneparselib::family::{family_indices, find_trios, test_within_family,
+                             tdt as compute_tdt, TdtResult};
+use rsgeneparselib::fasta::{FastaReader, RefCheck};
+use rsgeneparselib::genes::{get_variants_in_gene, GeneModel};
+use rsgeneparselib::grm::{compute_grm, haseman_elston, write_grm,
+                          GrmAccumulator};
+use rsgeneparselib::ibs::{mds as compute_mds, write_matrix, write_mds,
+                          IbsAccumulator};
+use rsgeneparselib::index::{build_index, delete_index, index_exists,
+                            inspect_index, IndexFormat, IndexInfo};
+use rsgeneparselib::ldsc::{ld_scores, ldsc as regress_ld_scores,
+                           match_sumstats};
+use rsgeneparselib::liftover::{liftover_plink, ChainMap};
+use rsgeneparselib::merge::merge_plink;
+use rsgeneparselib::outliers::OutlierDetector;
+use rsgeneparselib::pca::{pcs_from_grm, streaming_pcs, write_eigenval,
+                          write_eigenvec, DEFAULT_PASSES};
+use rsgeneparselib::pgen::PgenWriter;
+use rsgeneparselib::plink::{BimReader, PlinkReader, PlinkWriter};
+use rsgeneparselib::qc::QcReport;
+use rsgeneparselib::regions::{complex_regions, high_ld_regions, RegionLabels,
+                              RegionSet};
+use rsgeneparselib::roundtrip::{self, RoundtripFormat};
+use rsgeneparselib::sampling::{write_subset_fileset, VariantSelection};
+use rsgeneparselib::sexchrom::{coded_allele_counts, hemizygous_coding,
+                               split_x_genotypes, GenomeBuild};
+use rsgeneparselib::store::VariantCounts;
+use rsgeneparselib::sumstats::{SummaryStatsColumns, SummaryStatsReader};
+use rsgeneparselib::simulate::{simulate_samples, GenotypeSimulator,
+                               MafDistribution};
+use rsgeneparselib::source::{open_source, ChromosomeFilter, GenotypeSource};
+use rsgeneparselib::units::FrequencyEstimator;
+use rsgeneparselib::utils::compute_ld;
+use rsgeneparselib::vcf::VcfWriter;
+use rsgeneparselib::visitor::VariantScan;
+use rsgeneparselib::zarr::ZarrWriter;
+
+use crate::pipeline::Pipeline;
+
+
+pub struct Args {
+    values: HashMap<String, Vec<String>>
+}
+
+impl Args {
+    // Options without a value (e.g. `--flag --other x`) are set to "". All
+    // the values up to the next option are kept.
+    pub fn parse(args: &[String], known: &[&str]) -> Result<Args, String> {
+        let mut values = HashMap::new();
+        let mut iter = args.iter().peekable();
+
+        while let Some(arg) = iter.next() {
+            let name = arg.strip_prefix("--")
+                .ok_or_else(|| format!("Unexpected argument `{}`.", arg))?;
+
+            if !known.contains(&name) {
+                return Err(format!("Unknown option `--{}`.", name));
+            }
+
+            let mut option_values = Vec::new();
+            while let Some(next) = iter.peek() {
+                if next.starts_with("--") {
+                    break;
+                }
+                option_values.push(iter.next().unwrap().to_string());
+            }
+
+            values.insert(name.to_string(), option_values);
+        }
+
+        Ok(Args { values })
+    }
+
+    // The first value of an option.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name)
+            .map(|values| values.first().map_or("", |value| value.as_str()))
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.values.get(name)
+            .map(|values| values.iter().map(|value| value.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn required(&self, name: &str) -> Result<&str, String> {
+        match self.get(name) {
+            Some(value) if !value.is_empty() => Ok(value),
+            _ => Err(format!("Missing required option `--{}`.", name))
+        }
+    }
+
+    pub fn parse_or<T: FromStr>(&self, name: &str, default: T)
+        -> Result<T, String>
+    {
+        match self.get(name) {
+            Some(value) => value.parse().map_err(|_| {
+                format!("Invalid value for `--{}`: `{}`.", name, value)
+            }),
+            None => Ok(default)
+        }
+    }
+}
+
+
+// Write to the `--out` file or to stdout.
+fn output(args: &Args) -> Result<Box<dyn Write>, String> {
+    match args.get("out") {
+        Some(filename) if !filename.is_empty() => {
+            let f = File::create(filename)
+                .map_err(|e| format!("Could not create `{}`: {}", filename,
+                                     e))?;
+            Ok(Box::new(BufWriter::new(f)))
+        },
+        _ => Ok(Box::new(BufWriter::new(io::stdout())))
+    }
+}
+
+
+fn open_plink(prefix: &str) -> Result<PlinkReader, String> {
+    PlinkReader::new(prefix).map_err(|e| e.to_string())
+}
+
+
+// Chromosomes used by the analyses assuming diploid variants (QC, GRM, PCA
+// and association): only the autosomes unless `--all-chromosomes`, without
+// the chromosomes given to `--exclude-chr`.
+fn chromosome_filter(args: &Args) -> Result<ChromosomeFilter, String> {
+    let excluded = args.get_all("exclude-chr").into_iter()
+        .map(|name| {
+            name.parse::<Chromosome>()
+                .map_err(|_| format!("Invalid chromosome `{}`.", name))
+        })
+        .collect::<Result<Vec<Chromosome>, String>>()?;
+
+    let filter = match args.get("all-chromosomes") {
+        Some(_) => ChromosomeFilter::default(),
+        None => ChromosomeFilter::autosomes()
+    };
+    Ok(filter.exclude(&excluded))
+}
+
+
+// Regions excluded from the GRM and the PCs with `--exclude-regions`: a BED
+// file, or `high-ld` for the long-range LD regions of `--build` (GRCh37 by
+// default). No regions are excluded without the option.
+fn excluded_regions(args: &Args) -> Result<RegionSet, String> {
+    match args.get("exclude-regions") {
+        Some("high-ld") => {
+            let build: GenomeBuild = args.get("build")
+                .unwrap_or("grch37")
+                .parse()?;
+            Ok(high_ld_regions(build))
+        },
+        Some(_) => Ok(RegionSet::new(args.required("exclude-regions")?)),
+        None => Ok(RegionSet::default())
+    }
+}
+
+
+// Named regions flagged in the results (`--flag-regions`): `complex` for the
+// MHC and KIR regions of the build, or a BED file with the names in the 4th
+// column.
+pub fn flagged_regions(spec: &str, build: GenomeBuild) -> RegionLabels {
+    match spec {
+        "complex" => complex_regions(build),
+        filename => RegionLabels::new(filename)
+    }
+}
+
+
+
+// Write the r² between the index variant and the other variants using the
+// columns of `plink --r2`. Pairs with an r² below `min_r2` are omitted.
+pub fn write_ld_report<W: Write>(out: &mut W, g: Genotypes,
+                                 others: Vec<Genotypes>, min_r2: f64)
+    -> io::Result<()>
+{
+    writeln!(out, "CHR_A\tBP_A\tSNP_A\tCHR_B\tBP_B\tSNP_B\tR2")?;
+
+    let a = g.variant.clone();
+    let variants: Vec<_> = others.iter().map(|o| o.variant.clone()).collect();
+
+    for (b, r2) in variants.iter().zip(compute_ld(g, others, true)) {
+        if r2.is_nan() || r2 < min_r2 {
+            continue;
+        }
+
+        writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{:.6}",
+                 a.chrom, a.position, a.name,
+                 b.chrom, b.position, b.name, r2)?;
+    }
+
+    out.flush()
+}
+
+
+// genepa ld --bfile prefix --ld-snp rs123 [--window-kb 500]
+//           [--ld-window-r2 0.2] [--out ld.tsv]
+//
+// The index variant is given by name or by locus (e.g. `chr1:12345:A:G`).
+pub fn ld(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "ld-snp", "window-kb",
+                                   "ld-window-r2", "out"])?;
+
+    let prefix = args.required("bfile")?;
+    let name = args.required("ld-snp")?;
+    let query: Variant = name.parse()
+        .map_err(|e| format!("Invalid variant `{}`: {}", name, e))?;
+    let window_kb: u32 = args.parse_or("window-kb", 1000)?;
+    let min_r2: f64 = args.parse_or("ld-window-r2", 0.2)?;
+
+    // Find the position of the index variant in the BIM.
+    let mut index = None;
+    for oav in BimReader::new(&format!("{}.bim", prefix))
+        .map_err(|e| e.to_string())?
+    {
+        let v = oav.map_err(|e| e.to_string())?.variant;
+        if query.identifies(&v) {
+            index = Some(v);
+            break;
+        }
+    }
+    let index = index.ok_or_else(|| {
+        format!("Variant `{}` is not in `{}.bim`.", name, prefix)
+    })?;
+
+    let window = window_kb.saturating_mul(1000);
+    let mut reader = open_plink(prefix)?;
+    let region = reader.get_variants_in_region(
+        &index.chrom,
+        index.position.saturating_sub(window),
+        index.position.saturating_add(window)
+    );
+
+    let i = region.iter()
+        .position(|g| query.identifies(&g.variant))
+        .ok_or_else(|| format!("Could not read variant `{}`.", name))?;
+    let g = region[i].clone();
+
+    write_ld_report(&mut output(&args)?, g, region, min_r2)
+        .map_err(|e| format!("Could not write the LD report: {}", e))
+}
+
+
+// Read the groups of a plink `--within` file (FID, IID and group) as the
+// indices of their samples. Groups are in order of first appearance and
+// samples that are not in the file are not in any group.
+pub fn read_groups<R: BufRead>(reader: R, samples: &[Sample])
+    -> Result<Vec<(String, Vec<usize>)>, String>
+{
+    let index: HashMap<(&str, &str), usize> = samples.iter()
+        .enumerate()
+        .map(|(i, s)| ((s.fid.as_str(), s.iid.as_str()), i))
+        .collect();
+
+    let mut groups: Vec<(String, Vec<usize>)> = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| format!("Could not read groups: {}", e))?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        if fields.is_empty() {
+            continue;
+        }
+
+        if fields.len() < 3 {
+            return Err(format!("Expected FID, IID and group on line {} of \
+                                the groups file.", i + 1));
+        }
+
+        let sample = match index.get(&(fields[0], fields[1])) {
+            Some(&sample) => sample,
+            None => continue
+        };
+
+        match groups.iter_mut().find(|(name, _)| name == fields[2]) {
+            Some((_, members)) => members.push(sample),
+            None => groups.push((fields[2].to_string(), vec![sample]))
+        }
+    }
+
+    Ok(groups)
+}
+
+
+// Number of copies of the coded allele and of observed alleles among the
+// given samples.
+fn format_freq(count: u64, n_obs: u64) -> String {
+    if n_obs == 0 {
+        "NA".to_string()
+    } else {
+        format!("{:.6}", count as f64 / n_obs as f64)
+    }
+}
+
+
+// Write the allele frequencies using the columns of plink `--freq` (.frq)
+// or, if there are groups, `--freq --within` (.frq.strat). A1 is the minor
+// allele in the whole sample. On the X chromosome, the males count as a
+// single allele (see `coded_allele_counts`).
+pub fn write_freq<W, I>(out: &mut W, genotypes: I, sexes: &[Sex],
+                        groups: Option<&[(String, Vec<usize>)]>)
+    -> io::Result<()>
+    where W: Write, I: IntoIterator<Item = Genotypes>
+{
+    match groups {
+        Some(_) => writeln!(out, "CHR\tSNP\tCLST\tA1\tA2\tMAF\tMAC\tNCHROBS")?,
+        None => writeln!(out, "CHR\tSNP\tA1\tA2\tMAF\tNCHROBS")?
+    }
+
+    for g in genotypes {
+        let (n_coded, n_obs) = coded_allele_counts(&g, sexes,
+                                                   0..g.genotypes.len());
+
+        // Count the minor allele.
+        let flip = 2 * n_coded > n_obs;
+        let (a1, a2) = if flip {
+            (g.other_allele(), g.coded_allele())
+        } else {
+            (g.coded_allele(), g.other_allele())
+        };
+        let minor = |n_coded: u64, n_obs: u64| {
+            if flip { n_obs - n_coded } else { n_coded }
+        };
+
+        match groups {
+            Some(groups) => {
+                for (name, members) in groups {
+                    let (n_coded, n_obs) = coded_allele_counts(
+                        &g, sexes, members.iter().copied()
+                    );
+                    let mac = minor(n_coded, n_obs);
+
+                    writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                             g.variant.chrom, g.variant.name, name, a1, a2,
+                             format_freq(mac, n_obs), mac, n_obs)?;
+                }
+            },
+            None => {
+                writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}",
+                         g.variant.chrom, g.variant.name, a1, a2,
+                         format_freq(minor(n_coded, n_obs), n_obs), n_obs)?;
+            }
+        }
+    }
+
+    out.flush()
+}
+
+
+// genepa freq --bfile prefix [--within groups.tsv]
+//             [--split-x [--build grch37]] [--out file]
+//
+// With `--split-x`, the X variants in the PARs of the build are relabeled
+// XY (see `split_x`), so that the males count as two alleles.
+pub fn freq(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "within", "split-x", "build",
+                                   "out"])?;
+
+    let reader = open_plink(args.required("bfile")?)?;
+    let build: GenomeBuild = args.get("build").unwrap_or("grch37").parse()?;
+    let sexes: Vec<Sex> = reader.samples().iter().map(|s| s.sex).collect();
+
+    let groups = match args.get("within") {
+        Some(filename) => {
+            let f = File::open(filename)
+                .map_err(|e| format!("Could not open `{}`: {}", filename, e))?;
+            Some(read_groups(BufReader::new(f), reader.samples())?)
+        },
+        None => None
+    };
+
+    let genotypes: Box<dyn Iterator<Item = Genotypes>> =
+        match args.get("split-x") {
+            Some(_) => Box::new(split_x_genotypes(reader, build)),
+            None => Box::new(reader)
+        };
+
+    write_freq(&mut output(&args)?, genotypes, &sexes, groups.as_deref())
+        .map_err(|e| format!("Could not write the frequencies: {}", e))
+}
+
+
+
+// genepa mds --bfile prefix [--dims 4] [--clusters k [--linkage average]]
+//            [--all-chromosomes] [--exclude-chr chrom ...]
+//            [--exclude-regions high-ld|file.bed [--build grch37]]
+//            --out prefix
+//
+// Classical MDS of the IBS distances between the samples (like plink
+// `--cluster --mds-plot`). The coordinates are written to `{out}.mds` and
+// the IBS similarity matrix to `{out}.mibs`. With `--clusters`, the samples
+// are also grouped by hierarchical clustering of the IBS distances (the SOL
+// column of the `.mds`).
+pub fn mds(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "dims", "clusters", "linkage",
+                                   "all-chromosomes", "exclude-chr",
+                                   "exclude-regions", "build", "out"])?;
+
+    let reader = open_plink(args.required("bfile")?)?
+        .filter_chromosomes(chromosome_filter(&args)?);
+    let samples = reader.samples().to_vec();
+    let dims: usize = args.parse_or("dims", 4)?;
+    // 0 for no clustering.
+    let n_clusters: usize = args.parse_or("clusters", 0)?;
+    let linkage: Linkage = args.parse_or("linkage", Linkage::Average)?;
+    let out = args.required("out")?;
+
+    if dims == 0 || dims > samples.len() {
+        return Err(format!("Expected 1 to {} dimensions for `--dims`.",
+                           samples.len()));
+    }
+
+    if n_clusters > samples.len() {
+        return Err(format!("Expected at most {} clusters for `--clusters`.",
+                           samples.len()));
+    }
+
+    let regions = excluded_regions(&args)?;
+    let mut acc = IbsAccumulator::new();
+    for g in reader.filter(|g| !regions.contains(&g.variant)) {
+        acc.add(&g);
+    }
+    let n_variants = acc.n_variants();
+    let ibs = acc.finish();
+    let distances = ibs.distance();
+    if distances.iter().any(|d| d.is_nan()) {
+        return Err("Some pairs of samples have no common calls.".to_string());
+    }
+
+    let write = |extension: &str,
+                 f: &dyn Fn(&mut BufWriter<File>) -> io::Result<()>| {
+        let filename = format!("{}.{}", out, extension);
+        File::create(&filename)
+            .and_then(|file| f(&mut BufWriter::new(file)))
+            .map_err(|e| format!("Could not write `{}`: {}", filename, e))
+    };
+
+    write("mibs", &|w| write_matrix(w, &ibs.similarity()))?;
+    let clusters = if n_clusters > 0 {
+        Some(hierarchical(&distances, n_clusters, linkage))
+    } else {
+        None
+    };
+    write("mds", &|w| write_mds(w, &samples, &compute_mds(&distances, dims),
+                                clusters.as_ref()))?;
+
+    eprintln!("Computed the IBS of {} samples using {} variants.",
+              samples.len(), n_variants);
+
+    Ok(())
+}
+
+
+// Samples recommended for exclusion, with the reasons: a missing call rate
+// or heterozygosity with an absolute robust z-score greater than `--max-z`,
+// or a robust Mahalanobis distance on the first `--pcs` principal
+// components with a p-value smaller than `--min-p` (0 PCs to only use the QC
+// metrics). The output can be given to plink `--remove`. Like the other
+// analyses of the samples, only the autosomes are used by default (see
+// `chromosome_filter`), and the variants of the `--exclude-regions` aren't
+// used for the QC metrics and the PCs (see `excluded_regions`).
+pub fn outliers(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "pcs", "max-z", "min-p",
+                                   "all-chromosomes", "exclude-chr",
+                                   "exclude-regions", "build", "out"])?;
+
+    let reader = open_plink(args.required("bfile")?)?
+        .filter_chromosomes(chromosome_filter(&args)?);
+    let samples = reader.samples().to_vec();
+    let n_pcs: usize = args.parse_or("pcs", 4)?;
+    let max_z: f64 = args.parse_or("max-z", 4.0)?;
+    let min_p: f64 = args.parse_or("min-p", 1e-6)?;
+    let mut out = output(&args)?;
+
+    if n_pcs > samples.len() {
+        return Err(format!("Expected at most {} PCs for `--pcs`.",
+                           samples.len()));
+    }
+
+    let regions = excluded_regions(&args)?;
+    let mut qc = QcReport::new();
+    let mut grm = GrmAccumulator::new();
+    VariantScan::new()
+        .register(&mut qc)
+        .register(&mut grm)
+        .run(reader.filter(|g| !regions.contains(&g.variant)));
+
+    let missing: Vec<f64> = qc.samples.iter()
+        .map(|s| s.n_missing as f64 / (s.n_called() + s.n_missing) as f64)
+        .collect();
+    let heterozygosity: Vec<f64> = qc.samples.iter()
+        .map(|s| s.n_het as f64 / s.n_called() as f64)
+        .collect();
+
+    let mut detector = OutlierDetector::new(samples.len());
+    detector.flag_metric("missing", &missing, max_z);
+    detector.flag_metric("het", &heterozygosity, max_z);
+    if n_pcs > 0 {
+        if grm.n_variants() == 0 {
+            return Err("No variants to compute the PCs.".to_string());
+        }
+        let pcs = pcs_from_grm(&grm.finish(), n_pcs);
+        detector.flag_multivariate(&format!("PC1-{}", n_pcs), &pcs.vectors,
+                                   min_p);
+    }
+
+    let n = detector.write_exclusions(&mut out, &samples)
+        .map_err(|e| format!("Could not write the exclusions: {}", e))?;
+    eprintln!("Recommended the exclusion of {} of {} samples.", n,
+              samples.len());
+
+    Ok(())
+}
+
+
+// genepa pca --bfile prefix [--pcs 10] [--passes 6] [--all-chromosomes]
+//            [--exclude-chr chrom ...]
+//            [--exclude-regions high-ld|file.bed [--build grch37]]
+//            --out prefix
+//
+// PCs of the samples computed without the GRM (see `streaming_pcs`), for
+// datasets too large for `outliers`. The fileset is read `--passes` times,
+// and more passes give more accurate PCs. The PCs and their eigenvalues are
+// written to `{out}.eigenvec` and `{out}.eigenval` (like plink2 `--pca`).
+pub fn pca(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "pcs", "passes",
+                                   "all-chromosomes", "exclude-chr",
+                                   "exclude-regions", "build", "out"])?;
+
+    let bfile = args.required("bfile")?;
+    let samples = open_plink(bfile)?.samples().to_vec();
+    let n_pcs: usize = args.parse_or("pcs", 10)?;
+    let passes: usize = args.parse_or("passes", DEFAULT_PASSES)?;
+    let out = args.required("out")?;
+
+    if n_pcs == 0 || n_pcs > samples.len() {
+        return Err(format!("Expected 1 to {} PCs for `--pcs`.",
+                           samples.len()));
+    }
+
+    if passes < 2 {
+        return Err("Expected at least 2 passes for `--passes`.".to_string());
+    }
+
+    let filter = chromosome_filter(&args)?;
+    let regions = &excluded_regions(&args)?;
+    let pcs = streaming_pcs(
+        || {
+            PlinkReader::new(bfile)
+                .expect("Could not read the fileset again.")
+                .filter_chromosomes(filter.clone())
+                .filter(move |g| !regions.contains(&g.variant))
+        },
+        n_pcs,
+        passes
+    );
+
+    let write = |extension: &str,
+                 f: &dyn Fn(&mut BufWriter<File>) -> io::Result<()>| {
+        let filename = format!("{}.{}", out, extension);
+        File::create(&filename)
+            .and_then(|file| f(&mut BufWriter::new(file)))
+            .map_err(|e| format!("Could not write `{}`: {}", filename, e))
+    };
+
+    write("eigenvec", &|w| write_eigenvec(w, &samples, &pcs))?;
+    write("eigenval", &|w| write_eigenval(w, &pcs))?;
+    eprintln!("Wrote {} PCs of {} samples to `{}.eigenvec`.", n_pcs,
+              samples.len(), out);
+
+    Ok(())
+}
+
+
+// genepa merge --bfiles a b c --out merged
+//
+// The harmonized and dropped variants are listed in `{out}.mismatches`.
+pub fn merge(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfiles", "out"])?;
+
+    let prefixes = args.get_all("bfiles");
+    if prefixes.len() < 2 {
+        return Err("At least 2 filesets are required for `--bfiles`."
+                   .to_string());
+    }
+    let out = args.required("out")?;
+
+    let report = merge_plink(&prefixes, out)
+        .map_err(|e| format!("Could not merge the filesets: {}", e))?;
+
+    let filename = format!("{}.mismatches", out);
+    File::create(&filename)
+        .and_then(|f| {
+            let mut w = BufWriter::new(f);
+            report.write_mismatches(&mut w, &prefixes)?;
+            w.flush()
+        })
+        .map_err(|e| format!("Could not write `{}`: {}", filename, e))?;
+
+    eprintln!("Merged {} samples and {} variants into `{}` ({} variants \
+               harmonized or dropped, see `{}`).", report.n_samples,
+              report.n_variants, out, report.mismatches.len(), filename);
+
+    Ok(())
+}
+
+
+
+// genepa liftover --bfile prefix --chain hg19ToHg38.chain --out prefix_b38
+//
+// The variants that were not lifted are listed in `{out}.unmapped`.
+pub fn liftover(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "chain", "out"])?;
+
+    let prefix = args.required("bfile")?;
+    let out = args.required("out")?;
+    let chains = ChainMap::new(args.required("chain")?);
+
+    let report = liftover_plink(prefix, &chains, out)
+        .map_err(|e| format!("Could not lift the fileset: {}", e))?;
+
+    let filename = format!("{}.unmapped", out);
+    File::create(&filename)
+        .and_then(|f| {
+            let mut w = BufWriter::new(f);
+            report.write_unmapped(&mut w)?;
+            w.flush()
+        })
+        .map_err(|e| format!("Could not write `{}`: {}", filename, e))?;
+
+    eprintln!("Lifted {} variants into `{}` ({} not lifted, see `{}`).",
+              report.n_variants, out, report.unmapped.len(), filename);
+
+    Ok(())
+}
+
+
+// genepa simulate --n-samples 1000 --n-variants 50000
+//                 [--maf-dist uniform|neutral|0.2] [--seed 1] --out sim
+pub fn simulate(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["n-samples", "n-variants", "maf-dist",
+                                   "seed", "out"])?;
+
+    let n_samples: usize = args.required("n-samples")?
+        .parse()
+        .map_err(|_| "Invalid value for `--n-samples`.".to_string())?;
+    let n_variants: usize = args.required("n-variants")?
+        .parse()
+        .map_err(|_| "Invalid value for `--n-variants`.".to_string())?;
+    let maf_distribution: MafDistribution = args.get("maf-dist")
+        .unwrap_or("uniform")
+        .parse()?;
+    let seed: u64 = args.parse_or("seed", 1)?;
+    let out = args.required("out")?;
+
+    let simulator = GenotypeSimulator::new(n_samples, n_variants,
+                                           maf_distribution, seed);
+
+    let n_written = PlinkWriter::new(out, &simulate_samples(n_samples))
+        .and_then(|writer| writer.write_all(simulator))
+        .map_err(|e| format!("Could not write `{}`: {}", out, e))?;
+
+    eprintln!("Simulated {} samples and {} variants into `{}`.", n_samples,
+              n_written, out);
+
+    Ok(())
+}
+
+
+// genepa sample --bfile prefix [--variants all|fraction|n] [--n-samples n]
+//               [--seed 1] --out prefix
+//
+// Write a random subset of the variants and samples of a fileset (see
+// VariantSelection), reproducible given the seed.
+pub fn sample(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "variants", "n-samples", "seed",
+                                   "out"])?;
+
+    let prefix = args.required("bfile")?;
+    let variants: VariantSelection = args.get("variants")
+        .unwrap_or("all")
+        .parse()?;
+    let n_samples: Option<usize> = args.get("n-samples")
+        .map(|n| {
+            n.parse()
+                .map_err(|_| "Invalid value for `--n-samples`.".to_string())
+        })
+        .transpose()?;
+    let seed: u64 = args.parse_or("seed", 1)?;
+    let out = args.required("out")?;
+
+    let reader = open_plink(prefix)?;
+    let n_written = write_subset_fileset(reader, out, variants, n_samples,
+                                         seed)
+        .map_err(|e| format!("Could not write `{}`: {}", out, e))?;
+
+    eprintln!("Wrote {} variants into `{}`.", n_written, out);
+
+    Ok(())
+}
+
+
+// genepa filter --bfile prefix [--maf 0.01] [--geno 0.05]
+//               [--freq-estimator observed] (--out prefix | --dry-run)
+//
+// Keep the variants with a MAF of at least `--maf` and a missing call rate
+// of at most `--geno` (like plink). With `--dry-run`, the variants passing
+// the filters are only counted, without decoding the genotypes. The MAF is
+// estimated from the observed alleles unless another estimator is given
+// (see FrequencyEstimator).
+pub fn filter(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "maf", "geno", "freq-estimator",
+                                   "out", "dry-run"])?;
+
+    let prefix = args.required("bfile")?;
+    let min_maf: f64 = args.parse_or("maf", 0.0)?;
+    let max_missing: f64 = args.parse_or("geno", 1.0)?;
+    let estimator: FrequencyEstimator =
+        args.parse_or("freq-estimator", FrequencyEstimator::Observed)?;
+    let dry_run = args.get("dry-run").is_some();
+
+    let passes = |c: &VariantCounts| {
+        let maf = c.coded_frequency_with(2, estimator)
+            .map_or(f64::NAN, |p| p.minor().get());
+        maf >= min_maf && 1.0 - c.call_rate() <= max_missing
+    };
+
+    let mut reader = open_plink(prefix)?;
+    let n_variants = reader.n_variants();
+
+    let n_passing = if dry_run {
+        reader.count_if(|_, c| passes(c))
+    } else {
+        let out = args.required("out")?;
+        reader.haploid_policy(None);
+
+        PlinkWriter::new(out, reader.samples())
+            .and_then(|writer| {
+                writer.write_all(reader.filter(|g| {
+                    passes(&VariantCounts::of(g))
+                }))
+            })
+            .map_err(|e| format!("Could not write `{}`: {}", out, e))?
+    };
+
+    eprintln!("{} of {} variants pass the filters.", n_passing, n_variants);
+
+    Ok(())
+}
+
+
+// genepa rename --bim file --annotation file.vcf.gz [--out file]
+//
+// Name the variants of a BIM without names (`.`) with the IDs of the
+// records of an indexed annotation VCF (e.g. dbSNP) with the same position
+// and alleles. The other variants are written unchanged.
+pub fn rename(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bim", "annotation", "out"])?;
+
+    let bim = args.required("bim")?;
+    let mut annotation = AnnotationVcf::new(args.required("annotation")?);
+    let lines = File::open(bim)
+        .map(|f| BufReader::new(f).lines())
+        .map_err(|e| format!("Could not open `{}`: {}", bim, e))?;
+
+    let mut out = output(&args)?;
+    let (mut n_missing, mut n_renamed) = (0, 0);
+
+    for (i, line) in lines.enumerate() {
+        let line = line.map_err(|e| format!("Could not read `{}`: {}", bim,
+                                            e))?;
+        let mut fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 6 {
+            return Err(format!("Expected 6 columns on line {} of `{}`.",
+                               i + 1, bim));
+        }
+
+        let mut v = Variant::new(
+            fields[1].to_string(), fields[0].to_string(),
+            fields[3].parse().map_err(|_| {
+                format!("Invalid position on line {} of `{}`.", i + 1, bim)
+            })?,
+            (fields[4].to_string(), fields[5].to_string())
+        );
+
+        if is_missing_name(&v.name) {
+            n_missing += 1;
+            if annotation.rename(&mut v) {
+                n_renamed += 1;
+                fields[1] = &v.name;
+            }
+        }
+
+        writeln!(out, "{}", fields.join("\t"))
+            .map_err(|e| format!("Could not write the BIM: {}", e))?;
+    }
+
+    out.flush().map_err(|e| format!("Could not write the BIM: {}", e))?;
+    eprintln!("Renamed {} of {} variants without a name.", n_renamed,
+              n_missing);
+
+    Ok(())
+}
+
+
+// genepa check-ref --bim file --fasta ref.fa [--out file]
+//
+// Compare the alleles of the BIM variants to an indexed reference FASTA of
+// the same build. The variants that don't match the reference are written
+// with the reference base and the result of the check (strand-flip, mismatch
+// or unknown).
+pub fn check_ref(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bim", "fasta", "out"])?;
+
+    let bim = args.required("bim")?;
+    let mut fasta = FastaReader::new(args.required("fasta")?);
+    let mut out = output(&args)?;
+    let write_err = |e: io::Error| {
+        format!("Could not write the variants: {}", e)
+    };
+
+    writeln!(out, "SNP\tCHR\tBP\tA1\tA2\tREF\tSTATUS").map_err(write_err)?;
+
+    let mut counts: HashMap<RefCheck, usize> = HashMap::new();
+    for oav in BimReader::new(bim).map_err(|e| e.to_string())? {
+        let oav = oav.map_err(|e| e.to_string())?;
+        let v = &oav.variant;
+        let check = fasta.check(v);
+        *counts.entry(check).or_default() += 1;
+
+        let status = match check {
+            RefCheck::Match => continue,
+            RefCheck::StrandFlip => "strand-flip",
+            RefCheck::Mismatch => "mismatch",
+            RefCheck::Unknown => "unknown"
+        };
+
+        let (a1, a2) = if oav.a1_idx == 0 {
+            (&v.alleles.0, &v.alleles.1)
+        } else {
+            (&v.alleles.1, &v.alleles.0)
+        };
+        let reference = fasta
+            .fetch(&v.chrom.to_string(), v.position, v.position)
+            .unwrap_or_else(|| ".".to_string());
+
+        writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}", v.name, v.chrom,
+                 v.position, a1, a2, reference, status).map_err(write_err)?;
+    }
+
+    out.flush().map_err(write_err)?;
+    let count = |check| counts.get(&check).copied().unwrap_or(0);
+    eprintln!("{} variants match the reference, {} are on the other strand, \
+               {} don't match and {} are unknown.", count(RefCheck::Match),
+              count(RefCheck::StrandFlip), count(RefCheck::Mismatch),
+              count(RefCheck::Unknown));
+
+    Ok(())
+}
+
+
+// genepa append --bfile prefix (--samples prefix | --variants prefix)
+//
+// Append the samples or the variants of another fileset to the fileset in
+// place.
+pub fn append(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "samples", "variants"])?;
+    let prefix = args.required("bfile")?;
+
+    let report = match (args.get("samples"), args.get("variants")) {
+        (Some(_), None) => {
+            let new_prefix = args.required("samples")?;
+            append_samples(prefix, new_prefix)
+        },
+        (None, Some(_)) => {
+            let new_prefix = args.required("variants")?;
+            append_variants(prefix, new_prefix)
+        },
+        _ => return Err("Expected one of `--samples` or `--variants`."
+                        .to_string())
+    }.map_err(|e| format!("Could not append to `{}`: {}", prefix, e))?;
+
+    eprintln!("`{}` now has {} samples and {} variants.", prefix,
+              report.n_samples, report.n_variants);
+
+    Ok(())
+}
+
+
+// genepa verify-roundtrip --bfile prefix [--format plink|vcf|bgen]
+//                         --out prefix
+//
+// Write the fileset in the format, read it back and check that nothing
+// changed. The mismatching variants are listed on stderr.
+pub fn verify_roundtrip(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "format", "out"])?;
+
+    let prefix = args.required("bfile")?;
+    let out = args.required("out")?;
+    let format: RoundtripFormat = args.get("format")
+        .unwrap_or("plink")
+        .parse()?;
+
+    let report = roundtrip::verify_roundtrip(prefix, format, out)
+        .map_err(|e| format!("Could not write `{}`: {}",
+                             format.filename(out), e))?;
+
+    for mismatch in report.mismatches.iter() {
+        eprintln!("{}", mismatch);
+    }
+
+    if !report.samples_match {
+        return Err("The samples changed in the round-trip.".to_string());
+    }
+    if report.n_variants != report.n_reread {
+        return Err(format!("Wrote {} variants but read back {}.",
+                           report.n_variants, report.n_reread));
+    }
+    if report.n_mismatches > 0 {
+        return Err(format!("{} of {} variants changed in the round-trip.",
+                           report.n_mismatches, report.n_variants));
+    }
+
+    eprintln!("The {} samples and {} variants are identical after the \
+               round-trip.", report.n_samples, report.n_variants);
+
+    Ok(())
+}
+
+
+// The whitespace separated fields of the non-empty lines of a file.
+pub fn read_fields(filename: &str) -> Result<Vec<Vec<String>>, String> {
+    let f = File::open(filename)
+        .map_err(|e| format!("Could not open `{}`: {}", filename, e))?;
+
+    let mut lines = Vec::new();
+    for line in BufReader::new(f).lines() {
+        let line = line.map_err(|e| format!("Could not read `{}`: {}",
+                                            filename, e))?;
+        let fields: Vec<String> = line.split_whitespace()
+            .map(|field| field.to_string())
+            .collect();
+
+        if !fields.is_empty() {
+            lines.push(fields);
+        }
+    }
+
+    Ok(lines)
+}
+
+
+// genepa convert --in path [--format plink|pgen|vcf|bgen|zarr]
+//                --out prefix [--keep file] [--extract file]
+//
+// Convert a dataset in any format read by `open_source`, keeping only the
+// samples of `--keep` (FID and IID) and the variants of `--extract` (names)
+// if given. The VCF header needs the contigs, so they are found by a first
+// pass over the variants.
+pub fn convert(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["in", "format", "out", "keep",
+                                   "extract"])?;
+
+    let input = args.required("in")?;
+    let out = args.required("out")?;
+    let format = args.get("format").unwrap_or("plink");
+
+    let source = open_source(input);
+    let mut filters = Filters::default();
+
+    if args.get("keep").is_some() {
+        let keep = read_fields(args.required("keep")?)?;
+        let ids: HashSet<(String, String)> = keep.into_iter()
+            .filter(|fields| fields.len() >= 2)
+            .map(|fields| (fields[0].clone(), fields[1].clone()))
+            .collect();
+        filters = filters.keep_samples(source.samples(), &ids);
+    }
+
+    if args.get("extract").is_some() {
+        let names = read_fields(args.required("extract")?)?
+            .into_iter()
+            .map(|fields| fields[0].clone())
+            .collect();
+        filters = filters.keep_variants(names);
+    }
+
+    let samples = filters.kept_samples(source.samples());
+
+    let report = match format {
+        "plink" => PlinkWriter::new(out, &samples)
+            .and_then(|writer| convert_filtered(source, writer, &filters)),
+        "pgen" => PgenWriter::new(out, &samples)
+            .and_then(|writer| convert_filtered(source, writer, &filters)),
+        "vcf" => {
+            let mut contigs: Vec<String> = Vec::new();
+            for g in open_source(input) {
+                let contig = g.variant.chrom.to_string();
+                if !contigs.contains(&contig) {
+                    contigs.push(contig);
+                }
+            }
+            let contigs: Vec<&str> = contigs.iter()
+                .map(|c| c.as_str())
+                .collect();
+
+            VcfWriter::create(&format!("{}.vcf", out), &samples, &contigs,
+                              false)
+                .and_then(|writer| convert_filtered(source, writer, &filters))
+        },
+        "bgen" => BgenWriter::create(&format!("{}.bgen", out), &samples)
+            .and_then(|writer| convert_filtered(source, writer, &filters)),
+        "zarr" => ZarrWriter::new(&format!("{}.zarr", out), &samples)
+            .and_then(|writer| convert_filtered(source, writer, &filters)),
+        _ => return Err(format!("Unknown format `{}` (expected plink, pgen, \
+                                 vcf, bgen or zarr).", format))
+    }.map_err(|e| format!("Could not convert `{}`: {}", input, e))?;
+
+    eprintln!("Wrote {} of {} variants for {} samples.", report.n_written,
+              report.n_read, samples.len());
+
+    Ok(())
+}
+
+
+// Parse a `chrom:start-end` region.
+fn parse_region(region: &str) -> Result<(Chromosome, u32, u32), String> {
+    let invalid = || format!("Invalid region `{}` (expected \
+                              chrom:start-end).", region);
+
+    let (chrom, range) = region.rsplit_once(':').ok_or_else(invalid)?;
+    let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+
+    Ok((Chromosome::new(chrom),
+        start.parse().map_err(|_| invalid())?,
+        end.parse().map_err(|_| invalid())?))
+}
+
+
+// genepa export-long --in path (--region chrom:start-end | --extract file |
+//                    --gene name --gtf file [--flank 0]) [--out file]
+//
+// Write the genotypes of a region, of a list of variants (names) or of a
+// gene (and its flanking regions, from a GTF or GFF3 file) with one row per
+// sample and variant.
+pub fn export_long(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["in", "region", "extract", "gene", "gtf",
+                                   "flank", "out"])?;
+    let input = args.required("in")?;
+
+    let mut source = open_source(input);
+    let samples = source.samples().to_vec();
+
+    let selections = ["region", "extract", "gene"].iter()
+        .filter(|name| args.get(name).is_some())
+        .count();
+    if selections != 1 {
+        return Err("Expected one of `--region`, `--extract` or `--gene`."
+                   .to_string());
+    }
+
+    let genotypes: Box<dyn Iterator<Item = Genotypes>> =
+        if let Some(region) = args.get("region") {
+            let (chrom, start, end) = parse_region(region)?;
+            Box::new(source.get_variants_in_region(&chrom, start, end)
+                     .into_iter())
+        } else if args.get("extract").is_some() {
+            let names: HashSet<String> =
+                read_fields(args.required("extract")?)?
+                    .into_iter()
+                    .map(|fields| fields[0].clone())
+                    .collect();
+            Box::new(source.filter(move |g| names.contains(&g.variant.name)))
+        } else {
+            let gene = args.required("gene")?;
+            let genes = GeneModel::new(args.required("gtf")?);
+            let flank: u32 = args.parse_or("flank", 0)?;
+
+            let genotypes = get_variants_in_gene(&mut source, &genes, gene,
+                                                 flank)
+                .ok_or_else(|| format!("Unknown gene `{}`.", gene))?;
+            Box::new(genotypes.into_iter())
+        };
+
+    let mut out = output(&args)?;
+    let n_rows = write_long_format(&mut out, &samples, genotypes)
+        .and_then(|n| out.flush().map(|_| n))
+        .map_err(|e| format!("Could not write the genotypes: {}", e))?;
+
+    eprintln!("Wrote {} rows for {} samples.", n_rows, samples.len());
+
+    Ok(())
+}
+
+
+// Named columns of values, one value per sample.
+pub type SampleTable = Vec<(String, Vec<Option<f64>>)>;
+
+
+// Read a plink phenotype or covariate file (FID, IID and values) as one
+// vector per column, in the order of the samples. The column names are
+// taken from a `FID IID ...` (or `#FID IID ...`) header if there is one.
+// Missing values (-9, NA or .) and samples not in the file are None.
+pub fn read_sample_table<R: BufRead>(reader: R, samples: &[Sample],
+                                     default_name: &str)
+    -> Result<SampleTable, String>
+{
+    let index: HashMap<(&str, &str), usize> = samples.iter()
+        .enumerate()
+        .map(|(i, s)| ((s.fid.as_str(), s.iid.as_str()), i))
+        .collect();
+
+    let mut columns: SampleTable = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| format!("Could not read line {}: {}",
+                                            i + 1, e))?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        if fields.is_empty() {
+            continue;
+        }
+
+        if columns.is_empty() {
+            if fields.len() < 3 {
+                return Err(format!("Expected FID, IID and values on line \
+                                    {}.", i + 1));
+            }
+
+            let header = matches!(fields[0], "FID" | "#FID");
+            columns = (2..fields.len())
+                .map(|j| {
+                    let name = if header {
+                        fields[j].to_string()
+                    } else {
+                        format!("{}{}", default_name, j - 1)
+                    };
+                    (name, vec![None; samples.len()])
+                })
+                .collect();
+
+            if header {
+                continue;
+            }
+        }
+
+        if fields.len() != columns.len() + 2 {
+            return Err(format!("Expected {} columns on line {}, got {}.",
+                               columns.len() + 2, i + 1, fields.len()));
+        }
+
+        let sample = match index.get(&(fields[0], fields[1])) {
+            Some(&sample) => sample,
+            None => continue
+        };
+
+        for ((_, values), field) in columns.iter_mut().zip(&fields[2..]) {
+            values[sample] = match *field {
+                "-9" | "NA" | "." => None,
+                value => Some(value.parse().map_err(|_| {
+                    format!("Invalid value `{}` on line {}.", value, i + 1)
+                })?)
+            };
+        }
+    }
+
+    Ok(columns)
+}
+
+
+// Read a phenotype or covariate file (see read_sample_table).
+pub fn read_sample_file(filename: &str, samples: &[Sample],
+                        default_name: &str)
+    -> Result<SampleTable, String>
+{
+    let f = File::open(filename)
+        .map_err(|e| format!("Could not open `{}`: {}", filename, e))?;
+    read_sample_table(BufReader::new(f), samples, default_name)
+        .map_err(|e| format!("`{}`: {}", filename, e))
+}
+
+
+// The phenotype named `name` (or the first one) of a phenotype file, recoded
+// as 0 and 1 for logistic regression.
+pub fn read_phenotype(filename: &str, samples: &[Sample], name: Option<&str>,
+                      model: Model)
+    -> Result<(String, Vec<Option<f64>>), String>
+{
+    let phenotypes = read_sample_file(filename, samples, "PHENO")?;
+    let (name, phenotype) = match name {
+        Some(name) => phenotypes.into_iter()
+            .find(|(column, _)| column == name)
+            .ok_or_else(|| format!("No phenotype named `{}`.", name))?,
+        None => phenotypes.into_iter().next()
+            .ok_or("The phenotype file is empty.")?
+    };
+
+    match model {
+        Model::Linear => Ok((name, phenotype)),
+        Model::Logistic => Ok((name, recode_binary(&phenotype)?))
+    }
+}
+
+
+// Recode a binary phenotype as 0 (control) and 1 (case). Both the plink
+// (1 and 2) and the 0 and 1 codings are accepted.
+fn recode_binary(values: &[Option<f64>]) -> Result<Vec<Option<f64>>, String> {
+    let observed = |code: f64| values.contains(&Some(code));
+    let shift = if observed(2.0) { 1.0 } else { 0.0 };
+
+    values.iter()
+        .map(|v| match v.map(|v| v - shift) {
+            Some(v) if v == 0.0 || v == 1.0 => Ok(Some(v)),
+            Some(_) => Err("Binary phenotypes must be coded 1 (control) \
+                            and 2 (case), or 0 and 1.".to_string()),
+            None => Ok(None)
+        })
+        .collect()
+}
+
+
+// Format like printf's %g with 6 significant digits (as in plink).
+fn format_glm_float(x: f64) -> String {
+    if x == 0.0 || !x.is_finite() {
+        return x.to_string();
+    }
+
+    let exponent = x.abs().log10().floor() as i32;
+    if !(-4..6).contains(&exponent) {
+        let s = format!("{:.5e}", x);
+        let (mantissa, exp) = s.split_once('e').unwrap();
+        let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+        let exp: i32 = exp.parse().unwrap();
+        format!("{}e{}{:02}", mantissa, if exp < 0 { '-' } else { '+' },
+                exp.abs())
+    } else {
+        let s = format!("{:.*}", (5 - exponent) as usize, x);
+        s.trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+}
+
+
+// With flagged regions, the REGION column has the names of the regions of
+// every variant (comma-separated, or `.`).
+pub fn write_glm_header<W: Write>(out: &mut W, model: Model,
+                                  flagged: Option<&RegionLabels>)
+    -> io::Result<()>
+{
+    match model {
+        Model::Linear => write!(out, "#CHROM\tPOS\tID\tREF\tALT\tA1\tTEST\t\
+                                      OBS_CT\tBETA\tSE\tT_STAT\tP")?,
+        Model::Logistic => write!(out, "#CHROM\tPOS\tID\tREF\tALT\tA1\t\
+                                        TEST\tOBS_CT\tOR\tLOG(OR)_SE\t\
+                                        Z_STAT\tP")?
+    }
+
+    match flagged {
+        Some(_) => writeln!(out, "\tREGION"),
+        None => writeln!(out)
+    }
+}
+
+
+pub fn write_glm_row<W: Write>(out: &mut W, g: &Genotypes,
+                               result: &AssocResult, model: Model,
+                               flagged: Option<&RegionLabels>)
+    -> io::Result<()>
+{
+    let v = &g.variant;
+    write!(out, "{}\t{}\t{}\t{}\t{}\t{}\tADD\t{}", v.chrom, v.position,
+           v.name, g.other_allele(), g.coded_allele(), g.coded_allele(),
+           result.n_obs)?;
+
+    match result.estimate {
+        Some(e) => {
+            let effect = match model {
+                Model::Linear => e.beta,
+                Model::Logistic => e.beta.exp()
+            };
+            write!(out, "\t{}\t{}\t{}\t{}", format_glm_float(effect),
+                   format_glm_float(e.se), format_glm_float(e.stat),
+                   format_glm_float(e.p))?;
+        },
+        None => write!(out, "\tNA\tNA\tNA\tNA")?
+    }
+
+    match flagged.map(|flagged| flagged.labels(v)) {
+        Some(labels) if labels.is_empty() => writeln!(out, "\t."),
+        Some(labels) => writeln!(out, "\t{}", labels.join(",")),
+        None => writeln!(out)
+    }
+}
+
+
+// Write association results using the columns of plink2 `--glm` (only the
+// additive genotype test, like `hide-covar`). A1 (and ALT) is the coded
+// allele. Results of models that could not be fitted are NA.
+pub fn write_glm<W, I>(out: &mut W, results: I, model: Model,
+                       flagged: Option<&RegionLabels>)
+    -> io::Result<()>
+    where W: Write, I: IntoIterator<Item = (Genotypes, AssocResult)>
+{
+    write_glm_header(out, model, flagged)?;
+    for (g, result) in results {
+        write_glm_row(out, &g, &result, model, flagged)?;
+    }
+
+    out.flush()
+}
+
+
+// Results of `assoc` for a phenotype (like plink2).
+pub fn glm_filename(out: &str, pheno_name: &str, model: Model) -> String {
+    let extension = match model {
+        Model::Linear => "linear",
+        Model::Logistic => "logistic"
+    };
+    format!("{}.{}.glm.{}", out, pheno_name, extension)
+}
+
+
+// genepa assoc --bfile prefix --pheno p.tsv [--pheno-name name]
+//              [--covar c.tsv] [--model linear|logistic] [--within-family]
+//              [--all-chromosomes] [--exclude-chr chrom ...] [--split-x]
+//              [--flag-regions complex|file.bed] [--build grch37]
+//              --out results
+//
+// The results are written to `{out}.{pheno}.glm.{linear|logistic}`. With
+// `--within-family`, the linear model has family (FID) fixed effects. With
+// `--flag-regions`, the results are flagged in the REGION column (see
+// `flagged_regions`). The males are coded 0 or 1 on the X chromosome (see
+// `hemizygous_coding`), except in the PARs of the build with `--split-x`.
+pub fn assoc(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "pheno", "pheno-name", "covar",
+                                   "model", "within-family",
+                                   "all-chromosomes", "exclude-chr",
+                                   "split-x", "flag-regions", "build",
+                                   "out"])?;
+
+    let reader = open_plink(args.required("bfile")?)?
+        .filter_chromosomes(chromosome_filter(&args)?);
+    let sexes: Vec<Sex> = reader.samples().iter().map(|s| s.sex).collect();
+    let build: GenomeBuild = args.get("build").unwrap_or("grch37").parse()?;
+    let flagged = match args.get("flag-regions") {
+        Some(_) => Some(flagged_regions(args.required("flag-regions")?, build)),
+        None => None
+    };
+    let model: Model = args.get("model").unwrap_or("linear").parse()?;
+    let out = args.required("out")?;
+
+    let families = match args.get("within-family") {
+        Some(_) if model == Model::Logistic => {
+            return Err("The within-family test requires the linear model."
+                       .to_string());
+        },
+        Some(_) => Some(family_indices(reader.samples())),
+        None => None
+    };
+
+    let (name, phenotype) = read_phenotype(args.required("pheno")?,
+                                           reader.samples(),
+                                           args.get("pheno-name"), model)?;
+
+    let covariates: Vec<Vec<Option<f64>>> = match args.get("covar") {
+        Some(_) => read_sample_file(args.required("covar")?,
+                                    reader.samples(), "COVAR")?
+            .into_iter()
+            .map(|(_, values)| values)
+            .collect(),
+        None => Vec::new()
+    };
+
+    let filename = glm_filename(out, &name, model);
+    let f = File::create(&filename)
+        .map_err(|e| format!("Could not create `{}`: {}", filename, e))?;
+
+    let genotypes: Box<dyn Iterator<Item = Genotypes>> =
+        match args.get("split-x") {
+            Some(_) => Box::new(split_x_genotypes(reader, build)),
+            None => Box::new(reader)
+        };
+
+    let results = genotypes.map(|g| {
+        let g = hemizygous_coding(g, &sexes);
+        let result = match &families {
+            Some(families) => {
+                test_within_family(&g, &phenotype, &covariates, families)
+            },
+            None => test_association(&g, &phenotype, &covariates, model)
+        };
+        (g, result)
+    });
+
+    write_glm(&mut BufWriter::new(f), results, model, flagged.as_ref())
+        .map_err(|e| format!("Could not write `{}`: {}", filename, e))
+}
+
+
+// Write the TDT results using the columns of plink `--tdt` (with the exact
+// p-value). A1 is the coded allele.
+pub fn write_tdt<W, I>(out: &mut W, results: I) -> io::Result<()>
+    where W: Write, I: IntoIterator<Item = (Genotypes, TdtResult)>
+{
+    writeln!(out, "CHR\tSNP\tBP\tA1\tA2\tT\tU\tOR\tCHISQ\tP\tP_EXACT")?;
+
+    let format = |x: f64| {
+        if x.is_nan() { "NA".to_string() } else { format_glm_float(x) }
+    };
+
+    for (g, result) in results {
+        writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                 g.variant.chrom, g.variant.name, g.variant.position,
+                 g.coded_allele(), g.other_allele(), result.transmitted,
+                 result.untransmitted, format(result.odds_ratio()),
+                 format(result.chisq), format(result.p),
+                 format(result.exact_p))?;
+    }
+
+    out.flush()
+}
+
+
+// genepa grm --bfile prefix [--band-rows 10000] [--related 0.125]
+//            [--all-chromosomes] [--exclude-chr chrom ...]
+//            [--exclude-regions high-ld|file.bed [--build grch37]]
+//            --out prefix
+//
+// GRM of the samples written to disk in the GCTA binary format
+// (`{out}.grm.bin`, `{out}.grm.N.bin` and `{out}.grm.id`) without holding
+// it in memory: it is computed by bands of `--band-rows` rows, with a pass
+// over the fileset per band (see `write_grm`). With `--related`, the pairs
+// of samples with at least this relatedness are written to `{out}.related`.
+pub fn grm(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "band-rows", "related",
+                                   "all-chromosomes", "exclude-chr",
+                                   "exclude-regions", "build", "out"])?;
+
+    let bfile = args.required("bfile")?;
+    let samples = open_plink(bfile)?.samples().to_vec();
+    let band_rows: usize = args.parse_or("band-rows", 10000)?;
+    let min_relatedness: Option<f64> = args.get("related")
+        .map(|x| {
+            x.parse()
+                .map_err(|_| format!("Invalid value for `--related`: `{}`.",
+                                     x))
+        })
+        .transpose()?;
+    let out = args.required("out")?;
+
+    if band_rows == 0 {
+        return Err("Expected at least 1 row for `--band-rows`.".to_string());
+    }
+
+    let filter = chromosome_filter(&args)?;
+    let regions = &excluded_regions(&args)?;
+    let mut grm = write_grm(
+        || {
+            PlinkReader::new(bfile)
+                .expect("Could not read the fileset again.")
+                .filter_chromosomes(filter.clone())
+                .filter(move |g| !regions.contains(&g.variant))
+        },
+        &samples,
+        out,
+        band_rows
+    ).map_err(|e| format!("Could not write the GRM: {}", e))?;
+    eprintln!("Wrote the GRM of {} samples to `{}.grm.bin`.", samples.len(),
+              out);
+
+    let min_relatedness = match min_relatedness {
+        Some(x) => x,
+        None => return Ok(())
+    };
+
+    let filename = format!("{}.related", out);
+    let pairs = grm.related_pairs(min_relatedness)
+        .map_err(|e| format!("Could not read the GRM: {}", e))?;
+    File::create(&filename)
+        .and_then(|f| {
+            let mut w = BufWriter::new(f);
+            writeln!(w, "FID1\tIID1\tFID2\tIID2\tRELATEDNESS")?;
+            for (i, j, value) in pairs.iter() {
+                let (a, b) = (&samples[*j], &samples[*i]);
+                writeln!(w, "{}\t{}\t{}\t{}\t{:.6}", a.fid, a.iid, b.fid,
+                         b.iid, value)?;
+            }
+            w.flush()
+        })
+        .map_err(|e| format!("Could not write `{}`: {}", filename, e))?;
+    eprintln!("Found {} pairs with a relatedness of at least {}.",
+              pairs.len(), min_relatedness);
+
+    Ok(())
+}
+
+
+// genepa heritability --bfile prefix --pheno p.tsv [--pheno-name name]
+//                     [--all-chromosomes] [--exclude-chr chrom ...]
+//                     [--exclude-regions high-ld|file.bed [--build grch37]]
+//                     [--out file]
+//
+// Haseman-Elston regression on the GRM of the variants of the fileset.
+pub fn heritability(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "pheno", "pheno-name",
+                                   "all-chromosomes", "exclude-chr",
+                                   "exclude-regions", "build", "out"])?;
+
+    let reader = open_plink(args.required("bfile")?)?
+        .filter_chromosomes(chromosome_filter(&args)?);
+    let (name, phenotype) = read_phenotype(args.required("pheno")?,
+                                           reader.samples(),
+                                           args.get("pheno-name"),
+                                           Model::Linear)?;
+
+    let regions = excluded_regions(&args)?;
+    let grm = compute_grm(reader.filter(|g| !regions.contains(&g.variant)));
+    let he = haseman_elston(&grm, &phenotype).ok_or_else(|| {
+        format!("Could not estimate the heritability of `{}`.", name)
+    })?;
+
+    let mut out = output(&args)?;
+    writeln!(out, "PHENO\tN\tN_PAIRS\tH2\tSE")
+        .and_then(|_| {
+            writeln!(out, "{}\t{}\t{}\t{}\t{}", name, he.n_samples,
+                     he.n_pairs, format_glm_float(he.h2),
+                     format_glm_float(he.se))
+        })
+        .and_then(|_| out.flush())
+        .map_err(|e| format!("Could not write the heritability: {}", e))
+}
+
+
+// genepa ldsc --bfile prefix --sumstats file [--n n] [--window-kb 1000]
+//             [--blocks 200] [--out file]
+//
+// The LD scores are computed from the fileset. The summary statistics are
+// tab-delimited with the plink column names (SNP, CHR, BP, A1, A2, BETA, SE,
+// P and N), and `--n` is the sample size when there is no N column.
+pub fn ldsc(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "sumstats", "n", "window-kb",
+                                   "blocks", "out"])?;
+
+    let n: Option<f64> = args.get("n")
+        .map(|n| {
+            n.parse()
+                .map_err(|_| format!("Invalid value for `--n`: `{}`.", n))
+        })
+        .transpose()?;
+    let window_kb: u32 = args.parse_or("window-kb", 1000)?;
+    let n_blocks: usize = args.parse_or("blocks", 200)?;
+
+    let reader = open_plink(args.required("bfile")?)?;
+    let scores = ld_scores(reader, window_kb.saturating_mul(1000));
+
+    let sumstats = SummaryStatsReader::new(args.required("sumstats")?, '\t',
+                                           &SummaryStatsColumns::default());
+    let observations = match_sumstats(&scores, sumstats, n);
+
+    let result = regress_ld_scores(&observations, scores.len() as f64,
+                                   n_blocks)
+        .ok_or_else(|| {
+            format!("Could not regress the chi-squares of {} variants on \
+                     their LD scores.", observations.len())
+        })?;
+
+    let mut out = output(&args)?;
+    writeln!(out, "N_VARIANTS\tMEAN_CHISQ\tH2\tH2_SE\tINTERCEPT\t\
+                   INTERCEPT_SE")
+        .and_then(|_| {
+            writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}", result.n_variants,
+                     format_glm_float(result.mean_chisq),
+                     format_glm_float(result.h2.estimate),
+                     format_glm_float(result.h2.se),
+                     format_glm_float(result.intercept.estimate),
+                     format_glm_float(result.intercept.se))
+        })
+        .and_then(|_| out.flush())
+        .map_err(|e| format!("Could not write the LDSC results: {}", e))
+}
+
+
+// genepa tdt --bfile prefix [--out file]
+//
+// The trios are the samples with both parents in the FAM.
+pub fn tdt(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["bfile", "out"])?;
+
+    let prefix = args.required("bfile")?;
+    let reader = open_plink(prefix)?;
+    let trios = find_trios(reader.samples());
+    if trios.is_empty() {
+        return Err(format!("There are no trios in `{}.fam`.", prefix));
+    }
+
+    let results = reader
+        .filter(|g| !g.is_haploid())
+        .map(|g| {
+            let result = compute_tdt(&g, &trios);
+            (g, result)
+        });
+
+    write_tdt(&mut output(&args)?, results)
+        .map_err(|e| format!("Could not write the TDT results: {}", e))
+}
+
+
+// genepa run --config pipeline.toml [--dry-run]
+//
+// Run the steps of a pipeline (see the pipeline module for the format of the
+// configuration). With `--dry-run`, the passes over the input, the IO and the
+// peak memory are only estimated.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["config", "dry-run"])?;
+
+    let pipeline = Pipeline::from_file(args.required("config")?)?;
+    if args.get("dry-run").is_some() {
+        let plan = pipeline.plan()?;
+        return plan.write(&mut io::stdout())
+            .map_err(|e| format!("Could not write the plan: {}", e));
+    }
+
+    let (n_read, n_passing) = pipeline.run()?;
+    eprintln!("{} of {} variants passed the filters.", n_passing, n_read);
+
+    Ok(())
+}
+
+
+fn write_index_info<W: Write>(out: &mut W, prefix: &str, info: &IndexInfo)
+    -> io::Result<()>
+{
+    writeln!(out, "fileset\t{}", prefix)?;
+    writeln!(out, "format\t{}", info.format)?;
+    for (filename, size) in info.files.iter() {
+        writeln!(out, "file\t{}\t{}", filename, size)?;
+    }
+    writeln!(out, "n_indexed\t{}", info.n_indexed)?;
+    writeln!(out, "n_bim_variants\t{}", info.n_bim_variants)?;
+
+    if let Some(stale) = info.stale {
+        writeln!(out, "stale\t{}", if stale { "yes" } else { "no" })?;
+    }
+
+    for (chrom, n) in info.chromosomes.iter() {
+        writeln!(out, "chromosome\t{}\t{}", chrom, n)?;
+    }
+
+    Ok(())
+}
+
+
+// genepa index <build|validate|inspect|delete> --bfile prefix [--force]
+//              [--format v1|v2]
+//
+// Without `--format`, build creates a v1 (tabix) index and the other
+// actions apply to every index of the fileset.
+pub fn index(args: &[String]) -> Result<(), String> {
+    let (action, args) = match args.split_first() {
+        Some((action, rest)) if !action.starts_with("--") => (action, rest),
+        _ => return Err("Missing index action (build, validate, inspect or \
+                         delete).".to_string())
+    };
+
+    let args = Args::parse(args, &["bfile", "force", "format"])?;
+    let prefix = args.required("bfile")?;
+
+    let formats = match args.get("format") {
+        Some(format) => vec![format.parse::<IndexFormat>()?],
+        None if action == "build" => vec![IndexFormat::V1],
+        None => [IndexFormat::V1, IndexFormat::V2].iter()
+            .copied()
+            .filter(|&format| index_exists(prefix, format))
+            .collect()
+    };
+
+    let io_error = |e: io::Error| format!("Index of `{}`: {}", prefix, e);
+
+    match action.as_str() {
+        "build" => {
+            let format = formats[0];
+            if index_exists(prefix, format) && args.get("force").is_none() {
+                return Err(format!("The {} index of `{}` already exists (use \
+                                    `--force` to rebuild it).", format,
+                                   prefix));
+            }
+            build_index(prefix, format).map_err(io_error)
+        },
+        "delete" => {
+            for format in formats {
+                for filename in delete_index(prefix, format)
+                    .map_err(io_error)?
+                {
+                    eprintln!("Deleted `{}`.", filename);
+                }
+            }
+            Ok(())
+        },
+        "inspect" | "validate" => {
+            if formats.is_empty() {
+                return Err(format!("`{}` is not indexed.", prefix));
+            }
+
+            let mut out = io::stdout();
+            let mut problems = Vec::new();
+
+            for format in formats {
+                let info = inspect_index(prefix, format).map_err(io_error)?;
+
+                if action == "inspect" {
+                    write_index_info(&mut out, prefix, &info)
+                        .map_err(|e| e.to_string())?;
+                }
+
+                problems.extend(info.problems()
+                    .into_iter()
+                    .map(|p| format!("{} index: {}", format, p)));
+            }
+
+            match (action.as_str(), problems.is_empty()) {
+                ("validate", true) => {
+                    println!("ok");
+                    Ok(())
+                },
+                ("validate", false) => Err(problems.join("\n")),
+                _ => Ok(())
+            }
+        },
+        _ => Err(format!("Unknown index action `{}`.", action))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsgeneparselib::Variant;
+
+    fn genotypes(pos: u32, calls: Vec<Option<u8>>) -> Genotypes {
+        let v = Variant::new(
+            format!("rs{}", pos),
+            "1".to_string(),
+            pos,
+            ("A".to_string(), "G".to_string())
+        );
+
+        Genotypes::new(v, calls, "G")
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    // Temporary fileset (`genepa_cli_{name}_{pid}`) of 4 samples, with a v2
+    // index (the v1 index needs bgzip).
+    fn write_fileset(name: &str, genotypes: Vec<Genotypes>) -> String {
+        let prefix = std::env::temp_dir()
+            .join(format!("genepa_cli_{}_{}", name, std::process::id()));
+        let prefix = prefix.to_str().unwrap().to_string();
+
+        let samples: Vec<Sample> = (0..4)
+            .map(|i| Sample::new(format!("f{}", i), format!("s{}", i),
+                                 rsgeneparselib::Sex::Unknown))
+            .collect();
+        PlinkWriter::new(&prefix, &samples).unwrap()
+            .write_all(genotypes)
+            .unwrap();
+        build_index(&prefix, IndexFormat::V2).unwrap();
+
+        prefix
+    }
+
+    // Remove a fileset, its index and the outputs with the extensions.
+    fn remove_files(prefix: &str, extensions: &[&str]) {
+        let outputs = ["bed", "bim", "fam"].iter()
+            .chain(extensions)
+            .map(|ext| format!("{}.{}", prefix, ext));
+
+        for filename in outputs.chain(IndexFormat::V2.filenames(prefix)) {
+            let _ = std::fs::remove_file(filename);
+        }
+    }
+
+    #[test]
+    fn test_args() {
+        let args: Vec<String> = ["--bfile", "data", "--flag", "--n", "3",
+                                 "--bfiles", "a", "b"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let parsed = Args::parse(&args, &["bfile", "flag", "n", "bfiles"])
+            .unwrap();
+        assert_eq!(parsed.required("bfile").unwrap(), "data");
+        assert_eq!(parsed.get("flag"), Some(""));
+        assert_eq!(parsed.parse_or("n", 0).unwrap(), 3);
+        assert_eq!(parsed.parse_or("missing", 7).unwrap(), 7);
+        assert!(parsed.required("flag").is_err());
+        assert_eq!(parsed.get_all("bfiles"), vec!["a", "b"]);
+        assert!(parsed.get_all("flag").is_empty());
+
+        assert!(Args::parse(&args, &["bfile"]).is_err());
+    }
+
+    #[test]
+    fn test_glm() {
+        let samples: Vec<Sample> = (0..3)
+            .map(|i| Sample::new(format!("f{}", i), format!("s{}", i),
+                                 rsgeneparselib::Sex::Unknown))
+            .collect();
+
+        let table = read_sample_table(
+            &b"FID IID bmi\nf2 s2 -9\nf0 s0 20.5\nf9 s9 1\n"[..], &samples,
+            "PHENO"
+        ).unwrap();
+        assert_eq!(table[0].0, "bmi");
+        assert_eq!(table[0].1, vec![Some(20.5), None, None]);
+
+        assert_eq!(recode_binary(&[Some(1.0), Some(2.0), None]).unwrap(),
+                   vec![Some(0.0), Some(1.0), None]);
+        assert!(recode_binary(&[Some(0.0), Some(3.0)]).is_err());
+
+        assert_eq!(format_glm_float(0.00676014), "0.00676014");
+        assert_eq!(format_glm_float(1.25), "1.25");
+        assert_eq!(format_glm_float(1.234567e-12), "1.23457e-12");
+
+        let mut out = Vec::new();
+        let result = AssocResult { n_obs: 2, estimate: None };
+        write_glm(&mut out, vec![(genotypes(5, vec![Some(0), Some(1)]),
+                                  result)], Model::Logistic, None).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().nth(1).unwrap(),
+                   "1\t5\trs5\tA\tG\tG\tADD\t2\tNA\tNA\tNA\tNA");
+
+        let mut out = Vec::new();
+        let flagged = RegionLabels::from_reader("1\t0\t10\tMHC\n".as_bytes());
+        let result = AssocResult { n_obs: 2, estimate: None };
+        write_glm(&mut out, vec![(genotypes(5, vec![Some(0), Some(1)]),
+                                  result)], Model::Linear, Some(&flagged))
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines[0].ends_with("\tT_STAT\tP\tREGION"));
+        assert!(lines[1].ends_with("\tNA\tMHC"));
+    }
+
+    #[test]
+    fn test_freq() {
+        let g = || genotypes(1, vec![Some(2), Some(2), Some(1), None]);
+        let sexes = [Sex::Male, Sex::Female, Sex::Female, Sex::Male];
+
+        let mut out = Vec::new();
+        write_freq(&mut out, vec![g()], &sexes, None).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "CHR\tSNP\tA1\tA2\tMAF\tNCHROBS\n\
+                    1\trs1\tA\tG\t0.166667\t6\n");
+
+        // The male counts as a single allele on the X chromosome.
+        let mut x = g();
+        x.variant.chrom = Chromosome::X;
+        let mut out = Vec::new();
+        write_freq(&mut out, vec![x], &sexes, None).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().nth(1).unwrap(),
+                   "X\trs1\tA\tG\t0.200000\t5");
+
+        let samples: Vec<Sample> = (0..4)
+            .map(|i| Sample::new(format!("f{}", i), format!("s{}", i),
+                                 rsgeneparselib::Sex::Unknown))
+            .collect();
+
+        let groups = read_groups(
+            &b"f0 s0 a\nf2 s2 b\nf3 s3 a\nf9 s9 b\n"[..], &samples
+        ).unwrap();
+        assert_eq!(groups, vec![("a".to_string(), vec![0, 3]),
+                                ("b".to_string(), vec![2])]);
+
+        let mut out = Vec::new();
+        write_freq(&mut out, vec![g()], &sexes, Some(&groups)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "CHR\tSNP\tCLST\tA1\tA2\tMAF\tMAC\tNCHROBS\n\
+                    1\trs1\ta\tA\tG\t0.000000\t0\t2\n\
+                    1\trs1\tb\tA\tG\t0.500000\t1\t2\n");
+    }
+
+    #[test]
+    fn test_sample() {
+        let prefix = write_fileset("sample", (1..=10)
+            .map(|pos| genotypes(pos, vec![Some(0), Some(1), Some(2), None]))
+            .collect());
+        let out = format!("{}_out", prefix);
+
+        sample(&args(&["--bfile", &prefix, "--variants", "4",
+                       "--n-samples", "3", "--seed", "2", "--out", &out]))
+            .unwrap();
+        build_index(&out, IndexFormat::V2).unwrap();
+        let reader = PlinkReader::new(&out).unwrap();
+        assert_eq!(reader.samples().len(), 3);
+        assert_eq!(reader.count(), 4);
+
+        assert!(sample(&args(&["--bfile", &prefix, "--variants", "11",
+                               "--out", &out])).is_err());
+
+        remove_files(&prefix, &[]);
+        remove_files(&out, &[]);
+    }
+
+    #[test]
+    fn test_merge() {
+        // rs1 has its alleles swapped in b, which also fills the missing
+        // call of s3.
+        let a = write_fileset("merge_a", vec![
+            genotypes(1, vec![Some(0), Some(1), Some(2), None])
+        ]);
+        let swapped = Genotypes::new(genotypes(1, vec![]).variant,
+                                     vec![Some(2), Some(1), Some(0), Some(0)],
+                                     "A");
+        let b = write_fileset("merge_b", vec![
+            swapped, genotypes(2, vec![Some(1), Some(1), Some(1), Some(1)])
+        ]);
+        let out = format!("{}_out", a);
+
+        merge(&args(&["--bfiles", &a, &b, "--out", &out])).unwrap();
+
+        let mismatches = std::fs::read_to_string(format!("{}.mismatches", out))
+            .unwrap();
+        assert_eq!(mismatches.lines().collect::<Vec<&str>>(), vec![
+            "fileset\tvariant\tchrom\tpos\tcoded\tother\taction",
+            &format!("{}\trs1\t1\t1\tA\tG\tswapped", b)
+        ]);
+
+        build_index(&out, IndexFormat::V2).unwrap();
+        let merged: Vec<Genotypes> = PlinkReader::new(&out).unwrap()
+            .collect();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].coded_allele(), "G");
+        assert_eq!(merged[0].genotypes,
+                   vec![Some(0), Some(1), Some(2), Some(2)]);
+
+        assert!(merge(&args(&["--bfiles", &a, "--out", &out])).is_err());
+
+        remove_files(&a, &[]);
+        remove_files(&b, &[]);
+        remove_files(&out, &["mismatches"]);
+    }
+
+    #[test]
+    fn test_assoc() {
+        let mut x = genotypes(3, vec![Some(0), Some(1), Some(2), Some(2)]);
+        x.variant.chrom = Chromosome::X;
+        let prefix = write_fileset("assoc", vec![
+            genotypes(1, vec![Some(0), Some(1), Some(2), None]),
+            genotypes(2, vec![Some(0), Some(0), Some(0), Some(0)]),
+            x
+        ]);
+        let pheno = format!("{}.pheno", prefix);
+        std::fs::write(&pheno, "FID IID BMI\nf0 s0 1\nf1 s1 2.5\n\
+                                f2 s2 3\nf3 s3 4\n").unwrap();
+
+        assoc(&args(&["--bfile", &prefix, "--pheno", &pheno,
+                      "--all-chromosomes", "--out", &prefix])).unwrap();
+
+        // rs2 is monomorphic and the samples of unknown sex are missing on
+        // the X chromosome.
+        let filename = format!("{}.BMI.glm.linear", prefix);
+        let results = std::fs::read_to_string(&filename).unwrap();
+        assert_eq!(results.lines().collect::<Vec<&str>>(), vec![
+            "#CHROM\tPOS\tID\tREF\tALT\tA1\tTEST\tOBS_CT\tBETA\tSE\t\
+             T_STAT\tP",
+            "1\t1\trs1\tA\tG\tG\tADD\t3\t1\t0.288675\t3.4641\t0.178912",
+            "1\t2\trs2\tA\tG\tG\tADD\t4\tNA\tNA\tNA\tNA",
+            "X\t3\trs3\tA\tG\tG\tADD\t0\tNA\tNA\tNA\tNA"
+        ]);
+
+        assert!(assoc(&args(&["--bfile", &prefix, "--pheno", &pheno,
+                              "--within-family", "--model", "logistic",
+                              "--out", &prefix])).is_err());
+
+        remove_files(&prefix, &["pheno", "BMI.glm.linear"]);
+    }
+
+    #[test]
+    fn test_grm() {
+        // s0 and s1 have the same genotypes.
+        let prefix = write_fileset("grm", (1..=6)
+            .map(|pos| {
+                let x = (pos % 3) as u8;
+                genotypes(pos, vec![Some(x), Some(x), Some(2 - x),
+                                    Some((pos % 2) as u8)])
+            })
+            .collect());
+
+        grm(&args(&["--bfile", &prefix, "--band-rows", "2",
+                    "--related", "0.5", "--out", &prefix])).unwrap();
+
+        // The lower triangle (with the diagonal) of 4 samples as f32.
+        for extension in ["grm.bin", "grm.N.bin"].iter() {
+            let filename = format!("{}.{}", prefix, extension);
+            assert_eq!(std::fs::metadata(filename).unwrap().len(), 10 * 4);
+        }
+        let ids = std::fs::read_to_string(format!("{}.grm.id", prefix))
+            .unwrap();
+        assert_eq!(ids.lines().collect::<Vec<&str>>(),
+                   vec!["f0\ts0", "f1\ts1", "f2\ts2", "f3\ts3"]);
+
+        let related = std::fs::read_to_string(format!("{}.related", prefix))
+            .unwrap();
+        assert_eq!(related.lines().collect::<Vec<&str>>(), vec![
+            "FID1\tIID1\tFID2\tIID2\tRELATEDNESS",
+            "f0\ts0\tf1\ts1\t0.866667"
+        ]);
+
+        assert!(grm(&args(&["--bfile", &prefix, "--band-rows", "0",
+                            "--out", &prefix])).is_err());
+
+        remove_files(&prefix, &["grm.bin", "grm.N.bin", "grm.id",
+                                "related"]);
+    }
+
+    #[test]
+    fn test_index() {
+        let prefix = write_fileset("index", vec![
+            genotypes(1, vec![Some(0), Some(1), Some(2), None])
+        ]);
+        let index = |action: &str, extra: &[&str]| {
+            let mut arguments = args(&[action, "--bfile", &prefix]);
+            arguments.extend(args(extra));
+            index(&arguments)
+        };
+
+        assert!(index("build", &["--format", "v2"]).unwrap_err()
+                .contains("already exists"));
+        index("build", &["--format", "v2", "--force"]).unwrap();
+        index("validate", &[]).unwrap();
+        index("inspect", &["--format", "v2"]).unwrap();
+
+        index("delete", &[]).unwrap();
+        assert!(!index_exists(&prefix, IndexFormat::V2));
+        assert_eq!(index("validate", &[]).unwrap_err(),
+                   format!("`{}` is not indexed.", prefix));
+
+        assert!(index("rebuild", &[]).is_err());
+        assert!(super::index(&args(&["--bfile", &prefix])).is_err());
+
+        remove_files(&prefix, &[]);
+    }
+
+    #[test]
+    fn test_ld_report() {
+        let g = genotypes(100, vec![Some(0), Some(1), Some(2), Some(1)]);
+        let others = vec![
+            g.clone(),
+            genotypes(200, vec![Some(0), Some(1), Some(2), Some(2)]),
+            genotypes(300, vec![Some(1), Some(1), Some(1), Some(1)]),
+            genotypes(400, vec![Some(2), Some(1), Some(1), Some(1)])
+        ];
+
+        let mut out = Vec::new();
+        write_ld_report(&mut out, g, others, 0.5).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "CHR_A\tBP_A\tSNP_A\tCHR_B\tBP_B\tSNP_B\tR2\n\
+                    1\t100\trs100\t1\t100\trs100\t1.000000\n\
+                    1\t100\trs100\t1\t200\trs200\t0.727273\n\
+                    1\t100\trs100\t1\t400\trs400\t0.666667\n");
+    }
+}
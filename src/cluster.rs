@@ -0,0 +1,284 @@
+/*!
+ * Clustering of the samples.
+ *
+ * Samples can be grouped either by agglomerative hierarchical clustering of
+ * a distance matrix (e.g. the IBS distances, or the distances derived from
+ * the GRM with `grm_distances`) or by k-means on coordinates (e.g. the MDS
+ * coordinates or the principal components). The clusters are a crude
+ * ancestry grouping; samples in very small clusters are likely outliers.
+ */
+
+use std::str::FromStr;
+
+use ndarray::{Array1, Array2};
+
+
+// Maximal number of iterations of k-means.
+pub const MAX_KMEANS_ITERATIONS: usize = 100;
+
+
+// Distance between two clusters in hierarchical clustering.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Linkage {
+    // Closest pair of samples.
+    Single,
+    // Farthest pair of samples.
+    Complete,
+    // Mean distance over the pairs of samples (UPGMA).
+    Average
+}
+
+impl FromStr for Linkage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Linkage, String> {
+        match s {
+            "single" => Ok(Linkage::Single),
+            "complete" => Ok(Linkage::Complete),
+            "average" => Ok(Linkage::Average),
+            _ => Err(format!("Unknown linkage `{}` (expected single, \
+                              complete or average).", s))
+        }
+    }
+}
+
+
+// Cluster of every sample, numbered from 0 in the order of the samples.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clusters {
+    pub assignments: Vec<usize>
+}
+
+impl Clusters {
+    // Renumber the labels in the order of their first sample.
+    fn from_labels(labels: &[usize]) -> Clusters {
+        let mut renumbered: Vec<Option<usize>> = vec![None; labels.len()];
+        let mut n = 0;
+
+        let assignments = labels.iter()
+            .map(|&label| {
+                *renumbered[label].get_or_insert_with(|| {
+                    n += 1;
+                    n - 1
+                })
+            })
+            .collect();
+
+        Clusters { assignments }
+    }
+
+    pub fn n_clusters(&self) -> usize {
+        self.assignments.iter().max().map_or(0, |&max| max + 1)
+    }
+
+    pub fn sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![0; self.n_clusters()];
+        for &c in self.assignments.iter() {
+            sizes[c] += 1;
+        }
+
+        sizes
+    }
+
+    // Indices of the samples in clusters of less than `min_size` samples.
+    pub fn outliers(&self, min_size: usize) -> Vec<usize> {
+        let sizes = self.sizes();
+        self.assignments.iter()
+            .enumerate()
+            .filter(|(_, &c)| sizes[c] < min_size)
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+
+// Euclidean distances between the samples in the space of the standardized
+// genotypes: d(i, j)^2 = G(i, i) + G(j, j) - 2 G(i, j).
+pub fn grm_distances(grm: &Array2<f64>) -> Array2<f64> {
+    let n = grm.rows();
+    Array2::from_shape_fn((n, n), |(i, j)| {
+        (grm[[i, i]] + grm[[j, j]] - 2.0 * grm[[i, j]]).max(0.0).sqrt()
+    })
+}
+
+
+// Agglomerative clustering of a distance matrix, merging the closest
+// clusters until there are `n_clusters` left.
+pub fn hierarchical(distances: &Array2<f64>, n_clusters: usize,
+                    linkage: Linkage) -> Clusters
+{
+    let n = distances.rows();
+    assert_eq!(n, distances.cols(), "Expected a square matrix.");
+    assert!(n_clusters >= 1 && n_clusters <= n,
+            "Expected 1 to {} clusters.", n);
+    if distances.iter().any(|d| !d.is_finite()) {
+        panic!("Can't cluster samples without a distance.");
+    }
+
+    let mut d = distances.clone();
+    let mut labels: Vec<usize> = (0..n).collect();
+    let mut sizes = vec![1_usize; n];
+    let mut active: Vec<usize> = (0..n).collect();
+
+    while active.len() > n_clusters {
+        // Closest pair of active clusters (the first one on ties).
+        let mut closest = (f64::INFINITY, 0, 0);
+        for (a, &i) in active.iter().enumerate() {
+            for &j in active[a + 1..].iter() {
+                if d[[i, j]] < closest.0 {
+                    closest = (d[[i, j]], i, j);
+                }
+            }
+        }
+        let (_, i, j) = closest;
+
+        // Merge j into i (Lance-Williams updates).
+        for &k in active.iter().filter(|&&k| k != i && k != j) {
+            let merged = match linkage {
+                Linkage::Single => d[[i, k]].min(d[[j, k]]),
+                Linkage::Complete => d[[i, k]].max(d[[j, k]]),
+                Linkage::Average => {
+                    (sizes[i] as f64 * d[[i, k]] +
+                     sizes[j] as f64 * d[[j, k]]) /
+                    (sizes[i] + sizes[j]) as f64
+                }
+            };
+            d[[i, k]] = merged;
+            d[[k, i]] = merged;
+        }
+
+        sizes[i] += sizes[j];
+        for label in labels.iter_mut().filter(|label| **label == j) {
+            *label = i;
+        }
+        active.retain(|&k| k != j);
+    }
+
+    Clusters::from_labels(&labels)
+}
+
+
+fn squared_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+
+// K-means clustering of the rows of `points` (Lloyd's algorithm). The
+// initial centroids are chosen deterministically by farthest-first
+// traversal from the first sample.
+pub fn kmeans(points: &Array2<f64>, k: usize) -> Clusters {
+    let n = points.rows();
+    assert!(k >= 1 && k <= n, "Expected 1 to {} clusters.", n);
+
+    let rows: Vec<Vec<f64>> = points.outer_iter()
+        .map(|row| row.to_vec())
+        .collect();
+
+    let mut centroids = vec![rows[0].clone()];
+    let mut closest: Array1<f64> = rows.iter()
+        .map(|row| squared_distance(row, &rows[0]))
+        .collect();
+    while centroids.len() < k {
+        let farthest = (0..n)
+            .max_by(|&a, &b| closest[a].partial_cmp(&closest[b]).unwrap())
+            .unwrap();
+        centroids.push(rows[farthest].clone());
+
+        for (i, row) in rows.iter().enumerate() {
+            closest[i] = closest[i].min(squared_distance(row, &rows[farthest]));
+        }
+    }
+
+    let mut labels = vec![usize::MAX; n];
+    for _ in 0..MAX_KMEANS_ITERATIONS {
+        let new_labels: Vec<usize> = rows.iter()
+            .map(|row| {
+                (0..k)
+                    .min_by(|&a, &b| {
+                        squared_distance(row, &centroids[a])
+                            .partial_cmp(&squared_distance(row, &centroids[b]))
+                            .unwrap()
+                    })
+                    .unwrap()
+            })
+            .collect();
+
+        if new_labels == labels {
+            break;
+        }
+        labels = new_labels;
+
+        // Empty clusters keep their centroid.
+        for (c, centroid) in centroids.iter_mut().enumerate() {
+            let members: Vec<&Vec<f64>> = rows.iter()
+                .zip(labels.iter())
+                .filter(|(_, &label)| label == c)
+                .map(|(row, _)| row)
+                .collect();
+
+            if !members.is_empty() {
+                for (d, x) in centroid.iter_mut().enumerate() {
+                    *x = members.iter().map(|row| row[d]).sum::<f64>() /
+                         members.len() as f64;
+                }
+            }
+        }
+    }
+
+    Clusters::from_labels(&labels)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two groups on a line and an outlier.
+    fn points() -> Array2<f64> {
+        Array2::from_shape_vec(
+            (6, 1), vec![0.0, 10.0, 0.5, 10.5, 1.0, 50.0]
+        ).unwrap()
+    }
+
+    fn distances(points: &Array2<f64>) -> Array2<f64> {
+        let n = points.rows();
+        Array2::from_shape_fn((n, n), |(i, j)| {
+            (points[[i, 0]] - points[[j, 0]]).abs()
+        })
+    }
+
+    #[test]
+    fn test_hierarchical() {
+        let d = distances(&points());
+
+        for linkage in ["single", "complete", "average"].iter() {
+            let clusters = hierarchical(&d, 3, linkage.parse().unwrap());
+            assert_eq!(clusters.assignments, vec![0, 1, 0, 1, 0, 2]);
+            assert_eq!(clusters.sizes(), vec![3, 2, 1]);
+            assert_eq!(clusters.outliers(2), vec![5]);
+        }
+
+        assert_eq!(hierarchical(&d, 1, Linkage::Average).n_clusters(), 1);
+        assert_eq!(hierarchical(&d, 6, Linkage::Single).n_clusters(), 6);
+    }
+
+    #[test]
+    fn test_kmeans() {
+        let clusters = kmeans(&points(), 2);
+        assert_eq!(clusters.assignments, vec![0, 0, 0, 0, 0, 1]);
+
+        let clusters = kmeans(&points(), 3);
+        assert_eq!(clusters.assignments, vec![0, 1, 0, 1, 0, 2]);
+    }
+
+    #[test]
+    fn test_grm_distances() {
+        let grm = Array2::from_shape_vec(
+            (2, 2), vec![1.0, 0.5, 0.5, 1.5]
+        ).unwrap();
+
+        let d = grm_distances(&grm);
+        assert_eq!(d[[0, 0]], 0.0);
+        assert_eq!(d[[0, 1]], 1.5_f64.sqrt());
+    }
+}
@@ -0,0 +1,317 @@
+/*!
+ * Columnar exports of genotype matrices (requires the `columnar` feature).
+ *
+ * The genotypes are written as Apache Arrow record batches, either in an
+ * Arrow IPC file or in a Parquet file, for analysis with dataframe
+ * libraries. Every row is a variant with its metadata (name, chromosome,
+ * position, coded and other alleles and ploidy) followed by one column per
+ * sample (named as in VCF files, see `vcf_sample_id`) with the number of
+ * copies of the coded allele (null if missing).
+ *
+ * e.g. to export a plink fileset to Parquet:
+ *     let reader = PlinkReader::new("data")?;
+ *     let writer = ColumnarWriter::create("data.parquet", reader.samples(),
+ *                                         ColumnarFormat::Parquet)?;
+ *     convert(reader, writer)?;
+ */
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, StringArray, UInt32Array, UInt8Array};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+
+use crate::convert::GenotypeSink;
+use crate::core::{Genotypes, Sample};
+use crate::vcf::vcf_sample_id;
+
+
+// Number of variants per record batch (and Parquet row group).
+pub const DEFAULT_BATCH_SIZE: usize = 1024;
+
+// Columns of the variant metadata, before the sample columns.
+const N_VARIANT_COLUMNS: usize = 6;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnarFormat {
+    ArrowIpc,
+    Parquet
+}
+
+impl FromStr for ColumnarFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ColumnarFormat, String> {
+        match s {
+            "arrow" => Ok(ColumnarFormat::ArrowIpc),
+            "parquet" => Ok(ColumnarFormat::Parquet),
+            _ => Err(format!("Unknown format `{}` (expected arrow or \
+                              parquet).", s))
+        }
+    }
+}
+
+
+fn to_io_error<E: std::error::Error + Send + Sync + 'static>(e: E)
+    -> io::Error
+{
+    io::Error::other(e)
+}
+
+
+// Schema of the genotype matrix of the samples.
+pub fn genotype_schema(samples: &[Sample]) -> SchemaRef {
+    let mut fields = vec![
+        Field::new("name", DataType::Utf8, false),
+        Field::new("chrom", DataType::Utf8, false),
+        Field::new("position", DataType::UInt32, false),
+        Field::new("coded_allele", DataType::Utf8, false),
+        Field::new("other_allele", DataType::Utf8, false),
+        Field::new("ploidy", DataType::UInt8, false)
+    ];
+
+    for s in samples {
+        fields.push(Field::new(vcf_sample_id(s), DataType::UInt8, true));
+    }
+
+    Arc::new(Schema::new(fields))
+}
+
+
+// Record batch of the genotypes of some variants.
+pub fn record_batch(schema: &SchemaRef, genotypes: &[Genotypes])
+    -> io::Result<RecordBatch>
+{
+    let n_samples = schema.fields().len() - N_VARIANT_COLUMNS;
+    if let Some(g) = genotypes.iter().find(|g| g.genotypes.len() != n_samples)
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+            "Expected {} samples but {} has {}.", n_samples, g.variant.name,
+            g.genotypes.len()
+        )));
+    }
+
+    let strings = |f: &dyn Fn(&Genotypes) -> &str| -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(genotypes.iter().map(f)))
+    };
+
+    let mut columns: Vec<ArrayRef> = vec![
+        strings(&|g| &g.variant.name),
+        Arc::new(StringArray::from_iter_values(
+            genotypes.iter().map(|g| g.variant.chrom.to_string())
+        )),
+        Arc::new(genotypes.iter()
+            .map(|g| g.variant.position)
+            .collect::<UInt32Array>()),
+        strings(&|g| g.coded_allele()),
+        strings(&|g| g.other_allele()),
+        Arc::new(genotypes.iter()
+            .map(|g| g.ploidy())
+            .collect::<UInt8Array>())
+    ];
+
+    for i in 0..n_samples {
+        columns.push(Arc::new(genotypes.iter()
+            .map(|g| g.genotypes[i])
+            .collect::<UInt8Array>()));
+    }
+
+    RecordBatch::try_new(schema.clone(), columns).map_err(to_io_error)
+}
+
+
+enum BatchWriter<W: Write + Send> {
+    ArrowIpc(FileWriter<W>),
+    Parquet(ArrowWriter<W>)
+}
+
+
+// Writer of genotype matrices. The genotypes are buffered and written in
+// record batches of `batch_size` variants.
+pub struct ColumnarWriter<W: Write + Send> {
+    writer: BatchWriter<W>,
+    schema: SchemaRef,
+    batch: Vec<Genotypes>,
+    batch_size: usize
+}
+
+impl ColumnarWriter<io::BufWriter<File>> {
+    pub fn create(filename: &str, samples: &[Sample], format: ColumnarFormat)
+        -> io::Result<ColumnarWriter<io::BufWriter<File>>>
+    {
+        let f = io::BufWriter::new(File::create(filename)?);
+        ColumnarWriter::new(f, samples, format)
+    }
+}
+
+impl<W: Write + Send> ColumnarWriter<W> {
+    pub fn new(out: W, samples: &[Sample], format: ColumnarFormat)
+        -> io::Result<ColumnarWriter<W>>
+    {
+        let schema = genotype_schema(samples);
+
+        let writer = match format {
+            ColumnarFormat::ArrowIpc => BatchWriter::ArrowIpc(
+                FileWriter::try_new(out, &schema).map_err(to_io_error)?
+            ),
+            ColumnarFormat::Parquet => BatchWriter::Parquet(
+                ArrowWriter::try_new(out, schema.clone(), None)
+                    .map_err(to_io_error)?
+            )
+        };
+
+        Ok(ColumnarWriter {
+            writer,
+            schema,
+            batch: Vec::new(),
+            batch_size: DEFAULT_BATCH_SIZE
+        })
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> ColumnarWriter<W> {
+        assert!(batch_size > 0, "The batch size must be positive.");
+        self.batch_size = batch_size;
+        self
+    }
+
+    pub fn write(&mut self, g: &Genotypes) -> io::Result<()> {
+        self.batch.push(g.clone());
+        if self.batch.len() >= self.batch_size {
+            self._write_batch()?;
+        }
+
+        Ok(())
+    }
+
+    fn _write_batch(&mut self) -> io::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        let batch = record_batch(&self.schema, &self.batch)?;
+        self.batch.clear();
+
+        match &mut self.writer {
+            BatchWriter::ArrowIpc(w) => w.write(&batch).map_err(to_io_error),
+            BatchWriter::Parquet(w) => {
+                // Every batch is its own row group.
+                w.write(&batch).map_err(to_io_error)?;
+                w.flush().map_err(to_io_error)
+            }
+        }
+    }
+
+    // Write the buffered genotypes and the footer of the file.
+    pub fn finish(mut self) -> io::Result<W> {
+        self._write_batch()?;
+
+        match self.writer {
+            BatchWriter::ArrowIpc(mut w) => {
+                w.finish().map_err(to_io_error)?;
+                w.into_inner().map_err(to_io_error)
+            },
+            BatchWriter::Parquet(w) => w.into_inner().map_err(to_io_error)
+        }
+    }
+}
+
+impl<W: Write + Send> GenotypeSink for ColumnarWriter<W> {
+    fn write(&mut self, g: &Genotypes) -> io::Result<()> {
+        ColumnarWriter::write(self, g)
+    }
+
+    fn finish(self) -> io::Result<()> {
+        ColumnarWriter::finish(self).map(|_| ())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Array;
+    use arrow::ipc::reader::FileReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use crate::core::{Sex, Variant};
+
+    fn data() -> (Vec<Sample>, Vec<Genotypes>) {
+        let samples = vec![
+            Sample::new("f1".to_string(), "s1".to_string(), Sex::Male),
+            Sample::new("s2".to_string(), "s2".to_string(), Sex::Female)
+        ];
+
+        let genotypes = (0..5)
+            .map(|i| {
+                let v = Variant::new(format!("rs{}", i), "1".to_string(),
+                                     100 * (i + 1),
+                                     ("A".to_string(), "G".to_string()));
+                Genotypes::new(v, vec![Some((i % 3) as u8), None], "G")
+            })
+            .collect();
+
+        (samples, genotypes)
+    }
+
+    fn check(batches: &[RecordBatch]) {
+        // Batches of 2 variants.
+        assert_eq!(batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>(),
+                   vec![2, 2, 1]);
+
+        let batch = &batches[1];
+        let schema = batch.schema();
+        assert_eq!(schema.field(6).name(), "f1_s1");
+        assert_eq!(schema.field(7).name(), "s2");
+
+        let names = batch.column(0).as_any().downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(names.value(1), "rs3");
+
+        let calls = batch.column(6).as_any().downcast_ref::<UInt8Array>()
+            .unwrap();
+        assert_eq!((calls.value(0), calls.value(1)), (2, 0));
+        assert_eq!(batch.column(7).null_count(), 2);
+    }
+
+    #[test]
+    fn test_columnar_writer() {
+        let (samples, genotypes) = data();
+
+        let mut writer = ColumnarWriter::new(Vec::new(), &samples,
+                                             ColumnarFormat::ArrowIpc)
+            .unwrap()
+            .with_batch_size(2);
+        for g in genotypes.iter() {
+            writer.write(g).unwrap();
+        }
+        let ipc = writer.finish().unwrap();
+
+        let reader = FileReader::try_new(io::Cursor::new(ipc), None).unwrap();
+        check(&reader.collect::<Result<Vec<_>, _>>().unwrap());
+
+        let filename = std::env::temp_dir()
+            .join(format!("genepa_columnar_{}.parquet", std::process::id()));
+        let filename = filename.to_str().unwrap();
+
+        let mut writer = ColumnarWriter::create(filename, &samples,
+                                                "parquet".parse().unwrap())
+            .unwrap()
+            .with_batch_size(2);
+        for g in genotypes.iter() {
+            writer.write(g).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(
+            File::open(filename).unwrap()
+        ).unwrap().with_batch_size(2).build().unwrap();
+        check(&reader.collect::<Result<Vec<_>, _>>().unwrap());
+
+        std::fs::remove_file(filename).unwrap();
+    }
+}
@@ -0,0 +1,368 @@
+/*!
+ * Global configuration of the library.
+ *
+ * The parallel code paths (with the `parallel` feature) use `threads()`
+ * threads: the number given to `set_threads`, or else the number of CPUs
+ * available to the process. The CPUs available take the CPU affinity (e.g.
+ * the cores of an HPC job allocation) and the CPU quota of the cgroup (e.g.
+ * the limits of a container) into account, so that the library doesn't
+ * start more threads than it can use. `with_threads` overrides the number
+ * of threads for a single call.
+ *
+ * On multi-socket servers, the `numa` feature adds an opt-in mode
+ * (`set_numa`) where the variants read together are split in one range of
+ * consecutive variants per NUMA node, decoded by threads pinned to the CPUs
+ * of that node (Linux only). The decoded genotypes are then allocated in the
+ * memory of the node that decoded them, instead of being spread over all
+ * the nodes by threads moving between sockets.
+ */
+
+use std::cell::Cell;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "parallel")]
+use std::collections::HashMap;
+#[cfg(feature = "parallel")]
+use std::sync::{Arc, Mutex, OnceLock};
+#[cfg(feature = "numa")]
+use std::sync::atomic::AtomicBool;
+#[cfg(feature = "numa")]
+use std::thread;
+
+
+// 0 if the number of threads isn't set.
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "numa")]
+static NUMA: AtomicBool = AtomicBool::new(false);
+
+// Thread pools kept for the next calls.
+#[cfg(feature = "parallel")]
+type Pools<K> = OnceLock<Mutex<HashMap<K, Arc<rayon::ThreadPool>>>>;
+
+thread_local! {
+    static OVERRIDE: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+
+// Number of threads of the parallel code paths (0 to use the available
+// CPUs again).
+pub fn set_threads(n: usize) {
+    THREADS.store(n, Ordering::Relaxed);
+}
+
+
+pub fn threads() -> usize {
+    if let Some(n) = OVERRIDE.with(|o| o.get()) {
+        return n;
+    }
+
+    match THREADS.load(Ordering::Relaxed) {
+        0 => available_threads(),
+        n => n
+    }
+}
+
+
+// Run `f` with `n` threads for the parallel code paths of the current
+// thread (the global setting is restored afterwards).
+pub fn with_threads<T, F: FnOnce() -> T>(n: usize, f: F) -> T {
+    struct Restore(Option<usize>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            OVERRIDE.with(|o| o.set(self.0));
+        }
+    }
+
+    assert!(n > 0, "Expected at least 1 thread.");
+    let _restore = Restore(OVERRIDE.with(|o| o.replace(Some(n))));
+    f()
+}
+
+
+// CPUs available to the process: the CPUs it can run on, limited by the
+// CPU quota of its cgroup.
+pub fn available_threads() -> usize {
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    match cgroup_cpu_limit() {
+        Some(limit) => cpus.min(limit),
+        None => cpus
+    }
+}
+
+
+// CPU quota of the cgroup of the process rounded up to whole CPUs (None if
+// there is no quota). Both the cgroup v2 (`cpu.max`) and v1
+// (`cpu.cfs_quota_us`) interfaces are read, as mounted in containers.
+pub fn cgroup_cpu_limit() -> Option<usize> {
+    if let Ok(content) = fs::read_to_string("/sys/fs/cgroup/cpu.max") {
+        return parse_cpu_max(&content);
+    }
+
+    let read = |name: &str| -> Option<i64> {
+        fs::read_to_string(format!("/sys/fs/cgroup/cpu/{}", name)).ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+
+    cpu_limit(read("cpu.cfs_quota_us")?, read("cpu.cfs_period_us")?)
+}
+
+
+// `{quota} {period}` in microseconds, where the quota is `max` if there is
+// no limit.
+fn parse_cpu_max(content: &str) -> Option<usize> {
+    let mut fields = content.split_whitespace();
+    let quota = fields.next()?;
+    if quota == "max" {
+        return None;
+    }
+
+    let period = fields.next().unwrap_or("100000");
+    cpu_limit(quota.parse().ok()?, period.parse().ok()?)
+}
+
+
+// A quota of -1 means that there is no limit (cgroup v1).
+fn cpu_limit(quota: i64, period: i64) -> Option<usize> {
+    if quota <= 0 || period <= 0 {
+        return None;
+    }
+
+    Some(((quota + period - 1) / period) as usize)
+}
+
+
+// Run `f` in a thread pool with `threads()` threads. The pools are kept for
+// the next calls.
+#[cfg(feature = "parallel")]
+pub(crate) fn install<T, F>(f: F) -> T
+    where T: Send,
+          F: FnOnce() -> T + Send
+{
+    static POOLS: Pools<usize> = OnceLock::new();
+
+    let n = threads();
+    let pool = POOLS.get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap()
+        .entry(n)
+        .or_insert_with(|| {
+            Arc::new(rayon::ThreadPoolBuilder::new()
+                .num_threads(n)
+                .build()
+                .expect("Could not start the thread pool."))
+        })
+        .clone();
+
+    pool.install(f)
+}
+
+
+// Partition the work by NUMA node (see the module documentation).
+#[cfg(feature = "numa")]
+pub fn set_numa(enabled: bool) {
+    NUMA.store(enabled, Ordering::Relaxed);
+}
+
+
+#[cfg(feature = "numa")]
+pub fn numa() -> bool {
+    NUMA.load(Ordering::Relaxed)
+}
+
+
+// CPUs of every NUMA node with CPUs (a single node with all the CPUs if the
+// topology isn't available).
+#[cfg(feature = "numa")]
+pub fn numa_nodes() -> Vec<Vec<usize>> {
+    let mut nodes: Vec<(usize, Vec<usize>)> =
+        fs::read_dir("/sys/devices/system/node")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let node: usize = name.strip_prefix("node")?.parse().ok()?;
+                let cpus = fs::read_to_string(entry.path().join("cpulist"))
+                    .ok()?;
+                Some((node, parse_cpu_list(&cpus)?))
+            })
+            .filter(|(_, cpus)| !cpus.is_empty())
+            .collect();
+
+    nodes.sort();
+    match nodes.len() {
+        0 => vec![(0..available_threads()).collect()],
+        _ => nodes.into_iter().map(|(_, cpus)| cpus).collect()
+    }
+}
+
+
+// Linux CPU lists such as `0-3,8-11`.
+#[cfg(feature = "numa")]
+fn parse_cpu_list(s: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in s.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => {
+                cpus.extend(start.parse::<usize>().ok()?..=end.parse().ok()?)
+            },
+            None => cpus.push(range.parse().ok()?)
+        }
+    }
+
+    Some(cpus)
+}
+
+
+// Map `f` over the items in parallel. With the NUMA mode, the items are split
+// in one range of consecutive items per node, each mapped by a pool of
+// threads pinned to the CPUs of the node (the `threads()` threads are shared
+// between the nodes according to their number of CPUs).
+#[cfg(feature = "parallel")]
+pub(crate) fn par_map<T, R, F>(items: &[T], f: F) -> Vec<R>
+    where T: Sync,
+          R: Send,
+          F: Fn(&T) -> R + Sync
+{
+    use rayon::prelude::*;
+
+    #[cfg(feature = "numa")]
+    {
+        if numa() {
+            return numa_map(items, f);
+        }
+    }
+
+    install(|| items.par_iter().map(&f).collect())
+}
+
+
+#[cfg(feature = "numa")]
+fn numa_map<T, R, F>(items: &[T], f: F) -> Vec<R>
+    where T: Sync,
+          R: Send,
+          F: Fn(&T) -> R + Sync
+{
+    use rayon::prelude::*;
+
+    // By node and number of threads.
+    static POOLS: Pools<(usize, usize)> = OnceLock::new();
+
+    let nodes = numa_nodes();
+    let n_cpus: usize = nodes.iter().map(Vec::len).sum();
+    let n_threads = threads();
+
+    let pools: Vec<Arc<rayon::ThreadPool>> = nodes.iter()
+        .enumerate()
+        .map(|(node, cpus)| {
+            let n = (n_threads * cpus.len() / n_cpus).max(1);
+            POOLS.get_or_init(|| Mutex::new(HashMap::new()))
+                .lock()
+                .unwrap()
+                .entry((node, n))
+                .or_insert_with(|| {
+                    let cpus = cpus.clone();
+                    Arc::new(rayon::ThreadPoolBuilder::new()
+                        .num_threads(n)
+                        .start_handler(move |_| pin_thread(&cpus))
+                        .build()
+                        .expect("Could not start the thread pool."))
+                })
+                .clone()
+        })
+        .collect();
+
+    // The ranges of the nodes are proportional to their number of threads.
+    let weights: Vec<usize> = pools.iter()
+        .map(|pool| pool.current_num_threads())
+        .collect();
+    let total: usize = weights.iter().sum();
+    let mut start = 0;
+    let mut seen = 0;
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = pools.iter()
+            .zip(weights.iter())
+            .map(|(pool, weight)| {
+                seen += weight;
+                let end = items.len() * seen / total;
+                let range = &items[start..end];
+                start = end;
+
+                let f = &f;
+                scope.spawn(move || {
+                    pool.install(|| {
+                        range.par_iter().map(f).collect::<Vec<R>>()
+                    })
+                })
+            })
+            .collect();
+
+        handles.into_iter()
+            .flat_map(|handle| handle.join().expect("A NUMA worker failed."))
+            .collect()
+    })
+}
+
+
+#[cfg(all(feature = "numa", target_os = "linux"))]
+fn pin_thread(cpus: &[usize]) {
+    // Pinning is only an optimization: the thread stays unpinned if the
+    // CPUs aren't available to the process.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(),
+                                &set);
+    }
+}
+
+
+#[cfg(all(feature = "numa", not(target_os = "linux")))]
+fn pin_thread(_cpus: &[usize]) {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_limits() {
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+        assert_eq!(parse_cpu_max("200000 100000\n"), Some(2));
+        assert_eq!(parse_cpu_max("150000 100000\n"), Some(2));
+        assert_eq!(parse_cpu_max("50000 100000\n"), Some(1));
+        assert_eq!(cpu_limit(-1, 100000), None);
+        assert!(available_threads() >= 1);
+    }
+
+    #[cfg(feature = "numa")]
+    #[test]
+    fn test_numa() {
+        assert_eq!(parse_cpu_list("0-3,8,10-11\n"),
+                   Some(vec![0, 1, 2, 3, 8, 10, 11]));
+        assert_eq!(parse_cpu_list(""), Some(vec![]));
+        assert_eq!(parse_cpu_list("0-x"), None);
+        assert!(!numa_nodes().is_empty());
+
+        // Called directly rather than through `set_numa`, which would
+        // change the mode of the other tests running concurrently.
+        let items: Vec<usize> = (0..1000).collect();
+        let doubled = numa_map(&items, |x| 2 * x);
+        assert_eq!(doubled, items.iter().map(|x| 2 * x).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_with_threads() {
+        let default = threads();
+        assert_eq!(with_threads(3, || with_threads(2, threads) + threads()),
+                   5);
+        assert_eq!(threads(), default);
+    }
+}
@@ -0,0 +1,240 @@
+/*!
+ * Conversion between genotype formats.
+ *
+ * The variants of any reader (see GenotypeSource) are streamed to any writer
+ * implementing GenotypeSink, optionally keeping only some variants and
+ * samples along the way. Writers are created by the caller with the kept
+ * samples (see `Filters::kept_samples`).
+ *
+ * e.g. to convert a plink fileset to a VCF:
+ *     let reader = PlinkReader::new("data")?;
+ *     let writer = VcfWriter::create("data.vcf", reader.samples(),
+ *                                    &["1", "2"], false)?;
+ *     convert(reader, writer)?;
+ */
+
+use std::collections::HashSet;
+use std::io::{self, Seek, Write};
+
+use crate::bgen::BgenWriter;
+use crate::core::{Dosages, Genotypes, Sample};
+use crate::pgen::PgenWriter;
+use crate::plink::PlinkWriter;
+use crate::source::GenotypeSource;
+use crate::vcf::VcfWriter;
+use crate::zarr::ZarrWriter;
+
+
+// A writer that genotypes can be streamed to.
+pub trait GenotypeSink {
+    fn write(&mut self, g: &Genotypes) -> io::Result<()>;
+
+    // Flush everything that was written.
+    fn finish(self) -> io::Result<()> where Self: Sized;
+}
+
+
+impl GenotypeSink for PlinkWriter {
+    fn write(&mut self, g: &Genotypes) -> io::Result<()> {
+        PlinkWriter::write(self, g)
+    }
+
+    fn finish(self) -> io::Result<()> {
+        PlinkWriter::finish(self).map(|_| ())
+    }
+}
+
+
+impl GenotypeSink for PgenWriter {
+    fn write(&mut self, g: &Genotypes) -> io::Result<()> {
+        PgenWriter::write(self, g)
+    }
+
+    fn finish(self) -> io::Result<()> {
+        PgenWriter::finish(self).map(|_| ())
+    }
+}
+
+
+impl GenotypeSink for ZarrWriter {
+    fn write(&mut self, g: &Genotypes) -> io::Result<()> {
+        ZarrWriter::write(self, g)
+    }
+
+    fn finish(self) -> io::Result<()> {
+        ZarrWriter::finish(self).map(|_| ())
+    }
+}
+
+
+// Only the GT field is written, so the writer must not expect dosages.
+impl<W: Write> GenotypeSink for VcfWriter<W> {
+    fn write(&mut self, g: &Genotypes) -> io::Result<()> {
+        VcfWriter::write(self, g, None)
+    }
+
+    fn finish(self) -> io::Result<()> {
+        VcfWriter::finish(self).map(|_| ())
+    }
+}
+
+
+// The hard calls are written as dosages (haploid calls as homozygous).
+impl<W: Write + Seek> GenotypeSink for BgenWriter<W> {
+    fn write(&mut self, g: &Genotypes) -> io::Result<()> {
+        let scale = if g.is_haploid() { 2.0 } else { 1.0 };
+        let dosages = g.genotypes.iter()
+            .map(|call| call.map(|x| f64::from(x) * scale))
+            .collect();
+
+        BgenWriter::write(self, &Dosages::new(g.variant.clone(), dosages,
+                                              g.coded_allele()))
+    }
+
+    fn finish(self) -> io::Result<()> {
+        BgenWriter::finish(self).map(|_| ())
+    }
+}
+
+
+// Only the variants for which the predicate is true are written.
+pub type VariantFilter = Box<dyn Fn(&Genotypes) -> bool>;
+
+
+#[derive(Default)]
+pub struct Filters {
+    pub variants: Option<VariantFilter>,
+    // Indices of the samples to keep, in the order of the output.
+    pub samples: Option<Vec<usize>>
+}
+
+impl Filters {
+    // Samples of the output given the samples of the input.
+    pub fn kept_samples(&self, samples: &[Sample]) -> Vec<Sample> {
+        match &self.samples {
+            Some(indices) => indices.iter()
+                .map(|&i| samples[i].clone())
+                .collect(),
+            None => samples.to_vec()
+        }
+    }
+
+    // Keep the samples with the given (FID, IID), in the order of the
+    // input.
+    pub fn keep_samples(mut self, samples: &[Sample],
+                        ids: &HashSet<(String, String)>) -> Filters
+    {
+        self.samples = Some(samples.iter()
+            .enumerate()
+            .filter(|(_, s)| ids.contains(&(s.fid.clone(), s.iid.clone())))
+            .map(|(i, _)| i)
+            .collect());
+        self
+    }
+
+    // Keep the variants with the given names.
+    pub fn keep_variants(mut self, names: HashSet<String>) -> Filters {
+        self.variants = Some(Box::new(move |g: &Genotypes| {
+            names.contains(&g.variant.name)
+        }));
+        self
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConvertReport {
+    pub n_read: u64,
+    pub n_written: u64
+}
+
+
+// Write every variant of the input to the output.
+pub fn convert<S, K>(input: S, output: K) -> io::Result<ConvertReport>
+    where S: GenotypeSource,
+          K: GenotypeSink
+{
+    convert_filtered(input, output, &Filters::default())
+}
+
+
+pub fn convert_filtered<S, K>(input: S, mut output: K, filters: &Filters)
+    -> io::Result<ConvertReport>
+    where S: GenotypeSource,
+          K: GenotypeSink
+{
+    let mut report = ConvertReport::default();
+
+    for g in input {
+        report.n_read += 1;
+
+        if let Some(keep) = &filters.variants {
+            if !keep(&g) {
+                continue;
+            }
+        }
+
+        match &filters.samples {
+            Some(indices) => output.write(&g.subset(indices))?,
+            None => output.write(&g)?
+        }
+        report.n_written += 1;
+    }
+
+    output.finish()?;
+    Ok(report)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ped::PedReader;
+
+    #[test]
+    fn test_convert() {
+        let map = "1 rs1 0 100\n1 rs2 0 200\n1 rs3 0 300\n";
+        let ped = "f1 s1 0 0 1 -9 A A C C A A\n\
+                   f2 s2 0 0 2 -9 A G C T A G\n\
+                   f3 s3 0 0 0 -9 G G C C A A\n";
+        let reader = || PedReader::from_readers(ped.as_bytes(),
+                                                map.as_bytes());
+
+        let filters = Filters {
+            variants: Some(Box::new(|g: &Genotypes| g.variant.name != "rs2")),
+            samples: Some(vec![2, 0])
+        };
+
+        let samples = filters.kept_samples(reader().samples());
+        assert_eq!(samples[0].iid, "s3");
+
+        let mut out = Vec::new();
+        let writer = VcfWriter::new(&mut out, &samples, &["1"], false)
+            .unwrap();
+        let report = convert_filtered(reader(), writer, &filters).unwrap();
+        assert_eq!(report, ConvertReport { n_read: 3, n_written: 2 });
+
+        let vcf = String::from_utf8(out).unwrap();
+        let records: Vec<&str> = vcf.lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect();
+        assert_eq!(records, vec![
+            "1\t100\trs1\tA\tG\t.\t.\t.\tGT\t1/1\t0/0",
+            "1\t300\trs3\tA\tG\t.\t.\t.\tGT\t0/0\t0/0"
+        ]);
+
+        let ids = [("f3", "s3"), ("f1", "s1"), ("f9", "s9")].iter()
+            .map(|(fid, iid)| (fid.to_string(), iid.to_string()))
+            .collect();
+        let filters = Filters::default()
+            .keep_samples(reader().samples(), &ids)
+            .keep_variants(vec!["rs2".to_string()].into_iter().collect());
+        assert_eq!(filters.samples, Some(vec![0, 2]));
+
+        let kept: Vec<Genotypes> = reader()
+            .filter(|g| (filters.variants.as_ref().unwrap())(g))
+            .collect();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].variant.name, "rs2");
+    }
+}
@@ -5,8 +5,16 @@ use std::hash::{Hash, Hasher};
 use std::iter::FromIterator;
 use std::collections::HashSet;
 use std::collections::hash_map::DefaultHasher;
-use std::io::{BufReader, BufRead};
-use std::fs::File;
+use std::io::BufRead;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+
+use crate::checksum::to_hex;
+use crate::error::GenepaError;
+use crate::utils::try_open_text_file;
+use crate::units::{AlleleCount, Dosage, Frequency, FrequencyEstimator};
 
 
 #[derive(Debug)]
@@ -22,21 +30,34 @@ pub struct VarFieldIdx {
 
 
 pub struct DelimitedVariantsReader {
-    iter: Box<std::io::Lines<std::io::BufReader<std::fs::File>>>,
+    iter: Box<dyn Iterator<Item = std::io::Result<String>>>,
     delim: char,
-    idx: VarFieldIdx
+    idx: VarFieldIdx,
+    // Used in the errors.
+    path: String,
+    line_number: usize
 }
 
 
 impl DelimitedVariantsReader {
+    // The file can be gzipped.
     pub fn new(filename: &str, delim: char, has_header: bool, idx: VarFieldIdx)
-        -> DelimitedVariantsReader
+        -> Result<DelimitedVariantsReader, GenepaError>
     {
-        // Read the file.
-        let f = File::open(filename)
-            .expect(&format!("Couldn't open file: {:?}", filename));
+        let mut reader = DelimitedVariantsReader::from_reader(
+            try_open_text_file(filename)?, delim, has_header, idx
+        );
+        reader.path = filename.to_string();
+
+        Ok(reader)
+    }
 
-        let mut iter = BufReader::new(f).lines();
+    pub fn from_reader<R: BufRead + 'static>(reader: R, delim: char,
+                                             has_header: bool,
+                                             idx: VarFieldIdx)
+        -> DelimitedVariantsReader
+    {
+        let mut iter = reader.lines();
 
         // Skip header if needed.
         if has_header {
@@ -45,41 +66,66 @@ impl DelimitedVariantsReader {
 
         DelimitedVariantsReader {
             iter: Box::new(iter),
-            delim: delim,
-            idx: idx
+            delim,
+            idx,
+            path: "<reader>".to_string(),
+            line_number: if has_header { 1 } else { 0 }
         }
     }
 }
 
 
+// Parse the variant from the fields of a line of a delimited file.
+pub(crate) fn parse_delimited_variant(fields: &[&str], idx: &VarFieldIdx)
+    -> Result<OrderedAllelesVariant, String>
+{
+    let field = |i: usize| -> Result<&str, String> {
+        fields.get(i)
+            .copied()
+            .ok_or_else(|| format!("expected at least {} fields, got {}",
+                                   i + 1, fields.len()))
+    };
+
+    // Upper alleles.
+    let a1 = field(idx.a1)?.to_uppercase();
+    let a2 = field(idx.a2)?.to_uppercase();
+
+    let position = field(idx.pos)?;
+    let position = position.parse()
+        .map_err(|_| format!("invalid position `{}`", position))?;
+
+    // Parse the variant.
+    let v = Variant::new(
+        field(idx.name)?.to_string(),
+        field(idx.chrom)?.to_string(),
+        position,
+        (a1.clone(), a2)
+    );
+
+    let a1_idx = if &v.alleles.0 == &a1 { 0 } else { 1 };
+
+    Ok(OrderedAllelesVariant { variant: v, a1_idx })
+}
+
+
 impl Iterator for DelimitedVariantsReader {
-    type Item = OrderedAllelesVariant;
+    type Item = Result<OrderedAllelesVariant, GenepaError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(s) = self.iter.next() {
-            // Split the string.
-            let line = s.unwrap();
-            let fields = Vec::from_iter(line.split(self.delim));
+        let line = self.iter.next()?;
+        self.line_number += 1;
 
-            // Upper alleles.
-            let a1 = fields[self.idx.a1].to_string().to_uppercase();
-            let a2 = fields[self.idx.a2].to_string().to_uppercase();
-
-            // Parse the variant.
-            let v = Variant::new(
-                fields[self.idx.name].to_string(),
-                fields[self.idx.chrom].to_string(),
-                fields[self.idx.pos].parse().unwrap(),
-                (a1.clone(), a2)
-            );
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Some(Err(GenepaError::io(&self.path, e)))
+        };
 
-            let a1_idx = if &v.alleles.0 == &a1 { 0 } else { 1 };
+        // Split the string.
+        let fields = Vec::from_iter(line.split(self.delim));
 
-            return Some(
-                OrderedAllelesVariant { variant: v, a1_idx: a1_idx }
-            );
-        }
-        None
+        Some(parse_delimited_variant(&fields, &self.idx).map_err(|message| {
+            GenepaError::parse(&self.path, self.line_number, &message)
+        }))
     }
 }
 
@@ -98,20 +144,119 @@ pub struct OrderedAllelesVariant {
 }
 
 
-#[derive(PartialEq, Clone, Hash, Debug)]
-pub struct Chromosome {
-    pub name: String
+// Chromosome of a variant. The names are normalized when they are parsed
+// (see `normalize_chromosome`), so that e.g. `chr1` and `1` or `23` and
+// `chrX` are the same chromosome. The chromosomes are ordered as the
+// autosomes (by number), X, Y, XY (the pseudo-autosomal regions), MT and the
+// other contigs (by name).
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Chromosome {
+    Autosome(u8),
+    X,
+    Y,
+    XY,
+    MT,
+    // e.g. unplaced contigs or `0` (unknown chromosome in plink), without
+    // the `chr` prefix.
+    Other(String)
+}
+
+
+impl Chromosome {
+    // Names that are not valid chromosome names are kept as other contigs.
+    pub fn new(name: &str) -> Chromosome {
+        let trimmed = name.trim();
+        let stripped = match trimmed.get(..3) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("chr") &&
+                            trimmed.len() > 3 => &trimmed[3..],
+            _ => trimmed
+        };
+
+        let normalized = match normalize_chromosome(trimmed) {
+            Ok(normalized) => normalized,
+            Err(_) => return Chromosome::Other(stripped.to_string())
+        };
+
+        match normalized.as_str() {
+            "X" => Chromosome::X,
+            "Y" => Chromosome::Y,
+            "XY" => Chromosome::XY,
+            "MT" => Chromosome::MT,
+            other => match other.parse() {
+                Ok(n) if n > 0 => Chromosome::Autosome(n),
+                _ => Chromosome::Other(normalized)
+            }
+        }
+    }
+
+    pub fn is_autosome(&self) -> bool {
+        matches!(self, Chromosome::Autosome(_))
+    }
+
+    // Chromosomes where every sample carries a single copy (Y in males and
+    // the mitochondrial genome).
+    pub fn is_haploid(&self) -> bool {
+        matches!(self, Chromosome::Y | Chromosome::MT)
+    }
+}
+
+
+// Unlike `Chromosome::new`, invalid names are errors.
+impl FromStr for Chromosome {
+    type Err = VariantError;
+
+    fn from_str(name: &str) -> Result<Chromosome, VariantError> {
+        normalize_chromosome(name).map(|name| Chromosome::new(&name))
+    }
 }
 
 
 impl fmt::Display for Chromosome {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.name)
+        match self {
+            Chromosome::Autosome(n) => write!(f, "{}", n),
+            Chromosome::X => write!(f, "X"),
+            Chromosome::Y => write!(f, "Y"),
+            Chromosome::XY => write!(f, "XY"),
+            Chromosome::MT => write!(f, "MT"),
+            Chromosome::Other(name) => write!(f, "{}", name)
+        }
     }
 }
 
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VariantKind {
+    Snp,
+    Mnp,
+    Indel,
+    // Symbolic alleles of structural variants (e.g. `<DEL>`) or the `*`
+    // allele of the VCF (overlapping deletion).
+    Symbolic,
+    // e.g. alleles with ambiguous bases or missing alleles.
+    Other
+}
+
+
+// Access to the sequence of the reference genome (e.g. a FastaReader), used
+// to normalize the indels.
+pub trait ReferenceSequence {
+    // Uppercase bases from `start` to `end` (1-based and inclusive). None if
+    // the region isn't in the reference.
+    fn bases(&mut self, chrom: &Chromosome, start: u32, end: u32)
+        -> Option<String>;
+}
+
+
+// With the `serde` feature, the core types (variants, chromosomes,
+// genotypes and samples) can be serialized and deserialized. The
+// chromosomes are strings and the variants are deserialized as they are
+// serialized, while the genotypes are validated (see the serialization
+// module at the end).
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde",
+           serde(try_from = "serialization::SerializedVariant"))]
 #[repr(C)]
 pub struct Variant {
     pub name: String,
@@ -121,10 +266,106 @@ pub struct Variant {
 }
 
 
+// Compact form parsed by `Variant::from_str`: `chr1:12345:A:G`, or only the
+// name for the variants without a locus. The other contigs are written
+// without the `chr` prefix (e.g. `GL000192.1:100:A:C`).
 impl fmt::Display for Variant {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "<Variant chr{}:{}_{:?}>", self.chrom, self.position,
-               self.alleles)
+        if !self.has_locus() {
+            return write!(f, "{}", self.name);
+        }
+
+        if let Chromosome::Other(_) = self.chrom {
+            write!(f, "{}", self.chrom)?;
+        } else {
+            write!(f, "chr{}", self.chrom)?;
+        }
+
+        write!(f, ":{}:{}:{}", self.position, self.alleles.0,
+               self.alleles.1)
+    }
+}
+
+
+// Variants given in a compact form, e.g. on the command line: a locus with
+// its alleles (`chr1:12345:A:G` or `1_12345_A_G`), without a name, or only
+// a name (`rs12345`), without a locus (see `Variant::identifies`).
+impl FromStr for Variant {
+    type Err = VariantError;
+
+    fn from_str(s: &str) -> Result<Variant, VariantError> {
+        let s = s.trim();
+
+        // Contig names (e.g. `HLA-A*01:01`) and symbolic alleles (e.g.
+        // `<INS:ME>`) can contain colons, so the last 3 fields are the
+        // position and the alleles.
+        if s.contains(':') {
+            let fields = split_locus(s);
+            if fields.len() < 4 {
+                return parse_locus(&fields);
+            }
+
+            let (chrom, fields) = fields.split_at(fields.len() - 3);
+            let chrom = chrom.join(":");
+            return parse_locus(&[&chrom, fields[0], fields[1], fields[2]]);
+        }
+
+        // Contig names can contain underscores, so the fields are split
+        // from the end.
+        let mut fields: Vec<&str> = s.rsplitn(4, '_').collect();
+        fields.reverse();
+        if fields.len() == 4 && fields[1].parse::<u32>().is_ok() {
+            return parse_locus(&fields);
+        }
+
+        if s.is_empty() || s.contains(char::is_whitespace) {
+            return Err(VariantError::InvalidName(s.to_string()));
+        }
+
+        // Like the unplaced variants of plink files.
+        Ok(Variant {
+            name: s.to_string(),
+            chrom: Chromosome::Other("0".to_string()),
+            position: 0,
+            alleles: ("0".to_string(), "0".to_string())
+        })
+    }
+}
+
+
+// Fields separated by colons, except in the symbolic alleles.
+fn split_locus(s: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut in_allele = false;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            '<' => in_allele = true,
+            '>' => in_allele = false,
+            ':' if !in_allele => {
+                fields.push(&s[start..i]);
+                start = i + 1;
+            },
+            _ => {}
+        }
+    }
+    fields.push(&s[start..]);
+
+    fields
+}
+
+
+fn parse_locus(fields: &[&str]) -> Result<Variant, VariantError> {
+    match fields {
+        [chrom, position, a1, a2] => {
+            VariantBuilder::new()
+                .chrom(chrom)
+                .position_str(position)
+                .alleles(a1, a2)
+                .build()
+        },
+        _ => Err(VariantError::MissingField("alleles"))
     }
 }
 
@@ -144,7 +385,7 @@ impl Variant {
 
         Variant {
             name: name,
-            chrom: Chromosome { name: chrom },
+            chrom: Chromosome::new(&chrom),
             position: pos,
             alleles: uc_alleles
         }
@@ -160,12 +401,28 @@ impl Variant {
         }
     }
 
-    pub fn primitive_locus_eq(&self, chrom: &String, pos: u32) -> bool {
-        self.chrom.name == *chrom && self.position == pos
+    pub fn primitive_locus_eq(&self, chrom: &str, pos: u32) -> bool {
+        self.chrom == Chromosome::new(chrom) && self.position == pos
+    }
+
+    // Variants parsed from a name only (and the unplaced variants of plink
+    // files) have no locus.
+    pub fn has_locus(&self) -> bool {
+        self.position > 0
+    }
+
+    // If the other variant is the one designated by this variant: the same
+    // name if this variant has no locus, or else the same locus and alleles.
+    pub fn identifies(&self, other: &Variant) -> bool {
+        if self.has_locus() {
+            self == other
+        } else {
+            self.name == other.name
+        }
     }
 
     pub fn locus_eq(&self, other: &Variant) -> bool {
-        self.primitive_locus_eq(&other.chrom.name, other.position)
+        self.chrom == other.chrom && self.position == other.position
     }
 
     pub fn alleles_eq(&self, other: &Variant) -> bool {
@@ -191,6 +448,127 @@ impl Variant {
         alleles
     }
 
+    pub fn kind(&self) -> VariantKind {
+        let (a1, a2) = &self.alleles;
+
+        let is_nucleotides = |s: &String| {
+            s.chars().all(|c| matches!(c, 'A' | 'C' | 'G' | 'T'))
+        };
+
+        if is_symbolic_allele(a1) || is_symbolic_allele(a2) {
+            VariantKind::Symbolic
+        } else if a1 == "-" || a2 == "-" || a1.len() != a2.len() {
+            VariantKind::Indel
+        } else if !is_nucleotides(a1) || !is_nucleotides(a2) {
+            VariantKind::Other
+        } else if a1.len() == 1 {
+            VariantKind::Snp
+        } else {
+            VariantKind::Mnp
+        }
+    }
+
+    // Purine to purine (A <-> G) or pyrimidine to pyrimidine (C <-> T)
+    // substitution.
+    pub fn is_transition(&self) -> bool {
+        if self.kind() != VariantKind::Snp {
+            return false;
+        }
+
+        // Alleles are ordered alphabetically for SNPs.
+        matches!(
+            (self.alleles.0.as_str(), self.alleles.1.as_str()),
+            ("A", "G") | ("C", "T")
+        )
+    }
+
+    pub fn is_transversion(&self) -> bool {
+        self.kind() == VariantKind::Snp && !self.is_transition()
+    }
+
+    // Left-align and trim the alleles on the reference (like `bcftools
+    // norm`), so that the same indel has the same representation whatever
+    // its source: the bases shared by the end of both alleles are removed
+    // (extending both alleles with the reference base on their left when
+    // one of them becomes empty), then the bases shared by their start
+    // (keeping at least one base). Returns true if the variant changed.
+    //
+    // SNPs are left unchanged. The alleles must be nucleotides and one of
+    // them must match the reference at the position of the variant.
+    pub fn normalize<R>(&mut self, reference: &mut R)
+        -> Result<bool, VariantError>
+        where R: ReferenceSequence + ?Sized
+    {
+        for allele in [&self.alleles.0, &self.alleles.1] {
+            if allele.is_empty() ||
+               !allele.chars().all(|c| matches!(c, 'A' | 'C' | 'G' | 'T' | 'N'))
+            {
+                return Err(VariantError::InvalidAllele(allele.clone()));
+            }
+        }
+
+        if self.alleles.0 == self.alleles.1 {
+            return Err(VariantError::IdenticalAlleles(self.alleles.0.clone()));
+        }
+
+        if self.kind() == VariantKind::Snp {
+            return Ok(false);
+        }
+
+        let locus = |position: u32| format!("{}:{}", self.chrom, position);
+        let mut fetch = |start: u32, end: u32| {
+            reference.bases(&self.chrom, start, end)
+                .ok_or_else(|| VariantError::NotInReference(locus(start)))
+        };
+
+        let matches_reference = [&self.alleles.0, &self.alleles.1].iter()
+            .map(|allele| {
+                let end = self.position + allele.len() as u32 - 1;
+                fetch(self.position, end).map(|bases| bases == **allele)
+            })
+            .collect::<Result<Vec<bool>, VariantError>>()?;
+        if !matches_reference.contains(&true) {
+            return Err(VariantError::ReferenceMismatch(locus(self.position)));
+        }
+
+        let mut a1: Vec<u8> = self.alleles.0.bytes().collect();
+        let mut a2: Vec<u8> = self.alleles.1.bytes().collect();
+        let mut position = self.position;
+
+        loop {
+            if !a1.is_empty() && a1.last() == a2.last() {
+                a1.pop();
+                a2.pop();
+            } else if a1.is_empty() || a2.is_empty() {
+                if position <= 1 {
+                    return Err(VariantError::NotInReference(locus(0)));
+                }
+                position -= 1;
+                let base = fetch(position, position)?.as_bytes()[0];
+                a1.insert(0, base);
+                a2.insert(0, base);
+            } else {
+                break;
+            }
+        }
+
+        let shared = a1.iter().zip(a2.iter())
+            .take(a1.len().min(a2.len()) - 1)
+            .take_while(|(b1, b2)| b1 == b2)
+            .count();
+        position += shared as u32;
+
+        let alleles = order_alleles(
+            String::from_utf8(a1[shared..].to_vec()).unwrap(),
+            String::from_utf8(a2[shared..].to_vec()).unwrap()
+        );
+        let changed = position != self.position || alleles != self.alleles;
+        self.position = position;
+        self.alleles = alleles;
+
+        Ok(changed)
+    }
+
     pub fn get_hash(&self) -> u64 {
         let mut s = DefaultHasher::new();
         self.hash(&mut s);
@@ -253,6 +631,207 @@ impl Hash for Variant {
 }
 
 
+#[derive(Debug, PartialEq)]
+pub enum VariantError {
+    MissingField(&'static str),
+    InvalidPosition(String),
+    InvalidChromosome(String),
+    InvalidAllele(String),
+    InvalidName(String),
+    IdenticalAlleles(String),
+    // The locus (`chrom:position`) isn't in the reference.
+    NotInReference(String),
+    // None of the alleles match the reference at the locus.
+    ReferenceMismatch(String)
+}
+
+
+impl fmt::Display for VariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VariantError::MissingField(field) =>
+                write!(f, "Missing variant field: {}", field),
+            VariantError::InvalidPosition(pos) =>
+                write!(f, "Invalid position: `{}`", pos),
+            VariantError::InvalidChromosome(chrom) =>
+                write!(f, "Invalid chromosome: `{}`", chrom),
+            VariantError::InvalidAllele(allele) =>
+                write!(f, "Invalid allele: `{}`", allele),
+            VariantError::InvalidName(name) =>
+                write!(f, "Invalid variant name: `{}`", name),
+            VariantError::IdenticalAlleles(allele) =>
+                write!(f, "Both alleles are `{}`", allele),
+            VariantError::NotInReference(locus) =>
+                write!(f, "Not in the reference: `{}`", locus),
+            VariantError::ReferenceMismatch(locus) =>
+                write!(f, "The alleles don't match the reference at `{}`",
+                       locus)
+        }
+    }
+}
+
+
+impl std::error::Error for VariantError {}
+
+
+// Validating alternative to `Variant::new` for parsing paths where bad data
+// is common. The alleles must be nucleotides (ACGTN), `-` for a deletion or
+// symbolic, and the built variant is classified by `Variant::kind`.
+//
+// let v = VariantBuilder::new()
+//     .name("rs12345")
+//     .chrom("chr1")
+//     .position_str("12345")
+//     .alleles("a", "G")
+//     .build()?;
+#[derive(Default)]
+pub struct VariantBuilder {
+    name: Option<String>,
+    chrom: Option<String>,
+    position: Option<String>,
+    alleles: Option<(String, String)>
+}
+
+
+impl VariantBuilder {
+    pub fn new() -> VariantBuilder {
+        VariantBuilder::default()
+    }
+
+    pub fn name(mut self, name: &str) -> VariantBuilder {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn chrom(mut self, chrom: &str) -> VariantBuilder {
+        self.chrom = Some(chrom.to_string());
+        self
+    }
+
+    pub fn position(mut self, position: u32) -> VariantBuilder {
+        self.position = Some(position.to_string());
+        self
+    }
+
+    // Position as read from a file, it is parsed when building.
+    pub fn position_str(mut self, position: &str) -> VariantBuilder {
+        self.position = Some(position.to_string());
+        self
+    }
+
+    pub fn alleles(mut self, a1: &str, a2: &str) -> VariantBuilder {
+        self.alleles = Some((a1.to_string(), a2.to_string()));
+        self
+    }
+
+    pub fn build(self) -> Result<Variant, VariantError> {
+        // The name is optional (e.g. VCF files use `.` for unnamed variants).
+        let name = self.name.unwrap_or_default();
+
+        let chrom = normalize_chromosome(
+            &self.chrom.ok_or(VariantError::MissingField("chrom"))?
+        )?;
+
+        let position = self.position
+            .ok_or(VariantError::MissingField("position"))?;
+
+        let position: u32 = match position.trim().parse() {
+            Ok(pos) if pos > 0 => pos,
+            _ => return Err(VariantError::InvalidPosition(position))
+        };
+
+        let (a1, a2) = self.alleles
+            .ok_or(VariantError::MissingField("alleles"))?;
+
+        let a1 = validate_allele(&a1)?;
+        let a2 = validate_allele(&a2)?;
+
+        if a1 == a2 {
+            return Err(VariantError::IdenticalAlleles(a1));
+        }
+
+        Ok(Variant::new(name, chrom, position, (a1, a2)))
+    }
+}
+
+
+// Normalize the chromosome name: the `chr` prefix is stripped and the plink
+// numeric codes for the sex chromosomes and the mitochondria are replaced
+// by their usual names. `0` (unknown chromosome in plink) is kept, and the
+// other contigs (e.g. `GL000192.1` or `HLA-A*01:01`) keep their case.
+pub fn normalize_chromosome(chrom: &str) -> Result<String, VariantError> {
+    let trimmed = chrom.trim();
+
+    let name = match trimmed.get(..3) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("chr") => &trimmed[3..],
+        _ => trimmed
+    };
+
+    let valid = !name.is_empty() && name.chars().all(|c| {
+        c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '*' | ':')
+    });
+
+    if !valid {
+        return Err(VariantError::InvalidChromosome(chrom.to_string()));
+    }
+
+    if name.chars().all(|c| c.is_ascii_digit()) {
+        let normalized = match name.trim_start_matches('0') {
+            "" => "0",
+            "23" => "X",
+            "24" => "Y",
+            "25" => "XY",
+            "26" => "MT",
+            number => number
+        };
+
+        return Ok(normalized.to_string());
+    }
+
+    let upper = name.to_uppercase();
+    let normalized = match upper.as_str() {
+        "X" | "Y" | "XY" | "MT" => upper.as_str(),
+        "M" => "MT",
+        _ => name
+    };
+
+    Ok(normalized.to_string())
+}
+
+
+// Alleles are nucleotide sequences (which allows indels) or `-` for
+// deletions in formats that use it.
+// `<ID>` (e.g. `<DEL>` or `<INS:ME:ALU>`) or `*`.
+pub fn is_symbolic_allele(allele: &str) -> bool {
+    let id = allele.strip_prefix('<').and_then(|a| a.strip_suffix('>'));
+    allele == "*" || id.is_some_and(|id| {
+        !id.is_empty() && !id.contains(|c: char| c.is_whitespace() ||
+                                                 c == '<' || c == '>')
+    })
+}
+
+
+fn validate_allele(allele: &str) -> Result<String, VariantError> {
+    // The symbolic alleles are case-sensitive.
+    if is_symbolic_allele(allele.trim()) {
+        return Ok(allele.trim().to_string());
+    }
+
+    let allele = allele.trim().to_uppercase();
+
+    let valid = allele == "-" || (
+        !allele.is_empty() &&
+        allele.chars().all(|c| matches!(c, 'A' | 'C' | 'G' | 'T' | 'N'))
+    );
+
+    if valid {
+        Ok(allele)
+    } else {
+        Err(VariantError::InvalidAllele(allele))
+    }
+}
+
+
 pub fn complement(s: &String) -> String {
     String::from_iter(s.chars().map(|c| {
         match c {
@@ -266,11 +845,200 @@ pub fn complement(s: &String) -> String {
 }
 
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Sex {
+    Male,
+    Female,
+    Unknown
+}
+
+
+impl Sex {
+    // Sex as coded in the 5th column of the FAM.
+    pub fn from_plink_code(code: &str) -> Sex {
+        match code {
+            "1" => Sex::Male,
+            "2" => Sex::Female,
+            _ => Sex::Unknown
+        }
+    }
+
+    pub fn to_plink_code(self) -> &'static str {
+        match self {
+            Sex::Male => "1",
+            Sex::Female => "2",
+            Sex::Unknown => "0"
+        }
+    }
+}
+
+
+// Sample with the information of the FAM. The formats without a pedigree or
+// phenotype (e.g. VCF) leave them unknown.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sample {
+    pub fid: String,
+    pub iid: String,
+    // IIDs of the parents (in the same family), None if unknown (`0`).
+    pub father: Option<String>,
+    pub mother: Option<String>,
+    pub sex: Sex,
+    // Phenotype of the FAM (6th column), None if missing (`-9` or `NA`).
+    // Case/control status is coded as in the FAM (1 for controls and 2 for
+    // cases).
+    pub phenotype: Option<f64>
+}
+
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.fid, self.iid)
+    }
+}
+
+
+impl Sample {
+    // Sample without parents or phenotype.
+    pub fn new(fid: String, iid: String, sex: Sex) -> Sample {
+        Sample { fid, iid, father: None, mother: None, sex, phenotype: None }
+    }
+
+    // Parse the 6 columns of a FAM line (the parents, sex and phenotype can
+    // be omitted).
+    pub fn from_fam_fields(fields: &[&str]) -> Result<Sample, String> {
+        if fields.len() < 2 {
+            return Err("expected at least the FID and IID".to_string());
+        }
+
+        let parent = |i: usize| match fields.get(i) {
+            Some(&"0") | None => None,
+            Some(id) => Some(id.to_string())
+        };
+
+        let phenotype = match fields.get(5) {
+            Some(&"-9") | Some(&"NA") | None => None,
+            Some(value) => Some(value.parse().map_err(|_| {
+                format!("invalid phenotype `{}`", value)
+            })?)
+        };
+
+        Ok(Sample {
+            fid: fields[0].to_string(),
+            iid: fields[1].to_string(),
+            father: parent(2),
+            mother: parent(3),
+            sex: Sex::from_plink_code(fields.get(4).unwrap_or(&"0")),
+            phenotype
+        })
+    }
+
+    // The line of the sample in a FAM (without the newline).
+    pub fn to_fam_line(&self) -> String {
+        let phenotype = self.phenotype.map_or("-9".to_string(),
+                                              |p| p.to_string());
+        format!("{} {} {} {} {} {}", self.fid, self.iid,
+                self.father.as_deref().unwrap_or("0"),
+                self.mother.as_deref().unwrap_or("0"),
+                self.sex.to_plink_code(), phenotype)
+    }
+}
+
+
+// See `Chromosome::is_haploid`.
+pub fn is_haploid_chromosome(name: &str) -> bool {
+    Chromosome::new(name).is_haploid()
+}
+
+
+// What to do with heterozygous calls when converting to haploid genotypes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HaploidHets {
+    SetMissing,
+    Error
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct HeterozygousHaploidError {
+    pub variant: String,
+    // Indices of the samples with heterozygous calls.
+    pub samples: Vec<usize>
+}
+
+
+impl fmt::Display for HeterozygousHaploidError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} heterozygous call(s) for the haploid variant `{}`",
+               self.samples.len(), self.variant)
+    }
+}
+
+
+impl std::error::Error for HeterozygousHaploidError {}
+
+
+// What the statistical functions do with the missing genotypes: drop the
+// samples, replace the genotypes by the mean of the called samples or fail.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissingPolicy {
+    #[default]
+    Drop,
+    MeanImpute,
+    Fail
+}
+
+
+impl FromStr for MissingPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MissingPolicy, String> {
+        match s {
+            "drop" => Ok(MissingPolicy::Drop),
+            "mean" => Ok(MissingPolicy::MeanImpute),
+            "fail" => Ok(MissingPolicy::Fail),
+            _ => Err(format!("Unknown missing policy `{}` (expected drop, \
+                              mean or fail).", s))
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct MissingGenotypesError {
+    pub variant: String,
+    pub n_missing: usize
+}
+
+
+impl fmt::Display for MissingGenotypesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} missing genotype(s) for the variant `{}`",
+               self.n_missing, self.variant)
+    }
+}
+
+
+impl std::error::Error for MissingGenotypesError {}
+
+
+// The samples aren't serialized.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde",
+           serde(into = "serialization::SerializedGenotypes",
+                 try_from = "serialization::SerializedGenotypes"))]
 pub struct Genotypes {
     pub variant: Variant,
+    // Number of copies of the coded allele (0 to the ploidy).
     pub genotypes: Vec<Option<u8>>,
-    coded_idx: u8
+    // Optional sample context shared between all the Genotypes produced by
+    // a reader.
+    pub samples: Option<Arc<Vec<Sample>>>,
+    coded_idx: u8,
+    ploidy: u8
 }
 
 
@@ -295,31 +1063,658 @@ impl Genotypes {
                        coded_allele, &variant);
             };
 
-            Genotypes { variant, genotypes, coded_idx }
+            Genotypes {
+                variant, genotypes, samples: None, coded_idx, ploidy: 2
+            }
     }
 
-    pub fn coded_freq(&self) -> f64 {
-        let (n, sum) = self.genotypes
-            .iter()
-            .flatten()
-            .fold((0, 0), |(n, sum), g| (n + 1, sum + g));
-
-        (sum as f64) / (2.0 * (n as f64))
+    pub fn ploidy(&self) -> u8 {
+        self.ploidy
     }
 
-    pub fn maf(&self) -> f64 {
-        let freq = self.coded_freq();
-        freq.min(1.0 - freq)
+    // Set the ploidy of calls that are already coded accordingly.
+    pub(crate) fn with_ploidy(mut self, ploidy: u8) -> Genotypes {
+        self.ploidy = ploidy;
+        self
     }
-}
 
-impl PartialEq for Genotypes {
-    fn eq(&self, other: &Genotypes) -> bool {
-        (self.variant == other.variant) &&
-        (self.genotypes == other.genotypes) &&
-        (self.coded_idx == other.coded_idx)
+    pub fn is_haploid(&self) -> bool {
+        self.ploidy == 1
     }
-}
+
+    // Convert diploid coded calls (0 or 2, as stored for haploid variants in
+    // plink files) to haploid calls (0 or 1). Heterozygous calls are handled
+    // according to the policy.
+    pub fn into_haploid(mut self, hets: HaploidHets)
+        -> Result<Genotypes, HeterozygousHaploidError>
+    {
+        if self.is_haploid() {
+            return Ok(self);
+        }
+
+        let het_samples: Vec<usize> = self.genotypes.iter()
+            .enumerate()
+            .filter(|(_, g)| **g == Some(1))
+            .map(|(i, _)| i)
+            .collect();
+
+        if !het_samples.is_empty() && hets == HaploidHets::Error {
+            return Err(HeterozygousHaploidError {
+                variant: self.variant.to_string(),
+                samples: het_samples
+            });
+        }
+
+        for g in self.genotypes.iter_mut() {
+            *g = match *g {
+                Some(0) => Some(0),
+                Some(2) => Some(1),
+                _ => None
+            };
+        }
+
+        self.ploidy = 1;
+        Ok(self)
+    }
+
+    // Dosages of the coded allele. The haploid calls are coded as
+    // homozygous (0 or 2), as in plink files.
+    pub fn to_dosages(&self) -> Dosages {
+        let scale = if self.is_haploid() { 2.0 } else { 1.0 };
+        let dosages = self.genotypes.iter()
+            .map(|g| g.map(|g| scale * f64::from(g)))
+            .collect();
+
+        let mut d = Dosages::new(self.variant.clone(), dosages,
+                                 self.coded_allele());
+        d.samples = self.samples.clone();
+        d
+    }
+
+    // Attach the samples corresponding to the genotype vector.
+    pub fn with_samples(mut self, samples: Arc<Vec<Sample>>) -> Genotypes {
+        if samples.len() != self.genotypes.len() {
+            panic!("Got {} samples for {} genotypes.", samples.len(),
+                   self.genotypes.len());
+        }
+
+        self.samples = Some(samples);
+        self
+    }
+
+    // Count the other allele instead of the coded allele, in place (e.g. to
+    // code the effect allele of summary statistics).
+    pub fn flip_coded(&mut self) {
+        let ploidy = self.ploidy;
+        for g in self.genotypes.iter_mut() {
+            *g = g.map(|x| ploidy - x);
+        }
+
+        self.coded_idx = 1 - self.coded_idx;
+    }
+
+    // See `flip_coded`.
+    pub fn flip_coded_allele(mut self) -> Genotypes {
+        self.flip_coded();
+        self
+    }
+
+    // SHA-256 of the calls that doesn't depend on the coded allele: the
+    // copies of the second allele of the variant are hashed with the ploidy
+    // and missing calls are distinct from every call. The digest depends on
+    // the order of the samples.
+    pub fn content_hash(&self) -> String {
+        let calls: Vec<u8> = self.genotypes.iter()
+            .map(|g| match *g {
+                Some(x) if self.coded_idx == 1 => x,
+                Some(x) => self.ploidy - x,
+                None => 0xff
+            })
+            .collect();
+
+        let mut hasher = Sha256::new();
+        hasher.update([self.ploidy]);
+        hasher.update(&calls);
+        to_hex(&hasher.finalize())
+    }
+
+    pub fn coded_allele(&self) -> &str {
+        if self.coded_idx == 0 {
+            &self.variant.alleles.0
+        } else {
+            &self.variant.alleles.1
+        }
+    }
+
+    pub fn other_allele(&self) -> &str {
+        if self.coded_idx == 0 {
+            &self.variant.alleles.1
+        } else {
+            &self.variant.alleles.0
+        }
+    }
+
+    // The calls as checked allele counts of the coded allele.
+    pub fn allele_counts(&self)
+        -> impl Iterator<Item = Option<AlleleCount>> + '_
+    {
+        self.genotypes.iter().map(move |g| {
+            g.map(|x| {
+                AlleleCount::new(x, self.ploidy)
+                    .unwrap_or_else(|e| panic!("{} in `{}`.", e, self.variant))
+            })
+        })
+    }
+
+    // Frequency of the coded allele among the called samples (None if no
+    // sample is called).
+    pub fn coded_frequency(&self) -> Option<Frequency> {
+        self.coded_frequency_with(FrequencyEstimator::Observed)
+    }
+
+    pub fn coded_frequency_with(&self, estimator: FrequencyEstimator)
+        -> Option<Frequency>
+    {
+        let (n, sum) = self.genotypes
+            .iter()
+            .flatten()
+            .fold((0_u64, 0_u64), |(n, sum), &g| (n + 1, sum + u64::from(g)));
+
+        let ploidy = f64::from(self.ploidy);
+        estimator.estimate(sum as f64, ploidy * n as f64,
+                           ploidy * self.genotypes.len() as f64)
+    }
+
+    // See `coded_frequency`, NaN if no sample is called.
+    pub fn coded_freq(&self) -> f64 {
+        self.coded_frequency().map_or(f64::NAN, f64::from)
+    }
+
+    pub fn maf(&self) -> f64 {
+        let freq = self.coded_freq();
+        freq.min(1.0 - freq)
+    }
+
+    // Copies of the coded allele with the missing genotypes replaced by the
+    // mean of the called samples (NaN if no sample is called).
+    pub fn impute_mean(&self) -> Vec<f64> {
+        let mean = f64::from(self.ploidy) * self.coded_freq();
+        self.genotypes.iter()
+            .map(|g| g.map_or(mean, f64::from))
+            .collect()
+    }
+
+    // Genotypes centered by the expected number of copies of the coded
+    // allele (2p) and scaled by its standard deviation (sqrt(2p(1 - p))), as
+    // used for the GRM and the PCs. The missing genotypes are set to 0 (the
+    // mean). None if the variant is monomorphic or no sample is called.
+    pub fn standardized(&self) -> Option<Vec<f64>> {
+        let p = self.coded_freq();
+        if p.is_nan() || p <= 0.0 || p >= 1.0 {
+            return None;
+        }
+
+        let ploidy = f64::from(self.ploidy);
+        let sd = (ploidy * p * (1.0 - p)).sqrt();
+        Some(self.genotypes.iter()
+            .map(|g| g.map_or(0.0, |x| (f64::from(x) - ploidy * p) / sd))
+            .collect())
+    }
+
+    // Copies of the coded allele with the missing genotypes handled
+    // according to the policy (None for the dropped samples).
+    pub fn values(&self, policy: MissingPolicy)
+        -> Result<Vec<Option<f64>>, MissingGenotypesError>
+    {
+        match policy {
+            MissingPolicy::Drop => Ok(
+                self.genotypes.iter().map(|g| g.map(f64::from)).collect()
+            ),
+            MissingPolicy::MeanImpute => Ok(
+                self.impute_mean().into_iter().map(Some).collect()
+            ),
+            MissingPolicy::Fail => {
+                let n_missing = self.genotypes.iter()
+                    .filter(|g| g.is_none())
+                    .count();
+
+                if n_missing > 0 {
+                    return Err(MissingGenotypesError {
+                        variant: self.variant.to_string(),
+                        n_missing
+                    });
+                }
+
+                self.values(MissingPolicy::Drop)
+            }
+        }
+    }
+
+    // Genotype of the sample at the given index (`Some(None)` if missing),
+    // or None if the index is out of bounds (like `slice::get`).
+    pub fn get(&self, sample_index: usize) -> Option<Option<u8>> {
+        self.genotypes.get(sample_index).copied()
+    }
+
+    // Pair every genotype with the corresponding sample (e.g. the samples
+    // from the reader that produced these genotypes).
+    pub fn iter_with_samples<'a, S>(&'a self, samples: &'a [S])
+        -> impl Iterator<Item = (&'a S, Option<u8>)> + 'a
+    {
+        if samples.len() != self.genotypes.len() {
+            panic!("Got {} samples for {} genotypes.", samples.len(),
+                   self.genotypes.len());
+        }
+
+        samples.iter().zip(self.genotypes.iter().cloned())
+    }
+
+    // New Genotypes containing only the samples at the given indices (in
+    // the provided order). Panics if an index is out of bounds.
+    pub fn subset(&self, idx: &[usize]) -> Genotypes {
+        let genotypes = idx.iter().map(|&i| self.genotypes[i]).collect();
+
+        let samples = self.samples.as_ref().map(|samples| {
+            Arc::new(idx.iter().map(|&i| samples[i].clone()).collect())
+        });
+
+        Genotypes {
+            variant: self.variant.clone(),
+            genotypes,
+            samples,
+            coded_idx: self.coded_idx,
+            ploidy: self.ploidy
+        }
+    }
+}
+
+impl PartialEq for Genotypes {
+    fn eq(&self, other: &Genotypes) -> bool {
+        (self.variant == other.variant) &&
+        (self.genotypes == other.genotypes) &&
+        (self.coded_idx == other.coded_idx) &&
+        (self.ploidy == other.ploidy)
+    }
+}
+
+
+// Expected number of copies of the coded allele for every sample, as
+// produced by imputation.
+#[derive(Debug)]
+pub struct Dosages {
+    pub variant: Variant,
+    pub dosages: Vec<Option<f64>>,
+    pub samples: Option<Arc<Vec<Sample>>>,
+    // Imputation quality score (e.g. the IMPUTE2 info or the minimac Rsq)
+    // if it is provided with the dosages.
+    pub info: Option<f64>,
+    coded_idx: u8
+}
+
+
+impl fmt::Display for Dosages {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<Dosages n={}>", self.dosages.len())
+    }
+}
+
+
+impl Dosages {
+    pub fn new(variant: Variant, dosages: Vec<Option<f64>>,
+               coded_allele: &str) -> Dosages
+    {
+        let coded = coded_allele.to_uppercase();
+        let coded_idx = if variant.alleles.0 == coded {
+            0
+        } else if variant.alleles.1 == coded {
+            1
+        } else {
+            panic!("Coded allele `{}` is not an allele of `{}`",
+                   coded_allele, &variant);
+        };
+
+        Dosages { variant, dosages, samples: None, info: None, coded_idx }
+    }
+
+    // Attach the samples corresponding to the dosage vector.
+    pub fn with_samples(mut self, samples: Arc<Vec<Sample>>) -> Dosages {
+        if samples.len() != self.dosages.len() {
+            panic!("Got {} samples for {} dosages.", samples.len(),
+                   self.dosages.len());
+        }
+
+        self.samples = Some(samples);
+        self
+    }
+
+    pub fn with_info(mut self, info: f64) -> Dosages {
+        self.info = Some(info);
+        self
+    }
+
+    pub fn coded_allele(&self) -> &str {
+        if self.coded_idx == 0 {
+            &self.variant.alleles.0
+        } else {
+            &self.variant.alleles.1
+        }
+    }
+
+    pub fn other_allele(&self) -> &str {
+        if self.coded_idx == 0 {
+            &self.variant.alleles.1
+        } else {
+            &self.variant.alleles.0
+        }
+    }
+
+    // Frequency of the coded allele among the samples with a dosage (None
+    // if there are none or if the dosages are out of range).
+    pub fn coded_frequency(&self) -> Option<Frequency> {
+        self.coded_frequency_with(FrequencyEstimator::Observed)
+    }
+
+    pub fn coded_frequency_with(&self, estimator: FrequencyEstimator)
+        -> Option<Frequency>
+    {
+        let (n, sum) = self.dosages
+            .iter()
+            .flatten()
+            .fold((0_u64, 0.0), |(n, sum), d| (n + 1, sum + d));
+
+        estimator.estimate(sum, 2.0 * n as f64,
+                           2.0 * self.dosages.len() as f64)
+    }
+
+    // See `coded_frequency`, NaN if there is no dosage.
+    pub fn coded_freq(&self) -> f64 {
+        self.coded_frequency().map_or(f64::NAN, f64::from)
+    }
+
+    pub fn maf(&self) -> f64 {
+        let freq = self.coded_freq();
+        freq.min(1.0 - freq)
+    }
+
+    // Imputation quality: the provided info if any, otherwise the ratio of
+    // the observed variance of the dosages to the binomial variance 2p(1-p)
+    // (the MaCH Rsq estimator). NaN if there are no dosages or if the
+    // variant is monomorphic.
+    pub fn info_score(&self) -> f64 {
+        if let Some(info) = self.info {
+            return info;
+        }
+
+        let observed: Vec<f64> = self.dosages.iter().flatten().copied()
+            .collect();
+        let p = self.coded_freq();
+        if observed.is_empty() || p <= 0.0 || p >= 1.0 {
+            return f64::NAN;
+        }
+
+        let mean = 2.0 * p;
+        let variance = observed.iter()
+            .map(|d| (d - mean).powi(2))
+            .sum::<f64>() / observed.len() as f64;
+
+        variance / (2.0 * p * (1.0 - p))
+    }
+
+    // Round the dosages to hard calls. Dosages further than `max_distance`
+    // from an integer (or not between 0 and 2) are set to missing.
+    pub fn to_hard_calls(&self, max_distance: f64) -> Genotypes {
+        let genotypes = self.dosages.iter()
+            .map(|d| {
+                Dosage::new((*d)?).ok()?
+                    .to_allele_count(max_distance)
+                    .map(AlleleCount::get)
+            })
+            .collect();
+
+        let mut g = Genotypes::new(self.variant.clone(), genotypes,
+                                   self.coded_allele());
+        g.samples = self.samples.clone();
+        g
+    }
+}
+
+
+// Phased calls of a biallelic variant: the copies of the coded allele (0 or
+// 1) on the two haplotypes of every sample. The order of the haplotypes is
+// given by the source (e.g. paternal then maternal for the phasing by
+// transmission, see the family module).
+#[derive(Clone, Debug)]
+pub struct Haplotypes {
+    pub variant: Variant,
+    // None if the call is missing or unphased.
+    pub haplotypes: Vec<Option<(u8, u8)>>,
+    pub samples: Option<Arc<Vec<Sample>>>,
+    coded_idx: u8
+}
+
+
+impl fmt::Display for Haplotypes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<Haplotypes n={}>", self.haplotypes.len())
+    }
+}
+
+
+impl Haplotypes {
+    pub fn new(variant: Variant, haplotypes: Vec<Option<(u8, u8)>>,
+               coded_allele: &str) -> Haplotypes
+    {
+        let coded = coded_allele.to_uppercase();
+        let coded_idx = if variant.alleles.0 == coded {
+            0
+        } else if variant.alleles.1 == coded {
+            1
+        } else {
+            panic!("Coded allele `{}` is not an allele of `{}`",
+                   coded_allele, &variant);
+        };
+
+        Haplotypes { variant, haplotypes, samples: None, coded_idx }
+    }
+
+    // Attach the samples corresponding to the haplotypes.
+    pub fn with_samples(mut self, samples: Arc<Vec<Sample>>) -> Haplotypes {
+        if samples.len() != self.haplotypes.len() {
+            panic!("Got {} samples for {} haplotype pairs.", samples.len(),
+                   self.haplotypes.len());
+        }
+
+        self.samples = Some(samples);
+        self
+    }
+
+    pub fn coded_allele(&self) -> &str {
+        if self.coded_idx == 0 {
+            &self.variant.alleles.0
+        } else {
+            &self.variant.alleles.1
+        }
+    }
+
+    pub fn other_allele(&self) -> &str {
+        if self.coded_idx == 0 {
+            &self.variant.alleles.1
+        } else {
+            &self.variant.alleles.0
+        }
+    }
+
+    pub fn n_phased(&self) -> usize {
+        self.haplotypes.iter().filter(|h| h.is_some()).count()
+    }
+
+    // Unphased genotypes (the calls that aren't phased are missing).
+    pub fn to_genotypes(&self) -> Genotypes {
+        let genotypes = self.haplotypes.iter()
+            .map(|h| h.map(|(a, b)| a + b))
+            .collect();
+
+        let mut g = Genotypes::new(self.variant.clone(), genotypes,
+                                   self.coded_allele());
+        g.samples = self.samples.clone();
+        g
+    }
+}
+
+
+// Variant with any number of alleles (e.g. a multiallelic VCF record). The
+// alleles are kept in the order of the source: the first one is the
+// reference allele and the others are the alternate alleles.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MultiAllelicVariant {
+    pub name: String,
+    pub chrom: Chromosome,
+    pub position: u32,
+    pub alleles: Vec<String>
+}
+
+
+impl fmt::Display for MultiAllelicVariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<MultiAllelicVariant chr{}:{}_{:?}>", self.chrom,
+               self.position, self.alleles)
+    }
+}
+
+
+impl MultiAllelicVariant {
+    pub fn new(name: String, chrom: String, position: u32,
+               alleles: Vec<String>) -> MultiAllelicVariant
+    {
+        if alleles.len() < 2 {
+            panic!("Expected at least 2 alleles for `{}`, got {}.", name,
+                   alleles.len());
+        }
+
+        MultiAllelicVariant {
+            name,
+            chrom: Chromosome::new(&chrom),
+            position,
+            alleles: alleles.iter().map(|a| a.to_uppercase()).collect()
+        }
+    }
+
+    pub fn n_alleles(&self) -> usize {
+        self.alleles.len()
+    }
+
+    pub fn is_biallelic(&self) -> bool {
+        self.alleles.len() == 2
+    }
+
+    // Biallelic variant with the reference allele and the given alternate
+    // allele (1 for the first one).
+    pub fn biallelic(&self, allele: usize) -> Variant {
+        if allele == 0 || allele >= self.alleles.len() {
+            panic!("`{}` has no alternate allele {}.", self, allele);
+        }
+
+        Variant::new(self.name.clone(), self.chrom.to_string(),
+                     self.position,
+                     (self.alleles[0].clone(), self.alleles[allele].clone()))
+    }
+}
+
+
+// Calls of a multiallelic variant: the indices of the alleles of every call
+// (one per chromosome copy, i.e. 1 or 2 values).
+#[derive(Clone, Debug)]
+pub struct MultiAllelicGenotypes {
+    pub variant: MultiAllelicVariant,
+    // None if the call is missing.
+    pub calls: Vec<Option<Vec<u8>>>,
+    pub samples: Option<Arc<Vec<Sample>>>
+}
+
+
+impl fmt::Display for MultiAllelicGenotypes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<MultiAllelicGenotypes n={}>", self.calls.len())
+    }
+}
+
+
+impl MultiAllelicGenotypes {
+    pub fn new(variant: MultiAllelicVariant, calls: Vec<Option<Vec<u8>>>)
+        -> MultiAllelicGenotypes
+    {
+        let n_alleles = variant.n_alleles();
+        let invalid = calls.iter()
+            .flatten()
+            .flatten()
+            .any(|&allele| usize::from(allele) >= n_alleles);
+
+        if invalid {
+            panic!("The calls of `{}` have alleles out of range.", variant);
+        }
+
+        MultiAllelicGenotypes { variant, calls, samples: None }
+    }
+
+    pub fn with_samples(mut self, samples: Arc<Vec<Sample>>)
+        -> MultiAllelicGenotypes
+    {
+        if samples.len() != self.calls.len() {
+            panic!("Got {} samples for {} calls.", samples.len(),
+                   self.calls.len());
+        }
+
+        self.samples = Some(samples);
+        self
+    }
+
+    // Number of copies of an allele in every call.
+    pub fn allele_copies(&self, allele: usize) -> Vec<Option<u8>> {
+        self.calls.iter()
+            .map(|call| {
+                call.as_ref().map(|alleles| {
+                    alleles.iter()
+                        .filter(|&&a| usize::from(a) == allele)
+                        .count() as u8
+                })
+            })
+            .collect()
+    }
+
+    // Split into biallelic genotypes, one per alternate allele (coded), like
+    // `bcftools norm -m-`: the other alternate alleles are counted as the
+    // reference allele.
+    //
+    // Variants where every call is haploid are represented as haploid.
+    // Otherwise, haploid calls are coded as homozygous.
+    pub fn split(&self) -> Vec<Genotypes> {
+        let haploid = self.calls.iter().any(|call| call.is_some()) &&
+            self.calls.iter()
+                .flatten()
+                .all(|alleles| alleles.len() == 1);
+
+        (1..self.variant.n_alleles())
+            .map(|allele| {
+                let genotypes = self.allele_copies(allele).iter()
+                    .zip(self.calls.iter())
+                    .map(|(n, call)| match call {
+                        Some(alleles) if alleles.len() == 1 && !haploid => {
+                            n.map(|n| 2 * n)
+                        },
+                        _ => *n
+                    })
+                    .collect();
+
+                let mut g = Genotypes::new(self.variant.biallelic(allele),
+                                           genotypes,
+                                           &self.variant.alleles[allele])
+                    .with_ploidy(if haploid { 1 } else { 2 });
+                g.samples = self.samples.clone();
+                g
+            })
+            .collect()
+    }
+}
 
 
 fn order_alleles(a1: String, a2: String) -> (String, String) {
@@ -346,3 +1741,646 @@ fn order_alleles(a1: String, a2: String) -> (String, String) {
 
     }
 }
+
+
+#[cfg(feature = "serde")]
+mod serialization {
+    use std::convert::TryFrom;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde::de::Error;
+
+    use super::*;
+
+    impl Serialize for Chromosome {
+        fn serialize<S: Serializer>(&self, serializer: S)
+            -> Result<S::Ok, S::Error>
+        {
+            serializer.collect_str(self)
+        }
+    }
+
+    // Like `Chromosome::new`, so that the other contigs (e.g. `0`) are read
+    // back as they are written.
+    impl<'de> Deserialize<'de> for Chromosome {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D)
+            -> Result<Chromosome, D::Error>
+        {
+            let name = String::deserialize(deserializer)?;
+            if name.trim().is_empty() {
+                return Err(D::Error::custom(
+                    VariantError::InvalidChromosome(name)
+                ));
+            }
+
+            Ok(Chromosome::new(&name))
+        }
+    }
+
+    // Variants are built like with `Variant::new` (e.g. the unplaced
+    // variants of plink files have the `0` allele at position 0), only the
+    // alleles must not be empty or contain whitespace.
+    #[derive(Deserialize)]
+    pub struct SerializedVariant {
+        #[serde(default)]
+        name: String,
+        chrom: String,
+        position: u32,
+        alleles: (String, String)
+    }
+
+    impl TryFrom<SerializedVariant> for Variant {
+        type Error = VariantError;
+
+        fn try_from(v: SerializedVariant) -> Result<Variant, VariantError> {
+            for allele in [&v.alleles.0, &v.alleles.1] {
+                if allele.is_empty() || allele.contains(char::is_whitespace) {
+                    return Err(VariantError::InvalidAllele(allele.clone()));
+                }
+            }
+
+            if v.chrom.trim().is_empty() {
+                return Err(VariantError::InvalidChromosome(v.chrom));
+            }
+
+            Ok(Variant::new(v.name, v.chrom, v.position, v.alleles))
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct SerializedGenotypes {
+        variant: Variant,
+        coded_allele: String,
+        ploidy: u8,
+        genotypes: Vec<Option<u8>>
+    }
+
+    impl From<Genotypes> for SerializedGenotypes {
+        fn from(g: Genotypes) -> SerializedGenotypes {
+            SerializedGenotypes {
+                coded_allele: g.coded_allele().to_string(),
+                ploidy: g.ploidy,
+                variant: g.variant,
+                genotypes: g.genotypes
+            }
+        }
+    }
+
+    impl TryFrom<SerializedGenotypes> for Genotypes {
+        type Error = String;
+
+        fn try_from(g: SerializedGenotypes) -> Result<Genotypes, String> {
+            let alleles = &g.variant.alleles;
+            let coded = g.coded_allele.to_uppercase();
+            if coded != alleles.0 && coded != alleles.1 {
+                return Err(format!("Coded allele `{}` is not an allele of \
+                                    `{}`", g.coded_allele, g.variant));
+            }
+
+            if !matches!(g.ploidy, 1 | 2) {
+                return Err(format!("Invalid ploidy: {}", g.ploidy));
+            }
+
+            if g.genotypes.iter().flatten().any(|&x| x > g.ploidy) {
+                return Err(format!("Genotypes of `{}` larger than the \
+                                    ploidy", g.variant));
+            }
+
+            Ok(Genotypes::new(g.variant, g.genotypes, &coded)
+                .with_ploidy(g.ploidy))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Document {
+            genotypes: Genotypes,
+            samples: Vec<Sample>
+        }
+
+        #[test]
+        fn test_toml_roundtrip() {
+            let v = Variant::new("rs1".to_string(), "chr23".to_string(), 10,
+                                 ("G".to_string(), "A".to_string()));
+            // TOML has no null for the missing genotypes (unlike JSON).
+            let document = Document {
+                genotypes: Genotypes::new(v, vec![Some(0), Some(1), Some(1)],
+                                          "G").with_ploidy(1),
+                samples: vec![Sample::new("f1".to_string(),
+                                          "s1".to_string(), Sex::Female)]
+            };
+
+            let text = toml::to_string(&document).unwrap();
+            assert!(text.contains("chrom = \"X\""));
+            assert!(text.contains("sex = \"female\""));
+
+            let parsed: Document = toml::from_str(&text).unwrap();
+            assert_eq!(parsed, document);
+            assert_eq!(parsed.genotypes.coded_allele(), "G");
+            assert_eq!(parsed.genotypes.ploidy(), 1);
+
+            let invalid = "name = \"rs1\"\nchrom = \"1\"\nposition = 10\n\
+                           alleles = [\"A\", \"\"]\n";
+            assert!(toml::from_str::<Variant>(invalid).is_err());
+            assert_eq!(toml::from_str::<Chromosome>("").ok(), None);
+        }
+
+        #[test]
+        fn test_variant_roundtrip() {
+            #[derive(Debug, PartialEq, Serialize, Deserialize)]
+            struct Variants {
+                variants: Vec<Variant>
+            }
+
+            // Unplaced and non-standard contigs are read back unchanged.
+            let variants = [("0", 0, "0", "A"), ("GL000192.1", 100, "A", "C"),
+                            ("chrUn_gl000220", 105, "I", "D"),
+                            ("chr23", 10, "A", "<DEL>")]
+                .iter()
+                .map(|&(chrom, position, a1, a2)| {
+                    Variant::new("rs1".to_string(), chrom.to_string(),
+                                 position, (a1.to_string(), a2.to_string()))
+                })
+                .collect();
+            let document = Variants { variants };
+
+            let text = toml::to_string(&document).unwrap();
+            assert!(text.contains("chrom = \"GL000192.1\""));
+
+            let parsed: Variants = toml::from_str(&text).unwrap();
+            assert_eq!(parsed, document);
+            assert_eq!(parsed.variants[0].chrom,
+                       Chromosome::Other("0".to_string()));
+            assert_eq!(parsed.variants[2].chrom,
+                       Chromosome::Other("Un_gl000220".to_string()));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_genotypes() -> Genotypes {
+        let v = Variant::new(
+            "rs1".to_string(),
+            "1".to_string(),
+            1234,
+            ("A".to_string(), "G".to_string())
+        );
+
+        Genotypes::new(v, vec![Some(0), None, Some(2), Some(1)], "G")
+    }
+
+    fn variant_with_alleles(a1: &str, a2: &str) -> Variant {
+        Variant::new(
+            "v".to_string(),
+            "1".to_string(),
+            1,
+            (a1.to_string(), a2.to_string())
+        )
+    }
+
+    #[test]
+    fn test_variant_kind() {
+        assert_eq!(variant_with_alleles("A", "G").kind(), VariantKind::Snp);
+        assert_eq!(variant_with_alleles("AC", "GT").kind(), VariantKind::Mnp);
+        assert_eq!(variant_with_alleles("A", "AT").kind(),
+                   VariantKind::Indel);
+        assert_eq!(variant_with_alleles("-", "T").kind(), VariantKind::Indel);
+        assert_eq!(variant_with_alleles("N", "T").kind(), VariantKind::Other);
+        assert_eq!(variant_with_alleles("A", "<DEL>").kind(),
+                   VariantKind::Symbolic);
+        assert_eq!(variant_with_alleles("*", "AT").kind(),
+                   VariantKind::Symbolic);
+    }
+
+    #[test]
+    fn test_normalize() {
+        struct Reference(&'static str);
+
+        impl ReferenceSequence for Reference {
+            fn bases(&mut self, _: &Chromosome, start: u32, end: u32)
+                -> Option<String>
+            {
+                self.0.get((start as usize - 1)..(end as usize))
+                    .map(|bases| bases.to_string())
+            }
+        }
+
+        let mut reference = Reference("GCACACAT");
+        let mut normalize = |position: u32, a1: &str, a2: &str| {
+            let mut v = Variant::new("v".to_string(), "1".to_string(),
+                                     position,
+                                     (a1.to_string(), a2.to_string()));
+            v.normalize(&mut reference).map(|changed| {
+                (changed, v.position, v.alleles.0, v.alleles.1)
+            })
+        };
+        let normalized = |position: u32, a1: &str, a2: &str| {
+            (position, a1.to_string(), a2.to_string())
+        };
+
+        // Deletion of a CA repeat unit.
+        let (changed, position, a1, a2) = normalize(5, "ACA", "A").unwrap();
+        assert!(changed);
+        assert_eq!((position, a1, a2), normalized(1, "G", "GCA"));
+
+        let (changed, ..) = normalize(1, "G", "GCA").unwrap();
+        assert!(!changed);
+
+        // Shared bases of an MNP.
+        let (_, position, a1, a2) = normalize(1, "GCA", "GTA").unwrap();
+        assert_eq!((position, a1, a2), normalized(2, "C", "T"));
+
+        assert_eq!(normalize(3, "A", "T"), Ok((false, 3, "A".to_string(),
+                                                "T".to_string())));
+        assert_eq!(normalize(1, "TT", "T"),
+                   Err(VariantError::ReferenceMismatch("1:1".to_string())));
+        assert_eq!(normalize(8, "TA", "T"),
+                   Err(VariantError::NotInReference("1:8".to_string())));
+        assert_eq!(normalize(1, "-", "T"),
+                   Err(VariantError::InvalidAllele("-".to_string())));
+    }
+
+    #[test]
+    fn test_transitions() {
+        assert!(variant_with_alleles("G", "A").is_transition());
+        assert!(variant_with_alleles("T", "C").is_transition());
+        assert!(variant_with_alleles("A", "C").is_transversion());
+        assert!(variant_with_alleles("G", "T").is_transversion());
+
+        let indel = variant_with_alleles("A", "AG");
+        assert!(!indel.is_transition() && !indel.is_transversion());
+    }
+
+    #[test]
+    fn test_variant_builder() {
+        let v = VariantBuilder::new()
+            .name("rs1")
+            .chrom("chr01")
+            .position_str("1234")
+            .alleles("g", "A")
+            .build()
+            .unwrap();
+
+        assert_eq!(v.chrom, Chromosome::Autosome(1));
+        assert_eq!(v.alleles, ("A".to_string(), "G".to_string()));
+        assert_eq!(v.position, 1234);
+
+        let indel = VariantBuilder::new()
+            .chrom("23")
+            .position(10)
+            .alleles("A", "ATT")
+            .build()
+            .unwrap();
+
+        assert_eq!(indel.chrom, Chromosome::X);
+        assert_eq!(indel.name, "");
+        assert_eq!(indel.kind(), VariantKind::Indel);
+        assert_eq!(v.kind(), VariantKind::Snp);
+
+        let sv = VariantBuilder::new()
+            .chrom("1")
+            .position(10)
+            .alleles("a", "<INS:ME>")
+            .build()
+            .unwrap();
+        assert_eq!(sv.kind(), VariantKind::Symbolic);
+        assert!(sv.alleles_set().contains("<INS:ME>"));
+    }
+
+    #[test]
+    fn test_variant_builder_errors() {
+        let base = || {
+            VariantBuilder::new().chrom("1").position(10).alleles("A", "G")
+        };
+
+        assert_eq!(base().position(0).build(),
+                   Err(VariantError::InvalidPosition("0".to_string())));
+        assert_eq!(base().position_str("12a").build(),
+                   Err(VariantError::InvalidPosition("12a".to_string())));
+        assert_eq!(base().chrom("chr").build(),
+                   Err(VariantError::InvalidChromosome("chr".to_string())));
+        assert_eq!(base().alleles("", "G").build(),
+                   Err(VariantError::InvalidAllele("".to_string())));
+        assert_eq!(base().alleles("A", "Z").build(),
+                   Err(VariantError::InvalidAllele("Z".to_string())));
+        assert_eq!(base().alleles("A", "<>").build(),
+                   Err(VariantError::InvalidAllele("<>".to_string())));
+        assert_eq!(base().alleles("a", "A").build(),
+                   Err(VariantError::IdenticalAlleles("A".to_string())));
+        assert_eq!(VariantBuilder::new().position(1).build(),
+                   Err(VariantError::MissingField("chrom")));
+    }
+
+    #[test]
+    fn test_variant_from_str() {
+        let v: Variant = "chr1:12345:a:G".parse().unwrap();
+        assert_eq!(v.chrom, Chromosome::Autosome(1));
+        assert_eq!(v.position, 12345);
+        assert_eq!(v.alleles, ("A".to_string(), "G".to_string()));
+        assert_eq!(v.name, "");
+        assert_eq!(v.to_string(), "chr1:12345:A:G");
+
+        let contig: Variant = "Un_gl000220_105_A_ATT".parse().unwrap();
+        assert_eq!(contig.chrom, Chromosome::Other("Un_gl000220".to_string()));
+        assert_eq!(contig.position, 105);
+        assert_eq!(contig.to_string().parse::<Variant>(), Ok(contig));
+        assert_eq!("23_10_C_T".parse::<Variant>().unwrap().to_string(),
+                   "chrX:10:C:T");
+
+        // Display and parsing round-trip, including on the other contigs.
+        for (chrom, a2) in [("1", "G"), ("chrX", "G"), ("GL000192.1", "C"),
+                            ("chrUn_gl000220", "ATT"), ("HLA-A*01:01", "C"),
+                            ("2", "<INS:ME>")]
+        {
+            let v = Variant::new(String::new(), chrom.to_string(), 100,
+                                 ("A".to_string(), a2.to_string()));
+            assert_eq!(v.to_string().parse::<Variant>(), Ok(v));
+        }
+        let contig: Variant = "chrGL000192.1:100:A:C".parse().unwrap();
+        assert_eq!(contig.to_string(), "GL000192.1:100:A:C");
+
+        let named: Variant = "rs12345".parse().unwrap();
+        assert!(!named.has_locus());
+        assert_eq!(named.to_string(), "rs12345");
+        assert!(named.identifies(&Variant::new(
+            "rs12345".to_string(), "1".to_string(), 12345,
+            ("A".to_string(), "G".to_string())
+        )));
+        assert!(!named.identifies(&v));
+        assert!(v.identifies(&"1_12345_G_A".parse().unwrap()));
+
+        // Names with underscores that aren't loci.
+        assert_eq!("AX_123".parse::<Variant>().unwrap().name, "AX_123");
+
+        assert_eq!("1:12345".parse::<Variant>(),
+                   Err(VariantError::MissingField("alleles")));
+        assert_eq!("1:12345:A:Z".parse::<Variant>(),
+                   Err(VariantError::InvalidAllele("Z".to_string())));
+        assert_eq!("".parse::<Variant>(),
+                   Err(VariantError::InvalidName("".to_string())));
+    }
+
+    #[test]
+    fn test_chromosome() {
+        assert_eq!(Chromosome::new("chr01"), Chromosome::Autosome(1));
+        assert_eq!(Chromosome::new("23"), Chromosome::X);
+        assert_eq!(Chromosome::new("chrM"), Chromosome::MT);
+        assert_eq!(Chromosome::new("chrUn_gl000220"),
+                   Chromosome::Other("Un_gl000220".to_string()));
+        assert_eq!(Chromosome::new("0").to_string(), "0");
+        assert_eq!(Chromosome::new("chr25").to_string(), "XY");
+        assert!("chr".parse::<Chromosome>().is_err());
+        assert!("chr1 2".parse::<Chromosome>().is_err());
+        assert_eq!("chrX".parse(), Ok(Chromosome::X));
+
+        // Parsing and `new` agree on the other contigs and on `0`.
+        for name in ["chrUn_gl000220", "GL000192.1", "HLA-A*01:01", "0",
+                     "00"]
+        {
+            assert_eq!(name.parse::<Chromosome>(), Ok(Chromosome::new(name)));
+        }
+        assert_eq!(Chromosome::new("00"), Chromosome::Other("0".to_string()));
+        assert_eq!(normalize_chromosome("chr023"), Ok("X".to_string()));
+        assert_eq!(normalize_chromosome("chrm"), Ok("MT".to_string()));
+
+        let mut chroms: Vec<Chromosome> = ["Y", "10", "GL000192.1", "X",
+                                           "2", "MT"]
+            .iter()
+            .map(|name| Chromosome::new(name))
+            .collect();
+        chroms.sort();
+        let names: Vec<String> = chroms.iter().map(|c| c.to_string())
+            .collect();
+        assert_eq!(names, vec!["2", "10", "X", "Y", "MT", "GL000192.1"]);
+
+        assert!(Chromosome::Autosome(22).is_autosome());
+        assert!(!Chromosome::XY.is_autosome() && !Chromosome::XY.is_haploid());
+    }
+
+    #[test]
+    fn test_genotypes_get() {
+        let g = get_genotypes();
+        assert_eq!(g.get(0), Some(Some(0)));
+        assert_eq!(g.get(1), Some(None));
+        assert_eq!(g.get(3), Some(Some(1)));
+        assert_eq!(g.get(4), None);
+    }
+
+    #[test]
+    fn test_genotypes_iter_with_samples() {
+        let g = get_genotypes();
+        let samples = vec!["s1", "s2", "s3", "s4"];
+
+        let pairs: Vec<(&&str, Option<u8>)> = g.iter_with_samples(&samples)
+            .collect();
+
+        assert_eq!(pairs[1], (&"s2", None));
+        assert_eq!(pairs[2], (&"s3", Some(2)));
+    }
+
+    #[test]
+    fn test_genotypes_subset() {
+        let g = get_genotypes();
+        let sub = g.subset(&[3, 0]);
+
+        assert_eq!(sub.genotypes, vec![Some(1), Some(0)]);
+        assert_eq!(sub.variant, g.variant);
+        assert_eq!(sub.coded_idx, g.coded_idx);
+        assert!(sub.samples.is_none());
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let v = Variant::new("rs1".to_string(), "1".to_string(), 100,
+                             ("A".to_string(), "G".to_string()));
+        let g = Genotypes::new(v.clone(), vec![Some(0), Some(1), None], "G");
+
+        assert_eq!(g.content_hash().len(), 64);
+        assert_eq!(g.content_hash(), g.clone().flip_coded_allele()
+                                       .content_hash());
+
+        let missing = Genotypes::new(v.clone(), vec![Some(0), Some(1),
+                                                     Some(2)], "G");
+        assert_ne!(g.content_hash(), missing.content_hash());
+
+        let reordered = Genotypes::new(v, vec![Some(1), Some(0), None], "G");
+        assert_ne!(g.content_hash(), reordered.content_hash());
+    }
+
+    #[test]
+    fn test_standardized() {
+        // p = 0.5, so the genotypes are centered by 1 and scaled by 1/sqrt(2).
+        let z = get_genotypes().standardized().unwrap();
+        let expected = [-1.0, 0.0, 1.0, 0.0].map(|x| x * 2_f64.sqrt());
+        for (obs, exp) in z.iter().zip(expected.iter()) {
+            assert!((obs - exp).abs() < 1e-12);
+        }
+
+        let v = get_genotypes().variant;
+        let monomorphic = Genotypes::new(v, vec![Some(0), None, Some(0)], "G");
+        assert_eq!(monomorphic.standardized(), None);
+    }
+
+    #[test]
+    fn test_missing_policy() {
+        let g = get_genotypes();
+        assert_eq!(g.impute_mean(), vec![0.0, 1.0, 2.0, 1.0]);
+        assert_eq!(g.values(MissingPolicy::Drop).unwrap(),
+                   vec![Some(0.0), None, Some(2.0), Some(1.0)]);
+        assert_eq!(g.values(MissingPolicy::MeanImpute).unwrap()[1], Some(1.0));
+
+        let err = g.values(MissingPolicy::Fail).unwrap_err();
+        assert_eq!(err.n_missing, 1);
+        assert_eq!(err.to_string(),
+                   "1 missing genotype(s) for the variant `chr1:1234:A:G`");
+
+        let missing = Genotypes::new(g.variant, vec![None, None], "G");
+        assert!(missing.impute_mean().iter().all(|x| x.is_nan()));
+        assert_eq!("mean".parse(), Ok(MissingPolicy::MeanImpute));
+    }
+
+    #[test]
+    fn test_flip_coded_allele() {
+        let g = get_genotypes();
+        let flipped = g.clone().flip_coded_allele();
+        assert_eq!(flipped.coded_allele(), g.other_allele());
+        assert_eq!(flipped.genotypes, vec![Some(2), None, Some(0), Some(1)]);
+
+        let mut twice = flipped.clone();
+        twice.flip_coded();
+        assert_eq!(twice, g);
+
+        let haploid = Genotypes::new(g.variant, vec![Some(1), None], "A")
+            .with_ploidy(1)
+            .flip_coded_allele();
+        assert_eq!(haploid.genotypes, vec![Some(0), None]);
+    }
+
+    #[test]
+    fn test_into_haploid() {
+        let g = get_genotypes().into_haploid(HaploidHets::SetMissing).unwrap();
+        assert!(g.is_haploid());
+        assert_eq!(g.genotypes, vec![Some(0), None, Some(1), None]);
+        assert_eq!(g.coded_freq(), 0.5);
+
+        let err = get_genotypes().into_haploid(HaploidHets::Error)
+            .unwrap_err();
+        assert_eq!(err.samples, vec![3]);
+    }
+
+    #[test]
+    fn test_dosages() {
+        let v = get_genotypes().variant;
+        let d = Dosages::new(v, vec![Some(0.1), Some(1.0), None, Some(1.6)],
+                             "G");
+
+        assert!((d.coded_freq() - 2.7 / 6.0).abs() < 1e-12);
+        assert_eq!(d.to_hard_calls(0.2).genotypes,
+                   vec![Some(0), Some(1), None, None]);
+
+        let g = get_genotypes();
+        let d = g.to_dosages();
+        assert_eq!(d.dosages, vec![Some(0.0), None, Some(2.0), Some(1.0)]);
+        assert_eq!(d.to_hard_calls(0.0), g);
+        assert!((d.info_score() - 4.0 / 3.0).abs() < 1e-12);
+        assert_eq!(d.with_info(0.8).info_score(), 0.8);
+
+        // Dosages equal to the mean carry no information.
+        let d = Dosages::new(get_genotypes().variant, vec![Some(0.5); 4], "G");
+        assert_eq!(d.info_score(), 0.0);
+    }
+
+    #[test]
+    fn test_split_multiallelic() {
+        let v = MultiAllelicVariant::new(
+            "rs1".to_string(), "X".to_string(), 100,
+            vec!["a".to_string(), "C".to_string(), "T".to_string()]
+        );
+        assert_eq!(v.alleles, vec!["A", "C", "T"]);
+        assert!(!v.is_biallelic());
+
+        // The haploid call is coded as homozygous.
+        let g = MultiAllelicGenotypes::new(
+            v, vec![Some(vec![0, 2]), Some(vec![1, 2]), Some(vec![2]), None]
+        );
+        let split = g.split();
+        assert_eq!(split.len(), 2);
+
+        assert_eq!(split[0].variant.alleles,
+                   ("A".to_string(), "C".to_string()));
+        assert_eq!(split[0].coded_allele(), "C");
+        assert_eq!(split[0].genotypes, vec![Some(0), Some(1), Some(0), None]);
+        assert_eq!(split[1].coded_allele(), "T");
+        assert_eq!(split[1].genotypes, vec![Some(1), Some(1), Some(2), None]);
+        assert!(!split[1].is_haploid());
+    }
+
+    #[test]
+    fn test_coded_frequency() {
+        let v = get_genotypes().variant;
+
+        // The allele counts don't overflow.
+        let g = Genotypes::new(v.clone(), vec![Some(2); 300], "G");
+        assert_eq!(g.coded_frequency(), Frequency::new(1.0).ok());
+
+        let g = Genotypes::new(v.clone(), vec![None, None], "G");
+        assert_eq!(g.coded_frequency(), None);
+        assert!(g.coded_freq().is_nan());
+
+        let g = Genotypes::new(v.clone(), vec![Some(1), Some(0), None], "G");
+        let with = |e| g.coded_frequency_with(e).map(f64::from);
+        assert_eq!(with(FrequencyEstimator::AllSamples), Some(1.0 / 6.0));
+        assert_eq!(with(FrequencyEstimator::PseudoCount(1.0)), Some(2.0 / 6.0));
+
+        let g = Genotypes::new(v, vec![Some(1), None], "G").with_ploidy(1);
+        let counts: Vec<Option<u8>> = g.allele_counts()
+            .map(|c| c.map(|c| c.to_dosage().get() as u8))
+            .collect();
+        assert_eq!(counts, vec![Some(2), None]);
+    }
+
+    #[test]
+    fn test_sample_from_fam() {
+        let s = Sample::from_fam_fields(&["f1", "s3", "s1", "0", "2", "1.5"])
+            .unwrap();
+        assert_eq!(s.father, Some("s1".to_string()));
+        assert_eq!(s.mother, None);
+        assert_eq!(s.sex, Sex::Female);
+        assert_eq!(s.phenotype, Some(1.5));
+        assert_eq!(s.to_fam_line(), "f1 s3 s1 0 2 1.5");
+
+        let s = Sample::from_fam_fields(&["f1", "s1"]).unwrap();
+        assert_eq!(s, Sample::new("f1".to_string(), "s1".to_string(),
+                                  Sex::Unknown));
+        assert_eq!(s.to_fam_line(), "f1 s1 0 0 0 -9");
+
+        assert!(Sample::from_fam_fields(&["f1"]).is_err());
+        assert!(Sample::from_fam_fields(&["f1", "s1", "0", "0", "1", "x"])
+                    .is_err());
+    }
+
+    #[test]
+    fn test_genotypes_subset_with_samples() {
+        let samples: Vec<Sample> = (1..=4)
+            .map(|i| Sample::new(format!("f{}", i), format!("s{}", i),
+                                 Sex::Unknown))
+            .collect();
+
+        let g = get_genotypes().with_samples(Arc::new(samples));
+        let sub = g.subset(&[3, 0]);
+
+        let iids: Vec<&str> = sub.samples.as_ref().unwrap()
+            .iter()
+            .map(|s| s.iid.as_str())
+            .collect();
+
+        assert_eq!(iids, vec!["s4", "s1"]);
+    }
+}
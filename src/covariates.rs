@@ -0,0 +1,161 @@
+/*!
+ * Technical covariates derived from the genotypes (call rate, heterozygosity,
+ * genotyping plate and principal components) for use in association models.
+ */
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+use ndarray::Array2;
+
+use crate::core::{Genotypes, Sample};
+use crate::grm::GrmAccumulator;
+use crate::pca::{self, Pcs};
+use crate::qc::QcReport;
+
+
+#[derive(Debug)]
+pub struct Covariates {
+    pub names: Vec<String>,
+    // One row per sample.
+    pub values: Array2<f64>
+}
+
+
+impl Covariates {
+    pub fn write<W: Write>(&self, out: &mut W, samples: &[Sample])
+        -> io::Result<()>
+    {
+        if samples.len() != self.values.rows() {
+            panic!("Got {} samples for {} rows of covariates.",
+                   samples.len(), self.values.rows());
+        }
+
+        writeln!(out, "FID\tIID\t{}", self.names.join("\t"))?;
+
+        for (sample, row) in samples.iter().zip(self.values.genrows()) {
+            let values: Vec<String> = row.iter()
+                .map(|x| x.to_string())
+                .collect();
+
+            writeln!(out, "{}\t{}\t{}", sample.fid, sample.iid,
+                     values.join("\t"))?;
+        }
+
+        Ok(())
+    }
+}
+
+
+// Build the covariate matrix from an existing QC report and optional plate
+// assignments and PCs. Plates are dummy coded using the first plate (in
+// lexicographic order) as the reference.
+pub fn technical_covariates(report: &QcReport, plates: Option<&[String]>,
+                            pcs: Option<&Pcs>) -> Covariates
+{
+    let n = report.samples.len();
+
+    let mut names = vec!["call_rate".to_string(), "het_rate".to_string()];
+    let mut columns: Vec<Vec<f64>> = vec![
+        report.samples.iter()
+            .map(|s| {
+                s.n_called() as f64 / (s.n_called() + s.n_missing) as f64
+            })
+            .collect(),
+        report.samples.iter()
+            .map(|s| s.n_het as f64 / s.n_called() as f64)
+            .collect()
+    ];
+
+    if let Some(plates) = plates {
+        assert_eq!(plates.len(), n, "Expected one plate per sample.");
+
+        let levels: BTreeSet<&String> = plates.iter().collect();
+        for level in levels.into_iter().skip(1) {
+            names.push(format!("plate_{}", level));
+            columns.push(plates.iter()
+                .map(|p| if p == level { 1.0 } else { 0.0 })
+                .collect());
+        }
+    }
+
+    if let Some(pcs) = pcs {
+        assert_eq!(pcs.vectors.rows(), n, "Expected PCs for every sample.");
+
+        for j in 0..pcs.n_components() {
+            names.push(format!("PC{}", j + 1));
+            columns.push(pcs.vectors.column(j).to_vec());
+        }
+    }
+
+    let values = Array2::from_shape_fn((n, columns.len()), |(i, j)| {
+        columns[j][i]
+    });
+
+    Covariates { names, values }
+}
+
+
+// Compute the QC metrics and the GRM in a single pass over the genotypes
+// and build the technical covariates (including the first `n_pcs` PCs).
+pub fn compute_technical_covariates<I>(genotypes: I, plates: Option<&[String]>,
+                                       n_pcs: usize) -> Covariates
+    where I: IntoIterator<Item = Genotypes>
+{
+    let mut report = QcReport::new();
+    let mut grm = GrmAccumulator::new();
+
+    for g in genotypes {
+        report.add(&g);
+
+        if n_pcs > 0 {
+            grm.add(&g);
+        }
+    }
+
+    let pcs = if n_pcs > 0 {
+        Some(pca::pcs_from_grm(&grm.finish(), n_pcs))
+    } else {
+        None
+    };
+
+    technical_covariates(&report, plates, pcs.as_ref())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::genotypes;
+
+    #[test]
+    fn test_technical_covariates() {
+        let plates: Vec<String> = ["p2", "p1", "p3", "p1"].iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let covar = compute_technical_covariates(
+            vec![
+                genotypes(1, vec![Some(0), Some(1), Some(2), None]),
+                genotypes(2, vec![Some(1), Some(1), Some(0), Some(2)]),
+                genotypes(3, vec![Some(2), Some(0), Some(1), Some(1)]),
+            ],
+            Some(&plates),
+            2
+        );
+
+        assert_eq!(covar.names, vec![
+            "call_rate", "het_rate", "plate_p2", "plate_p3", "PC1", "PC2"
+        ]);
+        assert_eq!(covar.values.dim(), (4, 6));
+
+        assert_eq!(covar.values[[3, 0]], 2.0 / 3.0);
+        assert_eq!(covar.values[[1, 1]], 2.0 / 3.0);
+        assert_eq!(covar.values.column(2).to_vec(), vec![1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(covar.values.column(3).to_vec(), vec![0.0, 0.0, 1.0, 0.0]);
+
+        // PCs are unit vectors.
+        let pc1 = covar.values.column(4);
+        assert!((pc1.dot(&pc1) - 1.0).abs() < 1e-10);
+    }
+}
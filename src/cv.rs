@@ -0,0 +1,218 @@
+/*!
+ * Sample splitting for cross-validation.
+ *
+ * Related samples (same family or kinship above a threshold) are always
+ * assigned to the same split to avoid leaking information between the
+ * training and testing sets.
+ */
+
+use std::collections::HashMap;
+
+use ndarray::Array2;
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha8Rng;
+
+use crate::core::Sample;
+
+
+// Pairs of sample indices with a kinship coefficient above the threshold.
+pub fn related_pairs(kinship: &Array2<f64>, threshold: f64)
+    -> Vec<(usize, usize)>
+{
+    let n = kinship.rows();
+    assert_eq!(n, kinship.cols(), "Expected a square kinship matrix.");
+
+    let mut pairs = Vec::new();
+    for i in 0..n {
+        for j in (i + 1)..n {
+            if kinship[[i, j]] > threshold {
+                pairs.push((i, j));
+            }
+        }
+    }
+
+    pairs
+}
+
+
+// Group samples that share a family ID or that are related. Every group is
+// a vector of sample indices.
+pub fn family_groups(samples: &[Sample], related: &[(usize, usize)])
+    -> Vec<Vec<usize>>
+{
+    let mut parents: Vec<usize> = (0..samples.len()).collect();
+
+    fn find(parents: &mut [usize], i: usize) -> usize {
+        let mut root = i;
+        while parents[root] != root {
+            root = parents[root];
+        }
+
+        // Path compression.
+        let mut cur = i;
+        while parents[cur] != root {
+            let next = parents[cur];
+            parents[cur] = root;
+            cur = next;
+        }
+
+        root
+    }
+
+    fn union(parents: &mut [usize], i: usize, j: usize) {
+        let (ri, rj) = (find(parents, i), find(parents, j));
+        if ri != rj {
+            parents[ri.max(rj)] = ri.min(rj);
+        }
+    }
+
+    let mut first_of_family: HashMap<&str, usize> = HashMap::new();
+    for (i, sample) in samples.iter().enumerate() {
+        match first_of_family.get(sample.fid.as_str()) {
+            Some(&j) => union(&mut parents, i, j),
+            None => { first_of_family.insert(&sample.fid, i); }
+        }
+    }
+
+    for &(i, j) in related {
+        union(&mut parents, i, j);
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..samples.len() {
+        let root = find(&mut parents, i);
+        groups.entry(root).or_default().push(i);
+    }
+
+    let mut groups: Vec<Vec<usize>> = groups.into_values().collect();
+    groups.sort();
+
+    groups
+}
+
+
+// Assign the groups to k folds of similar sizes. Returns the sample indices
+// in every fold.
+pub fn k_fold(groups: &[Vec<usize>], k: usize, seed: u64) -> Vec<Vec<usize>> {
+    assert!(k > 1, "Need at least two folds.");
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut shuffled: Vec<&Vec<usize>> = groups.iter().collect();
+    shuffled.shuffle(&mut rng);
+
+    // Large families are placed first so that they don't unbalance the
+    // folds (the sort is stable so the shuffled order is kept for ties).
+    shuffled.sort_by_key(|group| std::cmp::Reverse(group.len()));
+
+    let mut folds: Vec<Vec<usize>> = vec![Vec::new(); k];
+    for group in shuffled {
+        let smallest = folds.iter_mut()
+            .min_by_key(|fold| fold.len())
+            .unwrap();
+
+        smallest.extend(group);
+    }
+
+    for fold in folds.iter_mut() {
+        fold.sort();
+    }
+
+    folds
+}
+
+
+// Split the groups into training and testing sets where the testing set
+// contains approximately `test_fraction` of the samples.
+pub fn train_test_split(groups: &[Vec<usize>], test_fraction: f64, seed: u64)
+    -> (Vec<usize>, Vec<usize>)
+{
+    assert!(test_fraction > 0.0 && test_fraction < 1.0,
+            "The test fraction should be in (0, 1).");
+
+    let n: usize = groups.iter().map(|group| group.len()).sum();
+    let n_test = (test_fraction * n as f64).round() as usize;
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut shuffled: Vec<&Vec<usize>> = groups.iter().collect();
+    shuffled.shuffle(&mut rng);
+
+    let mut train = Vec::new();
+    let mut test = Vec::new();
+    for group in shuffled {
+        if test.len() < n_test {
+            test.extend(group);
+        } else {
+            train.extend(group);
+        }
+    }
+
+    train.sort();
+    test.sort();
+
+    (train, test)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Sex;
+
+    fn get_samples() -> Vec<Sample> {
+        ["f1", "f1", "f2", "f3", "f3", "f3", "f4", "f5", "f6", "f7"]
+            .iter()
+            .enumerate()
+            .map(|(i, fid)| Sample::new(fid.to_string(), format!("s{}", i),
+                                        Sex::Unknown))
+            .collect()
+    }
+
+    #[test]
+    fn test_family_groups() {
+        let mut kinship = Array2::zeros((10, 10));
+        kinship[[6, 9]] = 0.25;
+        kinship[[7, 8]] = 0.01;
+
+        let related = related_pairs(&kinship, 0.0442);
+        assert_eq!(related, vec![(6, 9)]);
+
+        let groups = family_groups(&get_samples(), &related);
+        assert_eq!(groups, vec![
+            vec![0, 1], vec![2], vec![3, 4, 5], vec![6, 9], vec![7], vec![8]
+        ]);
+    }
+
+    #[test]
+    fn test_k_fold() {
+        let groups = family_groups(&get_samples(), &[]);
+        let folds = k_fold(&groups, 3, 42);
+
+        assert_eq!(folds.len(), 3);
+        assert_eq!(folds.iter().map(|f| f.len()).sum::<usize>(), 10);
+
+        // Families are never split.
+        for group in groups.iter() {
+            let n_folds = folds.iter()
+                .filter(|fold| group.iter().any(|i| fold.contains(i)))
+                .count();
+            assert_eq!(n_folds, 1);
+        }
+
+        // The split is reproducible.
+        assert_eq!(folds, k_fold(&groups, 3, 42));
+    }
+
+    #[test]
+    fn test_train_test_split() {
+        let groups = family_groups(&get_samples(), &[]);
+        let (train, test) = train_test_split(&groups, 0.2, 1);
+
+        assert_eq!(train.len() + test.len(), 10);
+        assert!(test.len() >= 2);
+
+        let in_test = |i| test.contains(&i);
+        assert_eq!(in_test(0), in_test(1));
+        assert_eq!(in_test(3), in_test(5));
+    }
+}
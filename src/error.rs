@@ -0,0 +1,132 @@
+/*!
+ * Errors of the readers.
+ *
+ * The constructors of the plink readers (PlinkReader, BedReader, BimIndex,
+ * PgenReader and DelimitedVariantsReader) return a GenepaError instead of
+ * aborting on missing files, malformed lines or truncated BEDs, so that the
+ * errors can be handled by the caller (e.g. through the C API). Most
+ * problems are found when the readers are created (e.g. the size of the BED
+ * is checked against the BIM and FAM). The errors found while reading are
+ * returned by `try_next` (on PlinkReader, FilteredVariants and the VCF
+ * readers), while the iterators over genotypes panic on them. Likewise,
+ * the queries of the PlinkReader (by locus, name or region, and
+ * `count_if`) and `BedReader::read_variants` panic on the errors returned by
+ * their `try_` version (e.g. tabix failing or a duplicated variant).
+ */
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+
+#[derive(Debug)]
+pub enum GenepaError {
+    // A file could not be opened or read.
+    Io { path: String, error: io::Error },
+    // A line (1-based) of a text file could not be parsed.
+    Parse { path: String, line: usize, message: String },
+    // The BED has no magic number or its size doesn't match the fileset.
+    InvalidBed { path: String, message: String },
+    // The components of a plink fileset don't describe the same variants.
+    InconsistentFileset {
+        prefix: String,
+        n_bim: u32,
+        n_indexed: u32,
+        n_bed: u32,
+        n_samples: u32
+    },
+    // The BIM index could not be created or read.
+    Index { path: String, message: String },
+    // A variant (or variant name) queried by its locus or name is
+    // duplicated in the BIM.
+    DuplicateVariant { path: String, variant: String },
+    // The PGEN has no magic number or doesn't match the PVAR and PSAM.
+    InvalidPgen { path: String, message: String },
+    // The storage mode of the PGEN can't be decoded (e.g. the variable-width
+    // mode 0x10 or the dosages).
+    UnsupportedPgenMode { path: String, mode: u8 }
+}
+
+impl GenepaError {
+    pub(crate) fn io(path: &str, error: io::Error) -> GenepaError {
+        GenepaError::Io { path: path.to_string(), error }
+    }
+
+    pub(crate) fn parse(path: &str, line: usize, message: &str)
+        -> GenepaError
+    {
+        GenepaError::Parse {
+            path: path.to_string(), line, message: message.to_string()
+        }
+    }
+
+    pub(crate) fn invalid_bed(path: &str, message: &str) -> GenepaError {
+        GenepaError::InvalidBed {
+            path: path.to_string(), message: message.to_string()
+        }
+    }
+
+    pub(crate) fn invalid_pgen(path: &str, message: &str) -> GenepaError {
+        GenepaError::InvalidPgen {
+            path: path.to_string(), message: message.to_string()
+        }
+    }
+
+    pub(crate) fn index(path: &str, message: &str) -> GenepaError {
+        GenepaError::Index {
+            path: path.to_string(), message: message.to_string()
+        }
+    }
+}
+
+impl fmt::Display for GenepaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GenepaError::Io { path, error } =>
+                write!(f, "Could not read `{}`: {}", path, error),
+            GenepaError::Parse { path, line, message } =>
+                write!(f, "Invalid line {} of `{}`: {}", line, path, message),
+            GenepaError::InvalidBed { path, message } =>
+                write!(f, "Invalid BED `{}`: {}", path, message),
+            GenepaError::InconsistentFileset {
+                prefix, n_bim, n_indexed, n_bed, n_samples
+            } =>
+                write!(f, "Inconsistent fileset `{}`: the BIM has {} \
+                           variants, the BIM index has {} and the BED has {} \
+                           (given {} samples in the FAM).", prefix, n_bim,
+                       n_indexed, n_bed, n_samples),
+            GenepaError::Index { path, message } =>
+                write!(f, "Could not use the BIM index `{}`: {}", path,
+                       message),
+            GenepaError::DuplicateVariant { path, variant } =>
+                write!(f, "There are duplicate variants `{}` in `{}`.",
+                       variant, path),
+            GenepaError::InvalidPgen { path, message } =>
+                write!(f, "Invalid PGEN `{}`: {}", path, message),
+            GenepaError::UnsupportedPgenMode { path, mode } =>
+                write!(f, "Unsupported storage mode {:#04x} of the PGEN `{}` \
+                           (only the fixed-width hardcall modes 0x01 and \
+                           0x02 are supported).", mode, path)
+        }
+    }
+}
+
+impl Error for GenepaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GenepaError::Io { error, .. } => Some(error),
+            _ => None
+        }
+    }
+}
+
+// For the functions returning io::Result (e.g. the writers).
+impl From<GenepaError> for io::Error {
+    fn from(e: GenepaError) -> io::Error {
+        let kind = match &e {
+            GenepaError::Io { error, .. } => error.kind(),
+            _ => io::ErrorKind::InvalidData
+        };
+        io::Error::new(kind, e.to_string())
+    }
+}
@@ -0,0 +1,316 @@
+/*!
+ * Exports under data sharing constraints.
+ *
+ * Aggregate exports only contain summary statistics and suppress the values
+ * derived from fewer than `min_cell_size` observations (small cell
+ * suppression). Individual level exports can strip the sample IDs and
+ * shuffle the order of the samples.
+ *
+ * Genotypes can also be written in a long format, with one row per sample
+ * and variant, to be loaded in databases or R without reshaping a matrix.
+ */
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha8Rng;
+
+use crate::core::{Genotypes, Sample};
+use crate::utils;
+use crate::vcf::vcf_sample_id;
+
+
+// Counts between 1 and min_cell_size - 1 are suppressed.
+fn is_small_cell(count: u64, min_cell_size: u64) -> bool {
+    count > 0 && count < min_cell_size
+}
+
+
+fn format_count(count: u64, min_cell_size: u64) -> String {
+    if is_small_cell(count, min_cell_size) {
+        format!("<{}", min_cell_size)
+    } else {
+        count.to_string()
+    }
+}
+
+
+// Number of copies of the minor allele.
+fn minor_allele_count(g: &Genotypes) -> u64 {
+    let (n_called, n_coded) = g.genotypes.iter()
+        .flatten()
+        .fold((0, 0), |(n, c), x| (n + 1, c + u64::from(*x)));
+
+    n_coded.min(u64::from(g.ploidy()) * n_called - n_coded)
+}
+
+
+// Write the genotype counts and the coded allele frequency of every variant.
+// The frequency is suppressed if the minor allele count is a small cell.
+pub fn write_aggregate_counts<W, I>(out: &mut W, genotypes: I,
+                                   min_cell_size: u64) -> io::Result<()>
+    where W: Write, I: IntoIterator<Item = Genotypes>
+{
+    writeln!(out, "variant\tchrom\tpos\tcoded\tother\tn_hom_other\tn_het\t\
+                   n_hom_coded\tn_missing\tcoded_freq")?;
+
+    for g in genotypes {
+        let mut counts = [0; 3];
+        let mut n_missing = 0;
+        for geno in g.genotypes.iter() {
+            match geno {
+                Some(x) => counts[usize::from(*x)] += 1,
+                None => n_missing += 1
+            }
+        }
+
+        // Haploid calls are counted as homozygous.
+        if g.is_haploid() {
+            counts.swap(1, 2);
+        }
+
+        let freq = if is_small_cell(minor_allele_count(&g), min_cell_size) {
+            "NA".to_string()
+        } else {
+            format!("{:.6}", g.coded_freq())
+        };
+
+        writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                 g.variant.name, g.variant.chrom, g.variant.position,
+                 g.coded_allele(), g.other_allele(),
+                 format_count(counts[0], min_cell_size),
+                 format_count(counts[1], min_cell_size),
+                 format_count(counts[2], min_cell_size),
+                 format_count(n_missing, min_cell_size), freq)?;
+    }
+
+    Ok(())
+}
+
+
+// Write the LD (r²) between a variant and other variants. The value is
+// suppressed if either variant has a minor allele count that is a small
+// cell.
+pub fn write_aggregate_ld<W: Write>(out: &mut W, g: Genotypes,
+                                    others: Vec<Genotypes>,
+                                    min_cell_size: u64) -> io::Result<()>
+{
+    writeln!(out, "variant_a\tvariant_b\tr2")?;
+
+    let small_a = is_small_cell(minor_allele_count(&g), min_cell_size);
+    let names: Vec<(String, bool)> = others.iter()
+        .map(|other| {
+            let small = is_small_cell(minor_allele_count(other),
+                                      min_cell_size);
+            (other.variant.name.clone(), small)
+        })
+        .collect();
+
+    let name_a = g.variant.name.clone();
+    let r2 = utils::compute_ld(g, others, true);
+
+    for ((name_b, small_b), r2) in names.into_iter().zip(r2) {
+        if small_a || small_b {
+            writeln!(out, "{}\t{}\tNA", name_a, name_b)?;
+        } else {
+            writeln!(out, "{}\t{}\t{:.6}", name_a, name_b, r2)?;
+        }
+    }
+
+    Ok(())
+}
+
+
+// Write the genotypes with one row per sample and variant: the sample
+// (named as in VCF files), the variant, the coded allele and the number of
+// copies of the coded allele (NA if missing). The variants are typically
+// the results of a region query or of a list of variants. Returns the
+// number of rows.
+pub fn write_long_format<W, I>(out: &mut W, samples: &[Sample], genotypes: I)
+    -> io::Result<u64>
+    where W: Write, I: IntoIterator<Item = Genotypes>
+{
+    writeln!(out, "sample_id\tvariant\tchrom\tpos\tcoded\tgenotype")?;
+
+    let ids: Vec<String> = samples.iter().map(vcf_sample_id).collect();
+    let mut n_rows = 0;
+
+    for g in genotypes {
+        if g.genotypes.len() != ids.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Got {} genotypes for {} samples.",
+                        g.genotypes.len(), ids.len())
+            ));
+        }
+
+        let v = &g.variant;
+        for (id, call) in ids.iter().zip(g.genotypes.iter()) {
+            let call = call.map_or("NA".to_string(), |x| x.to_string());
+            writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}", id, v.name, v.chrom,
+                     v.position, g.coded_allele(), call)?;
+        }
+
+        n_rows += ids.len() as u64;
+    }
+
+    Ok(n_rows)
+}
+
+
+// Options for individual level exports.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Anonymization {
+    // Replace the sample IDs by sequential IDs (in the exported order).
+    pub strip_ids: bool,
+    // Shuffle the order of the samples using this seed.
+    pub shuffle_seed: Option<u64>
+}
+
+
+pub struct AnonymizedSamples {
+    pub samples: Arc<Vec<Sample>>,
+    // Index of the original sample for every exported sample.
+    pub order: Vec<usize>
+}
+
+
+impl Anonymization {
+    pub fn apply(&self, samples: &[Sample]) -> AnonymizedSamples {
+        let mut order: Vec<usize> = (0..samples.len()).collect();
+
+        if let Some(seed) = self.shuffle_seed {
+            order.shuffle(&mut ChaCha8Rng::seed_from_u64(seed));
+        }
+
+        let exported = order.iter()
+            .enumerate()
+            .map(|(i, &j)| {
+                if self.strip_ids {
+                    let id = format!("ID{}", i + 1);
+                    Sample::new(id.clone(), id, samples[j].sex)
+                } else {
+                    samples[j].clone()
+                }
+            })
+            .collect();
+
+        AnonymizedSamples { samples: Arc::new(exported), order }
+    }
+}
+
+
+impl AnonymizedSamples {
+    // Reorder the genotypes to match the exported samples. Attached samples
+    // are replaced so that the original IDs can't leak.
+    pub fn genotypes<'a, I>(&'a self, genotypes: I)
+        -> impl Iterator<Item = Genotypes> + 'a
+        where I: IntoIterator<Item = Genotypes>, I::IntoIter: 'a
+    {
+        genotypes.into_iter().map(move |g| {
+            let mut g = g.subset(&self.order);
+            if g.samples.is_some() {
+                g.samples = Some(Arc::clone(&self.samples));
+            }
+
+            g
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::genotypes;
+    use crate::core::Sex;
+
+    #[test]
+    fn test_aggregate_counts() {
+        let mut out = Vec::new();
+        write_aggregate_counts(&mut out, vec![
+            genotypes(1, vec![Some(0), Some(0), Some(1), Some(2), None]),
+            genotypes(2, vec![Some(0), Some(0), Some(0), Some(1), Some(0)]),
+        ], 2).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert_eq!(lines[1], "v1\t1\t1\tG\tA\t2\t<2\t<2\t<2\t0.375000");
+        assert_eq!(lines[2], "v2\t1\t2\tG\tA\t4\t<2\t0\t0\tNA");
+    }
+
+    #[test]
+    fn test_aggregate_ld() {
+        let mut out = Vec::new();
+        write_aggregate_ld(
+            &mut out,
+            genotypes(1, vec![Some(0), Some(1), Some(2), Some(1)]),
+            vec![genotypes(2, vec![Some(0), Some(1), Some(2), Some(1)]),
+                 genotypes(3, vec![Some(0), Some(0), Some(0), Some(1)])],
+            2
+        ).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "variant_a\tvariant_b\tr2\n\
+                         v1\tv2\t1.000000\n\
+                         v1\tv3\tNA\n");
+    }
+
+    #[test]
+    fn test_long_format() {
+        let samples: Vec<Sample> = ["s1", "s2"].iter()
+            .map(|id| Sample::new(id.to_string(), id.to_string(), Sex::Unknown))
+            .collect();
+
+        let mut out = Vec::new();
+        let n = write_long_format(&mut out, &samples, vec![
+            genotypes(10, vec![Some(2), None]),
+            genotypes(20, vec![Some(0), Some(1)])
+        ]).unwrap();
+        assert_eq!(n, 4);
+
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "sample_id\tvariant\tchrom\tpos\tcoded\tgenotype\n\
+                    s1\tv10\t1\t10\tG\t2\n\
+                    s2\tv10\t1\t10\tG\tNA\n\
+                    s1\tv20\t1\t20\tG\t0\n\
+                    s2\tv20\t1\t20\tG\t1\n");
+
+        let mut out = Vec::new();
+        assert!(write_long_format(&mut out, &samples[..1], vec![
+            genotypes(10, vec![Some(2), None])
+        ]).is_err());
+    }
+
+    #[test]
+    fn test_anonymization() {
+        let samples: Vec<Sample> = (0..4)
+            .map(|i| Sample::new(format!("f{}", i), format!("s{}", i),
+                                 Sex::Unknown))
+            .collect();
+
+        let options = Anonymization { strip_ids: true, shuffle_seed: Some(1) };
+        let anonymized = options.apply(&samples);
+
+        let iids: Vec<&str> = anonymized.samples.iter()
+            .map(|s| s.iid.as_str())
+            .collect();
+        assert_eq!(iids, vec!["ID1", "ID2", "ID3", "ID4"]);
+
+        let g = genotypes(1, vec![Some(0), Some(1), Some(2), None])
+            .with_samples(Arc::new(samples));
+
+        let exported: Vec<Genotypes> = anonymized.genotypes(vec![g])
+            .collect();
+
+        let expected: Vec<Option<u8>> = anonymized.order.iter()
+            .map(|&i| [Some(0), Some(1), Some(2), None][i])
+            .collect();
+
+        assert_eq!(exported[0].genotypes, expected);
+        assert_eq!(exported[0].samples.as_ref().unwrap()[0].iid, "ID1");
+    }
+}
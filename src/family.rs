@@ -0,0 +1,429 @@
+/*!
+ * Family data: trios from the FAM pedigree, phasing by transmission,
+ * transmission disequilibrium test (TDT) and within-family association.
+ *
+ * The trios are the samples with both parents among the samples (the 3rd
+ * and 4th columns of the FAM, in the same family). The calls of a child are
+ * phased when the alleles transmitted by the father and the mother can be
+ * found from the genotypes of the trio, i.e. unless the child and both
+ * parents are heterozygous. The phased haplotypes are stored with the
+ * paternal allele first, so that the phase is consistent across the
+ * variants.
+ *
+ * The TDT counts the transmissions of the coded allele (T) and of the other
+ * allele (U) from the heterozygous parents to the children, using the trios
+ * where the three members are called and consistent with Mendelian
+ * inheritance (as plink `--tdt`).
+ *
+ * The within-family association test is a linear regression with family
+ * fixed effects: the genotypes, phenotype and covariates are centered within
+ * every family (FID), so that the estimate only uses the differences between
+ * relatives (e.g. siblings) and isn't biased by population stratification.
+ *
+ * The genotypes are assumed to be autosomal (diploid for every member of
+ * the trio).
+ */
+
+use std::collections::{BTreeMap, HashMap};
+
+use ndarray::{Array1, Array2};
+
+use crate::assoc::{linear_regression, AssocResult, Estimate};
+use crate::core::{Genotypes, Haplotypes, Sample};
+use crate::stats::{binomial_test_half, chi2_sf, student_t_two_sided};
+
+
+// Indices of the members of a trio in the samples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Trio {
+    pub child: usize,
+    pub father: usize,
+    pub mother: usize
+}
+
+
+// Trios of the samples (e.g. from the FAM, see `PlinkReader::samples`).
+pub fn find_trios(samples: &[Sample]) -> Vec<Trio> {
+    let index: HashMap<(&str, &str), usize> = samples.iter()
+        .enumerate()
+        .map(|(i, s)| ((s.fid.as_str(), s.iid.as_str()), i))
+        .collect();
+
+    let parent = |fid: &str, iid: &Option<String>| {
+        index.get(&(fid, iid.as_deref()?)).copied()
+    };
+
+    samples.iter()
+        .enumerate()
+        .filter_map(|(child, s)| {
+            Some(Trio {
+                child,
+                father: parent(&s.fid, &s.father)?,
+                mother: parent(&s.fid, &s.mother)?
+            })
+        })
+        .collect()
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrioPhase {
+    // Alleles (copies of the coded allele) transmitted by the father and
+    // the mother.
+    Phased(u8, u8),
+    // The transmitted alleles can't be found (e.g. the child and both
+    // parents are heterozygous).
+    Ambiguous,
+    // The genotypes are inconsistent with Mendelian inheritance.
+    MendelianError,
+    // The call of the child is missing.
+    Missing
+}
+
+
+// Can a parent (None if missing) transmit an allele (copies of the coded
+// allele)?
+fn can_transmit(parent: Option<u8>, allele: u8) -> bool {
+    match parent {
+        Some(p) if allele == 1 => p > 0,
+        Some(p) => p < 2,
+        None => true
+    }
+}
+
+
+// Phase the call of a child given the calls of the parents (the parents can
+// be missing).
+pub fn phase_trio(child: Option<u8>, father: Option<u8>,
+                  mother: Option<u8>) -> TrioPhase
+{
+    let child = match child {
+        Some(child) => child,
+        None => return TrioPhase::Missing
+    };
+
+    let transmissions: Vec<(u8, u8)> = [(0, 0), (0, 1), (1, 0), (1, 1)]
+        .iter()
+        .copied()
+        .filter(|&(paternal, maternal)| {
+            paternal + maternal == child &&
+                can_transmit(father, paternal) &&
+                can_transmit(mother, maternal)
+        })
+        .collect();
+
+    match transmissions[..] {
+        [] => TrioPhase::MendelianError,
+        [(paternal, maternal)] => TrioPhase::Phased(paternal, maternal),
+        _ => TrioPhase::Ambiguous
+    }
+}
+
+
+// Haplotypes of the children (paternal allele first). The calls of the
+// other samples, and those of the children that can't be phased, are None.
+pub fn phase_by_transmission(g: &Genotypes, trios: &[Trio]) -> Haplotypes {
+    let mut haplotypes = vec![None; g.genotypes.len()];
+
+    if !g.is_haploid() {
+        for trio in trios {
+            let phase = phase_trio(g.genotypes[trio.child],
+                                   g.genotypes[trio.father],
+                                   g.genotypes[trio.mother]);
+
+            if let TrioPhase::Phased(paternal, maternal) = phase {
+                haplotypes[trio.child] = Some((paternal, maternal));
+            }
+        }
+    }
+
+    let mut h = Haplotypes::new(g.variant.clone(), haplotypes,
+                                g.coded_allele());
+    h.samples = g.samples.clone();
+    h
+}
+
+
+// Indices of the trios with genotypes inconsistent with Mendelian
+// inheritance.
+pub fn mendelian_errors(g: &Genotypes, trios: &[Trio]) -> Vec<usize> {
+    trios.iter()
+        .enumerate()
+        .filter(|(_, trio)| {
+            phase_trio(g.genotypes[trio.child], g.genotypes[trio.father],
+                       g.genotypes[trio.mother]) == TrioPhase::MendelianError
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct TdtResult {
+    // Transmissions of the coded allele and of the other allele from the
+    // heterozygous parents.
+    pub transmitted: u64,
+    pub untransmitted: u64,
+    // McNemar chi-squared statistic (1 df) with its p-value and the exact
+    // (binomial) p-value. The statistics are NaN without transmissions.
+    pub chisq: f64,
+    pub p: f64,
+    pub exact_p: f64
+}
+
+impl TdtResult {
+    // Odds ratio of the transmission of the coded allele (T / U).
+    pub fn odds_ratio(&self) -> f64 {
+        self.transmitted as f64 / self.untransmitted as f64
+    }
+}
+
+
+// Transmissions of the coded allele and of the other allele (none for
+// haploid variants).
+fn count_transmissions(g: &Genotypes, trios: &[Trio]) -> (u64, u64) {
+    let (mut transmitted, mut untransmitted) = (0, 0);
+    if g.is_haploid() {
+        return (0, 0);
+    }
+
+    for trio in trios {
+        let (father, mother) = match (g.genotypes[trio.father],
+                                      g.genotypes[trio.mother]) {
+            (Some(father), Some(mother)) => (father, mother),
+            _ => continue
+        };
+
+        match phase_trio(g.genotypes[trio.child], Some(father), Some(mother)) {
+            TrioPhase::Phased(paternal, maternal) => {
+                for (parent, allele) in [(father, paternal), (mother, maternal)]
+                {
+                    match (parent, allele) {
+                        (1, 1) => transmitted += 1,
+                        (1, _) => untransmitted += 1,
+                        _ => {}
+                    }
+                }
+            },
+            // Both parents and the child are heterozygous: each allele was
+            // transmitted once.
+            TrioPhase::Ambiguous => {
+                transmitted += 1;
+                untransmitted += 1;
+            },
+            TrioPhase::MendelianError | TrioPhase::Missing => {}
+        }
+    }
+
+    (transmitted, untransmitted)
+}
+
+
+pub fn tdt(g: &Genotypes, trios: &[Trio]) -> TdtResult {
+    let (transmitted, untransmitted) = count_transmissions(g, trios);
+
+    let n = transmitted + untransmitted;
+    let (chisq, p, exact_p) = if n == 0 {
+        (f64::NAN, f64::NAN, f64::NAN)
+    } else {
+        let diff = transmitted as f64 - untransmitted as f64;
+        let chisq = diff * diff / n as f64;
+        (chisq, chi2_sf(chisq, 1.0), binomial_test_half(transmitted, n))
+    };
+
+    TdtResult { transmitted, untransmitted, chisq, p, exact_p }
+}
+
+
+// Index of the family (FID) of every sample, in the order of appearance.
+pub fn family_indices(samples: &[Sample]) -> Vec<usize> {
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    samples.iter()
+        .map(|sample| {
+            let n = index.len();
+            *index.entry(sample.fid.as_str()).or_insert(n)
+        })
+        .collect()
+}
+
+
+// Within-family test of the association with a quantitative phenotype given
+// the covariates (see `test_association`) and the family of every sample
+// (see `family_indices`). The families with a single observed sample aren't
+// informative and aren't counted in the observations.
+pub fn test_within_family(g: &Genotypes, phenotype: &[Option<f64>],
+                          covariates: &[Vec<Option<f64>>], families: &[usize])
+    -> AssocResult
+{
+    // Genotype, covariates and phenotype of the observed samples, by family.
+    let mut observed: BTreeMap<usize, Vec<Vec<f64>>> = BTreeMap::new();
+    for (i, (geno, pheno)) in g.genotypes.iter()
+        .zip(phenotype.iter())
+        .enumerate()
+    {
+        let covars: Option<Vec<f64>> = covariates.iter()
+            .map(|c| c[i])
+            .collect();
+
+        if let (Some(geno), Some(pheno), Some(covars)) = (geno, pheno,
+                                                          covars)
+        {
+            let mut row = vec![f64::from(*geno)];
+            row.extend(covars);
+            row.push(*pheno);
+            observed.entry(families[i]).or_default().push(row);
+        }
+    }
+
+    let p = 1 + covariates.len();
+    let mut rows: Vec<f64> = Vec::new();
+    let mut y: Vec<f64> = Vec::new();
+    let mut n_families = 0;
+
+    for members in observed.values().filter(|members| members.len() > 1) {
+        let means: Vec<f64> = (0..=p)
+            .map(|j| {
+                members.iter().map(|row| row[j]).sum::<f64>() /
+                    members.len() as f64
+            })
+            .collect();
+
+        for row in members {
+            rows.extend((0..p).map(|j| row[j] - means[j]));
+            y.push(row[p] - means[p]);
+        }
+        n_families += 1;
+    }
+
+    let n_obs = y.len();
+    if n_obs <= p + n_families {
+        return AssocResult { n_obs, estimate: None };
+    }
+
+    let x = Array2::from_shape_vec((n_obs, p), rows).unwrap();
+    let y = Array1::from_vec(y);
+
+    // The family means are also estimated, so the standard error is
+    // corrected for the degrees of freedom.
+    let df = (n_obs - p - n_families) as f64;
+    let estimate = linear_regression(&x, &y).and_then(|(beta, se)| {
+        let beta = beta[0];
+        let se = se[0] * ((n_obs - p) as f64 / df).sqrt();
+        if !se.is_finite() || se <= 0.0 {
+            return None;
+        }
+
+        let stat = beta / se;
+        Some(Estimate { beta, se, stat, p: student_t_two_sided(stat, df) })
+    });
+
+    AssocResult { n_obs, estimate }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Sex, Variant};
+    use crate::plink::read_fam_from_reader;
+
+    #[test]
+    fn test_phase_trio() {
+        assert_eq!(phase_trio(Some(1), Some(2), Some(1)),
+                   TrioPhase::Phased(1, 0));
+        assert_eq!(phase_trio(Some(1), Some(1), Some(2)),
+                   TrioPhase::Phased(0, 1));
+        assert_eq!(phase_trio(Some(1), None, Some(0)),
+                   TrioPhase::Phased(1, 0));
+        assert_eq!(phase_trio(Some(2), Some(1), None),
+                   TrioPhase::Phased(1, 1));
+        assert_eq!(phase_trio(Some(1), Some(1), Some(1)),
+                   TrioPhase::Ambiguous);
+        assert_eq!(phase_trio(Some(1), None, None), TrioPhase::Ambiguous);
+        assert_eq!(phase_trio(Some(2), Some(0), Some(1)),
+                   TrioPhase::MendelianError);
+        assert_eq!(phase_trio(None, Some(0), Some(0)), TrioPhase::Missing);
+    }
+
+    #[test]
+    fn test_phase_by_transmission() {
+        let fam = "f1 dad 0 0 1 -9\n\
+                   f1 mom 0 0 2 -9\n\
+                   f1 kid dad mom 1 2\n\
+                   f2 kid dad mom 2 2\n\
+                   f2 sib kid 0 2 2\n";
+        let trios = find_trios(&read_fam_from_reader(fam.as_bytes()));
+        assert_eq!(trios, vec![Trio { child: 2, father: 0, mother: 1 }]);
+
+        let v = Variant::new("rs1".to_string(), "1".to_string(), 100,
+                             ("A".to_string(), "G".to_string()));
+        let g = Genotypes::new(
+            v, vec![Some(0), Some(1), Some(1), Some(1), None], "G"
+        );
+
+        let h = phase_by_transmission(&g, &trios);
+        assert_eq!(h.haplotypes, vec![None, None, Some((0, 1)), None, None]);
+        assert_eq!(h.n_phased(), 1);
+        assert_eq!(h.to_genotypes().genotypes,
+                   vec![None, None, Some(1), None, None]);
+        assert!(mendelian_errors(&g, &trios).is_empty());
+    }
+
+    #[test]
+    fn test_tdt() {
+        let v = Variant::new("rs1".to_string(), "1".to_string(), 100,
+                             ("A".to_string(), "G".to_string()));
+        let trios: Vec<Trio> = (0..5_usize)
+            .map(|i| Trio { child: 3 * i, father: 3 * i + 1,
+                            mother: 3 * i + 2 })
+            .collect();
+
+        // Child, father and mother: het child of het parents / G from both
+        // het parents / het child of het parents / A from the het father
+        // and G from the hom mother / Mendelian error.
+        let calls = vec![Some(1), Some(1), Some(1),
+                         Some(2), Some(1), Some(1),
+                         Some(1), Some(1), Some(1),
+                         Some(1), Some(1), Some(2),
+                         Some(2), Some(0), Some(0)];
+        let g = Genotypes::new(v, calls, "G");
+
+        let result = tdt(&g, &trios);
+        assert_eq!((result.transmitted, result.untransmitted), (4, 3));
+        assert!((result.chisq - 1.0 / 7.0).abs() < 1e-12);
+        assert!((result.odds_ratio() - 4.0 / 3.0).abs() < 1e-12);
+        assert_eq!(result.exact_p, 1.0);
+        assert_eq!(mendelian_errors(&g, &trios), vec![4]);
+
+        let result = tdt(&g, &trios[4..]);
+        assert_eq!(result.transmitted + result.untransmitted, 0);
+        assert!(result.p.is_nan());
+    }
+
+    #[test]
+    fn test_within_family_association() {
+        let samples: Vec<Sample> = ["a", "a", "a", "b", "b", "b", "c"].iter()
+            .enumerate()
+            .map(|(i, fid)| Sample::new(fid.to_string(), format!("s{}", i),
+                                        Sex::Unknown))
+            .collect();
+        let families = family_indices(&samples);
+        assert_eq!(families, vec![0, 0, 0, 1, 1, 1, 2]);
+
+        // The families have different means (stratification) and the
+        // singleton isn't informative.
+        let v = Variant::new("rs1".to_string(), "1".to_string(), 100,
+                             ("A".to_string(), "G".to_string()));
+        let g = Genotypes::new(v, vec![Some(0), Some(1), Some(2), Some(1),
+                                       Some(2), Some(0), Some(2)], "G");
+        let y = vec![Some(10.0), Some(11.1), Some(11.9), Some(21.0),
+                     Some(21.9), Some(20.1), Some(50.0)];
+
+        let result = test_within_family(&g, &y, &[], &families);
+        assert_eq!(result.n_obs, 6);
+
+        let estimate = result.estimate.unwrap();
+        assert!((estimate.beta - 0.925).abs() < 1e-12);
+        assert!(estimate.p < 0.01);
+    }
+}
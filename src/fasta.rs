@@ -0,0 +1,261 @@
+/*!
+ * Access to the reference genome in an indexed FASTA file.
+ *
+ * The FASTA must be uncompressed and indexed with `samtools faidx` (the
+ * `.fai` file next to it). Only the requested bases are read, so that large
+ * references don't have to be loaded in memory. The contigs are matched on
+ * their normalized chromosome (e.g. `chr1` and `1`).
+ *
+ * `FastaReader::check` compares the alleles of a variant to the reference
+ * before strand harmonization: variants with none of their alleles in the
+ * reference on either strand are likely errors (e.g. wrong build). The
+ * reader is also a ReferenceSequence, to normalize the indels (see
+ * `Variant::normalize`).
+ */
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+
+use crate::core::{Chromosome, ReferenceSequence, Variant, complement,
+                  normalize_chromosome};
+
+
+// Record of the FASTA index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FaiEntry {
+    pub name: String,
+    pub length: u64,
+    // Offset of the first base of the sequence in the FASTA.
+    pub offset: u64,
+    pub line_bases: u64,
+    // Number of bytes per line, including the end of line.
+    pub line_width: u64
+}
+
+impl FaiEntry {
+    // Offset of a (0-based) position in the FASTA.
+    fn offset_of(&self, position: u64) -> u64 {
+        self.offset + position / self.line_bases * self.line_width +
+            position % self.line_bases
+    }
+}
+
+
+// Result of the comparison of the alleles of a variant to the reference.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RefCheck {
+    // One of the alleles is the reference.
+    Match,
+    // One of the complemented alleles is the reference.
+    StrandFlip,
+    // None of the alleles is the reference on either strand.
+    Mismatch,
+    // The locus isn't in the reference, the reference base is unknown (`N`)
+    // or the alleles can't be compared (e.g. `-` or `I`/`D`).
+    Unknown
+}
+
+
+fn is_nucleotides(allele: &str) -> bool {
+    !allele.is_empty() && allele.chars().all(|c| "ACGT".contains(c))
+}
+
+
+pub struct FastaReader<R> {
+    reader: R,
+    // Entries by normalized chromosome.
+    index: HashMap<String, FaiEntry>
+}
+
+impl FastaReader<BufReader<File>> {
+    pub fn new(filename: &str) -> FastaReader<BufReader<File>> {
+        let fai = format!("{}.fai", filename);
+        let fai = File::open(&fai).unwrap_or_else(|_| {
+            panic!("The FASTA `{}` must be indexed with samtools faidx.",
+                   filename)
+        });
+        let fasta = File::open(filename)
+            .unwrap_or_else(|_| panic!("Could not open `{}`", filename));
+
+        FastaReader::from_readers(BufReader::new(fasta), BufReader::new(fai))
+    }
+}
+
+impl<R: Read + Seek> FastaReader<R> {
+    pub fn from_readers<F: BufRead>(fasta: R, fai: F) -> FastaReader<R> {
+        let mut index = HashMap::new();
+
+        for (i, line) in fai.lines().enumerate() {
+            let line = line.expect("Could not read the FASTA index.");
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 5 {
+                panic!("Expected 5 columns on line {} of the FASTA index, got \
+                        {}.", i + 1, fields.len());
+            }
+
+            let number = |field: &str| -> u64 {
+                field.parse().unwrap_or_else(|_| {
+                    panic!("Invalid number `{}` on line {} of the FASTA \
+                            index.", field, i + 1)
+                })
+            };
+
+            let entry = FaiEntry {
+                name: fields[0].to_string(),
+                length: number(fields[1]),
+                offset: number(fields[2]),
+                line_bases: number(fields[3]),
+                line_width: number(fields[4])
+            };
+
+            // Contigs that aren't chromosomes are kept as is.
+            let chrom = normalize_chromosome(&entry.name)
+                .unwrap_or_else(|_| entry.name.clone());
+            index.entry(chrom).or_insert(entry);
+        }
+
+        FastaReader { reader: fasta, index }
+    }
+
+    pub fn contigs(&self) -> Vec<&FaiEntry> {
+        let mut contigs: Vec<&FaiEntry> = self.index.values().collect();
+        contigs.sort_by_key(|entry| entry.offset);
+        contigs
+    }
+
+    // Uppercase sequence from `start` to `end` (1-based and inclusive, like
+    // the region queries). None if the contig isn't in the FASTA or if the
+    // region is outside of it.
+    pub fn fetch(&mut self, chrom: &str, start: u32, end: u32)
+        -> Option<String>
+    {
+        let chrom = normalize_chromosome(chrom)
+            .unwrap_or_else(|_| chrom.to_string());
+        let entry = self.index.get(&chrom)?;
+
+        let (start, end) = (u64::from(start), u64::from(end));
+        if start == 0 || start > end || end > entry.length {
+            return None;
+        }
+
+        let first = entry.offset_of(start - 1);
+        let last = entry.offset_of(end - 1);
+        let mut buffer = vec![0; (last - first + 1) as usize];
+        self.reader.seek(SeekFrom::Start(first))
+            .and_then(|_| self.reader.read_exact(&mut buffer))
+            .expect("Could not read the FASTA.");
+
+        Some(buffer.into_iter()
+            .filter(|&c| c != b'\n' && c != b'\r')
+            .map(|c| char::from(c.to_ascii_uppercase()))
+            .collect())
+    }
+
+    // Compare the alleles of a variant to the reference. The alleles are
+    // compared to the reference sequence starting at the position of the
+    // variant (like the VCF). Ambiguous variants (A/T and C/G) always match
+    // if one of their alleles is the reference.
+    pub fn check(&mut self, v: &Variant) -> RefCheck {
+        let alleles = [&v.alleles.0, &v.alleles.1];
+        let mut found = (false, false);
+        let mut compared = false;
+
+        for allele in alleles.iter().filter(|a| is_nucleotides(a)) {
+            let end = v.position + allele.len() as u32 - 1;
+            let chrom = v.chrom.to_string();
+            let reference = match self.fetch(&chrom, v.position, end) {
+                Some(reference) => reference,
+                None => continue
+            };
+            if reference.starts_with('N') {
+                return RefCheck::Unknown;
+            }
+
+            compared = true;
+            found.0 |= **allele == reference;
+            found.1 |= complement(allele) == reference;
+        }
+
+        match found {
+            (true, _) => RefCheck::Match,
+            (false, true) => RefCheck::StrandFlip,
+            _ if compared => RefCheck::Mismatch,
+            _ => RefCheck::Unknown
+        }
+    }
+}
+
+
+impl<R: Read + Seek> ReferenceSequence for FastaReader<R> {
+    fn bases(&mut self, chrom: &Chromosome, start: u32, end: u32)
+        -> Option<String>
+    {
+        self.fetch(&chrom.to_string(), start, end)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Lines of 4 bases (samtools faidx on this FASTA).
+    const FASTA: &str = ">chr1 description\nACGT\nTTGA\nC\n>chrUn_1\nNNAC\n";
+    const FAI: &str = "chr1\t9\t18\t4\t5\nchrUn_1\t4\t39\t4\t5\n";
+
+    fn reader() -> FastaReader<Cursor<&'static [u8]>> {
+        FastaReader::from_readers(Cursor::new(FASTA.as_bytes()),
+                                  FAI.as_bytes())
+    }
+
+    #[test]
+    fn test_fetch() {
+        let mut fasta = reader();
+        assert_eq!(fasta.contigs().len(), 2);
+
+        assert_eq!(fasta.fetch("1", 1, 1).as_deref(), Some("A"));
+        assert_eq!(fasta.fetch("chr1", 3, 9).as_deref(), Some("GTTTGAC"));
+        assert_eq!(fasta.fetch("1", 8, 8).as_deref(), Some("A"));
+        assert_eq!(fasta.fetch("chrUn_1", 3, 4).as_deref(), Some("AC"));
+
+        assert_eq!(fasta.fetch("1", 9, 10), None);
+        assert_eq!(fasta.fetch("1", 0, 1), None);
+        assert_eq!(fasta.fetch("2", 1, 1), None);
+    }
+
+    #[test]
+    fn test_check() {
+        let mut fasta = reader();
+        let mut check = |position, a1: &str, a2: &str| {
+            let v = Variant::new("v".to_string(), "1".to_string(), position,
+                                 (a1.to_string(), a2.to_string()));
+            fasta.check(&v)
+        };
+
+        assert_eq!(check(2, "C", "T"), RefCheck::Match);
+        assert_eq!(check(2, "G", "A"), RefCheck::StrandFlip);
+        assert_eq!(check(2, "A", "T"), RefCheck::Mismatch);
+
+        // Indels.
+        assert_eq!(check(4, "TT", "T"), RefCheck::Match);
+        assert_eq!(check(6, "TG", "C"), RefCheck::Match);
+        assert_eq!(check(4, "GA", "G"), RefCheck::Mismatch);
+        assert_eq!(check(4, "-", "T"), RefCheck::Match);
+        assert_eq!(check(4, "I", "D"), RefCheck::Unknown);
+
+        // Outside of the reference.
+        assert_eq!(check(20, "A", "G"), RefCheck::Unknown);
+    }
+
+    #[test]
+    fn test_normalize_on_fasta() {
+        // Deletion of a T of the TTT at positions 4 to 6.
+        let mut v = Variant::new("v".to_string(), "chr1".to_string(), 6,
+                                 ("TG".to_string(), "G".to_string()));
+        assert_eq!(v.normalize(&mut reader()), Ok(true));
+        assert_eq!((v.position, v.alleles.0.as_str(), v.alleles.1.as_str()),
+                   (3, "G", "GT"));
+    }
+}
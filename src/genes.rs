@@ -0,0 +1,256 @@
+/*!
+ * Gene models from GTF or GFF3 files.
+ *
+ * Only the `gene` features are read. In GTF files, the genes are named by
+ * the `gene_name` attribute (or `gene_id`); in GFF3 files, by the `Name`
+ * attribute (or `gene_name` or `ID`). The format is guessed from the
+ * attributes of every line, so both can be read with the same functions.
+ * The chromosomes are normalized (e.g. `chr19` is `19`) like the variants
+ * of the readers.
+ *
+ * `get_variants_in_gene` translates a gene symbol into region queries on a
+ * GenotypeSource, e.g.:
+ *     let genes = GeneModel::new("gencode.v44.annotation.gtf.gz");
+ *     let apoe = get_variants_in_gene(&mut reader, &genes, "APOE", 10_000);
+ */
+
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use crate::core::{Chromosome, Genotypes, normalize_chromosome};
+use crate::source::GenotypeSource;
+use crate::utils::open_text_file;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gene {
+    pub id: String,
+    pub name: String,
+    pub chrom: Chromosome,
+    // 1-based and inclusive, like the region queries.
+    pub start: u32,
+    pub end: u32,
+    pub strand: char
+}
+
+impl Gene {
+    // Region of the gene extended by `flank` bases on both sides.
+    pub fn region(&self, flank: u32) -> (Chromosome, u32, u32) {
+        (self.chrom.clone(), self.start.saturating_sub(flank).max(1),
+         self.end.saturating_add(flank))
+    }
+}
+
+
+// Value of an attribute of a GTF (`key "value";`) or GFF3 (`key=value;`)
+// line.
+fn attribute<'a>(attributes: &'a str, key: &str) -> Option<&'a str> {
+    attributes.split(';')
+        .map(|entry| entry.trim())
+        .find_map(|entry| {
+            let value = entry.strip_prefix(key)?;
+            match value.strip_prefix('=') {
+                Some(value) => Some(value),
+                None if value.starts_with(' ') => {
+                    Some(value.trim().trim_matches('"'))
+                },
+                None => None
+            }
+        })
+}
+
+
+#[derive(Debug, Default)]
+pub struct GeneModel {
+    genes: Vec<Gene>,
+    // Indices of the genes by name and by ID.
+    index: HashMap<String, Vec<usize>>
+}
+
+impl GeneModel {
+    // The file can be gzipped.
+    pub fn new(filename: &str) -> GeneModel {
+        GeneModel::from_reader(open_text_file(filename))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> GeneModel {
+        let mut model = GeneModel::default();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.expect("Could not read the gene model.");
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 9 {
+                panic!("Expected 9 columns on line {} of the gene model, got \
+                        {}.", i + 1, fields.len());
+            }
+
+            if fields[2] != "gene" {
+                continue;
+            }
+
+            let position = |field: &str| -> u32 {
+                field.parse().unwrap_or_else(|_| {
+                    panic!("Invalid position `{}` on line {} of the gene \
+                            model.", field, i + 1)
+                })
+            };
+
+            // Contigs that aren't chromosomes (e.g. `chrUn_KI270742v1`) are
+            // kept as is.
+            let chrom = normalize_chromosome(fields[0])
+                .unwrap_or_else(|_| fields[0].to_string());
+
+            let id = ["gene_id", "ID"].iter()
+                .find_map(|key| attribute(fields[8], key))
+                .unwrap_or_else(|| {
+                    panic!("No gene ID on line {} of the gene model.", i + 1)
+                });
+            let name = ["gene_name", "Name"].iter()
+                .find_map(|key| attribute(fields[8], key))
+                .unwrap_or(id);
+
+            model.push(Gene {
+                id: id.to_string(),
+                name: name.to_string(),
+                chrom: Chromosome::new(&chrom),
+                start: position(fields[3]),
+                end: position(fields[4]),
+                strand: fields[6].chars().next().unwrap_or('.')
+            });
+        }
+
+        model
+    }
+
+    pub fn push(&mut self, gene: Gene) {
+        let idx = self.genes.len();
+        self.index.entry(gene.name.clone()).or_default().push(idx);
+        if gene.id != gene.name {
+            self.index.entry(gene.id.clone()).or_default().push(idx);
+        }
+
+        self.genes.push(gene);
+    }
+
+    pub fn genes(&self) -> &[Gene] {
+        &self.genes
+    }
+
+    pub fn len(&self) -> usize {
+        self.genes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.genes.is_empty()
+    }
+
+    // Genes with a name or ID (there can be more than one, e.g. in the
+    // pseudoautosomal regions).
+    pub fn get(&self, name: &str) -> Vec<&Gene> {
+        self.index.get(name)
+            .map(|indices| indices.iter().map(|&i| &self.genes[i]).collect())
+            .unwrap_or_default()
+    }
+}
+
+
+// Variants in a gene (by name or ID) and its flanking regions, None if the
+// gene isn't in the model. Regions of genes with the same name are queried
+// separately and the variants in more than one of them are only returned
+// once.
+pub fn get_variants_in_gene<S>(reader: &mut S, genes: &GeneModel, name: &str,
+                               flank: u32) -> Option<Vec<Genotypes>>
+    where S: GenotypeSource + ?Sized
+{
+    let matches = genes.get(name);
+    if matches.is_empty() {
+        return None;
+    }
+
+    let mut genotypes: Vec<Genotypes> = Vec::new();
+    for gene in matches {
+        let (chrom, start, end) = gene.region(flank);
+        for g in reader.get_variants_in_region(&chrom, start, end) {
+            if !genotypes.iter().any(|other| other.variant == g.variant) {
+                genotypes.push(g);
+            }
+        }
+    }
+
+    Some(genotypes)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gene_model() {
+        let gtf = "##format: gtf\n\
+                   chr19\tHAVANA\tgene\t44905791\t44909393\t.\t+\t.\t\
+                   gene_id \"ENSG00000130203.10\"; gene_type \
+                   \"protein_coding\"; gene_name \"APOE\";\n\
+                   chr19\tHAVANA\texon\t44905791\t44905856\t.\t+\t.\t\
+                   gene_id \"ENSG00000130203.10\"; gene_name \"APOE\";\n\
+                   X\tRefSeq\tgene\t100\t200\t.\t-\t.\t\
+                   ID=gene-PAR1;Name=PAR1;gene_biotype=protein_coding\n\
+                   chrY\tRefSeq\tgene\t1100\t1200\t.\t-\t.\tID=gene-PAR1_Y;\
+                   Name=PAR1\n";
+
+        let genes = GeneModel::from_reader(gtf.as_bytes());
+        assert_eq!(genes.len(), 3);
+
+        let apoe = genes.get("APOE");
+        assert_eq!(apoe.len(), 1);
+        assert_eq!(apoe[0].chrom, Chromosome::Autosome(19));
+        assert_eq!(apoe[0].id, "ENSG00000130203.10");
+        assert_eq!(genes.get("ENSG00000130203.10"), apoe);
+
+        let par = genes.get("PAR1");
+        assert_eq!(par.len(), 2);
+        assert_eq!(par[1].chrom, Chromosome::Y);
+        assert_eq!(par[0].strand, '-');
+        assert_eq!(par[0].region(150),
+                   (Chromosome::X, 1, 350));
+
+        assert!(genes.get("gene_id").is_empty());
+    }
+
+    #[test]
+    fn test_get_variants_in_gene() {
+        use crate::raw::RawReader;
+
+        let raw = "FID IID PAT MAT SEX PHENOTYPE rs1_A rs2_A rs3_A\n\
+                   f1 s1 0 0 1 -9 0 1 2\n";
+        let bim = "19\trs1\t0\t44905000\tA\tG\n\
+                   19\trs2\t0\t44909500\tA\tG\n\
+                   19\trs3\t0\t44950000\tA\tG\n";
+        let mut reader = RawReader::from_readers(raw.as_bytes(),
+                                                 Some(bim.as_bytes()));
+
+        let mut genes = GeneModel::default();
+        genes.push(Gene {
+            id: "ENSG00000130203".to_string(),
+            name: "APOE".to_string(),
+            chrom: Chromosome::Autosome(19),
+            start: 44905791,
+            end: 44909393,
+            strand: '+'
+        });
+
+        let mut names = |flank| -> Option<Vec<String>> {
+            get_variants_in_gene(&mut reader, &genes, "APOE", flank)
+                .map(|all| all.into_iter().map(|g| g.variant.name).collect())
+        };
+        assert_eq!(names(0), Some(vec![]));
+        assert_eq!(names(1000), Some(vec!["rs1".to_string(),
+                                          "rs2".to_string()]));
+        assert!(get_variants_in_gene(&mut reader, &genes, "TOMM40", 0)
+                .is_none());
+    }
+}
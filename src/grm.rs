@@ -0,0 +1,526 @@
+/*!
+ * Genetic relationship matrix (GRM).
+ *
+ * The GRM is the average over variants of the products of standardized
+ * genotypes between pairs of samples. Missing genotypes are set to the mean
+ * (i.e. 0 after standardization).
+ *
+ * The SNP-heritability of a quantitative phenotype can be estimated from the
+ * GRM by Haseman-Elston regression: the products of the standardized
+ * phenotypes of the pairs of samples are regressed on their relatedness, and
+ * the slope is the heritability explained by the variants of the GRM.
+ *
+ * For sample sizes where the n x n GRM doesn't fit in memory, `write_grm`
+ * computes it by bands of rows and writes it to disk in the GCTA binary
+ * format, where `DiskGrm` reads the relatedness of the pairs on demand. The
+ * memory is bounded by the band, but every band is a pass over all the
+ * genotypes: n / band_rows passes for n samples, so the bands should be as
+ * large as the memory allows.
+ */
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom,
+              Write};
+use std::mem;
+use std::ops::Range;
+
+use ndarray::{Array2, s};
+
+use crate::core::{Genotypes, Sample};
+
+
+// Number of variants that are standardized before updating the sums with a
+// single matrix product.
+pub(crate) const BLOCK_SIZE: usize = 256;
+
+
+// Append the standardized genotypes of the variant to the block (missing
+// genotypes are set to 0). Monomorphic variants are skipped, in which case
+// false is returned.
+pub(crate) fn standardize_into(g: &Genotypes, block: &mut Vec<f64>) -> bool {
+    match g.standardized() {
+        Some(z) => {
+            block.extend(z);
+            true
+        },
+        None => false
+    }
+}
+
+
+#[derive(Default)]
+pub struct GrmAccumulator {
+    sums: Option<Array2<f64>>,
+    block: Vec<f64>,
+    n_block: usize,
+    n_samples: usize,
+    n_variants: u64
+}
+
+
+impl GrmAccumulator {
+    pub fn new() -> GrmAccumulator {
+        GrmAccumulator::default()
+    }
+
+    // Add a variant to the GRM. Monomorphic variants are skipped, in which
+    // case false is returned.
+    pub fn add(&mut self, g: &Genotypes) -> bool {
+        if self.sums.is_none() {
+            self.n_samples = g.genotypes.len();
+            self.sums = Some(Array2::zeros((self.n_samples, self.n_samples)));
+        }
+
+        if g.genotypes.len() != self.n_samples {
+            panic!("Expected {} samples but `{}` has {} genotypes.",
+                   self.n_samples, g.variant, g.genotypes.len());
+        }
+
+        if !standardize_into(g, &mut self.block) {
+            return false;
+        }
+
+        self.n_block += 1;
+        self.n_variants += 1;
+
+        if self.n_block == BLOCK_SIZE {
+            self.flush();
+        }
+
+        true
+    }
+
+    fn flush(&mut self) {
+        if self.n_block == 0 {
+            return;
+        }
+
+        let block = Array2::from_shape_vec(
+            (self.n_block, self.n_samples),
+            std::mem::take(&mut self.block)
+        ).unwrap();
+
+        let sums = self.sums.as_mut().unwrap();
+        *sums += &block.t().dot(&block);
+
+        self.n_block = 0;
+    }
+
+    pub fn n_variants(&self) -> u64 {
+        self.n_variants
+    }
+
+    pub fn finish(mut self) -> Array2<f64> {
+        self.flush();
+
+        match self.sums {
+            Some(sums) if self.n_variants > 0 => sums / self.n_variants as f64,
+            _ => panic!("Can't compute a GRM without polymorphic variants.")
+        }
+    }
+}
+
+
+pub fn compute_grm<I>(genotypes: I) -> Array2<f64>
+    where I: IntoIterator<Item = Genotypes>
+{
+    let mut acc = GrmAccumulator::new();
+    for g in genotypes {
+        acc.add(&g);
+    }
+
+    acc.finish()
+}
+
+
+// Out-of-core GRM. The lower triangle is computed by bands of `band_rows`
+// rows, with a pass over the genotypes per band, and every band is appended
+// to the files as soon as it is done, so only the sums of a band (at most
+// `band_rows` x n) are kept in memory. `genotypes` is called at the start of
+// every pass and must give the same variants every time (e.g. by opening the
+// reader again). The files use the GCTA binary format: `{prefix}.grm.bin`
+// (the lower triangle with the diagonal, row by row, as f32),
+// `{prefix}.grm.N.bin` (the number of variants of every pair) and
+// `{prefix}.grm.id` (FID and IID). Inconsistent genotypes between the
+// passes and GRMs without polymorphic variants are InvalidData errors.
+pub fn write_grm<F, I>(mut genotypes: F, samples: &[Sample], prefix: &str,
+                       band_rows: usize) -> io::Result<DiskGrm>
+    where F: FnMut() -> I,
+          I: IntoIterator<Item = Genotypes>
+{
+    assert!(band_rows > 0, "Expected at least 1 row per band.");
+
+    let n = samples.len();
+    let create = |extension: &str| {
+        File::create(format!("{}.{}", prefix, extension)).map(BufWriter::new)
+    };
+    let mut bin = create("grm.bin")?;
+    let mut counts = create("grm.N.bin")?;
+
+    let mut n_variants = None;
+    for start in (0..n).step_by(band_rows) {
+        let rows = start..(start + band_rows).min(n);
+        let (sums, n_band) = band_sums(genotypes(), n, rows.clone())?;
+
+        match n_variants {
+            Some(m) if m != n_band => {
+                return Err(invalid_data(format!(
+                    "Expected {} variants at every pass but got {}.", m,
+                    n_band
+                )));
+            },
+            _ => n_variants = Some(n_band)
+        }
+
+        if n_band == 0 {
+            return Err(invalid_data(
+                "Can't compute a GRM without polymorphic variants."
+                    .to_string()
+            ));
+        }
+
+        let count = (n_band as f32).to_le_bytes();
+        for (i, row) in rows.zip(sums.outer_iter()) {
+            for x in row.slice(s![..i + 1]).iter() {
+                bin.write_all(&((x / n_band as f64) as f32).to_le_bytes())?;
+                counts.write_all(&count)?;
+            }
+        }
+    }
+
+    bin.flush()?;
+    counts.flush()?;
+
+    let mut ids = create("grm.id")?;
+    for s in samples {
+        writeln!(ids, "{}\t{}", s.fid, s.iid)?;
+    }
+    ids.flush()?;
+
+    DiskGrm::open(prefix)
+}
+
+
+// Sums of the products of the standardized genotypes between the samples of
+// the rows and the samples up to the end of the rows (the lower triangle of
+// the band), and the number of polymorphic variants.
+fn band_sums<I>(genotypes: I, n_samples: usize, rows: Range<usize>)
+    -> io::Result<(Array2<f64>, u64)>
+    where I: IntoIterator<Item = Genotypes>
+{
+    let mut sums = Array2::zeros((rows.len(), rows.end));
+    let mut block = Vec::new();
+    let mut n_block = 0;
+    let mut n_variants = 0;
+
+    let mut add_block = |block: &mut Vec<f64>, n_block: usize| {
+        let x = Array2::from_shape_vec((n_block, n_samples), mem::take(block))
+            .unwrap();
+        sums += &x.slice(s![.., rows.start..rows.end]).t()
+            .dot(&x.slice(s![.., ..rows.end]));
+    };
+
+    for g in genotypes {
+        if g.genotypes.len() != n_samples {
+            return Err(invalid_data(format!(
+                "Expected {} samples but `{}` has {} genotypes.", n_samples,
+                g.variant, g.genotypes.len()
+            )));
+        }
+
+        if !standardize_into(&g, &mut block) {
+            continue;
+        }
+
+        n_block += 1;
+        n_variants += 1;
+
+        if n_block == BLOCK_SIZE {
+            add_block(&mut block, n_block);
+            n_block = 0;
+        }
+    }
+
+    if n_block > 0 {
+        add_block(&mut block, n_block);
+    }
+
+    Ok((sums, n_variants))
+}
+
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+// GRM in the GCTA binary format (see `write_grm`), read from the disk on
+// demand.
+pub struct DiskGrm {
+    file: BufReader<File>,
+    n_samples: usize
+}
+
+
+impl DiskGrm {
+    // The number of samples is the number of lines of `{prefix}.grm.id`.
+    pub fn open(prefix: &str) -> io::Result<DiskGrm> {
+        let ids = File::open(format!("{}.grm.id", prefix))?;
+        let n_samples = BufReader::new(ids).lines()
+            .collect::<io::Result<Vec<String>>>()?
+            .len();
+
+        let file = File::open(format!("{}.grm.bin", prefix))?;
+        let expected = (n_samples * (n_samples + 1) / 2 * 4) as u64;
+        if file.metadata()?.len() != expected {
+            return Err(invalid_data(format!(
+                "Expected {} bytes in `{}.grm.bin` for {} samples.", expected,
+                prefix, n_samples
+            )));
+        }
+
+        Ok(DiskGrm { file: BufReader::new(file), n_samples })
+    }
+
+    pub fn n_samples(&self) -> usize {
+        self.n_samples
+    }
+
+    // Relatedness of samples i and j (in any order).
+    pub fn get(&mut self, i: usize, j: usize) -> io::Result<f64> {
+        assert!(i < self.n_samples && j < self.n_samples,
+                "Sample index out of range.");
+
+        let (i, j) = if i >= j { (i, j) } else { (j, i) };
+        let offset = (i * (i + 1) / 2 + j) * 4;
+        self.file.seek(SeekFrom::Start(offset as u64))?;
+        self.read_value()
+    }
+
+    // Pairs of distinct samples (i > j) with a relatedness of at least
+    // `min_relatedness`, in a single pass over the file.
+    pub fn related_pairs(&mut self, min_relatedness: f64)
+        -> io::Result<Vec<(usize, usize, f64)>>
+    {
+        self.file.seek(SeekFrom::Start(0))?;
+
+        let mut pairs = Vec::new();
+        for i in 0..self.n_samples {
+            for j in 0..=i {
+                let value = self.read_value()?;
+                if j < i && value >= min_relatedness {
+                    pairs.push((i, j, value));
+                }
+            }
+        }
+
+        Ok(pairs)
+    }
+
+    // The whole GRM (for the sample sizes where it fits in memory).
+    pub fn to_array(&mut self) -> io::Result<Array2<f64>> {
+        self.file.seek(SeekFrom::Start(0))?;
+
+        let n = self.n_samples;
+        let mut grm = Array2::zeros((n, n));
+        for i in 0..n {
+            for j in 0..=i {
+                let value = self.read_value()?;
+                grm[[i, j]] = value;
+                grm[[j, i]] = value;
+            }
+        }
+
+        Ok(grm)
+    }
+
+    fn read_value(&mut self) -> io::Result<f64> {
+        let mut buf = [0; 4];
+        self.file.read_exact(&mut buf)?;
+        Ok(f64::from(f32::from_le_bytes(buf)))
+    }
+}
+
+
+// Haseman-Elston estimate of the heritability.
+#[derive(Clone, Copy, Debug)]
+pub struct HeEstimate {
+    pub h2: f64,
+    // Standard error of the regression, which treats the pairs as
+    // independent (it is anticonservative).
+    pub se: f64,
+    pub n_samples: usize,
+    pub n_pairs: u64
+}
+
+
+// Haseman-Elston (cross-product) regression using the samples with a
+// phenotype. Returns None if there are less than 3 samples or if the
+// phenotype or the relatedness is constant.
+pub fn haseman_elston(grm: &Array2<f64>, phenotype: &[Option<f64>])
+    -> Option<HeEstimate>
+{
+    if grm.rows() != phenotype.len() || grm.cols() != phenotype.len() {
+        panic!("Expected a {0}x{0} GRM for {0} phenotypes.",
+               phenotype.len());
+    }
+
+    let observed: Vec<(usize, f64)> = phenotype.iter()
+        .enumerate()
+        .filter_map(|(i, y)| Some((i, (*y)?)))
+        .collect();
+
+    let n = observed.len();
+    if n < 3 {
+        return None;
+    }
+
+    let mean = observed.iter().map(|(_, y)| y).sum::<f64>() / n as f64;
+    let sd = (observed.iter().map(|(_, y)| (y - mean).powi(2)).sum::<f64>() /
+              (n - 1) as f64).sqrt();
+    if sd == 0.0 {
+        return None;
+    }
+
+    let z: Vec<(usize, f64)> = observed.iter()
+        .map(|&(i, y)| (i, (y - mean) / sd))
+        .collect();
+
+    // Sums for the regression of z_i z_j on A_ij over the pairs.
+    let (mut sx, mut sy, mut sxx, mut sxy, mut syy) = (0.0, 0.0, 0.0, 0.0,
+                                                       0.0);
+    for (k, &(i, zi)) in z.iter().enumerate() {
+        for &(j, zj) in z[k + 1..].iter() {
+            let (x, y) = (grm[[i, j]], zi * zj);
+            sx += x;
+            sy += y;
+            sxx += x * x;
+            sxy += x * y;
+            syy += y * y;
+        }
+    }
+
+    let m = (n * (n - 1) / 2) as f64;
+    let ssx = sxx - sx * sx / m;
+    if ssx <= 0.0 {
+        return None;
+    }
+
+    let h2 = (sxy - sx * sy / m) / ssx;
+    let intercept = (sy - h2 * sx) / m;
+    let rss = (syy - intercept * sy - h2 * sxy).max(0.0);
+    let se = (rss / (m - 2.0) / ssx).sqrt();
+
+    Some(HeEstimate { h2, se, n_samples: n, n_pairs: m as u64 })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::genotypes;
+
+    #[test]
+    fn test_grm() {
+        let grm = compute_grm(vec![
+            genotypes(1, vec![Some(0), Some(1), Some(2), Some(1)]),
+            genotypes(2, vec![Some(0), Some(0), Some(2), None]),
+            // Monomorphic, skipped.
+            genotypes(3, vec![Some(0), Some(0), Some(0), Some(0)]),
+        ]);
+
+        // First variant: p = 0.5, z = [-1.41, 0, 1.41, 0]
+        // Second variant: p = 1/3, z = [-1.15, -1.15, 2.31, 0]
+        let z1 = [-2.0_f64.sqrt(), 0.0, 2.0_f64.sqrt(), 0.0];
+        let sd2 = (4.0_f64 / 9.0).sqrt();
+        let z2 = [-2.0 / 3.0 / sd2, -2.0 / 3.0 / sd2, 4.0 / 3.0 / sd2, 0.0];
+
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = (z1[i] * z1[j] + z2[i] * z2[j]) / 2.0;
+                assert!((grm[[i, j]] - expected).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_grm() {
+        let calls = [
+            vec![Some(0), Some(1), Some(2), Some(1), Some(0)],
+            vec![Some(0), Some(0), Some(2), None, Some(1)],
+            vec![Some(2), Some(1), Some(1), Some(0), Some(0)],
+            vec![Some(0), Some(0), Some(0), Some(0), Some(0)]
+        ];
+        let data: Vec<Genotypes> = calls.iter()
+            .enumerate()
+            .map(|(i, calls)| genotypes(i as u32 + 1, calls.clone()))
+            .collect();
+        let samples: Vec<Sample> = (1..=5)
+            .map(|i| {
+                Sample::new(format!("f{}", i), format!("s{}", i),
+                            crate::core::Sex::Unknown)
+            })
+            .collect();
+
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_grm_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("grm").to_str().unwrap().to_string();
+
+        // 3 bands of 2, 2 and 1 rows.
+        let mut n_passes = 0;
+        let mut grm = write_grm(|| { n_passes += 1; data.clone() }, &samples,
+                                &prefix, 2).unwrap();
+        assert_eq!(n_passes, 3);
+        assert_eq!(grm.n_samples(), 5);
+
+        let expected = compute_grm(data.clone());
+        let observed = grm.to_array().unwrap();
+        for (obs, exp) in observed.iter().zip(expected.iter()) {
+            assert!((obs - exp).abs() < 1e-6);
+        }
+        assert!((grm.get(1, 3).unwrap() - expected[[3, 1]]).abs() < 1e-6);
+
+        let pairs = grm.related_pairs(0.0).unwrap();
+        let n_positive = (0..5)
+            .flat_map(|i| (0..i).map(move |j| (i, j)))
+            .filter(|&(i, j)| expected[[i, j]] >= 0.0)
+            .count();
+        assert_eq!(pairs.len(), n_positive);
+
+        let ids = std::fs::read_to_string(format!("{}.grm.id", prefix))
+            .unwrap();
+        assert!(ids.starts_with("f1\ts1\nf2\ts2\n"));
+
+        // The variants differ between the passes.
+        let mut n_passes = 0;
+        let err = write_grm(|| { n_passes += 1; data[..n_passes].to_vec() },
+                            &samples, &prefix, 2).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let monomorphic = vec![data[3].clone()];
+        let err = write_grm(|| monomorphic.clone(), &samples, &prefix, 2)
+            .err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_haseman_elston() {
+        let grm = ndarray::arr2(&[[1.0, 0.1, -0.5, 0.3],
+                                  [0.1, 1.0, 0.1, 0.2],
+                                  [-0.5, 0.1, 1.0, 0.0],
+                                  [0.3, 0.2, 0.0, 1.0]]);
+
+        // z = [-1, 0, 1] and the products are on the line 5/3 A - 1/6.
+        let phenotype = [Some(1.0), Some(2.0), Some(3.0), None];
+        let he = haseman_elston(&grm, &phenotype).unwrap();
+        assert!((he.h2 - 5.0 / 3.0).abs() < 1e-12);
+        assert!(he.se.abs() < 1e-6);
+        assert_eq!((he.n_samples, he.n_pairs), (3, 3));
+
+        assert!(haseman_elston(&grm, &[Some(1.0), Some(1.0), Some(1.0), None])
+                    .is_none());
+    }
+}
@@ -0,0 +1,370 @@
+/*!
+ * Remote files over HTTP(S) (requires the `http` feature).
+ *
+ * HttpReader is a seekable reader of a remote file that fetches aligned
+ * blocks using range requests, so only the parts of a large file that are
+ * read (e.g. the BED chunks of the queried variants) are downloaded. The
+ * server must report the length of the files and support range requests.
+ *
+ * Plink filesets can be opened from URLs using `PlinkReader::new`: the BIM
+ * and FAM are downloaded and the BIM is indexed in memory. With the
+ * `object-store` feature, `s3://` and `gs://` URLs are also supported (see
+ * the objectstore module).
+ */
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+#[cfg(feature = "object-store")]
+use crate::objectstore::{is_object_url, resolve, ObjectStoreConfig};
+
+
+pub const DEFAULT_BLOCK_SIZE: u64 = 256 * 1024;
+
+
+pub fn is_url(path: &str) -> bool {
+    #[cfg(feature = "object-store")]
+    {
+        if is_object_url(path) {
+            return true;
+        }
+    }
+
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+
+// Adds headers (e.g. authentication) to the requests for a URL.
+pub trait RequestSigner {
+    fn headers(&self, method: &str, url: &str) -> Vec<(String, String)>;
+}
+
+
+// The HTTP(S) URL of a file and the signer of its requests.
+fn locate(url: &str) -> io::Result<(String, Option<Box<dyn RequestSigner>>)> {
+    #[cfg(feature = "object-store")]
+    {
+        if is_object_url(url) {
+            let object = resolve(url, &ObjectStoreConfig::from_env())
+                .ok_or_else(|| io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid object URL `{}`.", url)
+                ))?;
+
+            let signer = object.signer
+                .map(|signer| Box::new(signer) as Box<dyn RequestSigner>);
+            return Ok((object.url, signer));
+        }
+    }
+
+    Ok((url.to_string(), None))
+}
+
+
+fn request(agent: &ureq::Agent, method: &str, url: &str,
+           signer: Option<&dyn RequestSigner>) -> ureq::Request
+{
+    let mut request = agent.request(method, url);
+    if let Some(signer) = signer {
+        for (name, value) in signer.headers(method, url) {
+            request = request.set(&name, &value);
+        }
+    }
+
+    request
+}
+
+
+fn request_error(url: &str, e: ureq::Error) -> io::Error {
+    io::Error::other(format!("Request to `{}` failed: {}", url, e))
+}
+
+
+// Download a whole file (e.g. a BIM or FAM).
+pub fn download(url: &str) -> io::Result<Vec<u8>> {
+    let (http_url, signer) = locate(url)?;
+    let agent = ureq::AgentBuilder::new().build();
+
+    let response = request(&agent, "GET", &http_url, signer.as_deref())
+        .call()
+        .map_err(|e| request_error(url, e))?;
+
+    let mut data = Vec::new();
+    response.into_reader().read_to_end(&mut data)?;
+    Ok(data)
+}
+
+
+pub struct HttpReader {
+    agent: ureq::Agent,
+    url: String,
+    len: u64,
+    pos: u64,
+    block_size: u64,
+    signer: Option<Box<dyn RequestSigner>>,
+    // Index and content of the last fetched block.
+    block: Option<(u64, Vec<u8>)>
+}
+
+impl HttpReader {
+    pub fn open(url: &str) -> io::Result<HttpReader> {
+        HttpReader::with_block_size(url, DEFAULT_BLOCK_SIZE)
+    }
+
+    pub fn with_block_size(url: &str, block_size: u64)
+        -> io::Result<HttpReader>
+    {
+        if block_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "The block size must be positive."));
+        }
+
+        let (http_url, signer) = locate(url)?;
+        let agent = ureq::AgentBuilder::new().build();
+        let response = request(&agent, "HEAD", &http_url, signer.as_deref())
+            .call()
+            .map_err(|e| request_error(url, e))?;
+
+        let len = response.header("Content-Length")
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("The length of `{}` is unknown.", url)
+            ))?;
+
+        Ok(HttpReader {
+            agent,
+            url: http_url,
+            len,
+            pos: 0,
+            block_size,
+            signer,
+            block: None
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn _fetch_block(&mut self, idx: u64) -> io::Result<()> {
+        if let Some((current, _)) = self.block {
+            if current == idx {
+                return Ok(());
+            }
+        }
+
+        let start = idx * self.block_size;
+        let end = (start + self.block_size).min(self.len);
+
+        let response = request(&self.agent, "GET", &self.url,
+                               self.signer.as_deref())
+            .set("Range", &format!("bytes={}-{}", start, end - 1))
+            .call()
+            .map_err(|e| request_error(&self.url, e))?;
+
+        if response.status() != 206 {
+            return Err(io::Error::other(format!(
+                "The server of `{}` doesn't support range requests.",
+                self.url
+            )));
+        }
+
+        let mut buf = Vec::with_capacity((end - start) as usize);
+        response.into_reader().read_to_end(&mut buf)?;
+
+        if buf.len() as u64 != end - start {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!(
+                "Got {} bytes instead of {} from `{}`.", buf.len(),
+                end - start, self.url
+            )));
+        }
+
+        self.block = Some((idx, buf));
+        Ok(())
+    }
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let idx = self.pos / self.block_size;
+        self._fetch_block(idx)?;
+
+        let block = &self.block.as_ref().unwrap().1;
+        let start = (self.pos - idx * self.block_size) as usize;
+        let n = buf.len().min(block.len() - start);
+
+        buf[..n].copy_from_slice(&block[start..start + n]);
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl Seek for HttpReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n)
+        };
+
+        match new_pos {
+            Some(n) => {
+                self.pos = n;
+                Ok(n)
+            },
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                       "Invalid seek to a negative or \
+                                        overflowing position."))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use crate::core::{Chromosome, Variant};
+    use crate::plink::PlinkReader;
+
+    // Serve the files (by path) with support for HEAD and range requests.
+    fn serve(files: HashMap<String, Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let fields: Vec<&str> = request.split(' ').collect();
+
+                let mut range: Option<(usize, usize)> = None;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+
+                    let line = line.trim().to_lowercase();
+                    if let Some(bytes) = line.strip_prefix("range: bytes=") {
+                        let (start, end) = bytes.split_once('-').unwrap();
+                        range = Some((start.parse().unwrap(),
+                                      end.parse().unwrap()));
+                    }
+                }
+
+                let (status, body) = match (files.get(fields[1]), range) {
+                    (None, _) => ("404 Not Found", &[][..]),
+                    (Some(data), Some((start, end))) => {
+                        ("206 Partial Content", &data[start..=end])
+                    },
+                    (Some(data), None) => ("200 OK", &data[..])
+                };
+
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\n\
+                                Connection: close\r\n\r\n",
+                       status, body.len()).unwrap();
+                if fields[0] != "HEAD" {
+                    stream.write_all(body).unwrap();
+                }
+            }
+        });
+
+        url
+    }
+
+    #[test]
+    fn test_http_reader() {
+        let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let mut files = HashMap::new();
+        files.insert("/data".to_string(), data.clone());
+        let url = serve(files);
+
+        let mut reader = HttpReader::with_block_size(&format!("{}/data", url),
+                                                     64).unwrap();
+        assert_eq!(reader.len(), 1000);
+
+        // Reads span blocks.
+        let mut buf = vec![0; 100];
+        reader.seek(SeekFrom::Start(30)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, &data[30..130]);
+
+        let mut end = Vec::new();
+        reader.seek(SeekFrom::End(-10)).unwrap();
+        reader.read_to_end(&mut end).unwrap();
+        assert_eq!(end, &data[990..]);
+
+        assert!(HttpReader::open(&format!("{}/missing", url)).is_err());
+    }
+
+    #[test]
+    fn test_remote_plink() {
+        let mut files = HashMap::new();
+        files.insert("/data/test.bim".to_string(),
+                     b"1\trs1\t0\t100\tA\tG\n1\trs2\t0\t200\tC\tT\n".to_vec());
+        files.insert("/data/test.fam".to_string(),
+                     b"f1 s1 0 0 1 -9\nf2 s2 0 0 2 -9\nf3 s3 0 0 0 -9\n"
+                         .to_vec());
+        files.insert("/data/test.bed".to_string(),
+                     vec![0x6c, 0x1b, 0x01, 0b11_10_00, 0b00_01_11]);
+        let url = serve(files);
+
+        let mut reader = PlinkReader::new(&format!("{}/data/test", url))
+            .unwrap();
+        assert_eq!(reader.samples().len(), 3);
+
+        let v = Variant::new("rs2".to_string(), "1".to_string(), 200,
+                             ("C".to_string(), "T".to_string()));
+        let g = reader.get_variant_genotypes(&v).unwrap();
+        assert_eq!(g.genotypes, vec![Some(0), None, Some(2)]);
+
+        let region = reader.get_variants_in_region(
+            &Chromosome::Autosome(1), 1, 150
+        );
+        assert_eq!(region.len(), 1);
+    }
+
+    #[test]
+    fn test_remote_bgen() {
+        use crate::bgen::{BgenReader, BgenWriter};
+        use crate::core::{Dosages, Sample, Sex};
+
+        let samples: Vec<Sample> = ["s1", "s2"].iter()
+            .map(|id| Sample::new(id.to_string(), id.to_string(), Sex::Unknown))
+            .collect();
+
+        let mut writer = BgenWriter::new(std::io::Cursor::new(Vec::new()),
+                                         &samples).unwrap();
+        for pos in 1..=50 {
+            let v = Variant::new(format!("rs{}", pos), "1".to_string(), pos,
+                                 ("A".to_string(), "G".to_string()));
+            writer.write(&Dosages::new(v, vec![Some(1.0), None], "G"))
+                .unwrap();
+        }
+
+        let mut files = HashMap::new();
+        files.insert("/test.bgen".to_string(),
+                     writer.finish().unwrap().into_inner());
+        let url = serve(files);
+
+        let reader = BgenReader::new_remote(&format!("{}/test.bgen", url));
+        assert_eq!(reader.samples()[1].iid, "s2");
+        assert_eq!(reader.map(|d| d.variant.position).last(), Some(50));
+    }
+}
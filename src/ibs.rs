@@ -0,0 +1,295 @@
+/*!
+ * Identity-by-state (IBS) between pairs of samples and multidimensional
+ * scaling (MDS).
+ *
+ * For every pair of samples, the variants called in both samples are
+ * counted by the number of alleles shared IBS (0, 1 or 2). As in plink
+ * `--cluster`, the IBS similarity of a pair is (IBS2 + 0.5 * IBS1) / N and
+ * its distance is 1 - similarity. Classical MDS of the distance matrix is a
+ * lighter alternative to PCA to visualize the population structure (like
+ * `--mds-plot`).
+ */
+
+use std::io::{self, Write};
+
+use ndarray::{Array1, Array2, Axis};
+
+use crate::cluster::Clusters;
+use crate::core::{Genotypes, Sample};
+use crate::linalg;
+
+
+// Number of variants that are accumulated before updating the counts with
+// matrix products.
+const BLOCK_SIZE: usize = 256;
+
+
+// Number of variants with 0, 1 and 2 alleles IBS for every pair of samples.
+#[derive(Debug)]
+pub struct IbsCounts {
+    pub n_ibs0: Array2<f64>,
+    pub n_ibs1: Array2<f64>,
+    pub n_ibs2: Array2<f64>
+}
+
+
+impl IbsCounts {
+    // Number of variants called in both samples.
+    pub fn n_called(&self) -> Array2<f64> {
+        &self.n_ibs0 + &self.n_ibs1 + &self.n_ibs2
+    }
+
+    // IBS similarity of the pairs (NaN if there are no common calls).
+    pub fn similarity(&self) -> Array2<f64> {
+        (&self.n_ibs2 + &(&self.n_ibs1 * 0.5)) / self.n_called()
+    }
+
+    pub fn distance(&self) -> Array2<f64> {
+        self.similarity().mapv(|s| 1.0 - s)
+    }
+}
+
+
+#[derive(Default)]
+pub struct IbsAccumulator {
+    // Indicators of the homozygous other, heterozygous and homozygous coded
+    // calls of the samples (one row per variant of the block).
+    blocks: [Vec<f64>; 3],
+    n_block: usize,
+    counts: Option<IbsCounts>,
+    n_samples: usize,
+    n_variants: u64
+}
+
+
+impl IbsAccumulator {
+    pub fn new() -> IbsAccumulator {
+        IbsAccumulator::default()
+    }
+
+    // Add a variant. Haploid calls count as homozygous diploid calls.
+    pub fn add(&mut self, g: &Genotypes) {
+        if self.counts.is_none() {
+            self.n_samples = g.genotypes.len();
+            let zeros = || Array2::zeros((self.n_samples, self.n_samples));
+            self.counts = Some(IbsCounts {
+                n_ibs0: zeros(),
+                n_ibs1: zeros(),
+                n_ibs2: zeros()
+            });
+        }
+
+        if g.genotypes.len() != self.n_samples {
+            panic!("Expected {} samples but `{}` has {} genotypes.",
+                   self.n_samples, g.variant, g.genotypes.len());
+        }
+
+        let scale = match g.ploidy() {
+            1 => 2,
+            2 => 1,
+            ploidy => panic!("Unsupported ploidy {} for `{}`.", ploidy,
+                             g.variant)
+        };
+
+        for (copies, block) in self.blocks.iter_mut().enumerate() {
+            block.extend(g.genotypes.iter().map(|call| match call {
+                Some(x) if usize::from(x * scale) == copies => 1.0,
+                _ => 0.0
+            }));
+        }
+
+        self.n_block += 1;
+        self.n_variants += 1;
+
+        if self.n_block == BLOCK_SIZE {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.n_block == 0 {
+            return;
+        }
+
+        let shape = (self.n_block, self.n_samples);
+        let [hom_other, het, hom_coded] = std::mem::take(&mut self.blocks)
+            .map(|block| Array2::from_shape_vec(shape, block).unwrap());
+        let hom = &hom_other + &hom_coded;
+
+        let counts = self.counts.as_mut().unwrap();
+
+        let opposite = hom_other.t().dot(&hom_coded);
+        counts.n_ibs0 += &opposite;
+        counts.n_ibs0 += &opposite.t();
+
+        let het_hom = het.t().dot(&hom);
+        counts.n_ibs1 += &het_hom;
+        counts.n_ibs1 += &het_hom.t();
+
+        counts.n_ibs2 += &hom_other.t().dot(&hom_other);
+        counts.n_ibs2 += &het.t().dot(&het);
+        counts.n_ibs2 += &hom_coded.t().dot(&hom_coded);
+
+        self.n_block = 0;
+    }
+
+    pub fn n_variants(&self) -> u64 {
+        self.n_variants
+    }
+
+    pub fn finish(mut self) -> IbsCounts {
+        self.flush();
+
+        match self.counts {
+            Some(counts) => counts,
+            None => panic!("Can't compute the IBS without variants.")
+        }
+    }
+}
+
+
+pub fn compute_ibs<I>(genotypes: I) -> IbsCounts
+    where I: IntoIterator<Item = Genotypes>
+{
+    let mut acc = IbsAccumulator::new();
+    for g in genotypes {
+        acc.add(&g);
+    }
+
+    acc.finish()
+}
+
+
+#[derive(Debug)]
+pub struct Mds {
+    pub eigenvalues: Array1<f64>,
+    // One row per sample and one column per dimension.
+    pub coordinates: Array2<f64>
+}
+
+
+// Classical (Torgerson) MDS of a distance matrix in k dimensions.
+pub fn mds(distances: &Array2<f64>, k: usize) -> Mds {
+    let n = distances.rows();
+    assert_eq!(n, distances.cols(), "Expected a square matrix.");
+    if distances.iter().any(|d| !d.is_finite()) {
+        panic!("Can't compute the MDS of pairs without a distance (no \
+                common calls).");
+    }
+
+    // Double centering of the squared distances.
+    let squared = distances.mapv(|d| d * d);
+    let row_means = squared.mean_axis(Axis(1));
+    let mean = row_means.sum() / n as f64;
+    let b = Array2::from_shape_fn((n, n), |(i, j)| {
+        -0.5 * (squared[[i, j]] - row_means[i] - row_means[j] + mean)
+    });
+
+    let (eigenvalues, vectors) = linalg::top_eigen(&b, k);
+    let mut coordinates = vectors;
+    for (mut column, &value) in coordinates.axis_iter_mut(Axis(1))
+        .zip(eigenvalues.iter())
+    {
+        column *= value.max(0.0).sqrt();
+    }
+
+    Mds { eigenvalues, coordinates }
+}
+
+
+// Write the coordinates with the columns of plink `.mds` files. SOL is the
+// cluster of the sample (0 for all the samples without clusters).
+pub fn write_mds<W: Write>(out: &mut W, samples: &[Sample], mds: &Mds,
+                           clusters: Option<&Clusters>) -> io::Result<()>
+{
+    let k = mds.coordinates.cols();
+    let header: Vec<String> = (1..=k).map(|i| format!("C{}", i)).collect();
+    writeln!(out, "FID\tIID\tSOL\t{}", header.join("\t"))?;
+
+    for (i, (s, row)) in samples.iter()
+        .zip(mds.coordinates.outer_iter())
+        .enumerate()
+    {
+        let coordinates: Vec<String> = row.iter()
+            .map(|x| format!("{:.6}", x))
+            .collect();
+        let cluster = clusters.map_or(0, |c| c.assignments[i]);
+        writeln!(out, "{}\t{}\t{}\t{}", s.fid, s.iid, cluster,
+                 coordinates.join("\t"))?;
+    }
+
+    out.flush()
+}
+
+
+// Write a matrix (e.g. the IBS similarity) like plink `.mibs` files: one
+// row per sample, without header.
+pub fn write_matrix<W: Write>(out: &mut W, matrix: &Array2<f64>)
+    -> io::Result<()>
+{
+    for row in matrix.outer_iter() {
+        let values: Vec<String> = row.iter()
+            .map(|x| format!("{:.6}", x))
+            .collect();
+        writeln!(out, "{}", values.join(" "))?;
+    }
+
+    out.flush()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::genotypes;
+
+    #[test]
+    fn test_ibs() {
+        let ibs = compute_ibs(vec![
+            genotypes(1, vec![Some(0), Some(1), Some(2), Some(0)]),
+            genotypes(2, vec![Some(2), Some(1), None, Some(2)]),
+            genotypes(3, vec![Some(1), Some(1), Some(0), Some(1)])
+                .with_ploidy(1)
+        ]);
+
+        // Samples 0 and 2: IBS0, missing and IBS0 (haploid calls).
+        assert_eq!(ibs.n_ibs0[[0, 2]], 2.0);
+        assert_eq!(ibs.n_called()[[0, 2]], 2.0);
+        assert_eq!(ibs.similarity()[[2, 0]], 0.0);
+
+        // Samples 0 and 1: IBS1, IBS1 and IBS2 (haploid calls).
+        assert_eq!(ibs.n_ibs1[[1, 0]], 2.0);
+        assert_eq!(ibs.distance()[[0, 1]], 1.0 - 2.0 / 3.0);
+
+        // Samples 0 and 3 are identical.
+        assert_eq!(ibs.distance()[[0, 3]], 0.0);
+        assert_eq!(ibs.n_ibs2[[2, 2]], 2.0);
+    }
+
+    #[test]
+    fn test_mds() {
+        // Points on a line at 0, 1 and 3.
+        let x = [0.0_f64, 1.0, 3.0];
+        let distances = Array2::from_shape_fn((3, 3), |(i, j)| {
+            (x[i] - x[j]).abs()
+        });
+
+        let mds = mds(&distances, 1);
+        let c = mds.coordinates.column(0);
+        for i in 0..3 {
+            for j in 0..3 {
+                assert!(((c[i] - c[j]).abs() - distances[[i, j]]).abs()
+                        < 1e-9);
+            }
+        }
+
+        let mut out = Vec::new();
+        let samples: Vec<Sample> = ["a", "b", "c"].iter()
+            .map(|id| Sample::new(id.to_string(), id.to_string(),
+                                  crate::core::Sex::Unknown))
+            .collect();
+        write_mds(&mut out, &samples, &mds, None).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("FID\tIID\tSOL\tC1\na\ta\t0\t"));
+    }
+}
@@ -0,0 +1,274 @@
+/*!
+ * Reader for IMPUTE2 output.
+ *
+ * Every line of the `.impute2` file is `snp_id rs_id position a1 a2`
+ * followed by the probabilities of the a1/a1, a1/a2 and a2/a2 genotypes of
+ * every sample. These are converted to the expected number of copies of a2.
+ * Samples with only null probabilities are missing. The file doesn't hold
+ * the chromosome, so it has to be provided.
+ *
+ * The samples are read from an Oxford `.sample` file and the info scores
+ * from the optional `.impute2_info` file, which has a line per variant in
+ * the same order.
+ */
+
+use std::io::{BufRead, Lines};
+use std::sync::Arc;
+
+use crate::core::{Dosages, Sample, Sex};
+use crate::utils::open_text_file;
+use crate::vcf::build_variant;
+
+
+// Read the samples from an Oxford `.sample` file. The first two lines are
+// the column names and types.
+pub fn read_sample_file<R: BufRead>(reader: R) -> Vec<Sample> {
+    let mut lines = reader.lines().map(|line| {
+        line.expect("Could not read the sample file.")
+    });
+
+    let header = lines.next().unwrap_or_default();
+    let sex = header.split_whitespace()
+        .position(|column| column.eq_ignore_ascii_case("sex"));
+
+    // Column types.
+    lines.next();
+
+    lines.filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            let sex = sex.map_or(Sex::Unknown,
+                                 |i| Sex::from_plink_code(fields[i]));
+            Sample::new(fields[0].to_string(), fields[1].to_string(), sex)
+        })
+        .collect()
+}
+
+
+struct InfoFile {
+    lines: Lines<Box<dyn BufRead>>,
+    // Indices of the rs_id, position and info columns.
+    idx: [usize; 3]
+}
+
+
+pub struct Impute2Reader<R: BufRead> {
+    lines: Lines<R>,
+    chrom: String,
+    info: Option<InfoFile>,
+    samples: Arc<Vec<Sample>>,
+    attach_samples: bool,
+    line_number: u64,
+    n_skipped: u32
+}
+
+
+impl Impute2Reader<Box<dyn BufRead>> {
+    // The `.impute2` and `.sample` files can be gzipped.
+    pub fn new(filename: &str, sample_filename: &str, chrom: &str)
+        -> Impute2Reader<Box<dyn BufRead>>
+    {
+        let samples = read_sample_file(open_text_file(sample_filename));
+        Impute2Reader::from_reader(open_text_file(filename), samples, chrom)
+    }
+}
+
+
+impl<R: BufRead> Impute2Reader<R> {
+    pub fn from_reader(reader: R, samples: Vec<Sample>, chrom: &str)
+        -> Impute2Reader<R>
+    {
+        Impute2Reader {
+            lines: reader.lines(),
+            chrom: chrom.to_string(),
+            info: None,
+            samples: Arc::new(samples),
+            attach_samples: false,
+            line_number: 0,
+            n_skipped: 0
+        }
+    }
+
+    // Attach the info scores from an `.impute2_info` file to the dosages.
+    pub fn with_info_file(self, filename: &str) -> Impute2Reader<R> {
+        self.with_info_reader(open_text_file(filename))
+    }
+
+    pub fn with_info_reader(mut self, reader: Box<dyn BufRead>)
+        -> Impute2Reader<R>
+    {
+        let mut lines = reader.lines();
+        let header = lines.next()
+            .and_then(|line| line.ok())
+            .expect("The info file is empty.");
+
+        let columns: Vec<&str> = header.split_whitespace().collect();
+        let column = |name: &str| {
+            columns.iter()
+                .position(|&c| c == name)
+                .unwrap_or_else(|| panic!("Missing column `{}` in the info \
+                                           file.", name))
+        };
+
+        let idx = [column("rs_id"), column("position"), column("info")];
+        self.info = Some(InfoFile { lines, idx });
+        self
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn attach_samples(&mut self, attach: bool) {
+        self.attach_samples = attach;
+    }
+
+    // Number of variants that were skipped because their alleles are not
+    // supported.
+    pub fn n_skipped(&self) -> u32 {
+        self.n_skipped
+    }
+
+    // Info score of the current line, making sure that the info file
+    // describes the same variant.
+    fn _next_info(&mut self, rs_id: &str, position: &str) -> Option<f64> {
+        let line_number = self.line_number;
+        let InfoFile { lines, idx } = self.info.as_mut()?;
+
+        let line = lines.next()
+            .unwrap_or_else(|| panic!("The info file has fewer variants than \
+                                       the IMPUTE2 file (line {}).",
+                                      line_number))
+            .expect("Could not read the info file.");
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields[idx[0]] != rs_id || fields[idx[1]] != position {
+            panic!("The info file doesn't match the IMPUTE2 file on line \
+                    {} (expected `{}` at {}).", line_number, rs_id, position);
+        }
+
+        fields[idx[2]].parse().ok()
+    }
+
+    fn _parse_line(&mut self, line: &str) -> Option<Dosages> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let n_samples = self.samples.len();
+
+        if fields.len() != 5 + 3 * n_samples {
+            panic!("Expected {} columns on line {} of the IMPUTE2 file, got \
+                    {}.", 5 + 3 * n_samples, self.line_number, fields.len());
+        }
+
+        let info = self._next_info(fields[1], fields[2]);
+
+        let location = format!("line {} of the IMPUTE2 file",
+                               self.line_number);
+        let variant = build_variant(fields[1], &self.chrom, fields[2],
+                                    fields[3], fields[4], &location)?;
+
+        let dosages = fields[5..].chunks(3)
+            .map(|probs| {
+                let probs: Vec<f64> = probs.iter()
+                    .map(|p| p.parse().unwrap_or_else(|_| {
+                        panic!("Invalid probability `{}` on {}.", p, location)
+                    }))
+                    .collect();
+
+                if probs.iter().all(|&p| p == 0.0) {
+                    None
+                } else {
+                    Some(probs[1] + 2.0 * probs[2])
+                }
+            })
+            .collect();
+
+        let mut d = Dosages::new(variant, dosages, fields[4]);
+        if let Some(info) = info {
+            d = d.with_info(info);
+        }
+
+        if self.attach_samples {
+            d = d.with_samples(Arc::clone(&self.samples));
+        }
+
+        Some(d)
+    }
+}
+
+
+impl<R: BufRead> Iterator for Impute2Reader<R> {
+    type Item = Dosages;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(line) = self.lines.next() {
+            let line = line.expect("Could not read the IMPUTE2 file.");
+            self.line_number += 1;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match self._parse_line(&line) {
+                Some(d) => return Some(d),
+                None => self.n_skipped += 1
+            }
+        }
+
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let samples = read_sample_file(
+            &b"ID_1 ID_2 missing sex\n0 0 0 D\nf1 s1 0 1\nf2 s2 0 2\n"[..]
+        );
+        assert_eq!(samples[1].iid, "s2");
+        assert_eq!(samples[1].sex, Sex::Female);
+
+        let gen = "--- rs1 100 A G 1 0 0 0.1 0.8 0.1\n\
+                   --- rs2 200 AC <DEL> 0 1 0 0 0 1\n\
+                   --- rs3 300 C T 0 0 1 0 0 0\n";
+        let info = "snp_id rs_id position exp_freq_a1 info certainty\n\
+                    --- rs1 100 0.5 0.912 0.9\n\
+                    --- rs2 200 0.5 0.5 0.9\n\
+                    --- rs3 300 0.5 -1 0.9\n";
+
+        let mut reader = Impute2Reader::from_reader(gen.as_bytes(), samples,
+                                                    "22")
+            .with_info_reader(Box::new(info.as_bytes()));
+        reader.attach_samples(true);
+
+        let dosages: Vec<Dosages> = reader.by_ref().collect();
+        assert_eq!(reader.n_skipped(), 1);
+        assert_eq!(dosages.len(), 2);
+
+        assert_eq!(dosages[0].variant.chrom.to_string(), "22");
+        assert_eq!(dosages[0].coded_allele(), "G");
+        assert_eq!(dosages[0].info, Some(0.912));
+        assert_eq!(dosages[0].dosages[0], Some(0.0));
+        assert!((dosages[0].dosages[1].unwrap() - 1.0).abs() < 1e-9);
+
+        assert_eq!(dosages[1].variant.name, "rs3");
+        assert_eq!(dosages[1].dosages, vec![Some(2.0), None]);
+        assert!(dosages[1].samples.is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't match")]
+    fn test_info_mismatch() {
+        let reader = Impute2Reader::from_reader(
+            &b"--- rs1 100 A G 1 0 0\n"[..],
+            read_sample_file(&b"ID_1 ID_2 missing\n0 0 0\nf1 s1 0\n"[..]),
+            "1"
+        ).with_info_reader(Box::new(&b"rs_id position info\nrs2 100 1\n"[..]));
+
+        reader.count();
+    }
+}
@@ -0,0 +1,709 @@
+/*!
+ * Native (v2) index of the BIM.
+ *
+ * The v1 index is a bgzipped copy of the BIM indexed with tabix
+ * (`{prefix}.bimidx.gz`), which requires the htslib tools. The v2 index
+ * (`{prefix}.bimidx2`) is a text file with a header describing the BIM it
+ * was built from (number of variants, size and modification time) followed
+ * by a `chrom pos idx offset` line per variant, sorted by locus. The offset
+ * of the BIM line is used to read the variant when answering queries.
+ *
+ * As the header describes the BIM, stale v2 indexes are detected when they
+ * are opened. The functions at the end of the module manage the indexes of
+ * a fileset explicitly (see `genepa index`).
+ *
+ * Both formats are indexed by locus. The variants are also indexed by name
+ * in `{prefix}.bimidx.names`, which has the same header as the v2 index
+ * followed by a `name idx offset` line per variant, sorted by name. It is
+ * built on the first query by name.
+ */
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::UNIX_EPOCH;
+
+use crate::core::{Chromosome, Variant};
+use crate::plink::{parse_bim_line, BimIndex};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexFormat {
+    // tabix index of the bgzipped BIM.
+    V1,
+    // Native index with the BIM metadata.
+    V2
+}
+
+impl IndexFormat {
+    // Files of an index of the fileset `prefix`.
+    pub fn filenames(self, prefix: &str) -> Vec<String> {
+        match self {
+            IndexFormat::V1 => vec![format!("{}.bimidx.gz", prefix),
+                                    format!("{}.bimidx.gz.tbi", prefix),
+                                    name_index_filename(prefix)],
+            IndexFormat::V2 => vec![format!("{}.bimidx2", prefix),
+                                    name_index_filename(prefix)]
+        }
+    }
+}
+
+impl FromStr for IndexFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<IndexFormat, String> {
+        match s {
+            "v1" => Ok(IndexFormat::V1),
+            "v2" => Ok(IndexFormat::V2),
+            _ => Err(format!("Unknown index format `{}` (expected v1 or v2).",
+                             s))
+        }
+    }
+}
+
+impl fmt::Display for IndexFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IndexFormat::V1 => write!(f, "v1"),
+            IndexFormat::V2 => write!(f, "v2")
+        }
+    }
+}
+
+
+pub fn name_index_filename(prefix: &str) -> String {
+    format!("{}.bimidx.names", prefix)
+}
+
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+
+// Size and modification time (in seconds) of the BIM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BimMetadata {
+    pub size: u64,
+    pub mtime: u64
+}
+
+impl BimMetadata {
+    pub fn of(bim_filename: &str) -> io::Result<BimMetadata> {
+        let metadata = fs::metadata(bim_filename)?;
+        let mtime = metadata.modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        Ok(BimMetadata { size: metadata.len(), mtime })
+    }
+}
+
+
+// A variant of the index (the chromosome is the key of its group).
+#[derive(Clone, Copy, Debug)]
+struct Record {
+    position: u32,
+    idx: u32,
+    offset: u64
+}
+
+
+pub struct NativeBimIndex {
+    bim_filename: String,
+    pub n_variants: u32,
+    pub bim: BimMetadata,
+    // Records sorted by position for every chromosome, in order of first
+    // appearance in the BIM.
+    chroms: Vec<(String, Vec<Record>)>
+}
+
+impl NativeBimIndex {
+    pub fn build(bim_filename: &str) -> io::Result<NativeBimIndex> {
+        let bim = BimMetadata::of(bim_filename)?;
+        let mut reader = BufReader::new(File::open(bim_filename)?);
+
+        let mut chroms: Vec<(String, Vec<Record>)> = Vec::new();
+        let mut chrom_idx: HashMap<String, usize> = HashMap::new();
+
+        let mut line = String::new();
+        let mut offset = 0;
+        let mut idx = 0;
+
+        loop {
+            line.clear();
+            let n = reader.read_line(&mut line)?;
+            if n == 0 {
+                break;
+            }
+
+            let mut fields = line.split('\t');
+            let chrom = fields.next().unwrap_or("");
+            let position = fields.nth(2)
+                .and_then(|pos| pos.parse().ok())
+                .ok_or_else(|| invalid_data(format!(
+                    "Invalid position on line {} of `{}`.", idx + 1,
+                    bim_filename
+                )))?;
+
+            let i = *chrom_idx.entry(chrom.to_string()).or_insert_with(|| {
+                chroms.push((chrom.to_string(), Vec::new()));
+                chroms.len() - 1
+            });
+            chroms[i].1.push(Record { position, idx, offset });
+
+            offset += n as u64;
+            idx += 1;
+        }
+
+        for (_, records) in chroms.iter_mut() {
+            records.sort_by_key(|r| (r.position, r.idx));
+        }
+
+        Ok(NativeBimIndex {
+            bim_filename: bim_filename.to_string(),
+            n_variants: idx,
+            bim,
+            chroms
+        })
+    }
+
+    pub fn write(&self, filename: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(filename)?);
+
+        writeln!(out, "#bimidx\tv2")?;
+        writeln!(out, "#n_variants\t{}", self.n_variants)?;
+        writeln!(out, "#bim_size\t{}", self.bim.size)?;
+        writeln!(out, "#bim_mtime\t{}", self.bim.mtime)?;
+
+        for (chrom, records) in self.chroms.iter() {
+            for r in records {
+                writeln!(out, "{}\t{}\t{}\t{}", chrom, r.position, r.idx,
+                         r.offset)?;
+            }
+        }
+
+        out.flush()
+    }
+
+    pub fn read(filename: &str, bim_filename: &str)
+        -> io::Result<NativeBimIndex>
+    {
+        let mut header: HashMap<String, String> = HashMap::new();
+        let mut chroms: Vec<(String, Vec<Record>)> = Vec::new();
+
+        for (i, line) in BufReader::new(File::open(filename)?).lines()
+            .enumerate()
+        {
+            let line = line?;
+            let invalid = || invalid_data(format!(
+                "Invalid line {} in the BIM index `{}`.", i + 1, filename
+            ));
+
+            if let Some(entry) = line.strip_prefix('#') {
+                let (key, value) = entry.split_once('\t')
+                    .ok_or_else(invalid)?;
+                header.insert(key.to_string(), value.to_string());
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 4 {
+                return Err(invalid());
+            }
+
+            let record = Record {
+                position: fields[1].parse().map_err(|_| invalid())?,
+                idx: fields[2].parse().map_err(|_| invalid())?,
+                offset: fields[3].parse().map_err(|_| invalid())?
+            };
+
+            match chroms.last_mut() {
+                Some((chrom, records)) if chrom == fields[0] => {
+                    records.push(record)
+                },
+                _ => chroms.push((fields[0].to_string(), vec![record]))
+            }
+        }
+
+        if header.get("bimidx").map(|v| v.as_str()) != Some("v2") {
+            return Err(invalid_data(format!(
+                "`{}` is not a v2 BIM index.", filename
+            )));
+        }
+
+        let value = |key: &str| -> io::Result<u64> {
+            header.get(key)
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| invalid_data(format!(
+                    "Missing `{}` in the header of the BIM index `{}`.", key,
+                    filename
+                )))
+        };
+
+        Ok(NativeBimIndex {
+            bim_filename: bim_filename.to_string(),
+            n_variants: value("n_variants")? as u32,
+            bim: BimMetadata {
+                size: value("bim_size")?,
+                mtime: value("bim_mtime")?
+            },
+            chroms
+        })
+    }
+
+    // Open the index of a BIM, building it if it doesn't exist or if the
+    // BIM changed since it was built.
+    pub fn get_or_create(filename: &str, bim_filename: &str)
+        -> io::Result<NativeBimIndex>
+    {
+        if Path::new(filename).is_file() {
+            let index = NativeBimIndex::read(filename, bim_filename)?;
+            if !index.is_stale()? {
+                return Ok(index);
+            }
+        }
+
+        let index = NativeBimIndex::build(bim_filename)?;
+        index.write(filename)?;
+        Ok(index)
+    }
+
+    // If the BIM doesn't match the metadata in the header.
+    pub fn is_stale(&self) -> io::Result<bool> {
+        Ok(BimMetadata::of(&self.bim_filename)? != self.bim)
+    }
+
+    pub fn count_indexed_variants(&self) -> u32 {
+        self.chroms.iter().map(|(_, records)| records.len() as u32).sum()
+    }
+
+    // Number of indexed variants of every chromosome.
+    pub fn chromosomes(&self) -> Vec<(&str, u32)> {
+        self.chroms.iter()
+            .map(|(chrom, records)| (chrom.as_str(), records.len() as u32))
+            .collect()
+    }
+
+    // Returns a vector of index, variant, coded_allele. The chromosomes of
+    // the index are compared once normalized (e.g. `23` and `X`).
+    pub(crate) fn get_region_index_and_coded(&self, chrom: &Chromosome,
+                                             start: u32, end: u32)
+        -> Vec<(u32, Variant, String)>
+    {
+        let matches = self.chroms.iter()
+            .filter(|(c, _)| Chromosome::new(c) == *chrom)
+            .flat_map(|(_, records)| {
+                let first = records.partition_point(|r| r.position < start);
+                records[first..].iter().take_while(|r| r.position <= end)
+            })
+            .collect::<Vec<&Record>>();
+
+        if matches.is_empty() {
+            return Vec::new();
+        }
+
+        read_bim_records(&self.bim_filename, matches)
+    }
+}
+
+
+// Read the variants of the records from their lines in the BIM. Returns the
+// index, variant and coded allele of every record.
+fn read_bim_records(bim_filename: &str, records: Vec<&Record>)
+    -> Vec<(u32, Variant, String)>
+{
+    let mut bim = BufReader::new(
+        File::open(bim_filename)
+            .unwrap_or_else(|_| panic!("Could not read BIM: `{}`",
+                                       bim_filename))
+    );
+
+    let mut line = String::new();
+    records.into_iter()
+        .map(|r| {
+            line.clear();
+            bim.seek(SeekFrom::Start(r.offset))
+                .and_then(|_| bim.read_line(&mut line))
+                .expect("Could not read the BIM line of an indexed variant.");
+
+            let (variant, a1) = parse_bim_line(line.trim_end())
+                .unwrap_or_else(|e| {
+                    panic!("Invalid line in `{}`: {}", bim_filename, e)
+                });
+            (r.idx, variant, a1)
+        })
+        .collect()
+}
+
+
+// Index of the variants of a BIM by name (the unnamed variants, `.`, are
+// not indexed).
+pub struct NameIndex {
+    bim_filename: String,
+    pub n_variants: u32,
+    pub bim: BimMetadata,
+    names: HashMap<String, Vec<Record>>
+}
+
+impl NameIndex {
+    pub fn build(bim_filename: &str) -> io::Result<NameIndex> {
+        let bim = BimMetadata::of(bim_filename)?;
+        let mut reader = BufReader::new(File::open(bim_filename)?);
+
+        let mut names: HashMap<String, Vec<Record>> = HashMap::new();
+        let mut line = String::new();
+        let mut offset = 0;
+        let mut idx = 0;
+
+        loop {
+            line.clear();
+            let n = reader.read_line(&mut line)?;
+            if n == 0 {
+                break;
+            }
+
+            let mut fields = line.split('\t');
+            let name = fields.nth(1).unwrap_or("");
+            let position = fields.nth(1)
+                .and_then(|pos| pos.parse().ok())
+                .ok_or_else(|| invalid_data(format!(
+                    "Invalid position on line {} of `{}`.", idx + 1,
+                    bim_filename
+                )))?;
+
+            if !name.is_empty() && name != "." {
+                names.entry(name.to_string())
+                    .or_default()
+                    .push(Record { position, idx, offset });
+            }
+
+            offset += n as u64;
+            idx += 1;
+        }
+
+        Ok(NameIndex {
+            bim_filename: bim_filename.to_string(),
+            n_variants: idx,
+            bim,
+            names
+        })
+    }
+
+    pub fn write(&self, filename: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(filename)?);
+
+        writeln!(out, "#bimidx\tnames")?;
+        writeln!(out, "#n_variants\t{}", self.n_variants)?;
+        writeln!(out, "#bim_size\t{}", self.bim.size)?;
+        writeln!(out, "#bim_mtime\t{}", self.bim.mtime)?;
+
+        let mut names: Vec<&String> = self.names.keys().collect();
+        names.sort();
+        for name in names {
+            for r in self.names[name].iter() {
+                writeln!(out, "{}\t{}\t{}\t{}", name, r.position, r.idx,
+                         r.offset)?;
+            }
+        }
+
+        out.flush()
+    }
+
+    pub fn read(filename: &str, bim_filename: &str) -> io::Result<NameIndex> {
+        let mut header: HashMap<String, String> = HashMap::new();
+        let mut names: HashMap<String, Vec<Record>> = HashMap::new();
+
+        for (i, line) in BufReader::new(File::open(filename)?).lines()
+            .enumerate()
+        {
+            let line = line?;
+            let invalid = || invalid_data(format!(
+                "Invalid line {} in the name index `{}`.", i + 1, filename
+            ));
+
+            if let Some(entry) = line.strip_prefix('#') {
+                let (key, value) = entry.split_once('\t')
+                    .ok_or_else(invalid)?;
+                header.insert(key.to_string(), value.to_string());
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 4 {
+                return Err(invalid());
+            }
+
+            names.entry(fields[0].to_string())
+                .or_default()
+                .push(Record {
+                    position: fields[1].parse().map_err(|_| invalid())?,
+                    idx: fields[2].parse().map_err(|_| invalid())?,
+                    offset: fields[3].parse().map_err(|_| invalid())?
+                });
+        }
+
+        if header.get("bimidx").map(|v| v.as_str()) != Some("names") {
+            return Err(invalid_data(format!(
+                "`{}` is not a name index.", filename
+            )));
+        }
+
+        let value = |key: &str| -> io::Result<u64> {
+            header.get(key)
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| invalid_data(format!(
+                    "Missing `{}` in the header of the name index `{}`.", key,
+                    filename
+                )))
+        };
+
+        Ok(NameIndex {
+            bim_filename: bim_filename.to_string(),
+            n_variants: value("n_variants")? as u32,
+            bim: BimMetadata {
+                size: value("bim_size")?,
+                mtime: value("bim_mtime")?
+            },
+            names
+        })
+    }
+
+    // Open the name index of a BIM, building it if it doesn't exist or if
+    // the BIM changed since it was built. The index is only kept in memory
+    // if it can't be written (e.g. in a read-only directory).
+    pub fn get_or_create(filename: &str, bim_filename: &str)
+        -> io::Result<NameIndex>
+    {
+        if Path::new(filename).is_file() {
+            let index = NameIndex::read(filename, bim_filename)?;
+            if !index.is_stale()? {
+                return Ok(index);
+            }
+        }
+
+        let index = NameIndex::build(bim_filename)?;
+        if index.write(filename).is_err() {
+            let _ = fs::remove_file(filename);
+        }
+        Ok(index)
+    }
+
+    pub fn is_stale(&self) -> io::Result<bool> {
+        Ok(BimMetadata::of(&self.bim_filename)? != self.bim)
+    }
+
+    // Returns the index, variant and coded allele of the variants with the
+    // name (more than one if the name is duplicated in the BIM).
+    pub(crate) fn get_name_index_and_coded(&self, name: &str)
+        -> Vec<(u32, Variant, String)>
+    {
+        match self.names.get(name) {
+            Some(records) => {
+                read_bim_records(&self.bim_filename, records.iter().collect())
+            },
+            None => Vec::new()
+        }
+    }
+}
+
+
+
+// Description of an index on disk.
+pub struct IndexInfo {
+    pub format: IndexFormat,
+    // Files with their size.
+    pub files: Vec<(String, u64)>,
+    pub n_indexed: u32,
+    pub n_bim_variants: u32,
+    // Only known for v2 indexes.
+    pub stale: Option<bool>,
+    pub chromosomes: Vec<(String, u32)>
+}
+
+impl IndexInfo {
+    // Inconsistencies between the index and the BIM.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.n_indexed != self.n_bim_variants {
+            problems.push(format!("The index has {} variants but the BIM \
+                                   has {}.", self.n_indexed,
+                                  self.n_bim_variants));
+        }
+
+        if self.stale == Some(true) {
+            problems.push("The BIM changed since the index was built."
+                          .to_string());
+        }
+
+        problems
+    }
+}
+
+
+pub fn index_exists(prefix: &str, format: IndexFormat) -> bool {
+    Path::new(&format.filenames(prefix)[0]).is_file()
+}
+
+
+// Build (or rebuild) the index of a fileset, with its name index.
+pub fn build_index(prefix: &str, format: IndexFormat) -> io::Result<()> {
+    delete_index(prefix, format)?;
+    let bim_filename = format!("{}.bim", prefix);
+
+    match format {
+        IndexFormat::V1 => {
+            BimIndex::get_or_create_bim_index(&bim_filename)?;
+        },
+        IndexFormat::V2 => {
+            NativeBimIndex::build(&bim_filename)?
+                .write(&format.filenames(prefix)[0])?;
+        }
+    }
+
+    NameIndex::build(&bim_filename)?.write(&name_index_filename(prefix))
+}
+
+
+// Delete the files of an index and return their names.
+pub fn delete_index(prefix: &str, format: IndexFormat)
+    -> io::Result<Vec<String>>
+{
+    let mut deleted = Vec::new();
+
+    for filename in format.filenames(prefix) {
+        if Path::new(&filename).is_file() {
+            fs::remove_file(&filename)?;
+            deleted.push(filename);
+        }
+    }
+
+    Ok(deleted)
+}
+
+
+pub fn inspect_index(prefix: &str, format: IndexFormat)
+    -> io::Result<IndexInfo>
+{
+    let bim_filename = format!("{}.bim", prefix);
+    let n_bim_variants = BufReader::new(File::open(&bim_filename)?)
+        .lines()
+        .count() as u32;
+
+    let files = format.filenames(prefix)
+        .into_iter()
+        .filter_map(|f| fs::metadata(&f).ok().map(|m| (f, m.len())))
+        .collect();
+
+    let mut info = IndexInfo {
+        format,
+        files,
+        n_indexed: 0,
+        n_bim_variants,
+        stale: None,
+        chromosomes: Vec::new()
+    };
+
+    match format {
+        IndexFormat::V1 => {
+            let index = BimIndex {
+                filename: format.filenames(prefix).remove(0),
+                n_variants: n_bim_variants,
+                contigs: OnceLock::new()
+            };
+            info.n_indexed = index.count_indexed_variants()?;
+        },
+        IndexFormat::V2 => {
+            let index = NativeBimIndex::read(&format.filenames(prefix)[0],
+                                             &bim_filename)?;
+            info.n_indexed = index.count_indexed_variants();
+            info.stale = Some(index.is_stale()?);
+            info.chromosomes = index.chromosomes()
+                .into_iter()
+                .map(|(chrom, n)| (chrom.to_string(), n))
+                .collect();
+        }
+    }
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_native_index() {
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_index_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bim = dir.join("test.bim").to_str().unwrap().to_string();
+        let filename = dir.join("test.bimidx2").to_str().unwrap().to_string();
+
+        std::fs::write(&bim, "1\trs2\t0\t200\tA\tG\n\
+                              2\trs3\t0\t50\tC\tT\n\
+                              1\trs1\t0\t100\tT\tG\n").unwrap();
+
+        NativeBimIndex::build(&bim).unwrap().write(&filename).unwrap();
+        let index = NativeBimIndex::read(&filename, &bim).unwrap();
+
+        assert_eq!(index.n_variants, 3);
+        assert_eq!(index.count_indexed_variants(), 3);
+        assert_eq!(index.chromosomes(), vec![("1", 2), ("2", 1)]);
+        assert!(!index.is_stale().unwrap());
+
+        let chr1 = Chromosome::Autosome(1);
+        let region = index.get_region_index_and_coded(&chr1, 0, 1000);
+        let found: Vec<(u32, &str, &str)> = region.iter()
+            .map(|(idx, v, a1)| (*idx, v.name.as_str(), a1.as_str()))
+            .collect();
+        assert_eq!(found, vec![(2, "rs1", "T"), (0, "rs2", "A")]);
+
+        assert!(index.get_region_index_and_coded(&chr1, 150, 199).is_empty());
+        let chr3 = Chromosome::new("chr3");
+        assert!(index.get_region_index_and_coded(&chr3, 0, 100).is_empty());
+
+        std::fs::write(&bim, "1\trs2\t0\t200\tA\tG\n").unwrap();
+        assert!(index.is_stale().unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_name_index() {
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_name_index_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bim = dir.join("test.bim").to_str().unwrap().to_string();
+        let filename = dir.join("test.bimidx.names").to_str().unwrap()
+            .to_string();
+
+        std::fs::write(&bim, "1\trs2\t0\t200\tA\tG\n\
+                              2\t.\t0\t50\tC\tT\n\
+                              1\trs1\t0\t100\tT\tG\n\
+                              3\trs1\t0\t10\tA\tC\n").unwrap();
+
+        NameIndex::get_or_create(&filename, &bim).unwrap();
+        let index = NameIndex::read(&filename, &bim).unwrap();
+        assert_eq!(index.n_variants, 4);
+        assert!(!index.is_stale().unwrap());
+
+        let found = index.get_name_index_and_coded("rs2");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, 0);
+        assert_eq!(found[0].1.position, 200);
+        assert_eq!(found[0].2, "A");
+
+        // Duplicated and unnamed variants.
+        assert_eq!(index.get_name_index_and_coded("rs1").len(), 2);
+        assert!(index.get_name_index_and_coded(".").is_empty());
+        assert!(index.get_name_index_and_coded("rs3").is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+/*!
+ * IO backends to read the BED files.
+ *
+ * The BED is read with buffered reads by default, which go through the page
+ * cache of the kernel. Genome-wide scans of very large BED files then evict
+ * the cached data of the other processes of the server. With the
+ * `io-backends` feature, the BED can instead be memory-mapped (Unix) or read
+ * with direct IO (`O_DIRECT`, Linux), which bypasses the page cache: the
+ * reads are done by aligned blocks of `DIRECT_BLOCK_SIZE` bytes.
+ */
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::str::FromStr;
+#[cfg(feature = "io-backends")]
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::plink::ReadSeek;
+
+
+// Size of the reads with direct IO (a multiple of the block size of the
+// devices).
+#[cfg(all(feature = "io-backends", target_os = "linux"))]
+pub const DIRECT_BLOCK_SIZE: usize = 1 << 20;
+
+// Alignment of the offsets, lengths and buffers of the direct reads.
+#[cfg(all(feature = "io-backends", target_os = "linux"))]
+const DIRECT_ALIGNMENT: usize = 4096;
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IoBackend {
+    #[default]
+    Buffered,
+    #[cfg(all(feature = "io-backends", unix))]
+    Mmap,
+    #[cfg(all(feature = "io-backends", target_os = "linux"))]
+    Direct
+}
+
+impl FromStr for IoBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<IoBackend, String> {
+        match s {
+            "buffered" => Ok(IoBackend::Buffered),
+            #[cfg(all(feature = "io-backends", unix))]
+            "mmap" => Ok(IoBackend::Mmap),
+            #[cfg(all(feature = "io-backends", target_os = "linux"))]
+            "direct" => Ok(IoBackend::Direct),
+            _ => Err(format!("Unknown or unsupported IO backend `{}`.", s))
+        }
+    }
+}
+
+impl fmt::Display for IoBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            IoBackend::Buffered => "buffered",
+            #[cfg(all(feature = "io-backends", unix))]
+            IoBackend::Mmap => "mmap",
+            #[cfg(all(feature = "io-backends", target_os = "linux"))]
+            IoBackend::Direct => "direct"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+
+// Open a file for reading with the backend.
+pub fn open(filename: &str, backend: IoBackend)
+    -> io::Result<Box<dyn ReadSeek>>
+{
+    match backend {
+        IoBackend::Buffered => Ok(Box::new(File::open(filename)?)),
+        #[cfg(all(feature = "io-backends", unix))]
+        IoBackend::Mmap => Ok(Box::new(MmapReader::open(filename)?)),
+        #[cfg(all(feature = "io-backends", target_os = "linux"))]
+        IoBackend::Direct => Ok(Box::new(DirectReader::open(filename)?))
+    }
+}
+
+
+#[cfg(feature = "io-backends")]
+fn seek_position(len: u64, current: u64, pos: SeekFrom) -> io::Result<u64> {
+    let new_pos = match pos {
+        SeekFrom::Start(n) => Some(n),
+        SeekFrom::End(n) => len.checked_add_signed(n),
+        SeekFrom::Current(n) => current.checked_add_signed(n)
+    };
+
+    new_pos.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput,
+                       "Invalid seek to a negative or overflowing position.")
+    })
+}
+
+
+// Read-only memory map of a whole file.
+#[cfg(all(feature = "io-backends", unix))]
+pub struct MmapReader {
+    ptr: *mut libc::c_void,
+    len: usize,
+    pos: u64
+}
+
+#[cfg(all(feature = "io-backends", unix))]
+impl MmapReader {
+    pub fn open(filename: &str) -> io::Result<MmapReader> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(filename)?;
+        let len = file.metadata()?.len() as usize;
+
+        // Empty files can't be mapped.
+        if len == 0 {
+            return Ok(MmapReader { ptr: std::ptr::null_mut(), len, pos: 0 });
+        }
+
+        let ptr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ,
+                       libc::MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The pages are read in order during scans (this is only a hint).
+        unsafe {
+            libc::madvise(ptr, len, libc::MADV_SEQUENTIAL);
+        }
+
+        Ok(MmapReader { ptr, len, pos: 0 })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(all(feature = "io-backends", unix))]
+impl Drop for MmapReader {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "io-backends", unix))]
+impl Read for MmapReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.as_slice();
+        if self.pos >= data.len() as u64 {
+            return Ok(0);
+        }
+
+        let start = self.pos as usize;
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+#[cfg(all(feature = "io-backends", unix))]
+impl Seek for MmapReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = seek_position(self.len as u64, self.pos, pos)?;
+        Ok(self.pos)
+    }
+}
+
+
+// Reader of a file opened with `O_DIRECT`, by aligned blocks.
+#[cfg(all(feature = "io-backends", target_os = "linux"))]
+pub struct DirectReader {
+    file: File,
+    len: u64,
+    pos: u64,
+    // The block is `buf[offset..offset + DIRECT_BLOCK_SIZE]`, which is
+    // aligned in memory.
+    buf: Vec<u8>,
+    offset: usize,
+    // Position in the file and number of bytes of the block that is read.
+    block: Option<(u64, usize)>
+}
+
+#[cfg(all(feature = "io-backends", target_os = "linux"))]
+impl DirectReader {
+    pub fn open(filename: &str) -> io::Result<DirectReader> {
+        use std::fs::OpenOptions;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(filename)?;
+        let len = file.metadata()?.len();
+
+        let buf = vec![0; DIRECT_BLOCK_SIZE + DIRECT_ALIGNMENT];
+        let offset = buf.as_ptr().align_offset(DIRECT_ALIGNMENT);
+
+        Ok(DirectReader { file, len, pos: 0, buf, offset, block: None })
+    }
+
+    fn _load_block(&mut self, start: u64) -> io::Result<usize> {
+        use std::os::unix::fs::FileExt;
+
+        if let Some((loaded, n)) = self.block {
+            if loaded == start {
+                return Ok(n);
+            }
+        }
+
+        // Reads are only short at the end of the file.
+        let block = &mut self.buf[self.offset..][..DIRECT_BLOCK_SIZE];
+        let mut n = 0;
+        while n < DIRECT_BLOCK_SIZE {
+            match self.file.read_at(&mut block[n..], start + n as u64) {
+                Ok(0) => break,
+                Ok(read) => n += read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e)
+            }
+        }
+
+        self.block = Some((start, n));
+        Ok(n)
+    }
+}
+
+#[cfg(all(feature = "io-backends", target_os = "linux"))]
+impl Read for DirectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block_size = DIRECT_BLOCK_SIZE as u64;
+        let start = self.pos / block_size * block_size;
+        let loaded = self._load_block(start)?;
+
+        let in_block = (self.pos - start) as usize;
+        if in_block >= loaded {
+            return Ok(0);
+        }
+
+        let n = buf.len().min(loaded - in_block);
+        let block = &self.buf[self.offset + in_block..][..n];
+        buf[..n].copy_from_slice(block);
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+#[cfg(all(feature = "io-backends", target_os = "linux"))]
+impl Seek for DirectReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = seek_position(self.len, self.pos, pos)?;
+        Ok(self.pos)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek, SeekFrom};
+
+    fn available_backends() -> Vec<IoBackend> {
+        ["buffered", "mmap", "direct"].iter()
+            .filter_map(|name| name.parse().ok())
+            .collect()
+    }
+
+    #[test]
+    fn test_backends() {
+        // More than one block of direct IO, with a partial last block.
+        let expected: Vec<u8> = (0..(1 << 20) + 12345)
+            .map(|i| (i * 31 % 251) as u8)
+            .collect();
+        let filename = std::env::temp_dir()
+            .join(format!("genepa_iobackend_{}.bed", std::process::id()));
+        let filename = filename.to_str().unwrap();
+        std::fs::write(filename, &expected).unwrap();
+
+        for backend in available_backends() {
+            assert_eq!(backend.to_string().parse(), Ok(backend));
+
+            let mut reader = open(filename, backend).unwrap();
+            let mut all = Vec::new();
+            reader.read_to_end(&mut all).unwrap();
+            assert_eq!(all, expected, "{}", backend);
+
+            for &start in &[3, (1 << 20) - 5, expected.len() as u64 - 10] {
+                let mut buf = [0; 10];
+                reader.seek(SeekFrom::Start(start)).unwrap();
+                reader.read_exact(&mut buf).unwrap();
+                let start = start as usize;
+                assert_eq!(&buf[..], &expected[start..start + 10]);
+            }
+
+            assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(),
+                       expected.len() as u64);
+            assert_eq!(reader.read(&mut [0; 4]).unwrap(), 0);
+        }
+
+        std::fs::remove_file(filename).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+/*!
+ * Block jackknife of the statistics computed over the genome.
+ *
+ * The variants are partitioned in blocks of consecutive variants, large
+ * enough for the variants of different blocks to be roughly independent,
+ * and the statistic is computed again leaving out every block in turn. The
+ * variance of these leave-one-out estimates gives the standard error of the
+ * statistic, accounting for the LD between nearby variants.
+ *
+ * The statistics computed from sums over the variants (e.g. the LD score
+ * regression) can subtract the sums of a block from the total instead of
+ * being computed again, and give their leave-one-out estimates to
+ * `JackknifeEstimate::from_leave_one_out`.
+ */
+
+use std::ops::Range;
+
+use crate::core::Variant;
+use crate::windows::WindowSize;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JackknifeEstimate {
+    // Estimate using all the blocks.
+    pub estimate: f64,
+    pub se: f64,
+    // Bias-corrected estimate (the mean of the pseudo-values).
+    pub corrected: f64,
+    pub n_blocks: usize
+}
+
+impl JackknifeEstimate {
+    // From the estimate using all the blocks and the estimates leaving out
+    // every block in turn (at least 2 blocks).
+    pub fn from_leave_one_out(estimate: f64, leave_one_out: &[f64])
+        -> JackknifeEstimate
+    {
+        let n_blocks = leave_one_out.len();
+        assert!(n_blocks >= 2, "The block jackknife needs at least 2 blocks.");
+
+        let b = n_blocks as f64;
+        let mean = leave_one_out.iter().sum::<f64>() / b;
+        let ss = leave_one_out.iter().map(|x| (x - mean).powi(2)).sum::<f64>();
+
+        JackknifeEstimate {
+            estimate,
+            se: ((b - 1.0) / b * ss).sqrt(),
+            corrected: b * estimate - (b - 1.0) * mean,
+            n_blocks
+        }
+    }
+}
+
+
+// Split `n` items in (at most) `n_blocks` blocks of consecutive items of
+// about the same size.
+pub fn equal_blocks(n: usize, n_blocks: usize) -> Vec<Range<usize>> {
+    let n_blocks = n_blocks.min(n);
+    (0..n_blocks)
+        .map(|k| (k * n / n_blocks)..((k + 1) * n / n_blocks))
+        .collect()
+}
+
+
+// Split the variants in blocks of the given size, which never span two
+// chromosomes (like the windows of the windows module). The variants are
+// expected to be sorted by position within chromosomes.
+pub fn genome_blocks(variants: &[Variant], size: WindowSize)
+    -> Vec<Range<usize>>
+{
+    let mut blocks: Vec<Range<usize>> = Vec::new();
+
+    for (i, v) in variants.iter().enumerate() {
+        let same_block = blocks.last().is_some_and(|block| {
+            let first = &variants[block.start];
+            first.chrom == v.chrom && match size {
+                WindowSize::Variants(n) => block.len() < n,
+                WindowSize::Bp(bp) => first.position / bp == v.position / bp
+            }
+        });
+
+        match blocks.last_mut() {
+            Some(block) if same_block => block.end = i + 1,
+            _ => blocks.push(i..(i + 1))
+        }
+    }
+
+    blocks
+}
+
+
+// Block jackknife of a statistic of the items (e.g. of the variants). The
+// statistic is computed from all the items and without the items of every
+// block. Returns None if there are less than 2 blocks or if the statistic
+// can't be computed.
+pub fn block_jackknife<T, F>(items: &[T], blocks: &[Range<usize>],
+                             mut statistic: F) -> Option<JackknifeEstimate>
+    where F: FnMut(&[&T]) -> Option<f64>
+{
+    if blocks.len() < 2 {
+        return None;
+    }
+
+    let all: Vec<&T> = items.iter().collect();
+    let estimate = statistic(&all)?;
+
+    let leave_one_out = blocks.iter()
+        .map(|block| {
+            let kept: Vec<&T> = items[..block.start].iter()
+                .chain(items[block.end..].iter())
+                .collect();
+            statistic(&kept)
+        })
+        .collect::<Option<Vec<f64>>>()?;
+
+    Some(JackknifeEstimate::from_leave_one_out(estimate, &leave_one_out))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks() {
+        assert_eq!(equal_blocks(10, 3), vec![0..3, 3..6, 6..10]);
+        assert_eq!(equal_blocks(2, 5), vec![0..1, 1..2]);
+
+        let variants: Vec<Variant> = [("1", 100), ("1", 900), ("1", 1200),
+                                      ("2", 1300), ("2", 1400)]
+            .iter()
+            .map(|(chrom, pos)| {
+                Variant::new(format!("rs{}", pos), chrom.to_string(), *pos,
+                             ("A".to_string(), "G".to_string()))
+            })
+            .collect();
+
+        assert_eq!(genome_blocks(&variants, WindowSize::Bp(1000)),
+                   vec![0..2, 2..3, 3..5]);
+        assert_eq!(genome_blocks(&variants, WindowSize::Variants(2)),
+                   vec![0..2, 2..3, 3..5]);
+        assert_eq!(genome_blocks(&variants, WindowSize::Variants(1)).len(), 5);
+    }
+
+    #[test]
+    fn test_block_jackknife() {
+        let mean = |values: &[&f64]| {
+            if values.is_empty() {
+                None
+            } else {
+                Some(values.iter().copied().sum::<f64>() / values.len() as f64)
+            }
+        };
+
+        // With a block per value, the SE of the mean is s / sqrt(n).
+        let values = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let blocks = equal_blocks(values.len(), 5);
+        let jk = block_jackknife(&values, &blocks, mean).unwrap();
+        assert_eq!(jk.estimate, 3.0);
+        assert!((jk.se - 0.5_f64.sqrt()).abs() < 1e-12);
+        assert!((jk.corrected - 3.0).abs() < 1e-12);
+        assert_eq!(jk.n_blocks, 5);
+
+        assert!(block_jackknife(&values, &equal_blocks(5, 1), mean).is_none());
+    }
+}
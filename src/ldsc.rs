@@ -0,0 +1,371 @@
+/*!
+ * LD score regression (LDSC) of GWAS summary statistics.
+ *
+ * The LD score of a variant is the sum of its r² with the variants of a
+ * window around it (itself included). Under a polygenic model, the expected
+ * chi-square of a variant is N h² l / M + a, where l is its LD score, N the
+ * sample size, M the number of variants with an LD score and a the intercept
+ * (1 without confounding or sample overlap). The LD scores are computed from
+ * the genotypes of a reference panel and the summary statistics are matched
+ * to its variants by name and alleles.
+ *
+ * As in ldsc, the regression is weighted for the redundancy of the variants
+ * in LD (1 / l) and for the heteroskedasticity of the chi-squares, and the
+ * standard errors are estimated by a block jackknife over blocks of
+ * consecutive variants.
+ */
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::core::{Genotypes, OrderedAllelesVariant, Variant};
+use crate::jackknife::{equal_blocks, JackknifeEstimate};
+use crate::merge::harmonize;
+use crate::sumstats::SummaryStat;
+
+
+// Standardized genotypes (missing genotypes are set to the mean), or None if
+// the variant is monomorphic.
+fn standardize(g: &Genotypes) -> Option<Vec<f64>> {
+    let called: Vec<f64> = g.genotypes.iter()
+        .filter_map(|x| Some(f64::from((*x)?)))
+        .collect();
+
+    let n = called.len() as f64;
+    let mean = called.iter().sum::<f64>() / n;
+    let var = called.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+    if called.len() < 2 || var <= 0.0 {
+        return None;
+    }
+
+    let sd = var.sqrt();
+    Some(g.genotypes.iter()
+        .map(|x| match x {
+            Some(x) => (f64::from(*x) - mean) / sd,
+            None => 0.0
+        })
+        .collect())
+}
+
+
+// LD scores from the r² with the variants at most `window` bp away on the
+// same chromosome. The r² are corrected for their bias (r² - (1 - r²) /
+// (n - 2) for n samples). Monomorphic variants have no LD score. The
+// variants are expected to be sorted by position within chromosomes.
+pub fn ld_scores<I>(genotypes: I, window: u32) -> Vec<(Variant, f64)>
+    where I: IntoIterator<Item = Genotypes>
+{
+    let mut scores: Vec<(Variant, f64)> = Vec::new();
+    // Index in the scores and standardized genotypes of the variants in the
+    // window.
+    let mut buffer: VecDeque<(usize, Vec<f64>)> = VecDeque::new();
+
+    for g in genotypes {
+        let x = match standardize(&g) {
+            Some(x) => x,
+            None => continue
+        };
+        let n = x.len() as f64;
+
+        while let Some((j, _)) = buffer.front() {
+            let other = &scores[*j].0;
+            if other.chrom == g.variant.chrom &&
+                other.position.saturating_add(window) >= g.variant.position
+            {
+                break;
+            }
+            buffer.pop_front();
+        }
+
+        let mut score = 1.0;
+        for (j, y) in buffer.iter() {
+            if y.len() != x.len() {
+                panic!("Expected {} samples but `{}` has {} genotypes.",
+                       y.len(), g.variant, x.len());
+            }
+
+            let r = x.iter().zip(y.iter()).map(|(a, b)| a * b).sum::<f64>() /
+                n;
+            let r2 = if n > 2.0 {
+                r * r - (1.0 - r * r) / (n - 2.0)
+            } else {
+                r * r
+            };
+
+            score += r2;
+            scores[*j].1 += r2;
+        }
+
+        buffer.push_back((scores.len(), x));
+        scores.push((g.variant, score));
+    }
+
+    scores
+}
+
+
+// Summary statistics of a variant with its LD score.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LdscObservation {
+    pub chisq: f64,
+    pub ld_score: f64,
+    // Sample size.
+    pub n: f64
+}
+
+
+fn alleles(v: &Variant) -> (&str, &str) {
+    (v.alleles.0.as_str(), v.alleles.1.as_str())
+}
+
+
+// Match the summary statistics to the LD scores by variant name. The
+// variants must have the same alleles (possibly on the other strand), a
+// chi-square and a sample size (`n` if there is none in the summary
+// statistics). The observations are in the order of the LD scores.
+pub fn match_sumstats<I>(ld_scores: &[(Variant, f64)], sumstats: I,
+                         n: Option<f64>) -> Vec<LdscObservation>
+    where I: IntoIterator<Item = (OrderedAllelesVariant, SummaryStat)>
+{
+    let index: HashMap<&str, usize> = ld_scores.iter()
+        .enumerate()
+        .map(|(i, (v, _))| (v.name.as_str(), i))
+        .collect();
+
+    let mut matched: Vec<Option<LdscObservation>> =
+        vec![None; ld_scores.len()];
+    for (oav, stat) in sumstats {
+        let i = match index.get(oav.variant.name.as_str()) {
+            Some(&i) => i,
+            None => continue
+        };
+
+        let (v, ld_score) = &ld_scores[i];
+        if harmonize(alleles(v), alleles(&oav.variant)).is_none() {
+            continue;
+        }
+
+        if let (Some(chisq), Some(n)) = (stat.chisq(), stat.n.or(n)) {
+            matched[i] = Some(LdscObservation {
+                chisq, ld_score: *ld_score, n
+            });
+        }
+    }
+
+    matched.into_iter().flatten().collect()
+}
+
+
+// The standard errors are those of the block jackknife.
+#[derive(Clone, Copy, Debug)]
+pub struct LdscResult {
+    pub h2: JackknifeEstimate,
+    pub intercept: JackknifeEstimate,
+    pub mean_chisq: f64,
+    pub n_variants: usize
+}
+
+
+// Weighted sums for the regression of the chi-squares on N l / M.
+#[derive(Clone, Copy, Debug, Default)]
+struct RegressionSums {
+    w: f64,
+    x: f64,
+    y: f64,
+    xx: f64,
+    xy: f64
+}
+
+impl RegressionSums {
+    fn add(&mut self, w: f64, x: f64, y: f64) {
+        self.w += w;
+        self.x += w * x;
+        self.y += w * y;
+        self.xx += w * x * x;
+        self.xy += w * x * y;
+    }
+
+    fn add_sums(&mut self, other: &RegressionSums) {
+        self.w += other.w;
+        self.x += other.x;
+        self.y += other.y;
+        self.xx += other.xx;
+        self.xy += other.xy;
+    }
+
+    fn without(&self, other: &RegressionSums) -> RegressionSums {
+        RegressionSums {
+            w: self.w - other.w,
+            x: self.x - other.x,
+            y: self.y - other.y,
+            xx: self.xx - other.xx,
+            xy: self.xy - other.xy
+        }
+    }
+
+    // The intercept and the slope.
+    fn solve(&self) -> Option<(f64, f64)> {
+        let det = self.w * self.xx - self.x * self.x;
+        if det <= 0.0 {
+            return None;
+        }
+
+        let slope = (self.w * self.xy - self.x * self.y) / det;
+        Some(((self.y - slope * self.x) / self.w, slope))
+    }
+}
+
+
+// LD score regression of the observations, with `m` the number of variants
+// with an LD score (the h² is the one of these variants). The standard
+// errors are estimated by a block jackknife over `n_blocks` blocks of
+// consecutive observations. Returns None if there are less than 3
+// observations or if the LD scores are constant.
+pub fn ldsc(observations: &[LdscObservation], m: f64, n_blocks: usize)
+    -> Option<LdscResult>
+{
+    let n_obs = observations.len();
+    if n_obs < 3 || m <= 0.0 {
+        return None;
+    }
+
+    let x = |o: &LdscObservation| o.n * o.ld_score / m;
+
+    // First estimate of the h² for the heteroskedasticity weights (bounded
+    // to [0, 1] like ldsc).
+    let mut sums = RegressionSums::default();
+    for o in observations {
+        sums.add(1.0 / o.ld_score.max(1.0), x(o), o.chisq);
+    }
+    let h2 = sums.solve()?.1.clamp(0.0, 1.0);
+
+    // The variance of the chi-square is 2 (a + N h² l / M)², with the
+    // intercept a set to 1.
+    let weight = |o: &LdscObservation| {
+        let l = o.ld_score.max(1.0);
+        1.0 / (l * 2.0 * (1.0 + h2 * x(o)).powi(2))
+    };
+
+    let mut total = RegressionSums::default();
+    let blocks: Vec<RegressionSums> = equal_blocks(n_obs, n_blocks.max(2))
+        .into_iter()
+        .map(|block| {
+            let mut sums = RegressionSums::default();
+            for o in &observations[block] {
+                sums.add(weight(o), x(o), o.chisq);
+            }
+            total.add_sums(&sums);
+            sums
+        })
+        .collect();
+
+    let (intercept, h2) = total.solve()?;
+
+    // Estimates without each of the blocks.
+    let estimates = blocks.iter()
+        .map(|block| total.without(block).solve())
+        .collect::<Option<Vec<(f64, f64)>>>()?;
+
+    let jackknife = |estimate: f64, f: fn(&(f64, f64)) -> f64| {
+        let leave_one_out: Vec<f64> = estimates.iter().map(f).collect();
+        JackknifeEstimate::from_leave_one_out(estimate, &leave_one_out)
+    };
+
+    Some(LdscResult {
+        h2: jackknife(h2, |e| e.1),
+        intercept: jackknife(intercept, |e| e.0),
+        mean_chisq: observations.iter().map(|o| o.chisq).sum::<f64>() /
+            n_obs as f64,
+        n_variants: n_obs
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::genotypes_on as genotypes;
+
+    #[test]
+    fn test_ld_scores() {
+        let calls = vec![Some(0), Some(1), Some(2), Some(0), Some(1), Some(2)];
+        let scores = ld_scores(vec![
+            genotypes("1", 100, calls.clone()),
+            // Monomorphic, skipped.
+            genotypes("1", 150, vec![Some(0); 6]),
+            genotypes("1", 200, calls.clone()),
+            genotypes("1", 5000, calls.clone()),
+            genotypes("2", 5100, calls)
+        ], 1000);
+
+        let expected = [("v1_100", 2.0), ("v1_200", 2.0),
+                        ("v1_5000", 1.0), ("v2_5100", 1.0)];
+        assert_eq!(scores.len(), expected.len());
+        for ((v, l), (name, expected)) in scores.iter().zip(expected.iter()) {
+            assert_eq!(v.name, *name);
+            assert!((l - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_ldsc() {
+        let (m, n) = (1e5, 1e4);
+        let observations: Vec<LdscObservation> = (0..500)
+            .map(|k| {
+                let ld_score = 1.0 + f64::from(k % 50) / 5.0;
+                LdscObservation {
+                    chisq: 1.1 + n * 0.3 * ld_score / m, ld_score, n
+                }
+            })
+            .collect();
+
+        let result = ldsc(&observations, m, 20).unwrap();
+        assert!((result.h2.estimate - 0.3).abs() < 1e-9);
+        assert!((result.intercept.estimate - 1.1).abs() < 1e-9);
+        assert!(result.h2.se < 1e-6 && result.intercept.se < 1e-6);
+        assert_eq!((result.n_variants, result.h2.n_blocks), (500, 20));
+
+        // With noise on the chi-squares.
+        let noisy: Vec<LdscObservation> = observations.iter()
+            .enumerate()
+            .map(|(k, o)| LdscObservation {
+                chisq: o.chisq + if k % 3 == 0 { 0.2 } else { -0.1 },
+                ..*o
+            })
+            .collect();
+        let result = ldsc(&noisy, m, 20).unwrap();
+        assert!((result.h2.estimate - 0.3).abs() < 0.05);
+        assert!(result.h2.se > 0.0 && result.intercept.se > 0.0);
+
+        assert!(ldsc(&observations[..2], m, 20).is_none());
+    }
+
+    #[test]
+    fn test_match_sumstats() {
+        let variant = |name: &str, alleles: (&str, &str)| {
+            let v = Variant::new(name.to_string(), "1".to_string(), 100,
+                                 (alleles.0.to_string(),
+                                  alleles.1.to_string()));
+            OrderedAllelesVariant { variant: v, a1_idx: 0 }
+        };
+        let stat = |p: f64| SummaryStat {
+            beta: None, se: None, p: Some(p), n: None
+        };
+
+        let scores = vec![(variant("rs1", ("A", "G")).variant, 1.5),
+                          (variant("rs2", ("A", "C")).variant, 2.5),
+                          (variant("rs3", ("C", "T")).variant, 3.5)];
+        let observations = match_sumstats(&scores, vec![
+            // On the other strand.
+            (variant("rs3", ("A", "G")), stat(1.0)),
+            // Other alleles.
+            (variant("rs2", ("A", "T")), stat(1.0)),
+            (variant("rs1", ("A", "G")), stat(1.0)),
+            (variant("rs4", ("A", "G")), stat(1.0))
+        ], Some(1000.0));
+
+        assert_eq!(observations, vec![
+            LdscObservation { chisq: 0.0, ld_score: 1.5, n: 1000.0 },
+            LdscObservation { chisq: 0.0, ld_score: 3.5, n: 1000.0 }
+        ]);
+    }
+}
@@ -1,8 +1,78 @@
 mod core;
 mod c_api;
 
+pub mod annotation;
+pub mod append;
+pub mod assoc;
+pub mod bcf;
+pub mod bgen;
+pub mod checksum;
+pub mod cluster;
+#[cfg(feature = "columnar")]
+pub mod columnar;
+pub mod config;
+pub mod convert;
+pub mod covariates;
+#[cfg(feature = "encryption")]
+pub mod crypto;
+pub mod cv;
+pub mod error;
+pub mod export;
+pub mod family;
+pub mod fasta;
+pub mod genes;
+pub mod grm;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod ibs;
+pub mod impute2;
+pub mod index;
+pub mod iobackend;
+pub mod jackknife;
+pub mod ldsc;
+pub mod liftover;
+pub mod linalg;
+pub mod merge;
+pub mod minimac;
+pub mod multiplink;
+#[cfg(feature = "object-store")]
+pub mod objectstore;
+pub mod outliers;
+pub mod pca;
+pub mod ped;
+pub mod pgen;
 pub mod plink;
+pub mod qc;
+pub mod raw;
+pub mod regions;
+pub mod roundtrip;
+pub mod sampling;
+pub mod score;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod sexchrom;
+pub mod simulate;
+pub mod source;
+pub mod stats;
+pub mod store;
+pub mod sumstats;
+#[cfg(test)]
+pub(crate) mod test_util;
+pub mod tped;
+pub mod units;
 pub mod utils;
+pub mod vcf;
+pub mod visitor;
+pub mod windows;
+pub mod zarr;
 
 pub use crate::c_api::*;
-pub use crate::core::{Variant, OrderedAllelesVariant};
+pub use crate::core::{Chromosome, Variant, VariantBuilder, VariantError,
+                      VariantKind, OrderedAllelesVariant, Genotypes, Dosages,
+                      Haplotypes, MultiAllelicVariant,
+                      MultiAllelicGenotypes, Sample, Sex, HaploidHets,
+                      HeterozygousHaploidError, MissingPolicy,
+                      MissingGenotypesError,
+                      ReferenceSequence, is_haploid_chromosome, VarFieldIdx,
+                      DelimitedVariantsReader};
+pub use crate::error::GenepaError;
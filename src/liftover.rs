@@ -0,0 +1,410 @@
+/*!
+ * Liftover of variants between genome builds using UCSC chain files.
+ *
+ * A chain file is a list of chains, each with a header line
+ * (`chain score tName tSize tStrand tStart tEnd qName qSize qStrand qStart
+ * qEnd id`) followed by aligned blocks (`size dt dq`, the last block only
+ * has a size). Coordinates are 0-based, half-open and, for chains on the
+ * reverse strand of the query, relative to the end of the query chromosome.
+ * Chromosome names are normalized, so `chr1` in the chain matches `1` in a
+ * BIM.
+ *
+ * Like the UCSC liftOver tool, a position that maps to multiple places is
+ * not lifted. Alleles of variants lifted to the reverse strand are
+ * complemented; as the position of an indel on the other strand depends on
+ * the alleles, indels lifted to the reverse strand are dropped.
+ */
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::core::{complement, normalize_chromosome, Chromosome, Genotypes,
+                  OrderedAllelesVariant, Variant, VariantKind};
+use crate::plink::{read_fam, BedReader, BimReader, PlinkWriter};
+use crate::utils::open_text_file;
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Liftover {
+    Mapped { chrom: String, position: u32, reverse: bool },
+    Unmapped,
+    // The position maps to more than one place.
+    Multiple
+}
+
+
+// An aligned block of a chain.
+struct Block {
+    t_start: u32,
+    t_end: u32,
+    q_chrom: usize,
+    q_start: u32,
+    q_size: u32,
+    reverse: bool
+}
+
+
+fn normalize(chrom: &str) -> String {
+    normalize_chromosome(chrom).unwrap_or_else(|_| chrom.to_string())
+}
+
+
+pub struct ChainMap {
+    // Blocks sorted by start, for every target chromosome.
+    blocks: HashMap<String, Vec<Block>>,
+    // Length of the longest block, to bound the search of a position.
+    max_block_length: u32,
+    query_chromosomes: Vec<String>
+}
+
+impl ChainMap {
+    // The chain file can be gzipped.
+    pub fn new(filename: &str) -> ChainMap {
+        ChainMap::from_reader(open_text_file(filename))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> ChainMap {
+        let mut blocks: HashMap<String, Vec<Block>> = HashMap::new();
+        let mut query_chromosomes: Vec<String> = Vec::new();
+        let mut max_block_length = 0;
+
+        // Current chain: target chromosome, query chromosome index, query
+        // size, strand and the positions of the next block.
+        let mut chain: Option<(String, usize, u32, bool, u32, u32)> = None;
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.expect("Could not read the chain file.");
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            let parse = |s: &str| -> u32 {
+                s.parse().unwrap_or_else(|_| {
+                    panic!("Invalid value `{}` on line {} of the chain \
+                            file.", s, i + 1)
+                })
+            };
+
+            if fields.is_empty() || fields[0].starts_with('#') {
+                continue;
+            }
+
+            if fields[0] == "chain" {
+                if fields.len() < 12 {
+                    panic!("Invalid chain header on line {} of the chain \
+                            file.", i + 1);
+                }
+                if fields[4] != "+" {
+                    panic!("Chains on the reverse strand of the target are \
+                            not supported (line {}).", i + 1);
+                }
+
+                let q_name = normalize(fields[7]);
+                let q_chrom = match query_chromosomes.iter()
+                    .position(|c| *c == q_name)
+                {
+                    Some(idx) => idx,
+                    None => {
+                        query_chromosomes.push(q_name);
+                        query_chromosomes.len() - 1
+                    }
+                };
+
+                chain = Some((normalize(fields[2]), q_chrom, parse(fields[8]),
+                              fields[9] == "-", parse(fields[5]),
+                              parse(fields[10])));
+                continue;
+            }
+
+            let (t_chrom, q_chrom, q_size, reverse, t_pos, q_pos) =
+                chain.as_mut().unwrap_or_else(|| {
+                    panic!("Alignment data without a chain header on line {} \
+                            of the chain file.", i + 1)
+                });
+
+            let size = parse(fields[0]);
+            blocks.entry(t_chrom.clone()).or_default().push(Block {
+                t_start: *t_pos,
+                t_end: *t_pos + size,
+                q_chrom: *q_chrom,
+                q_start: *q_pos,
+                q_size: *q_size,
+                reverse: *reverse
+            });
+            max_block_length = max_block_length.max(size);
+
+            match fields.len() {
+                1 => chain = None,
+                3 => {
+                    *t_pos += size + parse(fields[1]);
+                    *q_pos += size + parse(fields[2]);
+                },
+                n => panic!("Expected 1 or 3 columns on line {} of the chain \
+                             file, got {}.", i + 1, n)
+            }
+        }
+
+        for chrom_blocks in blocks.values_mut() {
+            chrom_blocks.sort_by_key(|b| b.t_start);
+        }
+
+        ChainMap { blocks, max_block_length, query_chromosomes }
+    }
+
+    // Lift a 1-based position.
+    pub fn lift(&self, chrom: &str, position: u32) -> Liftover {
+        let blocks = match self.blocks.get(&normalize(chrom)) {
+            Some(blocks) if position > 0 => blocks,
+            _ => return Liftover::Unmapped
+        };
+        let pos = position - 1;
+
+        let end = blocks.partition_point(|b| b.t_start <= pos);
+        let mut hits: Vec<Liftover> = Vec::new();
+
+        for b in blocks[..end].iter().rev() {
+            if b.t_start + self.max_block_length <= pos {
+                break;
+            }
+            if pos >= b.t_end {
+                continue;
+            }
+
+            let offset = b.q_start + (pos - b.t_start);
+            let hit = Liftover::Mapped {
+                chrom: self.query_chromosomes[b.q_chrom].clone(),
+                position: if b.reverse { b.q_size - offset } else {
+                    offset + 1
+                },
+                reverse: b.reverse
+            };
+
+            if !hits.contains(&hit) {
+                hits.push(hit);
+            }
+        }
+
+        match hits.len() {
+            0 => Liftover::Unmapped,
+            1 => hits.remove(0),
+            _ => Liftover::Multiple
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnmappedReason {
+    Unmapped,
+    Multiple,
+    ReverseStrandIndel
+}
+
+impl UnmappedReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UnmappedReason::Unmapped => "unmapped",
+            UnmappedReason::Multiple => "multiple",
+            UnmappedReason::ReverseStrandIndel => "reverse_strand_indel"
+        }
+    }
+}
+
+
+// Lift a variant. The coded allele is complemented with the alleles if the
+// variant is lifted to the reverse strand.
+pub fn lift_variant(chains: &ChainMap, v: &Variant, coded: &str)
+    -> Result<(Variant, String), UnmappedReason>
+{
+    let (chrom, position, reverse) = match chains.lift(&v.chrom.to_string(),
+                                                       v.position)
+    {
+        Liftover::Mapped { chrom, position, reverse } => {
+            (chrom, position, reverse)
+        },
+        Liftover::Unmapped => return Err(UnmappedReason::Unmapped),
+        Liftover::Multiple => return Err(UnmappedReason::Multiple)
+    };
+
+    if !reverse {
+        let lifted = Variant::new(v.name.clone(), chrom, position,
+                                  v.alleles.clone());
+        return Ok((lifted, coded.to_string()));
+    }
+
+    if v.kind() != VariantKind::Snp {
+        return Err(UnmappedReason::ReverseStrandIndel);
+    }
+
+    let mut lifted = Variant::new(v.name.clone(), chrom, position,
+                                  v.alleles.clone());
+    lifted.complement_alleles();
+    Ok((lifted, complement(&coded.to_string())))
+}
+
+
+pub struct LiftoverReport {
+    pub n_variants: u32,
+    // Variants (with their original coordinates) that were not lifted.
+    pub unmapped: Vec<(Variant, UnmappedReason)>
+}
+
+impl LiftoverReport {
+    pub fn write_unmapped<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "variant\tchrom\tpos\treason")?;
+
+        for (v, reason) in self.unmapped.iter() {
+            writeln!(out, "{}\t{}\t{}\t{}", v.name, v.chrom, v.position,
+                     reason.as_str())?;
+        }
+
+        Ok(())
+    }
+}
+
+
+// Lift the variants of a plink fileset and write them to `out_prefix`. The
+// lifted variants are sorted by position, with the chromosomes in order of
+// appearance.
+pub fn liftover_plink(prefix: &str, chains: &ChainMap, out_prefix: &str)
+    -> io::Result<LiftoverReport>
+{
+    let variants: Vec<OrderedAllelesVariant> =
+        BimReader::new(&format!("{}.bim", prefix))?
+            .collect::<Result<_, _>>()?;
+    let samples = read_fam(&format!("{}.fam", prefix))?;
+
+    let mut bed = BedReader::new(&format!("{}.bed", prefix),
+                                 samples.len() as u32, variants.len() as u32)?;
+
+    let mut lifted: Vec<(u32, Variant, String)> = Vec::new();
+    let mut unmapped = Vec::new();
+    let mut chrom_order: HashMap<Chromosome, usize> = HashMap::new();
+
+    for (idx, v) in variants.iter().enumerate() {
+        let coded = if v.a1_idx == 0 {
+            &v.variant.alleles.0
+        } else {
+            &v.variant.alleles.1
+        };
+
+        match lift_variant(chains, &v.variant, coded) {
+            Ok((variant, coded)) => {
+                let n_chrom = chrom_order.len();
+                chrom_order.entry(variant.chrom.clone())
+                    .or_insert(n_chrom);
+                lifted.push((idx as u32, variant, coded));
+            },
+            Err(reason) => unmapped.push((v.variant.clone(), reason))
+        }
+    }
+
+    lifted.sort_by_key(|(_, v, _)| {
+        (chrom_order[&v.chrom], v.position)
+    });
+
+    let mut writer = PlinkWriter::new(out_prefix, &samples)?;
+    for (idx, variant, coded) in lifted {
+        let calls = bed.read_variants(idx, 1).remove(0);
+        writer.write(&Genotypes::new(variant, calls, &coded))?;
+    }
+
+    Ok(LiftoverReport { n_variants: writer.finish()?, unmapped })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Sample, Sex};
+
+    // chr1:100-200 is shifted by 1000 and chr1:300-400 maps to the reverse
+    // strand of chr2 (size 10000). chr1:150-160 maps to two places.
+    const CHAIN: &str = "\
+chain 1000 chr1 5000 + 100 200 chr1 6000 + 1100 1200 1
+100
+
+chain 1000 chr1 5000 + 300 400 chr2 10000 - 500 610 2
+50 0 10
+50
+
+chain 10 chr1 5000 + 150 160 chr3 1000 + 0 10 3
+10
+";
+
+    #[test]
+    fn test_lift() {
+        let chains = ChainMap::from_reader(CHAIN.as_bytes());
+
+        assert_eq!(chains.lift("1", 101), Liftover::Mapped {
+            chrom: "1".to_string(), position: 1101, reverse: false
+        });
+        assert_eq!(chains.lift("chr1", 200), Liftover::Mapped {
+            chrom: "1".to_string(), position: 1200, reverse: false
+        });
+        assert_eq!(chains.lift("1", 155), Liftover::Multiple);
+        assert_eq!(chains.lift("1", 201), Liftover::Unmapped);
+        assert_eq!(chains.lift("2", 101), Liftover::Unmapped);
+
+        // 0-based 300 is 500 on the reverse strand, i.e. 10000 - 500.
+        assert_eq!(chains.lift("1", 301), Liftover::Mapped {
+            chrom: "2".to_string(), position: 9500, reverse: true
+        });
+        // The second block skips 10 bases of the query.
+        assert_eq!(chains.lift("1", 351), Liftover::Mapped {
+            chrom: "2".to_string(), position: 9440, reverse: true
+        });
+    }
+
+    #[test]
+    fn test_liftover_plink() {
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_liftover_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let samples: Vec<Sample> = ["s1", "s2"].iter()
+            .map(|iid| Sample::new(iid.to_string(), iid.to_string(),
+                                   Sex::Unknown))
+            .collect();
+
+        let genotypes = |name: &str, pos: u32, alleles: (&str, &str),
+                         calls: Vec<Option<u8>>| {
+            let v = Variant::new(name.to_string(), "1".to_string(), pos,
+                                 (alleles.0.to_string(),
+                                  alleles.1.to_string()));
+            Genotypes::new(v, calls, alleles.0)
+        };
+
+        let mut writer = PlinkWriter::new(&prefix("a"), &samples).unwrap();
+        for g in [
+            genotypes("rs1", 301, ("A", "G"), vec![Some(0), Some(1)]),
+            genotypes("rs2", 101, ("C", "T"), vec![Some(2), None]),
+            genotypes("rs3", 155, ("A", "C"), vec![Some(1), Some(1)]),
+            genotypes("rs4", 302, ("AT", "A"), vec![Some(1), Some(1)])
+        ] {
+            writer.write(&g).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let chains = ChainMap::from_reader(CHAIN.as_bytes());
+        let report = liftover_plink(&prefix("a"), &chains, &prefix("b"))
+            .unwrap();
+        assert_eq!(report.n_variants, 2);
+
+        let reasons: Vec<UnmappedReason> = report.unmapped.iter()
+            .map(|(_, reason)| *reason)
+            .collect();
+        assert_eq!(reasons, vec![UnmappedReason::Multiple,
+                                 UnmappedReason::ReverseStrandIndel]);
+
+        let bim = std::fs::read_to_string(format!("{}.bim", prefix("b")))
+            .unwrap();
+        assert_eq!(bim, "2\trs1\t0\t9500\tT\tC\n1\trs2\t0\t1101\tC\tT\n");
+
+        let mut bed = BedReader::new(&format!("{}.bed", prefix("b")), 2, 2)
+            .unwrap();
+        assert_eq!(bed.read_variants(0, 2),
+                   vec![vec![Some(0), Some(1)], vec![Some(2), None]]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
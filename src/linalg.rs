@@ -0,0 +1,373 @@
+/*!
+ * Small dense linear algebra routines used by the statistical modules.
+ *
+ * The problems solved here are small (e.g. a handful of covariates), so
+ * simple direct methods are sufficient.
+ */
+
+use ndarray::{Array1, Array2, Axis, s, stack};
+
+
+// Solve the linear system Ax = b using Gaussian elimination with partial
+// pivoting. Returns None if A is singular.
+pub fn solve(a: &Array2<f64>, b: &Array1<f64>) -> Option<Array1<f64>> {
+    let n = a.rows();
+    assert_eq!(n, a.cols(), "Expected a square matrix.");
+    assert_eq!(n, b.len(), "Dimension mismatch between A and b.");
+
+    let mut m = a.clone();
+    let mut x = b.clone();
+
+    for col in 0..n {
+        // Find the pivot.
+        let pivot = (col..n)
+            .max_by(|&i, &j| {
+                m[[i, col]].abs().partial_cmp(&m[[j, col]].abs()).unwrap()
+            })
+            .unwrap();
+
+        if m[[pivot, col]].abs() < 1e-12 {
+            return None;
+        }
+
+        if pivot != col {
+            for k in 0..n {
+                m.swap([pivot, k], [col, k]);
+            }
+            x.swap(pivot, col);
+        }
+
+        for row in (col + 1)..n {
+            let factor = m[[row, col]] / m[[col, col]];
+            if factor == 0.0 {
+                continue;
+            }
+
+            for k in col..n {
+                m[[row, k]] -= factor * m[[col, k]];
+            }
+            x[row] -= factor * x[col];
+        }
+    }
+
+    // Back substitution.
+    for row in (0..n).rev() {
+        let mut acc = x[row];
+        for k in (row + 1)..n {
+            acc -= m[[row, k]] * x[k];
+        }
+        x[row] = acc / m[[row, row]];
+    }
+
+    Some(x)
+}
+
+
+// Inverse of a square matrix (None if singular).
+pub fn invert(a: &Array2<f64>) -> Option<Array2<f64>> {
+    let n = a.rows();
+    let mut inv = Array2::zeros((n, n));
+
+    for j in 0..n {
+        let mut e = Array1::zeros(n);
+        e[j] = 1.0;
+
+        let col = solve(a, &e)?;
+        inv.column_mut(j).assign(&col);
+    }
+
+    Some(inv)
+}
+
+
+// Prepend a column of ones to the design matrix.
+pub fn with_intercept(x: &Array2<f64>) -> Array2<f64> {
+    let ones = Array2::ones((x.rows(), 1));
+    stack(Axis(1), &[ones.view(), x.view()]).unwrap()
+}
+
+
+// Ordinary least squares coefficients (None if X'X is singular).
+pub fn ols(x: &Array2<f64>, y: &Array1<f64>) -> Option<Array1<f64>> {
+    let xt = x.t();
+    solve(&xt.dot(x), &xt.dot(y))
+}
+
+
+// Coefficient of determination of the OLS fit of y on X. X should contain
+// an intercept column.
+pub fn r_squared(x: &Array2<f64>, y: &Array1<f64>) -> Option<f64> {
+    let beta = ols(x, y)?;
+    let fitted = x.dot(&beta);
+
+    let mean = y.mean_axis(Axis(0))[[]];
+    let ss_tot: f64 = y.iter().map(|v| (v - mean).powi(2)).sum();
+    let ss_res: f64 = y.iter()
+        .zip(fitted.iter())
+        .map(|(v, f)| (v - f).powi(2))
+        .sum();
+
+    Some(1.0 - ss_res / ss_tot)
+}
+
+
+// Eigendecomposition of a symmetric matrix using the cyclic Jacobi method.
+// Returns the eigenvalues in decreasing order and the corresponding
+// eigenvectors as columns.
+pub fn symmetric_eigen(a: &Array2<f64>) -> (Array1<f64>, Array2<f64>) {
+    let n = a.rows();
+    assert_eq!(n, a.cols(), "Expected a square matrix.");
+
+    let mut m = a.clone();
+    let mut v: Array2<f64> = Array2::eye(n);
+
+    let total: f64 = a.iter().map(|x| x * x).sum();
+
+    for _sweep in 0..100 {
+        let off_diag: f64 = (0..n)
+            .flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| m[[i, j]].powi(2))
+            .sum();
+
+        if off_diag <= 1e-24 * total {
+            break;
+        }
+
+        for p in 0..n {
+            for q in (p + 1)..n {
+                if m[[p, q]].abs() < 1e-300 {
+                    continue;
+                }
+
+                let theta = (m[[q, q]] - m[[p, p]]) / (2.0 * m[[p, q]]);
+                let t = theta.signum() /
+                        (theta.abs() + (theta * theta + 1.0).sqrt());
+                let t = if theta == 0.0 { 1.0 } else { t };
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                // Apply the rotation to the rows and columns p and q.
+                for k in 0..n {
+                    let mkp = m[[k, p]];
+                    let mkq = m[[k, q]];
+                    m[[k, p]] = c * mkp - s * mkq;
+                    m[[k, q]] = s * mkp + c * mkq;
+                }
+
+                for k in 0..n {
+                    let mpk = m[[p, k]];
+                    let mqk = m[[q, k]];
+                    m[[p, k]] = c * mpk - s * mqk;
+                    m[[q, k]] = s * mpk + c * mqk;
+                }
+
+                for k in 0..n {
+                    let vkp = v[[k, p]];
+                    let vkq = v[[k, q]];
+                    v[[k, p]] = c * vkp - s * vkq;
+                    v[[k, q]] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    sort_eigen(m.diag().to_owned(), v)
+}
+
+
+fn sort_eigen(values: Array1<f64>, vectors: Array2<f64>)
+    -> (Array1<f64>, Array2<f64>)
+{
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&i, &j| values[j].partial_cmp(&values[i]).unwrap());
+
+    let sorted_values = Array1::from_vec(
+        order.iter().map(|&i| values[i]).collect()
+    );
+
+    let mut sorted_vectors = Array2::zeros(vectors.dim());
+    for (new, &old) in order.iter().enumerate() {
+        sorted_vectors.column_mut(new).assign(&vectors.column(old));
+    }
+
+    (sorted_values, sorted_vectors)
+}
+
+
+// Orthonormalize the columns of the matrix in place (modified Gram-Schmidt).
+// The projections are done twice because a single pass loses orthogonality
+// when the columns are nearly collinear (as in subspace iteration). Columns
+// that are linearly dependent on the previous ones are replaced by a
+// canonical basis vector so that the result is always orthonormal.
+pub fn orthonormalize(q: &mut Array2<f64>) {
+    let n = q.rows();
+
+    let project_out = |q: &mut Array2<f64>, j: usize| {
+        for _ in 0..2 {
+            for i in 0..j {
+                let proj = q.column(i).dot(&q.column(j));
+                let col_i = q.column(i).to_owned();
+                q.column_mut(j).scaled_add(-proj, &col_i);
+            }
+        }
+
+        q.column(j).dot(&q.column(j)).sqrt()
+    };
+
+    for j in 0..q.cols() {
+        let original_norm = q.column(j).dot(&q.column(j)).sqrt();
+        let mut norm = project_out(q, j);
+
+        let mut basis = j;
+        while norm <= 1e-10 * original_norm.max(1.0) && basis < j + n {
+            q.column_mut(j).fill(0.0);
+            q[[basis % n, j]] = 1.0;
+            norm = project_out(q, j);
+            basis += 1;
+        }
+
+        q.column_mut(j).mapv_inplace(|x| x / norm);
+    }
+}
+
+
+// Deterministic orthonormal starting subspace of the subspace iterations.
+pub(crate) fn starting_subspace(n: usize, n_vectors: usize) -> Array2<f64> {
+    let mut q = Array2::from_shape_fn((n, n_vectors), |(i, j)| {
+        (((i + 1) * (j + 7)) as f64).sin()
+    });
+    orthonormalize(&mut q);
+    q
+}
+
+
+// Leading k eigenpairs of a symmetric matrix using subspace iteration
+// followed by a Rayleigh-Ritz projection. This is much faster than the full
+// decomposition when k is small.
+pub fn top_eigen(a: &Array2<f64>, k: usize) -> (Array1<f64>, Array2<f64>) {
+    let n = a.rows();
+    assert!(k <= n, "Can't compute more eigenvectors than rows.");
+
+    // Oversampling helps the convergence of the last requested vectors.
+    let n_vectors = (k + 10).min(n);
+
+    let mut q = starting_subspace(n, n_vectors);
+
+    let mut previous = Array1::zeros(n_vectors);
+    for _ in 0..1000 {
+        q = a.dot(&q);
+        orthonormalize(&mut q);
+
+        // Convergence is assessed on the Ritz values which converge much
+        // faster than the individual vectors when eigenvalues are close.
+        let projected = q.t().dot(a).dot(&q);
+        let (values, _) = symmetric_eigen(&projected);
+
+        // Only the requested eigenvalues need to converge.
+        let scale = values[0].abs().max(1e-300);
+        let converged = values.iter()
+            .zip(previous.iter())
+            .take(k)
+            .all(|(x, y): (&f64, &f64)| (x - y).abs() <= 1e-13 * scale);
+
+        previous = values;
+        if converged {
+            break;
+        }
+    }
+
+    let projected = q.t().dot(a).dot(&q);
+    let (values, vectors) = symmetric_eigen(&projected);
+    let vectors = q.dot(&vectors);
+
+    (
+        values.slice(s![..k]).to_owned(),
+        vectors.slice(s![.., ..k]).to_owned()
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solve() {
+        let a = Array2::from_shape_vec(
+            (3, 3),
+            vec![0.0, 2.0, 1.0,
+                 1.0, 1.0, 0.0,
+                 3.0, 0.0, 1.0]
+        ).unwrap();
+        let b = Array1::from_vec(vec![7.0, 3.0, 6.0]);
+
+        let x = solve(&a, &b).unwrap();
+        for (obs, exp) in x.iter().zip([1.0, 2.0, 3.0].iter()) {
+            assert!((obs - exp).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_symmetric_eigen() {
+        let a = Array2::from_shape_vec(
+            (3, 3),
+            vec![2.0, 1.0, 0.0,
+                 1.0, 2.0, 0.0,
+                 0.0, 0.0, 5.0]
+        ).unwrap();
+
+        let (values, vectors) = symmetric_eigen(&a);
+        for (obs, exp) in values.iter().zip([5.0, 3.0, 1.0].iter()) {
+            assert!((obs - exp).abs() < 1e-10);
+        }
+
+        // A v = lambda v
+        for j in 0..3 {
+            let v = vectors.column(j);
+            let av = a.dot(&v);
+            for i in 0..3 {
+                assert!((av[i] - values[j] * v[i]).abs() < 1e-10);
+            }
+        }
+    }
+
+    #[test]
+    fn test_top_eigen() {
+        let x = Array2::from_shape_fn((30, 30), |(i, j)| {
+            ((i * 7 + j * 3) % 11) as f64
+        });
+        let a = x.t().dot(&x);
+
+        let (all_values, _) = symmetric_eigen(&a);
+        let (values, vectors) = top_eigen(&a, 3);
+
+        for j in 0..3 {
+            assert!((values[j] - all_values[j]).abs() <
+                    1e-8 * all_values[0]);
+
+            let v = vectors.column(j);
+            let residual = &a.dot(&v) - &(&v * values[j]);
+            assert!(residual.iter().all(|r| r.abs() < 1e-6 * all_values[0]));
+        }
+    }
+
+    #[test]
+    fn test_singular() {
+        let a = Array2::from_shape_vec((2, 2), vec![1.0, 2.0, 2.0, 4.0])
+            .unwrap();
+        assert!(invert(&a).is_none());
+    }
+
+    #[test]
+    fn test_ols() {
+        let x = with_intercept(
+            &Array2::from_shape_vec((4, 1), vec![1.0, 2.0, 3.0, 4.0]).unwrap()
+        );
+        let y = Array1::from_vec(vec![3.0, 5.0, 7.0, 9.0]);
+
+        let beta = ols(&x, &y).unwrap();
+        assert!((beta[0] - 1.0).abs() < 1e-12);
+        assert!((beta[1] - 2.0).abs() < 1e-12);
+        assert!((r_squared(&x, &y).unwrap() - 1.0).abs() < 1e-12);
+    }
+}
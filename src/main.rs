@@ -1,13 +1,157 @@
-mod core;
-mod plink;
+mod cli;
+mod pipeline;
+
+use std::env;
+use std::process;
+
+
+const USAGE: &str = "\
+Usage: genepa [--threads n] <command> [options]
+
+The analyses of mds, outliers, pca, assoc, grm and heritability only use
+the autosomes unless --all-chromosomes is given. With --exclude-regions,
+mds, outliers, pca, grm and heritability also skip the long-range LD
+regions (high-ld) or the regions of a BED file.
+
+On the X chromosome, freq, assoc and the QC of run treat the males as
+haploid, except for the variants in the PARs with --split-x (or on XY).
+
+--threads sets the number of threads used to decode the genotypes (built
+with the parallel feature), by default the CPUs available to the process,
+within the limits of its cgroup.
+
+Commands:
+  ld    LD between a variant and its neighbours (plink --r2 columns)
+          --bfile prefix --ld-snp name|chr:pos:a1:a2 [--window-kb 1000]
+          [--ld-window-r2 0.2] [--out file]
+  freq  Allele frequencies (plink .frq, or .frq.strat with groups)
+          --bfile prefix [--within groups]
+          [--split-x [--build grch37|grch38]] [--out file]
+  assoc Association tests (plink2 --glm columns)
+          --bfile prefix --pheno file [--pheno-name name] [--covar file]
+          [--model linear|logistic] [--within-family] [--all-chromosomes]
+          [--exclude-chr chrom ...] [--split-x]
+          [--flag-regions complex|file.bed] [--build grch37|grch38]
+          --out prefix
+  grm   GRM written to disk by bands of rows, for large sample sizes
+          (GCTA {out}.grm.bin, .grm.N.bin and .grm.id; pairs in {out}.related)
+          --bfile prefix [--band-rows 10000] [--related 0.125]
+          [--all-chromosomes] [--exclude-chr chrom ...]
+          [--exclude-regions high-ld|file.bed [--build grch37|grch38]]
+          --out prefix
+  heritability SNP-heritability by Haseman-Elston regression on the GRM
+          --bfile prefix --pheno file [--pheno-name name]
+          [--all-chromosomes] [--exclude-chr chrom ...]
+          [--exclude-regions high-ld|file.bed [--build grch37|grch38]]
+          [--out file]
+  ldsc  LD score regression of summary statistics (h2 and intercept)
+          --bfile prefix --sumstats file [--n n] [--window-kb 1000]
+          [--blocks 200] [--out file]
+  tdt   Transmission disequilibrium test of the trios (plink .tdt)
+          --bfile prefix [--out file]
+  filter Keep the variants passing MAF and missingness thresholds
+          --bfile prefix [--maf 0.01] [--geno 0.05]
+          [--freq-estimator observed|all|pseudo[:c]|shrink:p:w]
+          (--out prefix | --dry-run)
+  mds   MDS of the IBS distances ({out}.mds, and {out}.mibs)
+          --bfile prefix [--dims 4] [--clusters k]
+          [--linkage single|complete|average] [--all-chromosomes]
+          [--exclude-chr chrom ...]
+          [--exclude-regions high-ld|file.bed [--build grch37|grch38]]
+          --out prefix
+  outliers Samples to exclude (QC metrics and PCs), with the reasons
+          --bfile prefix [--pcs 4] [--max-z 4] [--min-p 1e-6]
+          [--all-chromosomes] [--exclude-chr chrom ...]
+          [--exclude-regions high-ld|file.bed [--build grch37|grch38]]
+          [--out file]
+  pca   PCs without the GRM, for large datasets ({out}.eigenvec and
+          {out}.eigenval); more passes over the fileset are more accurate
+          --bfile prefix [--pcs 10] [--passes 6] [--all-chromosomes]
+          [--exclude-chr chrom ...]
+          [--exclude-regions high-ld|file.bed [--build grch37|grch38]]
+          --out prefix
+  merge Merge and harmonize plink filesets (mismatches in {out}.mismatches)
+          --bfiles prefix1 prefix2 ... --out prefix
+  convert Convert a dataset (plink, pgen, ped, raw, VCF, BCF or Zarr; a
+          glob such as `chr*` reads plink filesets split by chromosome)
+          --in path [--format plink|pgen|vcf|bgen|zarr] --out prefix
+          [--keep file] [--extract file]
+  export-long Genotypes of a region, variants or gene, one row per sample
+          --in path (--region chrom:start-end | --extract file |
+          --gene name --gtf file [--flank 0]) [--out file]
+  rename Name the BIM variants without names using an annotation VCF
+          --bim file --annotation file.vcf.gz [--out file]
+  check-ref Variants whose alleles don't match the reference FASTA
+          --bim file --fasta ref.fa [--out file]
+  append Append the samples or variants of a fileset in place
+          --bfile prefix (--samples prefix | --variants prefix)
+  liftover Lift a fileset to another build (unlifted in {out}.unmapped)
+          --bfile prefix --chain file --out prefix
+  simulate Simulate a fileset under Hardy-Weinberg equilibrium
+          --n-samples n --n-variants n [--maf-dist uniform|neutral|0.2]
+          [--seed 1] --out prefix
+  sample Random subset of the variants (all, a fraction or a number) and
+          samples of a fileset, reproducible given the seed
+          --bfile prefix [--variants all] [--n-samples n] [--seed 1]
+          --out prefix
+  verify-roundtrip Write a fileset, read it back and compare everything
+          --bfile prefix [--format plink|vcf|bgen] --out prefix
+  run   Run the steps of a pipeline (TOML) in a single pass over the input
+          --config pipeline.toml [--dry-run]
+  index Build, validate, inspect or delete the BIM index of a fileset
+          <build|validate|inspect|delete> --bfile prefix [--force]
+          [--format v1|v2]
+";
 
-use crate::plink::PlinkReader;
 
 fn main() {
-    // For fun read a plink file and compute all MAFs.
-    let reader = PlinkReader::new("/Users/legaultmarc/projects/StatGen/grs/test_data/big");
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    if args.first().map(|arg| arg.as_str()) == Some("--threads") {
+        match args.get(1).and_then(|n| n.parse::<usize>().ok()) {
+            Some(n) if n > 0 => rsgeneparselib::config::set_threads(n),
+            _ => {
+                eprintln!("Error: Invalid value for --threads.\n\n{}", USAGE);
+                process::exit(1);
+            }
+        }
+        args.drain(..2);
+    }
+
+    let result = match args.first().map(|command| command.as_str()) {
+        Some("ld") => cli::ld(&args[1..]),
+        Some("freq") => cli::freq(&args[1..]),
+        Some("filter") => cli::filter(&args[1..]),
+        Some("mds") => cli::mds(&args[1..]),
+        Some("outliers") => cli::outliers(&args[1..]),
+        Some("pca") => cli::pca(&args[1..]),
+        Some("merge") => cli::merge(&args[1..]),
+        Some("convert") => cli::convert(&args[1..]),
+        Some("export-long") => cli::export_long(&args[1..]),
+        Some("assoc") => cli::assoc(&args[1..]),
+        Some("grm") => cli::grm(&args[1..]),
+        Some("heritability") => cli::heritability(&args[1..]),
+        Some("ldsc") => cli::ldsc(&args[1..]),
+        Some("tdt") => cli::tdt(&args[1..]),
+        Some("rename") => cli::rename(&args[1..]),
+        Some("check-ref") => cli::check_ref(&args[1..]),
+        Some("append") => cli::append(&args[1..]),
+        Some("liftover") => cli::liftover(&args[1..]),
+        Some("simulate") => cli::simulate(&args[1..]),
+        Some("sample") => cli::sample(&args[1..]),
+        Some("verify-roundtrip") => cli::verify_roundtrip(&args[1..]),
+        Some("run") => cli::run(&args[1..]),
+        Some("index") => cli::index(&args[1..]),
+        Some("-h") | Some("--help") => {
+            print!("{}", USAGE);
+            return;
+        },
+        Some(command) => Err(format!("Unknown command `{}`.", command)),
+        None => Err("Missing command.".to_string())
+    };
 
-    for g in reader {
-        println!("{}: {}", g.variant, g.maf());
+    if let Err(e) = result {
+        eprintln!("Error: {}\n\n{}", e, USAGE);
+        process::exit(1);
     }
 }
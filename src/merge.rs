@@ -0,0 +1,456 @@
+/*!
+ * Merging and harmonization of plink filesets.
+ *
+ * Variants are matched on their locus and alleles, on either strand. The
+ * first fileset having a variant defines its coded allele and the calls from
+ * the other filesets are harmonized to it: swapped alleles are recoded and
+ * flipped strands are complemented. A variant with other alleles at a locus
+ * that is already in the merge is dropped from its fileset, as are
+ * duplicated variants.
+ *
+ * The merged fileset has the union of the samples and of the variants
+ * (sorted by position, with the chromosomes in order of appearance). When a
+ * sample is genotyped in multiple filesets, missing calls are filled from the
+ * other filesets and discordant calls are set to missing (like
+ * `plink --merge-mode 1`).
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+
+use crate::core::{complement, Chromosome, Genotypes, OrderedAllelesVariant,
+                  Sample, Variant};
+use crate::error::GenepaError;
+use crate::plink::{read_fam, BedReader, BimReader, PlinkWriter};
+
+
+// How the alleles of a fileset relate to the alleles of the merge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Harmonization {
+    Same,
+    // The coded and other alleles are inverted.
+    Swapped,
+    // The alleles are on the other strand.
+    Flipped,
+    FlippedSwapped
+}
+
+impl Harmonization {
+    fn is_swapped(self) -> bool {
+        matches!(self, Harmonization::Swapped | Harmonization::FlippedSwapped)
+    }
+}
+
+
+// Compare (coded, other) alleles to the reference (coded, other) alleles.
+// Ambiguous (A/T and C/G) variants are never considered flipped.
+pub fn harmonize(reference: (&str, &str), alleles: (&str, &str))
+    -> Option<Harmonization>
+{
+    let flip = |a: &str| complement(&a.to_string());
+    let flipped = (flip(alleles.0), flip(alleles.1));
+
+    if alleles == reference {
+        Some(Harmonization::Same)
+    } else if alleles == (reference.1, reference.0) {
+        Some(Harmonization::Swapped)
+    } else if (flipped.0.as_str(), flipped.1.as_str()) == reference {
+        Some(Harmonization::Flipped)
+    } else if (flipped.1.as_str(), flipped.0.as_str()) == reference {
+        Some(Harmonization::FlippedSwapped)
+    } else {
+        None
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MismatchKind {
+    Swapped,
+    Flipped,
+    FlippedSwapped,
+    // Other alleles at a locus that is already in the merge.
+    DroppedAlleles,
+    DroppedDuplicate
+}
+
+impl MismatchKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MismatchKind::Swapped => "swapped",
+            MismatchKind::Flipped => "flipped",
+            MismatchKind::FlippedSwapped => "flipped_swapped",
+            MismatchKind::DroppedAlleles => "dropped_alleles",
+            MismatchKind::DroppedDuplicate => "dropped_duplicate"
+        }
+    }
+}
+
+
+// A variant of a fileset that was harmonized or dropped.
+#[derive(Debug)]
+pub struct Mismatch {
+    pub fileset: usize,
+    pub variant: Variant,
+    // Coded allele in the fileset.
+    pub coded: String,
+    pub kind: MismatchKind
+}
+
+
+struct Source {
+    fileset: usize,
+    idx: u32,
+    harmonization: Harmonization
+}
+
+
+// A variant of the merge with its index in every fileset.
+struct MergedVariant {
+    variant: Variant,
+    coded: String,
+    sources: Vec<Source>
+}
+
+
+fn alleles(v: &OrderedAllelesVariant) -> (&str, &str) {
+    let (a, b) = &v.variant.alleles;
+    if v.a1_idx == 0 { (a, b) } else { (b, a) }
+}
+
+
+// Match the variants of every fileset. The merged variants are sorted.
+fn plan(filesets: &[Vec<OrderedAllelesVariant>])
+    -> (Vec<MergedVariant>, Vec<Mismatch>)
+{
+    let mut merged: Vec<MergedVariant> = Vec::new();
+    let mut by_variant: HashMap<Variant, usize> = HashMap::new();
+    let mut loci: HashSet<(Chromosome, u32)> = HashSet::new();
+    let mut chrom_order: HashMap<Chromosome, usize> = HashMap::new();
+    let mut mismatches = Vec::new();
+
+    for (fileset, variants) in filesets.iter().enumerate() {
+        for (idx, v) in variants.iter().enumerate() {
+            let (coded, other) = alleles(v);
+            let mut mismatch = |kind| {
+                mismatches.push(Mismatch {
+                    fileset,
+                    variant: v.variant.clone(),
+                    coded: coded.to_string(),
+                    kind
+                });
+            };
+
+            let chrom = &v.variant.chrom;
+            let locus = (chrom.clone(), v.variant.position);
+
+            let i = match by_variant.get(&v.variant) {
+                Some(&i) => i,
+                None if loci.contains(&locus) => {
+                    mismatch(MismatchKind::DroppedAlleles);
+                    continue;
+                },
+                None => {
+                    let n_chrom = chrom_order.len();
+                    chrom_order.entry(chrom.clone()).or_insert(n_chrom);
+                    loci.insert(locus);
+                    by_variant.insert(v.variant.clone(), merged.len());
+
+                    merged.push(MergedVariant {
+                        variant: v.variant.clone(),
+                        coded: coded.to_string(),
+                        sources: Vec::new()
+                    });
+                    merged.len() - 1
+                }
+            };
+
+            let m = &mut merged[i];
+            if m.sources.iter().any(|s| s.fileset == fileset) {
+                mismatch(MismatchKind::DroppedDuplicate);
+                continue;
+            }
+
+            let reference = (m.coded.as_str(), alleles_other(m));
+            let harmonization = match harmonize(reference, (coded, other)) {
+                Some(h) => h,
+                None => {
+                    mismatch(MismatchKind::DroppedAlleles);
+                    continue;
+                }
+            };
+
+            match harmonization {
+                Harmonization::Same => {},
+                Harmonization::Swapped => mismatch(MismatchKind::Swapped),
+                Harmonization::Flipped => mismatch(MismatchKind::Flipped),
+                Harmonization::FlippedSwapped => {
+                    mismatch(MismatchKind::FlippedSwapped)
+                }
+            }
+
+            m.sources.push(Source {
+                fileset,
+                idx: idx as u32,
+                harmonization
+            });
+        }
+    }
+
+    merged.sort_by_key(|m| {
+        (chrom_order[&m.variant.chrom], m.variant.position)
+    });
+
+    (merged, mismatches)
+}
+
+
+fn alleles_other(m: &MergedVariant) -> &str {
+    let (a, b) = &m.variant.alleles;
+    if *a == m.coded { b } else { a }
+}
+
+
+// Union of the samples of every fileset and the index of every sample of
+// every fileset in the union.
+fn merge_samples(filesets: Vec<Vec<Sample>>) -> (Vec<Sample>, Vec<Vec<usize>>)
+{
+    let mut samples: Vec<Sample> = Vec::new();
+    let mut by_id: HashMap<(String, String), usize> = HashMap::new();
+
+    let indices = filesets.into_iter()
+        .map(|fileset| {
+            fileset.into_iter()
+                .map(|s| {
+                    let id = (s.fid.clone(), s.iid.clone());
+                    *by_id.entry(id).or_insert_with(|| {
+                        samples.push(s);
+                        samples.len() - 1
+                    })
+                })
+                .collect()
+        })
+        .collect();
+
+    (samples, indices)
+}
+
+
+// Merge the calls of a sample. Once discordant, the call stays missing.
+fn merge_call(merged: &mut Option<u8>, discordant: &mut bool, call: Option<u8>)
+{
+    match (*merged, call) {
+        (_, None) => {},
+        (None, Some(x)) if !*discordant => *merged = Some(x),
+        (Some(y), Some(x)) if x != y => {
+            *merged = None;
+            *discordant = true;
+        },
+        _ => {}
+    }
+}
+
+
+pub struct MergeReport {
+    pub n_samples: usize,
+    pub n_variants: u32,
+    pub mismatches: Vec<Mismatch>
+}
+
+impl MergeReport {
+    // Write the harmonized and dropped variants. Filesets are identified by
+    // their names.
+    pub fn write_mismatches<W: Write>(&self, out: &mut W, names: &[&str])
+        -> io::Result<()>
+    {
+        writeln!(out, "fileset\tvariant\tchrom\tpos\tcoded\tother\taction")?;
+
+        for m in self.mismatches.iter() {
+            let v = &m.variant;
+            let other = if v.alleles.0 == m.coded {
+                &v.alleles.1
+            } else {
+                &v.alleles.0
+            };
+
+            writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}", names[m.fileset],
+                     v.name, v.chrom, v.position, m.coded, other,
+                     m.kind.as_str())?;
+        }
+
+        Ok(())
+    }
+}
+
+
+// Merge plink filesets into `out_prefix`.
+pub fn merge_plink(prefixes: &[&str], out_prefix: &str)
+    -> io::Result<MergeReport>
+{
+    let variants: Vec<Vec<OrderedAllelesVariant>> = prefixes.iter()
+        .map(|prefix| BimReader::new(&format!("{}.bim", prefix))?.collect())
+        .collect::<Result<_, GenepaError>>()?;
+
+    let fams: Vec<Vec<Sample>> = prefixes.iter()
+        .map(|prefix| read_fam(&format!("{}.fam", prefix)))
+        .collect::<Result<_, _>>()?;
+
+    let mut beds: Vec<_> = prefixes.iter()
+        .zip(fams.iter().zip(variants.iter()))
+        .map(|(prefix, (fam, bim))| {
+            BedReader::new(&format!("{}.bed", prefix), fam.len() as u32,
+                           bim.len() as u32)
+        })
+        .collect::<Result<_, _>>()?;
+
+    let (merged, mismatches) = plan(&variants);
+    let (samples, sample_indices) = merge_samples(fams);
+
+    let mut writer = PlinkWriter::new(out_prefix, &samples)?;
+
+    for m in merged.iter() {
+        let mut calls = vec![None; samples.len()];
+        let mut discordant = vec![false; samples.len()];
+
+        for source in m.sources.iter() {
+            let genotypes = beds[source.fileset]
+                .read_variants(source.idx, 1)
+                .remove(0);
+
+            for (&j, call) in sample_indices[source.fileset].iter()
+                .zip(genotypes)
+            {
+                let call = if source.harmonization.is_swapped() {
+                    call.map(|x| 2 - x)
+                } else {
+                    call
+                };
+
+                merge_call(&mut calls[j], &mut discordant[j], call);
+            }
+        }
+
+        writer.write(&Genotypes::new(m.variant.clone(), calls, &m.coded))?;
+    }
+
+    Ok(MergeReport {
+        n_samples: samples.len(),
+        n_variants: writer.finish()?,
+        mismatches
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Sex;
+
+    fn variant(name: &str, pos: u32, a1: &str, a2: &str)
+        -> OrderedAllelesVariant
+    {
+        let v = Variant::new(name.to_string(), "1".to_string(), pos,
+                             (a1.to_string(), a2.to_string()));
+        let a1_idx = if v.alleles.0 == a1 { 0 } else { 1 };
+        OrderedAllelesVariant { variant: v, a1_idx }
+    }
+
+    #[test]
+    fn test_harmonize() {
+        assert_eq!(harmonize(("A", "G"), ("A", "G")),
+                   Some(Harmonization::Same));
+        assert_eq!(harmonize(("A", "G"), ("G", "A")),
+                   Some(Harmonization::Swapped));
+        assert_eq!(harmonize(("A", "G"), ("T", "C")),
+                   Some(Harmonization::Flipped));
+        assert_eq!(harmonize(("A", "G"), ("C", "T")),
+                   Some(Harmonization::FlippedSwapped));
+        assert_eq!(harmonize(("A", "T"), ("T", "A")),
+                   Some(Harmonization::Swapped));
+        assert_eq!(harmonize(("A", "G"), ("A", "C")), None);
+    }
+
+    #[test]
+    fn test_plan() {
+        let filesets = vec![
+            vec![variant("rs2", 200, "A", "G"), variant("rs1", 100, "C", "T")],
+            vec![variant("rs1", 100, "A", "G"), variant("rs2", 200, "G", "A"),
+                 variant("rs3", 300, "A", "C"), variant("rs2b", 200, "A", "C"),
+                 variant("rs3", 300, "A", "C")]
+        ];
+
+        let (merged, mismatches) = plan(&filesets);
+
+        let names: Vec<&str> = merged.iter()
+            .map(|m| m.variant.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["rs1", "rs2", "rs3"]);
+        assert_eq!(merged[0].sources[1].harmonization,
+                   Harmonization::FlippedSwapped);
+        assert_eq!(merged[1].sources[1].harmonization,
+                   Harmonization::Swapped);
+
+        let kinds: Vec<MismatchKind> = mismatches.iter()
+            .map(|m| m.kind)
+            .collect();
+        assert_eq!(kinds, vec![MismatchKind::FlippedSwapped,
+                               MismatchKind::Swapped,
+                               MismatchKind::DroppedAlleles,
+                               MismatchKind::DroppedDuplicate]);
+        assert_eq!(mismatches[2].variant.name, "rs2b");
+    }
+
+    #[test]
+    fn test_merge_plink() {
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_merge_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let sample = |iid: &str| Sample::new(iid.to_string(), iid.to_string(),
+                                             Sex::Unknown);
+        let genotypes = |v: OrderedAllelesVariant, calls: Vec<Option<u8>>| {
+            let (coded, _) = alleles(&v);
+            let coded = coded.to_string();
+            Genotypes::new(v.variant, calls, &coded)
+        };
+
+        let mut a = PlinkWriter::new(&prefix("a"), &[sample("s1"),
+                                                    sample("s2")]).unwrap();
+        a.write(&genotypes(variant("rs1", 100, "A", "G"),
+                           vec![Some(0), Some(1)])).unwrap();
+        a.finish().unwrap();
+
+        let mut b = PlinkWriter::new(&prefix("b"), &[sample("s2"),
+                                                    sample("s3")]).unwrap();
+        b.write(&genotypes(variant("rs1", 100, "C", "T"),
+                           vec![Some(0), Some(2)])).unwrap();
+        b.write(&genotypes(variant("rs0", 50, "A", "C"),
+                           vec![None, Some(1)])).unwrap();
+        b.finish().unwrap();
+
+        let report = merge_plink(&[&prefix("a"), &prefix("b")],
+                                 &prefix("merged")).unwrap();
+        assert_eq!(report.n_samples, 3);
+        assert_eq!(report.n_variants, 2);
+
+        let mut out = Vec::new();
+        report.write_mismatches(&mut out, &["a", "b"]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().nth(1).unwrap(),
+                   "b\trs1\t1\t100\tC\tT\tflipped_swapped");
+
+        let bim = std::fs::read_to_string(format!("{}.bim",
+                                                  prefix("merged")))
+            .unwrap();
+        assert_eq!(bim, "1\trs0\t0\t50\tA\tC\n1\trs1\t0\t100\tA\tG\n");
+
+        let mut bed = BedReader::new(&format!("{}.bed", prefix("merged")),
+                                     3, 2).unwrap();
+        let calls = bed.read_variants(0, 2);
+        assert_eq!(calls[0], vec![None, None, Some(1)]);
+        // s2 is discordant (1 and 2 copies of A).
+        assert_eq!(calls[1], vec![Some(0), None, Some(0)]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,235 @@
+/*!
+ * Reader for minimac dosage output (`.dose` and `.info` files).
+ *
+ * The `.dose` file has a line per sample (`FID->IID DOSE d1 d2 ...`), so
+ * all the dosages are loaded in memory when the reader is created. The
+ * variants are described by the `.info` file, in the same order as the
+ * dosages. Variant names must be `chrom:pos` (optionally followed by other
+ * fields, e.g. `chrom:pos:ref:alt`) as there is no position column.
+ *
+ * minimac3 and later (`REF(0)` and `ALT(1)` columns) store the dosage of the
+ * ALT allele, while older versions (`Al1` and `Al2` columns) store the
+ * dosage of Al1. The Rsq is attached to the dosages as their info score.
+ */
+
+use std::io::BufRead;
+use std::sync::Arc;
+
+use crate::core::{Dosages, Sample, Sex, Variant};
+use crate::utils::open_text_file;
+use crate::vcf::build_variant;
+
+
+// Variants from the info file with the coded allele and the Rsq. Variants
+// with unsupported alleles are None.
+fn read_info<R: BufRead>(reader: R) -> Vec<Option<(Variant, String, Option<f64>)>> {
+    let mut lines = reader.lines().map(|line| {
+        line.expect("Could not read the minimac info file.")
+    });
+
+    let header = lines.next().expect("The minimac info file is empty.");
+    let columns: Vec<&str> = header.split_whitespace().collect();
+    let column = |name: &str| columns.iter().position(|&c| c == name);
+
+    // Indices of the first and second alleles and of the coded allele.
+    let (a1, a2, coded) = match (column("REF(0)"), column("ALT(1)")) {
+        (Some(reference), Some(alt)) => (reference, alt, alt),
+        _ => match (column("Al1"), column("Al2")) {
+            (Some(a1), Some(a2)) => (a1, a2, a1),
+            _ => panic!("Could not find the allele columns in the minimac \
+                         info file.")
+        }
+    };
+    let rsq = column("Rsq");
+
+    lines.enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let name = fields[0];
+
+            let mut locus = name.split(':');
+            let (chrom, pos) = match (locus.next(), locus.next()) {
+                (Some(chrom), Some(pos)) => (chrom, pos),
+                _ => panic!("Variant names must start with `chrom:pos` in \
+                             the minimac info file, got `{}`.", name)
+            };
+
+            let location = format!("line {} of the minimac info file", i + 2);
+            let variant = build_variant(name, chrom, pos, fields[a1],
+                                        fields[a2], &location)?;
+            let rsq = rsq.and_then(|j| fields[j].parse().ok());
+
+            Some((variant, fields[coded].to_string(), rsq))
+        })
+        .collect()
+}
+
+
+pub struct MinimacReader {
+    variants: Vec<Option<(Variant, String, Option<f64>)>>,
+    // Dosages of every sample (sample major).
+    dosages: Vec<Vec<f32>>,
+    samples: Arc<Vec<Sample>>,
+    attach_samples: bool,
+    n_read: usize,
+    n_skipped: u32
+}
+
+
+impl MinimacReader {
+    // Both files can be gzipped.
+    pub fn new(dose_filename: &str, info_filename: &str) -> MinimacReader {
+        MinimacReader::from_readers(open_text_file(dose_filename),
+                                    open_text_file(info_filename))
+    }
+
+    pub fn from_readers<D: BufRead, I: BufRead>(dose: D, info: I)
+        -> MinimacReader
+    {
+        let variants = read_info(info);
+        let mut samples = Vec::new();
+        let mut dosages = Vec::new();
+
+        for (i, line) in dose.lines().enumerate() {
+            let line = line.expect("Could not read the minimac dose file.");
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let id = fields.next().unwrap();
+            let (fid, iid) = id.split_once("->").unwrap_or((id, id));
+
+            if fields.next() != Some("DOSE") {
+                panic!("Expected `DOSE` in the second column of line {} of \
+                        the minimac dose file.", i + 1);
+            }
+
+            let sample_dosages: Vec<f32> = fields
+                .map(|d| d.parse().unwrap_or_else(|_| {
+                    panic!("Invalid dosage `{}` on line {} of the minimac \
+                            dose file.", d, i + 1)
+                }))
+                .collect();
+
+            if sample_dosages.len() != variants.len() {
+                panic!("Line {} of the minimac dose file has {} dosages, but \
+                        there are {} variants in the info file.", i + 1,
+                        sample_dosages.len(), variants.len());
+            }
+
+            samples.push(Sample::new(fid.to_string(), iid.to_string(),
+                                     Sex::Unknown));
+            dosages.push(sample_dosages);
+        }
+
+        MinimacReader {
+            variants,
+            dosages,
+            samples: Arc::new(samples),
+            attach_samples: false,
+            n_read: 0,
+            n_skipped: 0
+        }
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn attach_samples(&mut self, attach: bool) {
+        self.attach_samples = attach;
+    }
+
+    // Number of variants that were skipped because their alleles are not
+    // supported.
+    pub fn n_skipped(&self) -> u32 {
+        self.n_skipped
+    }
+}
+
+
+impl Iterator for MinimacReader {
+    type Item = Dosages;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.n_read < self.variants.len() {
+            let i = self.n_read;
+            self.n_read += 1;
+
+            let (variant, coded, rsq) = match self.variants[i].take() {
+                Some(v) => v,
+                None => {
+                    self.n_skipped += 1;
+                    continue;
+                }
+            };
+
+            let dosages = self.dosages.iter()
+                .map(|sample| Some(f64::from(sample[i])))
+                .collect();
+
+            let mut d = Dosages::new(variant, dosages, &coded);
+            if let Some(rsq) = rsq {
+                d = d.with_info(rsq);
+            }
+
+            if self.attach_samples {
+                d = d.with_samples(Arc::clone(&self.samples));
+            }
+
+            return Some(d);
+        }
+
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let info = "SNP\tREF(0)\tALT(1)\tALT_Frq\tMAF\tAvgCall\tRsq\n\
+                    22:100\tA\tG\t0.25\t0.25\t0.99\t0.95\n\
+                    22:200:A:<CN0>\tA\t<CN0>\t0.1\t0.1\t0.99\t0.8\n\
+                    22:300:C:T\tC\tT\t0.5\t0.5\t0.9\t-\n";
+        let dose = "f1->s1\tDOSE\t0.5\t0\t1.25\n\
+                    f2->s2\tDOSE\t0\t1\t2\n";
+
+        let mut reader = MinimacReader::from_readers(dose.as_bytes(),
+                                                     info.as_bytes());
+        assert_eq!(reader.samples()[1].fid, "f2");
+        assert_eq!(reader.samples()[1].iid, "s2");
+
+        let dosages: Vec<Dosages> = reader.by_ref().collect();
+        assert_eq!(reader.n_skipped(), 1);
+        assert_eq!(dosages.len(), 2);
+
+        assert_eq!(dosages[0].variant.position, 100);
+        assert_eq!(dosages[0].coded_allele(), "G");
+        assert_eq!(dosages[0].info, Some(0.95));
+        assert_eq!(dosages[0].dosages, vec![Some(0.5), Some(0.0)]);
+
+        assert_eq!(dosages[1].variant.name, "22:300:C:T");
+        assert_eq!(dosages[1].info, None);
+        assert_eq!(dosages[1].dosages, vec![Some(1.25), Some(2.0)]);
+    }
+
+    #[test]
+    fn test_mach_alleles() {
+        let info = "SNP\tAl1\tAl2\tFreq1\tMAF\tQuality\tRsq\n\
+                    1:100\tT\tC\t0.9\t0.1\t0.99\t0.7\n";
+        let dose = "s1->s1 DOSE 1.8\n";
+
+        let d = MinimacReader::from_readers(dose.as_bytes(), info.as_bytes())
+            .next()
+            .unwrap();
+
+        assert_eq!(d.coded_allele(), "T");
+        assert_eq!(d.other_allele(), "C");
+    }
+}
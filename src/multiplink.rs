@@ -0,0 +1,373 @@
+/*!
+ * Reader of plink filesets split by chromosome (e.g. `chr1.bed` to
+ * `chr22.bed`) as a single dataset.
+ *
+ * The filesets must have the same samples, in the same order. The variants
+ * are read from the filesets in turn, and the queries are done on all of
+ * them. The filesets can be given as a list of prefixes or as a glob on the
+ * prefixes or BED files (e.g. `data/chr*` or `data/chr*.bed`). The filesets
+ * matching a glob are sorted in natural order (`chr2` before `chr10`).
+ */
+
+use std::cmp::Ordering;
+use std::fs;
+use std::iter::FusedIterator;
+use std::path::Path;
+
+use crate::core::{Chromosome, Genotypes, HaploidHets, Sample, Variant};
+use crate::plink::{Orientation, PlinkReader};
+use crate::source::{RegionPage, MAX_PAGE_SIZE};
+
+
+// Match a file name against a pattern with `*` (any characters) and `?` (any
+// character) wildcards.
+fn matches_pattern(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some('*'), _) => {
+            matches_pattern(&pattern[1..], name) ||
+                (!name.is_empty() && matches_pattern(pattern, &name[1..]))
+        },
+        (Some('?'), Some(_)) => matches_pattern(&pattern[1..], &name[1..]),
+        (Some(p), Some(c)) if p == c => {
+            matches_pattern(&pattern[1..], &name[1..])
+        },
+        _ => false
+    }
+}
+
+
+// Compare strings with their runs of digits compared as numbers.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let chunks = |s: &str| -> Vec<(bool, String)> {
+        let mut chunks: Vec<(bool, String)> = Vec::new();
+        for c in s.chars() {
+            let digit = c.is_ascii_digit();
+            match chunks.last_mut() {
+                Some((is_digit, chunk)) if *is_digit == digit => chunk.push(c),
+                _ => chunks.push((digit, c.to_string()))
+            }
+        }
+        chunks
+    };
+
+    let (a, b) = (chunks(a), chunks(b));
+    for ((a_digit, a), (b_digit, b)) in a.iter().zip(b.iter()) {
+        let order = if *a_digit && *b_digit {
+            let (a, b) = (a.trim_start_matches('0'), b.trim_start_matches('0'));
+            a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+        } else {
+            a.cmp(b)
+        };
+
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+
+    a.len().cmp(&b.len())
+}
+
+
+// Prefixes of the filesets matching a glob on the file names (the directory
+// can't have wildcards), in natural order.
+pub fn glob_prefixes(pattern: &str) -> Vec<String> {
+    let pattern = pattern.strip_suffix(".bed").unwrap_or(pattern);
+    let path = Path::new(pattern);
+    let dir = path.parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let name: Vec<char> = path.file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+        .chars()
+        .collect();
+
+    let entries = fs::read_dir(dir)
+        .unwrap_or_else(|_| panic!("Could not list `{}`", dir.display()));
+
+    let mut prefixes: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let stem = file_name.strip_suffix(".bed")?;
+            let chars: Vec<char> = stem.chars().collect();
+            if matches_pattern(&name, &chars) {
+                Some(path.with_file_name(stem).to_string_lossy().into_owned())
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    prefixes.sort_by(|a, b| natural_cmp(a, b));
+    prefixes
+}
+
+
+pub struct MultiPlinkReader {
+    prefixes: Vec<String>,
+    readers: Vec<PlinkReader>,
+    // Index of the fileset being iterated.
+    current: usize
+}
+
+impl MultiPlinkReader {
+    pub fn new(prefixes: &[&str]) -> MultiPlinkReader {
+        let readers = prefixes.iter()
+            .map(|prefix| {
+                PlinkReader::new(prefix).unwrap_or_else(|e| panic!("{}", e))
+            })
+            .collect();
+
+        MultiPlinkReader::from_readers(
+            prefixes.iter().map(|prefix| prefix.to_string()).collect(),
+            readers
+        )
+    }
+
+    // Read the filesets matching a glob (see `glob_prefixes`).
+    pub fn from_glob(pattern: &str) -> MultiPlinkReader {
+        let prefixes = glob_prefixes(pattern);
+        if prefixes.is_empty() {
+            panic!("No plink fileset matches `{}`.", pattern);
+        }
+
+        let prefixes: Vec<&str> = prefixes.iter()
+            .map(|prefix| prefix.as_str())
+            .collect();
+        MultiPlinkReader::new(&prefixes)
+    }
+
+    // The prefixes are only used in the error messages.
+    pub fn from_readers(prefixes: Vec<String>, readers: Vec<PlinkReader>)
+        -> MultiPlinkReader
+    {
+        if readers.is_empty() {
+            panic!("Expected at least one plink fileset.");
+        }
+
+        for (prefix, reader) in prefixes.iter().zip(readers.iter()).skip(1) {
+            if reader.samples() != readers[0].samples() {
+                panic!("The samples of `{}` are not the samples of `{}`.",
+                       prefix, prefixes[0]);
+            }
+        }
+
+        MultiPlinkReader { prefixes, readers, current: 0 }
+    }
+
+    pub fn prefixes(&self) -> &[String] {
+        &self.prefixes
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        self.readers[0].samples()
+    }
+
+    pub fn n_variants(&self) -> u32 {
+        self.readers.iter().map(|reader| reader.n_variants()).sum()
+    }
+
+    // See PlinkReader (the options apply to all the filesets).
+    pub fn attach_samples(&mut self, attach: bool) {
+        for reader in self.readers.iter_mut() {
+            reader.attach_samples(attach);
+        }
+    }
+
+    pub fn haploid_policy(&mut self, hets: Option<HaploidHets>) {
+        for reader in self.readers.iter_mut() {
+            reader.haploid_policy(hets);
+        }
+    }
+
+    pub fn orientation(&mut self, orientation: Orientation) {
+        for reader in self.readers.iter_mut() {
+            reader.orientation(orientation);
+        }
+    }
+
+    pub fn keep_samples(&mut self, iids: &[String]) -> usize {
+        for reader in self.readers.iter_mut() {
+            reader.keep_samples(iids);
+        }
+        self.samples().len()
+    }
+
+    pub fn remove_samples(&mut self, iids: &[String]) -> usize {
+        for reader in self.readers.iter_mut() {
+            reader.remove_samples(iids);
+        }
+        self.samples().len()
+    }
+
+    // The variant is looked up in every fileset (the first one with the
+    // variant is used).
+    pub fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        self.readers.iter_mut()
+            .find_map(|reader| reader.get_variant_genotypes(v))
+    }
+
+    pub fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                                  end: u32) -> Vec<Genotypes>
+    {
+        self.readers.iter_mut()
+            .flat_map(|reader| {
+                reader.get_variants_in_region(chrom, start, end)
+            })
+            .collect()
+    }
+
+    // The offset is over the results of all the filesets, in order. The
+    // filesets before the offset are skipped using their index.
+    pub fn get_variants_in_region_page(&mut self, chrom: &Chromosome,
+                                       start: u32, end: u32, offset: usize,
+                                       limit: usize) -> RegionPage
+    {
+        let limit = limit.min(MAX_PAGE_SIZE);
+        let mut genotypes = Vec::new();
+        let mut skip = offset;
+        let mut truncated = false;
+
+        for reader in self.readers.iter_mut() {
+            let n = reader.n_variants_in_region(chrom, start, end)
+                .unwrap_or_else(|e| panic!("{}", e));
+            if n <= skip {
+                skip -= n;
+                continue;
+            }
+
+            if genotypes.len() == limit {
+                truncated = true;
+                break;
+            }
+
+            let page = reader.get_variants_in_region_page(
+                chrom, start, end, skip, limit - genotypes.len()
+            );
+            truncated = page.is_truncated();
+            genotypes.extend(page.genotypes);
+            skip = 0;
+
+            if truncated {
+                break;
+            }
+        }
+
+        let next_offset = if truncated {
+            Some(offset + genotypes.len())
+        } else {
+            None
+        };
+
+        RegionPage { genotypes, next_offset }
+    }
+}
+
+
+impl Iterator for MultiPlinkReader {
+    type Item = Genotypes;
+
+    fn next(&mut self) -> Option<Genotypes> {
+        while self.current < self.readers.len() {
+            match self.readers[self.current].next() {
+                Some(g) => return Some(g),
+                None => self.current += 1
+            }
+        }
+
+        None
+    }
+}
+
+impl FusedIterator for MultiPlinkReader {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Sex;
+    use crate::index::{build_index, IndexFormat};
+    use crate::plink::PlinkWriter;
+
+    #[test]
+    fn test_glob() {
+        let glob = |pattern: &str, name: &str| {
+            let pattern: Vec<char> = pattern.chars().collect();
+            let name: Vec<char> = name.chars().collect();
+            matches_pattern(&pattern, &name)
+        };
+        assert!(glob("chr*", "chr22"));
+        assert!(glob("chr?_qc", "chr1_qc"));
+        assert!(!glob("chr?_qc", "chr10_qc"));
+        assert!(glob("*.qc*", "cohort.chr1.qc"));
+        assert!(!glob("chr*", "sex_chr"));
+
+        let mut names = vec!["chr10", "chrX", "chr2", "chr1", "chr02b"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, vec!["chr1", "chr2", "chr02b", "chr10", "chrX"]);
+    }
+
+    #[test]
+    fn test_multi_plink_reader() {
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_multiplink_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let prefix = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let samples = vec![
+            Sample::new("f1".to_string(), "s1".to_string(), Sex::Male),
+            Sample::new("f2".to_string(), "s2".to_string(), Sex::Female)
+        ];
+
+        // 2 variants on chromosome 2 and 1 on chromosome 10.
+        for (chrom, positions) in [("2", vec![100, 200]), ("10", vec![100])]
+            .iter()
+        {
+            let name = format!("chr{}", chrom);
+            let mut writer = PlinkWriter::new(&prefix(&name), &samples)
+                .unwrap();
+            for &position in positions.iter() {
+                let v = Variant::new(format!("rs{}_{}", chrom, position),
+                                     chrom.to_string(), position,
+                                     ("A".to_string(), "G".to_string()));
+                writer.write(&Genotypes::new(v, vec![Some(0), Some(1)], "G"))
+                    .unwrap();
+            }
+            writer.finish().unwrap();
+            build_index(&prefix(&name), IndexFormat::V2).unwrap();
+        }
+
+        let reader = MultiPlinkReader::from_glob(&prefix("chr*.bed"));
+        assert_eq!(reader.prefixes(), &[prefix("chr2"), prefix("chr10")]);
+        assert_eq!(reader.samples(), &samples[..]);
+        assert_eq!(reader.n_variants(), 3);
+
+        let names: Vec<String> = reader.map(|g| g.variant.name).collect();
+        assert_eq!(names, vec!["rs2_100", "rs2_200", "rs10_100"]);
+
+        let mut reader = MultiPlinkReader::new(&[&prefix("chr2"),
+                                                 &prefix("chr10")]);
+
+        let chrom = |name: &str| Chromosome::new(name);
+        assert_eq!(reader.get_variants_in_region(&chrom("2"), 1, 1000).len(),
+                   2);
+
+        let page = reader.get_variants_in_region_page(&chrom("2"), 1, 1000,
+                                                      1, 1);
+        assert_eq!(page.genotypes[0].variant.name, "rs2_200");
+        assert_eq!(page.next_offset, None);
+
+        let page = reader.get_variants_in_region_page(&chrom("2"), 1, 1000,
+                                                      0, 1);
+        assert_eq!(page.genotypes[0].variant.name, "rs2_100");
+        assert_eq!(page.next_offset, Some(1));
+
+        let v = Variant::new("rs10_100".to_string(), "10".to_string(), 100,
+                             ("A".to_string(), "G".to_string()));
+        assert_eq!(reader.get_variant_genotypes(&v).unwrap().genotypes,
+                   vec![Some(0), Some(1)]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
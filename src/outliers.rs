@@ -0,0 +1,323 @@
+/*!
+ * Detection of outlier samples.
+ *
+ * Two robust criteria are available:
+ *
+ * - Univariate metrics (e.g. the missing call rate or the heterozygosity
+ *   from the QcReport) are flagged when their robust z-score, computed from
+ *   the median and the median absolute deviation (MAD), is too large.
+ * - Multivariate coordinates (e.g. the first principal components) are
+ *   flagged when their robust squared Mahalanobis distance is too large for
+ *   a chi-squared distribution with one degree of freedom per dimension.
+ *
+ * The OutlierDetector accumulates the reasons to exclude every sample so
+ * that the recommendations can be reviewed before removing the samples.
+ */
+
+use std::fmt;
+use std::io::{self, Write};
+
+use ndarray::{Array1, Array2, Axis};
+
+use crate::core::Sample;
+use crate::linalg;
+use crate::stats::chi2_sf;
+
+
+// Scale of the MAD to estimate the standard deviation of normal data.
+pub const MAD_SCALE: f64 = 1.4826;
+
+
+// Median of the values that aren't NaN (NaN if there are none).
+pub fn median(values: &[f64]) -> f64 {
+    let mut sorted: Vec<f64> = values.iter()
+        .copied()
+        .filter(|x| !x.is_nan())
+        .collect();
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = sorted.len() / 2;
+    if sorted.len() % 2 == 1 {
+        sorted[mid]
+    } else {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    }
+}
+
+
+// Robust z-scores (x - median) / (MAD_SCALE * MAD). The scores are NaN for
+// missing values or if the MAD is 0 (more than half of the values are
+// equal).
+pub fn robust_z_scores(values: &[f64]) -> Vec<f64> {
+    let center = median(values);
+    let deviations: Vec<f64> = values.iter()
+        .map(|x| (x - center).abs())
+        .collect();
+    let scale = MAD_SCALE * median(&deviations);
+
+    values.iter()
+        .map(|x| if scale > 0.0 { (x - center) / scale } else { f64::NAN })
+        .collect()
+}
+
+
+// Mean and inverse covariance matrix of some rows (None if the covariance
+// matrix is singular).
+fn location_and_precision(points: &Array2<f64>, rows: &[usize])
+    -> Option<(Array1<f64>, Array2<f64>)>
+{
+    let subset = points.select(Axis(0), rows);
+    let mean = subset.mean_axis(Axis(0));
+    let centered = &subset - &mean;
+    let covariance = centered.t().dot(&centered) / (rows.len() - 1) as f64;
+
+    Some((mean, linalg::invert(&covariance)?))
+}
+
+
+fn distances(points: &Array2<f64>, mean: &Array1<f64>,
+             precision: &Array2<f64>) -> Vec<f64>
+{
+    points.outer_iter()
+        .map(|row| {
+            let centered = &row - mean;
+            centered.dot(&precision.dot(&centered))
+        })
+        .collect()
+}
+
+
+// Squared Mahalanobis distances of the rows to their mean (None if the
+// covariance matrix is singular).
+pub fn mahalanobis(points: &Array2<f64>) -> Option<Vec<f64>> {
+    if points.rows() < 2 {
+        return None;
+    }
+
+    let rows: Vec<usize> = (0..points.rows()).collect();
+    let (mean, precision) = location_and_precision(points, &rows)?;
+    Some(distances(points, &mean, &precision))
+}
+
+
+// Squared Mahalanobis distances using the minimum covariance determinant
+// estimates of the location and scatter, so that the outliers don't mask
+// themselves by inflating the covariance. The estimates are computed from
+// the half of the rows closest to the mean (concentration steps of
+// FAST-MCD, starting from all the rows) and rescaled to be consistent for
+// normal data. None if the covariance matrix is singular.
+pub fn robust_mahalanobis(points: &Array2<f64>) -> Option<Vec<f64>> {
+    let (n, p) = (points.rows(), points.cols());
+    let h = (n + p).div_ceil(2);
+    if h <= p {
+        return None;
+    }
+
+    let mut d = mahalanobis(points)?;
+    let mut subset: Vec<usize> = Vec::new();
+    for _ in 0..100 {
+        let mut closest: Vec<usize> = (0..n).collect();
+        closest.sort_by(|&a, &b| d[a].partial_cmp(&d[b]).unwrap());
+        closest.truncate(h);
+        closest.sort_unstable();
+
+        if closest == subset {
+            break;
+        }
+        subset = closest;
+
+        let (mean, precision) = location_and_precision(points, &subset)?;
+        d = distances(points, &mean, &precision);
+    }
+
+    // Median of the chi-squared distribution (Wilson-Hilferty).
+    let p = p as f64;
+    let chi2_median = p * (1.0 - 2.0 / (9.0 * p)).powi(3);
+    let factor = median(&d) / chi2_median;
+
+    Some(d.into_iter().map(|x| x / factor).collect())
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutlierReason {
+    Metric { name: String, value: f64, z: f64 },
+    Multivariate { name: String, distance: f64, p: f64 }
+}
+
+impl fmt::Display for OutlierReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutlierReason::Metric { name, value, z } =>
+                write!(f, "{}={:.4} (z={:.2})", name, value, z),
+            OutlierReason::Multivariate { name, distance, p } =>
+                write!(f, "{} (D2={:.2}, p={:.2e})", name, distance, p)
+        }
+    }
+}
+
+
+pub struct OutlierDetector {
+    reasons: Vec<Vec<OutlierReason>>
+}
+
+impl OutlierDetector {
+    pub fn new(n_samples: usize) -> OutlierDetector {
+        OutlierDetector { reasons: vec![Vec::new(); n_samples] }
+    }
+
+    fn _check_len(&self, n: usize, name: &str) {
+        if n != self.reasons.len() {
+            panic!("Expected {} samples but `{}` has {} values.",
+                   self.reasons.len(), name, n);
+        }
+    }
+
+    // Flag the samples with an absolute robust z-score greater than `max_z`
+    // for a metric. Returns the number of flagged samples.
+    pub fn flag_metric(&mut self, name: &str, values: &[f64], max_z: f64)
+        -> usize
+    {
+        self._check_len(values.len(), name);
+
+        let mut n_flagged = 0;
+        for ((reasons, &value), z) in self.reasons.iter_mut()
+            .zip(values.iter())
+            .zip(robust_z_scores(values))
+        {
+            if z.abs() > max_z {
+                n_flagged += 1;
+                reasons.push(OutlierReason::Metric {
+                    name: name.to_string(), value, z
+                });
+            }
+        }
+
+        n_flagged
+    }
+
+    // Flag the samples (rows) with a robust squared Mahalanobis distance
+    // with a chi-squared p-value smaller than `min_p`. Returns the number of
+    // flagged samples (0 if the covariance is singular).
+    pub fn flag_multivariate(&mut self, name: &str, points: &Array2<f64>,
+                             min_p: f64) -> usize
+    {
+        self._check_len(points.rows(), name);
+
+        let distances = match robust_mahalanobis(points) {
+            Some(distances) => distances,
+            None => return 0
+        };
+        let df = points.cols() as f64;
+
+        let mut n_flagged = 0;
+        for (reasons, distance) in self.reasons.iter_mut().zip(distances) {
+            let p = chi2_sf(distance, df);
+            if p < min_p {
+                n_flagged += 1;
+                reasons.push(OutlierReason::Multivariate {
+                    name: name.to_string(), distance, p
+                });
+            }
+        }
+
+        n_flagged
+    }
+
+    // Reasons to exclude every sample (empty if it isn't an outlier).
+    pub fn reasons(&self) -> &[Vec<OutlierReason>] {
+        &self.reasons
+    }
+
+    // Indices of the samples recommended for exclusion.
+    pub fn exclusions(&self) -> Vec<usize> {
+        self.reasons.iter()
+            .enumerate()
+            .filter(|(_, reasons)| !reasons.is_empty())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    // Write the samples recommended for exclusion with their reasons
+    // (usable with plink `--remove`). Returns the number of samples.
+    pub fn write_exclusions<W: Write>(&self, out: &mut W, samples: &[Sample])
+        -> io::Result<usize>
+    {
+        self._check_len(samples.len(), "samples");
+
+        writeln!(out, "FID\tIID\treasons")?;
+        let exclusions = self.exclusions();
+        for &i in exclusions.iter() {
+            let reasons: Vec<String> = self.reasons[i].iter()
+                .map(|reason| reason.to_string())
+                .collect();
+            writeln!(out, "{}\t{}\t{}", samples[i].fid, samples[i].iid,
+                     reasons.join("; "))?;
+        }
+
+        out.flush()?;
+        Ok(exclusions.len())
+    }
+}
+
+
+// Columns (e.g. the first principal components) as rows of points.
+pub fn columns_as_points(columns: &[Array1<f64>]) -> Array2<f64> {
+    let n = columns.first().map_or(0, |c| c.len());
+    Array2::from_shape_fn((n, columns.len()), |(i, j)| columns[j][i])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Sex;
+
+    #[test]
+    fn test_robust_z_scores() {
+        assert_eq!(median(&[3.0, f64::NAN, 1.0, 2.0, 10.0]), 2.5);
+
+        // Median 3, MAD 1.
+        let z = robust_z_scores(&[1.0, 2.0, 3.0, 4.0, 100.0]);
+        assert_eq!(z[2], 0.0);
+        assert!((z[4] - 97.0 / MAD_SCALE).abs() < 1e-12);
+
+        assert!(robust_z_scores(&[1.0, 1.0, 1.0, 5.0])[3].is_nan());
+    }
+
+    #[test]
+    fn test_outlier_detector() {
+        let samples: Vec<Sample> = (0..8)
+            .map(|i| Sample::new(format!("f{}", i), format!("s{}", i),
+                                 Sex::Unknown))
+            .collect();
+
+        let missing = [0.01, 0.02, 0.01, 0.015, 0.3, 0.02, 0.01, 0.012];
+
+        // A cloud around the origin with an outlier on both dimensions.
+        let pcs = Array2::from_shape_vec((8, 2), vec![
+            0.1, 0.0, -0.1, 0.05, 0.0, -0.1, 0.05, 0.1,
+            -0.05, -0.05, 0.1, 0.1, -0.1, -0.1, 3.0, 3.0
+        ]).unwrap();
+
+        let mut detector = OutlierDetector::new(samples.len());
+        assert_eq!(detector.flag_metric("missing", &missing, 4.0), 1);
+        assert_eq!(detector.flag_multivariate("PC1-2", &pcs, 0.01), 1);
+        assert_eq!(detector.exclusions(), vec![4, 7]);
+
+        let mut out = Vec::new();
+        assert_eq!(detector.write_exclusions(&mut out, &samples).unwrap(), 2);
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("f4\ts4\tmissing=0.3000 (z="));
+        assert!(lines[2].starts_with("f7\ts7\tPC1-2 (D2="));
+
+        // Singular covariance.
+        let line = columns_as_points(&[Array1::zeros(8), Array1::ones(8)]);
+        assert_eq!(detector.flag_multivariate("flat", &line, 0.01), 0);
+    }
+}
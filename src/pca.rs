@@ -0,0 +1,259 @@
+/*!
+ * Principal component analysis of the samples.
+ *
+ * The PCs are usually the leading eigenvectors of the GRM, which needs the
+ * n x n GRM in memory. For larger datasets, `streaming_pcs` computes them
+ * directly from the standardized genotypes by randomized subspace iteration:
+ * every pass over the genotypes multiplies a small basis of n x (k + 10)
+ * vectors by the GRM, one block of variants at a time, so neither the GRM
+ * nor the genotype matrix is ever held in memory. More passes give more
+ * accurate PCs.
+ */
+
+use std::io::{self, Write};
+use std::mem;
+
+use ndarray::{Array1, Array2, s};
+
+use crate::core::{Genotypes, Sample};
+use crate::grm::{self, BLOCK_SIZE};
+use crate::linalg;
+
+
+// Number of passes over the genotypes of `streaming_pcs` if not specified.
+pub const DEFAULT_PASSES: usize = 6;
+
+
+#[derive(Debug)]
+pub struct Pcs {
+    pub eigenvalues: Array1<f64>,
+    // One row per sample and one column per component.
+    pub vectors: Array2<f64>
+}
+
+
+impl Pcs {
+    pub fn n_components(&self) -> usize {
+        self.vectors.cols()
+    }
+}
+
+
+// First k principal components from the eigendecomposition of the GRM.
+pub fn pcs_from_grm(grm: &Array2<f64>, k: usize) -> Pcs {
+    let (eigenvalues, vectors) = linalg::top_eigen(grm, k);
+    Pcs { eigenvalues, vectors }
+}
+
+
+// First k principal components of the standardized genotypes, on the same
+// scale as `pcs_from_grm` on the GRM of the same variants, in `passes`
+// passes over the genotypes (at least 2). `genotypes` is called at the
+// start of every pass and must give the same variants every time (e.g. by
+// opening the reader again). The first pass finds the range of the basis,
+// the following ones refine it (power iterations) and the last one projects
+// the GRM on it (Rayleigh-Ritz).
+pub fn streaming_pcs<F, I>(mut genotypes: F, k: usize, passes: usize) -> Pcs
+    where F: FnMut() -> I,
+          I: IntoIterator<Item = Genotypes>
+{
+    assert!(passes >= 2, "The streaming PCA needs at least 2 passes.");
+
+    let mut basis: Option<Array2<f64>> = None;
+    let mut n_variants = 0;
+
+    for pass in 0..passes {
+        let (product, n) = grm_product(genotypes(), &mut basis, k);
+        if pass > 0 && n != n_variants {
+            panic!("Expected {} variants at every pass but got {}.",
+                   n_variants, n);
+        }
+        n_variants = n;
+
+        let q = basis.as_mut().unwrap();
+        if pass + 1 < passes {
+            *q = product;
+            linalg::orthonormalize(q);
+            continue;
+        }
+
+        // The projection is only symmetric up to rounding errors.
+        let projected = q.t().dot(&product) / n_variants as f64;
+        let projected = (&projected + &projected.t()) / 2.0;
+        let (values, vectors) = linalg::symmetric_eigen(&projected);
+        let vectors = q.dot(&vectors);
+
+        return Pcs {
+            eigenvalues: values.slice(s![..k]).to_owned(),
+            vectors: vectors.slice(s![.., ..k]).to_owned()
+        };
+    }
+
+    unreachable!()
+}
+
+
+// Product of the sum over the variants of the outer products of their
+// standardized genotypes (the GRM times the number of variants) with the
+// basis, in a single pass over the genotypes. Returns the product and the
+// number of polymorphic variants. The starting basis is created on the first
+// variant if there is none yet.
+fn grm_product<I>(genotypes: I, basis: &mut Option<Array2<f64>>, k: usize)
+    -> (Array2<f64>, u64)
+    where I: IntoIterator<Item = Genotypes>
+{
+    let mut product: Option<Array2<f64>> = None;
+    let mut block: Vec<f64> = Vec::new();
+    let mut n_block = 0;
+    let mut n_variants = 0;
+
+    for g in genotypes {
+        let n = g.genotypes.len();
+        let q = basis.get_or_insert_with(|| {
+            assert!(k <= n, "Can't compute more PCs than samples.");
+            linalg::starting_subspace(n, (k + 10).min(n))
+        });
+
+        if n != q.rows() {
+            panic!("Expected {} samples but `{}` has {} genotypes.",
+                   q.rows(), g.variant, n);
+        }
+
+        if !grm::standardize_into(&g, &mut block) {
+            continue;
+        }
+
+        n_block += 1;
+        n_variants += 1;
+
+        if n_block == BLOCK_SIZE {
+            add_block(&mut block, n_block, q, &mut product);
+            n_block = 0;
+        }
+    }
+
+    if let Some(q) = basis.as_ref() {
+        add_block(&mut block, n_block, q, &mut product);
+    }
+
+    match product {
+        Some(product) if n_variants > 0 => (product, n_variants),
+        _ => panic!("Can't compute the PCs without polymorphic variants.")
+    }
+}
+
+
+fn add_block(block: &mut Vec<f64>, n_block: usize, q: &Array2<f64>,
+             product: &mut Option<Array2<f64>>)
+{
+    let product = product.get_or_insert_with(|| Array2::zeros(q.dim()));
+    if n_block == 0 {
+        return;
+    }
+
+    let x = Array2::from_shape_vec((n_block, q.rows()), mem::take(block))
+        .unwrap();
+    *product += &x.t().dot(&x.dot(q));
+}
+
+
+// Write the PCs like plink2 `.eigenvec` files (with a header).
+pub fn write_eigenvec<W: Write>(out: &mut W, samples: &[Sample], pcs: &Pcs)
+    -> io::Result<()>
+{
+    let header: Vec<String> = (1..=pcs.n_components())
+        .map(|i| format!("PC{}", i))
+        .collect();
+    writeln!(out, "#FID\tIID\t{}", header.join("\t"))?;
+
+    for (s, row) in samples.iter().zip(pcs.vectors.outer_iter()) {
+        let values: Vec<String> = row.iter()
+            .map(|x| format!("{:.6}", x))
+            .collect();
+        writeln!(out, "{}\t{}\t{}", s.fid, s.iid, values.join("\t"))?;
+    }
+
+    out.flush()
+}
+
+
+// Write the eigenvalues like plink2 `.eigenval` files (one per line).
+pub fn write_eigenval<W: Write>(out: &mut W, pcs: &Pcs) -> io::Result<()> {
+    for value in pcs.eigenvalues.iter() {
+        writeln!(out, "{:.6}", value)?;
+    }
+
+    out.flush()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Variant;
+    use crate::grm::compute_grm;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    // Two populations of 15 samples with different allele frequencies, and
+    // some missing genotypes.
+    fn dataset() -> Vec<Genotypes> {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+
+        (0..300)
+            .map(|j| {
+                let p: f64 = rng.gen_range(0.1..0.5);
+                let calls = (0..30)
+                    .map(|i| {
+                        let p = if i < 15 { p } else { 1.0 - p };
+                        if rng.gen_bool(0.02) {
+                            None
+                        } else {
+                            Some(rng.gen_bool(p) as u8 + rng.gen_bool(p) as u8)
+                        }
+                    })
+                    .collect();
+
+                let v = Variant::new(
+                    format!("v{}", j),
+                    "1".to_string(),
+                    j + 1,
+                    ("A".to_string(), "G".to_string())
+                );
+                Genotypes::new(v, calls, "G")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_streaming_pcs() {
+        let data = dataset();
+        let expected = pcs_from_grm(&compute_grm(data.clone()), 3);
+
+        let mut n_passes = 0;
+        let pcs = streaming_pcs(|| { n_passes += 1; data.clone() }, 3, 20);
+        assert_eq!(n_passes, 20);
+        assert_eq!(pcs.vectors.dim(), (30, 3));
+
+        for j in 0..3 {
+            let (obs, exp) = (pcs.eigenvalues[j], expected.eigenvalues[j]);
+            assert!((obs - exp).abs() < 1e-5 * exp);
+        }
+
+        // Same vector up to the sign (the next PCs have close eigenvalues
+        // and converge slowly).
+        let dot = pcs.vectors.column(0).dot(&expected.vectors.column(0));
+        assert!((dot.abs() - 1.0).abs() < 1e-9);
+
+        // Fewer passes are less accurate.
+        let rough = streaming_pcs(|| data.clone(), 3, 2);
+        let error = |pcs: &Pcs| (pcs.eigenvalues[2] - expected.eigenvalues[2])
+            .abs();
+        assert!(error(&rough) > error(&pcs));
+
+        // The first PC separates the populations.
+        let first = pcs.vectors.column(0);
+        assert!((0..15).all(|i| first[i] * first[0] > 0.0));
+        assert!((15..30).all(|i| first[i] * first[0] < 0.0));
+    }
+}
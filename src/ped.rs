@@ -0,0 +1,357 @@
+/*!
+ * Reader for the text plink format (ped and map).
+ *
+ * The ped has a line per sample (FID, IID, father, mother, sex, phenotype
+ * and two alleles per variant), so the whole file is loaded and transposed
+ * when the reader is created. The map has the chromosome, name, position in
+ * cM (optional) and position of every variant; variants with a negative
+ * position are excluded like in plink.
+ *
+ * As the map doesn't have alleles, they are taken from the ped. Like in
+ * plink, the coded allele (A1) is the minor allele. Variants with more or
+ * less than 2 observed alleles are skipped.
+ */
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::iter::FusedIterator;
+use std::sync::Arc;
+
+use crate::core::{Chromosome, Genotypes, HaploidHets, Sample, Sex, Variant};
+use crate::source::RegionPage;
+use crate::vcf::build_variant;
+
+
+const MISSING: u8 = u8::MAX;
+
+
+// A variant from the map.
+pub(crate) struct MapEntry {
+    pub(crate) chrom: String,
+    pub(crate) name: String,
+    pub(crate) position: i64
+}
+
+
+fn read_map<R: BufRead>(reader: R) -> Vec<MapEntry> {
+    reader.lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let line = line.expect("Could not read the map.");
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            let pos = match fields.len() {
+                0 => return None,
+                3 => fields[2],
+                4 => fields[3],
+                n => panic!("Expected 3 or 4 columns on line {} of the map, \
+                             got {}.", i + 1, n)
+            };
+
+            Some(MapEntry {
+                chrom: fields[0].to_string(),
+                name: fields[1].to_string(),
+                position: pos.parse().unwrap_or_else(|_| {
+                    panic!("Invalid position on line {} of the map.", i + 1)
+                })
+            })
+        })
+        .collect()
+}
+
+
+// Observed alleles and calls (allele indices) of a variant.
+#[derive(Default)]
+pub(crate) struct VariantCalls {
+    alleles: Vec<String>,
+    calls: Vec<[u8; 2]>,
+    multiallelic: bool
+}
+
+impl VariantCalls {
+    fn allele_index(&mut self, allele: &str) -> u8 {
+        if allele == "0" {
+            return MISSING;
+        }
+
+        match self.alleles.iter().position(|a| a == allele) {
+            Some(i) => i as u8,
+            None if self.alleles.len() < 2 => {
+                self.alleles.push(allele.to_string());
+                (self.alleles.len() - 1) as u8
+            },
+            None => {
+                self.multiallelic = true;
+                MISSING
+            }
+        }
+    }
+
+    pub(crate) fn push(&mut self, a: &str, b: &str) {
+        let call = [self.allele_index(a), self.allele_index(b)];
+        self.calls.push(call);
+    }
+
+    // Genotypes coded as the number of copies of the minor allele.
+    pub(crate) fn into_genotypes(self, entry: &MapEntry, location: &str)
+        -> Option<Genotypes>
+    {
+        if self.multiallelic || self.alleles.len() != 2 {
+            return None;
+        }
+
+        let mut counts = [0; 2];
+        for allele in self.calls.iter().flatten() {
+            if *allele != MISSING {
+                counts[usize::from(*allele)] += 1;
+            }
+        }
+
+        // Ties keep the allele that was seen first as A2.
+        let a1: u8 = if counts[0] < counts[1] { 0 } else { 1 };
+
+        let variant = build_variant(
+            &entry.name, &entry.chrom, &entry.position.to_string(),
+            &self.alleles[usize::from(a1)],
+            &self.alleles[usize::from(1 - a1)], location
+        )?;
+
+        let genotypes = self.calls.iter()
+            .map(|call| {
+                if call.contains(&MISSING) {
+                    None
+                } else {
+                    Some(call.iter().filter(|&&a| a == a1).count() as u8)
+                }
+            })
+            .collect();
+
+        let coded = self.alleles[usize::from(a1)].clone();
+        Some(Genotypes::new(variant, genotypes, &coded))
+    }
+}
+
+
+pub struct PedReader {
+    genotypes: Vec<Genotypes>,
+    samples: Arc<Vec<Sample>>,
+    attach_samples: bool,
+    haploid_hets: Option<HaploidHets>,
+    n_read: usize,
+    n_skipped: u32
+}
+
+impl PedReader {
+    pub fn new(prefix: &str) -> PedReader {
+        let open = |ext: &str| {
+            let filename = format!("{}.{}", prefix, ext);
+            let f = File::open(&filename)
+                .unwrap_or_else(|_| panic!("Could not open `{}`", filename));
+            BufReader::new(f)
+        };
+
+        PedReader::from_readers(open("ped"), open("map"))
+    }
+
+    pub fn from_readers<P: BufRead, M: BufRead>(ped: P, map: M) -> PedReader {
+        let map = read_map(map);
+        let mut variants: Vec<VariantCalls> = map.iter()
+            .map(|_| VariantCalls::default())
+            .collect();
+        let mut samples = Vec::new();
+
+        for (i, line) in ped.lines().enumerate() {
+            let line = line.expect("Could not read the ped.");
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            if fields.is_empty() {
+                continue;
+            }
+
+            if fields.len() != 6 + 2 * map.len() {
+                panic!("Expected {} columns on line {} of the ped (given \
+                        {} variants in the map), got {}.", 6 + 2 * map.len(),
+                        i + 1, map.len(), fields.len());
+            }
+
+            samples.push(Sample::new(fields[0].to_string(),
+                                     fields[1].to_string(),
+                                     Sex::from_plink_code(fields[4])));
+
+            for (variant, alleles) in variants.iter_mut()
+                .zip(fields[6..].chunks(2))
+            {
+                variant.push(alleles[0], alleles[1]);
+            }
+        }
+
+        let mut n_skipped = 0;
+        let genotypes = variants.into_iter()
+            .zip(map.iter())
+            .enumerate()
+            .filter_map(|(i, (calls, entry))| {
+                let location = format!("line {} of the map", i + 1);
+                let g = if entry.position < 0 {
+                    None
+                } else {
+                    calls.into_genotypes(entry, &location)
+                };
+
+                if g.is_none() {
+                    n_skipped += 1;
+                }
+                g
+            })
+            .collect();
+
+        PedReader {
+            genotypes,
+            samples: Arc::new(samples),
+            attach_samples: false,
+            haploid_hets: Some(HaploidHets::SetMissing),
+            n_read: 0,
+            n_skipped
+        }
+    }
+
+    pub fn attach_samples(&mut self, attach: bool) {
+        self.attach_samples = attach;
+    }
+
+    // See PlinkReader::haploid_policy.
+    pub fn haploid_policy(&mut self, hets: Option<HaploidHets>) {
+        self.haploid_hets = hets;
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn n_variants(&self) -> u32 {
+        self.genotypes.len() as u32
+    }
+
+    // Number of variants that were excluded (negative position) or skipped
+    // because they don't have exactly 2 alleles.
+    pub fn n_skipped(&self) -> u32 {
+        self.n_skipped
+    }
+
+    fn _make_genotypes(&self, idx: usize) -> Genotypes {
+        let mut g = self.genotypes[idx].clone();
+
+        if let Some(hets) = self.haploid_hets {
+            if g.variant.chrom.is_haploid() {
+                g = g.into_haploid(hets).unwrap_or_else(|e| panic!("{}", e));
+            }
+        }
+
+        if self.attach_samples {
+            g.with_samples(Arc::clone(&self.samples))
+        } else {
+            g
+        }
+    }
+
+    fn _region_indices(&self, chrom: &Chromosome, start: u32, end: u32)
+        -> Vec<usize>
+    {
+        self.genotypes.iter()
+            .enumerate()
+            .filter(|(_, g)| {
+                let v = &g.variant;
+                v.chrom == *chrom && v.position >= start && v.position <= end
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    pub fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        let matches: Vec<usize> = self._region_indices(&v.chrom, v.position,
+                                                       v.position)
+            .into_iter()
+            .filter(|&i| self.genotypes[i].variant == *v)
+            .collect();
+
+        match matches.len() {
+            0 => None,
+            1 => Some(self._make_genotypes(matches[0])),
+            _ => panic!("There are duplicate variants in the map file.")
+        }
+    }
+
+    pub fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                                  end: u32) -> Vec<Genotypes>
+    {
+        self._region_indices(chrom, start, end)
+            .into_iter()
+            .map(|i| self._make_genotypes(i))
+            .collect()
+    }
+
+    pub fn get_variants_in_region_page(&mut self, chrom: &Chromosome,
+                                       start: u32, end: u32, offset: usize,
+                                       limit: usize) -> RegionPage
+    {
+        let genotypes = self._region_indices(chrom, start, end)
+            .into_iter()
+            .skip(offset)
+            .map(|i| self._make_genotypes(i));
+
+        RegionPage::collect(genotypes, offset, limit)
+    }
+}
+
+
+impl Iterator for PedReader {
+    type Item = Genotypes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.n_read >= self.genotypes.len() {
+            return None;
+        }
+
+        self.n_read += 1;
+        Some(self._make_genotypes(self.n_read - 1))
+    }
+}
+
+impl FusedIterator for PedReader {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let map = "1\trs1\t0\t100\n\
+                   1\trs2\t0\t200\n\
+                   1\trs3\t0\t-300\n\
+                   2\trs4\t0\t400\n";
+        let ped = "f1 s1 0 0 1 -9 A A C C A A G G\n\
+                   f2 s2 0 0 2 -9 A G C T A A 0 0\n\
+                   f3 s3 0 0 0 -9 G G C G A A G T\n";
+
+        let mut reader = PedReader::from_readers(ped.as_bytes(),
+                                                 map.as_bytes());
+        assert_eq!(reader.samples()[0].sex, Sex::Male);
+        assert_eq!(reader.n_variants(), 2);
+
+        let region = reader.get_variants_in_region(
+            &Chromosome::Autosome(1), 100, 100
+        );
+        assert_eq!(region[0].coded_allele(), "G");
+        assert_eq!(region[0].other_allele(), "A");
+
+        let all: Vec<Genotypes> = reader.by_ref().collect();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].genotypes, vec![Some(0), Some(1), Some(2)]);
+
+        // rs2 has 3 alleles and rs3 is excluded.
+        assert_eq!(reader.n_skipped(), 2);
+        assert_eq!(all[1].variant.name, "rs4");
+        assert_eq!(all[1].coded_allele(), "T");
+        assert_eq!(all[1].genotypes, vec![Some(0), None, Some(1)]);
+    }
+}
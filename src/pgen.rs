@@ -0,0 +1,708 @@
+/*!
+ * Reader for PLINK 2 filesets (pgen, pvar and psam).
+ *
+ * Only the fixed-width storage modes of the pgen are supported: the PLINK 1
+ * BED layout (mode 0x01) and hardcalls without dosages (mode 0x02). The
+ * variable-width mode (0x10), which is what plink2 writes by default, uses
+ * LD and difference list compression that is not implemented yet: the
+ * reader returns a `GenepaError::UnsupportedPgenMode` for it (and for the
+ * dosage modes) instead of decoding it.
+ *
+ * Genotypes are coded with respect to the ALT allele. The fixed-width modes
+ * only store the number of copies of the first ALT allele, so multiallelic
+ * records can't be split and are skipped (like the records with symbolic
+ * alleles, see `n_skipped`).
+ *
+ * PgenWriter writes filesets in the hardcall mode (0x02), with the coded
+ * allele as ALT and the REF alleles flagged as provisional (the other
+ * allele is not necessarily the reference allele).
+ */
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom,
+              Write};
+use std::iter::FusedIterator;
+use std::sync::Arc;
+
+use crate::core::{Chromosome, Genotypes, HaploidHets, Sample, Sex, Variant,
+                  VariantBuilder, VariantError, VariantKind};
+use crate::error::GenepaError;
+use crate::plink::{BED_CODES, decode_2bit_chunk, try_read_fam_from_reader};
+use crate::source::RegionPage;
+
+
+// In the pgen, the 2 bit codes are the number of ALT alleles (3 is missing).
+const PGEN_CODES: [Option<u8>; 4] = [Some(0), Some(1), Some(2), None];
+
+
+// The variants of a pvar, with the index of their record in the pgen.
+struct Pvar {
+    variants: Vec<(usize, Variant, String)>,
+    n_records: usize
+}
+
+
+// Variant of a pvar record, or None if it is skipped (multiallelic or with
+// symbolic alleles). Like in the BIM, unplaced variants can be at position
+// 0 (which is invalid in VCFs).
+fn parse_pvar_variant(name: &str, chrom: &str, position: &str,
+                      reference: &str, alt: &str)
+    -> Result<Option<Variant>, String>
+{
+    if alt.contains(',') {
+        return Ok(None);
+    }
+
+    let position: u32 = position.trim().parse()
+        .map_err(|_| format!("invalid position `{}`", position))?;
+
+    let variant = VariantBuilder::new()
+        .name(name)
+        .chrom(chrom)
+        .position(position.max(1))
+        .alleles(reference, alt)
+        .build();
+
+    match variant {
+        Ok(v) if v.kind() == VariantKind::Symbolic => Ok(None),
+        Ok(v) => Ok(Some(Variant { position, ..v })),
+        Err(VariantError::InvalidAllele(_)) => Ok(None),
+        Err(e) => Err(format!("invalid variant: {}", e))
+    }
+}
+
+
+// Read the variants and their ALT allele from a pvar. Files without a
+// header line are in the BIM format.
+fn read_pvar<R: BufRead>(reader: R, filename: &str)
+    -> Result<Pvar, GenepaError>
+{
+    // Indices of CHROM, ID, POS, REF and ALT.
+    let mut idx = (0, 1, 3, 5, 4);
+    let mut pvar = Pvar { variants: Vec::new(), n_records: 0 };
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| GenepaError::io(filename, e))?;
+        let error = |message: &str| {
+            GenepaError::parse(filename, i + 1, message)
+        };
+
+        if line.starts_with("##") || line.is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+
+        if line.starts_with("#CHROM") {
+            let column = |name: &str| {
+                fields.iter()
+                    .position(|&f| f == name)
+                    .ok_or_else(|| {
+                        error(&format!("missing column `{}`", name))
+                    })
+            };
+
+            idx = (0, column("ID")?, column("POS")?, column("REF")?,
+                   column("ALT")?);
+            continue;
+        }
+
+        let (chrom, id, pos, reference, alt) = idx;
+        let n_columns = chrom.max(id).max(pos).max(reference).max(alt) + 1;
+        if fields.len() < n_columns {
+            return Err(error(&format!("expected at least {} columns, got {}",
+                                      n_columns, fields.len())));
+        }
+
+        let variant = parse_pvar_variant(fields[id], fields[chrom],
+                                         fields[pos], fields[reference],
+                                         fields[alt])
+            .map_err(|message| error(&message))?;
+
+        if let Some(variant) = variant {
+            pvar.variants.push((pvar.n_records, variant,
+                                fields[alt].to_string()));
+        }
+        pvar.n_records += 1;
+    }
+
+    Ok(pvar)
+}
+
+
+// Read the samples from a psam. Files without a header line are in the FAM
+// format. If there is no FID column, the IID is used.
+fn read_psam<R: BufRead>(mut reader: R, filename: &str)
+    -> Result<Vec<Sample>, GenepaError>
+{
+    let mut first = String::new();
+    reader.read_line(&mut first)
+        .map_err(|e| GenepaError::io(filename, e))?;
+
+    if !first.starts_with('#') {
+        return try_read_fam_from_reader(first.as_bytes().chain(reader),
+                                        filename);
+    }
+
+    let header: Vec<&str> = first.trim_end()
+        .trim_start_matches('#')
+        .split('\t')
+        .collect();
+
+    let fid = header.iter().position(|&f| f == "FID");
+    let iid = header.iter().position(|&f| f == "IID")
+        .ok_or_else(|| {
+            GenepaError::parse(filename, 1, "missing column `IID`")
+        })?;
+    let sex = header.iter().position(|&f| f == "SEX");
+    let n_columns = fid.unwrap_or(iid).max(iid).max(sex.unwrap_or(iid)) + 1;
+
+    reader.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = line.map_err(|e| GenepaError::io(filename, e))?;
+            let fields: Vec<&str> = line.split('\t').collect();
+
+            if fields.len() < n_columns {
+                return Err(GenepaError::parse(
+                    filename, i + 2,
+                    &format!("expected at least {} columns, got {}",
+                             n_columns, fields.len())
+                ));
+            }
+
+            let sex = sex.map_or(Sex::Unknown,
+                                 |j| Sex::from_plink_code(fields[j]));
+            Ok(Sample::new(fields[fid.unwrap_or(iid)].to_string(),
+                           fields[iid].to_string(), sex))
+        })
+        .collect()
+}
+
+
+pub struct PgenReader {
+    // Index of the record in the pgen, variant and ALT allele.
+    variants: Vec<(usize, Variant, String)>,
+    n_skipped: u32,
+    samples: Arc<Vec<Sample>>,
+    attach_samples: bool,
+    haploid_hets: Option<HaploidHets>,
+    reader: BufReader<File>,
+    codes: [Option<u8>; 4],
+    data_offset: u64,
+    chunk_size: usize,
+    // Index of the next variant to read and of the record at the current
+    // position in the pgen.
+    n_read: usize,
+    cur_idx: usize
+}
+
+impl PgenReader {
+    pub fn new(prefix: &str) -> Result<PgenReader, GenepaError> {
+        let open = |ext: &str| {
+            let filename = format!("{}.{}", prefix, ext);
+            File::open(&filename)
+                .map(|f| (BufReader::new(f), filename.clone()))
+                .map_err(|e| GenepaError::io(&filename, e))
+        };
+
+        let (pvar, pvar_filename) = open("pvar")?;
+        let pvar = read_pvar(pvar, &pvar_filename)?;
+
+        let (psam, psam_filename) = open("psam")?;
+        let samples = Arc::new(read_psam(psam, &psam_filename)?);
+
+        let (mut reader, pgen_filename) = open("pgen")?;
+        let n_variants = pvar.n_records as u64;
+        let n_samples = samples.len() as u32;
+        let invalid = |message: &str| {
+            GenepaError::invalid_pgen(&pgen_filename, message)
+        };
+
+        let mut magic = [0; 3];
+        reader.read_exact(&mut magic)
+            .map_err(|_| invalid("could not read the header"))?;
+
+        if magic[..2] != [0x6c, 0x1b] {
+            return Err(invalid("not in the PGEN format (according to the \
+                                magic number)"));
+        }
+
+        let (codes, data_offset) = match magic[2] {
+            0x01 => (BED_CODES, 3),
+            0x02 => {
+                let mut header = [0; 9];
+                reader.read_exact(&mut header)
+                    .map_err(|_| invalid("could not read the header"))?;
+
+                let mut counts = [0; 4];
+                counts.copy_from_slice(&header[..4]);
+                let pgen_variants = u32::from_le_bytes(counts);
+                counts.copy_from_slice(&header[4..8]);
+                let pgen_samples = u32::from_le_bytes(counts);
+
+                if u64::from(pgen_variants) != n_variants ||
+                   pgen_samples != n_samples
+                {
+                    return Err(invalid(&format!(
+                        "the PGEN has {} variants and {} samples, but there \
+                         are {} variants in the PVAR and {} samples in the \
+                         PSAM", pgen_variants, pgen_samples, n_variants,
+                        n_samples
+                    )));
+                }
+
+                // The flags indicating that the REF alleles are provisional
+                // can be stored after the header.
+                let nonref_flags = if header[8] >> 6 == 3 {
+                    n_variants.div_ceil(8)
+                } else {
+                    0
+                };
+
+                (PGEN_CODES, 12 + nonref_flags)
+            },
+            mode => return Err(GenepaError::UnsupportedPgenMode {
+                path: pgen_filename, mode
+            })
+        };
+
+        let chunk_size = (n_samples as usize).div_ceil(4);
+
+        let n_bytes = reader.get_ref().metadata()
+            .map_err(|e| GenepaError::io(&pgen_filename, e))?
+            .len();
+
+        if n_bytes != data_offset + chunk_size as u64 * n_variants {
+            return Err(invalid(&format!(
+                "the size is not consistent with {} variants and {} samples",
+                n_variants, n_samples
+            )));
+        }
+
+        reader.seek(SeekFrom::Start(data_offset))
+            .map_err(|e| GenepaError::io(&pgen_filename, e))?;
+
+        let n_skipped = (pvar.n_records - pvar.variants.len()) as u32;
+
+        Ok(PgenReader {
+            variants: pvar.variants,
+            n_skipped, samples, reader, codes, data_offset, chunk_size,
+            attach_samples: false,
+            haploid_hets: Some(HaploidHets::SetMissing),
+            n_read: 0,
+            cur_idx: 0
+        })
+    }
+
+    pub fn attach_samples(&mut self, attach: bool) {
+        self.attach_samples = attach;
+    }
+
+    // See PlinkReader::haploid_policy.
+    pub fn haploid_policy(&mut self, hets: Option<HaploidHets>) {
+        self.haploid_hets = hets;
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn n_variants(&self) -> u32 {
+        self.variants.len() as u32
+    }
+
+    // Number of records of the pvar that are skipped because they are
+    // multiallelic or have symbolic alleles.
+    pub fn n_skipped(&self) -> u32 {
+        self.n_skipped
+    }
+
+    fn _read_idx(&mut self, i: usize) -> Genotypes {
+        let idx = self.variants[i].0;
+        if idx != self.cur_idx {
+            let offset = self.data_offset + (self.chunk_size * idx) as u64;
+            self.reader.seek(SeekFrom::Start(offset))
+                .expect("Could not seek in PGEN");
+        }
+
+        let mut buf = vec![0; self.chunk_size];
+        self.reader.read_exact(&mut buf)
+            .expect("Could not read bytes (the PGEN may be truncated).");
+        self.cur_idx = idx + 1;
+
+        let calls = decode_2bit_chunk(&buf, self.samples.len(), &self.codes);
+        let (_, v, alt) = &self.variants[i];
+        let mut g = Genotypes::new(v.clone(), calls, alt);
+
+        if let Some(hets) = self.haploid_hets {
+            if g.variant.chrom.is_haploid() {
+                g = g.into_haploid(hets).unwrap_or_else(|e| panic!("{}", e));
+            }
+        }
+
+        if self.attach_samples {
+            g.with_samples(Arc::clone(&self.samples))
+        } else {
+            g
+        }
+    }
+
+    fn _region_indices(&self, chrom: &Chromosome, start: u32, end: u32)
+        -> Vec<usize>
+    {
+        self.variants.iter()
+            .enumerate()
+            .filter(|(_, (_, v, _))| {
+                v.chrom == *chrom && v.position >= start && v.position <= end
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    pub fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        let matches: Vec<usize> = self._region_indices(&v.chrom, v.position,
+                                                       v.position)
+            .into_iter()
+            .filter(|&i| self.variants[i].1 == *v)
+            .collect();
+
+        match matches.len() {
+            0 => None,
+            1 => Some(self._read_idx(matches[0])),
+            _ => panic!("There are duplicate variants in the pvar file.")
+        }
+    }
+
+    pub fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                                  end: u32) -> Vec<Genotypes>
+    {
+        self._region_indices(chrom, start, end)
+            .into_iter()
+            .map(|i| self._read_idx(i))
+            .collect()
+    }
+
+    pub fn get_variants_in_region_page(&mut self, chrom: &Chromosome,
+                                       start: u32, end: u32, offset: usize,
+                                       limit: usize) -> RegionPage
+    {
+        let indices = self._region_indices(chrom, start, end);
+        let genotypes = indices.into_iter()
+            .skip(offset)
+            .map(|i| self._read_idx(i));
+
+        RegionPage::collect(genotypes, offset, limit)
+    }
+}
+
+
+impl Iterator for PgenReader {
+    type Item = Genotypes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.n_read >= self.variants.len() {
+            return None;
+        }
+
+        let g = self._read_idx(self.n_read);
+        self.n_read += 1;
+
+        Some(g)
+    }
+}
+
+impl FusedIterator for PgenReader {}
+
+
+// The header flag indicating that all the REF alleles are provisional.
+const PROVISIONAL_REF: u8 = 0x40;
+
+
+fn encode_pgen_genotype(g: Option<u8>) -> u8 {
+    match g {
+        Some(x) if x <= 2 => x,
+        _ => 0b11
+    }
+}
+
+
+// Writer for PLINK 2 filesets. The PSAM is written when the writer is
+// created and the PVAR and PGEN as variants are added. As the number of
+// variants is in the header of the PGEN, it is updated when the writer is
+// finished. Haploid genotypes are written as homozygous.
+pub struct PgenWriter {
+    pvar: BufWriter<File>,
+    pgen: BufWriter<File>,
+    n_samples: usize,
+    n_variants: u32
+}
+
+impl PgenWriter {
+    pub fn new(prefix: &str, samples: &[Sample]) -> io::Result<PgenWriter> {
+        let mut psam = BufWriter::new(
+            File::create(format!("{}.psam", prefix))?
+        );
+        writeln!(psam, "#FID\tIID\tSEX")?;
+        for s in samples {
+            writeln!(psam, "{}\t{}\t{}", s.fid, s.iid,
+                     s.sex.to_plink_code())?;
+        }
+        psam.flush()?;
+
+        let mut pvar = BufWriter::new(
+            File::create(format!("{}.pvar", prefix))?
+        );
+        writeln!(pvar, "#CHROM\tPOS\tID\tREF\tALT")?;
+
+        let mut pgen = BufWriter::new(
+            File::create(format!("{}.pgen", prefix))?
+        );
+        pgen.write_all(&[0x6c, 0x1b, 0x02])?;
+        pgen.write_all(&0_u32.to_le_bytes())?;
+        pgen.write_all(&(samples.len() as u32).to_le_bytes())?;
+        pgen.write_all(&[PROVISIONAL_REF])?;
+
+        Ok(PgenWriter { pvar, pgen, n_samples: samples.len(), n_variants: 0 })
+    }
+
+    pub fn write(&mut self, g: &Genotypes) -> io::Result<()> {
+        if g.genotypes.len() != self.n_samples {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Got {} genotypes for {} samples.",
+                        g.genotypes.len(), self.n_samples)
+            ));
+        }
+
+        let v = &g.variant;
+        writeln!(self.pvar, "{}\t{}\t{}\t{}\t{}", v.chrom, v.position,
+                 v.name, g.other_allele(), g.coded_allele())?;
+
+        let scale = if g.is_haploid() { 2 } else { 1 };
+
+        let chunk: Vec<u8> = g.genotypes.chunks(4)
+            .map(|calls| {
+                calls.iter()
+                    .enumerate()
+                    .fold(0, |b, (i, call)| {
+                        let code = encode_pgen_genotype(
+                            call.map(|x| x * scale)
+                        );
+                        b | (code << (2 * i))
+                    })
+            })
+            .collect();
+
+        self.pgen.write_all(&chunk)?;
+        self.n_variants += 1;
+
+        Ok(())
+    }
+
+    // Update the number of variants in the header, flush the files and
+    // return the number of variants that were written.
+    pub fn finish(mut self) -> io::Result<u32> {
+        self.pvar.flush()?;
+
+        self.pgen.seek(SeekFrom::Start(3))?;
+        self.pgen.write_all(&self.n_variants.to_le_bytes())?;
+        self.pgen.flush()?;
+
+        Ok(self.n_variants)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_fileset(name: &str, pvar: &str, psam: &str, pgen: &[u8])
+        -> String
+    {
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_pgen_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let prefix = dir.join("test").to_str().unwrap().to_string();
+        std::fs::write(format!("{}.pvar", prefix), pvar).unwrap();
+        std::fs::write(format!("{}.psam", prefix), psam).unwrap();
+        std::fs::write(format!("{}.pgen", prefix), pgen).unwrap();
+
+        prefix
+    }
+
+    #[test]
+    fn test_fixed_width() {
+        let mut pgen = vec![0x6c, 0x1b, 0x02, 3, 0, 0, 0, 5, 0, 0, 0, 0];
+        // Samples are AA, BB, CC, DD in every byte.
+        pgen.extend(&[0b11_10_01_00, 0b01]);
+        pgen.extend(&[0b00_00_00_10, 0b11]);
+        pgen.extend(&[0b01_01_01_01, 0b00]);
+
+        let prefix = write_fileset(
+            "fixed",
+            "##fileformat=PVARv1.0\n\
+             #CHROM\tPOS\tID\tREF\tALT\n\
+             1\t100\trs1\tA\tG\n\
+             1\t200\trs2\tC\tT\n\
+             2\t100\trs3\tA\tC\n",
+            "#IID\tSEX\ns1\t1\ns2\t2\ns3\tNA\ns4\t1\ns5\t2\n",
+            &pgen
+        );
+
+        let mut reader = PgenReader::new(&prefix).unwrap();
+        assert_eq!(reader.samples()[0].fid, "s1");
+        assert_eq!(reader.samples()[1].sex, Sex::Female);
+        assert_eq!(reader.samples()[2].sex, Sex::Unknown);
+
+        let g = reader.get_variants_in_region(
+            &Chromosome::Autosome(1), 150, 250
+        );
+        assert_eq!(g.len(), 1);
+        assert_eq!(g[0].coded_allele(), "T");
+        assert_eq!(g[0].genotypes,
+                   vec![Some(2), Some(0), Some(0), Some(0), None]);
+
+        let all: Vec<Genotypes> = reader.collect();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].genotypes,
+                   vec![Some(0), Some(1), Some(2), None, Some(1)]);
+        assert_eq!(all[2].variant.name, "rs3");
+
+        std::fs::remove_dir_all(std::path::Path::new(&prefix).parent()
+                                .unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_pgen_writer() {
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_pgen_writer_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("test").to_str().unwrap().to_string();
+
+        let samples: Vec<Sample> = (1..=5)
+            .map(|i| Sample::new(format!("f{}", i), format!("s{}", i),
+                                 if i == 1 { Sex::Male } else { Sex::Unknown }))
+            .collect();
+
+        let data = [
+            ("1", vec![Some(0), Some(1), Some(2), None, Some(1)]),
+            ("Y", vec![Some(1), None, Some(0), Some(0), Some(1)])
+        ];
+
+        let mut writer = PgenWriter::new(&prefix, &samples).unwrap();
+        for (i, (chrom, calls)) in data.iter().enumerate() {
+            let v = Variant::new(format!("rs{}", i), chrom.to_string(), 100,
+                                 ("A".to_string(), "G".to_string()));
+            let mut g = Genotypes::new(v, calls.clone(), "A");
+            if *chrom == "Y" {
+                g = g.with_ploidy(1);
+            }
+            writer.write(&g).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), 2);
+
+        let reader = PgenReader::new(&prefix).unwrap();
+        assert_eq!(reader.samples(), &samples[..]);
+
+        let all: Vec<Genotypes> = reader.collect();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].coded_allele(), "A");
+        for (g, (_, calls)) in all.iter().zip(data.iter()) {
+            assert_eq!(&g.genotypes, calls);
+        }
+        assert!(all[1].is_haploid());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bed_mode() {
+        // A pvar without header is in the BIM format (ALT before REF).
+        let prefix = write_fileset(
+            "bed",
+            "1\trs1\t0\t100\tG\tA\n",
+            "f1 s1 0 0 1 -9\nf2 s2 0 0 2 -9\n",
+            &[0x6c, 0x1b, 0x01, 0b10_00]
+        );
+
+        let all: Vec<Genotypes> = PgenReader::new(&prefix).unwrap().collect();
+        assert_eq!(all[0].coded_allele(), "G");
+        assert_eq!(all[0].genotypes, vec![Some(2), Some(1)]);
+
+        std::fs::remove_dir_all(std::path::Path::new(&prefix).parent()
+                                .unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_skipped_records() {
+        let mut pgen = vec![0x6c, 0x1b, 0x02, 4, 0, 0, 0, 2, 0, 0, 0, 0];
+        pgen.extend(&[0b10_00, 0b01_01, 0b00_10, 0b11_01]);
+
+        // The multiallelic and symbolic records are skipped, and the
+        // unplaced variant is at position 0.
+        let prefix = write_fileset(
+            "skipped",
+            "#CHROM\tPOS\tID\tREF\tALT\n\
+             1\t100\trs1\tA\tG,T\n\
+             1\t200\trs2\tC\t<DEL>\n\
+             1\t300\trs3\tA\tC\n\
+             1\t0\trs4\tA\tG\n",
+            "#IID\ns1\ns2\n",
+            &pgen
+        );
+
+        let mut reader = PgenReader::new(&prefix).unwrap();
+        assert_eq!(reader.n_variants(), 2);
+        assert_eq!(reader.n_skipped(), 2);
+
+        let g = reader.get_variants_in_region(
+            &Chromosome::Autosome(1), 300, 300
+        );
+        assert_eq!(g[0].genotypes, vec![Some(2), Some(0)]);
+
+        let all: Vec<Genotypes> = reader.collect();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].variant.name, "rs3");
+        assert_eq!(all[1].variant.position, 0);
+        assert_eq!(all[1].genotypes, vec![Some(1), None]);
+
+        std::fs::remove_dir_all(std::path::Path::new(&prefix).parent()
+                                .unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_errors() {
+        let pvar = "#CHROM\tPOS\tID\tREF\tALT\n1\t100\trs1\tA\tG\n";
+        let psam = "#IID\ns1\n";
+
+        // The variable-width mode written by plink2.
+        let prefix = write_fileset("errors", pvar, psam,
+                                   &[0x6c, 0x1b, 0x10, 1, 0, 0, 0, 1, 0, 0, 0]);
+        assert!(matches!(
+            PgenReader::new(&prefix),
+            Err(GenepaError::UnsupportedPgenMode { mode: 0x10, .. })
+        ));
+
+        std::fs::write(format!("{}.pgen", prefix), [0x6c, 0x1c, 0x02])
+            .unwrap();
+        assert!(matches!(PgenReader::new(&prefix),
+                         Err(GenepaError::InvalidPgen { .. })));
+
+        std::fs::write(format!("{}.pvar", prefix),
+                       "#CHROM\tPOS\tREF\tALT\n1\t100\tA\tG\n").unwrap();
+        assert!(matches!(PgenReader::new(&prefix),
+                         Err(GenepaError::Parse { line: 1, .. })));
+
+        std::fs::write(format!("{}.pvar", prefix),
+                       "#CHROM\tPOS\tID\tREF\tALT\nchr1\tx\trs1\tA\tG\n")
+            .unwrap();
+        assert!(matches!(PgenReader::new(&prefix),
+                         Err(GenepaError::Parse { line: 2, .. })));
+
+        std::fs::remove_dir_all(std::path::Path::new(&prefix).parent()
+                                .unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,1076 @@
+/*!
+ * Pipelines described by a TOML configuration file (`genepa run`).
+ *
+ * A pipeline has an input dataset (any format read by `open_source`), the
+ * optional samples and variants to keep, QC thresholds on the variants and a
+ * list of output steps, e.g.:
+ *
+ *     [input]
+ *     path = "cohort"
+ *     keep = "unrelated.txt"
+ *
+ *     [qc]
+ *     maf = 0.01
+ *     geno = 0.05
+ *     hwe = 1e-6
+ *
+ *     [[step]]
+ *     type = "extract"
+ *     format = "pgen"
+ *     out = "results/cohort.qc"
+ *
+ *     [[step]]
+ *     type = "score"
+ *     weights = "prs.tsv"
+ *     out = "results/prs.sscore"
+ *
+ *     [[step]]
+ *     type = "assoc"
+ *     pheno = "pheno.txt"
+ *     pheno-name = "BMI"
+ *     covar = "covariates.txt"
+ *     model = "linear"
+ *     flag-regions = "complex"
+ *     out = "results/cohort"
+ *
+ * The score and assoc steps can flag the variants of named regions with
+ * `flag-regions` (`complex` for the MHC and KIR regions of the `build`,
+ * GRCh37 by default, or a BED file): the association results get a REGION
+ * column and the number of score variants of every region is reported.
+ *
+ * The input is read once: every variant passing the filters and the QC
+ * thresholds is given to all the steps. The relative paths are relative to
+ * the directory of the configuration file, so that a pipeline gives the same
+ * results wherever it is run from. Unknown keys are errors rather than being
+ * silently ignored.
+ *
+ * `Pipeline::plan` estimates the passes over the input, the IO and the peak
+ * memory of a pipeline without reading the genotypes (`genepa run
+ * --dry-run`).
+ */
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::mem;
+use std::path::Path;
+
+use toml::{Table, Value};
+
+use rsgeneparselib::{Genotypes, Sample, Sex, Variant};
+use rsgeneparselib::assoc::{test_association, Model};
+use rsgeneparselib::bgen::BgenWriter;
+use rsgeneparselib::convert::{Filters, GenotypeSink};
+use rsgeneparselib::pgen::PgenWriter;
+use rsgeneparselib::plink::PlinkWriter;
+use rsgeneparselib::regions::RegionLabels;
+use rsgeneparselib::score::{ScoreWeight, Scorer};
+use rsgeneparselib::sexchrom::{coded_freq, hemizygous_coding, hwe_p,
+                               is_haploid_in_males, GenomeBuild};
+use rsgeneparselib::source::open_source;
+use rsgeneparselib::stats::hwe_exact;
+use rsgeneparselib::store::VariantCounts;
+use rsgeneparselib::sumstats::{SummaryStatsColumns, SummaryStatsReader};
+use rsgeneparselib::units::FrequencyEstimator;
+use rsgeneparselib::utils::open_text_file;
+use rsgeneparselib::zarr::{ZarrWriter, DEFAULT_CHUNKS as ZARR_CHUNKS};
+
+use crate::cli::{flagged_regions, glm_filename, read_fields, read_phenotype,
+                 read_sample_file, write_glm_header, write_glm_row};
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Input {
+    pub path: String,
+    // Samples (FID and IID) and variants (names) to keep.
+    pub keep: Option<String>,
+    pub extract: Option<String>
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QcThresholds {
+    pub maf: f64,
+    // Maximal missing call rate.
+    pub geno: f64,
+    // Minimal p-value of the HWE exact test (0 to keep every variant).
+    pub hwe: f64,
+    pub freq_estimator: FrequencyEstimator
+}
+
+impl Default for QcThresholds {
+    fn default() -> QcThresholds {
+        QcThresholds {
+            maf: 0.0,
+            geno: 1.0,
+            hwe: 0.0,
+            freq_estimator: FrequencyEstimator::Observed
+        }
+    }
+}
+
+impl QcThresholds {
+    // The HWE test is only done on diploid variants. On the X chromosome,
+    // the males count as a single allele for the MAF (always estimated from
+    // the observed alleles) and only the females are used for the HWE test
+    // (see `sexchrom`).
+    pub fn passes(&self, g: &Genotypes, sexes: &[Sex]) -> bool {
+        let counts = VariantCounts::of(g);
+        let maf = if is_haploid_in_males(&g.variant) {
+            let freq = coded_freq(g, sexes);
+            freq.min(1.0 - freq)
+        } else {
+            counts.coded_frequency_with(g.ploidy(), self.freq_estimator)
+                .map_or(f64::NAN, |p| p.minor().get())
+        };
+
+        let hwe_p = if self.hwe <= 0.0 || g.is_haploid() {
+            1.0
+        } else if is_haploid_in_males(&g.variant) {
+            hwe_p(g, sexes)
+        } else {
+            hwe_exact(counts.n_geno[1], counts.n_geno[0], counts.n_geno[2])
+        };
+
+        maf >= self.maf && 1.0 - counts.call_rate() <= self.geno &&
+            hwe_p >= self.hwe
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    // Write the variants (plink, pgen, bgen or zarr).
+    Extract { format: String, out: String },
+    // Polygenic score from weights with the columns SNP, CHR, BP, A1 (the
+    // effect allele), A2 and BETA.
+    Score { weights: String, flag_regions: Option<Flagged>, out: String },
+    // Association tests (see `genepa assoc`).
+    Assoc {
+        pheno: String,
+        pheno_name: Option<String>,
+        covar: Option<String>,
+        model: Model,
+        flag_regions: Option<Flagged>,
+        out: String
+    }
+}
+
+
+// Regions flagged by a step (see `flagged_regions`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Flagged {
+    pub regions: String,
+    pub build: GenomeBuild
+}
+
+impl Flagged {
+    fn labels(&self) -> RegionLabels {
+        flagged_regions(&self.regions, self.build)
+    }
+}
+
+
+// Check that a table only has known keys.
+fn check_keys(table: &Table, section: &str, known: &[&str])
+    -> Result<(), String>
+{
+    match table.keys().find(|key| !known.contains(&key.as_str())) {
+        Some(key) => Err(format!("Unknown key `{}` in [{}].", key, section)),
+        None => Ok(())
+    }
+}
+
+
+fn get_str(table: &Table, section: &str, key: &str)
+    -> Result<Option<String>, String>
+{
+    match table.get(key) {
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(format!("Expected a string for `{}` in [{}].", key,
+                               section)),
+        None => Ok(None)
+    }
+}
+
+
+fn get_f64(table: &Table, section: &str, key: &str)
+    -> Result<Option<f64>, String>
+{
+    match table.get(key) {
+        Some(Value::Float(x)) => Ok(Some(*x)),
+        Some(Value::Integer(x)) => Ok(Some(*x as f64)),
+        Some(_) => Err(format!("Expected a number for `{}` in [{}].", key,
+                               section)),
+        None => Ok(None)
+    }
+}
+
+
+fn get_table<'a>(table: &'a Table, key: &str)
+    -> Result<Option<&'a Table>, String>
+{
+    match table.get(key) {
+        Some(Value::Table(t)) => Ok(Some(t)),
+        Some(_) => Err(format!("Expected a table for [{}].", key)),
+        None => Ok(None)
+    }
+}
+
+
+// A step of the pipeline, given the variants passing the QC one at a time.
+trait Stage {
+    fn add(&mut self, g: &Genotypes) -> io::Result<()>;
+
+    // Write everything and return a summary of the step.
+    fn finish(self: Box<Self>) -> io::Result<String>;
+}
+
+
+struct ExtractStage<K> {
+    sink: K,
+    out: String,
+    n_written: u64
+}
+
+impl<K: GenotypeSink> Stage for ExtractStage<K> {
+    fn add(&mut self, g: &Genotypes) -> io::Result<()> {
+        self.n_written += 1;
+        self.sink.write(g)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<String> {
+        self.sink.finish()?;
+        Ok(format!("Wrote {} variants to `{}`.", self.n_written, self.out))
+    }
+}
+
+
+struct ScoreStage {
+    scorer: Scorer,
+    samples: Vec<Sample>,
+    out: String,
+    n_used: u64,
+    flagged: Option<RegionLabels>,
+    // Number of variants used in every flagged region.
+    n_flagged: BTreeMap<String, u64>
+}
+
+impl Stage for ScoreStage {
+    fn add(&mut self, g: &Genotypes) -> io::Result<()> {
+        if self.scorer.add(g) {
+            self.n_used += 1;
+
+            if let Some(flagged) = &self.flagged {
+                for label in flagged.labels(&g.variant) {
+                    *self.n_flagged.entry(label.to_string()).or_insert(0) +=
+                        1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<String> {
+        let mut out = BufWriter::new(File::create(&self.out)?);
+        writeln!(out, "#FID\tIID\tN_VARIANTS\tSCORE_SUM")?;
+
+        for (i, sample) in self.samples.iter().enumerate() {
+            let score = self.scorer.scores().get(i).copied().unwrap_or(0.0);
+            let count = self.scorer.counts().get(i).copied().unwrap_or(0);
+            writeln!(out, "{}\t{}\t{}\t{}", sample.fid, sample.iid, count,
+                     score)?;
+        }
+
+        out.flush()?;
+        let flagged: String = self.n_flagged.iter()
+            .map(|(label, n)| format!(" ({} in {})", n, label))
+            .collect();
+        Ok(format!("Scored {} samples using {} variants{} in `{}`.",
+                   self.samples.len(), self.n_used, flagged, self.out))
+    }
+}
+
+
+struct AssocStage {
+    // To code the males 0 or 1 on the X chromosome.
+    sexes: Vec<Sex>,
+    phenotype: Vec<Option<f64>>,
+    covariates: Vec<Vec<Option<f64>>>,
+    model: Model,
+    flagged: Option<RegionLabels>,
+    out: BufWriter<File>,
+    filename: String,
+    n_tested: u64
+}
+
+impl Stage for AssocStage {
+    fn add(&mut self, g: &Genotypes) -> io::Result<()> {
+        let result = if is_haploid_in_males(&g.variant) {
+            test_association(&hemizygous_coding(g.clone(), &self.sexes),
+                             &self.phenotype, &self.covariates, self.model)
+        } else {
+            test_association(g, &self.phenotype, &self.covariates,
+                             self.model)
+        };
+        self.n_tested += 1;
+        write_glm_row(&mut self.out, g, &result, self.model,
+                      self.flagged.as_ref())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<String> {
+        self.out.flush()?;
+        Ok(format!("Tested {} variants in `{}`.", self.n_tested,
+                   self.filename))
+    }
+}
+
+
+// Files of the input dataset (as opened by `open_source`) and the file with
+// a line per variant, if there is one.
+fn dataset_files(path: &str) -> (Vec<String>, Option<String>) {
+    if path.ends_with(".vcf") || path.ends_with(".vcf.gz") ||
+        path.ends_with(".bcf") || path.ends_with(".raw")
+    {
+        return (vec![path.to_string()], None);
+    }
+
+    if path.trim_end_matches('/').ends_with(".zarr") {
+        return (vec![path.to_string()], None);
+    }
+
+    let strip = |extensions: &[&str]| {
+        extensions.iter().find_map(|ext| path.strip_suffix(ext))
+    };
+    let files = |prefix: &str, extensions: &[&str], variants: &str| {
+        let files = extensions.iter()
+            .map(|ext| format!("{}{}", prefix, ext))
+            .collect();
+        (files, Some(format!("{}{}", prefix, variants)))
+    };
+
+    if let Some(prefix) = strip(&[".pgen", ".pvar", ".psam"]) {
+        files(prefix, &[".pgen", ".pvar", ".psam"], ".pvar")
+    } else if let Some(prefix) = strip(&[".ped", ".map"]) {
+        files(prefix, &[".ped", ".map"], ".map")
+    } else {
+        let prefix = strip(&[".bed", ".bim", ".fam"]).unwrap_or(path);
+        files(prefix, &[".bed", ".bim", ".fam"], ".bim")
+    }
+}
+
+
+// Size of a file or of all the files in a directory (e.g. Zarr stores).
+fn disk_usage(path: &Path) -> u64 {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return 0
+    };
+
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    fs::read_dir(path)
+        .map(|entries| {
+            entries.filter_map(|entry| entry.ok())
+                .map(|entry| disk_usage(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+
+// Number of lines that aren't empty or comments (`#`).
+fn count_records(filename: &str) -> Option<u64> {
+    if !Path::new(filename).is_file() {
+        return None;
+    }
+
+    let n = open_text_file(filename).lines()
+        .map_while(Result::ok)
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .count();
+    Some(n as u64)
+}
+
+
+// Estimated resources of a step.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepPlan {
+    pub description: String,
+    // Whether the step only needs the current variant, so that it can share
+    // the pass over the input with the other steps.
+    pub streaming: bool,
+    // Upper bound on the size of the outputs (None if unknown).
+    pub output_bytes: Option<u64>,
+    pub memory_bytes: u64
+}
+
+
+// Estimated passes over the input, IO and memory of a pipeline, without
+// running it. The estimates assume that every variant passes the QC, so the
+// outputs are upper bounds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plan {
+    pub n_samples: usize,
+    // Variants of the input (None if they can't be counted without reading
+    // the genotypes, e.g. VCF).
+    pub n_variants: Option<u64>,
+    pub input_bytes: u64,
+    pub n_passes: usize,
+    // Memory used by every step (e.g. the decoded genotypes of a variant).
+    pub base_memory_bytes: u64,
+    pub steps: Vec<StepPlan>
+}
+
+impl Plan {
+    pub fn peak_memory_bytes(&self) -> u64 {
+        self.base_memory_bytes +
+            self.steps.iter().map(|step| step.memory_bytes).sum::<u64>()
+    }
+
+    pub fn read_bytes(&self) -> u64 {
+        self.input_bytes * self.n_passes as u64
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let bytes = |n: Option<u64>| {
+            n.map_or_else(|| "unknown".to_string(), format_bytes)
+        };
+
+        writeln!(out, "samples\t{}", self.n_samples)?;
+        writeln!(out, "variants\t{}", self.n_variants
+            .map_or_else(|| "unknown".to_string(), |n| n.to_string()))?;
+        writeln!(out, "passes\t{} ({} of {} steps fused)", self.n_passes,
+                 self.steps.iter().filter(|step| step.streaming).count(),
+                 self.steps.len())?;
+        writeln!(out, "read\t{}", format_bytes(self.read_bytes()))?;
+
+        let written = self.steps.iter()
+            .map(|step| step.output_bytes)
+            .sum::<Option<u64>>();
+        writeln!(out, "written\t{}", bytes(written))?;
+        writeln!(out, "peak_memory\t{}",
+                 format_bytes(self.peak_memory_bytes()))?;
+
+        for step in self.steps.iter() {
+            writeln!(out, "step\t{}\twritten={}\tmemory={}",
+                     step.description, bytes(step.output_bytes),
+                     format_bytes(step.memory_bytes))?;
+        }
+
+        out.flush()
+    }
+}
+
+
+// Binary units (e.g. `1.5 MiB`).
+pub fn format_bytes(n: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut x = n as f64;
+    let mut unit = 0;
+    while x >= 1024.0 && unit < units.len() - 1 {
+        x /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", n)
+    } else {
+        format!("{:.1} {}", x, units[unit])
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pipeline {
+    pub input: Input,
+    pub qc: QcThresholds,
+    pub steps: Vec<Step>
+}
+
+impl Pipeline {
+    pub fn from_file(filename: &str) -> Result<Pipeline, String> {
+        let text = fs::read_to_string(filename)
+            .map_err(|e| format!("Could not read `{}`: {}", filename, e))?;
+        let base = Path::new(filename).parent().unwrap_or(Path::new(""));
+
+        Pipeline::parse(&text, base)
+            .map_err(|e| format!("`{}`: {}", filename, e))
+    }
+
+    // Parse a configuration with the paths relative to `base`.
+    pub fn parse(text: &str, base: &Path) -> Result<Pipeline, String> {
+        let config: Table = text.parse().map_err(|e| format!("{}", e))?;
+        check_keys(&config, "pipeline", &["input", "qc", "step"])?;
+
+        let path = |p: String| -> String {
+            base.join(p).to_string_lossy().into_owned()
+        };
+
+        let table = get_table(&config, "input")?
+            .ok_or("Missing the [input] section.")?;
+        check_keys(table, "input", &["path", "keep", "extract"])?;
+        let input = Input {
+            path: get_str(table, "input", "path")?
+                .map(path)
+                .ok_or("Missing the `path` of the [input].")?,
+            keep: get_str(table, "input", "keep")?.map(path),
+            extract: get_str(table, "input", "extract")?.map(path)
+        };
+
+        let mut qc = QcThresholds::default();
+        if let Some(table) = get_table(&config, "qc")? {
+            check_keys(table, "qc", &["maf", "geno", "hwe",
+                                      "freq-estimator"])?;
+            qc.maf = get_f64(table, "qc", "maf")?.unwrap_or(qc.maf);
+            qc.geno = get_f64(table, "qc", "geno")?.unwrap_or(qc.geno);
+            qc.hwe = get_f64(table, "qc", "hwe")?.unwrap_or(qc.hwe);
+            if let Some(estimator) = get_str(table, "qc", "freq-estimator")? {
+                qc.freq_estimator = estimator.parse()?;
+            }
+        }
+
+        let steps = match config.get("step") {
+            Some(Value::Array(steps)) => steps.as_slice(),
+            Some(_) => return Err("Expected a list of [[step]].".to_string()),
+            None => &[]
+        };
+        if steps.is_empty() {
+            return Err("The pipeline has no [[step]].".to_string());
+        }
+
+        let steps = steps.iter()
+            .map(|step| {
+                let table = step.as_table().ok_or("Expected a [[step]].")?;
+                let kind = get_str(table, "step", "type")?
+                    .ok_or("Missing the `type` of a [[step]].")?;
+                let section = format!("step {}", kind);
+                let required = |key: &str| {
+                    get_str(table, &section, key)?.ok_or_else(|| {
+                        format!("Missing `{}` in [{}].", key, section)
+                    })
+                };
+                let flag_regions = || -> Result<Option<Flagged>, String> {
+                    let build = match get_str(table, &section, "build")? {
+                        Some(build) => build.parse()?,
+                        None => GenomeBuild::Grch37
+                    };
+                    Ok(get_str(table, &section, "flag-regions")?
+                        .map(|regions| Flagged {
+                            regions: match regions.as_str() {
+                                "complex" => regions,
+                                _ => path(regions)
+                            },
+                            build
+                        }))
+                };
+
+                match kind.as_str() {
+                    "extract" => {
+                        check_keys(table, &section, &["type", "format",
+                                                      "out"])?;
+                        Ok(Step::Extract {
+                            format: get_str(table, &section, "format")?
+                                .unwrap_or_else(|| "plink".to_string()),
+                            out: path(required("out")?)
+                        })
+                    },
+                    "score" => {
+                        check_keys(table, &section, &["type", "weights",
+                                                      "flag-regions", "build",
+                                                      "out"])?;
+                        Ok(Step::Score {
+                            weights: path(required("weights")?),
+                            flag_regions: flag_regions()?,
+                            out: path(required("out")?)
+                        })
+                    },
+                    "assoc" => {
+                        check_keys(table, &section, &[
+                            "type", "pheno", "pheno-name", "covar", "model",
+                            "flag-regions", "build", "out"
+                        ])?;
+                        Ok(Step::Assoc {
+                            pheno: path(required("pheno")?),
+                            pheno_name: get_str(table, &section,
+                                                "pheno-name")?,
+                            covar: get_str(table, &section, "covar")?
+                                .map(path),
+                            model: get_str(table, &section, "model")?
+                                .as_deref()
+                                .unwrap_or("linear")
+                                .parse()?,
+                            flag_regions: flag_regions()?,
+                            out: path(required("out")?)
+                        })
+                    },
+                    _ => Err(format!("Unknown step `{}` (expected extract, \
+                                      score or assoc).", kind))
+                }
+            })
+            .collect::<Result<Vec<Step>, String>>()?;
+
+        Ok(Pipeline { input, qc, steps })
+    }
+
+    fn stage(&self, step: &Step, samples: &[Sample])
+        -> Result<Box<dyn Stage>, String>
+    {
+        let stage: Box<dyn Stage> = match step {
+            Step::Extract { format, out } => {
+                let err = |e: io::Error| {
+                    format!("Could not create `{}`: {}", out, e)
+                };
+                let out = out.clone();
+                match format.as_str() {
+                    "plink" => Box::new(ExtractStage {
+                        sink: PlinkWriter::new(&out, samples).map_err(err)?,
+                        out, n_written: 0
+                    }),
+                    "pgen" => Box::new(ExtractStage {
+                        sink: PgenWriter::new(&out, samples).map_err(err)?,
+                        out, n_written: 0
+                    }),
+                    "bgen" => Box::new(ExtractStage {
+                        sink: BgenWriter::create(&format!("{}.bgen", out),
+                                                 samples).map_err(err)?,
+                        out, n_written: 0
+                    }),
+                    "zarr" => Box::new(ExtractStage {
+                        sink: ZarrWriter::new(&format!("{}.zarr", out),
+                                              samples).map_err(err)?,
+                        out, n_written: 0
+                    }),
+                    _ => return Err(format!("Unknown format `{}` (expected \
+                                             plink, pgen, bgen or zarr).",
+                                            format))
+                }
+            },
+            Step::Score { weights, flag_regions, out } => {
+                let weights = SummaryStatsReader::new(
+                    weights, '\t', &SummaryStatsColumns::default()
+                )
+                    .map(|(oav, stat)| {
+                        let v = oav.variant;
+                        let effect_allele = if oav.a1_idx == 0 {
+                            v.alleles.0.clone()
+                        } else {
+                            v.alleles.1.clone()
+                        };
+                        ScoreWeight {
+                            variant: v,
+                            effect_allele,
+                            beta: stat.beta.unwrap_or(0.0)
+                        }
+                    })
+                    .collect();
+
+                Box::new(ScoreStage {
+                    scorer: Scorer::new(weights),
+                    samples: samples.to_vec(),
+                    out: out.clone(),
+                    n_used: 0,
+                    flagged: flag_regions.as_ref().map(Flagged::labels),
+                    n_flagged: BTreeMap::new()
+                })
+            },
+            Step::Assoc { pheno, pheno_name, covar, model, flag_regions,
+                          out } => {
+                let (name, phenotype) = read_phenotype(pheno, samples,
+                                                       pheno_name.as_deref(),
+                                                       *model)?;
+                let covariates = match covar {
+                    Some(covar) => read_sample_file(covar, samples, "COVAR")?
+                        .into_iter()
+                        .map(|(_, values)| values)
+                        .collect(),
+                    None => Vec::new()
+                };
+
+                let flagged = flag_regions.as_ref().map(Flagged::labels);
+                let filename = glm_filename(out, &name, *model);
+                let out = File::create(&filename)
+                    .map(BufWriter::new)
+                    .and_then(|mut out| {
+                        write_glm_header(&mut out, *model, flagged.as_ref())
+                            .map(|_| out)
+                    })
+                    .map_err(|e| {
+                        format!("Could not create `{}`: {}", filename, e)
+                    })?;
+
+                Box::new(AssocStage {
+                    sexes: samples.iter().map(|s| s.sex).collect(),
+                    phenotype, covariates, model: *model, flagged, out,
+                    filename, n_tested: 0
+                })
+            }
+        };
+
+        Ok(stage)
+    }
+
+    fn plan_step(step: &Step, n_samples: u64, n_variants: Option<u64>)
+        -> StepPlan
+    {
+        // Decoded calls of a sample, dosages and floats.
+        let call = mem::size_of::<Option<u8>>() as u64;
+        let float = mem::size_of::<f64>() as u64;
+        let per_variants = |bytes: u64| n_variants.map(|n| n * bytes);
+
+        match step {
+            Step::Extract { format, out } => {
+                let (per_variant, memory) = match format.as_str() {
+                    "plink" => (n_samples.div_ceil(4), n_samples.div_ceil(4)),
+                    // Difference lists can make the PGEN smaller.
+                    "pgen" => (n_samples.div_ceil(4), n_samples.div_ceil(4)),
+                    // Uncompressed probabilities of 8 bits.
+                    "bgen" => (2 * n_samples, 3 * n_samples * float),
+                    // The chunks of variants are buffered before writing.
+                    _ => {
+                        let (variants, _) = ZARR_CHUNKS;
+                        (2 * n_samples, variants as u64 * 2 * n_samples)
+                    }
+                };
+
+                StepPlan {
+                    description: format!("extract\t{}\t{}", format, out),
+                    streaming: true,
+                    // The variants also have a line of text.
+                    output_bytes: per_variants(per_variant + 64),
+                    memory_bytes: memory
+                }
+            },
+            Step::Score { weights, out, .. } => {
+                // The weights are in memory, with their variant.
+                let n_weights = count_records(weights)
+                    .map_or(0, |n| n.saturating_sub(1));
+                let weight = (mem::size_of::<Variant>() + 64) as u64;
+
+                StepPlan {
+                    description: format!("score\t{}\t{}", weights, out),
+                    streaming: true,
+                    output_bytes: Some(64 * n_samples),
+                    memory_bytes: n_weights * weight +
+                        n_samples * (float + 4)
+                }
+            },
+            Step::Assoc { covar, model, out, .. } => {
+                let n_covariates = covar.as_deref()
+                    .and_then(|covar| {
+                        let f = File::open(covar).ok()?;
+                        let mut lines = BufReader::new(f).lines();
+                        let header = lines.next()?.ok()?;
+                        Some(header.split_whitespace().count()
+                             .saturating_sub(2) as u64)
+                    })
+                    .unwrap_or(0);
+
+                // Phenotype and covariates, and the design matrix (with the
+                // intercept and the genotypes) of every variant. Logistic
+                // regression also needs the weighted design matrix.
+                let n_columns = n_covariates + 2;
+                let design = n_samples * (n_columns + 1) * float;
+                let design = match model {
+                    Model::Linear => design,
+                    Model::Logistic => 2 * design
+                };
+
+                StepPlan {
+                    description: format!("assoc\t{}",
+                                         glm_filename(out, "*", *model)),
+                    streaming: true,
+                    output_bytes: per_variants(96),
+                    memory_bytes: n_samples * (n_covariates + 1) *
+                        (call + float) + design
+                }
+            }
+        }
+    }
+
+    // Estimate the passes, IO and memory of the pipeline without reading the
+    // genotypes.
+    pub fn plan(&self) -> Result<Plan, String> {
+        let source = open_source(&self.input.path);
+        let filters = self.filters(source.samples())?;
+        let n_samples = filters.kept_samples(source.samples()).len();
+
+        let (files, variants_file) = dataset_files(&self.input.path);
+        let n_variants = variants_file.and_then(|f| count_records(&f));
+        let input_bytes = files.iter()
+            .map(|f| disk_usage(Path::new(f)))
+            .sum();
+
+        let steps: Vec<StepPlan> = self.steps.iter()
+            .map(|step| Pipeline::plan_step(step, n_samples as u64,
+                                            n_variants))
+            .collect();
+
+        // Every step is streamed, so they are all fused in a single pass. The
+        // genotypes of the current variant are decoded (and subset).
+        let n_all = source.samples().len() as u64;
+        let call = mem::size_of::<Option<u8>>() as u64;
+
+        Ok(Plan {
+            n_samples,
+            n_variants,
+            input_bytes,
+            n_passes: if steps.iter().all(|step| step.streaming) {
+                1
+            } else {
+                steps.len()
+            },
+            base_memory_bytes: (n_all + n_samples as u64) * call,
+            steps
+        })
+    }
+
+    fn filters(&self, samples: &[Sample]) -> Result<Filters, String> {
+        let mut filters = Filters::default();
+
+        if let Some(keep) = &self.input.keep {
+            let ids: HashSet<(String, String)> = read_fields(keep)?
+                .into_iter()
+                .filter(|fields| fields.len() >= 2)
+                .map(|fields| (fields[0].clone(), fields[1].clone()))
+                .collect();
+            filters = filters.keep_samples(samples, &ids);
+        }
+
+        if let Some(extract) = &self.input.extract {
+            let names = read_fields(extract)?
+                .into_iter()
+                .map(|fields| fields[0].clone())
+                .collect();
+            filters = filters.keep_variants(names);
+        }
+
+        Ok(filters)
+    }
+
+    // Run all the steps in a single pass over the input. Returns the number
+    // of variants read and passing the filters.
+    pub fn run(&self) -> Result<(u64, u64), String> {
+        let source = open_source(&self.input.path);
+        let filters = self.filters(source.samples())?;
+
+        let samples = filters.kept_samples(source.samples());
+        let sexes: Vec<Sex> = samples.iter().map(|s| s.sex).collect();
+        let mut stages = self.steps.iter()
+            .map(|step| self.stage(step, &samples))
+            .collect::<Result<Vec<Box<dyn Stage>>, String>>()?;
+
+        let (mut n_read, mut n_passing) = (0, 0);
+        for g in source {
+            n_read += 1;
+
+            if let Some(keep) = &filters.variants {
+                if !keep(&g) {
+                    continue;
+                }
+            }
+
+            let g = match &filters.samples {
+                Some(indices) => g.subset(indices),
+                None => g
+            };
+            if !self.qc.passes(&g, &sexes) {
+                continue;
+            }
+
+            n_passing += 1;
+            for stage in stages.iter_mut() {
+                stage.add(&g)
+                    .map_err(|e| format!("Could not write a step: {}", e))?;
+            }
+        }
+
+        for stage in stages {
+            let summary = stage.finish()
+                .map_err(|e| format!("Could not finish a step: {}", e))?;
+            eprintln!("{}", summary);
+        }
+
+        Ok((n_read, n_passing))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsgeneparselib::{Sex, Variant};
+    use rsgeneparselib::index::{build_index, IndexFormat};
+
+    #[test]
+    fn test_parse() {
+        let config = "[input]\n\
+                      path = \"data/cohort\"\n\
+                      keep = \"/abs/keep.txt\"\n\
+                      [qc]\n\
+                      maf = 0.01\n\
+                      geno = 0\n\
+                      [[step]]\n\
+                      type = \"extract\"\n\
+                      out = \"qc\"\n\
+                      [[step]]\n\
+                      type = \"assoc\"\n\
+                      pheno = \"pheno.txt\"\n\
+                      model = \"logistic\"\n\
+                      out = \"res\"\n";
+
+        let pipeline = Pipeline::parse(config, Path::new("base")).unwrap();
+        assert_eq!(pipeline.input, Input {
+            path: "base/data/cohort".to_string(),
+            keep: Some("/abs/keep.txt".to_string()),
+            extract: None
+        });
+        assert_eq!(pipeline.qc.maf, 0.01);
+        assert_eq!(pipeline.qc.geno, 0.0);
+        assert_eq!(pipeline.qc.hwe, 0.0);
+        assert_eq!(pipeline.steps, vec![
+            Step::Extract { format: "plink".to_string(),
+                            out: "base/qc".to_string() },
+            Step::Assoc { pheno: "base/pheno.txt".to_string(),
+                          pheno_name: None, covar: None,
+                          model: Model::Logistic, flag_regions: None,
+                          out: "base/res".to_string() }
+        ]);
+
+        let config = "[input]\npath = \"a\"\n[[step]]\ntype = \"score\"\n\
+                      weights = \"w.tsv\"\nflag-regions = \"complex\"\n\
+                      build = \"hg38\"\nout = \"s\"\n";
+        let pipeline = Pipeline::parse(config, Path::new("")).unwrap();
+        assert_eq!(pipeline.steps, vec![
+            Step::Score { weights: "w.tsv".to_string(),
+                          flag_regions: Some(Flagged {
+                              regions: "complex".to_string(),
+                              build: GenomeBuild::Grch38
+                          }),
+                          out: "s".to_string() }
+        ]);
+
+        let error = |config: &str| {
+            Pipeline::parse(config, Path::new("")).unwrap_err()
+        };
+        assert_eq!(error("[input]\npath = \"a\"\n"),
+                   "The pipeline has no [[step]].");
+        assert_eq!(error("[input]\npath = \"a\"\nmaf = 0.1\n"),
+                   "Unknown key `maf` in [input].");
+        assert_eq!(error("[input]\npath = \"a\"\n[[step]]\ntype = \"score\"\n\
+                          out = \"a\"\n"),
+                   "Missing `weights` in [step score].");
+        assert_eq!(error("[input]\npath = 1\n"),
+                   "Expected a string for `path` in [input].");
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(1000), "1000 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 << 30), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_qc_x() {
+        let sexes = [Sex::Male, Sex::Male, Sex::Female, Sex::Female];
+        let genotypes = |chrom: &str| {
+            let v = Variant::new("rs1".to_string(), chrom.to_string(),
+                                 5_000_000,
+                                 ("A".to_string(), "G".to_string()));
+            Genotypes::new(v, vec![Some(2), Some(2), Some(0), Some(0)], "G")
+        };
+
+        // Only the females are tested for HWE on the X chromosome.
+        let qc = QcThresholds { hwe: 0.5, ..QcThresholds::default() };
+        assert!(!qc.passes(&genotypes("1"), &sexes));
+        assert!(qc.passes(&genotypes("X"), &sexes));
+
+        // The MAF is 1/3 when the males count as a single allele.
+        let qc = QcThresholds { maf: 0.4, ..QcThresholds::default() };
+        assert!(qc.passes(&genotypes("1"), &sexes));
+        assert!(!qc.passes(&genotypes("X"), &sexes));
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_pipeline_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let samples: Vec<Sample> = (1..=4)
+            .map(|i| Sample::new(format!("f{}", i), format!("s{}", i),
+                                 Sex::Unknown))
+            .collect();
+
+        let mut writer = PlinkWriter::new(&path("in"), &samples).unwrap();
+        let calls = [
+            vec![Some(0), Some(1), Some(2), Some(1)],
+            // Monomorphic.
+            vec![Some(0), Some(0), Some(0), Some(0)],
+            vec![Some(1), Some(1), None, Some(0)]
+        ];
+        for (i, calls) in calls.iter().enumerate() {
+            let v = Variant::new(format!("rs{}", i + 1), "1".to_string(),
+                                 100 * (i as u32 + 1),
+                                 ("A".to_string(), "G".to_string()));
+            writer.write(&Genotypes::new(v, calls.clone(), "G")).unwrap();
+        }
+        writer.finish().unwrap();
+        build_index(&path("in"), IndexFormat::V2).unwrap();
+
+        fs::write(path("keep.txt"), "f1 s1\nf2 s2\nf4 s4\n").unwrap();
+        fs::write(path("prs.tsv"), "SNP\tCHR\tBP\tA1\tA2\tBETA\n\
+                                    rs1\t1\t100\tA\tG\t0.5\n\
+                                    rs2\t1\t200\tG\tA\t2\n").unwrap();
+        fs::write(path("pipeline.toml"), "[input]\n\
+                                          path = \"in\"\n\
+                                          keep = \"keep.txt\"\n\
+                                          [qc]\n\
+                                          maf = 0.1\n\
+                                          [[step]]\n\
+                                          type = \"extract\"\n\
+                                          out = \"out\"\n\
+                                          [[step]]\n\
+                                          type = \"score\"\n\
+                                          weights = \"prs.tsv\"\n\
+                                          out = \"prs.sscore\"\n").unwrap();
+
+        let pipeline = Pipeline::from_file(&path("pipeline.toml")).unwrap();
+
+        let plan = pipeline.plan().unwrap();
+        assert_eq!((plan.n_samples, plan.n_variants), (3, Some(3)));
+        assert_eq!(plan.n_passes, 1);
+        assert_eq!(plan.input_bytes, fs::metadata(path("in.bed")).unwrap()
+                   .len() + fs::metadata(path("in.bim")).unwrap().len() +
+                   fs::metadata(path("in.fam")).unwrap().len());
+        // 1 byte per variant in the BED (and its BIM line).
+        assert_eq!(plan.steps[0].output_bytes, Some(3 * 65));
+        assert!(plan.peak_memory_bytes() > plan.steps[1].memory_bytes);
+
+        assert_eq!(pipeline.run(), Ok((3, 2)));
+
+        let fam = fs::read_to_string(path("out.fam")).unwrap();
+        assert_eq!(fam.lines().count(), 3);
+        let bim = fs::read_to_string(path("out.bim")).unwrap();
+        let names: Vec<&str> = bim.lines()
+            .map(|line| line.split('\t').nth(1).unwrap())
+            .collect();
+        assert_eq!(names, vec!["rs1", "rs3"]);
+
+        // Only rs1 is scored (rs2 doesn't pass the QC). The A allele is the
+        // other allele.
+        let scores = fs::read_to_string(path("prs.sscore")).unwrap();
+        assert_eq!(scores.lines().collect::<Vec<&str>>(), vec![
+            "#FID\tIID\tN_VARIANTS\tSCORE_SUM",
+            "f1\ts1\t1\t1",
+            "f2\ts2\t1\t0.5",
+            "f4\ts4\t1\t0.5"
+        ]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -4,289 +4,1354 @@
  * Utilities to read plink files.
  */
 
-use std::iter::{FromIterator};
+use std::collections::HashSet;
+use std::iter::{FromIterator, FusedIterator};
 use std::path::Path;
 use std::process::{Command, Stdio};
-use std::io::{BufReader, BufRead, Write, SeekFrom, Seek};
+use std::io::{self, BufReader, BufRead, BufWriter, Read, Write, SeekFrom,
+              Seek};
 use std::fs::{File, OpenOptions};
-
+use std::sync::{Arc, OnceLock};
+
+use crate::checksum::{Manifest, Verification, VerifyingReader};
+use crate::error::GenepaError;
+use crate::index::{name_index_filename, NameIndex, NativeBimIndex};
+use crate::iobackend::{self, IoBackend};
+use crate::source::RegionPage;
+use crate::store::VariantCounts;
+use crate::utils::try_open_text_file;
+#[cfg(feature = "encryption")]
+use crate::crypto::{DecryptingReader, EncryptionKey};
+#[cfg(feature = "http")]
+use crate::http::{download, is_url, HttpReader};
 use crate::core::{VarFieldIdx, DelimitedVariantsReader, Variant, Genotypes,
-                  Chromosome};
-
+                  Chromosome, Sample, HaploidHets, OrderedAllelesVariant};
 
-struct BimIndex {
-    filename: String,
-    n_variants: u32
 
+pub(crate) struct BimIndex {
+    pub(crate) filename: String,
+    pub(crate) n_variants: u32,
+    // Contig names of the index, as written in the BIM (listed by tabix on
+    // the first query).
+    pub(crate) contigs: OnceLock<Vec<String>>
 }
 
 impl BimIndex {
-    pub fn get_or_create_bim_index(filename: &str) -> BimIndex {
+    // The lines of the BIM are parsed as it is read, so that malformed
+    // lines are reported when the index is created.
+    pub fn get_or_create_bim_index(filename: &str)
+        -> Result<BimIndex, GenepaError>
+    {
         let f = File::open(filename)
-            .expect(&format!("Could not read BIM: `{}`", filename));
+            .map_err(|e| GenepaError::io(filename, e))?;
         let buf_reader = BufReader::new(f);
 
         let output_filename = String::from(filename).replace(".bim", ".bimidx.gz");
 
+        // Check a line of the BIM and count it.
+        let mut n_variants: usize = 0;
+        let mut check_line = |line: io::Result<String>| {
+            let line = line.map_err(|e| GenepaError::io(filename, e))?;
+            n_variants += 1;
+            parse_bim_line(&line).map_err(|message| {
+                GenepaError::parse(filename, n_variants, &message)
+            })?;
+            Ok(line)
+        };
+
         if Path::new(&output_filename).is_file() {
             // Unfortunately, we have to know the number of variants so it
-            // is necessary to count the lines.
-            let n_variants = buf_reader.lines().count() as u32;
+            // is necessary to read the lines.
+            for line in buf_reader.lines() {
+                check_line(line)?;
+            }
 
-            return BimIndex { filename: output_filename, n_variants };
+            return Ok(BimIndex {
+                filename: output_filename,
+                n_variants: n_variants as u32,
+                contigs: OnceLock::new()
+            });
         }
 
+        let index_error = |message: &str| {
+            GenepaError::index(&output_filename, message)
+        };
+
         let output = OpenOptions::new()
             .write(true)
             .create(true)
             .open(&output_filename)
-            .expect("Can't create BIM index file.");
+            .map_err(|e| GenepaError::io(&output_filename, e))?;
 
         // Spawn the bgzip process to directly write to it.
         let mut bgzip = Command::new("bgzip")
             .stdin(Stdio::piped())
             .stdout(Stdio::from(output))
             .spawn()
-            .unwrap();
+            .map_err(|e| index_error(&format!("could not run bgzip: {}", e)))?;
 
-        let mut bgzip_stdin = bgzip.stdin.as_mut()
-            .expect("Could not get bgzip stdin.");
+        let mut bgzip_stdin = bgzip.stdin.take()
+            .ok_or_else(|| index_error("could not get bgzip stdin"))?;
 
         // Write the index to disk.
-        let mut n_variants: usize = 0;
         for (i, line) in buf_reader.lines().enumerate() {
-            // TODO we could build a name index at the same time.
-            write!(&mut bgzip_stdin, "{}\t{}\n", line.unwrap().as_str(), i)
-                .expect("Failed writing line to BIM index.");
-
-            n_variants = i;
+            let line = check_line(line)?;
+            writeln!(&mut bgzip_stdin, "{}\t{}", line.as_str(), i)
+                .map_err(|e| GenepaError::io(&output_filename, e))?;
         };
+        drop(bgzip_stdin);
 
         match bgzip.wait() {
-            Ok(status) => {
-                 if !status.success() {
-                     panic!("Error creating index: bgzip process returned with \
-                            an error");
-                 }
-            },
-            Err(e) => panic!("Error executing bgzip: {:?}", e)
+            Ok(status) if status.success() => {},
+            Ok(_) => return Err(index_error("bgzip returned an error")),
+            Err(e) => {
+                return Err(index_error(&format!("could not run bgzip: {}", e)))
+            }
         };
 
         // Tabix
         let tabix_output = Command::new("tabix")
                 .args(&["-s", "1", "-b", "4", "-e", "4", &output_filename])
                 .output()
-                .expect("Tabix failed.");
+                .map_err(|e| {
+                    index_error(&format!("could not run tabix: {}", e))
+                })?;
 
         if !tabix_output.status.success() {
-            panic!("Tabix returned an error, could not build BIM index.");
+            return Err(index_error("tabix returned an error"));
         }
 
-        BimIndex {
+        Ok(BimIndex {
             filename: output_filename,
-            n_variants: n_variants as u32
-        }
+            n_variants: n_variants as u32,
+            contigs: OnceLock::new()
+        })
     }
 
-    // Returns a vector of index, variant, coded_allele
-    fn _run_tabix(&self, region: &str) -> Vec<(u32, Variant, String)> {
-        let tabix = Command::new("tabix")
-            .arg(&self.filename)
-            .arg(region)
+    fn _error(&self, message: &str) -> GenepaError {
+        GenepaError::index(&self.filename, message)
+    }
+
+    // Output of bgzip or tabix run with the arguments.
+    fn _run(&self, program: &str, args: &[&str])
+        -> Result<String, GenepaError>
+    {
+        let output = Command::new(program)
+            .args(args)
             .output()
-            .expect("Couldn't spawn tabix for BIM variant query.");
+            .map_err(|e| {
+                self._error(&format!("could not run {}: {}", program, e))
+            })?;
 
-        if !tabix.status.success() {
-            panic!("Error searching the BIM index using tabix.");
+        if !output.status.success() {
+            return Err(self._error(&format!("{} returned an error",
+                                            program)));
         }
 
-        String::from_utf8(tabix.stdout)
-            .unwrap()
+        String::from_utf8(output.stdout)
+            .map_err(|_| self._error(&format!("invalid output from {}",
+                                              program)))
+    }
+
+    // Count the records in the bgzipped index. This should match the number
+    // of lines in the BIM it was built from.
+    pub(crate) fn count_indexed_variants(&self) -> Result<u32, GenepaError> {
+        let records = self._run("bgzip", &["-dc", &self.filename])?;
+        Ok(bytecount_newlines(records.as_bytes()))
+    }
+
+    // Returns a vector of index, variant, coded_allele
+    fn _run_tabix(&self, region: &str)
+        -> Result<Vec<(u32, Variant, String)>, GenepaError>
+    {
+        self._run("tabix", &[&self.filename, region])?
             .lines()
             .map(|line| {
-                // Parse a variant.
-                let vec = Vec::from_iter(line.split('\t'));
-
-                let chrom: String = vec[0].to_string();
-                let name: String = vec[1].to_string();
-                let pos: u32 = vec[3].to_string().parse().unwrap();
-                let a1: String = vec[4].to_string();
-                let a2: String = vec[5].to_string();
+                let (variant, a1) = parse_bim_line(line)
+                    .map_err(|e| self._error(&e))?;
+                let idx: u32 = line.rsplit('\t').next()
+                    .and_then(|idx| idx.parse().ok())
+                    .ok_or_else(|| {
+                        self._error(&format!("invalid record `{}`", line))
+                    })?;
+
+                Ok((idx, variant, a1))
+            })
+            .collect()
+    }
 
-                let variant = Variant::new(name, chrom, pos, (a1.clone(), a2));
+    fn contigs(&self) -> Result<&[String], GenepaError> {
+        if let Some(contigs) = self.contigs.get() {
+            return Ok(contigs);
+        }
 
-                let idx: u32 = vec[6].to_string().parse().unwrap();
+        let contigs = self._run("tabix", &["-l", &self.filename])?
+            .lines()
+            .map(|contig| contig.to_string())
+            .collect();
 
-                (idx, variant, a1)
-            })
-            .collect()
+        Ok(self.contigs.get_or_init(|| contigs))
     }
 
-    fn get_region_index_and_coded(&self, chrom: &str, start: u32, end: u32)
-        -> Vec<(u32, Variant, String)> {
-            let region = format!("{}:{}-{}", chrom, start, end);
-            self._run_tabix(&region)
+    // The region is queried on every contig with the chromosome's name once
+    // normalized (e.g. `23` and `X`).
+    fn get_region_index_and_coded(&self, chrom: &Chromosome, start: u32,
+                                  end: u32)
+        -> Result<Vec<(u32, Variant, String)>, GenepaError>
+    {
+        let mut matches = Vec::new();
+        for contig in self.contigs()? {
+            if Chromosome::new(contig) == *chrom {
+                let region = format!("{}:{}-{}", contig, start, end);
+                matches.extend(self._run_tabix(&region)?);
+            }
         }
 
-    fn get_variant_index_and_coded(&self, v: &Variant) -> Option<(u32, String)> {
-        let region = format!("{}:{}-{}", v.chrom.name, v.position, v.position);
+        Ok(matches)
+    }
+}
 
-        let matches: Vec<(u32, Variant, String)> = self._run_tabix(&region)
-            .into_iter()
-            .filter(|(_, observed, _)| {
-                observed == v
-            })
-            .collect();
 
-        match matches.len() {
-            0 => None,
-            1 => {
-                let mtch = &matches[0];
-                // Returns index and a1.
-                Some((mtch.0, mtch.2.clone()))
+// Parse a variant and its coded allele from a BIM line.
+pub(crate) fn parse_bim_line(line: &str) -> Result<(Variant, String), String> {
+    let vec = Vec::from_iter(line.split('\t'));
+    if vec.len() < 6 {
+        return Err(format!("expected 6 columns, got {}", vec.len()));
+    }
+
+    let chrom: String = vec[0].to_string();
+    let name: String = vec[1].to_string();
+    let pos: u32 = vec[3].parse()
+        .map_err(|_| format!("invalid position `{}`", vec[3]))?;
+    let a1: String = vec[4].to_string();
+    let a2: String = vec[5].to_string();
+
+    Ok((Variant::new(name, chrom, pos, (a1.clone(), a2)), a1))
+}
+
+
+enum VariantIndex {
+    // Tabix index of the BIM on disk.
+    Tabix(BimIndex),
+    // Native index of the BIM on disk (see the index module).
+    Native(NativeBimIndex),
+    // Variants and coded alleles in the order of the BIM. This is used when
+    // the fileset can't be indexed on disk (e.g. it is encrypted).
+    Memory(Vec<(Variant, String)>)
+}
+
+impl VariantIndex {
+    fn n_variants(&self) -> u32 {
+        match self {
+            VariantIndex::Tabix(index) => index.n_variants,
+            VariantIndex::Native(index) => index.n_variants,
+            VariantIndex::Memory(variants) => variants.len() as u32
+        }
+    }
+
+    fn count_indexed_variants(&self) -> Result<u32, GenepaError> {
+        match self {
+            VariantIndex::Tabix(index) => index.count_indexed_variants(),
+            VariantIndex::Native(index) => Ok(index.count_indexed_variants()),
+            VariantIndex::Memory(variants) => Ok(variants.len() as u32)
+        }
+    }
+
+    fn get_region_index_and_coded(&self, chrom: &Chromosome, start: u32,
+                                  end: u32)
+        -> Result<Vec<(u32, Variant, String)>, GenepaError>
+    {
+        match self {
+            VariantIndex::Tabix(index) => {
+                index.get_region_index_and_coded(chrom, start, end)
+            },
+            VariantIndex::Native(index) => {
+                Ok(index.get_region_index_and_coded(chrom, start, end))
             },
-            _ => panic!("There are duplicate variants in the bim file.")
+            VariantIndex::Memory(variants) => {
+                Ok(variants.iter()
+                    .enumerate()
+                    .filter(|(_, (v, _))| {
+                        v.chrom == *chrom &&
+                        v.position >= start &&
+                        v.position <= end
+                    })
+                    .map(|(i, (v, a1))| (i as u32, v.clone(), a1.clone()))
+                    .collect())
+            }
         }
     }
+
+    // The in-memory index is searched directly and the disk indexes use the
+    // name index of the fileset, which is opened (or built) on the first
+    // query.
+    fn get_name_index_and_coded(&self, name: &str,
+                                names: &OnceLock<NameIndex>, prefix: &str)
+        -> Result<Vec<(u32, Variant, String)>, GenepaError>
+    {
+        if let VariantIndex::Memory(variants) = self {
+            return Ok(variants.iter()
+                .enumerate()
+                .filter(|(_, (v, _))| v.name == name)
+                .map(|(i, (v, a1))| (i as u32, v.clone(), a1.clone()))
+                .collect());
+        }
+
+        if names.get().is_none() {
+            let filename = name_index_filename(prefix);
+            let index = NameIndex::get_or_create(
+                &filename, &format!("{}.bim", prefix)
+            ).map_err(|e| GenepaError::index(&filename, &e.to_string()))?;
+            let _ = names.set(index);
+        }
+
+        Ok(names.get().unwrap().get_name_index_and_coded(name))
+    }
+
+    // The index and coded allele of every indexed variant equal to `v`.
+    fn get_variant_index_and_coded(&self, v: &Variant)
+        -> Result<Vec<(u32, String)>, GenepaError>
+    {
+        Ok(self.get_region_index_and_coded(&v.chrom, v.position, v.position)?
+            .into_iter()
+            .filter(|(_, observed, _)| observed == v)
+            .map(|(idx, _, a1)| (idx, a1))
+            .collect())
+    }
 }
 
-// Read a fam into a vector of sample IDs.
-fn read_fam(filename: &str) -> Vec<String> {
-    let f = File::open(filename).expect("Could not open FAM");
-    let reader = BufReader::new(f);
+fn bytecount_newlines(bytes: &[u8]) -> u32 {
+    bytes.iter().filter(|&&b| b == b'\n').count() as u32
+}
 
+// Read a fam into a vector of samples.
+// The FAM can be gzipped.
+pub(crate) fn read_fam(filename: &str) -> Result<Vec<Sample>, GenepaError> {
+    try_read_fam_from_reader(try_open_text_file(filename)?, filename)
+}
+
+pub(crate) fn read_fam_from_reader<R: BufRead>(reader: R) -> Vec<Sample> {
+    try_read_fam_from_reader(reader, "<reader>")
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+// The path is only used in the errors.
+pub(crate) fn try_read_fam_from_reader<R: BufRead>(reader: R, path: &str)
+    -> Result<Vec<Sample>, GenepaError>
+{
     reader
         .lines()
-        .map(|l| {
-            let line = l.unwrap();
-            let vec = Vec::from_iter(line.split('\t'));
-
-            vec[0].to_string()  // Return the sample id
+        .enumerate()
+        .map(|(i, l)| {
+            let line = l.map_err(|e| GenepaError::io(path, e))?;
+            let vec = Vec::from_iter(line.split_whitespace());
+            Sample::from_fam_fields(&vec).map_err(|message| {
+                GenepaError::parse(path, i + 1, &message)
+            })
         })
         .collect()
 }
 
 
+// Any seekable source of bytes (e.g. a file or a decrypting reader).
+pub trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+
+// Allele of the BIM that is counted by the genotypes. Like plink, the
+// readers count A1 (5th column) by default. Counting A2 (6th column) matches
+// the tools that count the reference allele of files written with
+// `--keep-allele-order`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Orientation {
+    CountA1,
+    CountA2
+}
+
+
+// Predicate on the variants of a fileset (see `PlinkReader::filter_variants`).
+pub enum VariantPredicate {
+    Chromosomes(HashSet<Chromosome>),
+    Names(HashSet<String>),
+    // Minimal MAF, from the genotype counts of the BED (so the haploid
+    // policy isn't applied).
+    MinMaf(f64),
+    // Exclude the strand ambiguous (A/T and C/G) variants.
+    NotAmbiguous,
+    Custom(Box<dyn Fn(&Variant) -> bool>)
+}
+
+impl VariantPredicate {
+    // The MAF predicate is only checked from the counts.
+    fn matches_variant(&self, v: &Variant) -> bool {
+        match self {
+            VariantPredicate::Chromosomes(chroms) => {
+                chroms.contains(&v.chrom)
+            },
+            VariantPredicate::Names(names) => names.contains(&v.name),
+            VariantPredicate::MinMaf(_) => true,
+            VariantPredicate::NotAmbiguous => !v.alleles_ambiguous(),
+            VariantPredicate::Custom(predicate) => predicate(v)
+        }
+    }
+
+    fn matches_counts(&self, counts: &VariantCounts) -> bool {
+        match self {
+            VariantPredicate::MinMaf(min_maf) => {
+                counts.coded_frequency(2)
+                    .is_some_and(|p| p.minor().get() >= *min_maf)
+            },
+            _ => true
+        }
+    }
+}
+
+
+// Allele of the BIM counted in the BED (A1).
+fn bed_coded_allele(oav: &OrderedAllelesVariant) -> &str {
+    match oav.a1_idx {
+        0 => &oav.variant.alleles.0,
+        1 => &oav.variant.alleles.1,
+        _ => panic!("Problem with the ordered allele variant index")
+    }
+}
+
+
 pub struct PlinkReader {
+    prefix: String,
     bim_reader: DelimitedVariantsReader,
-    bim_index: BimIndex,
-    samples: Vec<String>,
-    bed_reader: BedReader<BufReader<File>>
+    bim_index: VariantIndex,
+    // Opened on the first query by name.
+    names: OnceLock<NameIndex>,
+    samples: Arc<Vec<Sample>>,
+    // Indices in the FAM of the samples that are read (None if all the
+    // samples are read).
+    kept: Option<Vec<usize>>,
+    attach_samples: bool,
+    haploid_hets: Option<HaploidHets>,
+    orientation: Orientation,
+    bed_reader: BedReader<BufReader<Box<dyn ReadSeek>>>,
+    n_read: u32,
+    exhausted: bool
 }
 
 impl PlinkReader {
-    pub fn new(prefix: &str) -> PlinkReader {
-        // Get or create the index for the bim.
+    // With the `http` feature, the prefix can also be an http(s) URL (or,
+    // with the `object-store` feature, a `s3://` or `gs://` URL). See
+    // `new_remote`.
+    pub fn new(prefix: &str) -> Result<PlinkReader, GenepaError> {
+        #[cfg(feature = "http")]
+        {
+            if is_url(prefix) {
+                return PlinkReader::new_remote(prefix);
+            }
+        }
+
+        PlinkReader::new_with_backend(prefix, IoBackend::default())
+    }
+
+    // Read the BED with another IO backend (e.g. direct IO, to scan large
+    // files without filling the page cache). See the iobackend module.
+    pub fn new_with_backend(prefix: &str, backend: IoBackend)
+        -> Result<PlinkReader, GenepaError>
+    {
+        let bed_filename = format!("{}.bed", &prefix);
+        let bed = iobackend::open(&bed_filename, backend)
+            .map_err(|e| GenepaError::io(&bed_filename, e))?;
+
+        PlinkReader::_open(prefix, bed)
+    }
+
+    // Read a remote fileset. The BIM and FAM are downloaded and the BED is
+    // read using range requests, so only the queried variants are
+    // downloaded.
+    #[cfg(feature = "http")]
+    pub fn new_remote(prefix: &str) -> Result<PlinkReader, GenepaError> {
+        let url = |ext: &str| format!("{}.{}", prefix, ext);
+        let fetch = |ext: &str| {
+            download(&url(ext)).map_err(|e| GenepaError::io(&url(ext), e))
+        };
+
+        let bed = HttpReader::open(&url("bed"))
+            .map_err(|e| GenepaError::io(&url("bed"), e))?;
+
+        PlinkReader::from_readers(bed, &fetch("bim")?[..], &fetch("fam")?[..])
+    }
+
+    // Read a fileset after checking it against its checksum manifest (see
+    // the checksum module). The BIM and FAM are verified when opened and the
+    // BED either when opened or as its blocks are read.
+    pub fn new_verified(prefix: &str, verification: Verification)
+        -> Result<PlinkReader, GenepaError>
+    {
+        let manifest_filename = format!("{}.sha256", prefix);
+        let manifest = Manifest::read(&manifest_filename)
+            .map_err(|e| GenepaError::io(&manifest_filename, e))?;
+
+        let verify = |ext: &str| {
+            let filename = format!("{}.{}", prefix, ext);
+            manifest.verify_file(&filename)
+                .map_err(|e| GenepaError::io(&filename, e))
+        };
+
+        verify("bim")?;
+        verify("fam")?;
+
+        let bed_filename = format!("{}.bed", &prefix);
+        let bed: Box<dyn ReadSeek> = match verification {
+            Verification::Eager => {
+                verify("bed")?;
+                Box::new(File::open(&bed_filename)
+                    .map_err(|e| GenepaError::io(&bed_filename, e))?)
+            },
+            Verification::Lazy => {
+                verify("bed.sha256blocks")?;
+                Box::new(VerifyingReader::open(&bed_filename)
+                    .map_err(|e| GenepaError::io(&bed_filename, e))?)
+            }
+        };
+
+        PlinkReader::_open(prefix, bed)
+    }
+
+    fn _open(prefix: &str, bed: Box<dyn ReadSeek>)
+        -> Result<PlinkReader, GenepaError>
+    {
         let bim_filename = format!("{}.bim", &prefix);
-        let bim_index = BimIndex::get_or_create_bim_index(&bim_filename);
-        let bim_reader = BimReader::new(&bim_filename);
+        let gz_bim_filename = format!("{}.bim.gz", &prefix);
+        let native_filename = format!("{}.bimidx2", &prefix);
+
+        // A gzipped BIM can't be indexed on disk, so it is indexed in memory
+        // (like for encrypted filesets).
+        let gzipped = !Path::new(&bim_filename).is_file() &&
+                      Path::new(&gz_bim_filename).is_file();
+
+        let (bim_index, bim_reader) = if gzipped {
+            let mut bim = Vec::new();
+            try_open_text_file(&gz_bim_filename)?.read_to_end(&mut bim)
+                .map_err(|e| GenepaError::io(&gz_bim_filename, e))?;
+
+            PlinkReader::_memory_index(bim, &gz_bim_filename)?
+        } else {
+            (PlinkReader::_disk_index(&bim_filename, &native_filename)?,
+             BimReader::new(&bim_filename)?)
+        };
+
+        let mut fam_filename = format!("{}.fam", &prefix);
+        if !Path::new(&fam_filename).is_file() {
+            fam_filename.push_str(".gz");
+        }
+        let samples = Arc::new(read_fam(&fam_filename)?);
+
+        let n_samples = samples.len() as u32;
+
+        let bed_filename = format!("{}.bed", &prefix);
+        let (bed, n_transposed) = PlinkReader::_transpose_if_sample_major(
+            bed, n_samples, bim_index.n_variants(), &bed_filename
+        )?;
+        let n_bed = match n_transposed {
+            Some(n) => n,
+            None => BedReader::count_variants_in_file(&bed_filename,
+                                                      n_samples)?
+        };
+
+        PlinkReader::from_parts(prefix, bim_reader, bim_index, samples, bed,
+                                n_bed)
+    }
+
+    // Read a fileset from any sources (e.g. in-memory buffers or custom
+    // storage layers). The BIM and FAM are read when the reader is created
+    // and the BIM is indexed in memory.
+    pub fn from_readers<B, M, F>(bed: B, mut bim: M, fam: F)
+        -> Result<PlinkReader, GenepaError>
+        where B: Read + Seek + 'static,
+              M: Read,
+              F: Read
+    {
+        let mut bim_bytes = Vec::new();
+        bim.read_to_end(&mut bim_bytes)
+            .map_err(|e| GenepaError::io("<bim>", e))?;
+        let (bim_index, bim_reader) =
+            PlinkReader::_memory_index(bim_bytes, "<bim>")?;
+
+        let samples = Arc::new(
+            try_read_fam_from_reader(BufReader::new(fam), "<fam>")?
+        );
+        let n_samples = samples.len() as u32;
+
+        let (mut bed, n_transposed) = PlinkReader::_transpose_if_sample_major(
+            Box::new(bed), n_samples, bim_index.n_variants(), "<bed>"
+        )?;
+        let n_bed = match n_transposed {
+            Some(n) => n,
+            None => {
+                let n_bytes = bed.seek(SeekFrom::End(0))
+                    .and_then(|n| bed.seek(SeekFrom::Start(0)).map(|_| n))
+                    .map_err(|e| GenepaError::io("<bed>", e))?;
+
+                BedReader::count_variants(n_bytes, n_samples)
+                    .ok_or_else(|| GenepaError::invalid_bed(
+                        "<bed>",
+                        &format!("the size is not consistent with {} \
+                                  samples", n_samples)
+                    ))?
+            }
+        };
+
+        PlinkReader::from_parts("<reader>", bim_reader, bim_index, samples,
+                                bed, n_bed)
+    }
+
+    // Index the variants of a BIM in memory. The path is only used in the
+    // errors.
+    fn _memory_index(bim: Vec<u8>, path: &str)
+        -> Result<(VariantIndex, DelimitedVariantsReader), GenepaError>
+    {
+        let variants = String::from_utf8_lossy(&bim)
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                parse_bim_line(line).map_err(|message| {
+                    GenepaError::parse(path, i + 1, &message)
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let bim_reader = BimReader::from_reader(std::io::Cursor::new(bim));
+
+        Ok((VariantIndex::Memory(variants), bim_reader))
+    }
+
+    // Sample major BEDs are transposed in memory. The number of variants of
+    // the transposed BED is returned (None if the BED is variant major).
+    fn _transpose_if_sample_major(mut bed: Box<dyn ReadSeek>, n_samples: u32,
+                                  n_variants: u32, bed_filename: &str)
+        -> Result<(Box<dyn ReadSeek>, Option<u32>), GenepaError>
+    {
+        let mut magic = [0; 3];
+        bed.read_exact(&mut magic)
+            .and_then(|_| bed.seek(SeekFrom::Start(0)))
+            .map_err(|e| GenepaError::io(bed_filename, e))?;
+
+        if bed_mode(&magic) != Some(BedMode::SampleMajor) {
+            return Ok((bed, None));
+        }
+
+        let transposed = transpose_sample_major_bed(
+            BufReader::new(bed), n_samples, n_variants
+        ).map_err(|e| GenepaError::invalid_bed(bed_filename, &e.to_string()))?;
+
+        let n = BedReader::count_variants(transposed.len() as u64, n_samples);
+        Ok((Box::new(std::io::Cursor::new(transposed)), Some(n.unwrap_or(0))))
+    }
+
+    // Get or create the index for the bim. The native index is used if it
+    // was built (e.g. using `genepa index --format v2`).
+    fn _disk_index(bim_filename: &str, native_filename: &str)
+        -> Result<VariantIndex, GenepaError>
+    {
+        if Path::new(native_filename).is_file() {
+            NativeBimIndex::get_or_create(native_filename, bim_filename)
+                .map(VariantIndex::Native)
+                .map_err(|e| {
+                    GenepaError::index(native_filename, &e.to_string())
+                })
+        } else {
+            BimIndex::get_or_create_bim_index(bim_filename)
+                .map(VariantIndex::Tabix)
+        }
+    }
+
+    // Read a fileset encrypted using `crypto::encrypt_plink_fileset`. The
+    // files are decrypted on the fly and nothing is written to disk, so the
+    // BIM is indexed in memory.
+    #[cfg(feature = "encryption")]
+    pub fn new_encrypted(prefix: &str, key: &EncryptionKey)
+        -> Result<PlinkReader, GenepaError>
+    {
+        let filename = |ext: &str| format!("{}.{}.enc", prefix, ext);
+        let open = |ext: &str| {
+            DecryptingReader::open(&filename(ext), key)
+                .map_err(|e| GenepaError::io(&filename(ext), e))
+        };
+
+        let read_all = |ext: &str| {
+            let mut buf = Vec::new();
+            open(ext)?.read_to_end(&mut buf)
+                .map_err(|e| GenepaError::io(&filename(ext), e))?;
+            Ok::<_, GenepaError>(buf)
+        };
+
+        let (bim_index, bim_reader) =
+            PlinkReader::_memory_index(read_all("bim")?, &filename("bim"))?;
+
+        let samples = Arc::new(
+            try_read_fam_from_reader(&read_all("fam")?[..], &filename("fam"))?
+        );
+        let n_samples = samples.len() as u32;
+
+        let bed = open("bed")?;
+        let n_bed = BedReader::count_variants(bed.plaintext_len(), n_samples)
+            .ok_or_else(|| GenepaError::invalid_bed(
+                &filename("bed"),
+                &format!("the size is not consistent with {} samples",
+                         n_samples)
+            ))?;
+
+        PlinkReader::from_parts(prefix, bim_reader, bim_index, samples,
+                                Box::new(bed), n_bed)
+    }
+
+    fn from_parts(prefix: &str, bim_reader: DelimitedVariantsReader,
+                  bim_index: VariantIndex, samples: Arc<Vec<Sample>>,
+                  bed: Box<dyn ReadSeek>, n_bed: u32)
+        -> Result<PlinkReader, GenepaError>
+    {
+        let n_samples = samples.len() as u32;
+        let n_variants = bim_index.n_variants();
+
+        // Make sure all the components of the fileset describe the same
+        // variants. Otherwise, genotypes would be silently shifted.
+        let n_indexed = bim_index.count_indexed_variants()?;
+
+        if n_indexed != n_variants || n_bed != n_variants {
+            return Err(GenepaError::InconsistentFileset {
+                prefix: prefix.to_string(),
+                n_bim: n_variants,
+                n_indexed,
+                n_bed,
+                n_samples
+            });
+        }
+
+        let bed_reader = BedReader::_from_reader(
+            BufReader::new(bed), n_samples, n_variants,
+            &format!("{}.bed", prefix)
+        )?;
+
+        Ok(PlinkReader {
+            prefix: prefix.to_string(),
+            bim_reader, bim_index, samples, bed_reader,
+            names: OnceLock::new(),
+            kept: None,
+            attach_samples: false,
+            haploid_hets: Some(HaploidHets::SetMissing),
+            orientation: Orientation::CountA1,
+            n_read: 0,
+            exhausted: false
+        })
+    }
+
+    // If set, every Genotypes produced by the reader will hold a reference
+    // to the samples from the FAM (the other readers have the same option).
+    pub fn attach_samples(&mut self, attach: bool) {
+        self.attach_samples = attach;
+    }
+
+    // How heterozygous calls are handled when converting the variants on
+    // haploid chromosomes (Y and MT) to haploid genotypes. By default, they
+    // are set to missing. If None, the variants are left diploid coded.
+    pub fn haploid_policy(&mut self, hets: Option<HaploidHets>) {
+        self.haploid_hets = hets;
+    }
+
+    // Which allele of the BIM is the coded allele (A1 by default).
+    pub fn orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+    }
+
+    // Only read the samples with the given IIDs. The genotypes and the
+    // samples of the reader are then those of the kept samples, in the order
+    // of the FAM. The lists are applied to the samples that are currently
+    // kept, so `keep_samples` and `remove_samples` can be combined. Returns
+    // the number of kept samples.
+    pub fn keep_samples(&mut self, iids: &[String]) -> usize {
+        let iids: HashSet<&str> = iids.iter().map(|iid| iid.as_str())
+            .collect();
+        self._subset_samples(|sample| iids.contains(sample.iid.as_str()))
+    }
+
+    // Don't read the samples with the given IIDs (see `keep_samples`).
+    pub fn remove_samples(&mut self, iids: &[String]) -> usize {
+        let iids: HashSet<&str> = iids.iter().map(|iid| iid.as_str())
+            .collect();
+        self._subset_samples(|sample| !iids.contains(sample.iid.as_str()))
+    }
+
+    fn _subset_samples<F>(&mut self, keep: F) -> usize
+        where F: Fn(&Sample) -> bool
+    {
+        let n_fam = self.bed_reader.n_samples as usize;
+        let current = self.kept.take().unwrap_or_else(|| (0..n_fam).collect());
+
+        let (kept, samples): (Vec<usize>, Vec<Sample>) = current.into_iter()
+            .zip(self.samples.iter())
+            .filter(|(_, sample)| keep(sample))
+            .map(|(i, sample)| (i, sample.clone()))
+            .unzip();
+
+        self.samples = Arc::new(samples);
+        if kept.len() < n_fam {
+            self.kept = Some(kept);
+        }
+
+        self.samples.len()
+    }
+
+    // Read the genotypes of the kept samples for the next variant of the
+    // BED.
+    fn _read_genotypes(&mut self) -> Result<Vec<Option<u8>>, GenepaError> {
+        let mut chunk = vec![0; self.bed_reader._chunk_size];
+        self._read_chunk(&mut chunk)?;
+        Ok(self._decode_chunk(&chunk))
+    }
+
+    fn _bed_error(&self, message: &str) -> GenepaError {
+        GenepaError::invalid_bed(&format!("{}.bed", self.prefix), message)
+    }
+
+    fn _read_chunk(&mut self, chunk: &mut [u8]) -> Result<(), GenepaError> {
+        self.bed_reader.reader.read_exact(chunk)
+            .map_err(|e| self._bed_error(&format!(
+                "could not read variant {} ({}, the BED may be truncated)",
+                self.n_read + 1, e
+            )))
+    }
+
+    // Skip the next variant of the BED.
+    fn _skip_chunk(&mut self) -> Result<(), GenepaError> {
+        self.bed_reader.reader
+            .seek_relative(self.bed_reader._chunk_size as i64)
+            .map_err(|e| self._bed_error(&format!(
+                "could not seek past variant {} ({})", self.n_read, e
+            )))
+    }
+
+    fn _decode_chunk(&self, chunk: &[u8]) -> Vec<Option<u8>> {
+        match &self.kept {
+            Some(kept) => decode_samples_chunk(chunk, kept),
+            None => decode_variant_chunk(chunk,
+                                         self.bed_reader.n_samples as usize)
+        }
+    }
+
+    // Genotype counts of the kept samples, with respect to the coded allele.
+    fn _count_chunk(&self, chunk: &[u8]) -> VariantCounts {
+        let n_fam = self.bed_reader.n_samples as usize;
+        let mut counts = match &self.kept {
+            Some(kept) => count_variant_chunk(chunk, kept.iter().copied()),
+            None => count_variant_chunk(chunk, 0..n_fam)
+        };
+
+        if self.orientation == Orientation::CountA2 {
+            counts.n_geno.swap(0, 2);
+        }
+
+        counts
+    }
+
+    fn _make_genotypes(&self, v: Variant, geno_vec: Vec<Option<u8>>,
+                       coded: &str) -> Genotypes
+    {
+        self._try_make_genotypes(v, geno_vec, coded)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // The only error is a heterozygous call on a haploid chromosome (with
+    // the `HaploidHets::Error` policy).
+    fn _try_make_genotypes(&self, v: Variant, geno_vec: Vec<Option<u8>>,
+                           coded: &str) -> Result<Genotypes, GenepaError>
+    {
+        let mut g = Genotypes::new(v, geno_vec, coded);
+
+        if self.orientation == Orientation::CountA2 {
+            g = g.flip_coded_allele();
+        }
+
+        if let Some(hets) = self.haploid_hets {
+            if g.variant.chrom.is_haploid() {
+                g = g.into_haploid(hets)
+                    .map_err(|e| self._bed_error(&e.to_string()))?;
+            }
+        }
+
+        if self.attach_samples {
+            Ok(g.with_samples(Arc::clone(&self.samples)))
+        } else {
+            Ok(g)
+        }
+    }
+
+    // Called once the BIM is exhausted to make sure that the BED was read
+    // to the end.
+    fn _check_termination(&mut self) -> Result<(), GenepaError> {
+        if self.n_read != self.bed_reader.n_variants {
+            return Err(self._bed_error(&format!(
+                "read {} variants from the BIM but expected {}",
+                self.n_read, self.bed_reader.n_variants
+            )));
+        }
+
+        let mut trailing = [0; 1];
+        let n = self.bed_reader.reader.read(&mut trailing)
+            .map_err(|e| self._bed_error(&e.to_string()))?;
+
+        if n != 0 {
+            return Err(self._bed_error(&format!(
+                "the BED contains more data than expected after reading {} \
+                 variants", self.n_read
+            )));
+        }
+
+        Ok(())
+    }
+
+    // Iterate over the remaining variants passing all the predicates. The
+    // predicates on the variants are checked on the BIM, and the BED is
+    // seeked past the variants failing them without reading their
+    // genotypes. For the MAF, the genotypes are counted from the BED and
+    // only decoded if the variant passes.
+    pub fn filter_variants(self, predicates: Vec<VariantPredicate>)
+        -> FilteredVariants
+    {
+        let count = predicates.iter()
+            .any(|p| matches!(p, VariantPredicate::MinMaf(_)));
+        let chunk = vec![0; self.bed_reader._chunk_size];
+
+        FilteredVariants {
+            reader: self, predicates, count, chunk, n_skipped: 0
+        }
+    }
+
+    // Count the remaining variants for which the predicate on the genotype
+    // counts is true. The genotypes are counted directly from the BED, so
+    // no genotype vector is allocated. As the counts are taken from the
+    // BED, the haploid policy isn't applied.
+    //
+    // e.g. to count the variants with a MAF of at least 1%:
+    //     reader.count_if(|_, c| {
+    //         let freq = c.coded_freq(2);
+    //         freq.min(1.0 - freq) >= 0.01
+    //     })
+    //
+    // Panics on the errors returned by `try_count_if`.
+    pub fn count_if<F>(&mut self, predicate: F) -> u32
+        where F: FnMut(&Variant, &VariantCounts) -> bool
+    {
+        self.try_count_if(predicate).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // See `count_if`, returning the error of the first invalid variant (see
+    // `try_next`).
+    pub fn try_count_if<F>(&mut self, mut predicate: F)
+        -> Result<u32, GenepaError>
+        where F: FnMut(&Variant, &VariantCounts) -> bool
+    {
+        if self.exhausted {
+            return Ok(0);
+        }
+
+        // The reader can't be used after an error.
+        self.exhausted = true;
+
+        let mut chunk = vec![0; self.bed_reader._chunk_size];
+        let mut n = 0;
+
+        while let Some(oav) = self.bim_reader.next() {
+            let oav = oav?;
+            self._read_chunk(&mut chunk)?;
+            self.n_read += 1;
+
+            if predicate(&oav.variant, &self._count_chunk(&chunk)) {
+                n += 1;
+            }
+        }
+
+        self._check_termination()?;
+
+        Ok(n)
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn n_variants(&self) -> u32 {
+        self.bim_index.n_variants()
+    }
+
+    fn _seek_to_idx(&mut self, idx: u32) -> Result<(), GenepaError> {
+        let actual_seek = 3 + self.bed_reader._chunk_size * idx as usize;
+        self.bed_reader.reader.seek(SeekFrom::Start(actual_seek as u64))
+            .map(|_| ())
+            .map_err(|e| self._bed_error(&format!(
+                "could not seek to variant {} ({})", idx + 1, e
+            )))
+    }
+
+    // Genotypes of the variant at the index of the BED.
+    fn _read_at(&mut self, idx: u32, v: Variant, coded: &str)
+        -> Result<Genotypes, GenepaError>
+    {
+        self._seek_to_idx(idx)?;
+        let geno_vec = self._read_genotypes()?;
+        self._try_make_genotypes(v, geno_vec, coded)
+    }
+
+    // The queries panic on the errors returned by their `try_` version
+    // (e.g. a failing tabix, a truncated BED or a duplicated variant).
+    pub fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        self.try_get_variant_genotypes(v)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_get_variant_genotypes(&mut self, v: &Variant)
+        -> Result<Option<Genotypes>, GenepaError>
+    {
+        let mut matches = self.bim_index.get_variant_index_and_coded(v)?;
+
+        match matches.len() {
+            0 => Ok(None),
+            1 => {
+                let (idx, coded) = matches.remove(0);
+                self._read_at(idx, v.clone(), &coded).map(Some)
+            },
+            _ => Err(GenepaError::DuplicateVariant {
+                path: format!("{}.bim", self.prefix),
+                variant: v.to_string()
+            })
+        }
+    }
+
+    // Genotypes of the variant with the name (e.g. a rsID), without its
+    // locus. None if there is no such variant. Like for the loci, the name
+    // must not be duplicated in the BIM.
+    pub fn get_variant_by_name(&mut self, name: &str) -> Option<Genotypes> {
+        self.try_get_variant_by_name(name)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_get_variant_by_name(&mut self, name: &str)
+        -> Result<Option<Genotypes>, GenepaError>
+    {
+        let mut matches = self.bim_index.get_name_index_and_coded(
+            name, &self.names, &self.prefix
+        )?;
+
+        match matches.len() {
+            0 => Ok(None),
+            1 => {
+                let (idx, v, coded) = matches.remove(0);
+                self._read_at(idx, v, &coded).map(Some)
+            },
+            _ => Err(GenepaError::DuplicateVariant {
+                path: format!("{}.bim", self.prefix),
+                variant: name.to_string()
+            })
+        }
+    }
+
+    pub fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                                  end: u32)
+        -> Vec<Genotypes>
+    {
+        self.try_get_variants_in_region(chrom, start, end)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_get_variants_in_region(&mut self, chrom: &Chromosome,
+                                      start: u32, end: u32)
+        -> Result<Vec<Genotypes>, GenepaError>
+    {
+        // Do a region query on the BIM index, then read the genotypes of
+        // every index, variant and coded allele.
+        self.bim_index.get_region_index_and_coded(chrom, start, end)?
+            .into_iter()
+            .map(|(idx, v, coded)| self._read_at(idx, v, &coded))
+            .collect()
+    }
+
+    // Number of variants in a region, found from the index without reading
+    // the genotypes.
+    pub(crate) fn n_variants_in_region(&self, chrom: &Chromosome, start: u32,
+                                       end: u32)
+        -> Result<usize, GenepaError>
+    {
+        Ok(self.bim_index.get_region_index_and_coded(chrom, start, end)?
+            .len())
+    }
+
+    // Only the genotypes of the variants of the page are read.
+    pub fn get_variants_in_region_page(&mut self, chrom: &Chromosome,
+                                       start: u32, end: u32, offset: usize,
+                                       limit: usize) -> RegionPage
+    {
+        self.try_get_variants_in_region_page(chrom, start, end, offset, limit)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_get_variants_in_region_page(&mut self, chrom: &Chromosome,
+                                           start: u32, end: u32,
+                                           offset: usize, limit: usize)
+        -> Result<RegionPage, GenepaError>
+    {
+        let indexed = self.bim_index.get_region_index_and_coded(
+            chrom, start, end
+        )?;
+
+        // The page stops at the first error.
+        let mut error = None;
+        let genotypes = indexed.into_iter()
+            .skip(offset)
+            .map_while(|(idx, v, coded)| {
+                self._read_at(idx, v, &coded)
+                    .map_err(|e| error = Some(e))
+                    .ok()
+            });
+        let page = RegionPage::collect(genotypes, offset, limit);
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(page)
+        }
+    }
+}
+
+
+impl PlinkReader {
+    // Next genotypes, or the error of the first invalid variant (a
+    // malformed BIM line, a truncated BED, ...). The iteration stops after
+    // an error.
+    pub fn try_next(&mut self) -> Option<Result<Genotypes, GenepaError>> {
+        if self.exhausted {
+            return None;
+        }
+
+        let res = self._try_next();
+        if let Some(Err(_)) = res {
+            self.exhausted = true;
+        }
+
+        res
+    }
+
+    fn _try_next(&mut self) -> Option<Result<Genotypes, GenepaError>> {
+        match self.bim_reader.next() {
+            // oav is ordered alleles variant.
+            Some(oav) => Some(oav.and_then(|oav| {
+                let geno_vec = self._read_genotypes()?;
+                self.n_read += 1;
+
+                self._try_make_genotypes(
+                    oav.variant.to_owned(),
+                    geno_vec,
+                    bed_coded_allele(&oav)
+                )
+            })),
+            None => {
+                self.exhausted = true;
+                self._check_termination().err().map(Err)
+            }
+        }
+    }
+}
+
+impl Iterator for PlinkReader {
+    type Item = Genotypes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().map(|g| g.unwrap_or_else(|e| panic!("{}", e)))
+    }
+}
 
-        let fam_filename = format!("{}.fam", &prefix);
-        let samples = read_fam(&fam_filename);
+impl FusedIterator for PlinkReader {}
 
-        let n_samples = samples.len() as u32;
 
-        let bed_filename = format!("{}.bed", &prefix);
-        let bed_reader = BedReader::new(
-            &bed_filename, n_samples, bim_index.n_variants
-        );
+// Variants of a PlinkReader passing predicates (see
+// `PlinkReader::filter_variants`).
+pub struct FilteredVariants {
+    reader: PlinkReader,
+    predicates: Vec<VariantPredicate>,
+    // Whether the genotypes are counted (for the MAF) before decoding.
+    count: bool,
+    chunk: Vec<u8>,
+    n_skipped: u32
+}
 
-        PlinkReader {bim_reader, bim_index, samples, bed_reader}
+impl FilteredVariants {
+    pub fn samples(&self) -> &[Sample] {
+        self.reader.samples()
     }
 
-    fn _seek_to_idx(&mut self, idx: u32) {
-        let actual_seek = 3 + self.bed_reader._chunk_size * idx as usize;
-        self.bed_reader.reader.seek(SeekFrom::Start(actual_seek as u64))
-            .expect("Could not seek in BED");
+    // Number of variants that failed the predicates so far.
+    pub fn n_skipped(&self) -> u32 {
+        self.n_skipped
     }
 
-    fn _seek_and_read_to_idx(&mut self, idx: u32) -> Vec<Option<u8>> {
-        self._seek_to_idx(idx);
-        self.bed_reader._read_variant_chunk()
-    }
+    // Next genotypes passing the predicates, or the error of the first
+    // invalid variant (see `PlinkReader::try_next`).
+    pub fn try_next(&mut self) -> Option<Result<Genotypes, GenepaError>> {
+        if self.reader.exhausted {
+            return None;
+        }
 
-    pub fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
-        // Find in the index.
-        let res = self.bim_index.get_variant_index_and_coded(&v);
-        if let Some((idx, coded)) = res {
-            // Read variant
-            let geno_vec = self._seek_and_read_to_idx(idx);
-            return Some(Genotypes::new(v.clone(), geno_vec, &coded));
+        let res = self._try_next();
+        if let Some(Err(_)) = res {
+            self.reader.exhausted = true;
         }
-        None
+
+        res
     }
 
-    pub fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
-                                  end: u32)
-        -> Vec<Genotypes>
-    {
-        // Do a region query on the BIM index.
-        self.bim_index.get_region_index_and_coded(&chrom.name, start, end)
-            .into_iter()
-            .map(|(idx, v, coded)| {
-                // For every index, variant and coded, read the genotypes.
-                let geno_vec = self._seek_and_read_to_idx(idx);
+    fn _try_next(&mut self) -> Option<Result<Genotypes, GenepaError>> {
+        let reader = &mut self.reader;
 
-                Genotypes::new(v.clone(), geno_vec, &coded)
-            })
-            .collect()
+        while let Some(oav) = reader.bim_reader.next() {
+            let oav = match oav {
+                Ok(oav) => oav,
+                Err(e) => return Some(Err(e))
+            };
+            reader.n_read += 1;
+
+            let predicates = &self.predicates;
+            if !predicates.iter().all(|p| p.matches_variant(&oav.variant)) {
+                if let Err(e) = reader._skip_chunk() {
+                    return Some(Err(e));
+                }
+                self.n_skipped += 1;
+                continue;
+            }
+
+            if let Err(e) = reader._read_chunk(&mut self.chunk) {
+                return Some(Err(e));
+            }
+
+            if self.count {
+                let counts = reader._count_chunk(&self.chunk);
+                if !predicates.iter().all(|p| p.matches_counts(&counts)) {
+                    self.n_skipped += 1;
+                    continue;
+                }
+            }
+
+            let geno_vec = reader._decode_chunk(&self.chunk);
+            return Some(reader._try_make_genotypes(
+                oav.variant.clone(), geno_vec, bed_coded_allele(&oav)
+            ));
+        }
+
+        reader.exhausted = true;
+        reader._check_termination().err().map(Err)
     }
 }
 
-
-impl Iterator for PlinkReader {
+impl Iterator for FilteredVariants {
     type Item = Genotypes;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // Check if we're at the end of the file.
-        // let file = self.bed_reader.reader.get_mut();
-        // let tell = file.seek(SeekFrom::Current(0)).unwrap();
-        // let chunk_size = self.bed_reader._chunk_size as u64;
-        
-        match self.bim_reader.next() {
-            // oav is ordered alleles variant.
-            Some(ref oav) => {
-                let geno_vec = self.bed_reader._read_variant_chunk();
-
-                let coded_allele =  if oav.a1_idx == 0 {
-                    &oav.variant.alleles.0
-                } else if oav.a1_idx == 1 {
-                    &oav.variant.alleles.1
-                } else {
-                    panic!("Problem with the ordered allele variant index");
-                };
-
-                Some(Genotypes::new(
-                    oav.variant.to_owned(),
-                    geno_vec,
-                    &coded_allele)
-                )
-            }
-            None => None
-        }
+        self.try_next().map(|g| g.unwrap_or_else(|e| panic!("{}", e)))
     }
 }
 
+impl FusedIterator for FilteredVariants {}
+
 
 pub struct BimReader;
 impl BimReader {
-    pub fn new(filename: &str) -> DelimitedVariantsReader {
-        let idx = VarFieldIdx {
+    // BimReader only sets the columns of a DelimitedVariantsReader.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(filename: &str)
+        -> Result<DelimitedVariantsReader, GenepaError>
+    {
+        DelimitedVariantsReader::new(filename, '\t', false, BimReader::idx())
+    }
+
+    pub fn from_reader<R: BufRead + 'static>(reader: R)
+        -> DelimitedVariantsReader
+    {
+        DelimitedVariantsReader::from_reader(reader, '\t', false,
+                                             BimReader::idx())
+    }
+
+    fn idx() -> VarFieldIdx {
+        VarFieldIdx {
             delimiter: '\t',
             name: 1,
             chrom: 0,
             pos: 3,
             a1: 4,
             a2: 5
-        };
+        }
+    }
+}
+
+
+// Genotypes corresponding to the 2 bit codes in the BED (with respect to the
+// first allele in the BIM).
+pub(crate) const BED_CODES: [Option<u8>; 4] = [Some(2), None, Some(1), Some(0)];
+
+
+// Decode the genotypes of a single variant.
+fn decode_variant_chunk(chunk: &[u8], n_samples: usize) -> Vec<Option<u8>> {
+    decode_2bit_chunk(chunk, n_samples, &BED_CODES)
+}
+
+
+// Decode the genotypes of the given samples (indices in the FAM) only. Only
+// the bytes with the genotypes of these samples are read.
+fn decode_samples_chunk(chunk: &[u8], samples: &[usize]) -> Vec<Option<u8>> {
+    samples.iter()
+        .map(|&i| {
+            let code = (chunk[i / 4] >> (2 * (i % 4))) & 0b11;
+            BED_CODES[usize::from(code)]
+        })
+        .collect()
+}
+
+
+// Count the genotypes of the given samples of a single variant without
+// decoding them.
+fn count_variant_chunk<I>(chunk: &[u8], samples: I) -> VariantCounts
+    where I: IntoIterator<Item = usize>
+{
+    let mut counts = VariantCounts::default();
+
+    for i in samples {
+        let code = (chunk[i / 4] >> (2 * (i % 4))) & 0b11;
+        match BED_CODES[usize::from(code)] {
+            Some(g) => counts.n_geno[usize::from(g)] += 1,
+            None => counts.n_missing += 1
+        }
+    }
+
+    counts
+}
+
 
-        DelimitedVariantsReader::new(filename, '\t', false, idx)
+// Decode 2 bit packed genotypes given the genotype of every code.
+pub(crate) fn decode_2bit_chunk(chunk: &[u8], n_samples: usize,
+                                codes: &[Option<u8>; 4]) -> Vec<Option<u8>>
+{
+    let mut genotypes = Vec::with_capacity(4 * chunk.len());
+
+    // Every byte has the information on up to 4 samples (DD CC BB AA).
+    for b in chunk {
+        genotypes.push(codes[(b & 0b11) as usize]);
+        genotypes.push(codes[((b >> 2) & 0b11) as usize]);
+        genotypes.push(codes[((b >> 4) & 0b11) as usize]);
+        genotypes.push(codes[(b >> 6) as usize]);
     }
+
+    // The padding of the last byte is not relevant.
+    genotypes.truncate(n_samples);
+
+    genotypes
 }
 
 
-struct BedReader<T: BufRead> {
+pub struct BedReader<T: BufRead> {
     reader: T,
+    // Only used in the errors.
+    path: String,
     n_samples: u32,
     n_variants: u32,
     _chunk_size: usize
@@ -294,34 +1359,142 @@ struct BedReader<T: BufRead> {
 
 impl BedReader<BufReader<File>> {
     pub fn new(filename: &str, n_samples: u32, n_variants: u32)
-        -> BedReader<BufReader<File>> 
+        -> Result<BedReader<BufReader<File>>, GenepaError>
     {
-        let f = File::open(filename).unwrap();
-        BedReader::new_from_reader(BufReader::new(f), n_samples, n_variants)
+        let f = File::open(filename)
+            .map_err(|e| GenepaError::io(filename, e))?;
+        BedReader::_from_reader(BufReader::new(f), n_samples, n_variants,
+                                filename)
     }
 
-    fn get_chunk_size(n_samples: u32) -> usize {
+    pub(crate) fn get_chunk_size(n_samples: u32) -> usize {
         (f64::from(n_samples) / 4.0).ceil() as usize
     }
+
+    // Number of variants in a BED file as derived from its size.
+    pub fn count_variants_in_file(filename: &str, n_samples: u32)
+        -> Result<u32, GenepaError>
+    {
+        let n_bytes = std::fs::metadata(filename)
+            .map_err(|e| GenepaError::io(filename, e))?
+            .len();
+
+        BedReader::count_variants(n_bytes, n_samples)
+            .ok_or_else(|| GenepaError::invalid_bed(
+                filename,
+                &format!("the size is not consistent with {} samples",
+                         n_samples)
+            ))
+    }
+
+    pub(crate) fn count_variants(n_bytes: u64, n_samples: u32)
+        -> Option<u32>
+    {
+        let chunk_size = BedReader::get_chunk_size(n_samples) as u64;
+
+        if n_bytes < 3 || chunk_size == 0 {
+            return None;
+        }
+
+        let data_bytes = n_bytes - 3;
+        if !data_bytes.is_multiple_of(chunk_size) {
+            return None;
+        }
+
+        Some((data_bytes / chunk_size) as u32)
+    }
+}
+
+impl<T: BufRead + Seek> BedReader<T> {
+    // Read `n` consecutive variants starting at `start_idx` with a single
+    // read. With the `parallel` feature, the variants are decoded in
+    // parallel (with the threads and NUMA mode of the config module).
+    //
+    // Panics on the errors returned by `try_read_variants`.
+    pub fn read_variants(&mut self, start_idx: u32, n: u32)
+        -> Vec<Vec<Option<u8>>>
+    {
+        self.try_read_variants(start_idx, n)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // See `read_variants`. The variants must be in the BED and the BED must
+    // not be truncated.
+    pub fn try_read_variants(&mut self, start_idx: u32, n: u32)
+        -> Result<Vec<Vec<Option<u8>>>, GenepaError>
+    {
+        let end_idx = u64::from(start_idx) + u64::from(n);
+        if end_idx > u64::from(self.n_variants) {
+            return Err(GenepaError::invalid_bed(&self.path, &format!(
+                "can't read variants {} to {} from a BED with {} variants",
+                start_idx, end_idx, self.n_variants
+            )));
+        }
+
+        let offset = 3 + self._chunk_size as u64 * u64::from(start_idx);
+        let mut buf = vec![0; self._chunk_size * n as usize];
+        self.reader.seek(SeekFrom::Start(offset))
+            .and_then(|_| self.reader.read_exact(&mut buf))
+            .map_err(|e| GenepaError::invalid_bed(&self.path, &format!(
+                "could not read variants {} to {} ({}, the BED may be \
+                 truncated)", start_idx, end_idx, e
+            )))?;
+
+        let n_samples = self.n_samples as usize;
+
+        #[cfg(feature = "parallel")]
+        {
+            let chunks: Vec<&[u8]> = buf.chunks(self._chunk_size).collect();
+            Ok(crate::config::par_map(&chunks, |chunk| {
+                decode_variant_chunk(chunk, n_samples)
+            }))
+        }
+
+        #[cfg(not(feature = "parallel"))]
+        {
+            Ok(buf.chunks(self._chunk_size)
+                .map(|chunk| decode_variant_chunk(chunk, n_samples))
+                .collect())
+        }
+    }
 }
 
 impl<T: BufRead> BedReader<T> {
     pub fn new_from_reader(reader: T, n_samples: u32, n_variants: u32)
-        -> BedReader<T>
+        -> Result<BedReader<T>, GenepaError>
+    {
+        BedReader::_from_reader(reader, n_samples, n_variants, "<reader>")
+    }
+
+    fn _from_reader(reader: T, n_samples: u32, n_variants: u32, path: &str)
+        -> Result<BedReader<T>, GenepaError>
     {
         let mut bed_reader = BedReader {
             reader,
+            path: path.to_string(),
             n_samples,
             n_variants,
             _chunk_size: BedReader::get_chunk_size(n_samples)
         };
 
-        if !&bed_reader._verify_magic_number() {
-            panic!("The provided file is not in the BED format (according to \
-                   the magic number)");
-        }
+        let mode = bed_reader._read_mode()
+            .map_err(|e| GenepaError::io(path, e))?;
 
-        bed_reader
+        match mode {
+            Some(BedMode::VariantMajor) => Ok(bed_reader),
+            Some(BedMode::SampleMajor) => {
+                Err(GenepaError::invalid_bed(
+                    path, "the BED is sample major (it can be converted \
+                           using `convert_sample_major_bed`)"
+                ))
+            },
+            None => {
+                Err(GenepaError::invalid_bed(
+                    path, "not in the BED format (according to the magic \
+                           number)"
+                ))
+            }
+        }
     }
 
     fn _read_variant_chunk(&mut self) -> Vec<Option<u8>> {
@@ -329,58 +1502,212 @@ impl<T: BufRead> BedReader<T> {
 
         let mut buf_vec: Vec<u8> = vec![0; self._chunk_size];
         self.reader.read_exact(&mut buf_vec)
-            .expect("Could not read bytes.");
-
-        let mask: u8 = 0b11;
-        let mut genotypes: Vec<Option<u8>> = buf_vec
-          .iter()
-          .map(|b| {
-            // Every byte has the information on up to 4 samples.
-            // DD CC BB AA
-            // We use bitshifts to bring the current sample to the lowest bits
-            // and the mask to extract them.
-            let cur_geno: Vec<Option<u8>> = (0..=6).step_by(2).map(|shft| {
-                let coded_geno = (b >> shft as u8) & mask;
-                match coded_geno {
-                    0 => Some(2), // Homo A1
-                    1 => None,    // NA
-                    2 => Some(1), // Hetero
-                    3 => Some(0),  // Homo A2
-                    _ => panic!("Unexpected value in bed file.")
-                }
-            }).collect();
-            cur_geno
-          })
-          .flatten()
-          .collect();
+            .expect("Could not read bytes (the BED may be truncated).");
+
+        decode_variant_chunk(&buf_vec, n_samples)
+    }
+
+    fn _read_mode(&mut self) -> io::Result<Option<BedMode>> {
+        let mut first_3_bytes = [0; 3];
+        self.reader.read_exact(&mut first_3_bytes)?;
+
+        Ok(bed_mode(&first_3_bytes))
+    }
+}
+
 
-        // It is possible that the last data is not relevant.
-        if genotypes.len() > n_samples {
-            genotypes.truncate(n_samples)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BedMode {
+    VariantMajor,
+    // Written by plink 1.0 (`--make-bed` with the individual-major option).
+    SampleMajor
+}
+
+
+// Mode of a BED given its first 3 bytes (None if it is not a BED).
+pub fn bed_mode(magic: &[u8; 3]) -> Option<BedMode> {
+    match magic {
+        [0x6c, 0x1b, 0x01] => Some(BedMode::VariantMajor),
+        [0x6c, 0x1b, 0x00] => Some(BedMode::SampleMajor),
+        _ => None
+    }
+}
+
+
+// Transpose a sample major BED (including its magic number) into a variant
+// major BED. The sample major BED is streamed, but the transposed BED is
+// held in memory.
+pub fn transpose_sample_major_bed<R: Read>(mut reader: R, n_samples: u32,
+                                           n_variants: u32)
+    -> io::Result<Vec<u8>>
+{
+    let mut magic = [0; 3];
+    reader.read_exact(&mut magic)?;
+    if bed_mode(&magic) != Some(BedMode::SampleMajor) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  "The BED is not sample major."));
+    }
+
+    let (n_samples, n_variants) = (n_samples as usize, n_variants as usize);
+    let row_size = BedReader::get_chunk_size(n_variants as u32);
+    let chunk_size = BedReader::get_chunk_size(n_samples as u32);
+
+    let mut bed = vec![0; 3 + chunk_size * n_variants];
+    bed[..3].copy_from_slice(&[0x6c, 0x1b, 0x01]);
+
+    let mut row = vec![0; row_size];
+    for i in 0..n_samples {
+        reader.read_exact(&mut row)?;
+
+        for j in 0..n_variants {
+            let code = (row[j / 4] >> (2 * (j % 4))) & 0b11;
+            bed[3 + j * chunk_size + i / 4] |= code << (2 * (i % 4));
         }
+    }
 
-        genotypes
+    if reader.read(&mut [0; 1])? != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("The sample major BED has more data than expected for \
+                     {} samples and {} variants.", n_samples, n_variants)
+        ));
     }
 
-    fn _verify_magic_number(&mut self) -> bool {
-        // Make sure the first 3 bytes are 0x6c, 0x1b, 0x01.
-        let mut first_3_bytes = [0; 3];
-        self.reader.read_exact(&mut first_3_bytes).unwrap();
+    Ok(bed)
+}
+
 
-        (
-            first_3_bytes[0] == 0x6c &&
-            first_3_bytes[1] == 0x1b &&
-            first_3_bytes[2] == 0x01
-        )
+// Convert a sample major BED to a variant major BED that can be read by
+// the PlinkReader (or any recent tool).
+pub fn convert_sample_major_bed(input: &str, output: &str, n_samples: u32,
+                                n_variants: u32) -> io::Result<()>
+{
+    let reader = BufReader::new(File::open(input)?);
+    let bed = transpose_sample_major_bed(reader, n_samples, n_variants)?;
+    std::fs::write(output, bed)
+}
+
+
+
+// 2 bit code of a genotype in the BED (the coded allele is the first allele
+// in the BIM).
+fn encode_genotype(g: Option<u8>) -> u8 {
+    match g {
+        Some(2) => 0b00,
+        Some(1) => 0b10,
+        Some(0) => 0b11,
+        _ => 0b01
     }
 }
 
 
+// Writer for variant major plink filesets. The FAM is written when the
+// writer is created and the BIM and BED as variants are added. The coded
+// allele is written as A1. Haploid genotypes are written as homozygous.
+//
+// e.g. to write the variants of a reader that pass a filter:
+//     let writer = PlinkWriter::new("out", reader.samples())?;
+//     writer.write_all(reader.filter(|g| g.coded_freq() > 0.01))?;
+pub struct PlinkWriter {
+    bim: BufWriter<File>,
+    bed: BufWriter<File>,
+    samples: Vec<Sample>,
+    n_variants: u32
+}
+
+impl PlinkWriter {
+    pub fn new(prefix: &str, samples: &[Sample]) -> io::Result<PlinkWriter> {
+        let mut fam = BufWriter::new(File::create(format!("{}.fam", prefix))?);
+        for s in samples {
+            writeln!(fam, "{}", s.to_fam_line())?;
+        }
+        fam.flush()?;
+
+        let mut bed = BufWriter::new(File::create(format!("{}.bed", prefix))?);
+        bed.write_all(&[0x6c, 0x1b, 0x01])?;
+
+        Ok(PlinkWriter {
+            bim: BufWriter::new(File::create(format!("{}.bim", prefix))?),
+            bed,
+            samples: samples.to_vec(),
+            n_variants: 0
+        })
+    }
+
+    pub fn write(&mut self, g: &Genotypes) -> io::Result<()> {
+        if g.genotypes.len() != self.samples.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Got {} genotypes for {} samples.",
+                        g.genotypes.len(), self.samples.len())
+            ));
+        }
+
+        if let Some(samples) = &g.samples {
+            let same = samples.iter()
+                .zip(self.samples.iter())
+                .all(|(a, b)| a.fid == b.fid && a.iid == b.iid);
+
+            if !same {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("The samples of `{}` don't match the samples of \
+                             the fileset.", g.variant.name)
+                ));
+            }
+        }
+
+        let v = &g.variant;
+        writeln!(self.bim, "{}\t{}\t0\t{}\t{}\t{}", v.chrom, v.name,
+                 v.position, g.coded_allele(), g.other_allele())?;
+
+        // Haploid calls are stored as homozygous.
+        let scale = if g.is_haploid() { 2 } else { 1 };
+
+        let chunk: Vec<u8> = g.genotypes.chunks(4)
+            .map(|calls| {
+                calls.iter()
+                    .enumerate()
+                    .fold(0, |b, (i, call)| {
+                        let code = encode_genotype(call.map(|x| x * scale));
+                        b | (code << (2 * i))
+                    })
+            })
+            .collect();
+
+        self.bed.write_all(&chunk)?;
+        self.n_variants += 1;
+
+        Ok(())
+    }
+
+    // Write the genotypes from an iterator (e.g. a filtered reader) and
+    // finish the fileset.
+    pub fn write_all<I>(mut self, genotypes: I) -> io::Result<u32>
+        where I: IntoIterator<Item = Genotypes>
+    {
+        for g in genotypes {
+            self.write(&g)?;
+        }
+
+        self.finish()
+    }
+
+    // Flush the files and return the number of variants that were written.
+    pub fn finish(mut self) -> io::Result<u32> {
+        self.bim.flush()?;
+        self.bed.flush()?;
+
+        Ok(self.n_variants)
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use std::io::BufReader;
     use super::*;
+    use crate::core::{HaploidHets, Sex};
 
     fn get_example_bed() -> BufReader<&'static [u8]> {
         let bed = include_bytes!(
@@ -414,7 +1741,7 @@ mod tests {
             bytes_reader,
             503,
             11158
-        );
+        ).unwrap();
     }
 
     #[test]
@@ -423,7 +1750,487 @@ mod tests {
             "test_data/common_extracted_1kg.missing.bed",
             503,
             11158
+        ).unwrap();
+    }
+
+    #[test]
+    fn test_read_variants() {
+        // 5 samples use 2 bytes per variant.
+        let mut bed = vec![0x6c, 0x1b, 0x01];
+        for i in 0..20 {
+            bed.extend(&[0b1110_0100_u8.rotate_left(i), 0b11]);
+        }
+
+        let new_reader = || BedReader::new_from_reader(
+            BufReader::new(std::io::Cursor::new(bed.clone())), 5, 20
+        ).unwrap();
+
+        let block = new_reader().read_variants(5, 10);
+
+        let mut seq_reader = new_reader();
+        let expected: Vec<Vec<Option<u8>>> = (0..15)
+            .map(|_| seq_reader._read_variant_chunk())
+            .skip(5)
+            .collect();
+
+        assert_eq!(block.len(), 10);
+        assert_eq!(block, expected);
+
+        // 0b11_10_01_00 rotated left by 6 bits is 0b00_11_10_01.
+        assert_eq!(block[1], vec![None, Some(1), Some(0), Some(2), Some(0)]);
+
+        assert!(matches!(new_reader().try_read_variants(15, 10),
+                         Err(GenepaError::InvalidBed { .. })));
+    }
+
+    #[test]
+    fn test_count_variants() {
+        // 503 samples use 126 bytes per variant.
+        assert_eq!(BedReader::count_variants(3 + 126 * 11158, 503),
+                   Some(11158));
+        assert_eq!(BedReader::count_variants(3, 503), Some(0));
+        assert_eq!(BedReader::count_variants(3 + 126 * 2 + 1, 503), None);
+        assert_eq!(BedReader::count_variants(2, 503), None);
+    }
+
+    #[test]
+    fn test_from_readers() {
+        let bim = "1\trs1\t0\t100\tA\tG\n1\trs2\t0\t200\tC\tT\n";
+        let fam = "f1 s1 0 0 1 -9\nf2 s2 0 0 2 -9\nf3 s3 0 0 0 -9\n";
+        let bed = vec![0x6c, 0x1b, 0x01, 0b11_10_00, 0b00_01_11];
+
+        let reader = PlinkReader::from_readers(std::io::Cursor::new(bed),
+                                               bim.as_bytes(), fam.as_bytes())
+            .unwrap();
+        assert_eq!(reader.n_variants(), 2);
+        assert_eq!(reader.samples()[1].iid, "s2");
+
+        let calls: Vec<Vec<Option<u8>>> = reader.map(|g| g.genotypes)
+            .collect();
+        assert_eq!(calls, vec![vec![Some(2), Some(1), Some(0)],
+                               vec![Some(0), None, Some(2)]]);
+    }
+
+    #[test]
+    fn test_try_next() {
+        // The heterozygous call on Y is an error with this policy.
+        let bim = "1\trs1\t0\t100\tA\tG\nY\trs2\t0\t200\tC\tT\n\
+                   1\trs3\t0\t300\tA\tG\n";
+        let fam = "f1 s1 0 0 1 -9\nf2 s2 0 0 2 -9\nf3 s3 0 0 0 -9\n";
+        let bed = vec![0x6c, 0x1b, 0x01, 0b11_10_00, 0b00_10_11,
+                       0b11_10_00];
+
+        let mut reader = PlinkReader::from_readers(
+            std::io::Cursor::new(bed), bim.as_bytes(), fam.as_bytes()
+        ).unwrap();
+        reader.haploid_policy(Some(HaploidHets::Error));
+
+        let g = reader.try_next().unwrap().unwrap();
+        assert_eq!(g.variant.name, "rs1");
+
+        match reader.try_next() {
+            Some(Err(GenepaError::InvalidBed { message, .. })) =>
+                assert!(message.contains("chrY:200"), "{}", message),
+            other => panic!("Expected an error, got {:?}", other)
+        }
+
+        // The iteration stops after an error.
+        assert!(reader.try_next().is_none());
+    }
+
+    #[test]
+    fn test_query_errors() {
+        let bim = "1\trs1\t0\t100\tA\tG\n1\trs1\t0\t200\tC\tT\n\
+                   1\trs2\t0\t200\tC\tT\n";
+        let fam = "f1 s1 0 0 1 -9\nf2 s2 0 0 2 -9\nf3 s3 0 0 0 -9\n";
+        let bed = vec![0x6c, 0x1b, 0x01, 0b11_10_00, 0b00_01_11,
+                       0b11_10_00];
+
+        let mut reader = PlinkReader::from_readers(
+            std::io::Cursor::new(bed), bim.as_bytes(), fam.as_bytes()
+        ).unwrap();
+
+        assert!(matches!(reader.try_get_variant_by_name("rs1"),
+                         Err(GenepaError::DuplicateVariant { .. })));
+        let g = reader.try_get_variant_by_name("rs2").unwrap().unwrap();
+        assert_eq!(g.variant.position, 200);
+        assert!(reader.try_get_variant_by_name("rs3").unwrap().is_none());
+
+        let v = Variant::new("rs3".to_string(), "1".to_string(), 200,
+                             ("C".to_string(), "T".to_string()));
+        assert!(matches!(reader.try_get_variant_genotypes(&v),
+                         Err(GenepaError::DuplicateVariant { .. })));
+
+        // A missing tabix index is an error, not a panic.
+        let index = BimIndex {
+            filename: "missing.bim.gz".to_string(),
+            n_variants: 3,
+            contigs: OnceLock::new()
+        };
+        assert!(matches!(index.count_indexed_variants(),
+                         Err(GenepaError::Index { .. })));
+        assert!(index.get_region_index_and_coded(
+            &Chromosome::new("1"), 1, 300
+        ).is_err());
+    }
+
+    #[test]
+    fn test_get_variant_by_name() {
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_by_name_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("test").to_str().unwrap().to_string();
+
+        let samples: Vec<Sample> = ["s1", "s2", "s3"].iter()
+            .map(|iid| Sample::new(iid.to_string(), iid.to_string(),
+                                   Sex::Unknown))
+            .collect();
+        let mut writer = PlinkWriter::new(&prefix, &samples).unwrap();
+        for (name, pos, calls) in [("rs1", 100, [Some(0), Some(1), None]),
+                                   ("rs2", 200, [Some(2), Some(2), Some(1)])]
+        {
+            let v = Variant::new(name.to_string(), "1".to_string(), pos,
+                                 ("A".to_string(), "G".to_string()));
+            writer.write(&Genotypes::new(v, calls.to_vec(), "G")).unwrap();
+        }
+        writer.finish().unwrap();
+        crate::index::build_index(&prefix, crate::index::IndexFormat::V2)
+            .unwrap();
+
+        let mut reader = PlinkReader::new(&prefix).unwrap();
+        let g = reader.get_variant_by_name("rs2").unwrap();
+        assert_eq!(g.variant.position, 200);
+        assert_eq!(g.genotypes, vec![Some(2), Some(2), Some(1)]);
+        assert!(reader.get_variant_by_name("rs3").is_none());
+        assert!(Path::new(&format!("{}.bimidx.names", prefix)).is_file());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_filter_variants() {
+        let bim = "1\trs1\t0\t100\tA\tG\n1\trs2\t0\t200\tA\tT\n\
+                   2\trs3\t0\t100\tC\tT\n2\trs4\t0\t200\tC\tT\n";
+        let fam = "f1 s1 0 0 1 -9\nf2 s2 0 0 2 -9\nf3 s3 0 0 0 -9\n";
+        // rs4 is monomorphic.
+        let bed = vec![0x6c, 0x1b, 0x01, 0b11_10_00, 0b00_01_11, 0b11_11_10,
+                       0b11_11_11];
+        let open = || {
+            PlinkReader::from_readers(std::io::Cursor::new(bed.clone()),
+                                      bim.as_bytes(), fam.as_bytes())
+                .unwrap()
+        };
+        let names = |reader: &mut FilteredVariants| -> Vec<String> {
+            reader.map(|g| g.variant.name).collect()
+        };
+
+        let chroms = vec![Chromosome::Autosome(2)].into_iter().collect();
+        let mut reader = open().filter_variants(vec![
+            VariantPredicate::Chromosomes(chroms),
+            VariantPredicate::MinMaf(0.1)
+        ]);
+        assert_eq!(names(&mut reader), vec!["rs3"]);
+        assert_eq!(reader.n_skipped(), 3);
+
+        let mut reader = open().filter_variants(vec![
+            VariantPredicate::NotAmbiguous,
+            VariantPredicate::Custom(Box::new(|v| v.position == 100))
+        ]);
+        assert_eq!(names(&mut reader), vec!["rs1", "rs3"]);
+
+        let mut reader = open();
+        reader.keep_samples(&["s3".to_string()]);
+        let wanted = vec!["rs2".to_string(), "rs3".to_string()];
+        let genotypes: Vec<Genotypes> = reader.filter_variants(vec![
+            VariantPredicate::Names(wanted.into_iter().collect())
+        ]).collect();
+        assert_eq!(genotypes[0].genotypes, vec![Some(2)]);
+        assert_eq!(genotypes[1].genotypes, vec![Some(0)]);
+    }
+
+    #[test]
+    fn test_subset_samples() {
+        let bim = "1\trs1\t0\t100\tA\tG\n1\trs2\t0\t200\tC\tT\n";
+        let fam = "f1 s1 0 0 1 -9\nf2 s2 0 0 2 -9\nf3 s3 0 0 0 -9\n";
+        let bed = vec![0x6c, 0x1b, 0x01, 0b11_10_00, 0b00_01_11];
+        let open = || {
+            PlinkReader::from_readers(std::io::Cursor::new(bed.clone()),
+                                      bim.as_bytes(), fam.as_bytes())
+                .unwrap()
+        };
+
+        let mut reader = open();
+        let ids = |ids: &[&str]| -> Vec<String> {
+            ids.iter().map(|id| id.to_string()).collect()
+        };
+        assert_eq!(reader.keep_samples(&ids(&["s3", "s2", "s4"])), 2);
+        assert_eq!(reader.remove_samples(&ids(&["s2"])), 1);
+        assert_eq!(reader.samples()[0].iid, "s3");
+
+        let v = Variant::new("rs2".to_string(), "1".to_string(), 200,
+                             ("C".to_string(), "T".to_string()));
+        assert_eq!(reader.get_variant_genotypes(&v).unwrap().genotypes,
+                   vec![Some(2)]);
+
+        let mut reader = open();
+        reader.remove_samples(&ids(&["s2"]));
+        assert_eq!(reader.count_if(|_, counts| counts.n_missing == 0), 2);
+
+        let mut reader = open();
+        reader.keep_samples(&ids(&["s1", "s3"]));
+        reader.attach_samples(true);
+        let genotypes: Vec<Genotypes> = reader.collect();
+        assert_eq!(genotypes[0].genotypes, vec![Some(2), Some(0)]);
+        assert_eq!(genotypes[1].genotypes, vec![Some(0), Some(2)]);
+        assert_eq!(genotypes[1].samples.as_ref().unwrap()[1].iid, "s3");
+    }
+
+    #[test]
+    fn test_errors() {
+        let bim = "1\trs1\t0\t100\tA\tG\n1\trs2\t0\t200\tC\tT\n";
+        let fam = "f1 s1 0 0 1 -9\nf2 s2 0 0 2 -9\nf3 s3 0 0 0 -9\n";
+        let bed = vec![0x6c, 0x1b, 0x01, 0b11_10_00, 0b00_01_11];
+        let open = |bed: &[u8], bim: &str, fam: &str| {
+            PlinkReader::from_readers(std::io::Cursor::new(bed.to_vec()),
+                                      bim.as_bytes(), fam.as_bytes())
+                .err()
+                .unwrap()
+        };
+
+        match open(&bed[..4], bim, fam) {
+            GenepaError::InconsistentFileset { n_bim: 2, n_bed: 1, .. } => {},
+            e => panic!("Unexpected error: {}", e)
+        }
+
+        match open(&bed, "1\trs1\t0\tpos\tA\tG\n", fam) {
+            GenepaError::Parse { line: 1, .. } => {},
+            e => panic!("Unexpected error: {}", e)
+        }
+
+        match open(&bed, bim, "f1\n") {
+            GenepaError::Parse { line: 1, .. } => {},
+            e => panic!("Unexpected error: {}", e)
+        }
+
+        match PlinkReader::new("test_data/does_not_exist").err().unwrap() {
+            GenepaError::Io { path, .. } => {
+                assert_eq!(path, "test_data/does_not_exist.bed");
+            },
+            e => panic!("Unexpected error: {}", e)
+        }
+
+        let not_bed = BufReader::new(&b"#!/bin/sh"[..]);
+        match BedReader::new_from_reader(not_bed, 3, 2).err().unwrap() {
+            GenepaError::InvalidBed { .. } => {},
+            e => panic!("Unexpected error: {}", e)
+        }
+    }
+
+    #[test]
+    fn test_gzipped_bim_fam() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_gzipped_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("out").to_str().unwrap().to_string();
+
+        let samples: Vec<Sample> = (0..3)
+            .map(|i| Sample::new(format!("f{}", i), format!("s{}", i),
+                                 Sex::Unknown))
+            .collect();
+        let v = Variant::new("rs1".to_string(), "1".to_string(), 100,
+                             ("A".to_string(), "G".to_string()));
+        let g = Genotypes::new(v, vec![Some(0), None, Some(2)], "G");
+        PlinkWriter::new(&prefix, &samples).unwrap()
+            .write_all(vec![g.clone()]).unwrap();
+
+        for ext in &["bim", "fam"] {
+            let filename = format!("{}.{}", prefix, ext);
+            let mut encoder = GzEncoder::new(
+                File::create(format!("{}.gz", filename)).unwrap(),
+                Compression::default()
+            );
+            encoder.write_all(&std::fs::read(&filename).unwrap()).unwrap();
+            encoder.finish().unwrap();
+            std::fs::remove_file(&filename).unwrap();
+        }
+
+        assert_eq!(read_fam(&format!("{}.fam.gz", prefix)).unwrap()[2].iid,
+                   "s2");
+        assert_eq!(BimReader::new(&format!("{}.bim.gz", prefix)).unwrap()
+                       .count(), 1);
+
+        let mut reader = PlinkReader::new(&prefix).unwrap();
+        assert_eq!(reader.samples()[1].fid, "f1");
+        assert_eq!(reader.get_variant_genotypes(&g.variant).unwrap()
+                       .genotypes,
+                   g.genotypes);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_writer_roundtrip() {
+        use crate::index::NativeBimIndex;
+
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_writer_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("out").to_str().unwrap().to_string();
+
+        let samples: Vec<Sample> = (0..5)
+            .map(|i| {
+                let sex = if i % 2 == 0 { Sex::Male } else { Sex::Female };
+                Sample::new(format!("f{}", i), format!("s{}", i), sex)
+            })
+            .collect();
+
+        let variant = |name: &str, chrom: &str, pos: u32| {
+            Variant::new(name.to_string(), chrom.to_string(), pos,
+                         ("A".to_string(), "G".to_string()))
+        };
+
+        let genotypes = vec![
+            Genotypes::new(variant("rs1", "1", 100),
+                           vec![Some(0), Some(1), Some(2), None, Some(1)],
+                           "G"),
+            Genotypes::new(variant("rs2", "Y", 200),
+                           vec![Some(0), Some(1), Some(0), Some(1), None],
+                           "A").with_ploidy(1)
+        ];
+
+        // The pedigree and phenotypes are kept.
+        let mut samples = samples;
+        samples[4].father = Some("s0".to_string());
+        samples[4].mother = Some("s1".to_string());
+        samples[4].phenotype = Some(2.0);
+        samples[3].phenotype = Some(-0.5);
+
+        let writer = PlinkWriter::new(&prefix, &samples).unwrap();
+        assert_eq!(writer.write_all(genotypes.clone()).unwrap(), 2);
+        assert!(std::fs::read_to_string(format!("{}.fam", prefix)).unwrap()
+                    .ends_with("f3 s3 0 0 2 -0.5\nf4 s4 s0 s1 1 2\n"));
+
+        assert_eq!(std::fs::read_to_string(format!("{}.bim", prefix))
+                       .unwrap(),
+                   "1\trs1\t0\t100\tG\tA\nY\trs2\t0\t200\tA\tG\n");
+
+        NativeBimIndex::build(&format!("{}.bim", prefix)).unwrap()
+            .write(&format!("{}.bimidx2", prefix)).unwrap();
+
+        let mut reader = PlinkReader::new(&prefix).unwrap();
+        assert_eq!(reader.samples()[1].sex, Sex::Female);
+        assert_eq!(reader.samples(), &samples[..]);
+
+        let read: Vec<Genotypes> = reader.by_ref().collect();
+        assert_eq!(read.len(), 2);
+        for (observed, expected) in read.iter().zip(genotypes.iter()) {
+            assert_eq!(observed.variant, expected.variant);
+            assert_eq!(observed.coded_allele(), expected.coded_allele());
+            assert_eq!(observed.genotypes, expected.genotypes);
+        }
+
+        // The same fileset with a sample major BED.
+        let bed = std::fs::read(format!("{}.bed", prefix)).unwrap();
+        let mut sample_major = vec![0x6c, 0x1b, 0x00];
+        for i in 0..5 {
+            let mut row = 0;
+            for j in 0..2 {
+                let code = (bed[3 + 2 * j + i / 4] >> (2 * (i % 4))) & 0b11;
+                row |= code << (2 * j);
+            }
+            sample_major.push(row);
+        }
+
+        assert_eq!(transpose_sample_major_bed(&sample_major[..], 5, 2)
+                       .unwrap(),
+                   bed);
+        assert!(transpose_sample_major_bed(&bed[..], 5, 2).is_err());
+
+        std::fs::write(format!("{}.bed", prefix), &sample_major).unwrap();
+        let transposed: Vec<Genotypes> = PlinkReader::new(&prefix).unwrap()
+            .collect();
+        assert_eq!(transposed[1].genotypes, genotypes[1].genotypes);
+
+        convert_sample_major_bed(&format!("{}.bed", prefix),
+                                 &format!("{}.bed", prefix), 5, 2).unwrap();
+
+        let mut reader = PlinkReader::new(&prefix).unwrap();
+        assert_eq!(reader.count_if(|_, c| c.call_rate() >= 0.8), 2);
+        assert_eq!(reader.count_if(|_, _| true), 0);
+
+        let mut reader = PlinkReader::new(&prefix).unwrap();
+        reader.orientation(Orientation::CountA2);
+        assert_eq!(reader.count_if(|_, c| c.n_geno == [1, 2, 1]), 1);
+
+        let mut reader = PlinkReader::new(&prefix).unwrap();
+        reader.orientation(Orientation::CountA2);
+        let g = reader.next().unwrap();
+        assert_eq!(g.coded_allele(), "A");
+        assert_eq!(g.genotypes, vec![Some(2), Some(1), Some(0), None,
+                                     Some(1)]);
+
+        let mut reader = PlinkReader::new(&prefix).unwrap();
+        reader.next();
+        assert_eq!(reader.count_if(|v, c| {
+            v.name == "rs2" && c.n_geno == [2, 0, 2]
+        }), 1);
+
+        // The samples attached to the genotypes must be the samples of the
+        // fileset.
+        let mut reversed = samples.clone();
+        reversed.reverse();
+        let mut writer = PlinkWriter::new(&prefix, &reversed).unwrap();
+        let g = genotypes[0].clone().with_samples(Arc::new(samples));
+        assert!(writer.write(&g).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_fileset() {
+        use crate::crypto::encrypt_plink_fileset;
+
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_encrypted_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("plain").to_str().unwrap().to_string();
+        let enc_prefix = dir.join("enc").to_str().unwrap().to_string();
+
+        std::fs::write(format!("{}.bim", prefix),
+                       "1\trs1\t0\t100\tA\tG\n\
+                        1\trs2\t0\t200\tC\tT\n\
+                        2\trs3\t0\t100\tA\tC\n").unwrap();
+        std::fs::write(format!("{}.fam", prefix),
+                       "f1 s1 0 0 1 -9\nf2 s2 0 0 2 -9\n").unwrap();
+        std::fs::write(format!("{}.bed", prefix),
+                       [0x6c, 0x1b, 0x01, 0b1000, 0b0110, 0b0011]).unwrap();
+
+        let key = [3; 32];
+        encrypt_plink_fileset(&prefix, &enc_prefix, &key).unwrap();
+
+        let mut reader = PlinkReader::new_encrypted(&enc_prefix, &key).unwrap();
+        assert_eq!(reader.samples()[1].iid, "s2");
+        assert_eq!(reader.n_variants(), 3);
+
+        let region = reader.get_variants_in_region(
+            &Chromosome::Autosome(1), 150, 300
         );
+        assert_eq!(region.len(), 1);
+        assert_eq!(region[0].variant.name, "rs2");
+        assert_eq!(region[0].genotypes, vec![Some(1), None]);
+
+        let all: Vec<Vec<Option<u8>>> = PlinkReader::new_encrypted(
+            &enc_prefix, &key
+        ).unwrap().map(|g| g.genotypes).collect();
+        assert_eq!(all, vec![vec![Some(2), Some(1)],
+                             vec![Some(1), None],
+                             vec![Some(0), Some(2)]]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
@@ -431,14 +2238,14 @@ mod tests {
         // TODO
         BimIndex::get_or_create_bim_index(
             "test_data/common_extracted_1kg.missing.bim"
-        );
+        ).unwrap();
     }
 
     #[test]
     fn test_read_variant_genotypes() {
         let mut reader = PlinkReader::new(
             "test_data/common_extracted_1kg.missing"
-        );
+        ).unwrap();
 
         let v = Variant::new(
             "rs1610216".to_string(),
@@ -458,6 +2265,8 @@ mod tests {
             expct_geno,
             reader.get_variant_genotypes(&expct_geno.variant).unwrap()
         );
+        assert_eq!(expct_geno,
+                   reader.get_variant_by_name("rs1610216").unwrap());
     }
 
 /*
@@ -467,7 +2276,7 @@ mod tests {
             get_example_bed(),
             503,
             11158
-        );
+        ).unwrap();
         bed._verify_magic_number();
         let genotypes = bed._read_variant_chunk();
         println!("{:?}", genotypes);
@@ -0,0 +1,493 @@
+/*!
+ * Dataset-level quality control summaries.
+ *
+ * The report is built incrementally so that all the metrics are computed in
+ * a single pass over the genotypes.
+ */
+
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::core::{Chromosome, Genotypes, VariantKind, Sample};
+
+
+// Per-sample genotype counts. As in `bcftools stats`, the coded allele is
+// treated as the alternative allele.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SampleQc {
+    pub n_hom_other: u64,
+    pub n_het: u64,
+    pub n_hom_coded: u64,
+    pub n_missing: u64,
+    // Number of variants where this sample is the only carrier of the minor
+    // allele.
+    pub n_singletons: u64
+}
+
+
+impl SampleQc {
+    pub fn n_called(&self) -> u64 {
+        self.n_hom_other + self.n_het + self.n_hom_coded
+    }
+
+    pub fn het_hom_ratio(&self) -> f64 {
+        if self.n_hom_coded == 0 {
+            return f64::NAN;
+        }
+
+        self.n_het as f64 / self.n_hom_coded as f64
+    }
+}
+
+
+// Streaming mean and variance (Welford's algorithm).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunningStats {
+    pub n: u64,
+    mean: f64,
+    m2: f64
+}
+
+
+impl RunningStats {
+    pub fn push(&mut self, x: f64) {
+        self.n += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.n == 0 { f64::NAN } else { self.mean }
+    }
+
+    pub fn sd(&self) -> f64 {
+        if self.n < 2 {
+            return f64::NAN;
+        }
+
+        (self.m2 / (self.n - 1) as f64).sqrt()
+    }
+
+    // Standard error of the mean.
+    pub fn se(&self) -> f64 {
+        self.sd() / (self.n as f64).sqrt()
+    }
+}
+
+
+// Frequency and missingness statistics for the variants in a window of the
+// genome, used to detect positional artifacts (e.g. clusters of bad probes).
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowQc {
+    pub chrom: Chromosome,
+    // The window covers [start, end).
+    pub start: u32,
+    pub end: u32,
+    pub maf: RunningStats,
+    pub missing_rate: RunningStats
+}
+
+
+#[derive(Debug, Default, PartialEq)]
+pub struct QcReport {
+    pub n_variants: u64,
+    pub n_snps: u64,
+    pub n_mnps: u64,
+    pub n_indels: u64,
+    pub n_other: u64,
+    pub n_transitions: u64,
+    pub n_transversions: u64,
+    pub n_singletons: u64,
+    pub samples: Vec<SampleQc>,
+    pub window_size: Option<u32>,
+    pub windows: Vec<WindowQc>
+}
+
+
+impl QcReport {
+    pub fn new() -> QcReport {
+        QcReport::default()
+    }
+
+    // Also compute statistics in non-overlapping windows of the given size
+    // (in bp). The variants are expected to be sorted by position within
+    // chromosomes.
+    pub fn with_windows(window_size: u32) -> QcReport {
+        assert!(window_size > 0, "The window size must be positive.");
+
+        QcReport { window_size: Some(window_size), ..QcReport::default() }
+    }
+
+    pub fn from_genotypes<I>(genotypes: I) -> QcReport
+        where I: IntoIterator<Item = Genotypes>
+    {
+        let mut report = QcReport::new();
+        for g in genotypes {
+            report.add(&g);
+        }
+
+        report
+    }
+
+    // Update the report with the metrics from a single variant.
+    pub fn add(&mut self, g: &Genotypes) {
+        let v = &g.variant;
+        self.n_variants += 1;
+
+        match v.kind() {
+            VariantKind::Snp => {
+                self.n_snps += 1;
+
+                if v.is_transition() {
+                    self.n_transitions += 1;
+                } else {
+                    self.n_transversions += 1;
+                }
+            },
+            VariantKind::Mnp => self.n_mnps += 1,
+            VariantKind::Indel => self.n_indels += 1,
+            VariantKind::Symbolic | VariantKind::Other => self.n_other += 1
+        }
+
+        let (n_called, n_coded) = self.add_sample_metrics(g);
+
+        if let Some(size) = self.window_size {
+            let n_alleles = u64::from(g.ploidy()) * n_called;
+            self.add_window_metrics(g, size, n_called, n_alleles, n_coded);
+        }
+    }
+
+    fn add_window_metrics(&mut self, g: &Genotypes, size: u32, n_called: u64,
+                          n_alleles: u64, n_coded: u64)
+    {
+        let v = &g.variant;
+        let start = (v.position / size) * size;
+
+        let is_new_window = match self.windows.last() {
+            Some(w) => w.chrom != v.chrom || w.start != start,
+            None => true
+        };
+
+        if is_new_window {
+            self.windows.push(WindowQc {
+                chrom: v.chrom.clone(),
+                start,
+                end: start.saturating_add(size),
+                maf: RunningStats::default(),
+                missing_rate: RunningStats::default()
+            });
+        }
+
+        let window = self.windows.last_mut().unwrap();
+
+        let n = g.genotypes.len() as f64;
+        window.missing_rate.push(1.0 - n_called as f64 / n);
+
+        if n_called > 0 {
+            let freq = n_coded as f64 / n_alleles as f64;
+            window.maf.push(freq.min(1.0 - freq));
+        }
+    }
+
+    // Windows where the mean MAF or missing rate deviates from the average
+    // over all windows by more than `z` standard deviations.
+    pub fn outlier_windows(&self, z: f64) -> Vec<&WindowQc> {
+        let mut maf = RunningStats::default();
+        let mut missing = RunningStats::default();
+
+        for w in self.windows.iter() {
+            if w.maf.n > 0 {
+                maf.push(w.maf.mean());
+            }
+            missing.push(w.missing_rate.mean());
+        }
+
+        let is_outlier = |x: f64, stats: &RunningStats| {
+            stats.sd() > 0.0 && ((x - stats.mean()) / stats.sd()).abs() > z
+        };
+
+        self.windows.iter()
+            .filter(|w| {
+                (w.maf.n > 0 && is_outlier(w.maf.mean(), &maf)) ||
+                is_outlier(w.missing_rate.mean(), &missing)
+            })
+            .collect()
+    }
+
+    pub fn write_window_table<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "chrom\tstart\tend\tn_variants\tmean_maf\tmaf_se\t\
+                       mean_missing_rate\tmissing_rate_se")?;
+
+        for w in self.windows.iter() {
+            writeln!(out, "{}\t{}\t{}\t{}\t{:.6}\t{:.6}\t{:.6}\t{:.6}",
+                     w.chrom, w.start, w.end, w.missing_rate.n, w.maf.mean(),
+                     w.maf.se(), w.missing_rate.mean(),
+                     w.missing_rate.se())?;
+        }
+
+        Ok(())
+    }
+
+    // Returns the number of non-missing genotypes and the number of coded
+    // alleles for the variant.
+    fn add_sample_metrics(&mut self, g: &Genotypes) -> (u64, u64) {
+        if self.samples.is_empty() {
+            self.samples = vec![SampleQc::default(); g.genotypes.len()];
+        }
+
+        if self.samples.len() != g.genotypes.len() {
+            panic!("Expected {} samples but `{}` has {} genotypes.",
+                   self.samples.len(), g.variant, g.genotypes.len());
+        }
+
+        let haploid = g.is_haploid();
+
+        let mut n_called = 0;
+        let mut n_coded = 0;
+        let mut last_het = None;
+        let mut last_hom = [None, None];
+
+        for (i, geno) in g.genotypes.iter().enumerate() {
+            let sample = &mut self.samples[i];
+
+            // Haploid calls are counted as homozygous.
+            match (geno, haploid) {
+                (Some(0), _) => {
+                    sample.n_hom_other += 1;
+                    last_hom[0] = Some(i);
+                },
+                (Some(1), false) => {
+                    sample.n_het += 1;
+                    last_het = Some(i);
+                },
+                (Some(1), true) | (Some(2), false) => {
+                    sample.n_hom_coded += 1;
+                    last_hom[1] = Some(i);
+                },
+                (Some(x), _) => panic!("Unexpected genotype value: {}", x),
+                (None, _) => sample.n_missing += 1
+            }
+
+            if let Some(x) = geno {
+                n_called += 1;
+                n_coded += u64::from(*x);
+            }
+        }
+
+        // If the minor allele was observed once, its carrier is necessarily
+        // the last (and only) heterozygous sample, or the only sample with
+        // the allele for haploid variants.
+        let n_alleles = u64::from(g.ploidy()) * n_called;
+        let minor_count = n_coded.min(n_alleles - n_coded);
+
+        let carrier = match (minor_count, haploid) {
+            (1, false) => last_het,
+            (1, true) => last_hom[(n_coded == 1) as usize],
+            _ => None
+        };
+
+        if let Some(i) = carrier {
+            self.samples[i].n_singletons += 1;
+            self.n_singletons += 1;
+        }
+
+        (n_called, n_coded)
+    }
+
+    // Writes a tab-delimited table of the per-sample metrics.
+    pub fn write_sample_table<W: Write>(&self, out: &mut W,
+                                        samples: &[Sample])
+        -> io::Result<()>
+    {
+        if samples.len() != self.samples.len() {
+            panic!("Got {} samples for a report on {} samples.",
+                   samples.len(), self.samples.len());
+        }
+
+        writeln!(out, "fid\tiid\tn_hom_other\tn_het\tn_hom_coded\t\
+                       n_missing\tn_singletons\thet_hom_ratio")?;
+
+        for (sample, qc) in samples.iter().zip(self.samples.iter()) {
+            writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}",
+                     sample.fid, sample.iid, qc.n_hom_other, qc.n_het,
+                     qc.n_hom_coded, qc.n_missing, qc.n_singletons,
+                     qc.het_hom_ratio())?;
+        }
+
+        Ok(())
+    }
+
+    // Transition / transversion ratio (NaN if there are no transversions).
+    pub fn ts_tv_ratio(&self) -> f64 {
+        if self.n_transversions == 0 {
+            return f64::NAN;
+        }
+
+        self.n_transitions as f64 / self.n_transversions as f64
+    }
+}
+
+
+impl fmt::Display for QcReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Variants\t{}", self.n_variants)?;
+        writeln!(f, "SNPs\t{}", self.n_snps)?;
+        writeln!(f, "MNPs\t{}", self.n_mnps)?;
+        writeln!(f, "Indels\t{}", self.n_indels)?;
+        writeln!(f, "Other\t{}", self.n_other)?;
+        writeln!(f, "Transitions\t{}", self.n_transitions)?;
+        writeln!(f, "Transversions\t{}", self.n_transversions)?;
+        writeln!(f, "Ts/Tv\t{:.3}", self.ts_tv_ratio())?;
+        writeln!(f, "Singletons\t{}", self.n_singletons)?;
+
+        if let Some(size) = self.window_size {
+            writeln!(f, "Windows ({}bp)\t{}", size, self.windows.len())?;
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{HaploidHets, Variant};
+
+    fn genotypes(a1: &str, a2: &str) -> Genotypes {
+        genotypes_with_calls(a1, a2, vec![Some(0), Some(1)])
+    }
+
+    fn genotypes_with_calls(a1: &str, a2: &str, calls: Vec<Option<u8>>)
+        -> Genotypes
+    {
+        genotypes_at("1", 1, calls, a1, a2)
+    }
+
+    fn genotypes_at(chrom: &str, pos: u32, calls: Vec<Option<u8>>, a1: &str,
+                    a2: &str) -> Genotypes
+    {
+        let v = Variant::new(
+            "v".to_string(),
+            chrom.to_string(),
+            pos,
+            (a1.to_string(), a2.to_string())
+        );
+
+        Genotypes::new(v, calls, a1)
+    }
+
+    #[test]
+    fn test_running_stats() {
+        let mut stats = RunningStats::default();
+        for x in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0].iter() {
+            stats.push(*x);
+        }
+
+        assert_eq!(stats.mean(), 5.0);
+        assert!((stats.sd() - (32.0_f64 / 7.0).sqrt()).abs() < 1e-12);
+        assert!((stats.se() - stats.sd() / 8.0_f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_windows() {
+        let good = || vec![Some(0), Some(1), Some(2), Some(1)];
+        let bad = || vec![None, None, Some(2), Some(1)];
+
+        let mut report = QcReport::with_windows(1000);
+        for pos in &[10, 500, 999, 1000, 1500, 2500] {
+            report.add(&genotypes_at("1", *pos, good(), "A", "G"));
+        }
+
+        // Two windows on chromosome 2 with bad missingness in the second.
+        report.add(&genotypes_at("2", 10, good(), "A", "G"));
+        report.add(&genotypes_at("2", 5000, bad(), "A", "G"));
+        report.add(&genotypes_at("2", 5001, bad(), "A", "G"));
+
+        let windows: Vec<(String, u32, u64)> = report.windows.iter()
+            .map(|w| (w.chrom.to_string(), w.start, w.missing_rate.n))
+            .collect();
+        let windows: Vec<(&str, u32, u64)> = windows.iter()
+            .map(|(chrom, start, n)| (chrom.as_str(), *start, *n))
+            .collect();
+
+        assert_eq!(windows, vec![
+            ("1", 0, 3), ("1", 1000, 2), ("1", 2000, 1), ("2", 0, 1),
+            ("2", 5000, 2)
+        ]);
+
+        let last = report.windows.last().unwrap();
+        assert_eq!(last.missing_rate.mean(), 0.5);
+        assert_eq!(last.maf.mean(), 0.25);
+
+        let outliers = report.outlier_windows(1.5);
+        assert_eq!(outliers.len(), 1);
+        assert_eq!((&outliers[0].chrom, outliers[0].start),
+                   (&Chromosome::Autosome(2), 5000));
+    }
+
+    #[test]
+    fn test_sample_metrics() {
+        let report = QcReport::from_genotypes(vec![
+            genotypes_with_calls("A", "G", vec![Some(0), Some(1), None]),
+            genotypes_with_calls("A", "G", vec![Some(2), Some(1), Some(1)]),
+            genotypes_with_calls("A", "G", vec![Some(2), Some(2), Some(1)]),
+        ]);
+
+        assert_eq!(report.samples.len(), 3);
+        assert_eq!(report.samples[0], SampleQc {
+            n_hom_other: 1, n_het: 0, n_hom_coded: 2, n_missing: 0,
+            n_singletons: 0
+        });
+        assert_eq!(report.samples[1].het_hom_ratio(), 2.0);
+        assert_eq!(report.samples[2].n_called(), 2);
+
+        // The first variant has a single coded allele and the last one has
+        // a single copy of the other allele.
+        assert_eq!(report.n_singletons, 2);
+        assert_eq!(report.samples[1].n_singletons, 1);
+        assert_eq!(report.samples[2].n_singletons, 1);
+    }
+
+    #[test]
+    fn test_haploid_sample_metrics() {
+        let g = genotypes_at("MT", 1, vec![Some(0), Some(2), Some(0), Some(1)],
+                             "A", "G")
+            .into_haploid(HaploidHets::SetMissing)
+            .unwrap();
+
+        let report = QcReport::from_genotypes(vec![g]);
+
+        assert_eq!(report.samples[1], SampleQc {
+            n_hom_other: 0, n_het: 0, n_hom_coded: 1, n_missing: 0,
+            n_singletons: 1
+        });
+        assert_eq!(report.samples[3].n_missing, 1);
+        assert_eq!(report.n_singletons, 1);
+    }
+
+    #[test]
+    fn test_ts_tv() {
+        let report = QcReport::from_genotypes(vec![
+            genotypes("A", "G"),
+            genotypes("C", "T"),
+            genotypes("C", "T"),
+            genotypes("A", "C"),
+            genotypes("A", "AT")
+        ]);
+
+        assert_eq!(report.n_variants, 5);
+        assert_eq!(report.n_snps, 4);
+        assert_eq!(report.n_indels, 1);
+        assert_eq!(report.n_transitions, 3);
+        assert_eq!(report.n_transversions, 1);
+        assert_eq!(report.ts_tv_ratio(), 3.0);
+    }
+
+    #[test]
+    fn test_ts_tv_no_transversions() {
+        let report = QcReport::from_genotypes(vec![genotypes("A", "G")]);
+        assert!(report.ts_tv_ratio().is_nan());
+    }
+}
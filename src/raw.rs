@@ -0,0 +1,424 @@
+/*!
+ * Readers for the additive text exports of plink (`--recode A` and
+ * `--recode A-transpose`).
+ *
+ * The `.raw` files have one line per sample with the first 6 columns of
+ * the FAM followed by the number of copies of the counted allele for every
+ * variant (NA if missing). The variant columns are named `{name}_{A1}` or,
+ * with `include-alt`, `{name}_{A1}(/{A2})`. As these files don't have the
+ * positions of the variants, a BIM can be given to complete them; otherwise
+ * the chromosome and position are 0 (and the other allele too if it isn't
+ * in the header).
+ *
+ * The `.traw` files are transposed: every line has the chromosome, name,
+ * position in cM, position, counted allele and other allele of a variant
+ * followed by the counts of the samples. The samples are named `{FID}_{IID}`
+ * in the header, so FIDs or IIDs containing `_` can't be recovered exactly.
+ *
+ * The calls are read as diploid, like plink writes them.
+ */
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use std::iter::FusedIterator;
+use std::sync::Arc;
+
+use crate::core::{Chromosome, Genotypes, Sample, Sex, Variant};
+use crate::plink::BimReader;
+use crate::source::RegionPage;
+use crate::utils::open_text_file;
+
+
+// Number of copies of the counted allele.
+fn parse_call(value: &str, location: &str) -> Option<u8> {
+    match value {
+        "NA" => None,
+        "0" => Some(0),
+        "1" => Some(1),
+        "2" => Some(2),
+        _ => panic!("Invalid genotype `{}` on {}.", value, location)
+    }
+}
+
+
+// Name, counted allele and other allele (if given) of a `.raw` column.
+fn parse_raw_column(column: &str) -> (String, String, Option<String>) {
+    let (column, other) = match column.strip_suffix(')')
+        .and_then(|c| c.rsplit_once("(/"))
+    {
+        Some((column, other)) => (column, Some(other.to_string())),
+        None => (column, None)
+    };
+
+    match column.rsplit_once('_') {
+        Some((name, coded)) => (name.to_string(), coded.to_string(), other),
+        None => panic!("Invalid variant column `{}` in the raw file (expected \
+                        name_allele).", column)
+    }
+}
+
+
+// Sample of a `{FID}_{IID}` column of a `.traw` file. Columns made of the
+// same ID twice (e.g. `a_b_a_b`) have FID = IID.
+fn parse_traw_sample(column: &str) -> Sample {
+    let half = column.len() / 2;
+    let (fid, iid) = if column.len() % 2 == 1 &&
+                        column.get(half..half + 1) == Some("_") &&
+                        column[..half] == column[half + 1..]
+    {
+        (&column[..half], &column[..half])
+    } else {
+        column.split_once('_').unwrap_or((column, column))
+    };
+
+    Sample::new(fid.to_string(), iid.to_string(), Sex::Unknown)
+}
+
+
+pub struct RawReader {
+    genotypes: Vec<Genotypes>,
+    samples: Arc<Vec<Sample>>,
+    attach_samples: bool,
+    n_read: usize
+}
+
+impl RawReader {
+    pub fn new(filename: &str) -> RawReader {
+        RawReader::from_readers(open_text_file(filename), None::<&[u8]>)
+    }
+
+    // Read the chromosomes, positions and alleles of the variants from a
+    // BIM (matched by name).
+    pub fn with_bim(filename: &str, bim: &str) -> RawReader {
+        let f = File::open(bim)
+            .unwrap_or_else(|_| panic!("Could not open `{}`", bim));
+
+        RawReader::from_readers(open_text_file(filename),
+                                Some(BufReader::new(f)))
+    }
+
+    pub fn from_readers<R, B>(raw: R, bim: Option<B>) -> RawReader
+        where R: BufRead, B: BufRead + 'static
+    {
+        let mut lines = raw.lines();
+        let header = lines.next()
+            .expect("The raw file is empty.")
+            .expect("Could not read the raw file.");
+
+        let columns: Vec<(String, String, Option<String>)> = header
+            .split_whitespace()
+            .skip(6)
+            .map(parse_raw_column)
+            .collect();
+
+        let mut calls: Vec<Vec<Option<u8>>> = vec![Vec::new(); columns.len()];
+        let mut samples = Vec::new();
+
+        for (i, line) in lines.enumerate() {
+            let line = line.expect("Could not read the raw file.");
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            if fields.is_empty() {
+                continue;
+            }
+
+            if fields.len() != 6 + columns.len() {
+                panic!("Expected {} columns on line {} of the raw file, got \
+                        {}.", 6 + columns.len(), i + 2, fields.len());
+            }
+
+            samples.push(Sample::new(fields[0].to_string(),
+                                     fields[1].to_string(),
+                                     Sex::from_plink_code(fields[4])));
+
+            let location = format!("line {} of the raw file", i + 2);
+            for (variant_calls, value) in calls.iter_mut()
+                .zip(fields[6..].iter())
+            {
+                variant_calls.push(parse_call(value, &location));
+            }
+        }
+
+        let variants: HashMap<String, Variant> = match bim {
+            Some(bim) => BimReader::from_reader(bim)
+                .map(|v| {
+                    let v = v.unwrap_or_else(|e| panic!("{}", e)).variant;
+                    (v.name.clone(), v)
+                })
+                .collect(),
+            None => HashMap::new()
+        };
+
+        let genotypes = columns.into_iter()
+            .zip(calls)
+            .map(|((name, coded, other), calls)| {
+                let variant = match variants.get(&name) {
+                    Some(v) => {
+                        if v.alleles.0 != coded && v.alleles.1 != coded {
+                            panic!("The counted allele {} of `{}` is not an \
+                                    allele of the BIM.", coded, v);
+                        }
+                        v.clone()
+                    },
+                    None => {
+                        let other = other.unwrap_or_else(|| "0".to_string());
+                        Variant::new(name, "0".to_string(), 0,
+                                     (coded.clone(), other))
+                    }
+                };
+
+                Genotypes::new(variant, calls, &coded)
+            })
+            .collect();
+
+        RawReader {
+            genotypes,
+            samples: Arc::new(samples),
+            attach_samples: false,
+            n_read: 0
+        }
+    }
+
+    pub fn attach_samples(&mut self, attach: bool) {
+        self.attach_samples = attach;
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn n_variants(&self) -> u32 {
+        self.genotypes.len() as u32
+    }
+
+    fn _make_genotypes(&self, idx: usize) -> Genotypes {
+        let g = self.genotypes[idx].clone();
+
+        if self.attach_samples {
+            g.with_samples(Arc::clone(&self.samples))
+        } else {
+            g
+        }
+    }
+
+    fn _region_indices(&self, chrom: &Chromosome, start: u32, end: u32)
+        -> Vec<usize>
+    {
+        self.genotypes.iter()
+            .enumerate()
+            .filter(|(_, g)| {
+                let v = &g.variant;
+                v.chrom == *chrom && v.position >= start && v.position <= end
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    pub fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        let matches: Vec<usize> = self._region_indices(&v.chrom, v.position,
+                                                       v.position)
+            .into_iter()
+            .filter(|&i| self.genotypes[i].variant == *v)
+            .collect();
+
+        match matches.len() {
+            0 => None,
+            1 => Some(self._make_genotypes(matches[0])),
+            _ => panic!("There are duplicate variants in the raw file.")
+        }
+    }
+
+    pub fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                                  end: u32) -> Vec<Genotypes>
+    {
+        self._region_indices(chrom, start, end)
+            .into_iter()
+            .map(|i| self._make_genotypes(i))
+            .collect()
+    }
+
+    pub fn get_variants_in_region_page(&mut self, chrom: &Chromosome,
+                                       start: u32, end: u32, offset: usize,
+                                       limit: usize) -> RegionPage
+    {
+        let genotypes = self._region_indices(chrom, start, end)
+            .into_iter()
+            .skip(offset)
+            .map(|i| self._make_genotypes(i));
+
+        RegionPage::collect(genotypes, offset, limit)
+    }
+}
+
+
+impl Iterator for RawReader {
+    type Item = Genotypes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.n_read >= self.genotypes.len() {
+            return None;
+        }
+
+        self.n_read += 1;
+        Some(self._make_genotypes(self.n_read - 1))
+    }
+}
+
+impl FusedIterator for RawReader {}
+
+
+// The `.traw` is variant major, so it is read as it is iterated.
+pub struct TrawReader<R: BufRead> {
+    lines: Lines<R>,
+    samples: Arc<Vec<Sample>>,
+    attach_samples: bool,
+    line_number: u64
+}
+
+
+impl TrawReader<Box<dyn BufRead>> {
+    // The traw can be gzipped.
+    pub fn new(filename: &str) -> TrawReader<Box<dyn BufRead>> {
+        TrawReader::from_reader(open_text_file(filename))
+    }
+}
+
+
+impl<R: BufRead> TrawReader<R> {
+    pub fn from_reader(reader: R) -> TrawReader<R> {
+        let mut lines = reader.lines();
+        let header = lines.next()
+            .expect("The traw file is empty.")
+            .expect("Could not read the traw file.");
+
+        let samples = header.split_whitespace()
+            .skip(6)
+            .map(parse_traw_sample)
+            .collect();
+
+        TrawReader {
+            lines,
+            samples: Arc::new(samples),
+            attach_samples: false,
+            line_number: 1
+        }
+    }
+
+    pub fn attach_samples(&mut self, attach: bool) {
+        self.attach_samples = attach;
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    fn _parse_line(&self, line: &str) -> Genotypes {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let n_samples = self.samples.len();
+
+        if fields.len() != 6 + n_samples {
+            panic!("Expected {} columns on line {} of the traw, got {}.",
+                   6 + n_samples, self.line_number, fields.len());
+        }
+
+        let location = format!("line {} of the traw", self.line_number);
+        let position = fields[3].parse().unwrap_or_else(|_| {
+            panic!("Invalid position on {}.", location)
+        });
+
+        let variant = Variant::new(fields[1].to_string(),
+                                   fields[0].to_string(), position,
+                                   (fields[4].to_string(),
+                                    fields[5].to_string()));
+        let calls = fields[6..].iter()
+            .map(|value| parse_call(value, &location))
+            .collect();
+
+        let g = Genotypes::new(variant, calls, fields[4]);
+        if self.attach_samples {
+            g.with_samples(Arc::clone(&self.samples))
+        } else {
+            g
+        }
+    }
+}
+
+
+impl<R: BufRead> Iterator for TrawReader<R> {
+    type Item = Genotypes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in self.lines.by_ref() {
+            let line = line.expect("Could not read the traw.");
+            self.line_number += 1;
+
+            if !line.trim().is_empty() {
+                return Some(self._parse_line(&line));
+            }
+        }
+
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw() {
+        let raw = "FID IID PAT MAT SEX PHENOTYPE rs1_G rs_2_T(/C) rs3_A\n\
+                   f1 s1 0 0 1 -9 0 1 2\n\
+                   f2 s2 0 0 2 -9 NA 2 1\n";
+        let bim = "1\trs1\t0\t100\tG\tA\n1\trs3\t0\t300\tC\tA\n";
+
+        let mut reader = RawReader::from_readers(raw.as_bytes(),
+                                                 Some(bim.as_bytes()));
+        assert_eq!(reader.samples()[1].sex, Sex::Female);
+        assert_eq!(reader.n_variants(), 3);
+
+        let all: Vec<Genotypes> = reader.by_ref().collect();
+        assert_eq!(all[0].variant.position, 100);
+        assert_eq!(all[0].coded_allele(), "G");
+        assert_eq!(all[0].genotypes, vec![Some(0), None]);
+
+        // Not in the BIM, the other allele is in the header.
+        assert_eq!(all[1].variant.name, "rs_2");
+        assert_eq!(all[1].variant.chrom.to_string(), "0");
+        assert_eq!((all[1].coded_allele(), all[1].other_allele()),
+                   ("T", "C"));
+
+        // The counted allele is the second allele of the BIM.
+        assert_eq!(all[2].coded_allele(), "A");
+        let region = reader.get_variants_in_region(
+            &Chromosome::Autosome(1), 200, 400
+        );
+        assert_eq!(region[0].genotypes, vec![Some(2), Some(1)]);
+    }
+
+    #[test]
+    fn test_traw() {
+        let traw = "CHR\tSNP\t(C)M\tPOS\tCOUNTED\tALT\tf1_s1\ts2_s2\ta_b_a_b\n\
+                    1\trs1\t0\t100\tG\tA\t0\t1\tNA\n\
+                    \n\
+                    X\trs2\t0\t200\tT\tC\t2\t2\t0\n";
+
+        let mut reader = TrawReader::from_reader(traw.as_bytes());
+        reader.attach_samples(true);
+
+        let ids: Vec<(&str, &str)> = reader.samples().iter()
+            .map(|s| (s.fid.as_str(), s.iid.as_str()))
+            .collect();
+        assert_eq!(ids, vec![("f1", "s1"), ("s2", "s2"), ("a_b", "a_b")]);
+
+        let all: Vec<Genotypes> = reader.collect();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].coded_allele(), "G");
+        assert_eq!(all[0].genotypes, vec![Some(0), Some(1), None]);
+        assert_eq!(all[1].variant.position, 200);
+        assert_eq!(all[1].other_allele(), "C");
+        assert!(all[1].samples.is_some());
+    }
+}
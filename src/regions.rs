@@ -0,0 +1,357 @@
+/*!
+ * Sets of genomic regions, e.g. the regions to exclude from an analysis.
+ *
+ * The regions are read from BED files (`chrom start end`, 0-based with the
+ * end excluded, other columns ignored), so a variant at the 1-based
+ * `position` is in a region if `start < position <= end`. The chromosomes
+ * are normalized like the variants of the readers.
+ *
+ * `high_ld_regions` are the long-range LD regions of Price et al. (2008),
+ * e.g. the MHC and the inversion of chromosome 8, which are usually
+ * excluded before pruning, computing the GRM or the PCs: the variants of
+ * these regions are correlated over megabases and would dominate the top
+ * components.
+ *
+ * `complex_regions` are the regions with extreme LD and structural
+ * diversity (the MHC and the KIR cluster of the leukocyte receptor
+ * complex), where the association and PRS results are hard to interpret.
+ * They are named (`RegionLabels`) so that the results can be flagged
+ * instead of being excluded.
+ */
+
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use crate::core::{Chromosome, Variant};
+use crate::sexchrom::GenomeBuild;
+use crate::utils::open_text_file;
+
+
+// The long-range LD regions (as `chrom start end`) on GRCh37, and the same
+// regions lifted over to GRCh38.
+const HIGH_LD_GRCH37: [(u8, u32, u32); 24] = [
+    (1, 48_000_000, 52_000_000),
+    (2, 86_000_000, 100_500_000),
+    (2, 134_500_000, 138_000_000),
+    (2, 183_000_000, 190_000_000),
+    (3, 47_500_000, 50_000_000),
+    (3, 83_500_000, 87_000_000),
+    (3, 89_000_000, 97_500_000),
+    (5, 44_500_000, 50_500_000),
+    (5, 98_000_000, 100_500_000),
+    (5, 129_000_000, 132_000_000),
+    (5, 135_500_000, 138_500_000),
+    (6, 25_000_000, 35_000_000),
+    (6, 57_000_000, 64_000_000),
+    (6, 140_000_000, 142_500_000),
+    (7, 55_000_000, 66_000_000),
+    (8, 7_000_000, 13_000_000),
+    (8, 43_000_000, 50_000_000),
+    (8, 112_000_000, 115_000_000),
+    (10, 37_000_000, 43_000_000),
+    (11, 46_000_000, 57_000_000),
+    (11, 87_500_000, 90_500_000),
+    (12, 33_000_000, 40_000_000),
+    (12, 109_500_000, 112_000_000),
+    (20, 32_000_000, 34_500_000)
+];
+
+const HIGH_LD_GRCH38: [(u8, u32, u32); 24] = [
+    (1, 47_534_328, 51_534_328),
+    (2, 85_770_597, 100_129_917),
+    (2, 133_742_429, 137_242_430),
+    (2, 182_135_273, 189_135_274),
+    (3, 47_458_510, 49_962_567),
+    (3, 83_450_849, 86_950_850),
+    (3, 89_006_666, 97_364_689),
+    (5, 44_464_140, 50_464_140),
+    (5, 98_628_438, 101_128_438),
+    (5, 129_664_255, 132_664_255),
+    (5, 136_164_172, 139_164_172),
+    (6, 24_999_772, 35_032_223),
+    (6, 57_224_339, 64_165_963),
+    (6, 139_678_863, 142_178_863),
+    (7, 54_964_811, 65_986_375),
+    (8, 8_142_478, 12_142_491),
+    (8, 43_142_369, 50_045_890),
+    (8, 111_132_026, 114_132_026),
+    (10, 36_961_758, 42_962_144),
+    (11, 45_979_294, 56_979_294),
+    (11, 87_787_962, 90_787_962),
+    (12, 32_959_654, 39_959_654),
+    (12, 109_050_110, 111_550_110),
+    (20, 33_448_144, 35_958_144)
+];
+
+
+// The MHC and the leukocyte receptor complex (LRC, with the KIR genes) as
+// defined by the GRC for the alternate haplotypes.
+const COMPLEX_GRCH37: [(&str, u8, u32, u32); 2] = [
+    ("MHC", 6, 28_477_796, 33_448_354),
+    ("KIR", 19, 54_528_887, 55_595_686)
+];
+
+const COMPLEX_GRCH38: [(&str, u8, u32, u32); 2] = [
+    ("MHC", 6, 28_510_119, 33_480_577),
+    ("KIR", 19, 54_025_633, 55_084_318)
+];
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub chrom: Chromosome,
+    // BED coordinates (0-based, the end is excluded).
+    pub start: u32,
+    pub end: u32
+}
+
+
+#[derive(Clone, Debug, Default)]
+pub struct RegionSet {
+    // Sorted and disjoint intervals of every chromosome.
+    intervals: HashMap<Chromosome, Vec<(u32, u32)>>
+}
+
+impl RegionSet {
+    // The file can be gzipped.
+    pub fn new(filename: &str) -> RegionSet {
+        RegionSet::from_reader(open_text_file(filename))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> RegionSet {
+        RegionSet::from_regions(
+            read_bed(reader).into_iter().map(|(region, _)| region)
+        )
+    }
+
+
+    pub fn from_regions<I>(regions: I) -> RegionSet
+        where I: IntoIterator<Item = Region>
+    {
+        let mut intervals: HashMap<Chromosome, Vec<(u32, u32)>> =
+            HashMap::new();
+        for region in regions {
+            intervals.entry(region.chrom)
+                .or_default()
+                .push((region.start, region.end));
+        }
+
+        // Merge the overlapping regions so that the intervals can be
+        // searched by position.
+        for chrom_intervals in intervals.values_mut() {
+            chrom_intervals.sort_unstable();
+            let mut merged: Vec<(u32, u32)> = Vec::new();
+            for &(start, end) in chrom_intervals.iter() {
+                match merged.last_mut() {
+                    Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                    _ => merged.push((start, end))
+                }
+            }
+            *chrom_intervals = merged;
+        }
+
+        RegionSet { intervals }
+    }
+
+    // Number of (merged) regions.
+    pub fn len(&self) -> usize {
+        self.intervals.values().map(|intervals| intervals.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains_position(&self, chrom: &Chromosome, position: u32)
+        -> bool
+    {
+        let intervals = match self.intervals.get(chrom) {
+            Some(intervals) => intervals,
+            None => return false
+        };
+
+        // The last interval starting before the position.
+        let i = intervals.partition_point(|&(start, _)| start < position);
+        i > 0 && position <= intervals[i - 1].1
+    }
+
+    pub fn contains(&self, v: &Variant) -> bool {
+        self.contains_position(&v.chrom, v.position)
+    }
+}
+
+
+// Named sets of regions, to flag the variants of the results in these
+// regions. A variant can be in the regions of many names.
+#[derive(Clone, Debug, Default)]
+pub struct RegionLabels {
+    sets: Vec<(String, RegionSet)>
+}
+
+impl RegionLabels {
+    // The regions are named by the 4th column of the BED (the regions
+    // without a name are named `region`). The file can be gzipped.
+    pub fn new(filename: &str) -> RegionLabels {
+        RegionLabels::from_reader(open_text_file(filename))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> RegionLabels {
+        let mut by_name: Vec<(String, Vec<Region>)> = Vec::new();
+        for (region, name) in read_bed(reader) {
+            let name = name.unwrap_or_else(|| "region".to_string());
+            match by_name.iter_mut().find(|(other, _)| *other == name) {
+                Some((_, regions)) => regions.push(region),
+                None => by_name.push((name, vec![region]))
+            }
+        }
+
+        let mut labels = RegionLabels::default();
+        for (name, regions) in by_name {
+            labels.push(&name, RegionSet::from_regions(regions));
+        }
+        labels
+    }
+
+    pub fn push(&mut self, name: &str, regions: RegionSet) {
+        self.sets.push((name.to_string(), regions));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    // Names of the regions containing the variant, in the order they were
+    // added.
+    pub fn labels(&self, v: &Variant) -> Vec<&str> {
+        self.sets.iter()
+            .filter(|(_, regions)| regions.contains(v))
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+}
+
+
+// Regions of a BED with their name (4th column), if any. The header lines
+// (`#`, `track` and `browser`) are skipped.
+fn read_bed<R: BufRead>(reader: R) -> Vec<(Region, Option<String>)> {
+    let mut regions = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.expect("Could not read the regions.");
+        if line.trim().is_empty() || line.starts_with('#') ||
+           line.starts_with("track") || line.starts_with("browser")
+        {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 {
+            panic!("Expected at least 3 columns on line {} of the regions, \
+                    got {}.", i + 1, fields.len());
+        }
+
+        let position = |field: &str| -> u32 {
+            field.parse().unwrap_or_else(|_| {
+                panic!("Invalid position `{}` on line {} of the regions.",
+                       field, i + 1)
+            })
+        };
+
+        let region = Region {
+            chrom: Chromosome::new(fields[0]),
+            start: position(fields[1]),
+            end: position(fields[2])
+        };
+        regions.push((region, fields.get(3).map(|name| name.to_string())));
+    }
+
+    regions
+}
+
+
+// Long-range LD regions of the build (see the module documentation).
+pub fn high_ld_regions(build: GenomeBuild) -> RegionSet {
+    let regions = match build {
+        GenomeBuild::Grch37 => &HIGH_LD_GRCH37,
+        GenomeBuild::Grch38 => &HIGH_LD_GRCH38
+    };
+
+    RegionSet::from_regions(regions.iter().map(|&(chrom, start, end)| {
+        Region { chrom: Chromosome::Autosome(chrom), start, end }
+    }))
+}
+
+
+// Complex regions of the build (see the module documentation).
+pub fn complex_regions(build: GenomeBuild) -> RegionLabels {
+    let regions = match build {
+        GenomeBuild::Grch37 => &COMPLEX_GRCH37,
+        GenomeBuild::Grch38 => &COMPLEX_GRCH38
+    };
+
+    let mut labels = RegionLabels::default();
+    for &(name, chrom, start, end) in regions.iter() {
+        let region = Region { chrom: Chromosome::Autosome(chrom), start, end };
+        labels.push(name, RegionSet::from_regions(vec![region]));
+    }
+    labels
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_set() {
+        let bed = "track name=exclude\n\
+                   chr6\t100\t200\tMHC\n\
+                   6\t150\t300\n\
+                   8\t1000\t2000\n";
+        let regions = RegionSet::from_reader(bed.as_bytes());
+        assert_eq!(regions.len(), 2);
+
+        let chr6 = Chromosome::Autosome(6);
+        assert!(!regions.contains_position(&chr6, 100));
+        assert!(regions.contains_position(&chr6, 101));
+        assert!(regions.contains_position(&chr6, 250));
+        assert!(regions.contains_position(&chr6, 300));
+        assert!(!regions.contains_position(&chr6, 301));
+        assert!(!regions.contains_position(&Chromosome::Autosome(7), 150));
+
+        let v = Variant::new("rs1".to_string(), "chr8".to_string(), 1500,
+                             ("A".to_string(), "G".to_string()));
+        assert!(regions.contains(&v));
+    }
+
+    #[test]
+    fn test_high_ld_regions() {
+        let mhc = Chromosome::Autosome(6);
+        for build in [GenomeBuild::Grch37, GenomeBuild::Grch38] {
+            let regions = high_ld_regions(build);
+            assert_eq!(regions.len(), 24);
+            assert!(regions.contains_position(&mhc, 32_000_000));
+            assert!(!regions.contains_position(&mhc, 40_000_000));
+        }
+    }
+
+    #[test]
+    fn test_region_labels() {
+        let v = |chrom: &str, pos: u32| {
+            Variant::new("rs1".to_string(), chrom.to_string(), pos,
+                         ("A".to_string(), "G".to_string()))
+        };
+
+        let labels = complex_regions(GenomeBuild::Grch37);
+        assert_eq!(labels.labels(&v("6", 31_000_000)), vec!["MHC"]);
+        assert_eq!(labels.labels(&v("chr19", 55_000_000)), vec!["KIR"]);
+        assert!(labels.labels(&v("6", 40_000_000)).is_empty());
+
+        let bed = "1\t0\t100\tA\n1\t50\t150\tB\n2\t0\t100\n\
+                   1\t200\t300\tA\n";
+        let labels = RegionLabels::from_reader(bed.as_bytes());
+        assert_eq!(labels.labels(&v("1", 75)), vec!["A", "B"]);
+        assert_eq!(labels.labels(&v("1", 250)), vec!["A"]);
+        assert_eq!(labels.labels(&v("2", 1)), vec!["region"]);
+    }
+}
@@ -0,0 +1,297 @@
+/*!
+ * Round-trip verification of the writers.
+ *
+ * A plink fileset is read, written in one of the supported formats and read
+ * back, and everything that the format can represent is compared: the
+ * samples, the variants (name, position and alleles), the coded allele and
+ * the genotypes. This is a data integrity check that the writers and
+ * readers of this crate agree with each other on a real dataset.
+ *
+ * The formats don't all keep the same sample information: VCF and BGEN only
+ * have a sample identifier (see `vcf_sample_id`) and no sex. Haploid calls
+ * are compared as homozygous diploid calls as BGEN has no hard calls.
+ */
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::str::FromStr;
+
+use crate::bgen::{BgenReader, BgenWriter};
+use crate::convert::GenotypeSink;
+use crate::core::{Genotypes, Sample, Sex};
+use crate::index::{build_index, IndexFormat};
+use crate::plink::{BimReader, PlinkReader, PlinkWriter};
+use crate::vcf::{vcf_sample_id, VcfReader, VcfWriter};
+
+
+// Only the first mismatches are described in the report.
+const MAX_REPORTED_MISMATCHES: usize = 100;
+
+// BGEN probabilities are stored on 16 bits, so the dosages of hard calls
+// are read back within this distance of the calls.
+const HARD_CALL_DISTANCE: f64 = 1e-3;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoundtripFormat {
+    Plink,
+    Vcf,
+    Bgen
+}
+
+impl FromStr for RoundtripFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RoundtripFormat, String> {
+        match s {
+            "plink" => Ok(RoundtripFormat::Plink),
+            "vcf" => Ok(RoundtripFormat::Vcf),
+            "bgen" => Ok(RoundtripFormat::Bgen),
+            _ => Err(format!("Unknown format `{}` (expected plink, vcf or \
+                              bgen).", s))
+        }
+    }
+}
+
+impl RoundtripFormat {
+    // Filename of the written dataset (the prefix for plink).
+    pub fn filename(self, out_prefix: &str) -> String {
+        match self {
+            RoundtripFormat::Plink => out_prefix.to_string(),
+            RoundtripFormat::Vcf => format!("{}.vcf", out_prefix),
+            RoundtripFormat::Bgen => format!("{}.bgen", out_prefix)
+        }
+    }
+
+    // The sample as it is read back from the format.
+    fn stored_sample(self, s: &Sample) -> Sample {
+        let id = match self {
+            RoundtripFormat::Plink => return s.clone(),
+            RoundtripFormat::Vcf => vcf_sample_id(s),
+            RoundtripFormat::Bgen => s.iid.clone()
+        };
+
+        Sample::new(id.clone(), id, Sex::Unknown)
+    }
+}
+
+
+// What is compared for every variant.
+#[derive(PartialEq)]
+struct Record {
+    name: String,
+    chrom: String,
+    position: u32,
+    alleles: (String, String),
+    coded: String,
+    // Digest of the calls, counted as diploid calls.
+    calls: u64
+}
+
+impl Record {
+    fn of(g: &Genotypes) -> Record {
+        let scale = if g.is_haploid() { 2 } else { 1 };
+
+        let mut hasher = DefaultHasher::new();
+        for call in g.genotypes.iter() {
+            call.map(|x| x * scale).hash(&mut hasher);
+        }
+
+        let v = &g.variant;
+        Record {
+            name: v.name.clone(),
+            chrom: v.chrom.to_string(),
+            position: v.position,
+            alleles: v.alleles.clone(),
+            coded: g.coded_allele().to_string(),
+            calls: hasher.finish()
+        }
+    }
+
+    // What differs from the expected record.
+    fn difference(&self, expected: &Record) -> Option<&'static str> {
+        if self.name != expected.name || self.chrom != expected.chrom ||
+           self.position != expected.position
+        {
+            Some("different variant")
+        } else if self.alleles != expected.alleles {
+            Some("different alleles")
+        } else if self.coded != expected.coded {
+            Some("different coded allele")
+        } else if self.calls != expected.calls {
+            Some("different genotypes")
+        } else {
+            None
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoundtripReport {
+    pub n_samples: usize,
+    // Variants read from the input and from the written dataset.
+    pub n_variants: u64,
+    pub n_reread: u64,
+    pub samples_match: bool,
+    pub n_mismatches: u64,
+    // Description of the first mismatching variants.
+    pub mismatches: Vec<String>
+}
+
+impl RoundtripReport {
+    pub fn is_identical(&self) -> bool {
+        self.samples_match && self.n_mismatches == 0 &&
+            self.n_variants == self.n_reread
+    }
+
+    fn add_mismatch(&mut self, description: String) {
+        self.n_mismatches += 1;
+        if self.mismatches.len() < MAX_REPORTED_MISMATCHES {
+            self.mismatches.push(description);
+        }
+    }
+}
+
+
+fn write_records<I, K>(genotypes: I, mut output: K) -> io::Result<Vec<Record>>
+    where I: Iterator<Item = Genotypes>,
+          K: GenotypeSink
+{
+    let mut records = Vec::new();
+
+    for g in genotypes {
+        output.write(&g)?;
+        records.push(Record::of(&g));
+    }
+
+    output.finish()?;
+    Ok(records)
+}
+
+
+// Read the plink fileset `prefix`, write it to `out_prefix` in the given
+// format and read it back. The written files are left in place.
+pub fn verify_roundtrip(prefix: &str, format: RoundtripFormat,
+                        out_prefix: &str) -> io::Result<RoundtripReport>
+{
+    // Contigs of the VCF header, in order of appearance.
+    let mut contigs: Vec<String> = Vec::new();
+    for v in BimReader::new(&format!("{}.bim", prefix))? {
+        let v = v?;
+        let chrom = v.variant.chrom.to_string();
+        if !contigs.contains(&chrom) {
+            contigs.push(chrom);
+        }
+    }
+
+    let reader = PlinkReader::new(prefix)?;
+    let samples = reader.samples().to_vec();
+    let filename = format.filename(out_prefix);
+
+    let expected = match format {
+        RoundtripFormat::Plink => {
+            write_records(reader, PlinkWriter::new(out_prefix, &samples)?)?
+        },
+        RoundtripFormat::Vcf => {
+            let contigs: Vec<&str> = contigs.iter()
+                .map(|c| c.as_str())
+                .collect();
+            write_records(reader, VcfWriter::create(&filename, &samples,
+                                                    &contigs, false)?)?
+        },
+        RoundtripFormat::Bgen => {
+            write_records(reader, BgenWriter::create(&filename, &samples)?)?
+        }
+    };
+
+    let (reread_samples, records): (Vec<Sample>, Box<dyn Iterator<Item = _>>)
+        = match format
+    {
+        RoundtripFormat::Plink => {
+            build_index(out_prefix, IndexFormat::V2)?;
+            let reader = PlinkReader::new(out_prefix)?;
+            (reader.samples().to_vec(),
+             Box::new(reader.map(|g| Record::of(&g))))
+        },
+        RoundtripFormat::Vcf => {
+            let reader = VcfReader::new(&filename);
+            (reader.samples().to_vec(),
+             Box::new(reader.map(|g| Record::of(&g))))
+        },
+        RoundtripFormat::Bgen => {
+            let reader = BgenReader::new(&filename);
+            (reader.samples().to_vec(),
+             Box::new(reader.map(|d| {
+                 Record::of(&d.to_hard_calls(HARD_CALL_DISTANCE))
+             })))
+        }
+    };
+
+    let mut report = RoundtripReport {
+        n_samples: samples.len(),
+        n_variants: expected.len() as u64,
+        samples_match: samples.iter()
+            .map(|s| format.stored_sample(s))
+            .eq(reread_samples),
+        ..Default::default()
+    };
+
+    for record in records {
+        if let Some(e) = expected.get(report.n_reread as usize) {
+            if let Some(difference) = record.difference(e) {
+                report.add_mismatch(format!("{} ({}:{}): {}", e.name, e.chrom,
+                                            e.position, difference));
+            }
+        }
+        report.n_reread += 1;
+    }
+
+    Ok(report)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Variant;
+
+    #[test]
+    fn test_verify_roundtrip() {
+        assert_eq!("vcf".parse(), Ok(RoundtripFormat::Vcf));
+        assert!("pgen".parse::<RoundtripFormat>().is_err());
+
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_roundtrip_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let samples = vec![
+            Sample::new("f1".to_string(), "s1".to_string(), Sex::Male),
+            Sample::new("s2".to_string(), "s2".to_string(), Sex::Female)
+        ];
+
+        let mut writer = PlinkWriter::new(&prefix("in"), &samples).unwrap();
+        for (i, chrom) in ["1", "1", "2"].iter().enumerate() {
+            let v = Variant::new(format!("rs{}", i), chrom.to_string(),
+                                 100 * (i as u32 + 1),
+                                 ("A".to_string(), "G".to_string()));
+            let calls = vec![Some(i as u8), None];
+            writer.write(&Genotypes::new(v, calls, "G")).unwrap();
+        }
+        writer.finish().unwrap();
+        build_index(&prefix("in"), IndexFormat::V2).unwrap();
+
+        for format in [RoundtripFormat::Plink, RoundtripFormat::Vcf,
+                       RoundtripFormat::Bgen].iter()
+        {
+            let report = verify_roundtrip(&prefix("in"), *format,
+                                          &prefix("out")).unwrap();
+            assert!(report.is_identical(), "{:?}: {:?}", format, report);
+            assert_eq!(report.n_variants, 3);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+/*!
+ * Reproducible random downsampling of variants and samples.
+ *
+ * All the functions take a seed so that the same subset is selected when
+ * they are called again on the same data (e.g. to thin an LD reference
+ * panel consistently across runs). The subset of a plink fileset can also be
+ * written as another fileset (see `write_subset_fileset`).
+ */
+
+use std::io;
+use std::str::FromStr;
+
+use rand::{Rng, SeedableRng};
+use rand::seq::index;
+use rand_chacha::ChaCha8Rng;
+
+use crate::core::{Genotypes, Sample};
+use crate::plink::{PlinkReader, PlinkWriter};
+
+
+// Variants kept in a subset fileset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VariantSelection {
+    All,
+    // Every variant is kept with this probability.
+    Fraction(f64),
+    // Exactly this number of variants.
+    Count(usize)
+}
+
+impl FromStr for VariantSelection {
+    type Err = String;
+
+    // `all`, a fraction (e.g. `0.1`) or a number of variants (e.g. `1000`).
+    fn from_str(s: &str) -> Result<VariantSelection, String> {
+        if s == "all" {
+            return Ok(VariantSelection::All);
+        }
+
+        if let Ok(n) = s.parse::<usize>() {
+            return Ok(VariantSelection::Count(n));
+        }
+
+        match s.parse::<f64>() {
+            Ok(fraction) if (0.0..=1.0).contains(&fraction) => {
+                Ok(VariantSelection::Fraction(fraction))
+            },
+            _ => Err(format!("Unknown variant selection `{}` (expected all, \
+                              a fraction or a number of variants).", s))
+        }
+    }
+}
+
+
+// Keep every variant independently with probability `fraction`.
+pub fn thin_variants_by_fraction<I>(genotypes: I, fraction: f64, seed: u64)
+    -> impl Iterator<Item = Genotypes>
+    where I: IntoIterator<Item = Genotypes>
+{
+    assert!((0.0..=1.0).contains(&fraction),
+            "The fraction of variants to keep should be in [0, 1].");
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+    genotypes.into_iter()
+        .filter(move |_| rng.gen_bool(fraction))
+}
+
+
+// Keep exactly `n_keep` variants out of the `n_total` in the iterator.
+//
+// This uses selection sampling (Knuth's algorithm S) so the variants are
+// streamed in their original order without being buffered. If the iterator
+// doesn't yield `n_total` variants, fewer than `n_keep` could be returned.
+pub fn thin_variants_to_count<I>(genotypes: I, n_total: usize, n_keep: usize,
+                                 seed: u64)
+    -> impl Iterator<Item = Genotypes>
+    where I: IntoIterator<Item = Genotypes>
+{
+    assert!(n_keep <= n_total,
+            "Can't keep {} variants out of {}.", n_keep, n_total);
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut n_seen = 0;
+    let mut n_selected = 0;
+
+    genotypes.into_iter()
+        .filter(move |_| {
+            let n_left = n_total.saturating_sub(n_seen);
+            n_seen += 1;
+
+            if n_left == 0 {
+                return false;
+            }
+
+            let keep = rng.gen_range(0..n_left) < n_keep - n_selected;
+            if keep {
+                n_selected += 1;
+            }
+
+            keep
+        })
+}
+
+
+// Randomly choose `n_keep` sample indices out of `n_samples`. The indices are
+// returned sorted.
+pub fn sample_subset(n_samples: usize, n_keep: usize, seed: u64)
+    -> Vec<usize>
+{
+    assert!(n_keep <= n_samples,
+            "Can't keep {} samples out of {}.", n_keep, n_samples);
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut indices = index::sample(&mut rng, n_samples, n_keep).into_vec();
+    indices.sort_unstable();
+
+    indices
+}
+
+
+// Restrict every Genotypes to the given sample indices (see `sample_subset`).
+pub fn subset_samples<I>(genotypes: I, indices: Vec<usize>)
+    -> impl Iterator<Item = Genotypes>
+    where I: IntoIterator<Item = Genotypes>
+{
+    genotypes.into_iter().map(move |g| g.subset(&indices))
+}
+
+
+// Write the selected variants of a fileset for `n_samples` random samples
+// (all of them if None) as another fileset. Returns the number of variants
+// written.
+pub fn write_subset_fileset(reader: PlinkReader, out: &str,
+                            variants: VariantSelection,
+                            n_samples: Option<usize>, seed: u64)
+    -> io::Result<u32>
+{
+    let n_total = reader.n_variants() as usize;
+    let all_samples = reader.samples().len();
+
+    let n_keep = n_samples.unwrap_or(all_samples);
+    if n_keep > all_samples {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Can't keep {} samples out of {}.", n_keep, all_samples)
+        ));
+    }
+    if let VariantSelection::Count(n) = variants {
+        if n > n_total {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Can't keep {} variants out of {}.", n, n_total)
+            ));
+        }
+    }
+
+    let indices = sample_subset(all_samples, n_keep, seed);
+    let samples: Vec<Sample> = indices.iter()
+        .map(|&i| reader.samples()[i].clone())
+        .collect();
+
+    let selected: Box<dyn Iterator<Item = Genotypes>> = match variants {
+        VariantSelection::All => Box::new(reader),
+        VariantSelection::Fraction(fraction) => {
+            Box::new(thin_variants_by_fraction(reader, fraction, seed))
+        },
+        VariantSelection::Count(n) => {
+            Box::new(thin_variants_to_count(reader, n_total, n, seed))
+        }
+    };
+
+    PlinkWriter::new(out, &samples)?
+        .write_all(subset_samples(selected, indices))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Variant;
+
+    fn get_genotypes(n: u32) -> Vec<Genotypes> {
+        (1..=n)
+            .map(|pos| {
+                let v = Variant::new(
+                    format!("v{}", pos),
+                    "1".to_string(),
+                    pos,
+                    ("A".to_string(), "G".to_string())
+                );
+
+                Genotypes::new(v, vec![Some(0), Some(1), Some(2), None], "G")
+            })
+            .collect()
+    }
+
+    fn positions<I: Iterator<Item = Genotypes>>(iter: I) -> Vec<u32> {
+        iter.map(|g| g.variant.position).collect()
+    }
+
+    #[test]
+    fn test_thin_to_count() {
+        let thinned = positions(
+            thin_variants_to_count(get_genotypes(100), 100, 10, 3)
+        );
+
+        assert_eq!(thinned.len(), 10);
+
+        // The order is preserved and the selection is reproducible.
+        assert!(thinned.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(thinned, positions(
+            thin_variants_to_count(get_genotypes(100), 100, 10, 3)
+        ));
+        assert_ne!(thinned, positions(
+            thin_variants_to_count(get_genotypes(100), 100, 10, 4)
+        ));
+
+        assert_eq!(
+            thin_variants_to_count(get_genotypes(5), 5, 5, 1).count(), 5
+        );
+    }
+
+    #[test]
+    fn test_thin_by_fraction() {
+        let n = thin_variants_by_fraction(get_genotypes(1000), 0.2, 1).count();
+        assert!(n > 150 && n < 250);
+
+        assert_eq!(
+            thin_variants_by_fraction(get_genotypes(10), 0.0, 1).count(), 0
+        );
+    }
+
+    #[test]
+    fn test_write_subset_fileset() {
+        use crate::core::Sex;
+        use std::fs::File;
+
+        let prefix = std::env::temp_dir()
+            .join(format!("genepa_sampling_{}", std::process::id()));
+        let prefix = prefix.to_str().unwrap().to_string();
+        let samples: Vec<Sample> = ["s1", "s2", "s3", "s4"].iter()
+            .map(|iid| Sample::new(iid.to_string(), iid.to_string(),
+                                   Sex::Unknown))
+            .collect();
+        PlinkWriter::new(&prefix, &samples).unwrap()
+            .write_all(get_genotypes(20))
+            .unwrap();
+
+        let open = |prefix: &str| {
+            let open = |ext: &str| {
+                File::open(format!("{}.{}", prefix, ext)).unwrap()
+            };
+            PlinkReader::from_readers(open("bed"), open("bim"), open("fam"))
+                .unwrap()
+        };
+
+        let out = format!("{}_subset", prefix);
+        let n = write_subset_fileset(open(&prefix), &out,
+                                     VariantSelection::Count(5), Some(2), 3)
+            .unwrap();
+        assert_eq!(n, 5);
+
+        let subset = open(&out);
+        let indices = sample_subset(4, 2, 3);
+        let iids: Vec<&str> = subset.samples().iter()
+            .map(|s| s.iid.as_str())
+            .collect();
+        assert_eq!(iids, indices.iter()
+            .map(|&i| samples[i].iid.as_str())
+            .collect::<Vec<_>>());
+
+        let expected = positions(
+            thin_variants_to_count(get_genotypes(20), 20, 5, 3)
+        );
+        let written: Vec<Genotypes> = subset.collect();
+        assert_eq!(positions(written.iter().cloned()), expected);
+        assert_eq!(written[0].genotypes,
+                   get_genotypes(1)[0].subset(&indices).genotypes);
+
+        assert!(write_subset_fileset(open(&prefix), &out,
+                                     VariantSelection::All, Some(5), 1)
+            .is_err());
+        assert_eq!("0.5".parse(), Ok(VariantSelection::Fraction(0.5)));
+        assert_eq!("all".parse(), Ok(VariantSelection::All));
+        assert!("2.5".parse::<VariantSelection>().is_err());
+
+        for p in &[&prefix, &out] {
+            for ext in &["bed", "bim", "fam"] {
+                std::fs::remove_file(format!("{}.{}", p, ext)).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_subset_samples() {
+        let indices = sample_subset(4, 2, 7);
+        assert_eq!(indices.len(), 2);
+        assert!(indices[0] < indices[1]);
+        assert_eq!(indices, sample_subset(4, 2, 7));
+
+        let subset: Vec<Genotypes> = subset_samples(
+            get_genotypes(3), indices.clone()
+        ).collect();
+
+        let full = get_genotypes(1).remove(0);
+        assert_eq!(subset[0].genotypes, vec![
+            full.genotypes[indices[0]], full.genotypes[indices[1]]
+        ]);
+    }
+}
@@ -0,0 +1,475 @@
+/*!
+ * Polygenic risk scores.
+ *
+ * The scorer is fed genotypes one variant at a time (e.g. from a reader) and
+ * accumulates the weighted sum of effect allele counts for every sample.
+ */
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use ndarray::{Array1, Array2, Axis, stack};
+
+use crate::core::{Genotypes, Variant, complement};
+use crate::linalg;
+use crate::stats;
+
+
+#[derive(Clone, Debug)]
+pub struct ScoreWeight {
+    pub variant: Variant,
+    pub effect_allele: String,
+    pub beta: f64
+}
+
+
+// Contribution of a single variant to the score of a sample.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contribution {
+    pub variant: Variant,
+    pub effect_allele: String,
+    // Number of effect alleles carried by the sample (None if missing).
+    pub n_effect_alleles: Option<u8>,
+    pub beta: f64,
+    pub contribution: f64
+}
+
+
+pub struct Scorer {
+    weights: HashMap<Variant, (String, f64)>,
+    scores: Vec<f64>,
+    // Number of non-missing variants used for every sample.
+    counts: Vec<u32>,
+    explain_sample: Option<usize>,
+    contributions: Vec<Contribution>
+}
+
+
+impl Scorer {
+    pub fn new(weights: Vec<ScoreWeight>) -> Scorer {
+        let weights = weights
+            .into_iter()
+            .map(|w| {
+                let effect = w.effect_allele.to_uppercase();
+                (w.variant, (effect, w.beta))
+            })
+            .collect();
+
+        Scorer {
+            weights,
+            scores: Vec::new(),
+            counts: Vec::new(),
+            explain_sample: None,
+            contributions: Vec::new()
+        }
+    }
+
+    // Record the contribution of every variant for the sample at the given
+    // index.
+    pub fn explain_sample(&mut self, sample_index: usize) {
+        self.explain_sample = Some(sample_index);
+    }
+
+    // Add the genotypes of a variant to the scores. Variants that are not
+    // part of the score are ignored. Returns true if the variant was used.
+    pub fn add(&mut self, g: &Genotypes) -> bool {
+        let (effect, beta) = match self.weights.get(&g.variant) {
+            Some((effect, beta)) => (effect.clone(), *beta),
+            None => return false
+        };
+
+        if self.scores.is_empty() {
+            self.scores = vec![0.0; g.genotypes.len()];
+            self.counts = vec![0; g.genotypes.len()];
+        }
+
+        if self.scores.len() != g.genotypes.len() {
+            panic!("Expected {} samples but `{}` has {} genotypes.",
+                   self.scores.len(), g.variant, g.genotypes.len());
+        }
+
+        let effect_is_coded = effect_is_coded(g, &effect);
+        let ploidy = g.ploidy();
+
+        let n_effect = |geno: &Option<u8>| {
+            geno.map(|x| if effect_is_coded { x } else { ploidy - x })
+        };
+
+        for (i, geno) in g.genotypes.iter().enumerate() {
+            if let Some(x) = n_effect(geno) {
+                self.scores[i] += f64::from(x) * beta;
+                self.counts[i] += 1;
+            }
+        }
+
+        if let Some(i) = self.explain_sample {
+            let n_effect_alleles = n_effect(&g.genotypes[i]);
+
+            self.contributions.push(Contribution {
+                variant: g.variant.clone(),
+                effect_allele: effect,
+                n_effect_alleles,
+                beta,
+                contribution: f64::from(n_effect_alleles.unwrap_or(0)) * beta
+            });
+        }
+
+        true
+    }
+
+    pub fn scores(&self) -> &[f64] {
+        &self.scores
+    }
+
+    pub fn counts(&self) -> &[u32] {
+        &self.counts
+    }
+
+    // Contributions for the explained sample, sorted by decreasing absolute
+    // effect.
+    pub fn explanation(&self) -> Vec<Contribution> {
+        let mut contributions = self.contributions.clone();
+        contributions.sort_by(|a, b| {
+            b.contribution.abs().partial_cmp(&a.contribution.abs()).unwrap()
+        });
+
+        contributions
+    }
+
+    pub fn write_explanation<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "name\tchrom\tpos\teffect_allele\tn_effect_alleles\t\
+                       beta\tcontribution")?;
+
+        for c in self.explanation() {
+            let n_effect = match c.n_effect_alleles {
+                Some(x) => x.to_string(),
+                None => "NA".to_string()
+            };
+
+            writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}", c.variant.name,
+                     c.variant.chrom, c.variant.position, c.effect_allele,
+                     n_effect, c.beta, c.contribution)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+// Whether the effect allele is the coded allele, taking strand flips into
+// account.
+fn effect_is_coded(g: &Genotypes, effect: &str) -> bool {
+    let effect = effect.to_string();
+
+    if g.coded_allele() == effect {
+        true
+    } else if g.other_allele() == effect {
+        false
+    } else if g.coded_allele() == complement(&effect) {
+        true
+    } else if g.other_allele() == complement(&effect) {
+        false
+    } else {
+        panic!("Effect allele `{}` is not an allele of `{}`", effect,
+               g.variant);
+    }
+}
+
+
+/*
+ * Evaluation of computed scores against a phenotype.
+ */
+
+#[derive(Debug)]
+pub struct IncrementalR2 {
+    // Covariates only.
+    pub r2_base: f64,
+    // Covariates and score.
+    pub r2_full: f64,
+    pub delta: f64
+}
+
+
+// Variance explained by the score on top of the covariates (linear model,
+// an intercept is added to the covariates).
+pub fn incremental_r2(scores: &[f64], phenotype: &[f64],
+                      covariates: &Array2<f64>) -> Option<IncrementalR2>
+{
+    assert_eq!(scores.len(), phenotype.len());
+    assert_eq!(scores.len(), covariates.rows());
+
+    let y = Array1::from_vec(phenotype.to_vec());
+    let base = linalg::with_intercept(covariates);
+
+    let score_col = Array2::from_shape_vec((scores.len(), 1), scores.to_vec())
+        .unwrap();
+    let full = stack(Axis(1), &[base.view(), score_col.view()]).unwrap();
+
+    let r2_base = linalg::r_squared(&base, &y)?;
+    let r2_full = linalg::r_squared(&full, &y)?;
+
+    Some(IncrementalR2 { r2_base, r2_full, delta: r2_full - r2_base })
+}
+
+
+#[derive(Debug)]
+pub struct Auc {
+    pub auc: f64,
+    // DeLong standard error.
+    pub se: f64,
+    pub ci_low: f64,
+    pub ci_high: f64
+}
+
+
+// Area under the ROC curve with a DeLong confidence interval at the given
+// level (e.g. 0.95).
+pub fn auc(scores: &[f64], is_case: &[bool], level: f64) -> Auc {
+    assert_eq!(scores.len(), is_case.len());
+
+    let cases: Vec<f64> = scores.iter().zip(is_case)
+        .filter(|(_, &case)| case)
+        .map(|(s, _)| *s)
+        .collect();
+
+    let controls: Vec<f64> = scores.iter().zip(is_case)
+        .filter(|(_, &case)| !case)
+        .map(|(s, _)| *s)
+        .collect();
+
+    let m = cases.len();
+    let n = controls.len();
+    assert!(m > 1 && n > 1, "Need at least two cases and two controls.");
+
+    let psi = |case: f64, control: f64| {
+        if case > control { 1.0 } else if case == control { 0.5 } else { 0.0 }
+    };
+
+    // Structural components (placement values).
+    let v10: Vec<f64> = cases.iter()
+        .map(|&x| controls.iter().map(|&y| psi(x, y)).sum::<f64>() / n as f64)
+        .collect();
+
+    let v01: Vec<f64> = controls.iter()
+        .map(|&y| cases.iter().map(|&x| psi(x, y)).sum::<f64>() / m as f64)
+        .collect();
+
+    let auc = v10.iter().sum::<f64>() / m as f64;
+
+    let variance = |v: &[f64]| {
+        v.iter().map(|x| (x - auc).powi(2)).sum::<f64>() / (v.len() - 1) as f64
+    };
+
+    let se = (variance(&v10) / m as f64 + variance(&v01) / n as f64).sqrt();
+    let z = stats::normal_quantile(0.5 + level / 2.0);
+
+    Auc {
+        auc,
+        se,
+        ci_low: (auc - z * se).max(0.0),
+        ci_high: (auc + z * se).min(1.0)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct DecileOddsRatio {
+    // 1 to 10, the first decile is the reference.
+    pub decile: usize,
+    pub n_cases: u32,
+    pub n_controls: u32,
+    pub odds_ratio: f64,
+    pub ci_low: f64,
+    pub ci_high: f64
+}
+
+
+// Odds ratios (with 95% Woolf confidence intervals) of every score decile
+// compared to the first. A 0.5 correction is applied to the counts when a
+// cell is empty.
+pub fn decile_odds_ratios(scores: &[f64], is_case: &[bool])
+    -> Vec<DecileOddsRatio>
+{
+    assert_eq!(scores.len(), is_case.len());
+    let n = scores.len();
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| scores[i].partial_cmp(&scores[j]).unwrap());
+
+    let mut counts = [(0u32, 0u32); 10];
+    for (rank, &i) in order.iter().enumerate() {
+        let decile = rank * 10 / n;
+        if is_case[i] {
+            counts[decile].0 += 1;
+        } else {
+            counts[decile].1 += 1;
+        }
+    }
+
+    let (ref_cases, ref_controls) = counts[0];
+    let z = stats::normal_quantile(0.975);
+
+    counts.iter()
+        .enumerate()
+        .map(|(d, &(n_cases, n_controls))| {
+            let mut cells = [n_cases as f64, n_controls as f64,
+                             ref_cases as f64, ref_controls as f64];
+
+            if cells.contains(&0.0) {
+                cells.iter_mut().for_each(|x| *x += 0.5);
+            }
+
+            let log_or = (cells[0] * cells[3] / (cells[1] * cells[2])).ln();
+            let se = cells.iter().map(|x| 1.0 / x).sum::<f64>().sqrt();
+
+            DecileOddsRatio {
+                decile: d + 1,
+                n_cases,
+                n_controls,
+                odds_ratio: log_or.exp(),
+                ci_low: (log_or - z * se).exp(),
+                ci_high: (log_or + z * se).exp()
+            }
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(name: &str, pos: u32) -> Variant {
+        Variant::new(
+            name.to_string(),
+            "1".to_string(),
+            pos,
+            ("A".to_string(), "G".to_string())
+        )
+    }
+
+    fn get_scorer() -> Scorer {
+        Scorer::new(vec![
+            ScoreWeight {
+                variant: variant("rs1", 1),
+                effect_allele: "G".to_string(),
+                beta: 0.1
+            },
+            ScoreWeight {
+                variant: variant("rs2", 2),
+                effect_allele: "a".to_string(),
+                beta: -0.5
+            }
+        ])
+    }
+
+    #[test]
+    fn test_scores() {
+        let mut scorer = get_scorer();
+
+        let g1 = Genotypes::new(variant("rs1", 1),
+                                vec![Some(2), Some(1), None], "G");
+        let g2 = Genotypes::new(variant("rs2", 2),
+                                vec![Some(0), Some(2), Some(1)], "G");
+        let g3 = Genotypes::new(variant("rs3", 3),
+                                vec![Some(0), Some(2), Some(1)], "G");
+
+        assert!(scorer.add(&g1));
+        assert!(scorer.add(&g2));
+        assert!(!scorer.add(&g3));
+
+        // The effect allele for rs2 is not the coded allele.
+        let expected = [0.2 - 1.0, 0.1, -0.5];
+        for (obs, exp) in scorer.scores().iter().zip(expected.iter()) {
+            assert!((obs - exp).abs() < 1e-12);
+        }
+
+        assert_eq!(scorer.counts(), &[2, 2, 1]);
+    }
+
+    #[test]
+    fn test_haploid_scores() {
+        let y = |name: &str, pos: u32| {
+            Variant::new(name.to_string(), "Y".to_string(), pos,
+                         ("A".to_string(), "G".to_string()))
+        };
+        let weight = |variant: Variant, effect_allele: &str| ScoreWeight {
+            variant, effect_allele: effect_allele.to_string(), beta: 1.0
+        };
+        let mut scorer = Scorer::new(vec![weight(y("rs1", 1), "A"),
+                                          weight(y("rs2", 2), "G")]);
+
+        // Haploid calls count 0 or 1 effect allele.
+        let g1 = Genotypes::new(y("rs1", 1), vec![Some(1), Some(0)], "G")
+            .with_ploidy(1);
+        let g2 = Genotypes::new(y("rs2", 2), vec![Some(1), None], "G")
+            .with_ploidy(1);
+        assert!(scorer.add(&g1));
+        assert!(scorer.add(&g2));
+
+        assert_eq!(scorer.scores(), &[1.0, 1.0]);
+        assert_eq!(scorer.counts(), &[2, 1]);
+    }
+
+    #[test]
+    fn test_explanation() {
+        let mut scorer = get_scorer();
+        scorer.explain_sample(0);
+
+        scorer.add(&Genotypes::new(variant("rs1", 1),
+                                   vec![Some(1), Some(1)], "G"));
+        scorer.add(&Genotypes::new(variant("rs2", 2),
+                                   vec![Some(1), Some(1)], "G"));
+
+        let explanation = scorer.explanation();
+        assert_eq!(explanation.len(), 2);
+        assert_eq!(explanation[0].variant.name, "rs2");
+        assert_eq!(explanation[0].n_effect_alleles, Some(1));
+        assert_eq!(explanation[0].contribution, -0.5);
+        assert_eq!(explanation[1].contribution, 0.1);
+    }
+
+    #[test]
+    fn test_incremental_r2() {
+        let covar = Array2::from_shape_vec(
+            (6, 1), vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]
+        ).unwrap();
+        let scores = [1.0, 0.0, 1.0, 0.0, 1.0, 0.0];
+        let y: Vec<f64> = scores.iter()
+            .zip(covar.iter())
+            .map(|(s, c)| s + c)
+            .collect();
+
+        let r2 = incremental_r2(&scores, &y, &covar).unwrap();
+        assert!((r2.r2_full - 1.0).abs() < 1e-9);
+        assert!(r2.r2_base < 1.0);
+        assert!((r2.delta - (1.0 - r2.r2_base)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_auc() {
+        let scores = [0.9, 0.8, 0.4, 0.7, 0.3, 0.2];
+        let is_case = [true, true, true, false, false, false];
+
+        let res = auc(&scores, &is_case, 0.95);
+        assert!((res.auc - 8.0 / 9.0).abs() < 1e-12);
+
+        // Both structural components have a variance of 1/27.
+        let expected_se = (2.0 / 81.0_f64).sqrt();
+        assert!((res.se - expected_se).abs() < 1e-12);
+        assert_eq!(res.ci_high, 1.0);
+        assert!(res.ci_low < res.auc);
+    }
+
+    #[test]
+    fn test_decile_odds_ratios() {
+        let scores: Vec<f64> = (0..100).map(f64::from).collect();
+        let is_case: Vec<bool> = (0..100).map(|i| i % 10 < i / 10).collect();
+
+        let ors = decile_odds_ratios(&scores, &is_case);
+        assert_eq!(ors.len(), 10);
+        assert_eq!(ors[0].odds_ratio, 1.0);
+        assert_eq!((ors[5].n_cases, ors[5].n_controls), (5, 5));
+        assert!(ors[9].odds_ratio > ors[5].odds_ratio);
+    }
+}
@@ -0,0 +1,798 @@
+/*!
+ * gRPC server and client of the genotype queries (requires the `server`
+ * feature).
+ *
+ * A single indexed host can serve the region and variant queries of a
+ * GenotypeSource (e.g. a large plink fileset) to many analysis clients. The
+ * service is `genepa.Genotypes` of `proto/genepa.proto` (the messages below
+ * are the prost types of its messages), so clients in other languages can
+ * be generated from it.
+ *
+ * Region queries are paginated like `get_variants_in_region_page`, and the
+ * calls of the genotypes are sent as bytes (the number of copies of the
+ * coded allele, or `MISSING_CALL`).
+ *
+ * The readers are not thread-safe, so the source is opened and queried on
+ * a dedicated thread: the queries are answered one at a time. A query that
+ * panics (e.g. a truncated BED) returns an internal error but the source
+ * keeps serving the other queries. The client is synchronous, like the
+ * readers.
+ */
+
+use std::convert::Infallible;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+
+use tokio::runtime::Runtime;
+use tokio::sync::oneshot;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError};
+use tonic::transport::Channel;
+use tonic::Status;
+
+use crate::core::{Chromosome, Genotypes, Sample, Sex, Variant};
+use crate::source::{GenotypeSource, RegionPage};
+use crate::utils::compute_ld;
+
+
+pub const SERVICE_NAME: &str = "genepa.Genotypes";
+
+// Call of the samples without a genotype.
+pub const MISSING_CALL: u8 = 3;
+
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SamplesRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SampleMessage {
+    #[prost(string, tag = "1")]
+    pub fid: String,
+    #[prost(string, tag = "2")]
+    pub iid: String,
+    // Plink sex code.
+    #[prost(string, tag = "3")]
+    pub sex: String
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SamplesReply {
+    #[prost(message, repeated, tag = "1")]
+    pub samples: Vec<SampleMessage>
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct VariantMessage {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub chrom: String,
+    #[prost(uint32, tag = "3")]
+    pub position: u32,
+    #[prost(string, tag = "4")]
+    pub allele1: String,
+    #[prost(string, tag = "5")]
+    pub allele2: String
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GenotypesMessage {
+    #[prost(message, optional, tag = "1")]
+    pub variant: Option<VariantMessage>,
+    #[prost(string, tag = "2")]
+    pub coded_allele: String,
+    #[prost(bytes = "vec", tag = "3")]
+    pub calls: Vec<u8>,
+    #[prost(uint32, tag = "4")]
+    pub ploidy: u32
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RegionRequest {
+    #[prost(string, tag = "1")]
+    pub chrom: String,
+    #[prost(uint32, tag = "2")]
+    pub start: u32,
+    #[prost(uint32, tag = "3")]
+    pub end: u32,
+    #[prost(uint64, tag = "4")]
+    pub offset: u64,
+    #[prost(uint64, tag = "5")]
+    pub limit: u64
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RegionReply {
+    #[prost(message, repeated, tag = "1")]
+    pub genotypes: Vec<GenotypesMessage>,
+    #[prost(uint64, optional, tag = "2")]
+    pub next_offset: Option<u64>
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct VariantReply {
+    // Not set if the variant is not in the source.
+    #[prost(message, optional, tag = "1")]
+    pub genotypes: Option<GenotypesMessage>
+}
+
+// MAF of the variants of a page of the region.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MafReply {
+    #[prost(message, repeated, tag = "1")]
+    pub variants: Vec<VariantMessage>,
+    #[prost(double, repeated, tag = "2")]
+    pub mafs: Vec<f64>,
+    #[prost(uint64, optional, tag = "3")]
+    pub next_offset: Option<u64>
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LdRequest {
+    #[prost(message, optional, tag = "1")]
+    pub variant: Option<VariantMessage>,
+    #[prost(message, repeated, tag = "2")]
+    pub others: Vec<VariantMessage>
+}
+
+// r2 with every other variant (NaN if it is not in the source, see
+// `compute_ld`).
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LdReply {
+    #[prost(double, repeated, tag = "1")]
+    pub r2: Vec<f64>
+}
+
+
+impl From<&Variant> for VariantMessage {
+    fn from(v: &Variant) -> VariantMessage {
+        VariantMessage {
+            name: v.name.clone(),
+            chrom: v.chrom.to_string(),
+            position: v.position,
+            allele1: v.alleles.0.clone(),
+            allele2: v.alleles.1.clone()
+        }
+    }
+}
+
+impl From<&VariantMessage> for Variant {
+    fn from(v: &VariantMessage) -> Variant {
+        Variant::new(v.name.clone(), v.chrom.clone(), v.position,
+                     (v.allele1.clone(), v.allele2.clone()))
+    }
+}
+
+impl From<&Genotypes> for GenotypesMessage {
+    fn from(g: &Genotypes) -> GenotypesMessage {
+        GenotypesMessage {
+            variant: Some(VariantMessage::from(&g.variant)),
+            coded_allele: g.coded_allele().to_string(),
+            calls: g.genotypes.iter()
+                .map(|call| call.unwrap_or(MISSING_CALL))
+                .collect(),
+            ploidy: u32::from(g.ploidy())
+        }
+    }
+}
+
+impl GenotypesMessage {
+    // The messages come from the network, so they are checked before
+    // building the genotypes (which assume a valid coded allele and calls).
+    pub fn to_genotypes(&self) -> io::Result<Genotypes> {
+        let variant = self.variant.as_ref()
+            .map(Variant::from)
+            .ok_or_else(|| invalid_data("Genotypes without a variant."))?;
+
+        let coded = self.coded_allele.to_uppercase();
+        if coded != variant.alleles.0 && coded != variant.alleles.1 {
+            return Err(invalid_data(&format!(
+                "Coded allele `{}` is not an allele of `{}`.",
+                self.coded_allele, variant
+            )));
+        }
+
+        let ploidy = match self.ploidy {
+            1 => 1,
+            2 => 2,
+            ploidy => return Err(invalid_data(&format!(
+                "Invalid ploidy {} of `{}`.", ploidy, variant
+            )))
+        };
+
+        let calls = self.calls.iter()
+            .map(|&call| match call {
+                MISSING_CALL => Ok(None),
+                call if call <= ploidy => Ok(Some(call)),
+                call => Err(invalid_data(&format!(
+                    "Invalid call {} of `{}` with a ploidy of {}.", call,
+                    variant, ploidy
+                )))
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Genotypes::new(variant, calls, &self.coded_allele)
+            .with_ploidy(ploidy))
+    }
+}
+
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+
+fn page_request(request: &RegionRequest) -> (Chromosome, u32, u32, usize,
+                                              usize)
+{
+    (Chromosome::new(&request.chrom), request.start, request.end,
+     request.offset as usize, request.limit as usize)
+}
+
+
+type Job = Box<dyn FnOnce(&mut dyn GenotypeSource) + Send>;
+
+
+// gRPC service of a GenotypeSource (see the module documentation).
+#[derive(Clone)]
+pub struct GenotypeService {
+    jobs: mpsc::Sender<Job>
+}
+
+// The queries return the tonic Status of the replies.
+#[allow(clippy::result_large_err)]
+impl GenotypeService {
+    // Open the source on the thread answering the queries.
+    pub fn start<S, F>(open: F) -> GenotypeService
+        where S: GenotypeSource + 'static,
+              F: FnOnce() -> S + Send + 'static
+    {
+        let (jobs, queue) = mpsc::channel::<Job>();
+
+        thread::spawn(move || {
+            let mut source = open();
+            for job in queue {
+                // The reply of a query that panicked is dropped.
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                    job(&mut source)
+                }));
+            }
+        });
+
+        GenotypeService { jobs }
+    }
+
+    // Run a query on the source.
+    fn query<T, F>(&self, f: F) -> BoxFuture<T, Status>
+        where T: Send + 'static,
+              F: FnOnce(&mut dyn GenotypeSource) -> Result<T, Status>
+                  + Send + 'static
+    {
+        let (reply, result) = oneshot::channel();
+        let sent = self.jobs.send(Box::new(move |source| {
+            let _ = reply.send(f(source));
+        }));
+
+        Box::pin(async move {
+            sent.map_err(|_| Status::unavailable("The source is closed."))?;
+            result.await
+                .map_err(|_| Status::internal("The query failed."))?
+        })
+    }
+
+    fn samples(&self, _request: SamplesRequest)
+        -> BoxFuture<SamplesReply, Status>
+    {
+        self.query(|source| {
+            let samples = source.samples()
+                .iter()
+                .map(|s| SampleMessage {
+                    fid: s.fid.clone(),
+                    iid: s.iid.clone(),
+                    sex: s.sex.to_plink_code().to_string()
+                })
+                .collect();
+
+            Ok(SamplesReply { samples })
+        })
+    }
+
+    fn region(&self, request: RegionRequest) -> BoxFuture<RegionReply, Status> {
+        self.query(move |source| {
+            let (chrom, start, end, offset, limit) = page_request(&request);
+            let page = source.get_variants_in_region_page(&chrom, start, end,
+                                                          offset, limit);
+
+            Ok(RegionReply {
+                genotypes: page.genotypes.iter()
+                    .map(GenotypesMessage::from)
+                    .collect(),
+                next_offset: page.next_offset.map(|offset| offset as u64)
+            })
+        })
+    }
+
+    fn variant(&self, request: VariantMessage)
+        -> BoxFuture<VariantReply, Status>
+    {
+        self.query(move |source| {
+            let g = source.get_variant_genotypes(&Variant::from(&request));
+            Ok(VariantReply {
+                genotypes: g.as_ref().map(GenotypesMessage::from)
+            })
+        })
+    }
+
+    fn maf(&self, request: RegionRequest) -> BoxFuture<MafReply, Status> {
+        self.query(move |source| {
+            let (chrom, start, end, offset, limit) = page_request(&request);
+            let page = source.get_variants_in_region_page(&chrom, start, end,
+                                                          offset, limit);
+
+            Ok(MafReply {
+                variants: page.genotypes.iter()
+                    .map(|g| VariantMessage::from(&g.variant))
+                    .collect(),
+                mafs: page.genotypes.iter().map(Genotypes::maf).collect(),
+                next_offset: page.next_offset.map(|offset| offset as u64)
+            })
+        })
+    }
+
+    fn ld(&self, request: LdRequest) -> BoxFuture<LdReply, Status> {
+        self.query(move |source| {
+            let variant = request.variant.as_ref()
+                .ok_or_else(|| Status::invalid_argument("No variant."))?;
+            let g = source.get_variant_genotypes(&Variant::from(variant))
+                .ok_or_else(|| {
+                    Status::not_found(format!("Variant `{}` not found.",
+                                              variant.name))
+                })?;
+
+            let r2 = request.others.iter()
+                .map(|other| {
+                    match source.get_variant_genotypes(&Variant::from(other)) {
+                        Some(other) => compute_ld(g.clone(), vec![other],
+                                                  true)[0],
+                        None => f64::NAN
+                    }
+                })
+                .collect();
+
+            Ok(LdReply { r2 })
+        })
+    }
+}
+
+
+// Handler of a unary method.
+struct Unary<F>(F);
+
+impl<Req, Resp, F> tonic::server::UnaryService<Req> for Unary<F>
+    where Resp: Send + 'static,
+          F: FnMut(Req) -> BoxFuture<Resp, Status>
+{
+    type Response = Resp;
+    type Future = BoxFuture<tonic::Response<Resp>, Status>;
+
+    fn call(&mut self, request: tonic::Request<Req>) -> Self::Future {
+        let reply = (self.0)(request.into_inner());
+        Box::pin(async move { reply.await.map(tonic::Response::new) })
+    }
+}
+
+
+fn unary<B, Req, Resp, F>(request: http::Request<B>, method: F)
+    -> BoxFuture<http::Response<tonic::body::BoxBody>, Infallible>
+    where B: Body + Send + 'static,
+          B::Error: Into<StdError> + Send + 'static,
+          Req: prost::Message + Default + Send + 'static,
+          Resp: prost::Message + Send + 'static,
+          F: FnMut(Req) -> BoxFuture<Resp, Status> + Send + 'static
+{
+    Box::pin(async move {
+        let codec = ProstCodec::<Resp, Req>::default();
+        Ok(tonic::server::Grpc::new(codec).unary(Unary(method), request).await)
+    })
+}
+
+
+impl<B> Service<http::Request<B>> for GenotypeService
+    where B: Body + Send + 'static,
+          B::Error: Into<StdError> + Send + 'static
+{
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Infallible>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>)
+        -> Poll<Result<(), Infallible>>
+    {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let service = self.clone();
+        let method = request.uri()
+            .path()
+            .strip_prefix(&format!("/{}/", SERVICE_NAME))
+            .unwrap_or("")
+            .to_string();
+
+        match method.as_str() {
+            "Samples" => unary(request, move |r| service.samples(r)),
+            "Region" => unary(request, move |r| service.region(r)),
+            "Variant" => unary(request, move |r| service.variant(r)),
+            "Maf" => unary(request, move |r| service.maf(r)),
+            "Ld" => unary(request, move |r| service.ld(r)),
+            _ => Box::pin(async move {
+                Ok(Status::unimplemented(format!("Unknown method `{}`.",
+                                                 method))
+                    .into_http())
+            })
+        }
+    }
+}
+
+impl tonic::server::NamedService for GenotypeService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+
+// Serve the source opened by `open` on `addr` (this doesn't return unless
+// the server fails).
+pub fn serve<S, F>(addr: SocketAddr, open: F) -> io::Result<()>
+    where S: GenotypeSource + 'static,
+          F: FnOnce() -> S + Send + 'static
+{
+    serve_listener(TcpListener::bind(addr)?, open)
+}
+
+
+// Same as `serve` on a bound listener (e.g. on port 0).
+pub fn serve_listener<S, F>(listener: TcpListener, open: F) -> io::Result<()>
+    where S: GenotypeSource + 'static,
+          F: FnOnce() -> S + Send + 'static
+{
+    let service = GenotypeService::start(open);
+    let runtime = Runtime::new()?;
+
+    runtime.block_on(async move {
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        let incoming = tokio_stream::wrappers::TcpListenerStream::new(
+            listener
+        );
+
+        tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming(incoming)
+            .await
+            .map_err(io::Error::other)
+    })
+}
+
+
+// Synchronous client of a genotype server.
+pub struct GenotypeClient {
+    runtime: Runtime,
+    grpc: tonic::client::Grpc<Channel>
+}
+
+impl GenotypeClient {
+    // Connect to a server (e.g. `http://host:50051`).
+    pub fn connect(url: &str) -> io::Result<GenotypeClient> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let endpoint = Channel::from_shared(url.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let channel = runtime.block_on(endpoint.connect())
+            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused,
+                                        e))?;
+
+        Ok(GenotypeClient { runtime, grpc: tonic::client::Grpc::new(channel) })
+    }
+
+    fn call<Req, Resp>(&mut self, method: &str, request: Req)
+        -> io::Result<Resp>
+        where Req: prost::Message + Send + Sync + 'static,
+              Resp: prost::Message + Default + Send + Sync + 'static
+    {
+        let path = format!("/{}/{}", SERVICE_NAME, method);
+        let path = path.parse::<http::uri::PathAndQuery>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let grpc = &mut self.grpc;
+
+        self.runtime.block_on(async move {
+            grpc.ready().await.map_err(io::Error::other)?;
+
+            let codec = ProstCodec::<Req, Resp>::default();
+            grpc.unary(tonic::Request::new(request), path, codec)
+                .await
+                .map(tonic::Response::into_inner)
+                .map_err(|status| {
+                    io::Error::other(status.message().to_string())
+                })
+        })
+    }
+
+    pub fn samples(&mut self) -> io::Result<Vec<Sample>> {
+        let reply: SamplesReply = self.call("Samples", SamplesRequest {})?;
+
+        Ok(reply.samples.into_iter()
+            .map(|s| Sample::new(s.fid, s.iid, Sex::from_plink_code(&s.sex)))
+            .collect())
+    }
+
+    pub fn get_variant_genotypes(&mut self, v: &Variant)
+        -> io::Result<Option<Genotypes>>
+    {
+        let reply: VariantReply = self.call("Variant",
+                                            VariantMessage::from(v))?;
+        reply.genotypes.map(|g| g.to_genotypes()).transpose()
+    }
+
+    pub fn get_variants_in_region_page(&mut self, chrom: &Chromosome,
+                                       start: u32, end: u32, offset: usize,
+                                       limit: usize) -> io::Result<RegionPage>
+    {
+        let reply: RegionReply = self.call("Region", RegionRequest {
+            chrom: chrom.to_string(),
+            start,
+            end,
+            offset: offset as u64,
+            limit: limit as u64
+        })?;
+
+        Ok(RegionPage {
+            genotypes: reply.genotypes.iter()
+                .map(GenotypesMessage::to_genotypes)
+                .collect::<io::Result<_>>()?,
+            next_offset: reply.next_offset.map(|offset| offset as usize)
+        })
+    }
+
+    // All the pages of the region.
+    pub fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                                  end: u32) -> io::Result<Vec<Genotypes>>
+    {
+        let mut genotypes = Vec::new();
+        let mut offset = Some(0);
+
+        while let Some(start_offset) = offset {
+            let page = self.get_variants_in_region_page(
+                chrom, start, end, start_offset, usize::MAX
+            )?;
+            genotypes.extend(page.genotypes);
+            offset = page.next_offset;
+        }
+
+        Ok(genotypes)
+    }
+
+    // MAF of the variants of the region.
+    pub fn maf_in_region(&mut self, chrom: &Chromosome, start: u32, end: u32)
+        -> io::Result<Vec<(Variant, f64)>>
+    {
+        let mut mafs = Vec::new();
+        let mut offset = Some(0);
+
+        while let Some(start_offset) = offset {
+            let reply: MafReply = self.call("Maf", RegionRequest {
+                chrom: chrom.to_string(),
+                start,
+                end,
+                offset: start_offset as u64,
+                limit: u64::MAX
+            })?;
+
+            mafs.extend(reply.variants.iter()
+                .map(Variant::from)
+                .zip(reply.mafs));
+            offset = reply.next_offset.map(|offset| offset as usize);
+        }
+
+        Ok(mafs)
+    }
+
+    // r2 between a variant and every other variant (see LdReply).
+    pub fn ld(&mut self, v: &Variant, others: &[Variant])
+        -> io::Result<Vec<f64>>
+    {
+        let reply: LdReply = self.call("Ld", LdRequest {
+            variant: Some(VariantMessage::from(v)),
+            others: others.iter().map(VariantMessage::from).collect()
+        })?;
+
+        Ok(reply.r2)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use crate::plink::{PlinkReader, PlinkWriter};
+    use crate::test_util::genotypes;
+
+    // Genotypes of the fileset served by `start`.
+    fn data() -> Vec<Genotypes> {
+        vec![
+            genotypes(100, vec![Some(0), Some(1), Some(2), None]),
+            genotypes(200, vec![Some(0), Some(1), Some(2), Some(2)]),
+            genotypes(300, vec![Some(2), Some(1), Some(0), Some(0)])
+        ]
+    }
+
+    fn samples() -> Vec<Sample> {
+        (0..4)
+            .map(|i| Sample::new(format!("f{}", i), format!("s{}", i),
+                                 Sex::Female))
+            .collect()
+    }
+
+    // Serve a plink fileset of the data and connect to it.
+    fn start(name: &str) -> GenotypeClient {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let prefix = std::env::temp_dir()
+            .join(format!("genepa_server_{}_{}", name, std::process::id()));
+        let prefix = prefix.to_str().unwrap().to_string();
+        let mut writer = PlinkWriter::new(&prefix, &samples()).unwrap();
+        for g in data().iter() {
+            writer.write(g).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let open = |ext: &str| {
+            File::open(format!("{}.{}", prefix, ext)).unwrap()
+        };
+        let (bed, bim, fam) = (open("bed"), open("bim"), open("fam"));
+        // The reader only needs the open files.
+        for ext in &["bed", "bim", "fam"] {
+            fs::remove_file(format!("{}.{}", prefix, ext)).unwrap();
+        }
+
+        thread::spawn(move || {
+            serve_listener(listener, move || {
+                PlinkReader::from_readers(bed, bim, fam).unwrap()
+            })
+        });
+
+        GenotypeClient::connect(&url).unwrap()
+    }
+
+    fn other_variant() -> Variant {
+        Variant::new("x".to_string(), "2".to_string(), 1,
+                     ("A".to_string(), "C".to_string()))
+    }
+
+    #[test]
+    fn test_samples() {
+        let mut client = start("samples");
+        assert_eq!(client.samples().unwrap(), samples());
+    }
+
+    #[test]
+    fn test_region() {
+        let mut client = start("region");
+        let expected = data();
+        let chrom = Chromosome::new("1");
+
+        let page = client.get_variants_in_region_page(&chrom, 1, 250, 0, 1)
+            .unwrap();
+        assert_eq!(page.genotypes.len(), 1);
+        assert_eq!(page.genotypes[0].genotypes, expected[0].genotypes);
+        assert_eq!(page.next_offset, Some(1));
+
+        let page = client.get_variants_in_region_page(&chrom, 1, 250, 1, 1)
+            .unwrap();
+        assert_eq!(page.genotypes[0].variant, expected[1].variant);
+        assert_eq!(page.next_offset, None);
+
+        let region = client.get_variants_in_region(&chrom, 150, 1000)
+            .unwrap();
+        assert_eq!(region.len(), 2);
+        for (g, expected) in region.iter().zip(&expected[1..]) {
+            assert_eq!(g.variant, expected.variant);
+            assert_eq!(g.genotypes, expected.genotypes);
+            assert_eq!(g.coded_allele(), "G");
+            assert_eq!(g.ploidy(), 2);
+        }
+
+        let chrom = Chromosome::new("2");
+        assert!(client.get_variants_in_region(&chrom, 1, 1000).unwrap()
+                    .is_empty());
+    }
+
+    #[test]
+    fn test_variant() {
+        let mut client = start("variant");
+        let expected = data();
+
+        let g = client.get_variant_genotypes(&expected[1].variant).unwrap()
+            .unwrap();
+        assert_eq!(g.variant, expected[1].variant);
+        assert_eq!(g.genotypes, expected[1].genotypes);
+        assert_eq!(g.coded_allele(), "G");
+
+        assert!(client.get_variant_genotypes(&other_variant()).unwrap()
+                    .is_none());
+    }
+
+    #[test]
+    fn test_maf() {
+        let mut client = start("maf");
+        let expected = data();
+        let chrom = Chromosome::new("1");
+
+        let mafs = client.maf_in_region(&chrom, 1, 1000).unwrap();
+        assert_eq!(mafs.len(), 3);
+        for ((v, maf), expected) in mafs.iter().zip(&expected) {
+            assert_eq!(*v, expected.variant);
+            assert!((maf - expected.maf()).abs() < 1e-12);
+        }
+
+        let reply: MafReply = client.call("Maf", RegionRequest {
+            chrom: "1".to_string(),
+            start: 1,
+            end: 1000,
+            offset: 1,
+            limit: 1
+        }).unwrap();
+        assert_eq!(Variant::from(&reply.variants[0]), expected[1].variant);
+        assert_eq!(reply.mafs.len(), 1);
+        assert_eq!(reply.next_offset, Some(2));
+    }
+
+    #[test]
+    fn test_ld() {
+        let mut client = start("ld");
+        let expected = data();
+        let other = other_variant();
+
+        let r2 = client.ld(&expected[1].variant,
+                           &[expected[2].variant.clone(), other.clone()])
+            .unwrap();
+        let local = compute_ld(expected[1].clone(),
+                               vec![expected[2].clone()], true);
+        assert_eq!(r2.len(), 2);
+        assert!((r2[0] - local[0]).abs() < 1e-12);
+        assert!(r2[1].is_nan());
+
+        assert!(client.ld(&other, &[]).is_err());
+    }
+
+    #[test]
+    fn test_to_genotypes() {
+        let g = genotypes(100, vec![Some(0), Some(1), None]);
+        let message = GenotypesMessage::from(&g);
+        let decoded = message.to_genotypes().unwrap();
+        assert_eq!(decoded.variant, g.variant);
+        assert_eq!(decoded.genotypes, g.genotypes);
+
+        let haploid = GenotypesMessage { ploidy: 1, ..message.clone() };
+        assert_eq!(haploid.to_genotypes().unwrap().ploidy(), 1);
+
+        let invalid = [
+            GenotypesMessage { variant: None, ..message.clone() },
+            GenotypesMessage { coded_allele: "T".to_string(),
+                               ..message.clone() },
+            GenotypesMessage { ploidy: 0, ..message.clone() },
+            GenotypesMessage { ploidy: 258, ..message.clone() },
+            GenotypesMessage { calls: vec![0, 4], ..message.clone() },
+            GenotypesMessage { calls: vec![0, 2], ..haploid }
+        ];
+        for message in invalid.iter() {
+            let e = message.to_genotypes().unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+/*!
+ * Handling of the sex chromosomes.
+ *
+ * Following plink, variants in the pseudo-autosomal regions (PAR) of the X
+ * chromosome can be relabeled to the `XY` chromosome ("split") so that they
+ * are treated as diploid in males. The remaining `X` variants are haploid in
+ * males: their genotypes are coded 0 or 2 and heterozygous calls are errors.
+ *
+ * The frequencies and HWE tests of `genepa freq` and of the pipeline QC use
+ * `coded_allele_counts` and `hwe_p`, and `genepa assoc` codes the males 0 or
+ * 1 on the X chromosome (see `hemizygous_coding`).
+ */
+
+use std::str::FromStr;
+
+use crate::core::{Chromosome, Genotypes, Sex, Variant};
+use crate::stats;
+use crate::units::Frequency;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GenomeBuild {
+    // Also known as hg19.
+    Grch37,
+    // Also known as hg38.
+    Grch38
+}
+
+
+impl GenomeBuild {
+    // Boundaries of the PARs on the X chromosome as used by plink's
+    // `--split-x`: PAR1 is up to and including the first position and PAR2
+    // is strictly after the second position.
+    pub fn par_boundaries(&self) -> (u32, u32) {
+        match self {
+            GenomeBuild::Grch37 => (2_699_520, 154_931_043),
+            GenomeBuild::Grch38 => (2_781_479, 155_701_382)
+        }
+    }
+
+    pub fn is_in_par(&self, position: u32) -> bool {
+        let (par1_end, par2_start) = self.par_boundaries();
+        position <= par1_end || position > par2_start
+    }
+}
+
+impl FromStr for GenomeBuild {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<GenomeBuild, String> {
+        match s.to_lowercase().as_str() {
+            "grch37" | "hg19" => Ok(GenomeBuild::Grch37),
+            "grch38" | "hg38" => Ok(GenomeBuild::Grch38),
+            _ => Err(format!("Unknown genome build `{}` (expected grch37 or \
+                              grch38).", s))
+        }
+    }
+}
+
+
+// Relabel an X chromosome variant in the PAR to XY. Returns true if the
+// variant was relabeled.
+pub fn split_x(variant: &mut Variant, build: GenomeBuild) -> bool {
+    if variant.chrom == Chromosome::X && build.is_in_par(variant.position) {
+        variant.chrom = Chromosome::XY;
+        return true;
+    }
+
+    false
+}
+
+
+// Relabel an XY variant back to X (e.g. before writing files for tools that
+// don't know about the XY chromosome). Returns true if the variant was
+// relabeled.
+pub fn merge_x(variant: &mut Variant) -> bool {
+    if variant.chrom == Chromosome::XY {
+        variant.chrom = Chromosome::X;
+        return true;
+    }
+
+    false
+}
+
+
+// Split the PAR variants from an iterator of genotypes.
+pub fn split_x_genotypes<I>(genotypes: I, build: GenomeBuild)
+    -> impl Iterator<Item = Genotypes>
+    where I: IntoIterator<Item = Genotypes>
+{
+    genotypes.into_iter().map(move |mut g| {
+        split_x(&mut g.variant, build);
+        g
+    })
+}
+
+
+// Non-PAR X variants (i.e. after splitting) are haploid in males.
+pub fn is_haploid_in_males(variant: &Variant) -> bool {
+    variant.chrom == Chromosome::X
+}
+
+
+// Number of copies of the coded allele and number of alleles observed for a
+// sample, taking the ploidy into account. Males with heterozygous calls on
+// the X chromosome are treated as missing.
+fn allele_counts(g: &Genotypes, genotype: Option<u8>, sex: Sex)
+    -> Option<(u64, u64)>
+{
+    let geno = u64::from(genotype?);
+
+    if !is_haploid_in_males(&g.variant) {
+        return Some((geno, u64::from(g.ploidy())));
+    }
+
+    match sex {
+        Sex::Female => Some((geno, 2)),
+        Sex::Male if geno != 1 => Some((geno / 2, 1)),
+        _ => None
+    }
+}
+
+
+// Number of copies of the coded allele and number of alleles observed for
+// the samples at the given indices (see `coded_freq`).
+pub fn coded_allele_counts<I>(g: &Genotypes, sexes: &[Sex], samples: I)
+    -> (u64, u64)
+    where I: IntoIterator<Item = usize>
+{
+    samples.into_iter()
+        .filter_map(|i| allele_counts(g, g.genotypes[i], sexes[i]))
+        .fold((0, 0), |(c, n), (gc, gn)| (c + gc, n + gn))
+}
+
+
+// Frequency of the coded allele counting a single allele for males on the
+// X chromosome. Samples of unknown sex are ignored on the X chromosome.
+pub fn coded_freq(g: &Genotypes, sexes: &[Sex]) -> f64 {
+    let (n_coded, n_alleles) = g.iter_with_samples(sexes)
+        .filter_map(|(sex, geno)| allele_counts(g, geno, *sex))
+        .fold((0, 0), |(c, n), (gc, gn)| (c + gc, n + gn));
+
+    Frequency::from_counts(n_coded, n_alleles).map_or(f64::NAN, f64::from)
+}
+
+
+// Code the genotypes of the males 0 or 1 on the X chromosome (like plink's
+// default `--xchr-model 1` for the association tests). Heterozygous males
+// and samples of unknown sex are set as missing. The other variants are
+// unchanged.
+pub fn hemizygous_coding(mut g: Genotypes, sexes: &[Sex]) -> Genotypes {
+    if !is_haploid_in_males(&g.variant) {
+        return g;
+    }
+
+    if sexes.len() != g.genotypes.len() {
+        panic!("Got {} samples for {} genotypes.", sexes.len(),
+               g.genotypes.len());
+    }
+
+    for (geno, sex) in g.genotypes.iter_mut().zip(sexes.iter()) {
+        *geno = match sex {
+            Sex::Female => *geno,
+            Sex::Male => geno.and_then(|x| if x == 1 { None } else {
+                Some(x / 2)
+            }),
+            _ => None
+        };
+    }
+
+    g
+}
+
+
+// Hardy-Weinberg exact test p-value. Only females are used for the X
+// chromosome.
+pub fn hwe_p(g: &Genotypes, sexes: &[Sex]) -> f64 {
+    let haploid = is_haploid_in_males(&g.variant);
+
+    let mut counts = [0; 3];
+    for (sex, geno) in g.iter_with_samples(sexes) {
+        if haploid && *sex != Sex::Female {
+            continue;
+        }
+
+        if let Some(geno) = geno {
+            counts[geno as usize] += 1;
+        }
+    }
+
+    stats::hwe_exact(counts[1], counts[0], counts[2])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::genotypes_on as genotypes;
+
+    #[test]
+    fn test_split_merge() {
+        let g = vec![
+            genotypes("X", 60_001, vec![]),
+            genotypes("X", 2_699_521, vec![]),
+            genotypes("X", 155_000_000, vec![]),
+            genotypes("1", 100, vec![]),
+        ];
+
+        let chroms: Vec<String> = split_x_genotypes(g, GenomeBuild::Grch37)
+            .map(|g| g.variant.chrom.to_string())
+            .collect();
+
+        assert_eq!(chroms, vec!["XY", "X", "XY", "1"]);
+
+        let mut v = genotypes("XY", 100, vec![]).variant;
+        assert!(merge_x(&mut v));
+        assert_eq!(v.chrom, Chromosome::X);
+
+        assert!(!GenomeBuild::Grch38.is_in_par(2_781_480));
+    }
+
+    #[test]
+    fn test_x_frequency() {
+        use Sex::*;
+        let sexes = [Male, Male, Female, Female, Unknown];
+        let calls = vec![Some(2), Some(1), Some(1), Some(0), Some(2)];
+
+        // Males count as one allele and the heterozygous male is missing.
+        let g = genotypes("X", 5_000_000, calls.clone());
+        assert_eq!(coded_freq(&g, &sexes), 2.0 / 5.0);
+
+        // Diploid in the PAR.
+        let g = genotypes("XY", 100, calls);
+        assert_eq!(coded_freq(&g, &sexes), 6.0 / 10.0);
+        assert_eq!(g.coded_freq(), coded_freq(&g, &sexes));
+    }
+
+    #[test]
+    fn test_x_hwe() {
+        use Sex::*;
+        let sexes = [Male, Male, Female, Female];
+
+        // Only the females (one of each homozygote) are used.
+        let g = genotypes("X", 5_000_000, vec![Some(2), Some(2), Some(0),
+                                                Some(2)]);
+        assert!((hwe_p(&g, &sexes) - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_hemizygous_coding() {
+        use Sex::*;
+        let sexes = [Male, Male, Male, Female, Unknown];
+        let calls = vec![Some(2), Some(1), Some(0), Some(1), Some(2)];
+
+        let g = genotypes("X", 5_000_000, calls.clone());
+        assert_eq!(coded_allele_counts(&g, &sexes, vec![0, 2, 3]), (2, 4));
+        assert_eq!(hemizygous_coding(g, &sexes).genotypes,
+                   vec![Some(1), None, Some(0), Some(1), None]);
+
+        let g = genotypes("XY", 100, calls.clone());
+        assert_eq!(hemizygous_coding(g, &sexes).genotypes, calls);
+    }
+}
@@ -0,0 +1,170 @@
+/*!
+ * Simulation of genotypes (e.g. to produce test filesets or benchmarks).
+ *
+ * Every variant gets a minor allele frequency drawn from the chosen
+ * distribution and the genotypes are drawn independently for every sample
+ * under Hardy-Weinberg equilibrium. The variants are on chromosome 1, 1 kb
+ * apart, and the minor allele is the coded allele. Simulations are
+ * reproducible given the seed.
+ */
+
+use std::str::FromStr;
+
+use rand::{Rng, SeedableRng};
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha8Rng;
+
+use crate::core::{Genotypes, Sample, Sex, Variant};
+
+
+const MIN_MAF: f64 = 0.01;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MafDistribution {
+    // Uniform between 1% and 50%.
+    Uniform,
+    // Density proportional to 1 / MAF between 1% and 50% (the spectrum of
+    // neutral variants, i.e. most variants are rare).
+    Neutral,
+    Fixed(f64)
+}
+
+impl FromStr for MafDistribution {
+    type Err = String;
+
+    // `uniform`, `neutral` or a fixed frequency (e.g. `0.2`).
+    fn from_str(s: &str) -> Result<MafDistribution, String> {
+        match s {
+            "uniform" => Ok(MafDistribution::Uniform),
+            "neutral" => Ok(MafDistribution::Neutral),
+            _ => match s.parse::<f64>() {
+                Ok(maf) if (0.0..=0.5).contains(&maf) => {
+                    Ok(MafDistribution::Fixed(maf))
+                },
+                _ => Err(format!("Unknown MAF distribution `{}` (expected \
+                                  uniform, neutral or a frequency up to \
+                                  0.5).", s))
+            }
+        }
+    }
+}
+
+impl MafDistribution {
+    fn sample<R: Rng>(self, rng: &mut R) -> f64 {
+        match self {
+            MafDistribution::Uniform => rng.gen_range(MIN_MAF..0.5),
+            MafDistribution::Neutral => {
+                let u: f64 = rng.gen();
+                MIN_MAF * (0.5 / MIN_MAF).powf(u)
+            },
+            MafDistribution::Fixed(maf) => maf
+        }
+    }
+}
+
+
+// Samples named `sample_1`, `sample_2`, etc.
+pub fn simulate_samples(n_samples: usize) -> Vec<Sample> {
+    (1..=n_samples)
+        .map(|i| Sample::new(format!("sample_{}", i), format!("sample_{}", i),
+                             Sex::Unknown))
+        .collect()
+}
+
+
+pub struct GenotypeSimulator {
+    rng: ChaCha8Rng,
+    n_samples: usize,
+    n_variants: usize,
+    maf_distribution: MafDistribution,
+    n_simulated: usize
+}
+
+impl GenotypeSimulator {
+    pub fn new(n_samples: usize, n_variants: usize,
+               maf_distribution: MafDistribution, seed: u64)
+        -> GenotypeSimulator
+    {
+        GenotypeSimulator {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            n_samples,
+            n_variants,
+            maf_distribution,
+            n_simulated: 0
+        }
+    }
+}
+
+impl Iterator for GenotypeSimulator {
+    type Item = Genotypes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.n_simulated >= self.n_variants {
+            return None;
+        }
+        self.n_simulated += 1;
+
+        let rng = &mut self.rng;
+        let maf = self.maf_distribution.sample(rng);
+
+        let mut bases = ["A", "C", "G", "T"];
+        bases.shuffle(rng);
+        let (minor, major) = (bases[0], bases[1]);
+
+        let variant = Variant::new(
+            format!("sim_{}", self.n_simulated), "1".to_string(),
+            1000 * self.n_simulated as u32,
+            (minor.to_string(), major.to_string())
+        );
+
+        let genotypes = (0..self.n_samples)
+            .map(|_| Some(rng.gen_bool(maf) as u8 + rng.gen_bool(maf) as u8))
+            .collect();
+
+        Some(Genotypes::new(variant, genotypes, minor))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.n_variants - self.n_simulated;
+        (n, Some(n))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulate() {
+        assert_eq!("0.2".parse(), Ok(MafDistribution::Fixed(0.2)));
+        assert!("0.7".parse::<MafDistribution>().is_err());
+
+        let simulate = |seed| {
+            GenotypeSimulator::new(2000, 5, MafDistribution::Fixed(0.2), seed)
+                .collect::<Vec<Genotypes>>()
+        };
+
+        let a = simulate(1);
+        assert_eq!(a.len(), 5);
+        assert_eq!(a[4].variant.position, 5000);
+        assert_eq!(a.iter().map(|g| &g.genotypes).collect::<Vec<_>>(),
+                   simulate(1).iter().map(|g| &g.genotypes)
+                       .collect::<Vec<_>>());
+        assert_ne!(a[0].genotypes, simulate(2)[0].genotypes);
+
+        for g in a.iter() {
+            let n_coded: u32 = g.genotypes.iter()
+                .map(|x| u32::from(x.unwrap()))
+                .sum();
+            assert!((f64::from(n_coded) / 4000.0 - 0.2).abs() < 0.03);
+        }
+
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        for _ in 0..100 {
+            let maf = MafDistribution::Neutral.sample(&mut rng);
+            assert!((MIN_MAF..=0.5).contains(&maf));
+        }
+    }
+}
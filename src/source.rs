@@ -0,0 +1,590 @@
+/*!
+ * Common interface of the genotype readers.
+ *
+ * The analyses that only need to iterate over the variants take any
+ * iterator of Genotypes, and the ones that also need the samples or queries
+ * take a GenotypeSource. `open_source` opens a dataset in any of the
+ * supported formats (guessed from the path) as a boxed GenotypeSource, so
+ * that tools don't have to dispatch on the format themselves.
+ *
+ * The variants of a source can be restricted to some chromosomes (e.g.
+ * `autosomes_only`), which also applies to its queries. Most analyses (QC,
+ * GRM, PCA and association) assume two copies of every variant in every
+ * sample, so the commands running them only use the autosomes by default.
+ */
+
+use std::collections::HashSet;
+use std::io::{BufRead, Read};
+use std::path::Path;
+
+use crate::bcf::BcfReader;
+use crate::core::{Chromosome, Genotypes, Sample, Variant};
+use crate::multiplink::MultiPlinkReader;
+use crate::ped::PedReader;
+use crate::pgen::PgenReader;
+use crate::plink::PlinkReader;
+use crate::raw::RawReader;
+use crate::vcf::VcfReader;
+use crate::zarr::ZarrReader;
+
+
+// Maximal number of variants returned by a single page of a region query,
+// regardless of the requested limit.
+pub const MAX_PAGE_SIZE: usize = 10_000;
+
+
+// A page of the results of a region query. The readers (and GenotypeSource)
+// have a paginated `get_variants_in_region_page` with the same arguments as
+// `get_variants_in_region` and an `offset` and `limit`: the `next_offset` of
+// a page is the offset of the following page.
+#[derive(Debug)]
+pub struct RegionPage {
+    pub genotypes: Vec<Genotypes>,
+    // Offset of the next page if the results were truncated.
+    pub next_offset: Option<usize>
+}
+
+
+impl RegionPage {
+    // Collect up to `limit` (capped to MAX_PAGE_SIZE) genotypes from an
+    // iterator that already skipped the first `offset` results. Only one
+    // extra result is read to detect truncation.
+    pub fn collect<I>(genotypes: I, offset: usize, limit: usize) -> RegionPage
+        where I: Iterator<Item = Genotypes>
+    {
+        let limit = limit.min(MAX_PAGE_SIZE);
+
+        let mut genotypes: Vec<Genotypes> = genotypes.take(limit + 1)
+            .collect();
+
+        let next_offset = if genotypes.len() > limit {
+            genotypes.truncate(limit);
+            Some(offset + limit)
+        } else {
+            None
+        };
+
+        RegionPage { genotypes, next_offset }
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.next_offset.is_some()
+    }
+}
+
+
+// A reader that iterates over the variants of a dataset and that supports
+// region and variant queries.
+pub trait GenotypeSource: Iterator<Item = Genotypes> {
+    fn samples(&self) -> &[Sample];
+
+    fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes>;
+
+    fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                              end: u32) -> Vec<Genotypes>;
+
+    // Paginated region query for callers that can't bound the size of the
+    // region (e.g. services). Use the `next_offset` of the returned page to
+    // get the following results.
+    fn get_variants_in_region_page(&mut self, chrom: &Chromosome, start: u32,
+                                   end: u32, offset: usize, limit: usize)
+        -> RegionPage;
+
+    fn filter_chromosomes(self, filter: ChromosomeFilter)
+        -> ChromosomeFiltered<Self>
+        where Self: Sized
+    {
+        ChromosomeFiltered { source: self, filter }
+    }
+
+    // Drop the sex chromosomes, MT and the other contigs.
+    fn autosomes_only(self) -> ChromosomeFiltered<Self>
+        where Self: Sized
+    {
+        self.filter_chromosomes(ChromosomeFilter::autosomes())
+    }
+
+    fn exclude_chromosomes(self, chroms: &[Chromosome])
+        -> ChromosomeFiltered<Self>
+        where Self: Sized
+    {
+        self.filter_chromosomes(ChromosomeFilter::default().exclude(chroms))
+    }
+}
+
+
+// Boxed sources (e.g. from `open_source`) can be used where a source is
+// expected.
+impl<S: GenotypeSource + ?Sized> GenotypeSource for Box<S> {
+    fn samples(&self) -> &[Sample] {
+        (**self).samples()
+    }
+
+    fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        (**self).get_variant_genotypes(v)
+    }
+
+    fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                              end: u32) -> Vec<Genotypes>
+    {
+        (**self).get_variants_in_region(chrom, start, end)
+    }
+
+    fn get_variants_in_region_page(&mut self, chrom: &Chromosome, start: u32,
+                                   end: u32, offset: usize, limit: usize)
+        -> RegionPage
+    {
+        (**self).get_variants_in_region_page(chrom, start, end, offset, limit)
+    }
+}
+
+
+// Chromosomes kept from a source (all of them by default).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChromosomeFilter {
+    pub autosomes_only: bool,
+    pub excluded: HashSet<Chromosome>
+}
+
+impl ChromosomeFilter {
+    pub fn autosomes() -> ChromosomeFilter {
+        ChromosomeFilter { autosomes_only: true, excluded: HashSet::new() }
+    }
+
+    pub fn exclude(mut self, chroms: &[Chromosome]) -> ChromosomeFilter {
+        self.excluded.extend(chroms.iter().cloned());
+        self
+    }
+
+    pub fn keeps(&self, chrom: &Chromosome) -> bool {
+        (!self.autosomes_only || chrom.is_autosome()) &&
+            !self.excluded.contains(chrom)
+    }
+}
+
+
+// A source without the variants of the chromosomes rejected by the filter
+// (see `GenotypeSource::filter_chromosomes`).
+pub struct ChromosomeFiltered<S> {
+    source: S,
+    filter: ChromosomeFilter
+}
+
+impl<S> ChromosomeFiltered<S> {
+    pub fn filter(&self) -> &ChromosomeFilter {
+        &self.filter
+    }
+
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+impl<S: GenotypeSource> Iterator for ChromosomeFiltered<S> {
+    type Item = Genotypes;
+
+    fn next(&mut self) -> Option<Genotypes> {
+        let filter = &self.filter;
+        self.source.find(|g| filter.keeps(&g.variant.chrom))
+    }
+}
+
+impl<S: GenotypeSource> GenotypeSource for ChromosomeFiltered<S> {
+    fn samples(&self) -> &[Sample] {
+        self.source.samples()
+    }
+
+    fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        if !self.filter.keeps(&v.chrom) {
+            return None;
+        }
+        self.source.get_variant_genotypes(v)
+    }
+
+    fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                              end: u32) -> Vec<Genotypes>
+    {
+        if !self.filter.keeps(chrom) {
+            return Vec::new();
+        }
+        self.source.get_variants_in_region(chrom, start, end)
+    }
+
+    fn get_variants_in_region_page(&mut self, chrom: &Chromosome, start: u32,
+                                   end: u32, offset: usize, limit: usize)
+        -> RegionPage
+    {
+        if !self.filter.keeps(chrom) {
+            return RegionPage { genotypes: Vec::new(), next_offset: None };
+        }
+        self.source.get_variants_in_region_page(chrom, start, end, offset,
+                                                limit)
+    }
+}
+
+
+// Open a dataset from its path: a VCF (`.vcf` or `.vcf.gz`), a BCF (`.bcf`),
+// a Zarr store (`.zarr`), a plink `.raw` file (with the positions from the
+// BIM of the same prefix, if any) or the prefix (or any file) of a PGEN, ped
+// or plink fileset. Prefixes without an extension are opened as plink
+// filesets, and globs (e.g. `chr*`) as plink filesets split by chromosome
+// (see MultiPlinkReader).
+pub fn open_source(path: &str) -> Box<dyn GenotypeSource> {
+    if path.contains('*') || path.contains('?') {
+        return Box::new(MultiPlinkReader::from_glob(path));
+    }
+
+    if path.ends_with(".vcf") || path.ends_with(".vcf.gz") {
+        return Box::new(VcfReader::new(path));
+    }
+
+    if path.ends_with(".bcf") {
+        return Box::new(BcfReader::new(path));
+    }
+
+    if path.trim_end_matches('/').ends_with(".zarr") {
+        return Box::new(ZarrReader::new(path));
+    }
+
+    if let Some(prefix) = path.strip_suffix(".raw") {
+        let bim = format!("{}.bim", prefix);
+        return if Path::new(&bim).exists() {
+            Box::new(RawReader::with_bim(path, &bim))
+        } else {
+            Box::new(RawReader::new(path))
+        };
+    }
+
+    let strip = |extensions: &[&str]| {
+        extensions.iter().find_map(|ext| path.strip_suffix(ext))
+    };
+
+    if let Some(prefix) = strip(&[".pgen", ".pvar", ".psam"]) {
+        Box::new(PgenReader::new(prefix)
+            .unwrap_or_else(|e| panic!("{}", e)))
+    } else if let Some(prefix) = strip(&[".ped", ".map"]) {
+        Box::new(PedReader::new(prefix))
+    } else {
+        let prefix = strip(&[".bed", ".bim", ".fam"]).unwrap_or(path);
+        Box::new(PlinkReader::new(prefix)
+            .unwrap_or_else(|e| panic!("{}", e)))
+    }
+}
+
+
+impl GenotypeSource for PlinkReader {
+    fn samples(&self) -> &[Sample] {
+        PlinkReader::samples(self)
+    }
+
+    fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        PlinkReader::get_variant_genotypes(self, v)
+    }
+
+    fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                              end: u32) -> Vec<Genotypes>
+    {
+        PlinkReader::get_variants_in_region(self, chrom, start, end)
+    }
+
+    fn get_variants_in_region_page(&mut self, chrom: &Chromosome, start: u32,
+                                   end: u32, offset: usize, limit: usize)
+        -> RegionPage
+    {
+        PlinkReader::get_variants_in_region_page(self, chrom, start, end, offset,
+                                         limit)
+    }
+}
+
+
+impl GenotypeSource for MultiPlinkReader {
+    fn samples(&self) -> &[Sample] {
+        MultiPlinkReader::samples(self)
+    }
+
+    fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        MultiPlinkReader::get_variant_genotypes(self, v)
+    }
+
+    fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                              end: u32) -> Vec<Genotypes>
+    {
+        MultiPlinkReader::get_variants_in_region(self, chrom, start, end)
+    }
+
+    fn get_variants_in_region_page(&mut self, chrom: &Chromosome, start: u32,
+                                   end: u32, offset: usize, limit: usize)
+        -> RegionPage
+    {
+        MultiPlinkReader::get_variants_in_region_page(self, chrom, start, end,
+                                                      offset, limit)
+    }
+}
+
+
+impl GenotypeSource for PedReader {
+    fn samples(&self) -> &[Sample] {
+        PedReader::samples(self)
+    }
+
+    fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        PedReader::get_variant_genotypes(self, v)
+    }
+
+    fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                              end: u32) -> Vec<Genotypes>
+    {
+        PedReader::get_variants_in_region(self, chrom, start, end)
+    }
+
+    fn get_variants_in_region_page(&mut self, chrom: &Chromosome, start: u32,
+                                   end: u32, offset: usize, limit: usize)
+        -> RegionPage
+    {
+        PedReader::get_variants_in_region_page(self, chrom, start, end,
+                                               offset, limit)
+    }
+}
+
+
+impl GenotypeSource for PgenReader {
+    fn samples(&self) -> &[Sample] {
+        PgenReader::samples(self)
+    }
+
+    fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        PgenReader::get_variant_genotypes(self, v)
+    }
+
+    fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                              end: u32) -> Vec<Genotypes>
+    {
+        PgenReader::get_variants_in_region(self, chrom, start, end)
+    }
+
+    fn get_variants_in_region_page(&mut self, chrom: &Chromosome, start: u32,
+                                   end: u32, offset: usize, limit: usize)
+        -> RegionPage
+    {
+        PgenReader::get_variants_in_region_page(self, chrom, start, end,
+                                                offset, limit)
+    }
+}
+
+
+impl GenotypeSource for RawReader {
+    fn samples(&self) -> &[Sample] {
+        RawReader::samples(self)
+    }
+
+    fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        RawReader::get_variant_genotypes(self, v)
+    }
+
+    fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                              end: u32) -> Vec<Genotypes>
+    {
+        RawReader::get_variants_in_region(self, chrom, start, end)
+    }
+
+    fn get_variants_in_region_page(&mut self, chrom: &Chromosome, start: u32,
+                                   end: u32, offset: usize, limit: usize)
+        -> RegionPage
+    {
+        RawReader::get_variants_in_region_page(self, chrom, start, end,
+                                               offset, limit)
+    }
+}
+
+
+impl<R: BufRead> GenotypeSource for VcfReader<R> {
+    fn samples(&self) -> &[Sample] {
+        VcfReader::samples(self)
+    }
+
+    fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        VcfReader::get_variant_genotypes(self, v)
+    }
+
+    fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                              end: u32) -> Vec<Genotypes>
+    {
+        VcfReader::get_variants_in_region(self, chrom, start, end)
+    }
+
+    fn get_variants_in_region_page(&mut self, chrom: &Chromosome, start: u32,
+                                   end: u32, offset: usize, limit: usize)
+        -> RegionPage
+    {
+        VcfReader::get_variants_in_region_page(self, chrom, start, end, offset,
+                                         limit)
+    }
+}
+
+
+impl GenotypeSource for ZarrReader {
+    fn samples(&self) -> &[Sample] {
+        ZarrReader::samples(self)
+    }
+
+    fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        ZarrReader::get_variant_genotypes(self, v)
+    }
+
+    fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                              end: u32) -> Vec<Genotypes>
+    {
+        ZarrReader::get_variants_in_region(self, chrom, start, end)
+    }
+
+    fn get_variants_in_region_page(&mut self, chrom: &Chromosome, start: u32,
+                                   end: u32, offset: usize, limit: usize)
+        -> RegionPage
+    {
+        ZarrReader::get_variants_in_region_page(self, chrom, start, end,
+                                                offset, limit)
+    }
+}
+
+
+impl<R: Read> GenotypeSource for BcfReader<R> {
+    fn samples(&self) -> &[Sample] {
+        BcfReader::samples(self)
+    }
+
+    fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        BcfReader::get_variant_genotypes(self, v)
+    }
+
+    fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                              end: u32) -> Vec<Genotypes>
+    {
+        BcfReader::get_variants_in_region(self, chrom, start, end)
+    }
+
+    fn get_variants_in_region_page(&mut self, chrom: &Chromosome, start: u32,
+                                   end: u32, offset: usize, limit: usize)
+        -> RegionPage
+    {
+        BcfReader::get_variants_in_region_page(self, chrom, start, end, offset,
+                                         limit)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn genotypes(pos: u32) -> Genotypes {
+        let v = Variant::new(
+            format!("v{}", pos),
+            "1".to_string(),
+            pos,
+            ("A".to_string(), "G".to_string())
+        );
+
+        Genotypes::new(v, vec![Some(0)], "G")
+    }
+
+    #[test]
+    fn test_open_source() {
+        use crate::core::Sex;
+        use crate::index::{build_index, IndexFormat};
+        use crate::plink::PlinkWriter;
+        use crate::vcf::VcfWriter;
+
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_source_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let samples = vec![
+            Sample::new("s1".to_string(), "s1".to_string(), Sex::Unknown),
+            Sample::new("s2".to_string(), "s2".to_string(), Sex::Unknown)
+        ];
+        let data: Vec<Genotypes> = [10, 20, 30].iter()
+            .map(|&pos| {
+                let mut g = genotypes(pos);
+                g.genotypes = vec![Some(1), None];
+                g
+            })
+            .collect();
+
+        let mut writer = PlinkWriter::new(&path("data"), &samples).unwrap();
+        let mut vcf = VcfWriter::create(&path("data.vcf"), &samples, &["1"],
+                                        false).unwrap();
+        for g in data.iter() {
+            writer.write(g).unwrap();
+            vcf.write(g, None).unwrap();
+        }
+        writer.finish().unwrap();
+        vcf.finish().unwrap();
+        build_index(&path("data"), IndexFormat::V2).unwrap();
+
+        for p in [path("data"), path("data.bed"), path("data.vcf")].iter() {
+            let source = open_source(p);
+            assert_eq!(source.samples(), &samples[..], "{}", p);
+
+            let read: Vec<Genotypes> = source.collect();
+            assert_eq!(read.len(), 3);
+            assert_eq!(read[1].genotypes, vec![Some(1), None]);
+        }
+
+        let mut source = open_source(&path("data.fam"));
+        let region = source.get_variants_in_region(
+            &Chromosome::Autosome(1), 15, 30
+        );
+        assert_eq!(region.iter().map(|g| g.variant.position)
+                       .collect::<Vec<_>>(), vec![20, 30]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_filter_chromosomes() {
+        let raw = "FID IID PAT MAT SEX PHENOTYPE rs1_A rs2_A rs3_A rs4_A \
+                   rs5_A\n\
+                   f1 s1 0 0 1 -9 0 1 2 0 1\n";
+        let bim = "1\trs1\t0\t100\tA\tG\n\
+                   23\trs2\t0\t100\tA\tG\n\
+                   chr2\trs3\t0\t100\tA\tG\n\
+                   MT\trs4\t0\t100\tA\tG\n\
+                   2\trs5\t0\t200\tA\tG\n";
+        let open = || RawReader::from_readers(raw.as_bytes(),
+                                              Some(bim.as_bytes()));
+        let names = |source: ChromosomeFiltered<RawReader>| -> Vec<String> {
+            source.map(|g| g.variant.name).collect()
+        };
+
+        assert_eq!(names(open().autosomes_only()),
+                   vec!["rs1", "rs3", "rs5"]);
+        assert_eq!(names(open().exclude_chromosomes(&[Chromosome::X,
+                                                      Chromosome::new("2")])),
+                   vec!["rs1", "rs4"]);
+
+        let filter = ChromosomeFilter::autosomes()
+            .exclude(&[Chromosome::Autosome(1)]);
+        let mut source = open().filter_chromosomes(filter);
+        assert_eq!(source.samples().len(), 1);
+        assert!(source.get_variants_in_region(&Chromosome::X, 1, 1000)
+                    .is_empty());
+        assert_eq!(names(source), vec!["rs3", "rs5"]);
+    }
+
+    #[test]
+    fn test_region_page() {
+        let page = RegionPage::collect((1..=10).map(genotypes), 0, 4);
+        assert_eq!(page.genotypes.len(), 4);
+        assert_eq!(page.next_offset, Some(4));
+
+        let page = RegionPage::collect((9..=10).map(genotypes), 8, 4);
+        assert_eq!(page.genotypes.len(), 2);
+        assert!(!page.is_truncated());
+
+        // The limit is capped.
+        let page = RegionPage::collect((0..).map(genotypes), 0, usize::MAX);
+        assert_eq!(page.genotypes.len(), MAX_PAGE_SIZE);
+        assert!(page.is_truncated());
+    }
+}
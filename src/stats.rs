@@ -0,0 +1,355 @@
+/*!
+ * Probability distributions used for p-values and confidence intervals.
+ */
+
+
+// Complementary error function (Chebyshev approximation from Numerical
+// Recipes, fractional error < 1.2e-7 everywhere).
+pub fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+
+    let poly = -z * z - 1.265_512_23 + t * (1.000_023_68 + t * (0.374_091_96 +
+        t * (0.096_784_18 + t * (-0.186_288_06 + t * (0.278_868_07 +
+        t * (-1.135_203_98 + t * (1.488_515_87 + t * (-0.822_152_23 +
+        t * 0.170_872_77))))))));
+
+    let ans = t * poly.exp();
+    if x >= 0.0 { ans } else { 2.0 - ans }
+}
+
+
+pub fn normal_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / std::f64::consts::SQRT_2)
+}
+
+
+// Upper tail probability of the standard normal.
+pub fn normal_sf(x: f64) -> f64 {
+    0.5 * erfc(x / std::f64::consts::SQRT_2)
+}
+
+
+// Quantile function of the standard normal (Acklam's algorithm, relative
+// error < 1.15e-9).
+pub fn normal_quantile(p: f64) -> f64 {
+    assert!(p > 0.0 && p < 1.0, "Probability must be in (0, 1).");
+
+    let a = [-3.969_683_028_665_376e1, 2.209_460_984_245_205e2,
+             -2.759_285_104_469_687e2, 1.383_577_518_672_69e2,
+             -3.066_479_806_614_716e1, 2.506_628_277_459_239];
+    let b = [-5.447_609_879_822_406e1, 1.615_858_368_580_409e2,
+             -1.556_989_798_598_866e2, 6.680_131_188_771_972e1,
+             -1.328_068_155_288_572e1];
+    let c = [-7.784_894_002_430_293e-3, -3.223_964_580_411_365e-1,
+             -2.400_758_277_161_838, -2.549_732_539_343_734,
+             4.374_664_141_464_968, 2.938_163_982_698_783];
+    let d = [7.784_695_709_041_462e-3, 3.224_671_290_700_398e-1,
+             2.445_134_137_142_996, 3.754_408_661_907_416];
+
+    let p_low = 0.02425;
+
+    let tail = |q: f64| {
+        (((((c[0] * q + c[1]) * q + c[2]) * q + c[3]) * q + c[4]) * q + c[5]) /
+        ((((d[0] * q + d[1]) * q + d[2]) * q + d[3]) * q + 1.0)
+    };
+
+    if p < p_low {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p <= 1.0 - p_low {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((a[0] * r + a[1]) * r + a[2]) * r + a[3]) * r + a[4]) * r + a[5]) * q /
+        (((((b[0] * r + b[1]) * r + b[2]) * r + b[3]) * r + b[4]) * r + 1.0)
+    } else {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    }
+}
+
+
+
+// Natural logarithm of the gamma function (Lanczos approximation, g = 7).
+pub fn ln_gamma(x: f64) -> f64 {
+    let c = [0.999_999_999_999_809_9, 676.520_368_121_885_1,
+             -1_259.139_216_722_402_8, 771.323_428_777_653_1,
+             -176.615_029_162_140_6, 12.507_343_278_686_905,
+             -0.138_571_095_265_720_12, 9.984_369_578_019_572e-6,
+             1.505_632_735_149_311_6e-7];
+
+    if x < 0.5 {
+        // Reflection formula.
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let sum = c.iter()
+        .enumerate()
+        .skip(1)
+        .fold(c[0], |acc, (i, ci)| acc + ci / (x + i as f64));
+
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t +
+        sum.ln()
+}
+
+
+// Continued fraction of the incomplete beta function (modified Lentz's
+// method, from Numerical Recipes).
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+    let tiny = 1e-300;
+    let (qab, qap, qam) = (a + b, a + 1.0, a - 1.0);
+
+    let mut c = 1.0;
+    let mut d = 1.0 - qab * x / qap;
+    if d.abs() < tiny {
+        d = tiny;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+
+    for m in 1..300 {
+        let m = f64::from(m);
+        let m2 = 2.0 * m;
+
+        // Even and odd steps of the recurrence.
+        for aa in [m * (b - m) * x / ((qam + m2) * (a + m2)),
+                   -(a + m) * (qab + m) * x / ((a + m2) * (qap + m2))] {
+            d = 1.0 + aa * d;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = 1.0 + aa / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            h *= d * c;
+        }
+
+        if (d * c - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+
+    h
+}
+
+
+// Regularized incomplete beta function I_x(a, b).
+pub fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+
+    let ln_front = ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) +
+        a * x.ln() + b * (1.0 - x).ln();
+
+    // The continued fraction converges quickly for x < (a + 1) / (a + b + 2).
+    if x < (a + 1.0) / (a + b + 2.0) {
+        ln_front.exp() * beta_continued_fraction(a, b, x) / a
+    } else {
+        1.0 - ln_front.exp() * beta_continued_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+
+// Two-sided p-value of a Student t statistic.
+pub fn student_t_two_sided(t: f64, df: f64) -> f64 {
+    incomplete_beta(df / 2.0, 0.5, df / (df + t * t))
+}
+
+
+// Regularized upper incomplete gamma function Q(a, x), using the series of
+// P(a, x) for x < a + 1 and a continued fraction otherwise (Numerical
+// Recipes).
+pub fn upper_incomplete_gamma(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+
+    let ln_front = a * x.ln() - x - ln_gamma(a);
+
+    if x < a + 1.0 {
+        let (mut term, mut sum) = (1.0 / a, 1.0 / a);
+        for n in 1..1000 {
+            term *= x / (a + f64::from(n));
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+
+        1.0 - sum * ln_front.exp()
+    } else {
+        // Modified Lentz's method.
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+
+        for i in 1..1000 {
+            let an = -f64::from(i) * (f64::from(i) - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            h *= d * c;
+
+            if (d * c - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+
+        ln_front.exp() * h
+    }
+}
+
+
+// Upper tail probability of a chi-squared statistic.
+pub fn chi2_sf(x: f64, df: f64) -> f64 {
+    upper_incomplete_gamma(df / 2.0, x / 2.0)
+}
+
+// Exact two-sided binomial test of k successes out of n trials with a
+// probability of 1/2 (e.g. the exact TDT).
+pub fn binomial_test_half(k: u64, n: u64) -> f64 {
+    if n == 0 {
+        return 1.0;
+    }
+
+    let ln_choose = |i: u64| {
+        ln_gamma(n as f64 + 1.0) - ln_gamma(i as f64 + 1.0) -
+            ln_gamma((n - i) as f64 + 1.0)
+    };
+
+    let ln_half_n = n as f64 * 0.5_f64.ln();
+    let tail: f64 = (0..=k.min(n - k))
+        .map(|i| (ln_choose(i) + ln_half_n).exp())
+        .sum();
+
+    (2.0 * tail).min(1.0)
+}
+
+// Exact test of Hardy-Weinberg equilibrium (Wigginton et al., 2005) given
+// the genotype counts.
+pub fn hwe_exact(n_het: u64, n_hom1: u64, n_hom2: u64) -> f64 {
+    let n = n_het + n_hom1 + n_hom2;
+    if n == 0 {
+        return 1.0;
+    }
+
+    let n_rare = 2 * n_hom1.min(n_hom2) + n_het;
+
+    // Probabilities of every number of heterozygotes given the allele
+    // counts, starting from the mode and recursing on both sides.
+    let mut probs = vec![0.0; n_rare as usize + 1];
+
+    let mut mid = n_rare * (2 * n - n_rare) / (2 * n);
+    if mid % 2 != n_rare % 2 {
+        mid += 1;
+    }
+
+    probs[mid as usize] = 1.0;
+    let mut total = 1.0;
+
+    let (mut hets, mut hom_r, mut hom_c) =
+        (mid, (n_rare - mid) / 2, n - mid - (n_rare - mid) / 2);
+    while hets > 1 {
+        let p = probs[hets as usize] * (hets * (hets - 1)) as f64 /
+                (4 * (hom_r + 1) * (hom_c + 1)) as f64;
+        probs[hets as usize - 2] = p;
+        total += p;
+
+        hets -= 2;
+        hom_r += 1;
+        hom_c += 1;
+    }
+
+    let (mut hets, mut hom_r, mut hom_c) =
+        (mid, (n_rare - mid) / 2, n - mid - (n_rare - mid) / 2);
+    while hets + 2 <= n_rare {
+        let p = probs[hets as usize] * (4 * hom_r * hom_c) as f64 /
+                ((hets + 2) * (hets + 1)) as f64;
+        probs[hets as usize + 2] = p;
+        total += p;
+
+        hets += 2;
+        hom_r -= 1;
+        hom_c -= 1;
+    }
+
+    let observed = probs[n_het as usize];
+    let p: f64 = probs.iter().filter(|&&p| p <= observed).sum();
+
+    (p / total).min(1.0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normal() {
+        assert!((normal_cdf(0.0) - 0.5).abs() < 1e-7);
+        assert!((normal_cdf(1.959_964) - 0.975).abs() < 1e-7);
+        assert!((normal_sf(3.0) - 1.349_898e-3).abs() < 1e-9);
+        assert!((normal_quantile(0.975) - 1.959_964).abs() < 1e-6);
+        assert!((normal_quantile(0.001) + 3.090_232).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_student_t() {
+        assert!((ln_gamma(5.0) - 24f64.ln()).abs() < 1e-12);
+        assert!((ln_gamma(0.5) - std::f64::consts::PI.sqrt().ln()).abs()
+                < 1e-12);
+
+        assert!((student_t_two_sided(0.0, 10.0) - 1.0).abs() < 1e-12);
+        assert!((student_t_two_sided(2.228_139, 10.0) - 0.05).abs() < 1e-6);
+        assert!((student_t_two_sided(-12.706_2, 1.0) - 0.05).abs() < 1e-5);
+        assert!((student_t_two_sided(5.0, 1000.0) - 6.767_256e-7).abs()
+                < 1e-12);
+    }
+
+    #[test]
+    fn test_chi2() {
+        // 1 df: P(X > z^2) = 2 P(Z > z).
+        assert!((chi2_sf(3.841_459, 1.0) - 0.05).abs() < 1e-7);
+        // 2 df: P(X > x) = exp(-x / 2).
+        assert!((chi2_sf(1.0, 2.0) - (-0.5_f64).exp()).abs() < 1e-12);
+        assert!((chi2_sf(30.0, 2.0) - (-15.0_f64).exp()).abs() < 1e-15);
+        assert!((chi2_sf(18.307_038, 10.0) - 0.05).abs() < 1e-7);
+        assert_eq!(chi2_sf(0.0, 3.0), 1.0);
+    }
+
+    #[test]
+    fn test_binomial_test_half() {
+        assert!((binomial_test_half(0, 5) - 2.0 / 32.0).abs() < 1e-12);
+        assert!((binomial_test_half(8, 10) - 112.0 / 1024.0).abs() < 1e-12);
+        assert_eq!(binomial_test_half(5, 10), 1.0);
+        assert_eq!(binomial_test_half(0, 0), 1.0);
+    }
+
+    #[test]
+    fn test_hwe_exact() {
+        // With two genotypes and two copies of each allele, P(0 het) = 1/3
+        // and P(2 het) = 2/3.
+        assert!((hwe_exact(0, 1, 1) - 1.0 / 3.0).abs() < 1e-12);
+        assert_eq!(hwe_exact(2, 0, 0), 1.0);
+
+        assert!((hwe_exact(50, 25, 25) - 1.0).abs() < 1e-12);
+        assert!(hwe_exact(0, 50, 50) < 1e-25);
+        assert_eq!(hwe_exact(0, 0, 0), 1.0);
+    }
+}
@@ -0,0 +1,682 @@
+/*!
+ * In-memory genotype store.
+ *
+ * Genotypes are packed using 2 bits per sample and every variant has a
+ * sidecar of genotype counts so that repeated filters (e.g. on the MAF or
+ * the call rate) don't need to decode the genotypes again. The counts are
+ * relative to the current sample mask and are invalidated when it changes.
+ *
+ * Dosages (e.g. imputed) are kept in a `DosageStore` with the precision
+ * chosen for the store: 16 bits per sample (half-precision floats or fixed
+ * point values) use a quarter of the memory of f64 values, which is enough
+ * for the precision of imputed dosages.
+ */
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::core::{Dosages, Genotypes, Sample, Variant};
+use crate::units::{Frequency, FrequencyEstimator};
+
+
+const MISSING: u8 = 0b11;
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VariantCounts {
+    // Number of samples with 0, 1 or 2 copies of the coded allele.
+    pub n_geno: [u64; 3],
+    pub n_missing: u64
+}
+
+
+impl VariantCounts {
+    pub fn of(g: &Genotypes) -> VariantCounts {
+        let mut counts = VariantCounts::default();
+        for call in g.genotypes.iter() {
+            match call {
+                Some(x) => counts.n_geno[usize::from(*x)] += 1,
+                None => counts.n_missing += 1
+            }
+        }
+
+        counts
+    }
+
+    pub fn n_called(&self) -> u64 {
+        self.n_geno.iter().sum()
+    }
+
+    pub fn call_rate(&self) -> f64 {
+        self.n_called() as f64 / (self.n_called() + self.n_missing) as f64
+    }
+
+    pub fn coded_frequency(&self, ploidy: u8) -> Option<Frequency> {
+        self.coded_frequency_with(ploidy, FrequencyEstimator::Observed)
+    }
+
+    pub fn coded_frequency_with(&self, ploidy: u8,
+                                estimator: FrequencyEstimator)
+        -> Option<Frequency>
+    {
+        let n_coded = self.n_geno[1] + 2 * self.n_geno[2];
+        let ploidy = f64::from(ploidy);
+        estimator.estimate(n_coded as f64, ploidy * self.n_called() as f64,
+                           ploidy * (self.n_called() + self.n_missing) as f64)
+    }
+
+    // See `coded_frequency`, NaN if no sample is called.
+    pub fn coded_freq(&self, ploidy: u8) -> f64 {
+        self.coded_frequency(ploidy).map_or(f64::NAN, f64::from)
+    }
+}
+
+
+struct PackedVariant {
+    variant: Variant,
+    coded_allele: String,
+    ploidy: u8,
+    packed: Vec<u8>
+}
+
+
+impl PackedVariant {
+    fn get(&self, i: usize) -> Option<u8> {
+        match (self.packed[i / 4] >> (2 * (i % 4))) & 0b11 {
+            MISSING => None,
+            g => Some(g)
+        }
+    }
+}
+
+
+#[derive(Default)]
+pub struct GenotypeStore {
+    n_samples: usize,
+    samples: Option<Arc<Vec<Sample>>>,
+    variants: Vec<PackedVariant>,
+    // Samples that are kept (all of them if None).
+    mask: Option<Vec<bool>>,
+    // Counts sidecar, None when invalidated.
+    counts: Vec<Option<VariantCounts>>
+}
+
+
+impl GenotypeStore {
+    pub fn new() -> GenotypeStore {
+        GenotypeStore::default()
+    }
+
+    pub fn from_genotypes<I>(genotypes: I) -> GenotypeStore
+        where I: IntoIterator<Item = Genotypes>
+    {
+        let mut store = GenotypeStore::new();
+        for g in genotypes {
+            store.push(&g);
+        }
+
+        store
+    }
+
+    pub fn push(&mut self, g: &Genotypes) {
+        if self.variants.is_empty() {
+            self.n_samples = g.genotypes.len();
+            self.samples = g.samples.clone();
+        }
+
+        if g.genotypes.len() != self.n_samples {
+            panic!("Expected {} samples but `{}` has {} genotypes.",
+                   self.n_samples, g.variant, g.genotypes.len());
+        }
+
+        let mut packed = vec![0; self.n_samples.div_ceil(4)];
+        for (i, geno) in g.genotypes.iter().enumerate() {
+            let code = match geno {
+                Some(x) if *x <= 2 => *x,
+                Some(x) => panic!("Unexpected genotype value: {}", x),
+                None => MISSING
+            };
+
+            packed[i / 4] |= code << (2 * (i % 4));
+        }
+
+        self.variants.push(PackedVariant {
+            variant: g.variant.clone(),
+            coded_allele: g.coded_allele().to_string(),
+            ploidy: g.ploidy(),
+            packed
+        });
+        self.counts.push(None);
+    }
+
+    pub fn len(&self) -> usize {
+        self.variants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variants.is_empty()
+    }
+
+    // Number of samples in the mask.
+    pub fn n_samples(&self) -> usize {
+        match &self.mask {
+            Some(mask) => mask.iter().filter(|&&keep| keep).count(),
+            None => self.n_samples
+        }
+    }
+
+    // Restrict the store to a subset of the samples (or all of them if None).
+    // This invalidates the counts.
+    pub fn set_sample_mask(&mut self, mask: Option<Vec<bool>>) {
+        if let Some(mask) = &mask {
+            assert_eq!(mask.len(), self.n_samples,
+                       "Expected a mask value for every sample.");
+        }
+
+        self.mask = mask;
+        self.invalidate_counts();
+    }
+
+    pub fn invalidate_counts(&mut self) {
+        for counts in self.counts.iter_mut() {
+            *counts = None;
+        }
+    }
+
+    fn is_kept(&self, i: usize) -> bool {
+        self.mask.as_ref().is_none_or(|mask| mask[i])
+    }
+
+    // Genotype counts for the variant at the given index, computed the first
+    // time they are needed after an invalidation.
+    pub fn counts(&mut self, idx: usize) -> VariantCounts {
+        if let Some(counts) = self.counts[idx] {
+            return counts;
+        }
+
+        let v = &self.variants[idx];
+        let mut counts = VariantCounts::default();
+        for i in (0..self.n_samples).filter(|&i| self.is_kept(i)) {
+            match v.get(i) {
+                Some(g) => counts.n_geno[g as usize] += 1,
+                None => counts.n_missing += 1
+            }
+        }
+
+        self.counts[idx] = Some(counts);
+        counts
+    }
+
+    pub fn coded_freq(&mut self, idx: usize) -> f64 {
+        let ploidy = self.variants[idx].ploidy;
+        self.counts(idx).coded_freq(ploidy)
+    }
+
+    // Indices of the variants passing the MAF and call rate thresholds.
+    pub fn filter(&mut self, min_maf: f64, min_call_rate: f64) -> Vec<usize> {
+        (0..self.len())
+            .filter(|&idx| {
+                let freq = self.coded_freq(idx);
+                freq.min(1.0 - freq) >= min_maf &&
+                self.counts(idx).call_rate() >= min_call_rate
+            })
+            .collect()
+    }
+
+    // Decode the genotypes of the masked samples.
+    pub fn get(&self, idx: usize) -> Genotypes {
+        let v = &self.variants[idx];
+
+        let genotypes = (0..self.n_samples)
+            .filter(|&i| self.is_kept(i))
+            .map(|i| v.get(i))
+            .collect();
+
+        let mut g = Genotypes::new(v.variant.clone(), genotypes,
+                                   &v.coded_allele)
+            .with_ploidy(v.ploidy);
+
+        if let Some(samples) = &self.samples {
+            let kept = (0..self.n_samples)
+                .filter(|&i| self.is_kept(i))
+                .map(|i| samples[i].clone())
+                .collect();
+
+            g = g.with_samples(Arc::new(kept));
+        }
+
+        g
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Genotypes> + '_ {
+        (0..self.len()).map(move |idx| self.get(idx))
+    }
+}
+
+
+// Storage of the dosages of a DosageStore.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DosagePrecision {
+    #[default]
+    F64,
+    F32,
+    // Half-precision floats (the error is at most 5e-4 for dosages up to 2).
+    F16,
+    // Fixed-point values between 0 and 2 (the error is at most 2e-5). The
+    // dosages within `INT16_TOLERANCE` of the range (e.g. rounding errors)
+    // are clamped, the other ones can't be stored.
+    Int16
+}
+
+
+impl DosagePrecision {
+    pub fn bytes_per_dosage(self) -> usize {
+        match self {
+            DosagePrecision::F64 => 8,
+            DosagePrecision::F32 => 4,
+            DosagePrecision::F16 | DosagePrecision::Int16 => 2
+        }
+    }
+}
+
+
+impl FromStr for DosagePrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DosagePrecision, String> {
+        match s {
+            "f64" => Ok(DosagePrecision::F64),
+            "f32" => Ok(DosagePrecision::F32),
+            "f16" => Ok(DosagePrecision::F16),
+            "int16" => Ok(DosagePrecision::Int16),
+            _ => Err(format!("Unknown dosage precision `{}` (expected f64, \
+                              f32, f16 or int16).", s))
+        }
+    }
+}
+
+
+impl fmt::Display for DosagePrecision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DosagePrecision::F64 => write!(f, "f64"),
+            DosagePrecision::F32 => write!(f, "f32"),
+            DosagePrecision::F16 => write!(f, "f16"),
+            DosagePrecision::Int16 => write!(f, "int16")
+        }
+    }
+}
+
+
+// Largest fixed-point value (2.0), the next one is the missing value.
+const INT16_MAX: u16 = u16::MAX - 1;
+pub const INT16_TOLERANCE: f64 = 1e-6;
+const INT16_MISSING: u16 = u16::MAX;
+const F16_NAN: u16 = 0x7e00;
+
+
+enum PackedDosages {
+    // The missing dosages are NaN.
+    F64(Vec<f64>),
+    F32(Vec<f32>),
+    F16(Vec<u16>),
+    Int16(Vec<u16>)
+}
+
+
+impl PackedDosages {
+    // Returns the first dosage that can't be stored with the precision as
+    // the error.
+    fn pack(dosages: &Dosages, precision: DosagePrecision)
+        -> Result<PackedDosages, f64>
+    {
+        let values = dosages.dosages.iter().map(|d| d.unwrap_or(f64::NAN));
+
+        Ok(match precision {
+            DosagePrecision::F64 => PackedDosages::F64(values.collect()),
+            DosagePrecision::F32 => {
+                PackedDosages::F32(values.map(|x| x as f32).collect())
+            },
+            DosagePrecision::F16 => {
+                PackedDosages::F16(values.map(|x| f16_bits(x as f32))
+                    .collect())
+            },
+            DosagePrecision::Int16 => {
+                let range = -INT16_TOLERANCE..=(2.0 + INT16_TOLERANCE);
+                PackedDosages::Int16(values.map(|x| {
+                    if x.is_nan() {
+                        Ok(INT16_MISSING)
+                    } else if range.contains(&x) {
+                        let x = x.clamp(0.0, 2.0);
+                        Ok((x / 2.0 * f64::from(INT16_MAX)).round() as u16)
+                    } else {
+                        Err(x)
+                    }
+                }).collect::<Result<Vec<u16>, f64>>()?)
+            }
+        })
+    }
+
+    fn get(&self, i: usize) -> Option<f64> {
+        let x = match self {
+            PackedDosages::F64(values) => values[i],
+            PackedDosages::F32(values) => f64::from(values[i]),
+            PackedDosages::F16(values) => f64::from(f16_value(values[i])),
+            PackedDosages::Int16(values) => match values[i] {
+                INT16_MISSING => f64::NAN,
+                x => f64::from(x) * 2.0 / f64::from(INT16_MAX)
+            }
+        };
+
+        if x.is_nan() { None } else { Some(x) }
+    }
+}
+
+
+// Half-precision bits of a float, rounded to the nearest value.
+fn f16_bits(x: f32) -> u16 {
+    if x.is_nan() {
+        return F16_NAN;
+    }
+
+    let bits = x.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent >= 0x1f {
+        // Too large (or infinite).
+        return sign | 0x7c00;
+    }
+
+    // Subnormal values have no implicit leading bit.
+    let (half, shift) = if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        (0, (14 - exponent) as u32)
+    } else {
+        ((exponent as u32) << 10, 13)
+    };
+
+    let mantissa = if exponent <= 0 { mantissa | 0x80_0000 } else { mantissa };
+    let truncated = half | (mantissa >> shift);
+    let rest = mantissa & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+
+    // Ties are rounded to even. A carry into the exponent is correct.
+    let round_up = rest > halfway || (rest == halfway && truncated & 1 == 1);
+    sign | (truncated + u32::from(round_up)) as u16
+}
+
+
+fn f16_value(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((bits >> 10) & 0x1f);
+    let mantissa = f32::from(bits & 0x3ff);
+
+    match exponent {
+        0 => sign * mantissa * 2_f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2_f32.powi(exponent - 15)
+    }
+}
+
+
+struct StoredDosages {
+    variant: Variant,
+    coded_allele: String,
+    info: Option<f64>,
+    packed: PackedDosages
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum DosageStoreError {
+    // The dosages don't have the number of samples of the store.
+    SampleCount { variant: String, expected: usize, observed: usize },
+    // The dosage can't be stored with the precision of the store.
+    OutOfRange {
+        variant: String,
+        dosage: f64,
+        precision: DosagePrecision
+    }
+}
+
+
+impl fmt::Display for DosageStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DosageStoreError::SampleCount { variant, expected, observed } =>
+                write!(f, "Expected {} samples but `{}` has {} dosages",
+                       expected, variant, observed),
+            DosageStoreError::OutOfRange { variant, dosage, precision } =>
+                write!(f, "The dosage {} of `{}` can't be stored as {}",
+                       dosage, variant, precision)
+        }
+    }
+}
+
+
+impl std::error::Error for DosageStoreError {}
+
+
+// Dosages of variants kept in memory with the given precision. They are
+// converted when they are added and decoded back to f64 when they are read.
+pub struct DosageStore {
+    precision: DosagePrecision,
+    n_samples: usize,
+    samples: Option<Arc<Vec<Sample>>>,
+    variants: Vec<StoredDosages>
+}
+
+
+impl DosageStore {
+    pub fn new(precision: DosagePrecision) -> DosageStore {
+        DosageStore {
+            precision,
+            n_samples: 0,
+            samples: None,
+            variants: Vec::new()
+        }
+    }
+
+    pub fn from_dosages<I>(dosages: I, precision: DosagePrecision)
+        -> Result<DosageStore, DosageStoreError>
+        where I: IntoIterator<Item = Dosages>
+    {
+        let mut store = DosageStore::new(precision);
+        for d in dosages {
+            store.push(&d)?;
+        }
+
+        Ok(store)
+    }
+
+    // The store is unchanged if the dosages can't be added.
+    pub fn push(&mut self, d: &Dosages) -> Result<(), DosageStoreError> {
+        if !self.variants.is_empty() && d.dosages.len() != self.n_samples {
+            return Err(DosageStoreError::SampleCount {
+                variant: d.variant.to_string(),
+                expected: self.n_samples,
+                observed: d.dosages.len()
+            });
+        }
+
+        let packed = PackedDosages::pack(d, self.precision)
+            .map_err(|dosage| DosageStoreError::OutOfRange {
+                variant: d.variant.to_string(),
+                dosage,
+                precision: self.precision
+            })?;
+
+        if self.variants.is_empty() {
+            self.n_samples = d.dosages.len();
+            self.samples = d.samples.clone();
+        }
+
+        self.variants.push(StoredDosages {
+            variant: d.variant.clone(),
+            coded_allele: d.coded_allele().to_string(),
+            info: d.info,
+            packed
+        });
+
+        Ok(())
+    }
+
+    pub fn precision(&self) -> DosagePrecision {
+        self.precision
+    }
+
+    pub fn len(&self) -> usize {
+        self.variants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variants.is_empty()
+    }
+
+    pub fn n_samples(&self) -> usize {
+        self.n_samples
+    }
+
+    // Memory used by the dosages (without the variants).
+    pub fn dosage_bytes(&self) -> usize {
+        self.len() * self.n_samples * self.precision.bytes_per_dosage()
+    }
+
+    pub fn get(&self, idx: usize) -> Dosages {
+        let v = &self.variants[idx];
+        let values = (0..self.n_samples).map(|i| v.packed.get(i)).collect();
+
+        let mut d = Dosages::new(v.variant.clone(), values, &v.coded_allele);
+        if let Some(info) = v.info {
+            d = d.with_info(info);
+        }
+        if let Some(samples) = &self.samples {
+            d = d.with_samples(Arc::clone(samples));
+        }
+
+        d
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Dosages> + '_ {
+        (0..self.len()).map(move |idx| self.get(idx))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::genotypes;
+
+    fn get_store() -> GenotypeStore {
+        GenotypeStore::from_genotypes(vec![
+            genotypes(1, vec![Some(0), Some(1), Some(2), None, Some(0)]),
+            genotypes(2, vec![Some(0), Some(0), Some(0), Some(0), Some(1)]),
+        ])
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let store = get_store();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(0),
+                   genotypes(1, vec![Some(0), Some(1), Some(2), None,
+                                     Some(0)]));
+    }
+
+    #[test]
+    fn test_counts_and_mask() {
+        let mut store = get_store();
+
+        assert_eq!(store.counts(0), VariantCounts {
+            n_geno: [2, 1, 1], n_missing: 1
+        });
+        assert_eq!(store.coded_freq(1), 0.1);
+        assert_eq!(store.filter(0.05, 0.9), vec![1]);
+
+        // Dropping the last sample makes the second variant monomorphic.
+        store.set_sample_mask(Some(vec![true, true, true, true, false]));
+        assert_eq!(store.n_samples(), 4);
+        assert_eq!(store.coded_freq(1), 0.0);
+        assert_eq!(store.filter(0.05, 0.0), vec![0]);
+        assert_eq!(store.get(1).genotypes, vec![Some(0); 4]);
+    }
+
+    #[test]
+    fn test_f16() {
+        // Exact values, including the largest and a subnormal.
+        let tiny = 3.0 * 2_f32.powi(-24);
+        for x in [0.0, 1.0, 2.0, 0.5, -1.5, 1.999_023_4, 65504.0, tiny] {
+            assert_eq!(f16_value(f16_bits(x)), x);
+        }
+
+        // Ties are rounded to even.
+        let ulp = 2_f32.powi(-10);
+        assert_eq!(f16_value(f16_bits(1.0 + ulp / 2.0)), 1.0);
+        assert_eq!(f16_value(f16_bits(1.0 + 1.5 * ulp)), 1.0 + 2.0 * ulp);
+        assert_eq!(f16_value(f16_bits(1.0 + 0.6 * ulp)), 1.0 + ulp);
+        assert_eq!(f16_bits(1.0), 0x3c00);
+        assert_eq!(f16_bits(-2.0), 0xc000);
+        assert!(f16_value(f16_bits(f32::NAN)).is_nan());
+        assert_eq!(f16_value(f16_bits(1e6)), f32::INFINITY);
+    }
+
+    #[test]
+    fn test_dosage_store() {
+        let v = Variant::new("rs1".to_string(), "1".to_string(), 10,
+                             ("A".to_string(), "G".to_string()));
+        let values = vec![Some(0.0), Some(0.123_456), None, Some(1.999),
+                          Some(2.0)];
+        let dosages = || {
+            Dosages::new(v.clone(), values.clone(), "G").with_info(0.9)
+        };
+
+        for (precision, tolerance) in [(DosagePrecision::F64, 0.0),
+                                       (DosagePrecision::F32, 1e-7),
+                                       (DosagePrecision::F16, 1e-3),
+                                       (DosagePrecision::Int16, 2e-5)]
+        {
+            let store = DosageStore::from_dosages(vec![dosages(), dosages()],
+                                                  precision).unwrap();
+            assert_eq!(store.len(), 2);
+            assert_eq!(store.dosage_bytes(),
+                       2 * 5 * precision.bytes_per_dosage());
+
+            let d = store.get(1);
+            assert_eq!(d.coded_allele(), "G");
+            assert_eq!(d.info, Some(0.9));
+            for (obs, exp) in d.dosages.iter().zip(values.iter()) {
+                match (obs, exp) {
+                    (Some(obs), Some(exp)) => {
+                        assert!((obs - exp).abs() <= tolerance)
+                    },
+                    (obs, exp) => assert_eq!(obs, exp)
+                }
+            }
+        }
+
+        // Rounding errors are clamped, other dosages are errors.
+        let mut store = DosageStore::new(DosagePrecision::Int16);
+        let d = Dosages::new(v.clone(), vec![Some(2.000_000_1), Some(-1e-9)],
+                             "G");
+        store.push(&d).unwrap();
+        assert_eq!(store.get(0).dosages, vec![Some(2.0), Some(0.0)]);
+
+        let d = Dosages::new(v.clone(), vec![Some(1.0), Some(2.5)], "G");
+        assert!(matches!(store.push(&d),
+                         Err(DosageStoreError::OutOfRange { .. })));
+        let d = Dosages::new(v.clone(), vec![Some(1.0)], "G");
+        assert!(matches!(store.push(&d),
+                         Err(DosageStoreError::SampleCount { .. })));
+        assert_eq!(store.len(), 1);
+
+        assert_eq!("int16".parse(), Ok(DosagePrecision::Int16));
+        assert!("f8".parse::<DosagePrecision>().is_err());
+    }
+}
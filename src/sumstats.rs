@@ -0,0 +1,282 @@
+/*!
+ * Reader for GWAS summary statistics.
+ *
+ * Summary statistics are delimited files with a line per variant. Like for
+ * the DelimitedVariantsReader, the variant is parsed from the columns given
+ * by a VarFieldIdx (with `a1` being the effect allele) and the effect size,
+ * its standard error, the p-value and the sample size are parsed from the
+ * columns given by a StatFieldIdx. The column indices can also be found from
+ * the names in the header (see SummaryStatsColumns).
+ *
+ * Missing statistics (empty, NA, NaN or .) are None.
+ */
+
+use std::io::{BufRead, Lines};
+
+use crate::core::{parse_delimited_variant, OrderedAllelesVariant,
+                  VarFieldIdx};
+use crate::stats::normal_quantile;
+use crate::utils::open_text_file;
+
+
+// Indices of the columns with the statistics (None if there is no such
+// column).
+#[derive(Clone, Debug, Default)]
+pub struct StatFieldIdx {
+    pub beta: Option<usize>,
+    pub se: Option<usize>,
+    pub p: Option<usize>,
+    // Sample size.
+    pub n: Option<usize>
+}
+
+
+// Names of the columns in the header. The defaults are the column names
+// of the plink association results.
+#[derive(Clone, Debug)]
+pub struct SummaryStatsColumns {
+    pub name: String,
+    pub chrom: String,
+    pub pos: String,
+    // Effect allele.
+    pub a1: String,
+    pub a2: String,
+    pub beta: String,
+    pub se: String,
+    pub p: String,
+    pub n: String
+}
+
+impl Default for SummaryStatsColumns {
+    fn default() -> SummaryStatsColumns {
+        SummaryStatsColumns {
+            name: "SNP".to_string(),
+            chrom: "CHR".to_string(),
+            pos: "BP".to_string(),
+            a1: "A1".to_string(),
+            a2: "A2".to_string(),
+            beta: "BETA".to_string(),
+            se: "SE".to_string(),
+            p: "P".to_string(),
+            n: "N".to_string()
+        }
+    }
+}
+
+impl SummaryStatsColumns {
+    // Find the indices of the columns in the header. The variant columns are
+    // required and the statistics are optional.
+    pub fn indices(&self, header: &[&str], delimiter: char)
+        -> Result<(VarFieldIdx, StatFieldIdx), String>
+    {
+        let find = |name: &str| {
+            header.iter().position(|column| column.trim() == name)
+        };
+        let required = |name: &str| {
+            find(name).ok_or_else(|| {
+                format!("Column `{}` is not in the summary statistics.", name)
+            })
+        };
+
+        let variant_idx = VarFieldIdx {
+            delimiter,
+            name: required(&self.name)?,
+            chrom: required(&self.chrom)?,
+            pos: required(&self.pos)?,
+            a1: required(&self.a1)?,
+            a2: required(&self.a2)?
+        };
+
+        let stat_idx = StatFieldIdx {
+            beta: find(&self.beta),
+            se: find(&self.se),
+            p: find(&self.p),
+            n: find(&self.n)
+        };
+
+        Ok((variant_idx, stat_idx))
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SummaryStat {
+    // Effect of the `a1` allele.
+    pub beta: Option<f64>,
+    pub se: Option<f64>,
+    pub p: Option<f64>,
+    pub n: Option<f64>
+}
+
+impl SummaryStat {
+    // Chi-square statistic (1 df) of the association, from the effect size
+    // and its standard error or else from the p-value.
+    pub fn chisq(&self) -> Option<f64> {
+        if let (Some(beta), Some(se)) = (self.beta, self.se) {
+            if se > 0.0 {
+                return Some((beta / se).powi(2));
+            }
+        }
+
+        let p = self.p.filter(|&p| p > 0.0 && p <= 1.0)?;
+        Some(normal_quantile(p / 2.0).powi(2))
+    }
+}
+
+
+pub struct SummaryStatsReader<R: BufRead> {
+    lines: Lines<R>,
+    variant_idx: VarFieldIdx,
+    stat_idx: StatFieldIdx,
+    line_number: u64
+}
+
+
+impl SummaryStatsReader<Box<dyn BufRead>> {
+    // Read summary statistics with a header (the file can be gzipped).
+    pub fn new(filename: &str, delimiter: char, columns: &SummaryStatsColumns)
+        -> SummaryStatsReader<Box<dyn BufRead>>
+    {
+        SummaryStatsReader::from_header(open_text_file(filename), delimiter,
+                                        columns)
+            .unwrap_or_else(|e| panic!("{} (`{}`)", e, filename))
+    }
+}
+
+
+impl<R: BufRead> SummaryStatsReader<R> {
+    // The reader should be positioned after the header (if any).
+    pub fn from_reader(reader: R, variant_idx: VarFieldIdx,
+                       stat_idx: StatFieldIdx) -> SummaryStatsReader<R>
+    {
+        SummaryStatsReader {
+            lines: reader.lines(),
+            variant_idx,
+            stat_idx,
+            line_number: 0
+        }
+    }
+
+    // Get the column indices from the header (the first line).
+    pub fn from_header(reader: R, delimiter: char,
+                       columns: &SummaryStatsColumns)
+        -> Result<SummaryStatsReader<R>, String>
+    {
+        let mut lines = reader.lines();
+        let header = match lines.next() {
+            Some(line) => line.map_err(|e| {
+                format!("Could not read the summary statistics: {}", e)
+            })?,
+            None => return Err("The summary statistics are empty."
+                               .to_string())
+        };
+
+        let fields: Vec<&str> = header.split(delimiter).collect();
+        let (variant_idx, stat_idx) = columns.indices(&fields, delimiter)?;
+
+        Ok(SummaryStatsReader {
+            lines,
+            variant_idx,
+            stat_idx,
+            line_number: 1
+        })
+    }
+
+    fn parse_stat(&self, fields: &[&str], idx: Option<usize>) -> Option<f64> {
+        let value = fields[idx?].trim();
+
+        if value.is_empty() || value == "NA" || value == "." {
+            return None;
+        }
+
+        let x: f64 = value.parse().unwrap_or_else(|_| {
+            panic!("Invalid value `{}` on line {} of the summary \
+                    statistics.", value, self.line_number)
+        });
+
+        if x.is_nan() { None } else { Some(x) }
+    }
+}
+
+
+impl<R: BufRead> Iterator for SummaryStatsReader<R> {
+    type Item = (OrderedAllelesVariant, SummaryStat);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in self.lines.by_ref() {
+            let line = line.expect("Could not read the summary statistics.");
+            self.line_number += 1;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split(self.variant_idx.delimiter)
+                .collect();
+            let variant = parse_delimited_variant(&fields, &self.variant_idx)
+                .unwrap_or_else(|e| {
+                    panic!("Invalid variant on line {} of the summary \
+                            statistics: {}.", self.line_number, e)
+                });
+
+            let stat = SummaryStat {
+                beta: self.parse_stat(&fields, self.stat_idx.beta),
+                se: self.parse_stat(&fields, self.stat_idx.se),
+                p: self.parse_stat(&fields, self.stat_idx.p),
+                n: self.parse_stat(&fields, self.stat_idx.n)
+            };
+
+            return Some((variant, stat));
+        }
+
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let sumstats = "MarkerName\tCHR\tBP\tEA\tNEA\tBETA\tP\n\
+                        rs1\t1\t100\tg\tA\t0.5\t1e-8\n\
+                        \n\
+                        rs2\t2\t200\tC\tT\tNA\t0.2\n";
+
+        let columns = SummaryStatsColumns {
+            name: "MarkerName".to_string(),
+            a1: "EA".to_string(),
+            a2: "NEA".to_string(),
+            ..Default::default()
+        };
+
+        let reader = SummaryStatsReader::from_header(sumstats.as_bytes(),
+                                                     '\t', &columns)
+            .unwrap();
+        let stats: Vec<_> = reader.collect();
+        assert_eq!(stats.len(), 2);
+
+        let (v, stat) = &stats[0];
+        assert_eq!(v.variant.name, "rs1");
+        assert_eq!(v.variant.alleles.1, "G");
+        assert_eq!(v.a1_idx, 1);
+        assert_eq!(*stat, SummaryStat {
+            beta: Some(0.5), se: None, p: Some(1e-8), n: None
+        });
+
+        assert_eq!(stats[1].1.beta, None);
+        assert!((stats[1].1.chisq().unwrap() - 1.642_374).abs() < 1e-5);
+        let stat = SummaryStat {
+            beta: Some(-0.2), se: Some(0.1), p: Some(0.5), n: Some(1e4)
+        };
+        assert!((stat.chisq().unwrap() - 4.0).abs() < 1e-12);
+        assert_eq!(stats[1].0.variant.chrom.to_string(), "2");
+
+        // The variant columns are required.
+        let columns = SummaryStatsColumns::default();
+        assert!(SummaryStatsReader::from_header(sumstats.as_bytes(), '\t',
+                                                &columns).is_err());
+    }
+}
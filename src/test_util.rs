@@ -0,0 +1,33 @@
+/*!
+ * Fixtures shared by the unit tests.
+ */
+
+use crate::core::{Genotypes, Variant};
+
+
+// A/G variant on chromosome 1 named `v{pos}`, with G as the coded allele.
+pub(crate) fn genotypes(pos: u32, calls: Vec<Option<u8>>) -> Genotypes {
+    let v = Variant::new(
+        format!("v{}", pos),
+        "1".to_string(),
+        pos,
+        ("A".to_string(), "G".to_string())
+    );
+
+    Genotypes::new(v, calls, "G")
+}
+
+
+// Same as `genotypes` on another chromosome, named `v{chrom}_{pos}`.
+pub(crate) fn genotypes_on(chrom: &str, pos: u32, calls: Vec<Option<u8>>)
+    -> Genotypes
+{
+    let v = Variant::new(
+        format!("v{}_{}", chrom, pos),
+        chrom.to_string(),
+        pos,
+        ("A".to_string(), "G".to_string())
+    );
+
+    Genotypes::new(v, calls, "G")
+}
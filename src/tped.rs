@@ -0,0 +1,175 @@
+/*!
+ * Reader for the transposed text plink format (tped and tfam).
+ *
+ * Every line of the tped has the chromosome, name, position in cM and
+ * position of a variant followed by two alleles per sample, in the order of
+ * the tfam. As the lines are variant major, the tped is read as it is
+ * iterated.
+ *
+ * Like for the ped, the coded allele (A1) is the minor allele, variants
+ * with a negative position are excluded and variants with more or less than
+ * 2 observed alleles are skipped.
+ */
+
+use std::io::{BufRead, Lines};
+use std::sync::Arc;
+
+use crate::core::{Genotypes, HaploidHets, Sample};
+use crate::ped::{MapEntry, VariantCalls};
+use crate::plink::read_fam_from_reader;
+use crate::utils::open_text_file;
+
+
+pub struct TpedReader<R: BufRead> {
+    lines: Lines<R>,
+    samples: Arc<Vec<Sample>>,
+    attach_samples: bool,
+    haploid_hets: Option<HaploidHets>,
+    line_number: u64,
+    n_skipped: u32
+}
+
+
+impl TpedReader<Box<dyn BufRead>> {
+    // The tped can be gzipped.
+    pub fn new(prefix: &str) -> TpedReader<Box<dyn BufRead>> {
+        TpedReader::from_readers(open_text_file(&format!("{}.tped", prefix)),
+                                 open_text_file(&format!("{}.tfam", prefix)))
+    }
+}
+
+
+impl<R: BufRead> TpedReader<R> {
+    pub fn from_readers<F: BufRead>(tped: R, tfam: F) -> TpedReader<R> {
+        TpedReader {
+            lines: tped.lines(),
+            samples: Arc::new(read_fam_from_reader(tfam)),
+            attach_samples: false,
+            haploid_hets: Some(HaploidHets::SetMissing),
+            line_number: 0,
+            n_skipped: 0
+        }
+    }
+
+    pub fn attach_samples(&mut self, attach: bool) {
+        self.attach_samples = attach;
+    }
+
+    // See PlinkReader::haploid_policy.
+    pub fn haploid_policy(&mut self, hets: Option<HaploidHets>) {
+        self.haploid_hets = hets;
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    // Number of variants that were excluded (negative position) or skipped
+    // because they don't have exactly 2 alleles.
+    pub fn n_skipped(&self) -> u32 {
+        self.n_skipped
+    }
+
+    fn _parse_line(&self, line: &str) -> Option<Genotypes> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let n_samples = self.samples.len();
+
+        if fields.len() != 4 + 2 * n_samples {
+            panic!("Expected {} columns on line {} of the tped (given {} \
+                    samples in the tfam), got {}.", 4 + 2 * n_samples,
+                    self.line_number, n_samples, fields.len());
+        }
+
+        let entry = MapEntry {
+            chrom: fields[0].to_string(),
+            name: fields[1].to_string(),
+            position: fields[3].parse().unwrap_or_else(|_| {
+                panic!("Invalid position on line {} of the tped.",
+                       self.line_number)
+            })
+        };
+
+        if entry.position < 0 {
+            return None;
+        }
+
+        let mut calls = VariantCalls::default();
+        for alleles in fields[4..].chunks(2) {
+            calls.push(alleles[0], alleles[1]);
+        }
+
+        let location = format!("line {} of the tped", self.line_number);
+        let mut g = calls.into_genotypes(&entry, &location)?;
+
+        if let Some(hets) = self.haploid_hets {
+            if g.variant.chrom.is_haploid() {
+                g = g.into_haploid(hets).unwrap_or_else(|e| panic!("{}", e));
+            }
+        }
+
+        if self.attach_samples {
+            g = g.with_samples(Arc::clone(&self.samples));
+        }
+
+        Some(g)
+    }
+}
+
+
+impl<R: BufRead> Iterator for TpedReader<R> {
+    type Item = Genotypes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(line) = self.lines.next() {
+            let line = line.expect("Could not read the tped.");
+            self.line_number += 1;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match self._parse_line(&line) {
+                Some(g) => return Some(g),
+                None => self.n_skipped += 1
+            }
+        }
+
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Sex;
+
+    #[test]
+    fn test_read() {
+        let tfam = "f1 s1 0 0 1 -9\nf2 s2 0 0 2 -9\nf3 s3 0 0 0 -9\n";
+        let tped = "1 rs1 0 100 A A A G G G\n\
+                    1 rs2 0 200 C C C T A A\n\
+                    1 rs3 0 -300 A A A A A G\n\
+                    Y rs4 0 400 G G 0 0 T G\n";
+
+        let mut reader = TpedReader::from_readers(tped.as_bytes(),
+                                                  tfam.as_bytes());
+        reader.attach_samples(true);
+        assert_eq!(reader.samples()[1].sex, Sex::Female);
+
+        let all: Vec<Genotypes> = reader.by_ref().collect();
+        assert_eq!(reader.n_skipped(), 2);
+        assert_eq!(all.len(), 2);
+
+        // Ties keep the first allele as A2.
+        assert_eq!(all[0].coded_allele(), "G");
+        assert_eq!(all[0].genotypes, vec![Some(0), Some(1), Some(2)]);
+        assert!(all[0].samples.is_some());
+
+        // Heterozygous haploid calls are set to missing.
+        assert_eq!(all[1].variant.name, "rs4");
+        assert!(all[1].is_haploid());
+        assert_eq!(all[1].coded_allele(), "T");
+        assert_eq!(all[1].genotypes, vec![Some(0), None, None]);
+    }
+}
@@ -0,0 +1,337 @@
+/*!
+ * Units of the genotype values.
+ *
+ * Hard calls, dosages and allele frequencies are all plain numbers in the
+ * containers (`Genotypes::genotypes` and `Dosages::dosages`), which makes
+ * them easy to mix up: a frequency is not a dosage divided by 2 for haploid
+ * calls, and a dosage of 1.6 is not an allele count. These newtypes check
+ * the range of the values when they are created and only provide the
+ * conversions that are meaningful:
+ *
+ * - AlleleCount: copies of an allele in a hard call (0 to the ploidy).
+ * - Dosage: expected copies of an allele in a diploid sample (0 to 2).
+ * - Frequency: allele frequency (0 to 1).
+ *
+ * Frequencies are estimated from the observed alleles by default. For very
+ * small cohorts, a FrequencyEstimator can instead count the missing calls
+ * in the denominator or shrink the estimates towards a prior frequency.
+ */
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnitError {
+    AlleleCount { count: u8, ploidy: u8 },
+    Dosage(f64),
+    Frequency(f64)
+}
+
+impl fmt::Display for UnitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnitError::AlleleCount { count, ploidy } =>
+                write!(f, "Invalid allele count {} for ploidy {}", count,
+                       ploidy),
+            UnitError::Dosage(x) =>
+                write!(f, "Invalid dosage {} (expected 0 to 2)", x),
+            UnitError::Frequency(x) =>
+                write!(f, "Invalid frequency {} (expected 0 to 1)", x)
+        }
+    }
+}
+
+impl std::error::Error for UnitError {}
+
+
+// Copies of an allele in a hard call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AlleleCount {
+    count: u8,
+    ploidy: u8
+}
+
+impl AlleleCount {
+    pub fn new(count: u8, ploidy: u8) -> Result<AlleleCount, UnitError> {
+        if ploidy == 0 || count > ploidy {
+            return Err(UnitError::AlleleCount { count, ploidy });
+        }
+
+        Ok(AlleleCount { count, ploidy })
+    }
+
+    pub fn get(self) -> u8 {
+        self.count
+    }
+
+    pub fn ploidy(self) -> u8 {
+        self.ploidy
+    }
+
+    // Copies of the other allele.
+    pub fn flipped(self) -> AlleleCount {
+        AlleleCount { count: self.ploidy - self.count, ploidy: self.ploidy }
+    }
+
+    // Haploid calls count as homozygous diploid calls.
+    pub fn to_dosage(self) -> Dosage {
+        Dosage(f64::from(self.count) * 2.0 / f64::from(self.ploidy))
+    }
+}
+
+
+// Expected copies of an allele in a diploid sample.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Dosage(f64);
+
+impl Dosage {
+    pub fn new(x: f64) -> Result<Dosage, UnitError> {
+        if (0.0..=2.0).contains(&x) {
+            Ok(Dosage(x))
+        } else {
+            Err(UnitError::Dosage(x))
+        }
+    }
+
+    pub fn get(self) -> f64 {
+        self.0
+    }
+
+    pub fn flipped(self) -> Dosage {
+        Dosage(2.0 - self.0)
+    }
+
+    // The closest diploid hard call, if the dosage is within `max_distance`
+    // of it.
+    pub fn to_allele_count(self, max_distance: f64) -> Option<AlleleCount> {
+        let rounded = self.0.round();
+        if (self.0 - rounded).abs() <= max_distance {
+            Some(AlleleCount { count: rounded as u8, ploidy: 2 })
+        } else {
+            None
+        }
+    }
+}
+
+impl TryFrom<f64> for Dosage {
+    type Error = UnitError;
+
+    fn try_from(x: f64) -> Result<Dosage, UnitError> {
+        Dosage::new(x)
+    }
+}
+
+impl From<AlleleCount> for Dosage {
+    fn from(count: AlleleCount) -> Dosage {
+        count.to_dosage()
+    }
+}
+
+impl From<Dosage> for f64 {
+    fn from(d: Dosage) -> f64 {
+        d.0
+    }
+}
+
+
+// Frequency of an allele.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Frequency(f64);
+
+impl Frequency {
+    pub fn new(p: f64) -> Result<Frequency, UnitError> {
+        if (0.0..=1.0).contains(&p) {
+            Ok(Frequency(p))
+        } else {
+            Err(UnitError::Frequency(p))
+        }
+    }
+
+    // Frequency of `n_copies` out of `n_alleles` observed alleles (None if
+    // there are no observed alleles).
+    pub fn from_counts(n_copies: u64, n_alleles: u64) -> Option<Frequency> {
+        if n_alleles == 0 || n_copies > n_alleles {
+            None
+        } else {
+            Some(Frequency(n_copies as f64 / n_alleles as f64))
+        }
+    }
+
+    // Frequency from the sum of dosages of diploid samples.
+    pub fn from_dosages(sum: f64, n_samples: u64) -> Option<Frequency> {
+        if n_samples == 0 {
+            None
+        } else {
+            Frequency::new(sum / (2.0 * n_samples as f64)).ok()
+        }
+    }
+
+    pub fn get(self) -> f64 {
+        self.0
+    }
+
+    // Frequency of the other allele.
+    pub fn complement(self) -> Frequency {
+        Frequency(1.0 - self.0)
+    }
+
+    // Frequency of the minor allele.
+    pub fn minor(self) -> Frequency {
+        Frequency(self.0.min(1.0 - self.0))
+    }
+
+    // Expected dosage of a diploid sample under Hardy-Weinberg equilibrium.
+    pub fn expected_dosage(self) -> Dosage {
+        Dosage(2.0 * self.0)
+    }
+}
+
+impl TryFrom<f64> for Frequency {
+    type Error = UnitError;
+
+    fn try_from(p: f64) -> Result<Frequency, UnitError> {
+        Frequency::new(p)
+    }
+}
+
+impl From<Frequency> for f64 {
+    fn from(p: Frequency) -> f64 {
+        p.0
+    }
+}
+
+
+// Estimators of allele frequencies.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum FrequencyEstimator {
+    // Copies over the observed alleles (maximum likelihood).
+    #[default]
+    Observed,
+    // Copies over the alleles of all the samples, missing calls included
+    // (i.e. missing calls count as the other allele).
+    AllSamples,
+    // Add a pseudo-count to the copies of both alleles (e.g. 0.5 for the
+    // Jeffreys prior or 1 for Laplace smoothing).
+    PseudoCount(f64),
+    // Shrink towards a prior frequency, with the prior weighted as
+    // `weight` observed alleles.
+    Shrinkage { prior: Frequency, weight: f64 }
+}
+
+impl FrequencyEstimator {
+    // Estimate a frequency from the (expected) copies of the allele, the
+    // number of observed alleles and the number of alleles of all the
+    // samples. None if there is nothing to estimate from.
+    pub fn estimate(self, n_copies: f64, n_observed: f64, n_total: f64)
+        -> Option<Frequency>
+    {
+        let (numerator, denominator) = match self {
+            FrequencyEstimator::Observed => (n_copies, n_observed),
+            FrequencyEstimator::AllSamples => (n_copies, n_total),
+            FrequencyEstimator::PseudoCount(c) => {
+                (n_copies + c, n_observed + 2.0 * c)
+            },
+            FrequencyEstimator::Shrinkage { prior, weight } => {
+                (n_copies + weight * prior.0, n_observed + weight)
+            }
+        };
+
+        if denominator > 0.0 {
+            Frequency::new(numerator / denominator).ok()
+        } else {
+            None
+        }
+    }
+}
+
+// Parsed from `observed`, `all`, `pseudo[:count]` (0.5 by default) or
+// `shrink:prior:weight`.
+impl FromStr for FrequencyEstimator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FrequencyEstimator, String> {
+        let invalid = || format!("Invalid frequency estimator `{}` \
+                                  (expected observed, all, pseudo[:count] \
+                                  or shrink:prior:weight).", s);
+        let non_negative = |x: &str| {
+            x.parse::<f64>().ok().filter(|x| *x >= 0.0).ok_or_else(invalid)
+        };
+
+        let fields: Vec<&str> = s.split(':').collect();
+        match fields[..] {
+            ["observed"] => Ok(FrequencyEstimator::Observed),
+            ["all"] => Ok(FrequencyEstimator::AllSamples),
+            ["pseudo"] => Ok(FrequencyEstimator::PseudoCount(0.5)),
+            ["pseudo", c] => Ok(FrequencyEstimator::PseudoCount(
+                non_negative(c)?
+            )),
+            ["shrink", prior, weight] => Ok(FrequencyEstimator::Shrinkage {
+                prior: prior.parse().ok()
+                    .and_then(|p| Frequency::new(p).ok())
+                    .ok_or_else(invalid)?,
+                weight: non_negative(weight)?
+            }),
+            _ => Err(invalid())
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units() {
+        let het = AlleleCount::new(1, 2).unwrap();
+        assert_eq!(het.flipped().get(), 1);
+        assert_eq!(Dosage::from(het).get(), 1.0);
+        assert_eq!(AlleleCount::new(1, 1).unwrap().to_dosage().get(), 2.0);
+        assert_eq!(AlleleCount::new(2, 1),
+                   Err(UnitError::AlleleCount { count: 2, ploidy: 1 }));
+
+        assert!(Dosage::try_from(2.1).is_err());
+        assert!(Dosage::new(f64::NAN).is_err());
+        let d = Dosage::new(1.98).unwrap();
+        assert_eq!(d.to_allele_count(0.05).map(|c| c.get()), Some(2));
+        assert_eq!(d.to_allele_count(0.01), None);
+        assert_eq!(d.flipped().get(), 2.0 - 1.98);
+
+        let p = Frequency::from_counts(3, 4).unwrap();
+        assert_eq!(p.minor().get(), 0.25);
+        assert_eq!(p.complement(), Frequency::new(0.25).unwrap());
+        assert_eq!(p.expected_dosage().get(), 1.5);
+        assert_eq!(Frequency::from_counts(0, 0), None);
+        assert_eq!(Frequency::from_dosages(3.0, 2), Frequency::new(0.75).ok());
+        assert!(Frequency::try_from(-0.1).is_err());
+    }
+
+    #[test]
+    fn test_frequency_estimator() {
+        // 1 copy in 2 called diploid samples out of 3.
+        let estimate = |e: &str| {
+            e.parse::<FrequencyEstimator>().unwrap()
+                .estimate(1.0, 4.0, 6.0)
+                .map(f64::from)
+        };
+
+        assert_eq!(estimate("observed"), Some(0.25));
+        assert_eq!(estimate("all"), Some(1.0 / 6.0));
+        assert_eq!(estimate("pseudo"), Some(1.5 / 5.0));
+        assert_eq!(estimate("pseudo:1"), Some(2.0 / 6.0));
+        assert_eq!(estimate("shrink:0.1:6"), Some(1.6 / 10.0));
+
+        // Only the smoothed estimators have a value without observations.
+        assert_eq!(FrequencyEstimator::default().estimate(0.0, 0.0, 6.0),
+                   None);
+        assert_eq!(FrequencyEstimator::PseudoCount(1.0)
+                       .estimate(0.0, 0.0, 0.0),
+                   Frequency::new(0.5).ok());
+
+        assert!("pseudo:-1".parse::<FrequencyEstimator>().is_err());
+        assert!("shrink:2:10".parse::<FrequencyEstimator>().is_err());
+        assert!("mle".parse::<FrequencyEstimator>().is_err());
+    }
+}
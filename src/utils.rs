@@ -1,47 +1,76 @@
-use ndarray::ArrayViewMut;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use flate2::read::MultiGzDecoder;
 
 use crate::core::Genotypes;
+use crate::error::GenepaError;
+
+
+// Open a text file, decompressing it if its name ends with `.gz`.
+pub fn open_text_file(filename: &str) -> Box<dyn BufRead> {
+    try_open_text_file(filename)
+        .unwrap_or_else(|_| panic!("Could not open `{}`", filename))
+}
+
+
+// Fallible version of `open_text_file`.
+pub fn try_open_text_file(filename: &str)
+    -> Result<Box<dyn BufRead>, GenepaError>
+{
+    let f = File::open(filename)
+        .map_err(|e| GenepaError::io(filename, e))?;
 
-pub fn compute_ld(mut g: Genotypes, mut other_genotypes: Vec<Genotypes>, r2: bool)
+    if filename.ends_with(".gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(f))))
+    } else {
+        Ok(Box::new(BufReader::new(f)))
+    }
+}
+
+
+// Correlation (or squared correlation if `r2` is set) between the genotypes
+// of a variant and those of every other variant. Only the samples called for
+// both variants are used and NaN is returned if either is monomorphic.
+pub fn compute_ld(g: Genotypes, other_genotypes: Vec<Genotypes>, r2: bool)
     -> Vec<f64>
 {
-    let n_samples = g.genotypes.len();
-    let n_variants = other_genotypes.len();
+    other_genotypes.iter()
+        .map(|other| {
+            let r = correlation(&g, other);
+            if r2 { r * r } else { r }
+        })
+        .collect()
+}
+
 
-    let mut other_geno_data: Vec<&Option<u8>> = other_genotypes.iter_mut()
-        .flat_map(|g| &g.genotypes)
+fn correlation(g1: &Genotypes, g2: &Genotypes) -> f64 {
+    if g1.genotypes.len() != g2.genotypes.len() {
+        panic!("Can't compute the LD between `{}` and `{}` which have \
+                different numbers of samples.", g1.variant, g2.variant);
+    }
+
+    let pairs: Vec<(f64, f64)> = g1.genotypes.iter()
+        .zip(g2.genotypes.iter())
+        .filter_map(|(x, y)| Some((f64::from((*x)?), f64::from((*y)?))))
         .collect();
 
-    let geno_arr = ArrayViewMut::from_shape(
-        (1, n_samples),
-        &mut g.genotypes
-    ).unwrap();
-
-    let geno_arr_others = ArrayViewMut::from_shape(
-        (n_samples, n_variants),
-        &mut other_geno_data
-    ).unwrap();
-
-    // Example computing maf.
-    // acc is n_alleles, n_samples
-    let (n_alleles, n_samples) = geno_arr.fold((0, 0), |acc, x| {
-        match x {
-            Some(geno) => {
-                (
-                    (acc.0 + *geno as u64),
-                    acc.1 + 1
-                )
-            },
-            _ => acc
-        }
-    });
-
-    println!("{:?}", n_alleles);
-    println!("{:?}", n_samples);
-    println!("{:?}", n_alleles as f64 / (2.0 * n_samples as f64));
-
-    // TODO
-    vec![3.2]
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+
+    let (mut sxy, mut sxx, mut syy) = (0.0, 0.0, 0.0);
+    for (x, y) in pairs.iter() {
+        sxy += (x - mean_x) * (y - mean_y);
+        sxx += (x - mean_x).powi(2);
+        syy += (y - mean_y).powi(2);
+    }
+
+    if sxx == 0.0 || syy == 0.0 {
+        return f64::NAN;
+    }
+
+    sxy / (sxx * syy).sqrt()
 }
 
 #[cfg(test)]
@@ -49,12 +78,30 @@ mod tests {
     use crate::plink::PlinkReader;
     use crate::core::{Chromosome, Variant};
     use super::*;
+    use crate::test_util::genotypes;
+
+    #[test]
+    fn test_compute_ld() {
+        let g = genotypes(1, vec![Some(0), Some(1), Some(2), None]);
+
+        let ld = compute_ld(g, vec![
+            genotypes(2, vec![Some(0), Some(1), Some(2), Some(2)]),
+            genotypes(3, vec![Some(2), Some(1), Some(0), Some(0)]),
+            genotypes(4, vec![Some(0), Some(0), Some(1), Some(0)]),
+            genotypes(5, vec![Some(1), Some(1), Some(1), Some(0)]),
+        ], false);
+
+        assert!((ld[0] - 1.0).abs() < 1e-12);
+        assert!((ld[1] + 1.0).abs() < 1e-12);
+        assert!((ld[2] - 0.75_f64.sqrt()).abs() < 1e-12);
+        assert!(ld[3].is_nan());
+    }
 
     #[test]
     fn test_test() {
         let mut plink = PlinkReader::new(
             "./test_data/common_extracted_1kg.missing"
-        );
+        ).unwrap();
 
         let v = Variant::new(
             "rs77883301".to_string(),
@@ -64,7 +111,7 @@ mod tests {
         );
         let g = plink.get_variant_genotypes(&v).unwrap();
 
-        let chr = Chromosome { name: "16".to_string() };
+        let chr = Chromosome::Autosome(16);
         let other_geno = plink.get_variants_in_region(
             &chr,
             56651160,
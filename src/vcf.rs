@@ -0,0 +1,1081 @@
+/*!
+ * Reader for VCF files.
+ *
+ * The GT field is converted to the number of copies of the ALT allele so
+ * that the reader yields the same `Genotypes` as the `PlinkReader`. Only
+ * biallelic variants are supported: multiallelic records are skipped unless
+ * they are split into biallelic variants (see `split_multiallelic`), and
+ * symbolic alleles are skipped. The phased calls can also be read as
+ * haplotypes (see `haplotypes`).
+ *
+ * Sites-only VCFs (without samples) can omit the FORMAT column. The
+ * iterators panic on malformed records, while `try_next` returns a
+ * GenepaError instead.
+ *
+ * Bgzipped VCFs are decompressed using bgzip and region queries use a
+ * tabix index (created if needed), like the BIM index.
+ *
+ * The VcfWriter does the opposite conversion: the coded allele is written as
+ * the ALT allele, so the REF column is not necessarily the reference genome
+ * allele.
+ */
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Lines, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+
+use crate::core::{Chromosome, Dosages, Genotypes, Haplotypes,
+                  MultiAllelicGenotypes,
+                  MultiAllelicVariant, Sample, Sex, Variant, VariantBuilder,
+                  VariantError, VariantKind};
+use crate::error::GenepaError;
+use crate::source::RegionPage;
+
+
+pub struct VcfReader<R: BufRead> {
+    lines: Lines<R>,
+    // Filename used in the errors (`<vcf>` for other readers).
+    path: String,
+    // Bgzipped VCF used for region queries.
+    indexed_filename: Option<String>,
+    // Decompression process for bgzipped VCFs.
+    bgzip: Option<Child>,
+    samples: Arc<Vec<Sample>>,
+    attach_samples: bool,
+    split_multiallelic: bool,
+    // Biallelic variants split from the last multiallelic record.
+    pending: VecDeque<Genotypes>,
+    line_number: usize,
+    n_skipped: u64
+}
+
+
+impl VcfReader<Box<dyn BufRead>> {
+    // Open a plain text or bgzipped (`.vcf.gz`) VCF.
+    pub fn new(filename: &str) -> VcfReader<Box<dyn BufRead>> {
+        if !filename.ends_with(".gz") {
+            let f = File::open(filename).unwrap_or_else(|_| {
+                panic!("Could not open VCF: `{}`", filename)
+            });
+
+            let mut reader = VcfReader::from_reader(
+                Box::new(BufReader::new(f)) as Box<dyn BufRead>
+            );
+            reader.path = filename.to_string();
+
+            return reader;
+        }
+
+        let mut bgzip = Command::new("bgzip")
+            .args(["-dc", filename])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Couldn't spawn bgzip to read the VCF.");
+
+        let stdout = bgzip.stdout.take().expect("Could not get bgzip stdout.");
+
+        let mut reader = VcfReader::from_reader(
+            Box::new(BufReader::new(stdout)) as Box<dyn BufRead>
+        );
+        reader.path = filename.to_string();
+        reader.indexed_filename = Some(filename.to_string());
+        reader.bgzip = Some(bgzip);
+
+        reader
+    }
+}
+
+
+impl<R: BufRead> VcfReader<R> {
+    pub fn from_reader(reader: R) -> VcfReader<R> {
+        let mut lines = reader.lines();
+        let mut line_number = 0;
+
+        // Skip the meta-information lines up to the header.
+        let header = loop {
+            line_number += 1;
+            let line = lines.next()
+                .expect("Reached the end of the VCF before the header.")
+                .expect("Could not read from VCF.");
+
+            if line.starts_with("#CHROM") {
+                break line;
+            }
+
+            if !line.starts_with("##") {
+                panic!("Expected a VCF header line but got: `{}`", line);
+            }
+        };
+
+        // VCFs have a single sample identifier which is used as both the
+        // family and individual IDs, as plink does.
+        let samples = header.split('\t')
+            .skip(9)
+            .map(|id| Sample::new(id.to_string(), id.to_string(), Sex::Unknown))
+            .collect();
+
+        VcfReader {
+            lines,
+            path: "<vcf>".to_string(),
+            indexed_filename: None,
+            bgzip: None,
+            samples: Arc::new(samples),
+            attach_samples: false,
+            split_multiallelic: false,
+            pending: VecDeque::new(),
+            line_number,
+            n_skipped: 0
+        }
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn attach_samples(&mut self, attach: bool) {
+        self.attach_samples = attach;
+    }
+
+    // If set, the multiallelic records are split into biallelic variants
+    // (see `MultiAllelicGenotypes::split`) instead of being skipped.
+    pub fn split_multiallelic(&mut self, split: bool) {
+        self.split_multiallelic = split;
+    }
+
+    // Number of records that were skipped because they are not biallelic
+    // (unless split) or only have symbolic alleles.
+    pub fn n_skipped(&self) -> u64 {
+        self.n_skipped
+    }
+
+    // Iterate over the phased calls instead of the genotypes. Only the
+    // biallelic records are read.
+    pub fn haplotypes(self) -> VcfHaplotypes<R> {
+        VcfHaplotypes { reader: self }
+    }
+
+    // Make sure the VCF is bgzipped and indexed and return its filename.
+    fn tabix_filename(&self) -> &str {
+        let filename = self.indexed_filename.as_ref()
+            .expect("Region queries require a bgzipped VCF.");
+
+        let has_index = Path::new(&format!("{}.tbi", filename)).is_file() ||
+                        Path::new(&format!("{}.csi", filename)).is_file();
+
+        if !has_index {
+            let tabix = Command::new("tabix")
+                .args(["-p", "vcf", filename])
+                .output()
+                .expect("Tabix failed.");
+
+            if !tabix.status.success() {
+                panic!("Tabix returned an error, could not index the VCF.");
+            }
+        }
+
+        filename
+    }
+
+    // Name of the chromosome as written in the VCF (e.g. `chr1` for `1`).
+    fn contig_name(&self, chrom: &Chromosome) -> String {
+        let tabix = Command::new("tabix")
+            .args(["-l", self.tabix_filename()])
+            .output()
+            .expect("Couldn't spawn tabix to list the VCF contigs.");
+
+        String::from_utf8(tabix.stdout)
+            .unwrap()
+            .lines()
+            .find(|name| Chromosome::new(name) == *chrom)
+            .map_or_else(|| chrom.to_string(), |name| name.to_string())
+    }
+
+    fn _run_tabix(&self, region: &str) -> Vec<Genotypes> {
+        let tabix = Command::new("tabix")
+            .arg(self.tabix_filename())
+            .arg(region)
+            .output()
+            .expect("Couldn't spawn tabix for VCF query.");
+
+        if !tabix.status.success() {
+            panic!("Error searching the VCF using tabix.");
+        }
+
+        let path = format!("{} ({})", self.path, region);
+        String::from_utf8(tabix.stdout)
+            .unwrap()
+            .lines()
+            .enumerate()
+            .flat_map(|(i, line)| self._parse_tabix(&path, i + 1, line))
+            .collect()
+    }
+
+    // The line numbers of the errors are relative to the tabix output.
+    fn _parse_tabix(&self, path: &str, line_number: usize, line: &str)
+        -> Vec<Genotypes>
+    {
+        self.parse_record(line)
+            .map_err(|message| {
+                GenepaError::parse(path, line_number, &message)
+            })
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn get_variants_in_region(&self, chrom: &Chromosome, start: u32,
+                                  end: u32) -> Vec<Genotypes>
+    {
+        let contig = self.contig_name(chrom);
+        self._run_tabix(&format!("{}:{}-{}", contig, start, end))
+    }
+
+    // The tabix output is streamed so that only the page is parsed.
+    pub fn get_variants_in_region_page(&self, chrom: &Chromosome, start: u32,
+                                       end: u32, offset: usize, limit: usize)
+        -> RegionPage
+    {
+        let region = format!("{}:{}-{}", self.contig_name(chrom), start, end);
+        let path = format!("{} ({})", self.path, region);
+
+        let mut tabix = Command::new("tabix")
+            .arg(self.tabix_filename())
+            .arg(&region)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Couldn't spawn tabix for VCF query.");
+
+        let stdout = tabix.stdout.take().expect("Could not get tabix stdout.");
+
+        let genotypes = BufReader::new(stdout)
+            .lines()
+            .map(|line| line.expect("Could not read tabix output."))
+            .enumerate()
+            .flat_map(|(i, line)| self._parse_tabix(&path, i + 1, &line))
+            .skip(offset);
+
+        let page = RegionPage::collect(genotypes, offset, limit);
+
+        // The remaining results are not needed.
+        let _ = tabix.kill();
+        let _ = tabix.wait();
+
+        page
+    }
+
+    pub fn get_variant_genotypes(&self, v: &Variant) -> Option<Genotypes> {
+        let mut matches: Vec<Genotypes> = self
+            .get_variants_in_region(&v.chrom, v.position, v.position)
+            .into_iter()
+            .filter(|g| g.variant == *v)
+            .collect();
+
+        match matches.len() {
+            0 => None,
+            1 => matches.pop(),
+            _ => panic!("There are duplicate variants in the VCF.")
+        }
+    }
+
+    // Make sure that the decompression completed successfully.
+    fn _check_termination(&mut self) {
+        if let Some(mut bgzip) = self.bgzip.take() {
+            let status = bgzip.wait().expect("Error waiting for bgzip.");
+            if !status.success() {
+                panic!("Error decompressing the VCF using bgzip.");
+            }
+        }
+    }
+
+    // Fields of a record. Sites-only VCFs (without samples) can omit the
+    // FORMAT column.
+    fn split_fields<'a>(&self, line: &'a str) -> Result<Vec<&'a str>, String> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let expected = 9 + self.samples.len();
+        let sites_only = self.samples.is_empty() && fields.len() == 8;
+
+        if fields.len() != expected && !sites_only {
+            return Err(format!("expected {} fields but found {}", expected,
+                               fields.len()));
+        }
+
+        Ok(fields)
+    }
+
+    // GT value of every sample (`.` if missing from a sample field).
+    fn gt_values<'a>(&self, fields: &[&'a str])
+        -> Result<Vec<&'a str>, String>
+    {
+        if self.samples.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let gt_idx = fields[8].split(':')
+            .position(|key| key == "GT")
+            .ok_or_else(|| "no GT field".to_string())?;
+
+        Ok(fields[9..].iter()
+            .map(|field| field.split(':').nth(gt_idx).unwrap_or("."))
+            .collect())
+    }
+
+    // Genotypes of a record: none if the record is skipped, and one per
+    // alternate allele for the multiallelic records that are split.
+    fn parse_record(&self, line: &str) -> Result<Vec<Genotypes>, String> {
+        let fields = self.split_fields(line)?;
+
+        let (reference, alt) = (fields[3], fields[4]);
+        let alts: Vec<&str> = alt.split(',').collect();
+        if alts.len() > 1 && !self.split_multiallelic {
+            return Ok(Vec::new());
+        }
+
+        let name = if fields[2] == "." {
+            format!("{}:{}", fields[0], fields[1])
+        } else {
+            fields[2].to_string()
+        };
+
+        // The variants with symbolic alleles are None.
+        let variants: Vec<Option<Variant>> = alts.iter()
+            .map(|alt| {
+                try_build_variant(&name, fields[0], fields[1], reference, alt)
+            })
+            .collect::<Result<_, _>>()?;
+
+        let first = match variants.iter().flatten().next() {
+            Some(v) => v.clone(),
+            None => return Ok(Vec::new())
+        };
+
+        let gts = self.gt_values(&fields)?;
+        let invalid = |gt: &str| format!("invalid genotype `{}`", gt);
+
+        let genotypes = if alts.len() == 1 {
+            let calls: Vec<(Option<u8>, u8)> = gts.iter()
+                .map(|gt| parse_gt(gt).ok_or_else(|| invalid(gt)))
+                .collect::<Result<_, _>>()?;
+
+            vec![make_genotypes(first, alt, calls)]
+        } else {
+            let calls: Vec<Option<Vec<u8>>> = gts.iter()
+                .map(|gt| {
+                    parse_gt_alleles(gt)
+                        .filter(|call| {
+                            call.iter()
+                                .flatten()
+                                .all(|&a| usize::from(a) <= alts.len())
+                        })
+                        .ok_or_else(|| invalid(gt))
+                })
+                .collect::<Result<_, _>>()?;
+
+            let mut alleles = vec![reference.to_string()];
+            alleles.extend(alts.iter().map(|alt| alt.to_string()));
+            let variant = MultiAllelicVariant::new(
+                name, first.chrom.to_string(), first.position, alleles
+            );
+
+            MultiAllelicGenotypes::new(variant, calls)
+                .split()
+                .into_iter()
+                .zip(variants.iter())
+                .filter(|(_, v)| v.is_some())
+                .map(|(g, _)| g)
+                .collect()
+        };
+
+        if self.attach_samples {
+            Ok(genotypes.into_iter()
+                .map(|g| g.with_samples(Arc::clone(&self.samples)))
+                .collect())
+        } else {
+            Ok(genotypes)
+        }
+    }
+}
+
+
+impl<R: BufRead> VcfReader<R> {
+    // Next record (None at the end of the VCF).
+    fn next_line(&mut self) -> Option<Result<String, GenepaError>> {
+        loop {
+            let line = match self.lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(e)) => {
+                    return Some(Err(GenepaError::io(&self.path, e)));
+                },
+                None => {
+                    self._check_termination();
+                    return None;
+                }
+            };
+
+            self.line_number += 1;
+
+            if !line.is_empty() {
+                return Some(Ok(line));
+            }
+        }
+    }
+
+    fn parse_error(&self, message: String) -> GenepaError {
+        GenepaError::parse(&self.path, self.line_number, &message)
+    }
+
+    // Phased calls of a biallelic record (copies of the ALT allele on the
+    // two haplotypes). The unphased, haploid and missing calls are None.
+    fn parse_haplotypes(&self, line: &str)
+        -> Result<Option<Haplotypes>, String>
+    {
+        let fields = self.split_fields(line)?;
+
+        let (reference, alt) = (fields[3], fields[4]);
+        if alt.contains(',') {
+            return Ok(None);
+        }
+
+        let name = if fields[2] == "." {
+            format!("{}:{}", fields[0], fields[1])
+        } else {
+            fields[2].to_string()
+        };
+
+        let variant = match try_build_variant(&name, fields[0], fields[1],
+                                              reference, alt)? {
+            Some(variant) => variant,
+            None => return Ok(None)
+        };
+
+        let haplotypes = self.gt_values(&fields)?
+            .into_iter()
+            .map(|gt| {
+                let (a, b) = gt.split_once('|')?;
+                let allele = |a: &str| match a {
+                    "0" => Some(0),
+                    "1" => Some(1),
+                    _ => None
+                };
+                Some((allele(a)?, allele(b)?))
+            })
+            .collect();
+
+        let h = Haplotypes::new(variant, haplotypes, alt);
+        if self.attach_samples {
+            Ok(Some(h.with_samples(Arc::clone(&self.samples))))
+        } else {
+            Ok(Some(h))
+        }
+    }
+}
+
+
+pub struct VcfHaplotypes<R: BufRead> {
+    reader: VcfReader<R>
+}
+
+impl<R: BufRead> VcfHaplotypes<R> {
+    pub fn samples(&self) -> &[Sample] {
+        self.reader.samples()
+    }
+
+    pub fn n_skipped(&self) -> u64 {
+        self.reader.n_skipped
+    }
+
+    // Next haplotypes, or the error of the first invalid record.
+    pub fn try_next(&mut self) -> Option<Result<Haplotypes, GenepaError>> {
+        loop {
+            let line = match self.reader.next_line()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e))
+            };
+
+            match self.reader.parse_haplotypes(&line) {
+                Ok(Some(h)) => return Some(Ok(h)),
+                Ok(None) => self.reader.n_skipped += 1,
+                Err(message) => {
+                    return Some(Err(self.reader.parse_error(message)));
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for VcfHaplotypes<R> {
+    type Item = Haplotypes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().map(|h| h.unwrap_or_else(|e| panic!("{}", e)))
+    }
+}
+
+
+// Validate the variant from a record. Returns None for variants with
+// symbolic alleles (e.g. `<DEL>` or `*`) which can't be represented.
+pub(crate) fn build_variant(name: &str, chrom: &str, position: &str,
+                            reference: &str, alt: &str, location: &str)
+    -> Option<Variant>
+{
+    try_build_variant(name, chrom, position, reference, alt)
+        .unwrap_or_else(|e| panic!("Invalid variant on {}: {}", location, e))
+}
+
+
+fn try_build_variant(name: &str, chrom: &str, position: &str,
+                     reference: &str, alt: &str)
+    -> Result<Option<Variant>, String>
+{
+    let variant = VariantBuilder::new()
+        .name(name)
+        .chrom(chrom)
+        .position_str(position)
+        .alleles(reference, alt)
+        .build();
+
+    match variant {
+        Ok(v) if v.kind() == VariantKind::Symbolic => Ok(None),
+        Ok(v) => Ok(Some(v)),
+        Err(VariantError::InvalidAllele(_)) => Ok(None),
+        Err(e) => Err(format!("invalid variant: {}", e))
+    }
+}
+
+
+// Genotypes from the number of ALT alleles and ploidy of every call.
+//
+// Variants where every call is haploid (e.g. on Y or MT) are represented as
+// haploid. Otherwise, haploid calls are coded as homozygous (e.g. for males
+// on the X chromosome).
+pub(crate) fn make_genotypes(variant: Variant, alt: &str,
+                             calls: Vec<(Option<u8>, u8)>) -> Genotypes
+{
+    let haploid = calls.iter()
+        .filter(|(g, _)| g.is_some())
+        .all(|(_, ploidy)| *ploidy == 1) &&
+        calls.iter().any(|(g, _)| g.is_some());
+
+    let genotypes = calls.into_iter()
+        .map(|(g, ploidy)| {
+            if ploidy == 1 && !haploid { g.map(|x| 2 * x) } else { g }
+        })
+        .collect();
+
+    Genotypes::new(variant, genotypes, alt)
+        .with_ploidy(if haploid { 1 } else { 2 })
+}
+
+
+// Parse a GT value into the number of ALT alleles (None if missing) and the
+// number of alleles in the call. Returns None if the value is invalid.
+fn parse_gt(gt: &str) -> Option<(Option<u8>, u8)> {
+    let mut n_alt = 0;
+    let mut ploidy = 0;
+    let mut missing = false;
+
+    for allele in gt.split(['/', '|']) {
+        ploidy += 1;
+        match allele {
+            "0" => {},
+            "1" => n_alt += 1,
+            "." => missing = true,
+            _ => return None
+        }
+    }
+
+    if ploidy > 2 {
+        return None;
+    }
+
+    Some((if missing { None } else { Some(n_alt) }, ploidy))
+}
+
+
+// Parse a GT value into the indices of the alleles (None if missing). Returns
+// None if the value is invalid.
+fn parse_gt_alleles(gt: &str) -> Option<Option<Vec<u8>>> {
+    let alleles: Vec<&str> = gt.split(['/', '|']).collect();
+    if alleles.len() > 2 {
+        return None;
+    }
+
+    if alleles.contains(&".") {
+        return Some(None);
+    }
+
+    alleles.iter()
+        .map(|allele| allele.parse().ok())
+        .collect::<Option<Vec<u8>>>()
+        .map(Some)
+}
+
+
+impl<R: BufRead> VcfReader<R> {
+    // Next genotypes, or the error of the first invalid record.
+    pub fn try_next(&mut self) -> Option<Result<Genotypes, GenepaError>> {
+        loop {
+            if let Some(g) = self.pending.pop_front() {
+                return Some(Ok(g));
+            }
+
+            let line = match self.next_line()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e))
+            };
+
+            let genotypes = match self.parse_record(&line) {
+                Ok(genotypes) => genotypes,
+                Err(message) => return Some(Err(self.parse_error(message)))
+            };
+            if genotypes.is_empty() {
+                self.n_skipped += 1;
+            }
+
+            self.pending.extend(genotypes);
+        }
+    }
+}
+
+
+// Stop the decompression if the VCF wasn't read to the end.
+impl<R: BufRead> Drop for VcfReader<R> {
+    fn drop(&mut self) {
+        if let Some(mut bgzip) = self.bgzip.take() {
+            let _ = bgzip.kill();
+            let _ = bgzip.wait();
+        }
+    }
+}
+
+
+impl<R: BufRead> Iterator for VcfReader<R> {
+    type Item = Genotypes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().map(|g| g.unwrap_or_else(|e| panic!("{}", e)))
+    }
+}
+
+
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+
+// Alleles that can be written in the REF and ALT columns.
+fn is_vcf_allele(allele: &str) -> bool {
+    !allele.is_empty() &&
+        allele.chars().all(|c| matches!(c, 'A' | 'C' | 'G' | 'T' | 'N'))
+}
+
+
+// Sample column name: the IID, prefixed by the FID if it is informative (as
+// plink does when exporting VCFs).
+pub(crate) fn vcf_sample_id(s: &Sample) -> String {
+    if s.fid == s.iid || s.fid == "0" {
+        s.iid.clone()
+    } else {
+        format!("{}_{}", s.fid, s.iid)
+    }
+}
+
+
+fn format_gt(call: Option<u8>, ploidy: u8) -> &'static str {
+    match (ploidy, call) {
+        (1, Some(0)) => "0",
+        (1, Some(_)) => "1",
+        (1, None) => ".",
+        (_, Some(0)) => "0/0",
+        (_, Some(1)) => "0/1",
+        (_, Some(_)) => "1/1",
+        (_, None) => "./."
+    }
+}
+
+
+// Writer for VCF 4.2 files. The header (with the contigs and samples) is
+// written when the writer is created. If the writer has dosages, every
+// record has a DS field (dosage of the ALT allele) after the GT.
+pub struct VcfWriter<W: Write> {
+    out: W,
+    contigs: Vec<String>,
+    n_samples: usize,
+    dosages: bool
+}
+
+impl VcfWriter<io::BufWriter<File>> {
+    pub fn create(filename: &str, samples: &[Sample], contigs: &[&str],
+                  dosages: bool) -> io::Result<VcfWriter<io::BufWriter<File>>>
+    {
+        let f = io::BufWriter::new(File::create(filename)?);
+        VcfWriter::new(f, samples, contigs, dosages)
+    }
+}
+
+impl<W: Write> VcfWriter<W> {
+    pub fn new(mut out: W, samples: &[Sample], contigs: &[&str],
+               dosages: bool) -> io::Result<VcfWriter<W>>
+    {
+        writeln!(out, "##fileformat=VCFv4.2")?;
+        writeln!(out, "##source=genepa")?;
+        for contig in contigs {
+            writeln!(out, "##contig=<ID={}>", contig)?;
+        }
+
+        writeln!(out, "##FORMAT=<ID=GT,Number=1,Type=String,\
+                       Description=\"Genotype\">")?;
+        if dosages {
+            writeln!(out, "##FORMAT=<ID=DS,Number=A,Type=Float,\
+                           Description=\"Dosage of the ALT allele\">")?;
+        }
+
+        write!(out, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT")?;
+        for s in samples {
+            write!(out, "\t{}", vcf_sample_id(s))?;
+        }
+        writeln!(out)?;
+
+        Ok(VcfWriter {
+            out,
+            contigs: contigs.iter().map(|c| c.to_string()).collect(),
+            n_samples: samples.len(),
+            dosages
+        })
+    }
+
+    // Write a record. The dosages are required if the writer has dosages
+    // and must describe the same variant.
+    pub fn write(&mut self, g: &Genotypes, dosages: Option<&Dosages>)
+        -> io::Result<()>
+    {
+        let v = &g.variant;
+
+        // The contig is written as named in the header (e.g. `chr1`).
+        let contig = match self.contigs.iter()
+            .find(|contig| Chromosome::new(contig) == v.chrom)
+        {
+            Some(contig) => contig,
+            None => return Err(invalid_input(format!(
+                "The contig of `{}` is not in the VCF header.", v.name
+            )))
+        };
+
+        if g.genotypes.len() != self.n_samples {
+            return Err(invalid_input(format!(
+                "Got {} genotypes for {} samples.", g.genotypes.len(),
+                self.n_samples
+            )));
+        }
+
+        let (reference, alt) = (g.other_allele(), g.coded_allele());
+        if !is_vcf_allele(reference) || !is_vcf_allele(alt) {
+            return Err(invalid_input(format!(
+                "The alleles of `{}` can't be written in a VCF.", v.name
+            )));
+        }
+
+        // Dosages of the ALT allele.
+        let dosages: Option<Vec<Option<f64>>> = match (self.dosages, dosages) {
+            (false, _) => None,
+            (true, None) => {
+                return Err(invalid_input(format!(
+                    "Missing dosages for `{}`.", v.name
+                )));
+            },
+            (true, Some(d)) => {
+                if d.variant != *v || d.dosages.len() != self.n_samples {
+                    return Err(invalid_input(format!(
+                        "The dosages don't match the genotypes of `{}`.",
+                        v.name
+                    )));
+                }
+
+                let flip = d.coded_allele() != alt;
+                Some(d.dosages.iter()
+                    .map(|x| x.map(|x| if flip { 2.0 - x } else { x }))
+                    .collect())
+            }
+        };
+
+        let id = if v.name.is_empty() { "." } else { &v.name };
+        write!(self.out, "{}\t{}\t{}\t{}\t{}\t.\t.\t.\t{}", contig,
+               v.position, id, reference, alt,
+               if dosages.is_some() { "GT:DS" } else { "GT" })?;
+
+        for (i, call) in g.genotypes.iter().enumerate() {
+            write!(self.out, "\t{}", format_gt(*call, g.ploidy()))?;
+
+            if let Some(dosages) = &dosages {
+                match dosages[i] {
+                    Some(x) => write!(self.out, ":{:.3}", x)?,
+                    None => write!(self.out, ":.")?
+                }
+            }
+        }
+
+        writeln!(self.out)
+    }
+
+    // Flush and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const VCF: &str = "\
+##fileformat=VCFv4.2
+##contig=<ID=1>
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ts1\ts2\ts3
+chr1\t100\trs1\tA\tG\t.\tPASS\t.\tGT:DP\t0/0:10\t0|1:12\t1/1:8
+1\t200\t.\tC\tT,G\t.\tPASS\t.\tGT\t0/1\t0/2\t./.
+1\t300\t.\tC\t<DEL>\t.\tPASS\t.\tGT\t0/1\t0/0\t./.
+X\t400\trs2\tT\tC\t.\tPASS\t.\tDP:GT\t3:1\t4:0/1\t5:.
+MT\t500\trs3\tG\tA\t.\tPASS\t.\tGT\t1\t0\t.
+";
+
+    fn get_reader() -> VcfReader<Cursor<&'static str>> {
+        VcfReader::from_reader(Cursor::new(VCF))
+    }
+
+    #[test]
+    fn test_writer() {
+        let samples: Vec<Sample> = ["s1", "s2", "s3"].iter()
+            .map(|id| Sample::new("0".to_string(), id.to_string(),
+                                  Sex::Unknown))
+            .collect();
+
+        let v = Variant::new("rs1".to_string(), "1".to_string(), 100,
+                             ("A".to_string(), "G".to_string()));
+        let g = Genotypes::new(v.clone(), vec![Some(0), Some(1), None], "G");
+        let d = Dosages::new(v, vec![Some(1.9), Some(1.0), None], "A");
+
+        let mut writer = VcfWriter::new(Vec::new(), &samples, &["1"], true)
+            .unwrap();
+        writer.write(&g, Some(&d)).unwrap();
+
+        // The genotypes of other contigs are rejected.
+        let y = Variant::new("rs2".to_string(), "Y".to_string(), 10,
+                             ("A".to_string(), "G".to_string()));
+        let haploid = Genotypes::new(y, vec![Some(0), Some(1), None], "G")
+            .with_ploidy(1);
+        assert!(writer.write(&haploid, Some(&d)).is_err());
+
+        let vcf = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert!(vcf.starts_with("##fileformat=VCFv4.2\n"));
+        assert!(vcf.contains("##contig=<ID=1>\n"));
+        assert!(vcf.ends_with("1\t100\trs1\tA\tG\t.\t.\t.\tGT:DS\t\
+                               0/0:0.100\t0/1:1.000\t./.:.\n"));
+
+        // Read back the genotypes.
+        let mut writer = VcfWriter::new(Vec::new(), &samples, &["1", "Y"],
+                                        false).unwrap();
+        writer.write(&g, None).unwrap();
+        writer.write(&haploid, None).unwrap();
+        let vcf = writer.finish().unwrap();
+
+        let mut reader = VcfReader::from_reader(Cursor::new(vcf));
+        assert_eq!(reader.samples()[1].iid, "s2");
+
+        let read: Vec<Genotypes> = reader.by_ref().collect();
+        assert_eq!(read[0].genotypes, g.genotypes);
+        assert_eq!(read[0].coded_allele(), "G");
+        assert_eq!(read[1].genotypes, haploid.genotypes);
+        assert!(read[1].is_haploid());
+    }
+
+    #[test]
+    fn test_samples() {
+        let reader = get_reader();
+        let iids: Vec<&str> = reader.samples().iter()
+            .map(|s| s.iid.as_str())
+            .collect();
+
+        assert_eq!(iids, vec!["s1", "s2", "s3"]);
+    }
+
+    #[test]
+    fn test_read() {
+        let mut reader = get_reader();
+        let genotypes: Vec<Genotypes> = reader.by_ref().collect();
+
+        assert_eq!(genotypes.len(), 3);
+        assert_eq!(reader.n_skipped(), 2);
+
+        let g = &genotypes[0];
+        assert_eq!(g.variant.chrom.to_string(), "1");
+        assert_eq!(g.coded_allele(), "G");
+        assert_eq!(g.genotypes, vec![Some(0), Some(1), Some(2)]);
+
+        // The haploid call on X is coded as homozygous.
+        assert_eq!(genotypes[1].genotypes, vec![Some(2), Some(1), None]);
+        assert!(!genotypes[1].is_haploid());
+
+        assert!(genotypes[2].is_haploid());
+        assert_eq!(genotypes[2].genotypes, vec![Some(1), Some(0), None]);
+        assert_eq!(genotypes[2].variant.name, "rs3");
+    }
+
+    #[test]
+    fn test_haplotypes() {
+        let mut reader = get_reader().haplotypes();
+        let haplotypes: Vec<Haplotypes> = reader.by_ref().collect();
+        assert_eq!(haplotypes.len(), 3);
+        assert_eq!(reader.n_skipped(), 2);
+
+        assert_eq!(haplotypes[0].coded_allele(), "G");
+        assert_eq!(haplotypes[0].haplotypes, vec![None, Some((0, 1)), None]);
+        assert_eq!(haplotypes[2].n_phased(), 0);
+    }
+
+    #[test]
+    fn test_split_multiallelic() {
+        let mut reader = get_reader();
+        reader.split_multiallelic(true);
+        let genotypes: Vec<Genotypes> = reader.by_ref().collect();
+
+        assert_eq!(genotypes.len(), 5);
+        assert_eq!(reader.n_skipped(), 1);
+
+        // The other ALT allele is counted as the REF allele.
+        let (t, g) = (&genotypes[1], &genotypes[2]);
+        assert_eq!(t.variant.name, "1:200");
+        assert_eq!(t.coded_allele(), "T");
+        assert_eq!(t.other_allele(), "C");
+        assert_eq!(t.genotypes, vec![Some(1), Some(0), None]);
+        assert_eq!(g.coded_allele(), "G");
+        assert_eq!(g.genotypes, vec![Some(0), Some(1), None]);
+    }
+
+    #[test]
+    fn test_sites_only() {
+        let vcf = "##fileformat=VCFv4.2\n\
+                   #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+                   1\t100\trs1\tA\tG\t.\tPASS\t.\n\
+                   1\t200\trs2\tC\tT\t.\tPASS\t.\tGT\n";
+
+        let genotypes: Vec<Genotypes> =
+            VcfReader::from_reader(Cursor::new(vcf)).collect();
+        assert_eq!(genotypes.len(), 2);
+        assert_eq!(genotypes[0].variant.name, "rs1");
+        assert!(genotypes[0].genotypes.is_empty());
+
+        let haplotypes: Vec<Haplotypes> =
+            VcfReader::from_reader(Cursor::new(vcf)).haplotypes().collect();
+        assert_eq!(haplotypes.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let header = "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\t\
+                      FORMAT\ts1\ts2\n";
+        let records = [
+            "1\t100\trs1\tA\tG\t.\tPASS\t.\tGT\t0/1\n",
+            "1\t100\trs1\tA\tG\t.\tPASS\t.\tDP\t1\t2\n",
+            "1\t100\trs1\tA\tG\t.\tPASS\t.\tGT\t0/1\t0/3\n",
+            "1\tx\trs1\tA\tG\t.\tPASS\t.\tGT\t0/1\t0/1\n"
+        ];
+
+        for record in records.iter() {
+            let first = "1\t50\trs0\tA\tG\t.\t.\t.\tGT\t0/0\t1/1\n";
+            let vcf = format!("{}{}{}", header, first, record);
+            let mut reader = VcfReader::from_reader(Cursor::new(vcf));
+            assert!(reader.try_next().unwrap().is_ok());
+
+            match reader.try_next() {
+                Some(Err(GenepaError::Parse { line, .. })) => {
+                    assert_eq!(line, 3)
+                },
+                other => panic!("Expected a parse error, got {:?}", other)
+            }
+
+            // The unphased calls are missing haplotypes (not validated).
+            let vcf = format!("{}{}", header, record);
+            let mut haplotypes = VcfReader::from_reader(Cursor::new(vcf))
+                .haplotypes();
+            assert_eq!(haplotypes.try_next().unwrap().is_err(),
+                       !record.contains("0/3"));
+        }
+
+        // Unplaced contigs are not errors.
+        let vcf = format!("{}GL000192.1\t100\trs1\tA\tG\t.\t.\t.\tGT\t0/1\t\
+                           1/1\n", header);
+        let g = VcfReader::from_reader(Cursor::new(vcf)).try_next().unwrap()
+            .unwrap();
+        assert_eq!(g.variant.chrom,
+                   Chromosome::Other("GL000192.1".to_string()));
+    }
+
+    // Needs bgzip and tabix (skipped otherwise).
+    #[test]
+    fn test_region_queries() {
+        let installed = |tool: &str| {
+            Command::new(tool).arg("--version").output().is_ok()
+        };
+        if !installed("bgzip") || !installed("tabix") {
+            eprintln!("bgzip or tabix is not installed, skipping.");
+            return;
+        }
+
+        let filename = std::env::temp_dir()
+            .join(format!("genepa_vcf_regions_{}.vcf.gz", std::process::id()));
+        let filename = filename.to_str().unwrap();
+
+        let mut bgzip = Command::new("bgzip")
+            .arg("-c")
+            .stdin(Stdio::piped())
+            .stdout(File::create(filename).unwrap())
+            .spawn()
+            .unwrap();
+        bgzip.stdin.take().unwrap().write_all(VCF.as_bytes()).unwrap();
+        assert!(bgzip.wait().unwrap().success());
+
+        let reader = VcfReader::new(filename);
+        let chrom1 = Chromosome::new("1");
+
+        let region = reader.get_variants_in_region(&chrom1, 1, 150);
+        assert_eq!(region.len(), 1);
+        assert_eq!(region[0].variant.name, "rs1");
+        assert!(reader.get_variants_in_region(&chrom1, 101, 150).is_empty());
+
+        let x = reader.get_variants_in_region(&Chromosome::new("X"), 1, 1000);
+        assert_eq!(x[0].genotypes, vec![Some(2), Some(1), None]);
+
+        let page = reader.get_variants_in_region_page(&chrom1, 1, 150, 0, 1);
+        assert_eq!(page.genotypes.len(), 1);
+        assert!(!page.is_truncated());
+
+        let g = reader.get_variant_genotypes(&region[0].variant).unwrap();
+        assert_eq!(g.genotypes, vec![Some(0), Some(1), Some(2)]);
+        let missing = Variant::new("rs1".to_string(), "1".to_string(), 100,
+                                   ("A".to_string(), "T".to_string()));
+        assert!(reader.get_variant_genotypes(&missing).is_none());
+
+        // The reader is dropped before reading the VCF (stops bgzip).
+        drop(reader);
+        for ext in &["", ".tbi"] {
+            let _ = std::fs::remove_file(format!("{}{}", filename, ext));
+        }
+    }
+
+    #[test]
+    fn test_parse_gt() {
+        assert_eq!(parse_gt("0/1"), Some((Some(1), 2)));
+        assert_eq!(parse_gt("1|1"), Some((Some(2), 2)));
+        assert_eq!(parse_gt("./."), Some((None, 2)));
+        assert_eq!(parse_gt("."), Some((None, 1)));
+        assert_eq!(parse_gt("0/2"), None);
+
+        assert_eq!(parse_gt_alleles("0/2"), Some(Some(vec![0, 2])));
+        assert_eq!(parse_gt_alleles("3"), Some(Some(vec![3])));
+        assert_eq!(parse_gt_alleles("./2"), Some(None));
+        assert_eq!(parse_gt_alleles("0/1/2"), None);
+    }
+}
@@ -0,0 +1,225 @@
+/*!
+ * Per-variant computations fused in a single pass over the genotypes.
+ *
+ * The features that process every variant (e.g. the MAF, the HWE test, the
+ * association tests or the scores) implement VariantVisitor. The visitors
+ * registered in a VariantScan are given every variant as it is read, with
+ * genotype counts computed once for all of them, so that the genotypes are
+ * decoded a single time instead of re-reading the BED for every feature.
+ *
+ * e.g. to compute the MAF and a score in a single pass:
+ *     let mut maf = StatVisitor::maf();
+ *     let mut scorer = Scorer::new(weights);
+ *     VariantScan::new()
+ *         .register(&mut maf)
+ *         .register(&mut scorer)
+ *         .run(PlinkReader::new("data")?);
+ */
+
+use crate::assoc::{test_association, AssocResult, Model};
+use crate::core::{Genotypes, Variant};
+use crate::grm::GrmAccumulator;
+use crate::qc::QcReport;
+use crate::score::Scorer;
+use crate::stats::hwe_exact;
+use crate::store::VariantCounts;
+
+
+pub trait VariantVisitor {
+    // The counts are those of the genotypes (shared by all the visitors).
+    fn visit(&mut self, g: &Genotypes, counts: &VariantCounts);
+}
+
+
+#[derive(Default)]
+pub struct VariantScan<'a> {
+    visitors: Vec<&'a mut dyn VariantVisitor>
+}
+
+impl<'a> VariantScan<'a> {
+    pub fn new() -> VariantScan<'a> {
+        VariantScan::default()
+    }
+
+    // The visitors are called in the order of registration. They are
+    // borrowed for the scan so that their results can be used afterwards.
+    pub fn register(mut self, visitor: &'a mut dyn VariantVisitor)
+        -> VariantScan<'a>
+    {
+        self.visitors.push(visitor);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.visitors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.visitors.is_empty()
+    }
+
+    pub fn visit(&mut self, g: &Genotypes) {
+        let counts = VariantCounts::of(g);
+        for visitor in self.visitors.iter_mut() {
+            visitor.visit(g, &counts);
+        }
+    }
+
+    // Give all the variants to the visitors. Returns the number of
+    // variants.
+    pub fn run<I>(mut self, genotypes: I) -> u64
+        where I: IntoIterator<Item = Genotypes>
+    {
+        let mut n = 0;
+        for g in genotypes {
+            self.visit(&g);
+            n += 1;
+        }
+
+        n
+    }
+}
+
+
+// Statistic computed for every variant.
+pub struct StatVisitor {
+    stat: fn(&Genotypes, &VariantCounts) -> f64,
+    values: Vec<(Variant, f64)>
+}
+
+impl StatVisitor {
+    pub fn new(stat: fn(&Genotypes, &VariantCounts) -> f64) -> StatVisitor {
+        StatVisitor { stat, values: Vec::new() }
+    }
+
+    // Minor allele frequency among the called samples (NaN if none).
+    pub fn maf() -> StatVisitor {
+        StatVisitor::new(|g, counts| {
+            counts.coded_frequency(g.ploidy())
+                .map_or(f64::NAN, |p| p.minor().get())
+        })
+    }
+
+    pub fn missing_rate() -> StatVisitor {
+        StatVisitor::new(|_, counts| 1.0 - counts.call_rate())
+    }
+
+    // Exact HWE test p-value (NaN for haploid variants).
+    pub fn hwe() -> StatVisitor {
+        StatVisitor::new(|g, counts| {
+            if g.is_haploid() {
+                return f64::NAN;
+            }
+
+            hwe_exact(counts.n_geno[1], counts.n_geno[0], counts.n_geno[2])
+        })
+    }
+
+    // Values in the order of the variants.
+    pub fn values(&self) -> &[(Variant, f64)] {
+        &self.values
+    }
+}
+
+impl VariantVisitor for StatVisitor {
+    fn visit(&mut self, g: &Genotypes, counts: &VariantCounts) {
+        self.values.push((g.variant.clone(), (self.stat)(g, counts)));
+    }
+}
+
+
+// Association test of every variant (see `test_association`).
+pub struct AssocVisitor<'a> {
+    phenotype: &'a [Option<f64>],
+    covariates: &'a [Vec<Option<f64>>],
+    model: Model,
+    results: Vec<(Variant, AssocResult)>
+}
+
+impl<'a> AssocVisitor<'a> {
+    pub fn new(phenotype: &'a [Option<f64>],
+               covariates: &'a [Vec<Option<f64>>], model: Model)
+        -> AssocVisitor<'a>
+    {
+        AssocVisitor { phenotype, covariates, model, results: Vec::new() }
+    }
+
+    pub fn results(&self) -> &[(Variant, AssocResult)] {
+        &self.results
+    }
+}
+
+impl<'a> VariantVisitor for AssocVisitor<'a> {
+    fn visit(&mut self, g: &Genotypes, _: &VariantCounts) {
+        let result = test_association(g, self.phenotype, self.covariates,
+                                      self.model);
+        self.results.push((g.variant.clone(), result));
+    }
+}
+
+
+impl VariantVisitor for Scorer {
+    fn visit(&mut self, g: &Genotypes, _: &VariantCounts) {
+        self.add(g);
+    }
+}
+
+
+impl VariantVisitor for QcReport {
+    fn visit(&mut self, g: &Genotypes, _: &VariantCounts) {
+        self.add(g);
+    }
+}
+
+
+impl VariantVisitor for GrmAccumulator {
+    fn visit(&mut self, g: &Genotypes, _: &VariantCounts) {
+        self.add(g);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::genotypes;
+    use crate::score::ScoreWeight;
+
+    #[test]
+    fn test_variant_scan() {
+        let all = vec![
+            genotypes(100, vec![Some(0), Some(1), Some(2), None]),
+            genotypes(200, vec![Some(0), Some(0), Some(0), Some(1)])
+        ];
+        let phenotype = [Some(1.0), Some(2.0), Some(3.0), Some(4.0)];
+
+        let mut maf = StatVisitor::maf();
+        let mut missing = StatVisitor::missing_rate();
+        let mut hwe = StatVisitor::hwe();
+        let mut assoc = AssocVisitor::new(&phenotype, &[], Model::Linear);
+        let mut scorer = Scorer::new(vec![ScoreWeight {
+            variant: all[1].variant.clone(),
+            effect_allele: "G".to_string(),
+            beta: 0.5
+        }]);
+
+        let scan = VariantScan::new()
+            .register(&mut maf)
+            .register(&mut missing)
+            .register(&mut hwe)
+            .register(&mut assoc)
+            .register(&mut scorer);
+        assert_eq!(scan.len(), 5);
+        assert_eq!(scan.run(all.clone()), 2);
+
+        assert_eq!(maf.values()[0].1, 0.5);
+        assert_eq!(maf.values()[1].1, 0.125);
+        assert_eq!(missing.values()[0].1, 0.25);
+        assert_eq!(missing.values()[1].1, 0.0);
+        assert_eq!(hwe.values()[1], (all[1].variant.clone(), 1.0));
+
+        assert_eq!(assoc.results().len(), 2);
+        assert_eq!(assoc.results()[0].1.n_obs, 3);
+        assert_eq!(scorer.scores(), &[0.0, 0.0, 0.0, 0.5]);
+    }
+}
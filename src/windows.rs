@@ -0,0 +1,114 @@
+/*!
+ * Iteration over the variants in non-overlapping windows.
+ *
+ * Windows are either a number of consecutive variants (e.g. the blocks of
+ * blocked LD or PCA algorithms) or a region of a fixed size in bp, aligned
+ * on multiples of the size like the QC windows. A window never spans two
+ * chromosomes, so the last window of every chromosome can have fewer
+ * variants. The variants are expected to be sorted by position within
+ * chromosomes.
+ */
+
+use std::iter::{FusedIterator, Peekable};
+
+use crate::core::Genotypes;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowSize {
+    Variants(usize),
+    Bp(u32)
+}
+
+
+pub struct Windows<I: Iterator<Item = Genotypes>> {
+    genotypes: Peekable<I>,
+    size: WindowSize
+}
+
+
+// Group the variants in windows of the given size.
+pub fn windows<I>(genotypes: I, size: WindowSize) -> Windows<I::IntoIter>
+    where I: IntoIterator<Item = Genotypes>
+{
+    match size {
+        WindowSize::Variants(n) => {
+            assert!(n > 0, "The number of variants per window must be \
+                            positive.")
+        },
+        WindowSize::Bp(bp) => {
+            assert!(bp > 0, "The window size must be positive.")
+        }
+    }
+
+    Windows { genotypes: genotypes.into_iter().peekable(), size }
+}
+
+
+impl<I: Iterator<Item = Genotypes>> Iterator for Windows<I> {
+    type Item = Vec<Genotypes>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = self.genotypes.next()?;
+        let chrom = first.variant.chrom.clone();
+        let start = first.variant.position;
+
+        let mut window = vec![first];
+
+        while let Some(g) = self.genotypes.peek() {
+            if g.variant.chrom != chrom {
+                break;
+            }
+
+            let same_window = match self.size {
+                WindowSize::Variants(n) => window.len() < n,
+                WindowSize::Bp(bp) => g.variant.position / bp == start / bp
+            };
+
+            if !same_window {
+                break;
+            }
+
+            window.push(self.genotypes.next().unwrap());
+        }
+
+        Some(window)
+    }
+}
+
+impl<I: Iterator<Item = Genotypes>> FusedIterator for Windows<I> {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::genotypes_on;
+
+    fn positions(windows: Windows<std::vec::IntoIter<Genotypes>>)
+        -> Vec<Vec<u32>>
+    {
+        windows
+            .map(|w| w.iter().map(|g| g.variant.position).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_windows() {
+        let genotypes = |chrom, pos| genotypes_on(chrom, pos, vec![Some(0)]);
+        let data = || vec![
+            genotypes("1", 10), genotypes("1", 20), genotypes("1", 150),
+            genotypes("1", 160), genotypes("1", 170), genotypes("2", 5),
+            genotypes("2", 199)
+        ];
+
+        assert_eq!(positions(windows(data(), WindowSize::Variants(2))),
+                   vec![vec![10, 20], vec![150, 160], vec![170],
+                        vec![5, 199]]);
+
+        assert_eq!(positions(windows(data(), WindowSize::Bp(100))),
+                   vec![vec![10, 20], vec![150, 160, 170], vec![5],
+                        vec![199]]);
+
+        assert_eq!(windows(Vec::new(), WindowSize::Variants(3)).count(), 0);
+    }
+}
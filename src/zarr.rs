@@ -0,0 +1,936 @@
+/*!
+ * Chunked genotype store in the Zarr (version 2) format.
+ *
+ * The genotypes are stored as a 2D array of variants by samples split in
+ * rectangular chunks, so both the genotypes of a variant and the genotypes
+ * of a sample across all the variants can be read without decoding the
+ * whole dataset (the BED format is only efficient for variant slices). The
+ * store is a directory with a group and the following arrays:
+ *
+ * - `genotypes` (variants x samples, `|i1`): copies of the coded allele,
+ *   -1 if missing.
+ * - `variant_contig`, `variant_position`, `variant_id` and `variant_ploidy`.
+ * - `variant_allele` (variants x 2): the other allele and the coded allele.
+ * - `sample_id` (samples x 2): FID and IID, and `sample_sex` (plink code).
+ *
+ * Chunks are compressed with zlib. The stores can also be read with the
+ * Zarr implementations (e.g. zarr-python) as long as the metadata arrays are
+ * only chunked along their first dimension.
+ */
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use crate::core::{Chromosome, Genotypes, Sample, Sex, Variant};
+use crate::source::RegionPage;
+
+
+// Variants and samples per chunk of the genotypes.
+pub const DEFAULT_CHUNKS: (usize, usize) = (256, 4096);
+
+const MISSING: i8 = -1;
+
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+
+// The subset of JSON needed to read the metadata of the arrays.
+#[derive(Clone, Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(HashMap<String, Json>)
+}
+
+struct JsonParser<'a> {
+    s: &'a [u8],
+    pos: usize
+}
+
+impl<'a> JsonParser<'a> {
+    fn parse(s: &str) -> Option<Json> {
+        let mut parser = JsonParser { s: s.as_bytes(), pos: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+
+        if parser.pos == parser.s.len() {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.s.get(self.pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    // Consume the expected byte (after whitespace).
+    fn expect(&mut self, b: u8) -> Option<()> {
+        self.skip_whitespace();
+        if self.s.get(self.pos) == Some(&b) {
+            self.pos += 1;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn literal(&mut self, literal: &str, value: Json) -> Option<Json> {
+        if self.s[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    fn value(&mut self) -> Option<Json> {
+        self.skip_whitespace();
+
+        match *self.s.get(self.pos)? {
+            b'n' => self.literal("null", Json::Null),
+            b't' => self.literal("true", Json::Bool(true)),
+            b'f' => self.literal("false", Json::Bool(false)),
+            b'"' => self.string().map(Json::String),
+            b'[' => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                if self.expect(b']').is_some() {
+                    return Some(Json::Array(values));
+                }
+
+                loop {
+                    values.push(self.value()?);
+                    if self.expect(b']').is_some() {
+                        return Some(Json::Array(values));
+                    }
+                    self.expect(b',')?;
+                }
+            },
+            b'{' => {
+                self.pos += 1;
+                let mut members = HashMap::new();
+                if self.expect(b'}').is_some() {
+                    return Some(Json::Object(members));
+                }
+
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    members.insert(key, self.value()?);
+
+                    if self.expect(b'}').is_some() {
+                        return Some(Json::Object(members));
+                    }
+                    self.expect(b',')?;
+                }
+            },
+            _ => {
+                let start = self.pos;
+                while self.s.get(self.pos).is_some_and(|&b| {
+                    b.is_ascii_digit() || b"+-.eE".contains(&b)
+                }) {
+                    self.pos += 1;
+                }
+
+                std::str::from_utf8(&self.s[start..self.pos]).ok()?
+                    .parse().ok()
+                    .map(Json::Number)
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if self.s.get(self.pos) != Some(&b'"') {
+            return None;
+        }
+        self.pos += 1;
+
+        let mut bytes = Vec::new();
+        loop {
+            let b = *self.s.get(self.pos)?;
+            self.pos += 1;
+
+            match b {
+                b'"' => return String::from_utf8(bytes).ok(),
+                b'\\' => {
+                    let escaped = *self.s.get(self.pos)?;
+                    self.pos += 1;
+                    bytes.push(match escaped {
+                        b'n' => b'\n',
+                        b't' => b'\t',
+                        b'r' => b'\r',
+                        b'"' | b'\\' | b'/' => escaped,
+                        _ => return None
+                    });
+                },
+                _ => bytes.push(b)
+            }
+        }
+    }
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.get(key),
+            _ => None
+        }
+    }
+
+    fn as_usizes(&self) -> Option<Vec<usize>> {
+        match self {
+            Json::Array(values) => values.iter()
+                .map(|value| match value {
+                    Json::Number(x) if *x >= 0.0 => Some(*x as usize),
+                    _ => None
+                })
+                .collect(),
+            _ => None
+        }
+    }
+}
+
+
+// Metadata of an array (`.zarray`).
+#[derive(Clone, Debug, PartialEq)]
+struct ArrayMeta {
+    shape: Vec<usize>,
+    chunks: Vec<usize>,
+    dtype: String,
+    compressed: bool
+}
+
+impl ArrayMeta {
+    fn new(shape: Vec<usize>, chunks: Vec<usize>, dtype: &str) -> ArrayMeta {
+        // Chunks can't be empty, even if the array is.
+        let chunks = chunks.into_iter().map(|c| c.max(1)).collect();
+        ArrayMeta { shape, chunks, dtype: dtype.to_string(), compressed: true }
+    }
+
+    fn itemsize(&self) -> usize {
+        self.dtype[2..].parse().unwrap_or(1)
+    }
+
+    fn chunk_len(&self) -> usize {
+        self.chunks.iter().product::<usize>() * self.itemsize()
+    }
+
+    // Number of chunks along a dimension.
+    fn n_chunks(&self, dim: usize) -> usize {
+        self.shape[dim].div_ceil(self.chunks[dim])
+    }
+
+    fn write(&self, dir: &Path, fill_value: &str) -> io::Result<()> {
+        let list = |values: &[usize]| {
+            values.iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join(".zarray"), format!(
+            "{{\n    \"zarr_format\": 2,\n    \"shape\": [{}],\n    \
+             \"chunks\": [{}],\n    \"dtype\": \"{}\",\n    \
+             \"compressor\": {{\"id\": \"zlib\", \"level\": 1}},\n    \
+             \"fill_value\": {},\n    \"order\": \"C\",\n    \
+             \"filters\": null\n}}\n",
+            list(&self.shape), list(&self.chunks), self.dtype, fill_value
+        ))
+    }
+
+    fn read(dir: &Path) -> io::Result<ArrayMeta> {
+        let filename = dir.join(".zarray");
+        let json = JsonParser::parse(&fs::read_to_string(&filename)?)
+            .ok_or_else(|| invalid_data(format!(
+                "Invalid JSON in `{}`.", filename.display()
+            )))?;
+
+        let invalid = |what: &str| invalid_data(format!(
+            "Invalid or unsupported {} in `{}`.", what, filename.display()
+        ));
+
+        let shape = json.get("shape").and_then(|s| s.as_usizes())
+            .ok_or_else(|| invalid("shape"))?;
+        let chunks = json.get("chunks").and_then(|c| c.as_usizes())
+            .filter(|c| c.len() == shape.len() && !c.contains(&0))
+            .ok_or_else(|| invalid("chunks"))?;
+
+        let dtype = match json.get("dtype") {
+            Some(Json::String(dtype)) => dtype.clone(),
+            _ => return Err(invalid("dtype"))
+        };
+
+        if json.get("order") != Some(&Json::String("C".to_string())) ||
+           !matches!(json.get("filters"), None | Some(Json::Null))
+        {
+            return Err(invalid("order or filters"));
+        }
+
+        let compressed = match json.get("compressor") {
+            None | Some(Json::Null) => false,
+            Some(compressor) => {
+                if compressor.get("id") != Some(&Json::String("zlib".into())) {
+                    return Err(invalid("compressor (only zlib is \
+                                        supported)"));
+                }
+                true
+            }
+        };
+
+        Ok(ArrayMeta { shape, chunks, dtype, compressed })
+    }
+
+    fn write_chunk(&self, dir: &Path, key: &str, data: &[u8])
+        -> io::Result<()>
+    {
+        let mut f = File::create(dir.join(key))?;
+        if self.compressed {
+            let mut encoder = ZlibEncoder::new(f, Compression::fast());
+            encoder.write_all(data)?;
+            encoder.finish()?;
+        } else {
+            f.write_all(data)?;
+        }
+
+        Ok(())
+    }
+
+    // The decoded chunk, None if it is missing (i.e. only fill values).
+    fn read_chunk(&self, dir: &Path, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let f = match File::open(dir.join(key)) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e)
+        };
+
+        let mut data = Vec::with_capacity(self.chunk_len());
+        if self.compressed {
+            ZlibDecoder::new(f).read_to_end(&mut data)?;
+        } else {
+            io::BufReader::new(f).read_to_end(&mut data)?;
+        }
+
+        if data.len() != self.chunk_len() {
+            return Err(invalid_data(format!(
+                "Expected {} bytes in chunk `{}` of `{}`, got {}.",
+                self.chunk_len(), key, dir.display(), data.len()
+            )));
+        }
+
+        Ok(Some(data))
+    }
+}
+
+
+// Write an array in chunks along its first dimension.
+fn write_array(dir: &Path, meta: &ArrayMeta, data: &[u8]) -> io::Result<()> {
+    meta.write(dir, "null")?;
+
+    let chunk_len = meta.chunk_len();
+    for (i, chunk) in data.chunks(chunk_len).enumerate() {
+        // The last chunk is padded to the full size of the chunks.
+        let mut chunk = chunk.to_vec();
+        chunk.resize(chunk_len, 0);
+
+        let key = std::iter::once(i.to_string())
+            .chain((1..meta.shape.len()).map(|_| "0".to_string()))
+            .collect::<Vec<_>>()
+            .join(".");
+        meta.write_chunk(dir, &key, &chunk)?;
+    }
+
+    Ok(())
+}
+
+
+// Read an array that is only chunked along its first dimension.
+fn read_array(dir: &Path) -> io::Result<(ArrayMeta, Vec<u8>)> {
+    let meta = ArrayMeta::read(dir)?;
+    if meta.shape.is_empty() || meta.chunks[1..] != meta.shape[1..] {
+        return Err(invalid_data(format!(
+            "`{}` must be chunked along its first dimension only.",
+            dir.display()
+        )));
+    }
+
+    let len = meta.shape.iter().product::<usize>() * meta.itemsize();
+    let mut data = Vec::with_capacity(len);
+
+    for i in 0..meta.n_chunks(0) {
+        let key = std::iter::once(i.to_string())
+            .chain((1..meta.shape.len()).map(|_| "0".to_string()))
+            .collect::<Vec<_>>()
+            .join(".");
+
+        match meta.read_chunk(dir, &key)? {
+            Some(chunk) => data.extend(chunk),
+            None => data.resize(data.len() + meta.chunk_len(), 0)
+        }
+    }
+
+    data.truncate(len);
+    Ok((meta, data))
+}
+
+
+// Fixed length byte strings (`|S{n}`) padded with NUL bytes.
+fn encode_strings(strings: &[&str]) -> (String, Vec<u8>) {
+    let n = strings.iter().map(|s| s.len()).max().unwrap_or(0).max(1);
+
+    let mut data = Vec::with_capacity(n * strings.len());
+    for s in strings {
+        data.extend(s.as_bytes());
+        data.resize(data.len() + n - s.len(), 0);
+    }
+
+    (format!("|S{}", n), data)
+}
+
+fn decode_strings(meta: &ArrayMeta, data: &[u8]) -> io::Result<Vec<String>> {
+    if !meta.dtype.starts_with("|S") {
+        return Err(invalid_data(format!("Expected strings, got `{}`.",
+                                         meta.dtype)));
+    }
+
+    data.chunks(meta.itemsize())
+        .map(|s| {
+            let end = s.iter().position(|&b| b == 0).unwrap_or(s.len());
+            String::from_utf8(s[..end].to_vec())
+                .map_err(|e| invalid_data(e.to_string()))
+        })
+        .collect()
+}
+
+
+// Writer of genotype stores. The genotypes are written by rows of chunks
+// and the metadata arrays when the writer is finished.
+pub struct ZarrWriter {
+    path: PathBuf,
+    samples: Vec<Sample>,
+    chunks: (usize, usize),
+    // Variant, coded allele and ploidy of the written variants.
+    variants: Vec<(Variant, String, u8)>,
+    // Calls of the variants of the current row of chunks.
+    row: Vec<i8>
+}
+
+impl ZarrWriter {
+    pub fn new(path: &str, samples: &[Sample]) -> io::Result<ZarrWriter> {
+        ZarrWriter::with_chunks(path, samples, DEFAULT_CHUNKS)
+    }
+
+    // Chunks of (variants, samples).
+    pub fn with_chunks(path: &str, samples: &[Sample], chunks: (usize, usize))
+        -> io::Result<ZarrWriter>
+    {
+        if chunks.0 == 0 || chunks.1 == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "The chunks can't be empty."));
+        }
+
+        let path = PathBuf::from(path);
+        fs::create_dir_all(path.join("genotypes"))?;
+        fs::write(path.join(".zgroup"), "{\n    \"zarr_format\": 2\n}\n")?;
+
+        Ok(ZarrWriter {
+            path,
+            samples: samples.to_vec(),
+            chunks,
+            variants: Vec::new(),
+            row: Vec::new()
+        })
+    }
+
+    fn genotypes_meta(&self) -> ArrayMeta {
+        ArrayMeta::new(vec![self.variants.len(), self.samples.len()],
+                       vec![self.chunks.0, self.chunks.1], "|i1")
+    }
+
+    pub fn write(&mut self, g: &Genotypes) -> io::Result<()> {
+        if g.genotypes.len() != self.samples.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Got {} genotypes for {} samples.",
+                        g.genotypes.len(), self.samples.len())
+            ));
+        }
+
+        self.row.extend(g.genotypes.iter().map(|call| {
+            call.map_or(MISSING, |x| x as i8)
+        }));
+        self.variants.push((g.variant.clone(), g.coded_allele().to_string(),
+                            g.ploidy()));
+
+        if self.variants.len().is_multiple_of(self.chunks.0) {
+            self._write_row()?;
+        }
+
+        Ok(())
+    }
+
+    // Write the chunks of the current row, padded with missing calls.
+    fn _write_row(&mut self) -> io::Result<()> {
+        if self.row.is_empty() {
+            return Ok(());
+        }
+
+        let meta = self.genotypes_meta();
+        let n_samples = self.samples.len();
+        let (chunk_variants, chunk_samples) = self.chunks;
+        let row_idx = (self.variants.len() - 1) / chunk_variants;
+        let n_variants = self.row.len() / n_samples;
+
+        for j in 0..meta.n_chunks(1) {
+            let mut chunk = vec![MISSING as u8;
+                                 chunk_variants * chunk_samples];
+
+            for i in 0..n_variants {
+                let start = j * chunk_samples;
+                let end = (start + chunk_samples).min(n_samples);
+                for (k, &call) in self.row[i * n_samples..][start..end]
+                    .iter()
+                    .enumerate()
+                {
+                    chunk[i * chunk_samples + k] = call as u8;
+                }
+            }
+
+            meta.write_chunk(&self.path.join("genotypes"),
+                             &format!("{}.{}", row_idx, j), &chunk)?;
+        }
+
+        self.row.clear();
+        Ok(())
+    }
+
+    // Write the last chunks and the metadata. Returns the number of
+    // variants.
+    pub fn finish(mut self) -> io::Result<u32> {
+        self._write_row()?;
+        self.genotypes_meta().write(&self.path.join("genotypes"), "-1")?;
+
+        let n_variants = self.variants.len();
+        let n_samples = self.samples.len();
+        let write_strings = |name: &str, shape: Vec<usize>,
+                             strings: Vec<&str>| {
+            let (dtype, data) = encode_strings(&strings);
+            let chunks = shape.clone();
+            write_array(&self.path.join(name),
+                        &ArrayMeta::new(shape, chunks, &dtype), &data)
+        };
+
+        let contigs: Vec<String> = self.variants.iter()
+            .map(|(v, _, _)| v.chrom.to_string())
+            .collect();
+        write_strings("variant_contig", vec![n_variants],
+                      contigs.iter().map(|contig| contig.as_str()).collect())?;
+        write_strings("variant_id", vec![n_variants],
+                      self.variants.iter()
+                          .map(|(v, _, _)| v.name.as_str())
+                          .collect())?;
+        write_strings("variant_allele", vec![n_variants, 2],
+                      self.variants.iter()
+                          .flat_map(|(v, coded, _)| {
+                              let other = if v.alleles.0 == *coded {
+                                  &v.alleles.1
+                              } else {
+                                  &v.alleles.0
+                              };
+                              vec![other.as_str(), coded.as_str()]
+                          })
+                          .collect())?;
+        write_strings("sample_id", vec![n_samples, 2],
+                      self.samples.iter()
+                          .flat_map(|s| vec![s.fid.as_str(), s.iid.as_str()])
+                          .collect())?;
+
+        let positions: Vec<u8> = self.variants.iter()
+            .flat_map(|(v, _, _)| v.position.to_le_bytes())
+            .collect();
+        write_array(&self.path.join("variant_position"),
+                    &ArrayMeta::new(vec![n_variants], vec![n_variants],
+                                    "<u4"),
+                    &positions)?;
+
+        let ploidy: Vec<u8> = self.variants.iter().map(|v| v.2).collect();
+        write_array(&self.path.join("variant_ploidy"),
+                    &ArrayMeta::new(vec![n_variants], vec![n_variants],
+                                    "|u1"),
+                    &ploidy)?;
+
+        let sexes: Vec<u8> = self.samples.iter()
+            .map(|s| s.sex.to_plink_code().as_bytes()[0] - b'0')
+            .collect();
+        write_array(&self.path.join("sample_sex"),
+                    &ArrayMeta::new(vec![n_samples], vec![n_samples], "|u1"),
+                    &sexes)?;
+
+        Ok(n_variants as u32)
+    }
+}
+
+
+pub struct ZarrReader {
+    path: PathBuf,
+    samples: Arc<Vec<Sample>>,
+    attach_samples: bool,
+    // Variant, coded allele and ploidy.
+    variants: Vec<(Variant, String, u8)>,
+    genotypes: ArrayMeta,
+    // Decoded chunks of the last read row of chunks.
+    row: Option<(usize, Vec<Option<Vec<u8>>>)>,
+    n_read: usize
+}
+
+impl ZarrReader {
+    pub fn new(path: &str) -> ZarrReader {
+        ZarrReader::_open(Path::new(path))
+            .unwrap_or_else(|e| panic!("Could not open Zarr store `{}`: {}",
+                                       path, e))
+    }
+
+    fn _open(path: &Path) -> io::Result<ZarrReader> {
+        let strings = |name: &str| -> io::Result<Vec<String>> {
+            let (meta, data) = read_array(&path.join(name))?;
+            decode_strings(&meta, &data)
+        };
+        let bytes = |name: &str, dtype: &str| -> io::Result<Vec<u8>> {
+            let (meta, data) = read_array(&path.join(name))?;
+            if meta.dtype != dtype {
+                return Err(invalid_data(format!(
+                    "Expected `{}` for `{}`, got `{}`.", dtype, name,
+                    meta.dtype
+                )));
+            }
+            Ok(data)
+        };
+
+        let ids = strings("sample_id")?;
+        let sexes = bytes("sample_sex", "|u1")?;
+        let samples: Vec<Sample> = ids.chunks(2)
+            .zip(sexes.iter())
+            .map(|(id, sex)| {
+                Sample::new(id[0].clone(), id[1].clone(),
+                            Sex::from_plink_code(&sex.to_string()))
+            })
+            .collect();
+
+        let contigs = strings("variant_contig")?;
+        let names = strings("variant_id")?;
+        let alleles = strings("variant_allele")?;
+        let positions = bytes("variant_position", "<u4")?;
+        let ploidy = bytes("variant_ploidy", "|u1")?;
+
+        let n_variants = contigs.len();
+        if names.len() != n_variants || alleles.len() != 2 * n_variants ||
+           positions.len() != 4 * n_variants || ploidy.len() != n_variants
+        {
+            return Err(invalid_data("The variant arrays have different \
+                                     lengths.".to_string()));
+        }
+
+        let variants = (0..n_variants)
+            .map(|i| {
+                let mut position = [0; 4];
+                position.copy_from_slice(&positions[4 * i..4 * i + 4]);
+
+                let (other, coded) = (&alleles[2 * i], &alleles[2 * i + 1]);
+                let v = Variant::new(names[i].clone(), contigs[i].clone(),
+                                     u32::from_le_bytes(position),
+                                     (other.clone(), coded.clone()));
+                (v, coded.clone(), ploidy[i])
+            })
+            .collect();
+
+        let genotypes = ArrayMeta::read(&path.join("genotypes"))?;
+        if genotypes.dtype != "|i1" ||
+           genotypes.shape != [n_variants, samples.len()]
+        {
+            return Err(invalid_data(
+                "The genotypes array doesn't match the variants and \
+                 samples.".to_string()
+            ));
+        }
+
+        Ok(ZarrReader {
+            path: path.to_path_buf(),
+            samples: Arc::new(samples),
+            attach_samples: false,
+            variants,
+            genotypes,
+            row: None,
+            n_read: 0
+        })
+    }
+
+    pub fn attach_samples(&mut self, attach: bool) {
+        self.attach_samples = attach;
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn n_variants(&self) -> u32 {
+        self.variants.len() as u32
+    }
+
+    pub fn variants(&self) -> impl Iterator<Item = &Variant> {
+        self.variants.iter().map(|(v, _, _)| v)
+    }
+
+    fn _read_chunk(&self, i: usize, j: usize) -> Option<Vec<u8>> {
+        self.genotypes
+            .read_chunk(&self.path.join("genotypes"), &format!("{}.{}", i, j))
+            .unwrap_or_else(|e| panic!("Could not read the genotypes of \
+                                        `{}`: {}", self.path.display(), e))
+    }
+
+    fn decode(call: u8) -> Option<u8> {
+        if call as i8 == MISSING {
+            None
+        } else {
+            Some(call)
+        }
+    }
+
+    // The genotypes of the variant at an index. The chunks of its row are
+    // kept to read the next variants.
+    pub fn variant_genotypes(&mut self, idx: usize) -> Genotypes {
+        let (chunk_variants, chunk_samples) =
+            (self.genotypes.chunks[0], self.genotypes.chunks[1]);
+        let row_idx = idx / chunk_variants;
+
+        if self.row.as_ref().map(|(i, _)| *i) != Some(row_idx) {
+            let chunks = (0..self.genotypes.n_chunks(1))
+                .map(|j| self._read_chunk(row_idx, j))
+                .collect();
+            self.row = Some((row_idx, chunks));
+        }
+
+        let offset = (idx % chunk_variants) * chunk_samples;
+        let chunks = &self.row.as_ref().unwrap().1;
+        let calls = (0..self.samples.len())
+            .map(|k| {
+                chunks[k / chunk_samples].as_ref()
+                    .and_then(|c| ZarrReader::decode(c[offset +
+                                                     k % chunk_samples]))
+            })
+            .collect();
+
+        let (v, coded, ploidy) = &self.variants[idx];
+        let g = Genotypes::new(v.clone(), calls, coded).with_ploidy(*ploidy);
+
+        if self.attach_samples {
+            g.with_samples(Arc::clone(&self.samples))
+        } else {
+            g
+        }
+    }
+
+    // The genotypes of a sample for all the variants. Only the chunks of
+    // the sample are read.
+    pub fn sample_genotypes(&self, sample_idx: usize) -> Vec<Option<u8>> {
+        assert!(sample_idx < self.samples.len(), "Invalid sample index {}.",
+                sample_idx);
+
+        let (chunk_variants, chunk_samples) =
+            (self.genotypes.chunks[0], self.genotypes.chunks[1]);
+        let (j, k) = (sample_idx / chunk_samples, sample_idx % chunk_samples);
+
+        let mut calls = Vec::with_capacity(self.variants.len());
+        for i in 0..self.genotypes.n_chunks(0) {
+            let n = chunk_variants.min(self.variants.len() - calls.len());
+            match self._read_chunk(i, j) {
+                Some(chunk) => calls.extend((0..n).map(|row| {
+                    ZarrReader::decode(chunk[row * chunk_samples + k])
+                })),
+                None => calls.resize(calls.len() + n, None)
+            }
+        }
+
+        calls
+    }
+
+    fn _region_indices(&self, chrom: &Chromosome, start: u32, end: u32)
+        -> Vec<usize>
+    {
+        self.variants.iter()
+            .enumerate()
+            .filter(|(_, (v, _, _))| {
+                v.chrom == *chrom && v.position >= start && v.position <= end
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    pub fn get_variant_genotypes(&mut self, v: &Variant) -> Option<Genotypes> {
+        let matches: Vec<usize> = self._region_indices(&v.chrom, v.position,
+                                                       v.position)
+            .into_iter()
+            .filter(|&i| self.variants[i].0 == *v)
+            .collect();
+
+        match matches.len() {
+            0 => None,
+            1 => Some(self.variant_genotypes(matches[0])),
+            _ => panic!("There are duplicate variants in the Zarr store.")
+        }
+    }
+
+    pub fn get_variants_in_region(&mut self, chrom: &Chromosome, start: u32,
+                                  end: u32) -> Vec<Genotypes>
+    {
+        self._region_indices(chrom, start, end)
+            .into_iter()
+            .map(|i| self.variant_genotypes(i))
+            .collect()
+    }
+
+    pub fn get_variants_in_region_page(&mut self, chrom: &Chromosome,
+                                       start: u32, end: u32, offset: usize,
+                                       limit: usize) -> RegionPage
+    {
+        let indices = self._region_indices(chrom, start, end);
+        let genotypes = indices.into_iter()
+            .skip(offset)
+            .map(|i| self.variant_genotypes(i));
+
+        RegionPage::collect(genotypes, offset, limit)
+    }
+}
+
+
+impl Iterator for ZarrReader {
+    type Item = Genotypes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.n_read >= self.variants.len() {
+            return None;
+        }
+
+        let g = self.variant_genotypes(self.n_read);
+        self.n_read += 1;
+
+        Some(g)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let json = JsonParser::parse(
+            "{\"shape\": [10, 3], \"dtype\": \"|i1\", \"x\": null, \
+             \"y\": {\"id\": \"zlib\", \"level\": 1}, \"z\": [true, -1.5e0]}"
+        ).unwrap();
+
+        assert_eq!(json.get("shape").unwrap().as_usizes(), Some(vec![10, 3]));
+        assert_eq!(json.get("dtype"), Some(&Json::String("|i1".to_string())));
+        assert_eq!(json.get("y").unwrap().get("level"),
+                   Some(&Json::Number(1.0)));
+        assert_eq!(json.get("z"), Some(&Json::Array(vec![
+            Json::Bool(true), Json::Number(-1.5)
+        ])));
+
+        assert!(JsonParser::parse("{\"a\": 1,}").is_none());
+        assert!(JsonParser::parse("[1] 2").is_none());
+    }
+
+    #[test]
+    fn test_zarr_store() {
+        let dir = std::env::temp_dir()
+            .join(format!("genepa_zarr_{}.zarr", std::process::id()));
+        let path = dir.to_str().unwrap();
+
+        let samples: Vec<Sample> = (0..5)
+            .map(|i| {
+                let sex = if i == 0 { Sex::Female } else { Sex::Unknown };
+                Sample::new(format!("f{}", i), format!("s{}", i), sex)
+            })
+            .collect();
+
+        let data: Vec<Genotypes> = (0..7)
+            .map(|i| {
+                let chrom = if i < 6 { "1" } else { "Y" };
+                let v = Variant::new(format!("rs{}", i), chrom.to_string(),
+                                     100 * (i + 1),
+                                     ("A".to_string(), "CT".to_string()));
+                let calls = (0..5)
+                    .map(|j| if (i + j) % 4 == 3 { None }
+                             else { Some(((i + j) % 3) as u8) })
+                    .collect();
+                let g = Genotypes::new(v, calls, if i % 2 == 0 { "A" }
+                                                 else { "CT" });
+                if chrom == "Y" {
+                    Genotypes::new(g.variant.clone(),
+                                   vec![Some(0), Some(1), None, Some(1),
+                                        Some(0)],
+                                   "A").with_ploidy(1)
+                } else {
+                    g
+                }
+            })
+            .collect();
+
+        // Chunks that don't divide the dimensions.
+        let mut writer = ZarrWriter::with_chunks(path, &samples, (3, 2))
+            .unwrap();
+        for g in data.iter() {
+            writer.write(g).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), 7);
+
+        let mut reader = ZarrReader::new(path);
+        assert_eq!(reader.samples(), &samples[..]);
+        assert_eq!(reader.n_variants(), 7);
+
+        let sample: Vec<Option<u8>> = data.iter()
+            .map(|g| g.genotypes[3])
+            .collect();
+        assert_eq!(reader.sample_genotypes(3), sample);
+
+        let region = reader.get_variants_in_region(
+            &Chromosome::Autosome(1), 250, 450
+        );
+        assert_eq!(region.len(), 2);
+        assert_eq!(region[1].coded_allele(), "CT");
+        assert_eq!(region[1].genotypes, data[3].genotypes);
+
+        let all: Vec<Genotypes> = reader.collect();
+        for (g, expected) in all.iter().zip(data.iter()) {
+            assert_eq!(g.variant, expected.variant);
+            assert_eq!(g.coded_allele(), expected.coded_allele());
+            assert_eq!(g.genotypes, expected.genotypes);
+            assert_eq!(g.ploidy(), expected.ploidy());
+        }
+
+        // The last row of chunks is padded.
+        let meta = ArrayMeta::read(&dir.join("genotypes")).unwrap();
+        assert_eq!(meta.shape, vec![7, 5]);
+        assert_eq!(meta.read_chunk(&dir.join("genotypes"), "2.2").unwrap()
+                       .map(|c| c.len()), Some(6));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}